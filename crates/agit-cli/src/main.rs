@@ -0,0 +1,488 @@
+//! `agit` — inspect and edit agit repositories from the shell.
+//!
+//! Every command works on the SQLite repository in `--repo` (default: the
+//! current directory) or on the storage named by `--storage`. Output is
+//! human-readable unless `--json` is given.
+
+mod render;
+mod storage;
+
+use std::process::ExitCode;
+
+use agit_core::{
+    gc::GcOptions, ActionType, AgentState, FsckOptions, LogFilter, MergeOptions, MergeStrategy,
+    Repository,
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde_json::Value;
+
+use crate::storage::Location;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn cli() -> Command {
+    Command::new("agit")
+        .about("Version control for AI agent state")
+        .version(env!("CARGO_PKG_VERSION"))
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("repo")
+                .short('r')
+                .long("repo")
+                .global(true)
+                .default_value(".")
+                .help("Repository directory (SQLite)"),
+        )
+        .arg(
+            Arg::new("storage")
+                .long("storage")
+                .global(true)
+                .help("Storage URL: postgres://…, s3://bucket/prefix or sqlite://path"),
+        )
+        .arg(
+            Arg::new("agent")
+                .short('a')
+                .long("agent")
+                .global(true)
+                .default_value("cli")
+                .help("Agent id recorded on commits and audit entries"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print machine-readable JSON"),
+        )
+        .subcommand(Command::new("init").about("Create a repository"))
+        .subcommand(
+            Command::new("commit")
+                .about("Commit agent state read from JSON files")
+                .arg(
+                    Arg::new("memory-file")
+                        .long("memory-file")
+                        .required(true)
+                        .help("JSON file holding the agent memory"),
+                )
+                .arg(
+                    Arg::new("world-file")
+                        .long("world-file")
+                        .help("JSON file holding the world state (default: {})"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .long("message")
+                        .required(true)
+                        .help("Commit message"),
+                )
+                .arg(
+                    Arg::new("action")
+                        .long("action")
+                        .default_value("checkpoint")
+                        .help("Action type that produced the state"),
+                ),
+        )
+        .subcommand(
+            Command::new("log")
+                .about("Show commit history")
+                .arg(
+                    Arg::new("branch")
+                        .short('b')
+                        .long("branch")
+                        .help("Branch to show"),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Maximum number of commits"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the changes between two commits")
+                .arg(Arg::new("hash1").required(true))
+                .arg(Arg::new("hash2").required(true)),
+        )
+        .subcommand(
+            Command::new("checkout")
+                .about("Switch to a branch or commit")
+                .arg(Arg::new("target").required(true)),
+        )
+        .subcommand(
+            Command::new("branch")
+                .about("List, create or delete branches")
+                .arg(Arg::new("name"))
+                .arg(
+                    Arg::new("from")
+                        .short('f')
+                        .long("from")
+                        .help("Commit or branch to start from (default: HEAD)"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .long("delete")
+                        .action(ArgAction::SetTrue)
+                        .requires("name")
+                        .conflicts_with("from")
+                        .help("Delete the branch"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .requires("delete")
+                        .help("Delete the checked-out branch, detaching HEAD at its tip"),
+                ),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merge a branch into the current branch")
+                .arg(Arg::new("branch").required(true))
+                .arg(
+                    Arg::new("strategy")
+                        .short('s')
+                        .long("strategy")
+                        .default_value("three_way")
+                        .value_parser(["ours", "theirs", "three_way"])
+                        .help("How conflicts are resolved"),
+                )
+                .arg(
+                    Arg::new("no-ff")
+                        .long("no-ff")
+                        .action(ArgAction::SetTrue)
+                        .help("Create a merge commit even when a fast-forward is possible"),
+                ),
+        )
+        .subcommand(
+            Command::new("revert")
+                .about("Commit the state of an earlier commit")
+                .arg(Arg::new("hash").required(true)),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("Remove objects no branch can reach")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("Report what would be removed without deleting"),
+                )
+                .arg(
+                    Arg::new("keep")
+                        .long("keep")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Always keep this many commits on each branch"),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Show the audit log")
+                .arg(
+                    Arg::new("agent")
+                        .long("agent")
+                        .help("Only entries by this agent"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Only entries at or after this RFC 3339 timestamp"),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .default_value("50")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Maximum number of entries"),
+                ),
+        )
+        .subcommand(
+            Command::new("fsck")
+                .about("Check the repository for missing or corrupt objects")
+                .arg(
+                    Arg::new("repair")
+                        .long("repair")
+                        .action(ArgAction::SetTrue)
+                        .help("Re-upload missing objects stored under another key"),
+                ),
+        )
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let matches = cli().get_matches();
+    match run(&matches).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(matches: &ArgMatches) -> Result<ExitCode> {
+    let location = Location::new(
+        string(matches, "repo").unwrap_or("."),
+        string(matches, "storage"),
+    )?;
+    let json = matches.get_flag("json");
+    let (name, sub) = matches.subcommand().expect("subcommand is required");
+
+    let storage = location.connect(name == "init").await?;
+    let mut repo = Repository::init(storage).await?;
+    repo.set_agent_id(string(matches, "agent").unwrap_or("cli"));
+
+    let result = dispatch(&mut repo, &location, name, sub).await;
+    repo.close().await?;
+    let output = result?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&output.json)?);
+    } else if !output.text.is_empty() {
+        println!("{}", output.text);
+    }
+    Ok(if output.ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// What a command prints, in both forms, and whether it succeeded.
+struct Output {
+    text: String,
+    json: Value,
+    ok: bool,
+}
+
+impl Output {
+    fn new(text: String, json: Value) -> Self {
+        Output {
+            text,
+            json,
+            ok: true,
+        }
+    }
+}
+
+async fn dispatch(
+    repo: &mut Repository,
+    location: &Location,
+    name: &str,
+    sub: &ArgMatches,
+) -> Result<Output> {
+    match name {
+        "init" => Ok(Output::new(
+            format!("Initialized agit repository at {}", location.describe()),
+            serde_json::json!({ "repository": location.describe() }),
+        )),
+        "commit" => {
+            let memory = read_json(string(sub, "memory-file").expect("required"))?;
+            let world = match string(sub, "world-file") {
+                Some(path) => read_json(path)?,
+                None => serde_json::json!({}),
+            };
+            let action = parse_action_type(string(sub, "action").unwrap_or("checkpoint"))?;
+            let message = string(sub, "message").expect("required");
+            let hash = repo
+                .commit(&AgentState::new(memory, world), message, action)
+                .await?;
+            let branch = repo.current_branch().unwrap_or("HEAD").to_string();
+            Ok(Output::new(
+                format!("[{} {}] {}", branch, hash.short(), message),
+                serde_json::json!({ "hash": hash.as_str(), "branch": branch }),
+            ))
+        }
+        "log" => {
+            let limit = *sub.get_one::<usize>("limit").expect("has default");
+            let commits = repo.log(string(sub, "branch"), limit).await?;
+            Ok(Output::new(
+                render::log(&commits),
+                Value::Array(
+                    commits
+                        .iter()
+                        .map(render::commit_json)
+                        .collect::<Result<_>>()?,
+                ),
+            ))
+        }
+        "diff" => {
+            let diff = repo
+                .diff(
+                    string(sub, "hash1").expect("required"),
+                    string(sub, "hash2").expect("required"),
+                )
+                .await?;
+            Ok(Output::new(
+                render::diff(&diff),
+                serde_json::to_value(&diff)?,
+            ))
+        }
+        "checkout" => {
+            let target = string(sub, "target").expect("required");
+            repo.checkout(target).await?;
+            let head = repo.head()?;
+            let text = match repo.current_branch() {
+                Some(branch) => format!("Switched to branch '{}'", branch),
+                None => format!("HEAD is now at {}", head.short()),
+            };
+            Ok(Output::new(
+                text,
+                serde_json::json!({ "branch": repo.current_branch(), "head": head.as_str() }),
+            ))
+        }
+        "branch" => branch(repo, sub).await,
+        "merge" => {
+            let branch = string(sub, "branch").expect("required");
+            let strategy = parse_strategy(string(sub, "strategy").expect("has default"))?;
+            let options = MergeOptions {
+                no_ff: sub.get_flag("no-ff"),
+            };
+            let hash = repo.merge_with_options(branch, strategy, options).await?;
+            Ok(Output::new(
+                format!("Merged '{}' at {}", branch, hash.short()),
+                serde_json::json!({ "hash": hash.as_str(), "branch": branch }),
+            ))
+        }
+        "revert" => {
+            let target = string(sub, "hash").expect("required");
+            repo.revert(target).await?;
+            let head = repo.head()?;
+            Ok(Output::new(
+                format!("Reverted to {} at {}", target, head.short()),
+                serde_json::json!({ "hash": head.as_str(), "reverted_to": target }),
+            ))
+        }
+        "gc" => {
+            let options = GcOptions {
+                dry_run: sub.get_flag("dry-run"),
+                keep_last_n: *sub.get_one::<usize>("keep").expect("has default"),
+                ..Default::default()
+            };
+            let result = repo.gc_with_options(&options).await?;
+            Ok(Output::new(render::gc(&result), render::gc_json(&result)))
+        }
+        "audit" => {
+            let filter = LogFilter {
+                agent_id: string(sub, "agent").map(String::from),
+                since: string(sub, "since").map(String::from),
+                limit: Some(*sub.get_one::<usize>("limit").expect("has default")),
+                ..Default::default()
+            };
+            let entries = repo.audit_log(&filter).await?;
+            Ok(Output::new(
+                render::audit(&entries),
+                serde_json::to_value(&entries)?,
+            ))
+        }
+        "fsck" => {
+            let report = repo
+                .fsck_with_options(FsckOptions {
+                    repair: sub.get_flag("repair"),
+                })
+                .await?;
+            let mut output = Output::new(render::fsck(&report), render::fsck_json(&report));
+            output.ok = report.is_ok();
+            Ok(output)
+        }
+        _ => unreachable!("clap rejects unknown subcommands"),
+    }
+}
+
+async fn branch(repo: &mut Repository, sub: &ArgMatches) -> Result<Output> {
+    let Some(name) = string(sub, "name") else {
+        let mut branches: Vec<(&String, &agit_core::Hash)> = repo.list_branches().iter().collect();
+        branches.sort_by_key(|(name, _)| *name);
+        let current = repo.current_branch();
+        let json = serde_json::json!({
+            "current": current,
+            "branches": branches
+                .iter()
+                .map(|(name, hash)| ((*name).clone(), Value::from(hash.as_str())))
+                .collect::<serde_json::Map<_, _>>(),
+        });
+        return Ok(Output::new(render::branches(&branches, current), json));
+    };
+    if sub.get_flag("delete") {
+        repo.delete_branch(name, sub.get_flag("force")).await?;
+        return Ok(Output::new(
+            format!("Deleted branch '{}'", name),
+            serde_json::json!({ "deleted": name }),
+        ));
+    }
+    repo.branch(name, string(sub, "from")).await?;
+    let hash = repo
+        .list_branches()
+        .get(name)
+        .map(|h| h.as_str().to_string());
+    Ok(Output::new(
+        format!("Created branch '{}'", name),
+        serde_json::json!({ "created": name, "hash": hash }),
+    ))
+}
+
+fn string<'a>(matches: &'a ArgMatches, id: &str) -> Option<&'a str> {
+    matches.get_one::<String>(id).map(String::as_str)
+}
+
+fn read_json(path: &str) -> Result<Value> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: invalid JSON: {}", path, e).into())
+}
+
+fn parse_action_type(s: &str) -> Result<ActionType> {
+    Ok(match s {
+        "tool_call" => ActionType::ToolCall,
+        "llm_response" => ActionType::LlmResponse,
+        "user_input" => ActionType::UserInput,
+        "system_event" => ActionType::SystemEvent,
+        "retry" => ActionType::Retry,
+        "rollback" => ActionType::Rollback,
+        "merge" => ActionType::Merge,
+        "checkpoint" => ActionType::Checkpoint,
+        other => match other.strip_prefix("custom:") {
+            Some(name) => ActionType::Custom(name.to_string()),
+            None => return Err(format!("unknown action type '{}'", other).into()),
+        },
+    })
+}
+
+fn parse_strategy(s: &str) -> Result<MergeStrategy> {
+    match s {
+        "ours" => Ok(MergeStrategy::Ours),
+        "theirs" => Ok(MergeStrategy::Theirs),
+        "three_way" => Ok(MergeStrategy::ThreeWay),
+        other => Err(format!("unknown merge strategy '{}'", other).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        cli().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_action_type() {
+        assert_eq!(
+            parse_action_type("tool_call").unwrap(),
+            ActionType::ToolCall
+        );
+        assert_eq!(
+            parse_action_type("custom:plan").unwrap(),
+            ActionType::Custom("plan".to_string())
+        );
+        assert!(parse_action_type("nope").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Human-readable and JSON renderings of command results.
+
+use agit_core::gc::GcResult;
+use agit_core::{Commit, FsckReport, Hash, LogEntry, StateDiff};
+use serde_json::{json, Value};
+
+use crate::Result;
+
+/// One line per commit: short hash, action type and message.
+pub fn log(commits: &[(Hash, Commit)]) -> String {
+    if commits.is_empty() {
+        return "No commits yet.".to_string();
+    }
+    commits
+        .iter()
+        .map(|(hash, c)| {
+            let merge = if c.parent_hashes.len() > 1 {
+                " (merge)"
+            } else {
+                ""
+            };
+            format!(
+                "{} {} [{}] {}{}",
+                hash.short(),
+                c.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                c.action_type,
+                c.message,
+                merge
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A commit as JSON, with its hash added.
+pub fn commit_json((hash, commit): &(Hash, Commit)) -> Result<Value> {
+    let mut value = serde_json::to_value(commit)?;
+    if let Value::Object(map) = &mut value {
+        map.insert("hash".to_string(), Value::from(hash.as_str()));
+    }
+    Ok(value)
+}
+
+pub fn diff(diff: &StateDiff) -> String {
+    if diff.entries.is_empty() {
+        return "No differences.".to_string();
+    }
+    diff.render()
+}
+
+/// Branch names, sorted, with the current one starred.
+pub fn branches(branches: &[(&String, &Hash)], current: Option<&str>) -> String {
+    branches
+        .iter()
+        .map(|(name, hash)| {
+            let marker = if current == Some(name.as_str()) {
+                '*'
+            } else {
+                ' '
+            };
+            format!("{} {} {}", marker, name, hash.short())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn gc(result: &GcResult) -> String {
+    if result.dry_run {
+        format!(
+            "Would remove {} of {} objects ({} bytes)",
+            result.unreachable_count, result.objects_before, result.freed.bytes
+        )
+    } else {
+        format!(
+            "Removed {} of {} objects ({} bytes freed)",
+            result.objects_removed, result.objects_before, result.freed.bytes
+        )
+    }
+}
+
+pub fn gc_json(result: &GcResult) -> Value {
+    json!({
+        "dry_run": result.dry_run,
+        "objects_before": result.objects_before,
+        "objects_removed": result.objects_removed,
+        "objects_after": result.objects_after,
+        "unreachable": result.unreachable,
+        "unreachable_count": result.unreachable_count,
+        "bytes_freed": result.freed.bytes,
+    })
+}
+
+/// One line per entry: timestamp, agent, action and message.
+pub fn audit(entries: &[LogEntry]) -> String {
+    if entries.is_empty() {
+        return "No audit entries.".to_string();
+    }
+    entries
+        .iter()
+        .map(|e| format!("{} {} {}: {}", e.timestamp, e.agent_id, e.action, e.message))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn fsck(report: &FsckReport) -> String {
+    let mut lines = vec![format!(
+        "Checked {} objects: {}",
+        report.objects_checked,
+        if report.is_ok() {
+            "ok"
+        } else {
+            "problems found"
+        }
+    )];
+    lines.extend(report.missing.iter().map(|h| format!("missing {}", h)));
+    lines.extend(
+        report
+            .hash_mismatches
+            .iter()
+            .map(|m| format!("corrupt {} (content hashes to {})", m.key, m.actual)),
+    );
+    lines.extend(report.repaired.iter().map(|h| format!("repaired {}", h)));
+    if !report.orphans.is_empty() {
+        lines.push(format!(
+            "{} unreachable objects (run `agit gc`)",
+            report.orphans.len()
+        ));
+    }
+    lines.join("\n")
+}
+
+pub fn fsck_json(report: &FsckReport) -> Value {
+    json!({
+        "ok": report.is_ok(),
+        "objects_checked": report.objects_checked,
+        "missing": report.missing,
+        "hash_mismatches": report
+            .hash_mismatches
+            .iter()
+            .map(|m| json!({ "key": m.key, "actual": m.actual }))
+            .collect::<Vec<_>>(),
+        "orphans": report.orphans,
+        "repaired": report.repaired,
+    })
+}
@@ -0,0 +1,178 @@
+//! Opening the storage backend named by `--repo` or `--storage`.
+
+use std::path::{Path, PathBuf};
+
+use agit_core::{SqliteStorage, StorageBackend};
+
+use crate::Result;
+
+/// Where a repository lives.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    /// SQLite database file.
+    Sqlite(PathBuf),
+    /// Postgres connection string and tenant namespace.
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    Postgres { dsn: String, namespace: String },
+    /// S3 bucket and key prefix.
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    S3 { bucket: String, prefix: String },
+}
+
+impl Location {
+    /// The repository at `storage` if given, else the SQLite repository in
+    /// the `repo` directory.
+    pub fn new(repo: &str, storage: Option<&str>) -> Result<Self> {
+        match storage {
+            Some(url) => Self::from_url(url),
+            None => Ok(Location::Sqlite(sqlite_db_path(repo))),
+        }
+    }
+
+    /// Parse `postgres://…` (a `namespace` query parameter selects the
+    /// tenant), `s3://bucket/prefix`, `sqlite://path` or a plain path.
+    pub fn from_url(url: &str) -> Result<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            let (dsn, namespace) = split_namespace(url);
+            return Ok(Location::Postgres {
+                dsn,
+                namespace: namespace.unwrap_or_default(),
+            });
+        }
+        if let Some(rest) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(format!("no bucket in '{}'", url).into());
+            }
+            let prefix = prefix.trim_matches('/');
+            return Ok(Location::S3 {
+                bucket: bucket.to_string(),
+                prefix: if prefix.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", prefix)
+                },
+            });
+        }
+        let path = url.strip_prefix("sqlite://").unwrap_or(url);
+        Ok(Location::Sqlite(sqlite_db_path(path)))
+    }
+
+    /// Connect to the repository's storage. Unless `create` is set, a
+    /// SQLite repository must already exist.
+    pub async fn connect(&self, create: bool) -> Result<Box<dyn StorageBackend>> {
+        match self {
+            Location::Sqlite(path) => {
+                if create {
+                    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                        std::fs::create_dir_all(dir)?;
+                    }
+                } else if !path.exists() {
+                    return Err(format!(
+                        "no agit repository at {} (run `agit init` first)",
+                        path.display()
+                    )
+                    .into());
+                }
+                Ok(Box::new(SqliteStorage::new(&path.to_string_lossy()).await?))
+            }
+            #[cfg(feature = "postgres")]
+            Location::Postgres { dsn, namespace } => Ok(Box::new(
+                agit_core::storage::PostgresStorage::new_scoped(dsn, namespace).await?,
+            )),
+            #[cfg(not(feature = "postgres"))]
+            Location::Postgres { .. } => Err(backend_unavailable("postgres")),
+            #[cfg(feature = "s3")]
+            Location::S3 { bucket, prefix } => Ok(Box::new(
+                agit_core::storage::S3Storage::new(bucket.clone(), prefix.clone(), None).await?,
+            )),
+            #[cfg(not(feature = "s3"))]
+            Location::S3 { .. } => Err(backend_unavailable("s3")),
+        }
+    }
+
+    /// Human-readable description for messages.
+    pub fn describe(&self) -> String {
+        match self {
+            Location::Sqlite(path) => path.display().to_string(),
+            Location::Postgres { namespace, .. } if namespace.is_empty() => "postgres".to_string(),
+            Location::Postgres { namespace, .. } => format!("postgres namespace '{}'", namespace),
+            Location::S3 { bucket, prefix } => format!("s3://{}/{}", bucket, prefix),
+        }
+    }
+}
+
+/// `path` itself if it names a `.db` file or `:memory:`, otherwise
+/// `agit.db` inside it, matching the Python binding's layout.
+fn sqlite_db_path(path: &str) -> PathBuf {
+    if path.ends_with(".db") || path == ":memory:" {
+        PathBuf::from(path)
+    } else {
+        Path::new(path).join("agit.db")
+    }
+}
+
+/// Remove a `namespace` query parameter from a Postgres URL, which the
+/// driver would reject, returning the remaining URL and the namespace.
+fn split_namespace(url: &str) -> (String, Option<String>) {
+    let Some((base, query)) = url.split_once('?') else {
+        return (url.to_string(), None);
+    };
+    let mut namespace = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| match pair.strip_prefix("namespace=") {
+            Some(value) => {
+                namespace = Some(value.to_string());
+                false
+            }
+            None => !pair.is_empty(),
+        })
+        .collect();
+    if rest.is_empty() {
+        (base.to_string(), namespace)
+    } else {
+        (format!("{}?{}", base, rest.join("&")), namespace)
+    }
+}
+
+#[cfg(not(all(feature = "postgres", feature = "s3")))]
+fn backend_unavailable(feature: &str) -> crate::Error {
+    format!(
+        "{} backend not available: agit was built without the `{}` feature",
+        feature, feature
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locations() {
+        assert_eq!(
+            Location::new("repo", None).unwrap(),
+            Location::Sqlite(PathBuf::from("repo/agit.db"))
+        );
+        assert_eq!(
+            Location::new(".", Some("sqlite://state.db")).unwrap(),
+            Location::Sqlite(PathBuf::from("state.db"))
+        );
+        assert_eq!(
+            Location::from_url("postgres://u@h/db?sslmode=require&namespace=t1").unwrap(),
+            Location::Postgres {
+                dsn: "postgres://u@h/db?sslmode=require".to_string(),
+                namespace: "t1".to_string(),
+            }
+        );
+        assert_eq!(
+            Location::from_url("s3://bucket/a/b/").unwrap(),
+            Location::S3 {
+                bucket: "bucket".to_string(),
+                prefix: "a/b/".to_string(),
+            }
+        );
+        assert!(Location::from_url("s3:///x").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! End-to-end tests driving the `agit` binary against a SQLite repository.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+fn agit(repo: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_agit"))
+        .arg("--repo")
+        .arg(repo)
+        .args(args)
+        .output()
+        .expect("failed to run agit")
+}
+
+/// Run a command that must succeed and return its stdout.
+fn ok(repo: &Path, args: &[&str]) -> String {
+    let out = agit(repo, args);
+    assert!(
+        out.status.success(),
+        "agit {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+fn json(repo: &Path, args: &[&str]) -> Value {
+    let mut args = args.to_vec();
+    args.push("--json");
+    serde_json::from_str(&ok(repo, &args)).unwrap()
+}
+
+/// Commit `memory` and return the new commit's hash.
+fn commit(repo: &Path, memory: &str, message: &str) -> String {
+    let file = repo.join("memory.json");
+    std::fs::write(&file, memory).unwrap();
+    let out = json(
+        repo,
+        &[
+            "commit",
+            "--memory-file",
+            file.to_str().unwrap(),
+            "-m",
+            message,
+        ],
+    );
+    out["hash"].as_str().unwrap().to_string()
+}
+
+fn init() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    ok(dir.path(), &["init"]);
+    dir
+}
+
+#[test]
+fn test_commands_need_a_repository() {
+    let dir = TempDir::new().unwrap();
+    let out = agit(dir.path(), &["log"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("agit init"));
+}
+
+#[test]
+fn test_commit_log_and_diff() {
+    let dir = init();
+    let first = commit(dir.path(), r#"{"step": 1, "plan": "a"}"#, "first");
+    let second = commit(dir.path(), r#"{"step": 2, "done": true}"#, "second");
+
+    let log = json(dir.path(), &["log"]);
+    let hashes: Vec<&str> = log
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["hash"].as_str().unwrap())
+        .collect();
+    assert_eq!(hashes, vec![second.as_str(), first.as_str()]);
+    assert_eq!(log[0]["message"], "second");
+    assert_eq!(log[0]["author"], "cli");
+
+    let text = ok(dir.path(), &["log", "-n", "1"]);
+    assert_eq!(text.lines().count(), 1);
+    assert!(text.starts_with(&second[..8]));
+    assert!(text.contains("[checkpoint] second"));
+
+    let diff = ok(dir.path(), &["diff", &first, &second]);
+    assert!(diff.contains("~ memory.step: 1 -> 2"));
+    assert!(diff.contains("- memory.plan: \"a\""));
+    assert!(diff.contains("+ memory.done: true"));
+    let entries = json(dir.path(), &["diff", &first, &second]);
+    let memory_changes = entries["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["path"][0] == "memory")
+        .count();
+    assert_eq!(memory_changes, 3);
+}
+
+#[test]
+fn test_branch_checkout_and_merge() {
+    let dir = init();
+    commit(dir.path(), r#"{"step": 1}"#, "base");
+    ok(dir.path(), &["branch", "feature"]);
+    ok(dir.path(), &["checkout", "feature"]);
+    let theirs = commit(dir.path(), r#"{"step": 1, "feature": true}"#, "on feature");
+    ok(dir.path(), &["checkout", "main"]);
+
+    let branches = ok(dir.path(), &["branch"]);
+    assert!(branches.contains("  feature"));
+    assert!(branches.contains("* main"));
+
+    let merged = json(dir.path(), &["merge", "feature", "--strategy", "theirs"]);
+    assert_eq!(merged["hash"], theirs.as_str());
+
+    ok(dir.path(), &["branch", "-d", "feature"]);
+    let branches = json(dir.path(), &["branch"]);
+    assert_eq!(branches["current"], "main");
+    assert!(branches["branches"].get("feature").is_none());
+}
+
+#[test]
+fn test_delete_checked_out_branch() {
+    let dir = init();
+    commit(dir.path(), r#"{"step": 1}"#, "base");
+    ok(dir.path(), &["branch", "scratch"]);
+    ok(dir.path(), &["checkout", "scratch"]);
+    let tip = commit(dir.path(), r#"{"step": 2}"#, "on scratch");
+
+    let out = agit(dir.path(), &["branch", "-d", "scratch"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("checked out"));
+
+    ok(dir.path(), &["branch", "-d", "scratch", "--force"]);
+    let branches = json(dir.path(), &["branch"]);
+    assert!(branches["current"].is_null());
+    assert!(branches["branches"].get("scratch").is_none());
+    assert_eq!(json(dir.path(), &["log"])[0]["hash"], tip.as_str());
+}
+
+#[test]
+fn test_revert_commits_earlier_state() {
+    let dir = init();
+    let first = commit(dir.path(), r#"{"step": 1}"#, "first");
+    let second = commit(dir.path(), r#"{"step": 2}"#, "second");
+
+    let reverted = json(dir.path(), &["revert", &first]);
+    let head = reverted["hash"].as_str().unwrap();
+    assert_ne!(head, second);
+    assert!(ok(dir.path(), &["diff", &first, head]).contains("No differences."));
+}
+
+#[test]
+fn test_gc_audit_and_fsck() {
+    let dir = init();
+    commit(dir.path(), r#"{"step": 0}"#, "base");
+    ok(dir.path(), &["branch", "scratch"]);
+    ok(dir.path(), &["checkout", "scratch"]);
+    commit(dir.path(), r#"{"step": 1}"#, "scratch work");
+    ok(dir.path(), &["checkout", "main"]);
+    ok(dir.path(), &["branch", "--delete", "scratch"]);
+
+    let dry = json(dir.path(), &["gc", "--dry-run"]);
+    assert_eq!(dry["objects_removed"], 0);
+    let unreachable = dry["unreachable_count"].as_u64().unwrap();
+    assert!(unreachable > 0);
+
+    let fsck = json(dir.path(), &["fsck"]);
+    assert_eq!(fsck["ok"], true);
+    assert_eq!(
+        fsck["orphans"].as_array().unwrap().len() as u64,
+        unreachable
+    );
+
+    let real = json(dir.path(), &["gc"]);
+    assert_eq!(real["objects_removed"].as_u64().unwrap(), unreachable);
+    assert!(ok(dir.path(), &["fsck"]).contains("ok"));
+
+    let audit = json(dir.path(), &["audit", "--agent", "cli"]);
+    assert!(audit
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|e| e["action"] == "checkpoint"));
+    assert_eq!(
+        json(dir.path(), &["audit", "--agent", "someone-else"]),
+        Value::Array(vec![])
+    );
+    let future = json(dir.path(), &["audit", "--since", "2999-01-01T00:00:00Z"]);
+    assert_eq!(future, Value::Array(vec![]));
+}
+
+#[test]
+fn test_unavailable_storage_backend() {
+    let dir = TempDir::new().unwrap();
+    let out = agit(dir.path(), &["--storage", "s3://bucket/prefix", "log"]);
+    if cfg!(not(feature = "s3")) {
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("without the `s3` feature"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Diff benchmarks: cached Merkle trees vs building both trees per call.
+//!
+//! Run with `cargo bench -p agit-core --bench diff`. Pass a number to change
+//! the iteration count, e.g. `cargo bench --bench diff -- 50`.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+
+use agit_core::{merkle_diff, ActionType, AgentState, Hash, Repository, SqliteStorage};
+
+/// A memory object with `keys` entries of roughly 100 bytes each.
+fn large_memory(keys: usize, changed: usize) -> Value {
+    let mut map = Map::new();
+    for i in 0..keys {
+        let value = if i == changed { "changed" } else { "original" };
+        map.insert(
+            format!("fact_{:06}", i),
+            json!({ "text": format!("{}-{:0>80}", value, i), "score": i }),
+        );
+    }
+    Value::Object(map)
+}
+
+async fn setup(keys: usize) -> (Repository, Hash, Hash) {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    let s1 = AgentState::new(large_memory(keys, usize::MAX), json!({}));
+    let h1 = repo
+        .commit(&s1, "base", ActionType::Checkpoint)
+        .await
+        .unwrap();
+    let mut s2 = s1.clone();
+    s2.memory = large_memory(keys, keys / 2);
+    let h2 = repo
+        .commit(&s2, "one change", ActionType::Checkpoint)
+        .await
+        .unwrap();
+    (repo, h1, h2)
+}
+
+/// Mean wall time of `iterations` runs of `f`, after one warm-up run.
+async fn time<F, Fut, T>(iterations: u32, mut f: F) -> Duration
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = T>,
+{
+    std::hint::black_box(f().await);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(f().await);
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; the first numeric argument is the iteration count
+    let iterations = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(20);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    println!("{:>8}  {:>12}  {:>12}", "keys", "cached", "uncached");
+    for keys in [1_000, 10_000, 100_000] {
+        rt.block_on(async {
+            let (repo, h1, h2) = setup(keys).await;
+
+            let cached = time(iterations, || repo.diff(h1.as_str(), h2.as_str())).await;
+            let uncached = time(iterations, || async {
+                let v1 = repo.get_state(h1.as_str()).await.unwrap().to_value();
+                let v2 = repo.get_state(h2.as_str()).await.unwrap().to_value();
+                merkle_diff(&v1, &v2)
+            })
+            .await;
+
+            println!("{:>8}  {:>12.2?}  {:>12.2?}", keys, cached, uncached);
+        });
+    }
+}
@@ -0,0 +1,68 @@
+//! `log(None, 500)` against PostgreSQL with and without the object cache.
+//!
+//! Needs a database: run with
+//! `AGIT_BENCH_POSTGRES_URL=postgres://... cargo bench -p agit-core --features postgres --bench log`.
+//! Pass a number to change the iteration count, e.g. `-- 50`.
+
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use agit_core::storage::PostgresStorage;
+use agit_core::{ActionType, AgentState, RepoOptions, Repository};
+
+const COMMITS: usize = 500;
+
+async fn open(url: &str, namespace: &str, cache: bool) -> Repository {
+    let storage = PostgresStorage::new_scoped(url, namespace).await.unwrap();
+    let options = RepoOptions {
+        cache_max_entries: if cache { 4096 } else { 0 },
+        ..Default::default()
+    };
+    Repository::init_with_options(Box::new(storage), options)
+        .await
+        .unwrap()
+}
+
+/// Mean wall time of `iterations` calls to `log(None, 500)`, after one warm-up call.
+async fn time_log(repo: &Repository, iterations: u32) -> Duration {
+    std::hint::black_box(repo.log(None, COMMITS).await.unwrap());
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(repo.log(None, COMMITS).await.unwrap());
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    let Ok(url) = std::env::var("AGIT_BENCH_POSTGRES_URL") else {
+        eprintln!("AGIT_BENCH_POSTGRES_URL is not set; skipping");
+        return;
+    };
+    // `cargo bench` passes `--bench`; the first numeric argument is the iteration count
+    let iterations = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(20);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async {
+        let namespace = format!("bench-log-{}", uuid::Uuid::new_v4());
+        let mut repo = open(&url, &namespace, false).await;
+        for step in 0..COMMITS {
+            let state = AgentState::new(json!({ "step": step }), json!({}));
+            repo.commit(&state, "step", ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
+
+        let uncached = time_log(&repo, iterations).await;
+        let repo = open(&url, &namespace, true).await;
+        let cached = time_log(&repo, iterations).await;
+        let stats = repo.cache_stats();
+
+        println!("{:>12}  {:>12}", "uncached", "cached");
+        println!("{:>12.2?}  {:>12.2?}", uncached, cached);
+        println!("cache hits: {}, misses: {}", stats.hits, stats.misses);
+    });
+}
@@ -0,0 +1,63 @@
+//! Cost of re-hashing log results, which `log` now returns with each
+//! commit's hash instead of leaving callers to recompute it.
+//!
+//! Run with `cargo bench -p agit-core --bench log_hash`. Pass a number to
+//! change the iteration count, e.g. `cargo bench --bench log_hash -- 50`.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use agit_core::{ActionType, AgentState, Repository, SqliteStorage};
+
+async fn setup(commits: usize) -> Repository {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    for step in 0..commits {
+        let state = AgentState::new(json!({ "step": step }), json!({}));
+        repo.commit(&state, "step", ActionType::ToolCall)
+            .await
+            .unwrap();
+    }
+    repo
+}
+
+/// Mean wall time of `iterations` runs of `f`, after one warm-up run.
+async fn time<F, Fut, T>(iterations: u32, mut f: F) -> Duration
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = T>,
+{
+    std::hint::black_box(f().await);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(f().await);
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; the first numeric argument is the iteration count
+    let iterations = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(20);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    println!("{:>8}  {:>12}  {:>12}", "commits", "log", "re-hashed");
+    for commits in [100, 1_000] {
+        rt.block_on(async {
+            let repo = setup(commits).await;
+
+            let log = time(iterations, || repo.log(None, commits)).await;
+            let rehashed = time(iterations, || async {
+                let log = repo.log(None, commits).await.unwrap();
+                log.iter().map(|(_, c)| c.hash()).collect::<Vec<_>>()
+            })
+            .await;
+
+            println!("{:>8}  {:>12.2?}  {:>12.2?}", commits, log, rehashed);
+        });
+    }
+}
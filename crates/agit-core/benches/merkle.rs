@@ -0,0 +1,40 @@
+//! Merkle tree construction: sequential vs rayon-parallel.
+//!
+//! Run with `cargo bench -p agit-core --features parallel --bench merkle`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::{json, Map, Value};
+
+use agit_core::MerkleNode;
+
+/// A state with `keys` memory entries, each a small object.
+fn synthetic_state(keys: usize) -> Value {
+    let mut memory = Map::new();
+    for i in 0..keys {
+        memory.insert(
+            format!("fact_{:06}", i),
+            json!({ "text": format!("observation number {}", i), "score": i, "tags": ["a", "b"] }),
+        );
+    }
+    json!({ "memory": memory, "world_state": {}, "cost": 0.0 })
+}
+
+fn bench_merkle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merkle_from_value");
+    group.sample_size(20);
+
+    for keys in [10_000, 100_000] {
+        let state = synthetic_state(keys);
+        group.bench_with_input(BenchmarkId::new("sequential", keys), &state, |b, v| {
+            b.iter(|| MerkleNode::from_value_sequential(v))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", keys), &state, |b, v| {
+            b.iter(|| MerkleNode::from_value_parallel(v))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_merkle);
+criterion_main!(benches);
@@ -0,0 +1,544 @@
+//! Audit log integrity chain.
+//!
+//! Every entry written by `Repository::log_action` stores an `integrity_hash`
+//! in its details, computed over the entry fields and the previous entry's
+//! hash for the same agent. Verification replays that chain per agent.
+//!
+//! Entries at `chain_version` 2 also record the [`CommitProvenance`] of the
+//! commit they reference and cover it in their hash. Entries without a
+//! version use the original formula and still verify.
+//!
+//! In repositories with an encryption key, an entry's message and the
+//! details its writer supplied are encrypted at rest, the details under
+//! [`ENCRYPTED_DETAILS_KEY`]. Agent, action, timestamp, commit provenance
+//! and the chain fields stay in plaintext for filtering, but chain hashes
+//! cover the plaintext message, so such entries only verify with the key.
+//!
+//! Log retention deletes the oldest entries of a chain. The summary entry it
+//! writes records, per agent, the previous hash of the oldest surviving
+//! entry; verification accepts a chain that starts from such an anchor.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::hash::canonical_serialize;
+use crate::objects::Commit;
+use crate::storage::LogEntry;
+use crate::types::ActionType;
+
+/// Details key holding an entry's chain hash.
+pub const INTEGRITY_HASH_KEY: &str = "integrity_hash";
+
+/// Details key holding the previous entry's chain hash.
+pub const PREV_HASH_KEY: &str = "prev_integrity_hash";
+
+/// Details key holding the version of the hash formula an entry was
+/// chained with. Absent on version 1 entries.
+pub const CHAIN_VERSION_KEY: &str = "chain_version";
+
+/// Hash formula version written by this release.
+pub const CHAIN_VERSION: u64 = 2;
+
+/// Details key holding the encrypted details of an entry written with an
+/// encryption key set.
+pub const ENCRYPTED_DETAILS_KEY: &str = "encrypted_details";
+
+/// Action of the summary entry written by `Repository::apply_log_retention`.
+pub const RETENTION_ACTION: &str = "log_retention";
+
+/// Details key of a retention summary mapping each agent to the previous
+/// hash of its oldest surviving entry.
+pub const RETENTION_ANCHORS_KEY: &str = "retention_anchors";
+
+/// First entry whose chain hash does not verify.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditBreak {
+    /// Agent whose chain is broken.
+    pub agent_id: String,
+    /// Position of the entry in that agent's chain, oldest first.
+    pub index: usize,
+    /// ID of the offending entry.
+    pub id: String,
+}
+
+/// Result of verifying the audit log hash chain.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditVerification {
+    /// Number of entries whose chain hash was recomputed.
+    pub entries_checked: usize,
+    /// Leading entries written before chaining existed; not verifiable.
+    pub legacy_entries: usize,
+    /// Earliest broken link, if any.
+    pub broken: Option<AuditBreak>,
+}
+
+impl AuditVerification {
+    /// True if no broken link was found.
+    pub fn is_valid(&self) -> bool {
+        self.broken.is_none()
+    }
+}
+
+/// What the commit referenced by an audit entry contains. Stored flattened
+/// in the entry's details so a reviewer can check the produced state
+/// without loading the commit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitProvenance {
+    /// Hash of the commit's state blob.
+    pub tree_hash: String,
+    /// Hashes of the commit's parents.
+    pub parent_hashes: Vec<String>,
+    /// Byte size of the canonically serialized state.
+    pub state_size: u64,
+    /// Action type recorded on the commit.
+    pub action_type: ActionType,
+}
+
+impl CommitProvenance {
+    /// Provenance of `commit`, whose state serializes to `state_size` bytes.
+    pub fn of(commit: &Commit, state_size: u64) -> Self {
+        Self {
+            tree_hash: commit.tree_hash.to_string(),
+            parent_hashes: commit.parent_hashes.iter().map(|h| h.to_string()).collect(),
+            state_size,
+            action_type: commit.action_type.clone(),
+        }
+    }
+
+    /// Read the provenance fields from an entry's details, if present.
+    pub fn from_details(details: &serde_json::Value) -> Option<Self> {
+        details.get("tree_hash")?;
+        serde_json::from_value(details.clone()).ok()
+    }
+}
+
+/// Compute the version 1 chain hash for an audit entry.
+pub(crate) fn compute_audit_hash(
+    id: &str,
+    timestamp: &str,
+    agent_id: &str,
+    action: &str,
+    message: &str,
+    commit_hash: &str,
+    prev_hash: Option<&str>,
+) -> String {
+    let hasher = entry_hasher(
+        id,
+        timestamp,
+        agent_id,
+        action,
+        message,
+        commit_hash,
+        prev_hash,
+    );
+    format!("{:x}", hasher.finalize())
+}
+
+/// Compute the version 2 chain hash, which also covers the provenance of
+/// the referenced commit.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn compute_audit_hash_v2(
+    id: &str,
+    timestamp: &str,
+    agent_id: &str,
+    action: &str,
+    message: &str,
+    commit_hash: &str,
+    prev_hash: Option<&str>,
+    provenance: Option<&CommitProvenance>,
+) -> String {
+    let mut hasher = entry_hasher(
+        id,
+        timestamp,
+        agent_id,
+        action,
+        message,
+        commit_hash,
+        prev_hash,
+    );
+    hasher.update(b"|v2|");
+    if let Some(provenance) = provenance {
+        let value = serde_json::to_value(provenance).unwrap_or_default();
+        hasher.update(canonical_serialize(&value));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn entry_hasher(
+    id: &str,
+    timestamp: &str,
+    agent_id: &str,
+    action: &str,
+    message: &str,
+    commit_hash: &str,
+    prev_hash: Option<&str>,
+) -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update(id.as_bytes());
+    hasher.update(b"|");
+    hasher.update(timestamp.as_bytes());
+    hasher.update(b"|");
+    hasher.update(agent_id.as_bytes());
+    hasher.update(b"|");
+    hasher.update(action.as_bytes());
+    hasher.update(b"|");
+    hasher.update(message.as_bytes());
+    hasher.update(b"|");
+    hasher.update(commit_hash.as_bytes());
+    hasher.update(b"|");
+    hasher.update(prev_hash.unwrap_or("").as_bytes());
+    hasher
+}
+
+/// Recompute an entry's chain hash with the formula its version names.
+/// `None` for versions this release does not know.
+fn expected_hash(entry: &LogEntry, prev: Option<&str>) -> Option<String> {
+    let commit_hash = entry.commit_hash.as_deref().unwrap_or("");
+    let version = entry
+        .details
+        .as_ref()
+        .and_then(|d| d.get(CHAIN_VERSION_KEY))
+        .map(|v| v.as_u64());
+    match version {
+        None => Some(compute_audit_hash(
+            &entry.id,
+            &entry.timestamp,
+            &entry.agent_id,
+            &entry.action,
+            &entry.message,
+            commit_hash,
+            prev,
+        )),
+        Some(Some(2)) => {
+            let provenance = entry
+                .details
+                .as_ref()
+                .and_then(CommitProvenance::from_details);
+            Some(compute_audit_hash_v2(
+                &entry.id,
+                &entry.timestamp,
+                &entry.agent_id,
+                &entry.action,
+                &entry.message,
+                commit_hash,
+                prev,
+                provenance.as_ref(),
+            ))
+        }
+        Some(_) => None,
+    }
+}
+
+fn integrity_hash(entry: &LogEntry) -> Option<&str> {
+    detail_str(entry, INTEGRITY_HASH_KEY)
+}
+
+fn detail_str<'a>(entry: &'a LogEntry, key: &str) -> Option<&'a str> {
+    entry
+        .details
+        .as_ref()
+        .and_then(|d| d.get(key))
+        .and_then(|v| v.as_str())
+}
+
+/// Chain anchors recorded by the retention summaries among `entries`: for
+/// each agent, the hashes its oldest surviving entry may link back to.
+pub fn retention_anchors(entries: &[LogEntry]) -> HashMap<String, HashSet<String>> {
+    let mut anchors: HashMap<String, HashSet<String>> = HashMap::new();
+    for entry in entries.iter().filter(|e| e.action == RETENTION_ACTION) {
+        let Some(recorded) = entry
+            .details
+            .as_ref()
+            .and_then(|d| d.get(RETENTION_ANCHORS_KEY))
+            .and_then(|v| v.as_object())
+        else {
+            continue;
+        };
+        for (agent_id, hash) in recorded {
+            if let Some(hash) = hash.as_str() {
+                anchors
+                    .entry(agent_id.clone())
+                    .or_default()
+                    .insert(hash.to_string());
+            }
+        }
+    }
+    anchors
+}
+
+/// Verify the hash chain of `entries`, which must be ordered oldest first.
+///
+/// Chains are checked independently per agent, and each entry with the hash
+/// formula of its `chain_version`. Entries without an integrity hash are
+/// tolerated only before an agent's first chained entry, and that entry may
+/// link to a hash anchored by a retention summary in `entries`.
+pub fn verify_chain(entries: &[LogEntry]) -> AuditVerification {
+    verify_chain_anchored(entries, &retention_anchors(entries))
+}
+
+/// Like [`verify_chain`], with the retention anchors supplied by the caller.
+pub fn verify_chain_anchored(
+    entries: &[LogEntry],
+    anchors: &HashMap<String, HashSet<String>>,
+) -> AuditVerification {
+    let mut by_agent: BTreeMap<&str, Vec<&LogEntry>> = BTreeMap::new();
+    for entry in entries {
+        by_agent.entry(&entry.agent_id).or_default().push(entry);
+    }
+
+    let mut result = AuditVerification::default();
+    // (timestamp, break) of the earliest break across agents
+    let mut earliest: Option<(&str, AuditBreak)> = None;
+
+    for (agent_id, chain) in by_agent {
+        let mut prev: Option<&str> = None;
+        let mut started = false;
+
+        for (index, entry) in chain.iter().enumerate() {
+            let stored = integrity_hash(entry);
+            if stored.is_none() && !started {
+                result.legacy_entries += 1;
+                continue;
+            }
+            if !started {
+                // Earlier entries may have been removed by log retention
+                prev = detail_str(entry, PREV_HASH_KEY)
+                    .filter(|p| anchors.get(agent_id).is_some_and(|a| a.contains(*p)));
+            }
+            started = true;
+            result.entries_checked += 1;
+
+            let expected = expected_hash(entry, prev);
+            if stored != expected.as_deref() {
+                let brk = AuditBreak {
+                    agent_id: agent_id.to_string(),
+                    index,
+                    id: entry.id.clone(),
+                };
+                if earliest
+                    .as_ref()
+                    .is_none_or(|(ts, _)| entry.timestamp.as_str() < *ts)
+                {
+                    earliest = Some((&entry.timestamp, brk));
+                }
+                break;
+            }
+            prev = stored;
+        }
+    }
+
+    result.broken = earliest.map(|(_, brk)| brk);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+    use serde_json::json;
+
+    fn chain(agent: &str, n: usize, start: usize) -> Vec<LogEntry> {
+        let mut prev: Option<String> = None;
+        (0..n)
+            .map(|i| {
+                let id = format!("{}-{}", agent, i);
+                let timestamp = format!("2026-01-01T00:00:{:02}+00:00", start + i * 2);
+                let hash = compute_audit_hash(
+                    &id,
+                    &timestamp,
+                    agent,
+                    "commit",
+                    "msg",
+                    "",
+                    prev.as_deref(),
+                );
+                let entry = LogEntry {
+                    id,
+                    timestamp,
+                    agent_id: agent.to_string(),
+                    action: "commit".to_string(),
+                    message: "msg".to_string(),
+                    commit_hash: None,
+                    details: Some(json!({ "integrity_hash": hash, "prev_integrity_hash": prev })),
+                    level: LogLevel::Info,
+                };
+                prev = Some(hash);
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_chain() {
+        let entries = chain("a", 3, 0);
+        let result = verify_chain(&entries);
+        assert!(result.is_valid());
+        assert_eq!(result.entries_checked, 3);
+    }
+
+    #[test]
+    fn test_tampered_entry() {
+        let mut entries = chain("a", 3, 0);
+        entries[1].message = "rewritten".to_string();
+        let brk = verify_chain(&entries).broken.unwrap();
+        assert_eq!(brk.index, 1);
+        assert_eq!(brk.id, "a-1");
+    }
+
+    #[test]
+    fn test_deleted_and_reordered_entries() {
+        let mut entries = chain("a", 3, 0);
+        entries.remove(1);
+        assert_eq!(verify_chain(&entries).broken.unwrap().id, "a-2");
+
+        let mut entries = chain("a", 3, 0);
+        entries.swap(0, 1);
+        assert_eq!(verify_chain(&entries).broken.unwrap().index, 0);
+
+        // Deleting the first chained entry is also detected
+        let mut entries = chain("a", 3, 0);
+        entries.remove(0);
+        assert_eq!(verify_chain(&entries).broken.unwrap().id, "a-1");
+    }
+
+    #[test]
+    fn test_multi_agent_interleaved() {
+        let mut entries = chain("a", 3, 0);
+        entries.extend(chain("b", 3, 1));
+        entries.sort_by(|x, y| x.timestamp.cmp(&y.timestamp));
+        assert!(verify_chain(&entries).is_valid());
+
+        let pos = entries.iter().position(|e| e.id == "b-2").unwrap();
+        entries[pos].action = "delete_branch".to_string();
+        let brk = verify_chain(&entries).broken.unwrap();
+        assert_eq!(brk.agent_id, "b");
+        assert_eq!(brk.index, 2);
+    }
+
+    #[test]
+    fn test_legacy_entries_skipped() {
+        let mut legacy = chain("a", 2, 0);
+        for e in &mut legacy {
+            e.details = None;
+        }
+        let mut entries = legacy;
+        entries.extend(chain("a", 2, 10));
+        let result = verify_chain(&entries);
+        assert!(result.is_valid());
+        assert_eq!(result.legacy_entries, 2);
+        assert_eq!(result.entries_checked, 2);
+
+        // An unchained entry after chaining started is a break
+        let mut unchained = chain("a", 1, 30).remove(0);
+        unchained.details = None;
+        entries.push(unchained);
+        assert_eq!(verify_chain(&entries).broken.unwrap().index, 4);
+    }
+
+    /// Append a version 2 entry for `agent` referencing a commit.
+    fn push_v2(entries: &mut Vec<LogEntry>, agent: &str, timestamp: &str) {
+        let prev = entries
+            .iter()
+            .rev()
+            .find(|e| e.agent_id == agent)
+            .and_then(|e| integrity_hash(e).map(|h| h.to_string()));
+        let id = format!("{}-v2-{}", agent, entries.len());
+        let provenance = CommitProvenance {
+            tree_hash: "tree".to_string(),
+            parent_hashes: vec!["parent".to_string()],
+            state_size: 42,
+            action_type: ActionType::ToolCall,
+        };
+        let hash = compute_audit_hash_v2(
+            &id,
+            timestamp,
+            agent,
+            "tool_call",
+            "msg",
+            "commit",
+            prev.as_deref(),
+            Some(&provenance),
+        );
+        let mut details = serde_json::to_value(&provenance).unwrap();
+        details[CHAIN_VERSION_KEY] = json!(2);
+        details[INTEGRITY_HASH_KEY] = json!(hash);
+        details[PREV_HASH_KEY] = json!(prev);
+        entries.push(LogEntry {
+            id,
+            timestamp: timestamp.to_string(),
+            agent_id: agent.to_string(),
+            action: "tool_call".to_string(),
+            message: "msg".to_string(),
+            commit_hash: Some("commit".to_string()),
+            details: Some(details),
+            level: LogLevel::Info,
+        });
+    }
+
+    #[test]
+    fn test_v1_and_v2_entries_in_one_chain() {
+        let mut entries = chain("a", 2, 0);
+        push_v2(&mut entries, "a", "2026-01-01T00:00:10+00:00");
+        push_v2(&mut entries, "a", "2026-01-01T00:00:11+00:00");
+        let result = verify_chain(&entries);
+        assert!(result.is_valid());
+        assert_eq!(result.entries_checked, 4);
+
+        // The provenance is covered by the hash
+        let mut tampered = entries.clone();
+        tampered[2].details.as_mut().unwrap()["state_size"] = json!(43);
+        assert_eq!(verify_chain(&tampered).broken.unwrap().index, 2);
+
+        let mut tampered = entries.clone();
+        tampered[3].details.as_mut().unwrap()["tree_hash"] = json!("other");
+        assert_eq!(verify_chain(&tampered).broken.unwrap().index, 3);
+
+        // Downgrading an entry to v1 does not verify either
+        let mut tampered = entries.clone();
+        let details = tampered[3]
+            .details
+            .as_mut()
+            .unwrap()
+            .as_object_mut()
+            .unwrap();
+        details.remove(CHAIN_VERSION_KEY);
+        assert_eq!(verify_chain(&tampered).broken.unwrap().index, 3);
+
+        // Unknown versions are reported as breaks
+        entries[3].details.as_mut().unwrap()[CHAIN_VERSION_KEY] = json!(3);
+        assert_eq!(verify_chain(&entries).broken.unwrap().index, 3);
+    }
+
+    #[test]
+    fn test_retention_anchor_accepts_truncated_chain() {
+        let mut entries = chain("a", 4, 0);
+        let anchor = detail_str(&entries[2], PREV_HASH_KEY).unwrap().to_string();
+        entries.drain(..2);
+        assert_eq!(verify_chain(&entries).broken.unwrap().id, "a-2");
+
+        let mut summary = chain("b", 1, 20).remove(0);
+        summary.action = RETENTION_ACTION.to_string();
+        summary.details.as_mut().unwrap()[RETENTION_ANCHORS_KEY] = json!({ "a": anchor });
+        // Hash recomputed after the action change
+        let hash = compute_audit_hash(
+            &summary.id,
+            &summary.timestamp,
+            "b",
+            RETENTION_ACTION,
+            "msg",
+            "",
+            None,
+        );
+        summary.details.as_mut().unwrap()[INTEGRITY_HASH_KEY] = json!(hash);
+        entries.push(summary.clone());
+        let result = verify_chain(&entries);
+        assert!(result.is_valid());
+        assert_eq!(result.entries_checked, 3);
+
+        // The anchor does not cover entries deleted after the summary
+        entries.remove(0);
+        assert_eq!(verify_chain(&entries).broken.unwrap().id, "a-3");
+    }
+}
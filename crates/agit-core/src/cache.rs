@@ -0,0 +1,216 @@
+//! Bounded LRU cache of objects read by `Repository`.
+//!
+//! Objects are content-addressed and immutable, so entries never go stale;
+//! they only leave the cache when evicted or when garbage collection
+//! deletes the underlying object.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::objects::Commit;
+
+/// Hit and miss counts of a repository's object cache, returned by
+/// `Repository::cache_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Objects currently cached.
+    pub entries: usize,
+    /// Serialized size of the cached objects.
+    pub bytes: usize,
+}
+
+#[derive(Clone)]
+enum CachedObject {
+    Commit(Arc<Commit>),
+    Blob(Arc<Vec<u8>>),
+}
+
+struct Entry {
+    object: CachedObject,
+    size: usize,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Last-use tick to key, oldest first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl Inner {
+    fn get(&mut self, hash: &str) -> Option<CachedObject> {
+        let Some(entry) = self.entries.get_mut(hash) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.tick += 1;
+        self.order.remove(&entry.tick);
+        self.order.insert(self.tick, hash.to_string());
+        entry.tick = self.tick;
+        Some(entry.object.clone())
+    }
+
+    fn remove(&mut self, hash: &str) {
+        if let Some(entry) = self.entries.remove(hash) {
+            self.order.remove(&entry.tick);
+            self.bytes -= entry.size;
+        }
+    }
+}
+
+/// LRU cache of parsed commits and raw blob bytes, keyed by hash.
+pub(crate) struct ObjectCache {
+    max_entries: usize,
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+}
+
+impl ObjectCache {
+    /// A cache holding at most `max_entries` objects and `max_bytes` of
+    /// serialized data; either limit at 0 disables caching.
+    pub(crate) fn new(max_entries: usize, max_bytes: usize) -> Self {
+        ObjectCache {
+            max_entries,
+            max_bytes,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_entries > 0 && self.max_bytes > 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn get_commit(&self, hash: &str) -> Option<Arc<Commit>> {
+        if !self.enabled() {
+            return None;
+        }
+        match self.lock().get(hash)? {
+            CachedObject::Commit(commit) => Some(commit),
+            CachedObject::Blob(_) => None,
+        }
+    }
+
+    pub(crate) fn get_blob(&self, hash: &str) -> Option<Arc<Vec<u8>>> {
+        if !self.enabled() {
+            return None;
+        }
+        match self.lock().get(hash)? {
+            CachedObject::Blob(data) => Some(data),
+            CachedObject::Commit(_) => None,
+        }
+    }
+
+    /// Cache a commit whose serialized form is `size` bytes.
+    pub(crate) fn insert_commit(&self, hash: &str, commit: Arc<Commit>, size: usize) {
+        self.insert(hash, CachedObject::Commit(commit), size);
+    }
+
+    pub(crate) fn insert_blob(&self, hash: &str, data: Arc<Vec<u8>>) {
+        let size = data.len();
+        self.insert(hash, CachedObject::Blob(data), size);
+    }
+
+    fn insert(&self, hash: &str, object: CachedObject, size: usize) {
+        if !self.enabled() || size > self.max_bytes {
+            return;
+        }
+        let mut inner = self.lock();
+        inner.remove(hash);
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.order.insert(tick, hash.to_string());
+        inner
+            .entries
+            .insert(hash.to_string(), Entry { object, size, tick });
+        inner.bytes += size;
+
+        while inner.entries.len() > self.max_entries || inner.bytes > self.max_bytes {
+            let Some((_, oldest)) = inner.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = inner.entries.remove(&oldest) {
+                inner.bytes -= entry.size;
+            }
+        }
+    }
+
+    /// Drop the entries for `hashes`, e.g. after their objects were deleted.
+    pub(crate) fn remove<'a>(&self, hashes: impl IntoIterator<Item = &'a String>) {
+        let mut inner = self.lock();
+        for hash in hashes {
+            inner.remove(hash);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let inner = self.lock();
+        CacheStats {
+            hits: inner.hits,
+            misses: inner.misses,
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(len: usize) -> Arc<Vec<u8>> {
+        Arc::new(vec![0; len])
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = ObjectCache::new(2, 100);
+        cache.insert_blob("a", blob(1));
+        cache.insert_blob("b", blob(1));
+        // Use "a" so "b" is least recently used
+        assert!(cache.get_blob("a").is_some());
+        cache.insert_blob("c", blob(1));
+        assert!(cache.get_blob("b").is_none());
+        assert!(cache.get_blob("a").is_some());
+        assert!(cache.get_blob("c").is_some());
+
+        // Byte limit
+        cache.insert_blob("d", blob(60));
+        cache.insert_blob("e", blob(60));
+        assert!(cache.get_blob("d").is_none());
+        assert_eq!(cache.stats().bytes, 60);
+        // Larger than the whole cache
+        cache.insert_blob("f", blob(101));
+        assert!(cache.get_blob("f").is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn test_disabled_and_remove() {
+        let cache = ObjectCache::new(0, 100);
+        cache.insert_blob("a", blob(1));
+        assert!(cache.get_blob("a").is_none());
+        assert_eq!(cache.stats(), CacheStats::default());
+
+        let cache = ObjectCache::new(10, 100);
+        cache.insert_blob("a", blob(5));
+        cache.remove([&"a".to_string()]);
+        assert!(cache.get_blob("a").is_none());
+        assert_eq!(cache.stats().bytes, 0);
+    }
+}
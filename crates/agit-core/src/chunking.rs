@@ -0,0 +1,287 @@
+//! Content-defined chunking for large state values.
+//!
+//! Values whose serialized size exceeds a threshold are split into chunks
+//! with a gear rolling hash and stored as separate blobs. The value is
+//! replaced in the state blob by a manifest `{"__agit_chunks__": [hashes]}`.
+//! Chunk boundaries depend only on content, so an unchanged large field maps
+//! to the same chunks on every commit and is stored once.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::error::{AgitError, Result};
+use crate::hash::{canonical_serialize, compute_hash};
+use crate::storage::StorageBackend;
+use crate::types::ObjectType;
+
+/// Manifest key marking a chunked value. Reserved in state objects.
+pub const CHUNKS_KEY: &str = "__agit_chunks__";
+
+/// Chunking thresholds and target sizes, in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkingOptions {
+    /// Values whose serialized JSON is larger than this are chunked.
+    pub threshold: usize,
+    /// No boundary is placed before this many bytes.
+    pub min_chunk_size: usize,
+    /// Target average chunk size; rounded up to a power of two.
+    pub avg_chunk_size: usize,
+    /// A boundary is forced after this many bytes.
+    pub max_chunk_size: usize,
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 1024 * 1024,
+            min_chunk_size: 256 * 1024,
+            avg_chunk_size: 1024 * 1024,
+            max_chunk_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Random gear table for the rolling hash, generated with splitmix64.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Split `data` at content-defined boundaries.
+pub fn split<'a>(data: &'a [u8], options: &ChunkingOptions) -> Vec<&'a [u8]> {
+    let mask = (options.avg_chunk_size.max(1).next_power_of_two() - 1) as u64;
+    let min = options.min_chunk_size.max(1);
+    let max = options.max_chunk_size.max(min);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + max).min(data.len());
+        let mut cut = end;
+        let mut hash: u64 = 0;
+        let mut i = start + min;
+        while i < end {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & mask == 0 {
+                cut = i + 1;
+                break;
+            }
+            i += 1;
+        }
+        chunks.push(&data[start..cut]);
+        start = cut;
+    }
+    chunks
+}
+
+/// Replace values larger than `options.threshold` with chunk manifests.
+///
+/// Objects are descended into so only the oversized fields are chunked;
+/// any other value is chunked as a whole. Returns the chunks to store,
+/// keyed by hash.
+pub fn chunk_value(value: &mut Value, options: &ChunkingOptions) -> HashMap<String, Vec<u8>> {
+    let mut chunks = HashMap::new();
+    chunk_into(value, options, &mut chunks);
+    chunks
+}
+
+fn chunk_into(value: &mut Value, options: &ChunkingOptions, out: &mut HashMap<String, Vec<u8>>) {
+    if let Value::Object(map) = value {
+        for child in map.values_mut() {
+            chunk_into(child, options, out);
+        }
+        return;
+    }
+    // Cheap lower bound before serializing
+    if matches!(value, Value::String(s) if s.len() < options.threshold) {
+        return;
+    }
+    if matches!(value, Value::Null | Value::Bool(_) | Value::Number(_)) {
+        return;
+    }
+    let data = canonical_serialize(value);
+    if data.len() <= options.threshold {
+        return;
+    }
+
+    let mut hashes = Vec::new();
+    for chunk in split(&data, options) {
+        let hash = compute_hash(ObjectType::Blob, chunk).0;
+        out.entry(hash.clone()).or_insert_with(|| chunk.to_vec());
+        hashes.push(Value::String(hash));
+    }
+    let mut manifest = serde_json::Map::new();
+    manifest.insert(CHUNKS_KEY.to_string(), Value::Array(hashes));
+    *value = Value::Object(manifest);
+}
+
+/// Chunk hashes listed by `value` if it is a manifest.
+fn manifest_hashes(value: &Value) -> Option<Vec<&str>> {
+    let map = value.as_object()?;
+    if map.len() != 1 {
+        return None;
+    }
+    map.get(CHUNKS_KEY)?
+        .as_array()?
+        .iter()
+        .map(|h| h.as_str())
+        .collect()
+}
+
+/// All chunk hashes referenced by manifests within `value`.
+pub fn chunk_refs(value: &Value) -> Vec<String> {
+    let mut refs = Vec::new();
+    collect_refs(value, &mut refs);
+    refs
+}
+
+fn collect_refs(value: &Value, refs: &mut Vec<String>) {
+    if let Some(hashes) = manifest_hashes(value) {
+        refs.extend(hashes.into_iter().map(String::from));
+    } else if let Value::Object(map) = value {
+        for child in map.values() {
+            collect_refs(child, refs);
+        }
+    }
+}
+
+/// Chunk hashes referenced by a stored blob, without parsing blobs that
+/// contain no manifest.
+pub fn chunk_refs_in(data: &[u8]) -> Vec<String> {
+    let key = CHUNKS_KEY.as_bytes();
+    if !data.windows(key.len()).any(|w| w == key) {
+        return Vec::new();
+    }
+    serde_json::from_slice::<Value>(data)
+        .map(|v| chunk_refs(&v))
+        .unwrap_or_default()
+}
+
+/// Replace every chunk manifest within `value` with the reassembled value.
+pub async fn reassemble(storage: &dyn StorageBackend, value: &mut Value) -> Result<()> {
+    // Collect manifest locations first; objects only nest, so a path of
+    // keys identifies each one
+    let mut manifests: Vec<(Vec<String>, Vec<String>)> = Vec::new();
+    find_manifests(value, &mut vec![], &mut manifests);
+
+    for (path, hashes) in manifests {
+        let mut data = Vec::new();
+        for hash in &hashes {
+            let chunk = storage
+                .get_object(hash)
+                .await?
+                .ok_or_else(|| AgitError::ObjectNotFound { hash: hash.clone() })?;
+            data.extend_from_slice(&chunk);
+        }
+        let restored: Value = serde_json::from_slice(&data)?;
+        let mut slot = &mut *value;
+        for key in &path {
+            slot = slot.get_mut(key).ok_or_else(|| {
+                AgitError::Serialization(format!("chunk manifest moved: {}", key))
+            })?;
+        }
+        *slot = restored;
+    }
+    Ok(())
+}
+
+fn find_manifests(
+    value: &Value,
+    path: &mut Vec<String>,
+    out: &mut Vec<(Vec<String>, Vec<String>)>,
+) {
+    if let Some(hashes) = manifest_hashes(value) {
+        out.push((path.clone(), hashes.into_iter().map(String::from).collect()));
+    } else if let Value::Object(map) = value {
+        for (key, child) in map {
+            path.push(key.clone());
+            find_manifests(child, path, out);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn small_options() -> ChunkingOptions {
+        ChunkingOptions {
+            threshold: 1024,
+            min_chunk_size: 64,
+            avg_chunk_size: 256,
+            max_chunk_size: 1024,
+        }
+    }
+
+    fn pseudo_random(len: usize, seed: u64) -> String {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                (b'a' + (x % 26) as u8) as char
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_bounds_and_roundtrip() {
+        let options = small_options();
+        let data = pseudo_random(20_000, 1).into_bytes();
+        let chunks = split(&data, &options);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= options.min_chunk_size);
+            assert!(chunk.len() <= options.max_chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_boundaries_survive_prefix_edit() {
+        let options = small_options();
+        let original = pseudo_random(20_000, 2);
+        let edited = format!("XYZ{}", original);
+        let a: Vec<&[u8]> = split(original.as_bytes(), &options);
+        let b: Vec<&[u8]> = split(edited.as_bytes(), &options);
+        // Content-defined boundaries resynchronize after the edit
+        let shared = a.iter().filter(|c| b.contains(c)).count();
+        assert!(shared >= a.len() - 2, "shared {} of {}", shared, a.len());
+    }
+
+    #[test]
+    fn test_chunk_value_only_large_fields() {
+        let options = small_options();
+        let big = pseudo_random(5_000, 3);
+        let mut value = json!({"memory": {"page": big, "note": "small"}, "n": 1});
+        let chunks = chunk_value(&mut value, &options);
+
+        assert!(!chunks.is_empty());
+        assert_eq!(value["memory"]["note"], json!("small"));
+        let refs = chunk_refs(&value);
+        assert_eq!(
+            refs.len(),
+            value["memory"]["page"][CHUNKS_KEY]
+                .as_array()
+                .unwrap()
+                .len()
+        );
+        assert!(refs.iter().all(|h| chunks.contains_key(h)));
+        assert_eq!(chunk_refs_in(&canonical_serialize(&value)), refs);
+        assert!(chunk_refs_in(b"{\"a\":1}").is_empty());
+    }
+}
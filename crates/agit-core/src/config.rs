@@ -0,0 +1,112 @@
+//! Repository-level configuration.
+//!
+//! Each key's value is stored as a JSON blob referenced from the
+//! `config/<key>` ref, so values are content-addressed, every process
+//! sharing the storage sees them, and a change replaces the ref with a
+//! compare-and-swap. Keys agit manages through typed APIs, such as branch
+//! protection and remotes, can be read here but not written.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::error::{AgitError, Result};
+use crate::refs::CONFIG_REF_PREFIX;
+use crate::storage::StorageBackend;
+use crate::types::Hash;
+
+/// Keys written only through their own `Repository` methods: branch
+/// protection, remotes, encryption settings, auto-GC bookkeeping and key
+/// rotation progress.
+pub const RESERVED_CONFIG_KEYS: &[&str] = &[
+    "protection",
+    "remotes",
+    "encryption",
+    "gc_state",
+    "key_rotation",
+];
+
+/// Full name of the ref holding `key`.
+pub fn config_ref(key: &str) -> String {
+    format!("{}{}", CONFIG_REF_PREFIX, key)
+}
+
+/// Fail unless `key` is `/`-separated segments of ASCII letters, digits,
+/// `_`, `-` and `.`, none of them `.` or `..`.
+pub(crate) fn check_config_key(key: &str) -> Result<()> {
+    let valid_segment = |segment: &str| {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    };
+    if !key.split('/').all(valid_segment) {
+        return Err(AgitError::InvalidArgument(format!(
+            "invalid config key '{}'",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Fail unless `key` is valid and not reserved for agit's own settings.
+pub(crate) fn check_writable_key(key: &str) -> Result<()> {
+    check_config_key(key)?;
+    if RESERVED_CONFIG_KEYS.contains(&key) {
+        return Err(AgitError::InvalidArgument(format!(
+            "config key '{}' is managed by agit",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Load the value stored under `key` along with its blob hash, if set.
+pub(crate) async fn load_config(
+    storage: &dyn StorageBackend,
+    key: &str,
+) -> Result<Option<(Hash, Value)>> {
+    let Some(hash) = storage.get_ref(&config_ref(key)).await? else {
+        return Ok(None);
+    };
+    let data = storage
+        .get_object(&hash)
+        .await?
+        .ok_or_else(|| AgitError::ObjectNotFound { hash: hash.clone() })?;
+    Ok(Some((Hash::from(hash), serde_json::from_slice(&data)?)))
+}
+
+/// Load every configuration value, by key.
+pub(crate) async fn list_config(storage: &dyn StorageBackend) -> Result<BTreeMap<String, Value>> {
+    let mut config = BTreeMap::new();
+    for (name, hash) in storage.list_refs().await? {
+        let Some(key) = name.strip_prefix(CONFIG_REF_PREFIX) else {
+            continue;
+        };
+        let data = storage
+            .get_object(&hash)
+            .await?
+            .ok_or(AgitError::ObjectNotFound { hash })?;
+        config.insert(key.to_string(), serde_json::from_slice(&data)?);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_keys() {
+        for key in ["retention", "schemas/main", "auto-gc.interval", "a_b"] {
+            check_writable_key(key).unwrap();
+        }
+        for key in ["", "/", "a/", "/a", "a//b", "..", "a/./b", "a b", "ümlaut"] {
+            assert!(check_config_key(key).is_err(), "{key:?}");
+        }
+        check_config_key("protection").unwrap();
+        assert!(check_writable_key("protection").is_err());
+    }
+}
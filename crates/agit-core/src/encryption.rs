@@ -2,28 +2,102 @@
 //!
 //! Enable with `--features encryption`.
 //! Provides `StateEncryptor` that encrypts/decrypts `AgentState` fields.
+//!
+//! Ciphertexts carry an envelope naming the key and salt that produced
+//! them: `v:2|kid:<key id>|salt:<hex salt>|<base64 nonce + ciphertext>`.
+//! Payloads written before the envelope are bare base64.
+
+/// Ref holding a repository's encryption settings, such as its key
+/// derivation salt.
+pub const ENCRYPTION_CONFIG_REF: &str = "config/encryption";
+
+/// Marks a string field holding a ciphertext from `encrypt_value`.
+pub const ENCRYPTED_PREFIX: &str = "ENC:";
+
+/// Whether `value` is a ciphertext field written by `encrypt_state`.
+pub fn is_encrypted_value(value: &serde_json::Value) -> bool {
+    value
+        .as_str()
+        .is_some_and(|s| s.starts_with(ENCRYPTED_PREFIX))
+}
+
+/// Whether any field of `state` that `encrypt_state` seals holds a ciphertext.
+pub fn has_encrypted_fields(state: &crate::state::AgentState) -> bool {
+    is_encrypted_value(&state.memory) || is_encrypted_value(&state.world_state)
+}
+
+/// How a `StateEncryptor` picks the nonce of each ciphertext.
+///
+/// `Randomized` draws a fresh nonce every time, so encrypting a value twice
+/// gives unrelated ciphertexts. `Convergent` derives the nonce from an HMAC
+/// of the plaintext under a secret scoped to one repository (SIV-style):
+/// equal plaintexts encrypt identically there, so their blobs share a
+/// content hash and deduplicate.
+///
+/// The price is equality leakage. Anyone who can read the storage learns
+/// which states, fields and audit messages are identical to each other,
+/// though not what they contain. Ciphertexts from other repositories or
+/// other keys stay unlinkable. Prefer `Randomized` unless dedup matters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncryptionMode {
+    #[default]
+    Randomized,
+    Convergent,
+}
 
 #[cfg(feature = "encryption")]
 mod inner {
+    use crate::error::{AgitError, Result};
+    use crate::hash::hex;
+    use crate::state::AgentState;
+    use aes_gcm::aead::generic_array::GenericArray;
+    use aes_gcm::aead::rand_core::RngCore;
     use aes_gcm::{
         aead::{Aead, KeyInit, OsRng},
         Aes256Gcm, Nonce,
     };
-    use aes_gcm::aead::generic_array::GenericArray;
-    use aes_gcm::aead::rand_core::RngCore;
     use argon2::Argon2;
-    use sha2::{Digest, Sha256};
-    use crate::error::{AgitError, Result};
-    use crate::state::AgentState;
+    use hmac::{Hmac, Mac};
     use serde_json::Value;
+    use sha2::{Digest, Sha256};
+    use zeroize::{ZeroizeOnDrop, Zeroizing};
+
+    use super::EncryptionMode;
+
+    type HmacSha256 = Hmac<Sha256>;
 
     /// Legacy fallback salt. Prefer per-context derived salts.
     const LEGACY_SALT: &[u8; 16] = b"agit-enc-v1-salt";
 
+    /// Leads every ciphertext written with a key-versioned envelope.
+    const ENVELOPE_PREFIX: &str = "v:2|";
+
+    /// Hex digits of the key fingerprint used as its id.
+    const KEY_ID_LEN: usize = 16;
+
+    /// Encryption settings persisted under `ENCRYPTION_CONFIG_REF`.
+    #[cfg(feature = "storage")]
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    pub(crate) struct EncryptionConfig {
+        /// Hex-encoded key derivation salt.
+        pub salt: String,
+    }
+
     /// Encrypts and decrypts agent state fields using AES-256-GCM.
     /// Key derivation uses Argon2id (memory-hard KDF) for passphrase-based keys.
+    ///
+    /// Neither the passphrase nor the derived key bytes are kept; the AES
+    /// key schedules are wiped when the encryptor is dropped.
     pub struct StateEncryptor {
         cipher: Aes256Gcm,
+        key_id: String,
+        /// Hex salt the key was derived with, empty for raw keys.
+        salt: String,
+        /// Opens payloads written before the envelope.
+        legacy: Aes256Gcm,
+        /// Keys the plaintext HMAC that yields convergent nonces.
+        nonce_key: Zeroizing<[u8; 32]>,
+        mode: EncryptionMode,
     }
 
     impl StateEncryptor {
@@ -43,44 +117,126 @@ mod inner {
         /// Create from a passphrase with a custom salt.
         /// Each tenant should use a unique salt for key isolation.
         pub fn with_salt(key: &str, salt: &[u8]) -> Self {
-            let mut key_bytes = [0u8; 32];
+            let mut key_bytes = Zeroizing::new([0u8; 32]);
             Argon2::default()
-                .hash_password_into(key.as_bytes(), salt, &mut key_bytes)
+                .hash_password_into(key.as_bytes(), salt, key_bytes.as_mut())
                 .expect("Argon2 key derivation failed");
-            let cipher = Aes256Gcm::new(GenericArray::from_slice(&key_bytes));
-            Self { cipher }
+            Self::build(&key_bytes, hex::encode(salt))
         }
 
         /// Create from raw 32-byte key.
         pub fn from_key_bytes(key: &[u8; 32]) -> Self {
+            Self::build(key, String::new())
+        }
+
+        /// A random 256-bit key for `from_key_bytes`, drawn from the OS RNG.
+        /// Callers own storing it; agit keeps only its key id.
+        pub fn generate_key() -> [u8; 32] {
+            random_key()
+        }
+
+        fn build(key: &[u8; 32], salt: String) -> Self {
             let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
-            Self { cipher }
+            let mut hasher = Sha256::new();
+            hasher.update(b"agit-kid:");
+            hasher.update(key);
+            let mut key_id = hex::encode(hasher.finalize());
+            key_id.truncate(KEY_ID_LEN);
+            Self {
+                legacy: cipher.clone(),
+                cipher,
+                key_id,
+                salt,
+                nonce_key: hmac(key, b"agit-convergent-nonce"),
+                mode: EncryptionMode::Randomized,
+            }
         }
 
-        /// Encrypt a JSON value, returning a base64-encoded ciphertext string.
-        pub fn encrypt_value(&self, value: &Value) -> Result<String> {
-            let plaintext = serde_json::to_vec(value)
-                .map_err(|e| AgitError::Serialization(e.to_string()))?;
+        /// Encrypt equal plaintexts to equal ciphertexts, with nonces
+        /// derived from the plaintext. `scope` (e.g. a repository's salt)
+        /// keeps ciphertexts under the same key unlinkable across scopes.
+        /// See `EncryptionMode::Convergent` for what this gives away.
+        pub fn convergent(mut self, scope: &[u8]) -> Self {
+            self.nonce_key = hmac(self.nonce_key.as_ref(), scope);
+            self.mode = EncryptionMode::Convergent;
+            self
+        }
+
+        /// How this encryptor picks nonces.
+        pub fn mode(&self) -> EncryptionMode {
+            self.mode
+        }
+
+        /// Open payloads written without an envelope with `legacy`'s key
+        /// instead of this one, e.g. the key an older version derived from
+        /// the same passphrase.
+        pub fn with_legacy(mut self, legacy: StateEncryptor) -> Self {
+            self.legacy = legacy.cipher;
+            self
+        }
+
+        /// Short fingerprint of the key, recorded in every ciphertext's
+        /// envelope. Reveals nothing about the key itself.
+        pub fn key_id(&self) -> &str {
+            &self.key_id
+        }
 
-            // Generate random 12-byte nonce
-            let mut nonce_bytes = [0u8; 12];
-            OsRng.fill_bytes(&mut nonce_bytes);
-            let nonce = Nonce::from_slice(&nonce_bytes);
+        /// Encrypt a JSON value, returning an enveloped ciphertext string.
+        pub fn encrypt_value(&self, value: &Value) -> Result<String> {
+            let plaintext = Zeroizing::new(
+                serde_json::to_vec(value).map_err(|e| AgitError::Serialization(e.to_string()))?,
+            );
 
-            let ciphertext = self.cipher.encrypt(nonce, plaintext.as_ref())
-                .map_err(|e| AgitError::EncryptionError(format!("encrypt failed: {e}")))?;
+            let nonce_bytes = match self.mode {
+                EncryptionMode::Randomized => random_nonce(),
+                EncryptionMode::Convergent => {
+                    let mut nonce = [0u8; 12];
+                    nonce.copy_from_slice(&hmac(self.nonce_key.as_ref(), &plaintext)[..12]);
+                    nonce
+                }
+            };
+            let ciphertext = seal(&self.cipher, &nonce_bytes, &plaintext)?;
 
             // Prepend nonce to ciphertext, then base64 encode
             let mut combined = Vec::with_capacity(12 + ciphertext.len());
             combined.extend_from_slice(&nonce_bytes);
             combined.extend_from_slice(&ciphertext);
 
-            Ok(super::base64_encode(&combined))
+            Ok(format!(
+                "{}kid:{}|salt:{}|{}",
+                ENVELOPE_PREFIX,
+                self.key_id,
+                self.salt,
+                super::base64_encode(&combined)
+            ))
         }
 
-        /// Decrypt a base64-encoded ciphertext back to a JSON value.
+        /// Decrypt a ciphertext from `encrypt_value` back to a JSON value.
+        ///
+        /// Enveloped ciphertexts from a different key fail with an unknown
+        /// key id error; bare ones are opened with the legacy key.
         pub fn decrypt_value(&self, encrypted: &str) -> Result<Value> {
-            let combined = super::base64_decode(encrypted)
+            let (cipher, payload) = match encrypted.strip_prefix(ENVELOPE_PREFIX) {
+                Some(rest) => {
+                    let mut fields = rest.splitn(3, '|');
+                    let kid = fields.next().and_then(|f| f.strip_prefix("kid:"));
+                    let salt = fields.next().and_then(|f| f.strip_prefix("salt:"));
+                    let (Some(kid), Some(_), Some(payload)) = (kid, salt, fields.next()) else {
+                        return Err(AgitError::EncryptionError(
+                            "malformed ciphertext envelope".into(),
+                        ));
+                    };
+                    if kid != self.key_id {
+                        return Err(AgitError::EncryptionError(format!(
+                            "unknown key id {kid} (this key is {})",
+                            self.key_id
+                        )));
+                    }
+                    (&self.cipher, payload)
+                }
+                None => (&self.legacy, encrypted),
+            };
+            let combined = super::base64_decode(payload)
                 .map_err(|e| AgitError::EncryptionError(format!("base64 decode: {e}")))?;
 
             if combined.len() < 12 {
@@ -88,13 +244,9 @@ mod inner {
             }
 
             let (nonce_bytes, ciphertext) = combined.split_at(12);
-            let nonce = Nonce::from_slice(nonce_bytes);
+            let plaintext = Zeroizing::new(open(cipher, nonce_bytes, ciphertext)?);
 
-            let plaintext = self.cipher.decrypt(nonce, ciphertext)
-                .map_err(|e| AgitError::EncryptionError(format!("decrypt failed: {e}")))?;
-
-            serde_json::from_slice(&plaintext)
-                .map_err(|e| AgitError::Serialization(e.to_string()))
+            serde_json::from_slice(&plaintext).map_err(|e| AgitError::Serialization(e.to_string()))
         }
 
         /// Encrypt an AgentState's memory and world_state fields in-place.
@@ -104,8 +256,8 @@ mod inner {
             let enc_world = self.encrypt_value(&state.world_state)?;
 
             Ok(AgentState {
-                memory: Value::String(format!("ENC:{}", enc_memory)),
-                world_state: Value::String(format!("ENC:{}", enc_world)),
+                memory: Value::String(format!("{}{}", super::ENCRYPTED_PREFIX, enc_memory)),
+                world_state: Value::String(format!("{}{}", super::ENCRYPTED_PREFIX, enc_world)),
                 timestamp: state.timestamp,
                 cost: state.cost,
                 metadata: state.metadata.clone(),
@@ -127,15 +279,97 @@ mod inner {
         }
 
         fn decrypt_field(&self, value: &Value) -> Result<Value> {
-            match value {
-                Value::String(s) if s.starts_with("ENC:") => {
-                    self.decrypt_value(&s[4..])
-                }
-                _ => Ok(value.clone()), // Not encrypted, pass through
+            match value
+                .as_str()
+                .and_then(|s| s.strip_prefix(super::ENCRYPTED_PREFIX))
+            {
+                Some(encrypted) => self.decrypt_value(encrypted),
+                None => Ok(value.clone()), // Not encrypted, pass through
             }
         }
     }
 
+    // Both ciphers wipe their AES key schedules on drop (the `zeroize`
+    // feature of `aes`) and the nonce key is `Zeroizing`, so the encryptor
+    // holds no key material afterwards.
+    impl ZeroizeOnDrop for StateEncryptor {}
+
+    const _: fn() = || {
+        fn wiped_on_drop<T: ZeroizeOnDrop>() {}
+        wiped_on_drop::<aes::Aes256>();
+    };
+
+    fn seal(cipher: &Aes256Gcm, nonce: &[u8; 12], plaintext: &[u8]) -> Result<Vec<u8>> {
+        cipher
+            .encrypt(Nonce::from_slice(nonce), plaintext)
+            .map_err(|e| AgitError::EncryptionError(format!("encrypt failed: {e}")))
+    }
+
+    /// A random 96-bit AES-GCM nonce.
+    fn random_nonce() -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        nonce
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Zeroizing<[u8; 32]> {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+        Zeroizing::new(mac.finalize().into_bytes().into())
+    }
+
+    fn open(cipher: &Aes256Gcm, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != 12 {
+            return Err(AgitError::EncryptionError("invalid nonce length".into()));
+        }
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| AgitError::EncryptionError(format!("decrypt failed: {e}")))
+    }
+
+    /// A random 128-bit key derivation salt.
+    #[cfg(feature = "storage")]
+    pub(crate) fn random_salt() -> [u8; 16] {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        salt
+    }
+
+    /// A random 256-bit key, e.g. a per-object data key.
+    pub(crate) fn random_key() -> [u8; 32] {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        key
+    }
+
+    /// Encrypt raw bytes with AES-256-GCM under `key`, returning the random
+    /// nonce and the ciphertext.
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    pub(crate) fn encrypt_bytes(key: &[u8; 32], plaintext: &[u8]) -> Result<([u8; 12], Vec<u8>)> {
+        let nonce = random_nonce();
+        let ciphertext = seal(
+            &Aes256Gcm::new(GenericArray::from_slice(key)),
+            &nonce,
+            plaintext,
+        )?;
+        Ok((nonce, ciphertext))
+    }
+
+    /// Decrypt bytes produced by `encrypt_bytes`.
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    pub(crate) fn decrypt_bytes(
+        key: &[u8; 32],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        open(
+            &Aes256Gcm::new(GenericArray::from_slice(key)),
+            nonce,
+            ciphertext,
+        )
+    }
+
     fn derive_salt(context: &str) -> [u8; 16] {
         if context.is_empty() {
             return *LEGACY_SALT;
@@ -150,57 +384,34 @@ mod inner {
     }
 }
 
-// Simple base64 encoding (no external dependency needed)
-#[allow(dead_code)]
-fn base64_encode(data: &[u8]) -> String {
-    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
-    for chunk in data.chunks(3) {
-        let b0 = chunk[0] as u32;
-        let b1 = if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
-        let b2 = if chunk.len() > 2 { chunk[2] as u32 } else { 0 };
-        let n = (b0 << 16) | (b1 << 8) | b2;
-        result.push(ALPHABET[((n >> 18) & 0x3F) as usize] as char);
-        result.push(ALPHABET[((n >> 12) & 0x3F) as usize] as char);
-        if chunk.len() > 1 {
-            result.push(ALPHABET[((n >> 6) & 0x3F) as usize] as char);
-        } else {
-            result.push('=');
-        }
-        if chunk.len() > 2 {
-            result.push(ALPHABET[(n & 0x3F) as usize] as char);
-        } else {
-            result.push('=');
-        }
-    }
-    result
+/// Standard-alphabet base64 that writes padding but, like the hand-rolled
+/// codec it replaced, reads payloads with or without it.
+#[cfg(feature = "encryption")]
+const BASE64: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    base64::engine::GeneralPurposeConfig::new()
+        .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+#[cfg(feature = "encryption")]
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    use base64::Engine;
+    BASE64.encode(data)
 }
 
-#[allow(dead_code)]
-fn base64_decode(input: &str) -> std::result::Result<Vec<u8>, String> {
-    let input = input.trim_end_matches('=');
-    let mut result = Vec::with_capacity(input.len() * 3 / 4);
-    let mut buf = 0u32;
-    let mut bits = 0;
-    for c in input.bytes() {
-        let val = match c {
-            b'A'..=b'Z' => c - b'A',
-            b'a'..=b'z' => c - b'a' + 26,
-            b'0'..=b'9' => c - b'0' + 52,
-            b'+' => 62,
-            b'/' => 63,
-            _ => return Err(format!("invalid base64 char: {c}")),
-        };
-        buf = (buf << 6) | val as u32;
-        bits += 6;
-        if bits >= 8 {
-            bits -= 8;
-            result.push((buf >> bits) as u8);
-            buf &= (1 << bits) - 1;
-        }
-    }
-    Ok(result)
+#[cfg(feature = "encryption")]
+pub(crate) fn base64_decode(input: &str) -> std::result::Result<Vec<u8>, String> {
+    use base64::Engine;
+    BASE64.decode(input).map_err(|e| e.to_string())
 }
 
 #[cfg(feature = "encryption")]
 pub use inner::StateEncryptor;
+
+#[cfg(all(feature = "encryption", feature = "storage"))]
+pub(crate) use inner::{random_salt, EncryptionConfig};
+
+#[cfg(feature = "encryption")]
+#[cfg_attr(not(feature = "s3"), allow(unused_imports))]
+pub(crate) use inner::{decrypt_bytes, encrypt_bytes, random_key};
@@ -1,6 +1,9 @@
 use thiserror::Error;
 
+use crate::state::MergeConflict;
+
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum AgitError {
     #[error("object not found: {hash}")]
     ObjectNotFound { hash: String },
@@ -14,14 +17,37 @@ pub enum AgitError {
     #[error("branch not found: {name}")]
     BranchNotFound { name: String },
 
-    #[error("merge conflict: {details}")]
-    MergeConflict { details: String },
+    /// HEAD is attached to the branch, so it cannot be deleted.
+    #[error("branch '{name}' is checked out")]
+    BranchCheckedOut { name: String },
+
+    /// A three-way merge left `conflicts`, each with the base, ours and
+    /// theirs values at its path.
+    #[error("merge conflict: conflicts at: {}", conflict_paths(.conflicts))]
+    MergeConflict { conflicts: Vec<MergeConflict> },
+
+    /// A ref changed in storage since it was last read. Retryable after
+    /// re-reading refs.
+    #[error("ref '{name}' was updated concurrently")]
+    ConcurrentUpdate { name: String },
+
+    /// A storage lock was still held by someone else when the timeout
+    /// passed.
+    #[error("timed out waiting for lock '{name}'")]
+    LockTimeout { name: String },
+
+    /// Two commits share no history, so there is no merge base.
+    #[error("no common ancestor between {ours} and {theirs}")]
+    NoCommonAncestor { ours: String, theirs: String },
 
     #[error("detached HEAD: cannot perform operation requiring a branch")]
     DetachedHead,
 
-    #[error("storage error: {0}")]
-    Storage(String),
+    /// A storage backend operation failed. `retryable` marks transient
+    /// failures, such as a busy database or a throttled request, that may
+    /// succeed if tried again.
+    #[error("storage error: {message}")]
+    Storage { message: String, retryable: bool },
 
     #[error("serialization error: {0}")]
     Serialization(String),
@@ -38,15 +64,266 @@ pub enum AgitError {
     #[error("encryption error: {0}")]
     EncryptionError(String),
 
+    /// The state object `hash` holds ciphertext, but the repository was
+    /// opened without an encryption key.
+    #[error("state {hash} is encrypted and no encryption key is configured")]
+    EncryptedState { hash: String },
+
     #[error("depth limit exceeded: {0}")]
     DepthLimitExceeded(String),
+
+    #[error("branch '{branch}' is protected: {rule}")]
+    ProtectedBranch { branch: String, rule: String },
+
+    /// A state does not match the schema configured for the repository.
+    #[error("state violates schema at '{path}': {reason}")]
+    SchemaViolation { path: String, reason: String },
+
+    #[error("patch does not apply at '{path}': {reason}")]
+    PatchConflict { path: String, reason: String },
+
+    #[error("state is {bytes} bytes, exceeding the limit of {limit}")]
+    StateTooLarge { bytes: usize, limit: usize },
+
+    #[error("state nesting depth {depth} exceeds the limit of {limit}")]
+    StateTooDeep { depth: usize, limit: usize },
+
+    #[error("state has {keys} keys, exceeding the limit of {limit}")]
+    StateTooManyKeys { keys: usize, limit: usize },
 }
 
 pub type Result<T> = std::result::Result<T, AgitError>;
 
+fn conflict_paths(conflicts: &[MergeConflict]) -> String {
+    conflicts
+        .iter()
+        .map(MergeConflict::dotted_path)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl AgitError {
+    /// A storage error that retrying will not fix.
+    pub fn storage(message: impl Into<String>) -> Self {
+        AgitError::Storage {
+            message: message.into(),
+            retryable: false,
+        }
+    }
+
+    /// A transient storage error that may succeed if retried.
+    pub fn transient_storage(message: impl Into<String>) -> Self {
+        AgitError::Storage {
+            message: message.into(),
+            retryable: true,
+        }
+    }
+
+    /// Whether retrying the operation may succeed: a transient storage
+    /// error, a ref that moved underneath us, or a lock that was busy.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            AgitError::Storage {
+                retryable: true,
+                ..
+            } | AgitError::ConcurrentUpdate { .. }
+                | AgitError::LockTimeout { .. }
+        )
+    }
+
+    /// Stable machine-readable code, shared by the Python and Node bindings.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AgitError::ObjectNotFound { .. } => "AGIT_OBJECT_NOT_FOUND",
+            AgitError::RefNotFound { .. } => "AGIT_REF_NOT_FOUND",
+            AgitError::BranchExists { .. } => "AGIT_BRANCH_EXISTS",
+            AgitError::BranchNotFound { .. } => "AGIT_BRANCH_NOT_FOUND",
+            AgitError::BranchCheckedOut { .. } => "AGIT_BRANCH_CHECKED_OUT",
+            AgitError::MergeConflict { .. } => "AGIT_MERGE_CONFLICT",
+            AgitError::ConcurrentUpdate { .. } => "AGIT_CONCURRENT_UPDATE",
+            AgitError::LockTimeout { .. } => "AGIT_LOCK_TIMEOUT",
+            AgitError::NoCommonAncestor { .. } => "AGIT_NO_COMMON_ANCESTOR",
+            AgitError::DetachedHead => "AGIT_DETACHED_HEAD",
+            AgitError::Storage { .. } => "AGIT_STORAGE",
+            AgitError::Serialization(_) => "AGIT_SERIALIZATION",
+            AgitError::InvalidArgument(_) => "AGIT_INVALID_ARGUMENT",
+            AgitError::InvalidOperation(_) => "AGIT_INVALID_OPERATION",
+            AgitError::NoCommits => "AGIT_NO_COMMITS",
+            AgitError::EncryptionError(_) => "AGIT_ENCRYPTION",
+            AgitError::EncryptedState { .. } => "AGIT_ENCRYPTED_STATE",
+            AgitError::DepthLimitExceeded(_) => "AGIT_DEPTH_LIMIT_EXCEEDED",
+            AgitError::ProtectedBranch { .. } => "AGIT_PROTECTED_BRANCH",
+            AgitError::SchemaViolation { .. } => "AGIT_SCHEMA_VIOLATION",
+            AgitError::PatchConflict { .. } => "AGIT_PATCH_CONFLICT",
+            AgitError::StateTooLarge { .. } => "AGIT_STATE_TOO_LARGE",
+            AgitError::StateTooDeep { .. } => "AGIT_STATE_TOO_DEEP",
+            AgitError::StateTooManyKeys { .. } => "AGIT_STATE_TOO_MANY_KEYS",
+        }
+    }
+}
+
 impl From<serde_json::Error> for AgitError {
     fn from(e: serde_json::Error) -> Self {
         AgitError::Serialization(e.to_string())
     }
 }
 
+impl From<std::io::Error> for AgitError {
+    fn from(e: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        let retryable = matches!(
+            e.kind(),
+            ErrorKind::Interrupted
+                | ErrorKind::TimedOut
+                | ErrorKind::WouldBlock
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+        );
+        AgitError::Storage {
+            message: e.to_string(),
+            retryable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s() -> String {
+        "x".to_string()
+    }
+
+    #[test]
+    fn test_codes_and_retryability() {
+        let cases = [
+            (
+                AgitError::ObjectNotFound { hash: s() },
+                "AGIT_OBJECT_NOT_FOUND",
+                false,
+            ),
+            (
+                AgitError::RefNotFound { name: s() },
+                "AGIT_REF_NOT_FOUND",
+                false,
+            ),
+            (
+                AgitError::BranchExists { name: s() },
+                "AGIT_BRANCH_EXISTS",
+                false,
+            ),
+            (
+                AgitError::BranchNotFound { name: s() },
+                "AGIT_BRANCH_NOT_FOUND",
+                false,
+            ),
+            (
+                AgitError::BranchCheckedOut { name: s() },
+                "AGIT_BRANCH_CHECKED_OUT",
+                false,
+            ),
+            (
+                AgitError::MergeConflict { conflicts: vec![] },
+                "AGIT_MERGE_CONFLICT",
+                false,
+            ),
+            (
+                AgitError::ConcurrentUpdate { name: s() },
+                "AGIT_CONCURRENT_UPDATE",
+                true,
+            ),
+            (
+                AgitError::LockTimeout { name: s() },
+                "AGIT_LOCK_TIMEOUT",
+                true,
+            ),
+            (
+                AgitError::NoCommonAncestor {
+                    ours: s(),
+                    theirs: s(),
+                },
+                "AGIT_NO_COMMON_ANCESTOR",
+                false,
+            ),
+            (AgitError::DetachedHead, "AGIT_DETACHED_HEAD", false),
+            (AgitError::storage("x"), "AGIT_STORAGE", false),
+            (AgitError::transient_storage("x"), "AGIT_STORAGE", true),
+            (AgitError::Serialization(s()), "AGIT_SERIALIZATION", false),
+            (
+                AgitError::InvalidArgument(s()),
+                "AGIT_INVALID_ARGUMENT",
+                false,
+            ),
+            (
+                AgitError::InvalidOperation(s()),
+                "AGIT_INVALID_OPERATION",
+                false,
+            ),
+            (AgitError::NoCommits, "AGIT_NO_COMMITS", false),
+            (AgitError::EncryptionError(s()), "AGIT_ENCRYPTION", false),
+            (
+                AgitError::EncryptedState { hash: s() },
+                "AGIT_ENCRYPTED_STATE",
+                false,
+            ),
+            (
+                AgitError::DepthLimitExceeded(s()),
+                "AGIT_DEPTH_LIMIT_EXCEEDED",
+                false,
+            ),
+            (
+                AgitError::ProtectedBranch {
+                    branch: s(),
+                    rule: s(),
+                },
+                "AGIT_PROTECTED_BRANCH",
+                false,
+            ),
+            (
+                AgitError::SchemaViolation {
+                    path: s(),
+                    reason: s(),
+                },
+                "AGIT_SCHEMA_VIOLATION",
+                false,
+            ),
+            (
+                AgitError::PatchConflict {
+                    path: s(),
+                    reason: s(),
+                },
+                "AGIT_PATCH_CONFLICT",
+                false,
+            ),
+            (
+                AgitError::StateTooLarge { bytes: 2, limit: 1 },
+                "AGIT_STATE_TOO_LARGE",
+                false,
+            ),
+            (
+                AgitError::StateTooDeep { depth: 2, limit: 1 },
+                "AGIT_STATE_TOO_DEEP",
+                false,
+            ),
+            (
+                AgitError::StateTooManyKeys { keys: 2, limit: 1 },
+                "AGIT_STATE_TOO_MANY_KEYS",
+                false,
+            ),
+        ];
+        for (err, code, retryable) in cases {
+            assert_eq!(err.code(), code, "{err:?}");
+            assert_eq!(err.is_retryable(), retryable, "{err:?}");
+        }
+    }
+
+    #[test]
+    fn test_io_errors_become_storage_errors() {
+        use std::io::{Error, ErrorKind};
+        let err = AgitError::from(Error::new(ErrorKind::TimedOut, "slow disk"));
+        assert!(matches!(&err, AgitError::Storage { message, .. } if message == "slow disk"));
+        assert!(err.is_retryable());
+        assert!(!AgitError::from(Error::from(ErrorKind::PermissionDenied)).is_retryable());
+    }
+}
@@ -0,0 +1,242 @@
+//! Repository integrity checking.
+//!
+//! `fsck` recomputes the hash of every stored object, walks the commit DAG
+//! from all refs, and reports missing objects, hash mismatches, and orphans.
+//! State blobs behind remote-tracking refs are allowed to be missing, since
+//! fetching leaves them behind.
+//! Cached Merkle tree objects are checked against the blob they describe, and
+//! chunks listed by a blob's chunk manifests count as reachable from it.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::chunking;
+use crate::error::Result;
+use crate::hash::{canonical_serialize, compute_hash};
+use crate::objects::{tree_key, Commit, TREE_KEY_SUFFIX};
+use crate::refs::{Head, RefStore, CONFIG_REF_PREFIX};
+use crate::state::MerkleNode;
+use crate::storage::StorageBackend;
+use crate::types::{Hash, ObjectType};
+
+/// Options for `Repository::fsck_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct FsckOptions {
+    /// Re-upload missing objects when identical content is stored under another key.
+    pub repair: bool,
+}
+
+/// An object whose content does not hash to the key it is stored under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashMismatch {
+    /// Key the object is stored under.
+    pub key: String,
+    /// Hash recomputed from the stored content. For a Merkle tree object,
+    /// the root hash recomputed from its blob.
+    pub actual: String,
+}
+
+/// Result of an integrity check.
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// Number of stored objects examined.
+    pub objects_checked: usize,
+    /// Objects referenced by a ref, commit parent, or tree hash that do not exist.
+    pub missing: Vec<String>,
+    /// Objects whose content does not match their key.
+    pub hash_mismatches: Vec<HashMismatch>,
+    /// Stored objects not reachable from any ref.
+    pub orphans: Vec<String>,
+    /// Missing objects restored from a duplicate (only in repair mode).
+    pub repaired: Vec<String>,
+}
+
+impl FsckReport {
+    /// True if no missing objects or hash mismatches were found.
+    /// Orphans are not considered errors; `gc` removes them.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.hash_mismatches.is_empty()
+    }
+}
+
+/// A stored object parsed for checking.
+struct Checked {
+    obj_type: ObjectType,
+    actual: String,
+    commit: Option<Commit>,
+}
+
+fn check_object(key: &str, data: &[u8]) -> Checked {
+    if let Ok(commit) = serde_json::from_slice::<Commit>(data) {
+        return Checked {
+            obj_type: ObjectType::Commit,
+            actual: commit.hash().0,
+            commit: Some(commit),
+        };
+    }
+    // Blobs are stored canonically and chunks verbatim, so both hash as-is
+    let raw = compute_hash(ObjectType::Blob, data).0;
+    let actual = if raw == key {
+        raw
+    } else {
+        match serde_json::from_slice::<serde_json::Value>(data) {
+            Ok(value) => compute_hash(ObjectType::Blob, &canonical_serialize(&value)).0,
+            Err(_) => raw,
+        }
+    };
+    Checked {
+        obj_type: ObjectType::Blob,
+        actual,
+        commit: None,
+    }
+}
+
+/// Check a cached Merkle tree against its blob. Returns the recomputed root
+/// hash on mismatch; a tree whose blob is missing is left to the orphan check.
+async fn check_tree(
+    storage: &dyn StorageBackend,
+    key: &str,
+    data: &[u8],
+) -> Result<Option<String>> {
+    let blob_hash = &key[..key.len() - TREE_KEY_SUFFIX.len()];
+    let Some(blob) = storage.get_object(blob_hash).await? else {
+        return Ok(None);
+    };
+    let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&blob) else {
+        return Ok(Some(String::new()));
+    };
+    // Missing chunks are reported by the DAG walk
+    if chunking::reassemble(storage, &mut value).await.is_err() {
+        return Ok(None);
+    }
+    let expected = MerkleNode::from_value(&value).hash;
+    match serde_json::from_slice::<MerkleNode>(data) {
+        Ok(tree) if tree.hash == expected => Ok(None),
+        _ => Ok(Some(expected)),
+    }
+}
+
+/// Verify object integrity and DAG consistency.
+///
+/// # Arguments
+/// * `storage` - The storage backend
+/// * `refs` - The ref store with all branch tips
+/// * `options` - Whether to attempt repairs
+pub async fn fsck(
+    storage: &dyn StorageBackend,
+    refs: &RefStore,
+    options: &FsckOptions,
+) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    // Pass 1: recompute every object's hash
+    let all_objects = storage.list_objects().await?;
+    report.objects_checked = all_objects.len();
+    let mut commits: HashMap<String, Commit> = HashMap::new();
+    // Blob key -> chunks listed by its manifests
+    let mut chunk_refs: HashMap<String, Vec<String>> = HashMap::new();
+    // Recomputed content hash -> (stored key, type), for repair lookups
+    let mut by_content: HashMap<String, (String, ObjectType)> = HashMap::new();
+
+    for key in &all_objects {
+        let Some(data) = storage.get_object(key).await? else {
+            continue;
+        };
+        if key.ends_with(TREE_KEY_SUFFIX) {
+            if let Some(actual) = check_tree(storage, key, &data).await? {
+                report.hash_mismatches.push(HashMismatch {
+                    key: key.clone(),
+                    actual,
+                });
+            }
+            continue;
+        }
+        let checked = check_object(key, &data);
+        if checked.actual != *key {
+            report.hash_mismatches.push(HashMismatch {
+                key: key.clone(),
+                actual: checked.actual.clone(),
+            });
+        }
+        if let Some(commit) = checked.commit {
+            commits.insert(checked.actual.clone(), commit);
+        } else {
+            let chunks = chunking::chunk_refs_in(&data);
+            if !chunks.is_empty() {
+                chunk_refs.insert(key.clone(), chunks);
+            }
+        }
+        by_content
+            .entry(checked.actual)
+            .or_insert_with(|| (key.clone(), checked.obj_type));
+    }
+
+    // Pass 2: walk the DAG from every ref
+    let mut roots: Vec<Hash> = refs.list_branches().values().cloned().collect();
+    if let Head::Detached(hash) = refs.get_head() {
+        roots.push(hash.clone());
+    }
+    for (name, target) in storage.list_refs().await? {
+        if name.starts_with(CONFIG_REF_PREFIX) {
+            roots.push(Hash::from(target));
+        }
+    }
+    // Fetches copy commits but not their state, so remote-tracking refs are
+    // walked last and the blobs behind them may be absent
+    let tracking: Vec<Hash> = refs.list_tracking_refs().values().cloned().collect();
+
+    let existing: HashSet<&String> = all_objects.iter().collect();
+    let mut visited: HashSet<String> = HashSet::new();
+
+    for (roots, lazy) in [(roots, false), (tracking, true)] {
+        let mut queue: VecDeque<(String, bool)> = roots.into_iter().map(|h| (h.0, false)).collect();
+
+        while let Some((hash, optional)) = queue.pop_front() {
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+
+            if !existing.contains(&hash) {
+                let restored = match by_content.get(&hash) {
+                    Some((source, obj_type)) if options.repair => {
+                        match storage.get_object(source).await? {
+                            Some(data) => {
+                                storage.put_object(&hash, *obj_type, &data).await?;
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    _ => false,
+                };
+                if restored {
+                    report.repaired.push(hash.clone());
+                } else {
+                    if !optional {
+                        report.missing.push(hash);
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(commit) = commits.get(&hash) {
+                // The cached Merkle tree is optional, so it is never reported missing
+                visited.insert(tree_key(commit.tree_hash.as_str()));
+                queue.push_back((commit.tree_hash.0.clone(), lazy));
+                for parent in &commit.parent_hashes {
+                    queue.push_back((parent.0.clone(), false));
+                }
+            } else if let Some(chunks) = chunk_refs.get(&hash) {
+                queue.extend(chunks.iter().map(|c| (c.clone(), optional)));
+            }
+        }
+    }
+
+    report.orphans = all_objects
+        .into_iter()
+        .filter(|key| !visited.contains(key))
+        .collect();
+
+    report.missing.sort();
+    report.orphans.sort();
+    Ok(report)
+}
@@ -5,24 +5,197 @@
 //! - `squash`: Collapse a range of commits into a single commit
 
 use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
 
+use crate::chunking;
 use crate::error::{AgitError, Result};
-use crate::objects::Commit;
+use crate::objects::{tree_key, Commit};
 use crate::refs::RefStore;
-use crate::storage::StorageBackend;
-use crate::types::{ActionType, Hash, ObjectType};
+use crate::repo::{
+    COST_METADATA_KEY, ENCRYPTION_KEY_ID_METADATA_KEY, GENERATION_METADATA_KEY,
+    MERGE_REPORT_METADATA_KEY, MERKLE_ROOT_METADATA_KEY,
+};
+use crate::signing::{sign_or_strip, SIGNATURE_KEY};
+use crate::storage::{CompactReport, RefUpdate, StorageBackend, StorageStats};
+use crate::types::{Hash, ObjectType};
 
-/// Result of a garbage collection run.
+/// Objects garbage collection deletes per `delete_objects` call.
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// Objects marked between progress reports and cancellation checks.
+const MARK_PROGRESS_INTERVAL: usize = 1000;
+
+/// Most unreachable keys listed in a `GcResult`; `unreachable_count` has
+/// the full number.
+pub const GC_REPORT_LIMIT: usize = 1000;
+
+/// A batch of unreachable objects garbage collection failed to delete.
 #[derive(Debug, Clone)]
+pub struct GcBatchFailure {
+    /// Keys of the objects in the batch; some of them may have been deleted.
+    pub hashes: Vec<String>,
+    /// The storage error the batch failed with.
+    pub error: String,
+}
+
+/// Options for `Repository::gc_with_options`.
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+    /// Report what would be removed without deleting anything.
+    pub dry_run: bool,
+    /// Always keep at least this many commits on each branch (0 = only
+    /// keep reachable objects).
+    pub keep_last_n: usize,
+    /// Compact storage after the sweep to give freed space back.
+    pub compact: bool,
+    /// Fewest removed objects worth compacting for; smaller sweeps skip it.
+    pub compact_threshold: usize,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        GcOptions {
+            dry_run: false,
+            keep_last_n: 0,
+            compact: false,
+            compact_threshold: 100,
+        }
+    }
+}
+
+/// Stage of a garbage collection run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcPhase {
+    /// Walking the history from every root to mark reachable objects.
+    Marking,
+    /// Listing every stored object.
+    Listing,
+    /// Deleting unreachable objects in batches.
+    Sweeping,
+}
+
+impl GcPhase {
+    /// Lowercase name of the phase.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GcPhase::Marking => "marking",
+            GcPhase::Listing => "listing",
+            GcPhase::Sweeping => "sweeping",
+        }
+    }
+}
+
+/// Progress of a garbage collection run, passed to the callback of
+/// `Repository::gc_with_progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcProgress {
+    pub phase: GcPhase,
+    /// Objects processed so far in this phase.
+    pub processed: usize,
+    /// Objects this phase will process, when known.
+    pub total: Option<usize>,
+}
+
+/// Squashed-commit metadata key holding the sum of the squashed states'
+/// costs.
+pub const AGGREGATED_COST_METADATA_KEY: &str = "aggregated_cost";
+
+/// Squashed-commit metadata key listing the squashed commits' hashes,
+/// oldest first.
+pub const SQUASHED_HASHES_METADATA_KEY: &str = "squashed_hashes";
+
+/// Ref holding the auto-GC bookkeeping blob shared by every process.
+pub const GC_STATE_REF: &str = "config/gc_state";
+
+/// When `Repository::commit_with_metadata` runs garbage collection on its
+/// own, set through `RepoOptions::auto_gc`.
+#[derive(Debug, Clone)]
+pub struct AutoGcPolicy {
+    /// Run after this many commits since the last run (0 = never by count).
+    pub every_n_commits: usize,
+    /// Run when more than this fraction of stored objects is unreachable.
+    /// Measured with a dry run, at most once per `min_interval`.
+    pub max_unreachable_ratio: Option<f64>,
+    /// Least time between two runs, or two unreachable-ratio checks.
+    pub min_interval: Duration,
+    /// Passed to the run as `GcOptions::keep_last_n`.
+    pub keep_last_n: usize,
+}
+
+impl Default for AutoGcPolicy {
+    fn default() -> Self {
+        AutoGcPolicy {
+            every_n_commits: 1000,
+            max_unreachable_ratio: None,
+            min_interval: Duration::from_secs(3600),
+            keep_last_n: 0,
+        }
+    }
+}
+
+/// Auto-GC bookkeeping persisted under `GC_STATE_REF`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AutoGcState {
+    /// Commits made since the last run.
+    pub commits_since_gc: usize,
+    /// When auto-GC last ran.
+    pub last_run: Option<DateTime<Utc>>,
+    /// When the unreachable ratio was last measured.
+    pub last_check: Option<DateTime<Utc>>,
+}
+
+impl AutoGcState {
+    /// Whether `interval` has passed since `at`, or `at` never happened.
+    pub fn elapsed(at: Option<DateTime<Utc>>, interval: Duration, now: DateTime<Utc>) -> bool {
+        at.is_none_or(|at| {
+            now.signed_duration_since(at)
+                .to_std()
+                .is_ok_and(|since| since >= interval)
+        })
+    }
+}
+
+/// Result of a garbage collection run.
+#[derive(Debug, Clone, Default)]
 pub struct GcResult {
+    /// Whether this was a dry run that deleted nothing.
+    pub dry_run: bool,
+    /// Whether the run was cancelled. Batches swept before cancellation
+    /// stay deleted; no batch is left half-deleted.
+    pub cancelled: bool,
     /// Number of objects before GC.
     pub objects_before: usize,
     /// Number of objects removed.
     pub objects_removed: usize,
     /// Number of objects remaining.
     pub objects_after: usize,
+    /// Keys of the removed objects.
+    pub removed: Vec<String>,
+    /// Keys of the objects found unreachable, at most `GC_REPORT_LIMIT`.
+    pub unreachable: Vec<String>,
+    /// Number of objects found unreachable.
+    pub unreachable_count: usize,
+    /// Count and stored bytes of the objects freed, or that a dry run
+    /// would free, overall and per object type.
+    pub freed: StorageStats,
+    /// Batches whose deletion failed. Their objects count as remaining.
+    pub failures: Vec<GcBatchFailure>,
+    /// Storage compaction run after the sweep, if any.
+    pub compaction: Option<CompactReport>,
+}
+
+/// Options for `Repository::squash_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct SquashOptions {
+    /// Squash merge commits in the range, keeping their merged-in parents
+    /// as extra parents of the squashed commit. Without it a range
+    /// containing a merge is refused.
+    pub allow_merges: bool,
 }
 
 /// Result of a squash operation.
@@ -30,18 +203,37 @@ pub struct GcResult {
 pub struct SquashResult {
     /// The new squashed commit hash.
     pub new_hash: Hash,
+    /// The branch's new tip: `new_hash`, or the last commit replayed on
+    /// top of it.
+    pub new_tip: Hash,
     /// Number of commits squashed.
     pub commits_squashed: usize,
+    /// Hashes of the squashed commits, oldest first.
+    pub squashed_hashes: Vec<Hash>,
+    /// Sum of the squashed states' costs.
+    pub aggregated_cost: f64,
     /// The message of the squashed commit.
     pub message: String,
 }
 
 /// Collect all reachable object hashes starting from a set of root hashes.
-/// This traverses commits and their tree (blob) hashes.
+/// This traverses commits and their tree (blob) hashes, including the
+/// Merkle tree object cached for each blob and any chunks a blob lists.
 pub async fn collect_reachable(
     storage: &dyn StorageBackend,
     roots: &[Hash],
 ) -> Result<HashSet<String>> {
+    let reachable = mark_reachable(storage, roots, &mut |_| {}, &CancellationToken::new()).await?;
+    Ok(reachable.unwrap_or_default())
+}
+
+/// `collect_reachable` with marking progress, or `None` if `cancel` fired.
+async fn mark_reachable(
+    storage: &dyn StorageBackend,
+    roots: &[Hash],
+    on_progress: &mut (dyn FnMut(GcProgress) + Send),
+    cancel: &CancellationToken,
+) -> Result<Option<HashSet<String>>> {
     let mut reachable = HashSet::new();
     let mut queue: VecDeque<String> = roots.iter().map(|h| h.0.clone()).collect();
 
@@ -50,56 +242,124 @@ pub async fn collect_reachable(
             continue;
         }
         reachable.insert(hash.clone());
+        if reachable.len() % MARK_PROGRESS_INTERVAL == 0 {
+            if cancel.is_cancelled() {
+                return Ok(None);
+            }
+            on_progress(GcProgress {
+                phase: GcPhase::Marking,
+                processed: reachable.len(),
+                total: None,
+            });
+        }
 
         // Try to load as commit
         if let Some(data) = storage.get_object(&hash).await? {
             if let Ok(commit) = serde_json::from_slice::<Commit>(&data) {
-                // Add tree hash (blob)
+                // Add tree hash (blob) and its cached Merkle tree
                 if !reachable.contains(&commit.tree_hash.0) {
                     queue.push_back(commit.tree_hash.0.clone());
                 }
+                reachable.insert(tree_key(commit.tree_hash.as_str()));
                 // Add parent commits
                 for parent in &commit.parent_hashes {
                     if !reachable.contains(&parent.0) {
                         queue.push_back(parent.0.clone());
                     }
                 }
+            } else {
+                // A blob is already marked reachable; follow its chunk manifests
+                for chunk in chunking::chunk_refs_in(&data) {
+                    if !reachable.contains(&chunk) {
+                        queue.push_back(chunk);
+                    }
+                }
             }
-            // If it's a blob, it's already marked reachable
         }
     }
 
-    Ok(reachable)
+    Ok(Some(reachable))
 }
 
-/// Run garbage collection: remove objects not reachable from any branch tip.
+/// Run garbage collection: remove objects not reachable from any branch
+/// tip, HEAD, or persisted ref.
+///
+/// Each unreachable object is stat'ed before deletion to report the bytes
+/// freed. With `options.dry_run` nothing is deleted and the report
+/// describes what a real run would remove. Compaction is left to the
+/// caller.
 ///
 /// # Arguments
 /// * `storage` - The storage backend
 /// * `refs` - The ref store with all branch tips
-/// * `keep_last_n` - Always keep at least this many commits on each branch (0 = only keep reachable)
+/// * `options` - Dry-run and retention settings
 pub async fn gc(
     storage: &dyn StorageBackend,
     refs: &RefStore,
-    keep_last_n: usize,
+    options: &GcOptions,
 ) -> Result<GcResult> {
-    // Collect all branch tips as roots
-    let branches = refs.list_branches();
-    let roots: Vec<Hash> = branches.values().cloned().collect();
+    gc_with_progress(
+        storage,
+        refs,
+        options,
+        &mut |_| {},
+        &CancellationToken::new(),
+    )
+    .await
+}
+
+/// [`gc`], reporting progress to `on_progress` and stopping early once
+/// `cancel` fires.
+///
+/// Cancellation is checked while marking and before each sweep batch, so a
+/// cancelled run returns a partial result with `cancelled` set: either
+/// nothing was deleted or only whole batches were.
+pub async fn gc_with_progress(
+    storage: &dyn StorageBackend,
+    refs: &RefStore,
+    options: &GcOptions,
+    on_progress: &mut (dyn FnMut(GcProgress) + Send),
+    cancel: &CancellationToken,
+) -> Result<GcResult> {
+    let keep_last_n = options.keep_last_n;
+    let cancelled = || GcResult {
+        dry_run: options.dry_run,
+        cancelled: true,
+        ..Default::default()
+    };
+    // Root at every branch tip, the checked-out commit (even when HEAD is
+    // detached), and every persisted ref: configuration blobs, tags, stashes
+    // and branches other handles have written
+    let mut roots: Vec<Hash> = refs.list_branches().values().cloned().collect();
+    if let Ok(head) = refs.resolve_ref("HEAD") {
+        roots.push(head);
+    }
+    for (name, target) in storage.list_refs().await? {
+        if name == "HEAD" {
+            // A detached HEAD persisted by another handle is a commit too
+            if !target.starts_with("ref:") {
+                roots.push(Hash::from(target));
+            }
+        } else {
+            roots.push(Hash::from(target));
+        }
+    }
+    roots.sort_by(|a, b| a.0.cmp(&b.0));
+    roots.dedup();
 
     if roots.is_empty() {
         return Ok(GcResult {
-            objects_before: 0,
-            objects_removed: 0,
-            objects_after: 0,
+            dry_run: options.dry_run,
+            ..Default::default()
         });
     }
 
     // Find all reachable objects
-    let reachable = collect_reachable(storage, &roots).await?;
+    let Some(mut reachable) = mark_reachable(storage, &roots, on_progress, cancel).await? else {
+        return Ok(cancelled());
+    };
 
     // Additionally mark the last N commits per branch as reachable
-    let mut reachable = reachable;
     if keep_last_n > 0 {
         for root in &roots {
             let mut queue: VecDeque<String> = VecDeque::new();
@@ -115,8 +375,9 @@ pub async fn gc(
 
                 if let Some(data) = storage.get_object(&hash).await? {
                     if let Ok(commit) = serde_json::from_slice::<Commit>(&data) {
-                        // Mark the tree blob as reachable too
+                        // Mark the tree blob and its Merkle tree as reachable too
                         reachable.insert(commit.tree_hash.0.clone());
+                        reachable.insert(tree_key(commit.tree_hash.as_str()));
                         for parent in &commit.parent_hashes {
                             if !reachable.contains(&parent.0) {
                                 queue.push_back(parent.0.clone());
@@ -128,150 +389,305 @@ pub async fn gc(
         }
     }
 
+    if cancel.is_cancelled() {
+        return Ok(cancelled());
+    }
+    on_progress(GcProgress {
+        phase: GcPhase::Marking,
+        processed: reachable.len(),
+        total: Some(reachable.len()),
+    });
+
     // List all objects and delete unreachable ones
+    on_progress(GcProgress {
+        phase: GcPhase::Listing,
+        processed: 0,
+        total: None,
+    });
     let all_objects = storage.list_objects().await?;
     let objects_before = all_objects.len();
-    let mut objects_removed = 0;
+    on_progress(GcProgress {
+        phase: GcPhase::Listing,
+        processed: objects_before,
+        total: Some(objects_before),
+    });
+    let unreachable: Vec<String> = all_objects
+        .into_iter()
+        .filter(|hash| !reachable.contains(hash))
+        .collect();
+
+    let mut result = GcResult {
+        dry_run: options.dry_run,
+        objects_before,
+        objects_after: objects_before,
+        unreachable: unreachable.iter().take(GC_REPORT_LIMIT).cloned().collect(),
+        unreachable_count: unreachable.len(),
+        ..Default::default()
+    };
 
-    for hash in &all_objects {
-        if !reachable.contains(hash) {
-            if storage.delete_object(hash).await? {
-                objects_removed += 1;
+    // A failed batch is recorded and the sweep moves on to the next one
+    let mut processed = 0;
+    for batch in unreachable.chunks(DELETE_BATCH_SIZE) {
+        if cancel.is_cancelled() {
+            result.cancelled = true;
+            break;
+        }
+        let mut batch_stats = StorageStats::default();
+        for hash in batch {
+            if let Some(stat) = storage.stat_object(hash).await? {
+                batch_stats.add(stat.obj_type, stat.size);
+            }
+        }
+        if options.dry_run {
+            result.freed.merge(&batch_stats);
+        } else {
+            match storage.delete_objects(batch).await {
+                Ok(count) => {
+                    result.objects_removed += count;
+                    result.removed.extend_from_slice(batch);
+                    result.freed.merge(&batch_stats);
+                }
+                Err(e) => result.failures.push(GcBatchFailure {
+                    hashes: batch.to_vec(),
+                    error: e.to_string(),
+                }),
             }
         }
+        processed += batch.len();
+        on_progress(GcProgress {
+            phase: GcPhase::Sweeping,
+            processed,
+            total: Some(unreachable.len()),
+        });
     }
+    result.objects_after = objects_before - result.objects_removed;
 
-    Ok(GcResult {
-        objects_before,
-        objects_removed,
-        objects_after: objects_before - objects_removed,
-    })
+    Ok(result)
 }
 
 /// Squash a range of commits on a branch into a single commit.
 ///
-/// The squashed commit preserves the final state and has the parent
-/// of the first commit in the range as its parent.
+/// The range is the first-parent chain from `to_hash` back to `from_hash`,
+/// which must both lie on the branch's first-parent history. The squashed
+/// commit keeps the final state and action type and takes the first parent
+/// of `from_hash` as its parent. Its metadata is the union of the range's,
+/// later commits winning, plus `SQUASHED_HASHES_METADATA_KEY` and
+/// `AGGREGATED_COST_METADATA_KEY`. Commits made on the branch after `to_hash` are replayed
+/// on top of it with their states unchanged. The squashed and replayed
+/// commits are signed with `signing_key`, or left unsigned without one.
 ///
 /// # Arguments
 /// * `storage` - The storage backend
 /// * `refs` - The ref store
 /// * `agent_id` - Agent ID for the new commit
+/// * `signing_key` - Key to sign rewritten commits with, if any
 /// * `branch` - Branch name to squash on
 /// * `from_hash` - Start of range (oldest commit, inclusive)
 /// * `to_hash` - End of range (newest commit, inclusive -- its state is preserved)
+/// * `options` - Whether merge commits may be squashed
+#[allow(clippy::too_many_arguments)]
 pub async fn squash(
     storage: &dyn StorageBackend,
     refs: &mut RefStore,
     agent_id: &str,
+    signing_key: Option<&[u8]>,
     branch: &str,
     from_hash: &str,
     to_hash: &str,
+    options: &SquashOptions,
 ) -> Result<SquashResult> {
-    // Load the range of commits to count them
-    let mut commits_in_range = Vec::new();
-    let mut current = to_hash.to_string();
-
-    loop {
-        let data = storage
-            .get_object(&current)
-            .await?
-            .ok_or_else(|| AgitError::ObjectNotFound {
-                hash: current.clone(),
+    let tip =
+        refs.list_branches()
+            .get(branch)
+            .cloned()
+            .ok_or_else(|| AgitError::BranchNotFound {
+                name: branch.to_string(),
             })?;
-        let commit: Commit = serde_json::from_slice(&data)?;
-        commits_in_range.push(commit.clone());
 
-        if current == from_hash {
+    // Commits after the range, newest first, down to (excluding) to_hash
+    let mut later = Vec::new();
+    let mut current = Some(tip.0.clone());
+    loop {
+        let Some(hash) = current else {
+            return Err(AgitError::InvalidOperation(format!(
+                "{} is not on the first-parent history of branch '{}'",
+                to_hash, branch
+            )));
+        };
+        if hash == to_hash {
             break;
         }
+        let commit = load_commit(storage, &hash).await?;
+        current = commit.parent_hashes.first().map(|p| p.0.clone());
+        later.push(commit);
+    }
 
-        match commit.parent_hashes.first() {
-            Some(parent) => current = parent.0.clone(),
-            None => break,
+    // The range itself, newest first
+    let mut commits_in_range = Vec::new();
+    let mut current = Some(to_hash.to_string());
+    loop {
+        let Some(hash) = current else {
+            return Err(AgitError::InvalidOperation(format!(
+                "{} is not a first-parent ancestor of {} on branch '{}'",
+                from_hash, to_hash, branch
+            )));
+        };
+        let commit = load_commit(storage, &hash).await?;
+        current = commit.parent_hashes.first().map(|p| p.0.clone());
+        commits_in_range.push((hash.clone(), commit));
+        if hash == from_hash {
+            break;
         }
     }
 
-    if commits_in_range.is_empty() {
-        return Err(AgitError::InvalidOperation(
-            "No commits found in squash range".to_string(),
-        ));
+    let merges = commits_in_range
+        .iter()
+        .filter(|(_, c)| c.parent_hashes.len() > 1)
+        .count();
+    if merges > 0 && !options.allow_merges {
+        return Err(AgitError::InvalidOperation(format!(
+            "squash range contains {} merge commit(s); pass allow_merges to squash them",
+            merges
+        )));
     }
 
-    // Get the final state (from to_hash)
-    let final_commit_data = storage
-        .get_object(to_hash)
-        .await?
-        .ok_or_else(|| AgitError::ObjectNotFound {
-            hash: to_hash.to_string(),
-        })?;
-    let final_commit: Commit = serde_json::from_slice(&final_commit_data)?;
-
-    // Get the state blob
-    let state_data = storage
-        .get_object(final_commit.tree_hash.as_str())
-        .await?
-        .ok_or_else(|| AgitError::ObjectNotFound {
-            hash: final_commit.tree_hash.to_string(),
-        })?;
-
-    // Determine parent: the parent of from_hash (the commit before the range)
-    let from_data = storage
-        .get_object(from_hash)
-        .await?
-        .ok_or_else(|| AgitError::ObjectNotFound {
-            hash: from_hash.to_string(),
-        })?;
-    let from_commit: Commit = serde_json::from_slice(&from_data)?;
-    let parent_hashes = from_commit.parent_hashes.clone();
-
-    // Collect messages
-    let messages: Vec<String> = commits_in_range
-        .iter()
-        .rev()
-        .map(|c| c.message.clone())
+    // First parent of from_hash, then the merged-in parents of the range,
+    // oldest merge first
+    let in_range: HashSet<&str> = commits_in_range.iter().map(|(h, _)| h.as_str()).collect();
+    let (_, from_commit) = commits_in_range.last().expect("range is never empty");
+    let mut parent_hashes: Vec<Hash> = from_commit
+        .parent_hashes
+        .first()
+        .cloned()
+        .into_iter()
         .collect();
+    for (_, commit) in commits_in_range.iter().rev() {
+        for parent in commit.parent_hashes.iter().skip(1) {
+            if !in_range.contains(parent.as_str()) && !parent_hashes.contains(parent) {
+                parent_hashes.push(parent.clone());
+            }
+        }
+    }
+
+    // Collect messages, metadata and costs, oldest first
+    let mut messages = Vec::new();
+    let mut metadata = serde_json::Map::new();
+    let mut squashed_hashes = Vec::new();
+    let mut aggregated_cost = 0.0;
+    for (hash, commit) in commits_in_range.iter().rev() {
+        messages.push(commit.message.clone());
+        metadata.extend(commit.metadata.clone());
+        squashed_hashes.push(Hash(hash.clone()));
+        aggregated_cost += commit_cost(storage, commit).await?;
+    }
     let squash_message = format!(
         "squash {} commits: {}",
         commits_in_range.len(),
         messages.join("; ")
     );
 
-    // Create new squashed commit
-    let new_commit = Commit {
+    // Keys describing a single commit or its state come from the final
+    // commit alone; the new commit is signed afresh below, and generations
+    // are recomputed for the new topology when needed
+    let (_, final_commit) = &commits_in_range[0];
+    metadata.remove(SIGNATURE_KEY);
+    metadata.remove(MERGE_REPORT_METADATA_KEY);
+    metadata.remove(GENERATION_METADATA_KEY);
+    for key in [
+        COST_METADATA_KEY,
+        MERKLE_ROOT_METADATA_KEY,
+        ENCRYPTION_KEY_ID_METADATA_KEY,
+    ] {
+        match final_commit.metadata.get(key) {
+            Some(value) => metadata.insert(key.to_string(), value.clone()),
+            None => metadata.remove(key),
+        };
+    }
+    metadata.insert(
+        SQUASHED_HASHES_METADATA_KEY.to_string(),
+        serde_json::to_value(&squashed_hashes)?,
+    );
+    metadata.insert(
+        AGGREGATED_COST_METADATA_KEY.to_string(),
+        Value::from(aggregated_cost),
+    );
+
+    // Create new squashed commit with the final state
+    let mut new_commit = Commit {
         tree_hash: final_commit.tree_hash.clone(),
         parent_hashes,
         message: squash_message.clone(),
         author: agent_id.to_string(),
         timestamp: Utc::now(),
-        action_type: ActionType::Checkpoint,
-        metadata: serde_json::Map::new(),
+        action_type: final_commit.action_type.clone(),
+        metadata,
     };
-
+    sign_or_strip(&mut new_commit, signing_key);
     let new_hash = new_commit.hash();
-    let commit_data = serde_json::to_vec(&new_commit)?;
-
-    // Store the squashed commit
-    storage
-        .put_object(new_hash.as_str(), ObjectType::Commit, &commit_data)
-        .await?;
-
-    // Ensure the state blob exists (it should already)
     storage
         .put_object(
-            final_commit.tree_hash.as_str(),
-            ObjectType::Blob,
-            &state_data,
+            new_hash.as_str(),
+            ObjectType::Commit,
+            &serde_json::to_vec(&new_commit)?,
         )
         .await?;
 
-    // Update branch ref to point to new squashed commit
-    refs.update_branch(branch, new_hash.clone())?;
-    storage.set_ref(branch, new_hash.as_str()).await?;
+    // Replay later commits, oldest first, onto the squashed commit
+    let mut new_tip = new_hash.clone();
+    for mut commit in later.into_iter().rev() {
+        commit.parent_hashes[0] = new_tip;
+        commit.metadata.remove(GENERATION_METADATA_KEY);
+        // The old signature covers the old parent
+        sign_or_strip(&mut commit, signing_key);
+        new_tip = commit.hash();
+        storage
+            .put_object(
+                new_tip.as_str(),
+                ObjectType::Commit,
+                &serde_json::to_vec(&commit)?,
+            )
+            .await?;
+    }
+
+    // Only move the branch if nobody else has since
+    storage
+        .update_refs(&[RefUpdate::set(branch, new_tip.as_str()).expecting(tip.as_str())])
+        .await?;
+    refs.update_branch(branch, new_tip.clone())?;
 
     Ok(SquashResult {
         new_hash,
+        new_tip,
         commits_squashed: commits_in_range.len(),
+        squashed_hashes,
+        aggregated_cost,
         message: squash_message,
     })
 }
+
+/// The state cost of `commit`, from its metadata or, for commits written
+/// before the cost was cached there, its state blob.
+async fn commit_cost(storage: &dyn StorageBackend, commit: &Commit) -> Result<f64> {
+    if let Some(cost) = commit
+        .metadata
+        .get(COST_METADATA_KEY)
+        .and_then(Value::as_f64)
+    {
+        return Ok(cost);
+    }
+    let Some(data) = storage.get_object(commit.tree_hash.as_str()).await? else {
+        return Ok(0.0);
+    };
+    let state: Value = serde_json::from_slice(&data)?;
+    Ok(state.get("cost").and_then(Value::as_f64).unwrap_or(0.0))
+}
+
+async fn load_commit(storage: &dyn StorageBackend, hash: &str) -> Result<Commit> {
+    let data = storage
+        .get_object(hash)
+        .await?
+        .ok_or_else(|| AgitError::ObjectNotFound {
+            hash: hash.to_string(),
+        })?;
+    Ok(serde_json::from_slice(&data)?)
+}
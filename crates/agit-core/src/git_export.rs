@@ -0,0 +1,243 @@
+//! Exporting agit history as a Git repository.
+//!
+//! Each agit commit becomes a Git commit whose tree holds the state as
+//! pretty-printed `memory.json`, `world_state.json` and `metadata.json`,
+//! with the same parents, author, timestamp and branch names. The agit hash
+//! is kept in an `Agit-Hash:` trailer of the message. A mapping file of
+//! `<agit hash> <git oid>` lines records what has been exported so later
+//! runs only write new commits.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use git2::{Oid, Signature, Time};
+use serde_json::{json, Value};
+
+use crate::error::{AgitError, Result};
+use crate::objects::Commit;
+use crate::state::AgentState;
+use crate::types::Hash;
+
+/// Name of the mapping file inside the Git directory.
+pub const MAPPING_FILE: &str = "agit-export-map";
+
+/// Trailer holding the agit commit hash in each exported message.
+pub const HASH_TRAILER: &str = "Agit-Hash";
+
+/// Domain of the `<author>@agit` email given to exported commits.
+pub const EMAIL_DOMAIN: &str = "agit";
+
+/// Options for `Repository::export_git`.
+#[derive(Debug, Clone, Default)]
+pub struct GitExportOptions {
+    /// Branches to export; empty exports every branch.
+    pub branches: Vec<String>,
+    /// Where to keep the agit-to-Git mapping (default: `MAPPING_FILE` in
+    /// the Git directory).
+    pub mapping_file: Option<PathBuf>,
+}
+
+/// Result of a Git export.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GitExportReport {
+    /// Commits written to the Git repository by this run.
+    pub commits_exported: usize,
+    /// Reachable commits already exported by an earlier run.
+    pub commits_skipped: usize,
+    /// Branches whose Git refs were set, sorted.
+    pub branches: Vec<String>,
+    /// Git commit id of every exported agit commit, old and new.
+    pub mapping: HashMap<Hash, String>,
+}
+
+fn git_err(e: git2::Error) -> AgitError {
+    AgitError::storage(format!("git export: {}", e.message()))
+}
+
+fn io_err(path: &Path, e: std::io::Error) -> AgitError {
+    AgitError::storage(format!("git export: {}: {}", path.display(), e))
+}
+
+/// Writes commits into a Git repository, tracking the mapping.
+pub(crate) struct GitExporter {
+    git: git2::Repository,
+    mapping_path: PathBuf,
+    mapping: HashMap<Hash, Oid>,
+    added: Vec<(Hash, Oid)>,
+}
+
+impl GitExporter {
+    /// Open the Git repository at `path`, creating a bare one if there is
+    /// none, and load the mapping of earlier exports.
+    pub(crate) fn open(path: &Path, options: &GitExportOptions) -> Result<Self> {
+        let git = match git2::Repository::open(path) {
+            Ok(git) => git,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {
+                git2::Repository::init_bare(path).map_err(git_err)?
+            }
+            Err(e) => return Err(git_err(e)),
+        };
+        let mapping_path = options
+            .mapping_file
+            .clone()
+            .unwrap_or_else(|| git.path().join(MAPPING_FILE));
+        let mut mapping = HashMap::new();
+        match std::fs::read_to_string(&mapping_path) {
+            Ok(text) => {
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    let parsed = line
+                        .split_once(' ')
+                        .and_then(|(hash, oid)| Some((hash, Oid::from_str(oid.trim()).ok()?)));
+                    let Some((hash, oid)) = parsed else {
+                        return Err(AgitError::storage(format!(
+                            "git export: malformed line in {}: {}",
+                            mapping_path.display(),
+                            line
+                        )));
+                    };
+                    // Entries whose commit has since gone from Git are re-exported
+                    if git.find_commit(oid).is_ok() {
+                        mapping.insert(Hash(hash.to_string()), oid);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_err(&mapping_path, e)),
+        }
+        Ok(GitExporter {
+            git,
+            mapping_path,
+            mapping,
+            added: Vec::new(),
+        })
+    }
+
+    pub(crate) fn is_exported(&self, hash: &Hash) -> bool {
+        self.mapping.contains_key(hash)
+    }
+
+    /// Write `commit` with `state` as its tree. Its parents must already be
+    /// exported.
+    pub(crate) fn write_commit(
+        &mut self,
+        hash: &Hash,
+        commit: &Commit,
+        state: &AgentState,
+    ) -> Result<()> {
+        let metadata = json!({
+            "action_type": commit.action_type,
+            "cost": state.cost,
+            "timestamp": state.timestamp,
+            "commit": commit.metadata,
+            "state": state.metadata,
+        });
+        let mut tree = self.git.treebuilder(None).map_err(git_err)?;
+        for (name, value) in [
+            ("memory.json", &state.memory),
+            ("world_state.json", &state.world_state),
+            ("metadata.json", &metadata),
+        ] {
+            let blob = self.git.blob(&pretty(value)?).map_err(git_err)?;
+            tree.insert(name, blob, 0o100644).map_err(git_err)?;
+        }
+        let tree = self
+            .git
+            .find_tree(tree.write().map_err(git_err)?)
+            .map_err(git_err)?;
+
+        let parents = commit
+            .parent_hashes
+            .iter()
+            .map(|p| {
+                let oid = self
+                    .mapping
+                    .get(p)
+                    .ok_or_else(|| AgitError::ObjectNotFound {
+                        hash: p.to_string(),
+                    })?;
+                self.git.find_commit(*oid).map_err(git_err)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+
+        let time = Time::new(commit.timestamp.timestamp(), 0);
+        // Git wants an email; agents only have an id
+        let email = format!("{}@{}", commit.author, EMAIL_DOMAIN);
+        let signature = Signature::new(&commit.author, &email, &time).map_err(git_err)?;
+        let message = format!(
+            "{}\n\n{}: {}\n",
+            commit.message.trim_end(),
+            HASH_TRAILER,
+            hash
+        );
+        let oid = self
+            .git
+            .commit(None, &signature, &signature, &message, &tree, &parents)
+            .map_err(git_err)?;
+        self.mapping.insert(hash.clone(), oid);
+        self.added.push((hash.clone(), oid));
+        Ok(())
+    }
+
+    /// Point `refs/heads/<branch>` at each tip, attach HEAD to
+    /// `current_branch` when it was exported, and append new entries to
+    /// the mapping file.
+    pub(crate) fn finish(
+        self,
+        tips: &[(String, Hash)],
+        current_branch: Option<&str>,
+        mut report: GitExportReport,
+    ) -> Result<GitExportReport> {
+        for (branch, tip) in tips {
+            let oid = self
+                .mapping
+                .get(tip)
+                .ok_or_else(|| AgitError::ObjectNotFound {
+                    hash: tip.to_string(),
+                })?;
+            self.git
+                .reference(
+                    &format!("refs/heads/{}", branch),
+                    *oid,
+                    true,
+                    &format!("agit export: {}", branch),
+                )
+                .map_err(git_err)?;
+            report.branches.push(branch.clone());
+        }
+        if let Some(branch) = current_branch.filter(|b| tips.iter().any(|(name, _)| name == b)) {
+            self.git
+                .set_head(&format!("refs/heads/{}", branch))
+                .map_err(git_err)?;
+        }
+
+        if !self.added.is_empty() {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.mapping_path)
+                .map_err(|e| io_err(&self.mapping_path, e))?;
+            let mut lines = String::new();
+            for (hash, oid) in &self.added {
+                lines.push_str(&format!("{} {}\n", hash, oid));
+            }
+            file.write_all(lines.as_bytes())
+                .map_err(|e| io_err(&self.mapping_path, e))?;
+        }
+
+        report.branches.sort();
+        report.mapping = self
+            .mapping
+            .into_iter()
+            .map(|(hash, oid)| (hash, oid.to_string()))
+            .collect();
+        Ok(report)
+    }
+}
+
+fn pretty(value: &Value) -> Result<Vec<u8>> {
+    let mut bytes = serde_json::to_vec_pretty(value)?;
+    bytes.push(b'\n');
+    Ok(bytes)
+}
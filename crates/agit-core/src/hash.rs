@@ -2,11 +2,35 @@ use sha2::{Digest, Sha256};
 
 use crate::types::{Hash, ObjectType};
 
+/// Shape of a value, measured while canonically serializing it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SerializeStats {
+    /// Length of the serialized output.
+    pub bytes: usize,
+    /// Deepest nesting of objects and arrays; a scalar has depth 0.
+    pub depth: usize,
+    /// Total number of object keys at all levels.
+    pub keys: usize,
+}
+
 /// Serialize a JSON value with sorted keys for deterministic hashing.
 pub fn canonical_serialize(value: &serde_json::Value) -> Vec<u8> {
-    fn write_sorted(value: &serde_json::Value, buf: &mut Vec<u8>) {
+    canonical_serialize_with_stats(value).0
+}
+
+/// Like `canonical_serialize`, also measuring size, depth, and key count
+/// in the same pass.
+pub fn canonical_serialize_with_stats(value: &serde_json::Value) -> (Vec<u8>, SerializeStats) {
+    fn write_sorted(
+        value: &serde_json::Value,
+        buf: &mut Vec<u8>,
+        depth: usize,
+        stats: &mut SerializeStats,
+    ) {
         match value {
             serde_json::Value::Object(map) => {
+                stats.depth = stats.depth.max(depth + 1);
+                stats.keys += map.len();
                 let mut keys: Vec<&String> = map.keys().collect();
                 keys.sort();
                 buf.push(b'{');
@@ -20,17 +44,18 @@ pub fn canonical_serialize(value: &serde_json::Value) -> Vec<u8> {
                     buf.push(b'"');
                     buf.push(b':');
                     // Write value recursively
-                    write_sorted(&map[*key], buf);
+                    write_sorted(&map[*key], buf, depth + 1, stats);
                 }
                 buf.push(b'}');
             }
             serde_json::Value::Array(arr) => {
+                stats.depth = stats.depth.max(depth + 1);
                 buf.push(b'[');
                 for (i, item) in arr.iter().enumerate() {
                     if i > 0 {
                         buf.push(b',');
                     }
-                    write_sorted(item, buf);
+                    write_sorted(item, buf, depth + 1, stats);
                 }
                 buf.push(b']');
             }
@@ -43,8 +68,10 @@ pub fn canonical_serialize(value: &serde_json::Value) -> Vec<u8> {
     }
 
     let mut buf = Vec::new();
-    write_sorted(value, &mut buf);
-    buf
+    let mut stats = SerializeStats::default();
+    write_sorted(value, &mut buf, 0, &mut stats);
+    stats.bytes = buf.len();
+    (buf, stats)
 }
 
 /// Compute a SHA-256 hash using Git-style format: `<type> <len>\0<content>`.
@@ -64,7 +91,7 @@ pub fn compute_state_hash(state: &serde_json::Value) -> Hash {
 }
 
 // Inline hex encoding to avoid adding the `hex` crate dependency.
-mod hex {
+pub(crate) mod hex {
     pub fn encode(bytes: impl AsRef<[u8]>) -> String {
         bytes
             .as_ref()
@@ -72,6 +99,17 @@ mod hex {
             .map(|b| format!("{:02x}", b))
             .collect()
     }
+
+    /// Decode a lowercase or uppercase hex string; `None` if malformed.
+    pub fn decode(s: &str) -> Option<Vec<u8>> {
+        if !s.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -117,4 +155,16 @@ mod tests {
         let h2 = compute_state_hash(&state);
         assert_eq!(h1, h2);
     }
+    #[test]
+    fn test_serialize_stats() {
+        let value = serde_json::json!({"a": {"b": [1, {"c": 2}]}, "d": "x"});
+        let (bytes, stats) = canonical_serialize_with_stats(&value);
+        assert_eq!(bytes, canonical_serialize(&value));
+        assert_eq!(stats.bytes, bytes.len());
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.keys, 4);
+
+        let (_, scalar) = canonical_serialize_with_stats(&serde_json::json!(1));
+        assert_eq!((scalar.depth, scalar.keys), (0, 0));
+    }
 }
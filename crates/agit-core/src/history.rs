@@ -0,0 +1,237 @@
+//! Incremental, filtered walks over commit history.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use chrono::{DateTime, Utc};
+
+use crate::objects::Commit;
+use crate::types::{ActionType, Hash};
+
+/// Which commits a history walk yields. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct CommitFilter {
+    /// Only commits produced by this action.
+    pub action_type: Option<ActionType>,
+    /// Only commits by this author.
+    pub author: Option<String>,
+    /// Only commits made at or after this time.
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl CommitFilter {
+    /// Whether `commit` passes every set field.
+    pub fn matches(&self, commit: &Commit) -> bool {
+        self.action_type
+            .as_ref()
+            .is_none_or(|a| *a == commit.action_type)
+            && self.author.as_ref().is_none_or(|a| *a == commit.author)
+            && self.since.is_none_or(|t| commit.timestamp >= t)
+    }
+}
+
+/// Position in a newest-first walk of the commits reachable from a start
+/// commit, advanced with `Repository::next_commits`.
+///
+/// Only the commits on the frontier of the walk are held, so a long
+/// history is never loaded at once. With `since` set the walk ends at the
+/// first commit older than it, assuming parents are never newer than
+/// their children.
+#[derive(Debug)]
+pub struct CommitCursor {
+    filter: CommitFilter,
+    frontier: BinaryHeap<Pending>,
+    seen: HashSet<Hash>,
+}
+
+impl CommitCursor {
+    pub(crate) fn new(filter: CommitFilter) -> Self {
+        Self {
+            filter,
+            frontier: BinaryHeap::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Whether the walk has nothing left to visit.
+    pub fn is_done(&self) -> bool {
+        self.frontier.is_empty()
+    }
+
+    pub(crate) fn filter(&self) -> &CommitFilter {
+        &self.filter
+    }
+
+    /// Whether `hash` still needs loading, marking it as seen.
+    pub(crate) fn visit(&mut self, hash: &Hash) -> bool {
+        self.seen.insert(hash.clone())
+    }
+
+    pub(crate) fn push(&mut self, hash: Hash, commit: Commit) {
+        self.frontier.push(Pending { hash, commit });
+    }
+
+    /// The newest commit on the frontier, with its hash.
+    pub(crate) fn pop(&mut self) -> Option<(Hash, Commit)> {
+        self.frontier.pop().map(|p| (p.hash, p.commit))
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.frontier.clear();
+    }
+}
+
+/// A loaded commit waiting to be yielded, ordered by timestamp.
+#[derive(Debug)]
+struct Pending {
+    hash: Hash,
+    commit: Commit,
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.commit
+            .timestamp
+            .cmp(&other.commit.timestamp)
+            .then_with(|| self.hash.0.cmp(&other.hash.0))
+    }
+}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+    }
+}
+
+impl Eq for Pending {}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::repo::Repository;
+    use crate::state::AgentState;
+    use crate::storage::MemoryStorage;
+
+    async fn repo_with_history(n: usize) -> Repository {
+        let mut repo = Repository::init(Box::new(MemoryStorage::new()))
+            .await
+            .unwrap();
+        for i in 0..n {
+            let action = if i % 2 == 0 {
+                ActionType::ToolCall
+            } else {
+                ActionType::LlmResponse
+            };
+            let state = AgentState::new(json!({"step": i}), json!({}));
+            repo.commit(&state, &format!("step {i}"), action)
+                .await
+                .unwrap();
+        }
+        repo
+    }
+
+    async fn drain(repo: &Repository, cursor: &mut CommitCursor, batch: usize) -> Vec<Commit> {
+        let mut all = Vec::new();
+        while !cursor.is_done() {
+            for (hash, commit) in repo.next_commits(cursor, batch).await.unwrap() {
+                assert_eq!(hash, commit.hash());
+                all.push(commit);
+            }
+        }
+        all
+    }
+
+    #[tokio::test]
+    async fn test_batches_match_log() {
+        let repo = repo_with_history(7).await;
+        let expected: Vec<String> = repo
+            .log(None, 100)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, c)| c.message)
+            .collect();
+
+        for batch in [1, 2, 3, 7, 50] {
+            let mut cursor = repo
+                .commit_cursor(None, CommitFilter::default())
+                .await
+                .unwrap();
+            let walked: Vec<String> = drain(&repo, &mut cursor, batch)
+                .await
+                .into_iter()
+                .map(|c| c.message)
+                .collect();
+            assert_eq!(walked, expected, "batch size {batch}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_action_type() {
+        let repo = repo_with_history(5).await;
+        let filter = CommitFilter {
+            action_type: Some(ActionType::LlmResponse),
+            ..Default::default()
+        };
+        let mut cursor = repo.commit_cursor(None, filter).await.unwrap();
+        let messages: Vec<String> = drain(&repo, &mut cursor, 2)
+            .await
+            .into_iter()
+            .map(|c| c.message)
+            .collect();
+        assert_eq!(messages, vec!["step 3", "step 1"]);
+    }
+
+    #[tokio::test]
+    async fn test_since_stops_the_walk() {
+        let repo = repo_with_history(3).await;
+        let filter = CommitFilter {
+            since: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        let mut cursor = repo.commit_cursor(None, filter).await.unwrap();
+        assert!(repo.next_commits(&mut cursor, 10).await.unwrap().is_empty());
+        assert!(cursor.is_done());
+    }
+
+    #[tokio::test]
+    async fn test_merge_parents_yielded_once() {
+        let mut repo = repo_with_history(2).await;
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let state = AgentState::new(json!({"step": 0, "feature": true}), json!({}));
+        repo.commit(&state, "on feature", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+        let state = AgentState::new(json!({"step": 1, "main": true}), json!({}));
+        repo.commit(&state, "on main", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.merge_with_options(
+            "feature",
+            crate::types::MergeStrategy::Ours,
+            crate::repo::MergeOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut cursor = repo
+            .commit_cursor(None, CommitFilter::default())
+            .await
+            .unwrap();
+        let walked = drain(&repo, &mut cursor, 1).await;
+        let hashes: HashSet<Hash> = walked.iter().map(|c| c.hash()).collect();
+        assert_eq!(walked.len(), 5);
+        assert_eq!(hashes.len(), 5);
+        assert!(walked.windows(2).all(|w| w[0].timestamp >= w[1].timestamp));
+    }
+}
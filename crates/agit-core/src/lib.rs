@@ -1,26 +1,103 @@
+#[cfg(feature = "storage")]
+pub mod audit;
+#[cfg(feature = "storage")]
+pub mod cache;
+#[cfg(feature = "storage")]
+pub mod chunking;
+#[cfg(feature = "storage")]
+pub mod config;
 pub mod encryption;
 pub mod error;
+#[cfg(feature = "storage")]
+pub mod fsck;
+#[cfg(feature = "storage")]
 pub mod gc;
+#[cfg(feature = "git-export")]
+pub mod git_export;
 pub mod hash;
+#[cfg(feature = "storage")]
+pub mod history;
+pub mod merge_driver;
+#[cfg(feature = "storage")]
 pub mod migration;
 pub mod objects;
+pub mod protection;
 pub mod refs;
+#[cfg(feature = "storage")]
+pub mod remote;
+#[cfg(feature = "storage")]
 pub mod repo;
+#[cfg(feature = "storage")]
 pub mod retention;
+#[cfg(all(feature = "encryption", feature = "storage"))]
+pub mod rotation;
+#[cfg(feature = "storage")]
+pub mod scan;
+pub mod signing;
 pub mod state;
+#[cfg(feature = "storage")]
 pub mod storage;
 pub mod types;
 
 #[cfg(feature = "encryption")]
 pub use encryption::StateEncryptor;
+#[cfg(all(feature = "encryption", feature = "storage"))]
+pub use rotation::{RotationPhase, RotationProgress, RotationResult};
 
 // Re-export primary types for convenience
+#[cfg(feature = "storage")]
+pub use audit::{AuditBreak, AuditVerification, CommitProvenance};
+#[cfg(feature = "storage")]
+pub use cache::CacheStats;
+#[cfg(feature = "storage")]
+pub use chunking::ChunkingOptions;
+pub use encryption::EncryptionMode;
 pub use error::{AgitError, Result};
+#[cfg(feature = "storage")]
+pub use fsck::{FsckOptions, FsckReport, HashMismatch};
+#[cfg(feature = "storage")]
+pub use gc::{
+    AutoGcPolicy, GcBatchFailure, GcOptions, GcPhase, GcProgress, GcResult, SquashOptions,
+    SquashResult, GC_REPORT_LIMIT,
+};
+#[cfg(feature = "git-export")]
+pub use git_export::{GitExportOptions, GitExportReport};
+#[cfg(feature = "storage")]
+pub use history::{CommitCursor, CommitFilter};
+pub use merge_driver::{BuiltinDriver, MergeDriverRegistry};
 pub use objects::{Blob, Commit};
+pub use protection::{BranchProtection, ProtectionRule};
 pub use refs::{Head, RefStore};
-pub use repo::Repository;
-pub use state::{AgentState, DiffEntry, MergeConflict, MerkleNode, StateDiff, merkle_diff};
-pub use storage::sqlite::SqliteStorage;
-pub use storage::{LogEntry, LogFilter, StorageBackend};
-pub use gc::{GcResult, SquashResult};
-pub use types::{ActionType, ChangeType, Hash, MergeStrategy, ObjectType};
+#[cfg(feature = "storage")]
+pub use remote::FetchReport;
+#[cfg(feature = "storage")]
+pub use repo::{
+    CostSummary, MergeOptions, RepoOptions, Repository, ENCRYPTION_KEY_ID_METADATA_KEY,
+    MERKLE_ROOT_METADATA_KEY,
+};
+#[cfg(feature = "storage")]
+pub use retention::{BranchRetention, LogRetentionResult, RetentionPolicy, RetentionResult};
+pub use signing::VerificationReport;
+pub use state::{
+    merkle_diff, merkle_diff_with_trees, AgentState, AgentStateBuilder, ArrayMergeStrategy,
+    DiffEntry, DiffOptions, DiffStats, MergeConfig, MergeConflict, MergeReport, MergeResolution,
+    MerkleNode, MerkleProof, StateDiff,
+};
+#[cfg(feature = "storage")]
+pub use storage::fs::FsStorage;
+#[cfg(feature = "storage")]
+pub use storage::memory::MemoryStorage;
+#[cfg(feature = "storage")]
+pub use storage::retry::{RetryPolicy, RetryingStorage};
+#[cfg(feature = "storage")]
+pub use storage::sqlite::{SqliteOptions, SqliteStorage};
+#[cfg(feature = "storage")]
+pub use storage::tiered::TieredStorage;
+#[cfg(feature = "storage")]
+pub use storage::{
+    BackupReport, BulkWrite, CompactReport, LogCursor, LogDeleteFilter, LogEntry, LogFilter,
+    ObjectStat, RefUpdate, StorageBackend, StorageStats, TypeStats,
+};
+#[cfg(feature = "storage")]
+pub use tokio_util::sync::CancellationToken;
+pub use types::{ActionType, ChangeType, Hash, LogLevel, MergeStrategy, ObjectType};
@@ -0,0 +1,210 @@
+//! Per-path merge drivers.
+//!
+//! A driver resolves a three-way merge at a path where both sides changed
+//! differently. Drivers are registered against dotted path globs such as
+//! `memory.scratchpad.*` and are consulted before a conflict is recorded.
+
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::DateTime;
+use serde_json::Value;
+
+use crate::protection::glob_match;
+
+/// A merge driver: `(base, ours, theirs) -> resolved`, or `None` to decline.
+pub type MergeDriverFn = dyn Fn(&Value, &Value, &Value) -> Option<Value> + Send + Sync;
+
+/// Built-in merge drivers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuiltinDriver {
+    /// Always take our value.
+    TakeOurs,
+    /// Always take their value.
+    TakeTheirs,
+    /// Take the larger of two numbers.
+    NumericMax,
+    /// Apply both sides' deltas from base: `ours + theirs - base`.
+    NumericSum,
+    /// For objects carrying a timestamp in `field`, take the side whose
+    /// timestamp is later. Timestamps may be RFC 3339 strings or numbers.
+    LatestTimestamp { field: String },
+}
+
+impl BuiltinDriver {
+    /// Resolve a conflict with this driver.
+    pub fn resolve(&self, base: &Value, ours: &Value, theirs: &Value) -> Option<Value> {
+        match self {
+            BuiltinDriver::TakeOurs => Some(ours.clone()),
+            BuiltinDriver::TakeTheirs => Some(theirs.clone()),
+            BuiltinDriver::NumericMax => {
+                let (o, t) = (ours.as_f64()?, theirs.as_f64()?);
+                Some(if t > o { theirs.clone() } else { ours.clone() })
+            }
+            BuiltinDriver::NumericSum => {
+                // A missing base counts as zero
+                if let (Some(o), Some(t)) = (ours.as_i64(), theirs.as_i64()) {
+                    if base.is_null() || base.is_i64() {
+                        let b = base.as_i64().unwrap_or(0);
+                        if let Some(sum) = o.checked_add(t).and_then(|s| s.checked_sub(b)) {
+                            return Some(Value::from(sum));
+                        }
+                    }
+                }
+                let b = if base.is_null() { 0.0 } else { base.as_f64()? };
+                Some(Value::from(ours.as_f64()? + theirs.as_f64()? - b))
+            }
+            BuiltinDriver::LatestTimestamp { field } => {
+                let ours_ts = ours.get(field)?;
+                let theirs_ts = theirs.get(field)?;
+                let theirs_later = match (ours_ts, theirs_ts) {
+                    (Value::String(o), Value::String(t)) => {
+                        DateTime::parse_from_rfc3339(t).ok()?
+                            > DateTime::parse_from_rfc3339(o).ok()?
+                    }
+                    _ => theirs_ts.as_f64()? > ours_ts.as_f64()?,
+                };
+                Some(if theirs_later {
+                    theirs.clone()
+                } else {
+                    ours.clone()
+                })
+            }
+        }
+    }
+}
+
+/// Merge drivers keyed by dotted path glob; the first registered match that
+/// returns a value wins.
+#[derive(Clone, Default)]
+pub struct MergeDriverRegistry {
+    drivers: Vec<(String, Arc<MergeDriverFn>)>,
+}
+
+impl MergeDriverRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a custom driver for paths matching `pattern`.
+    pub fn register<F>(&mut self, pattern: &str, driver: F) -> &mut Self
+    where
+        F: Fn(&Value, &Value, &Value) -> Option<Value> + Send + Sync + 'static,
+    {
+        self.drivers.push((pattern.to_string(), Arc::new(driver)));
+        self
+    }
+
+    /// Register a built-in driver for paths matching `pattern`.
+    pub fn register_builtin(&mut self, pattern: &str, driver: BuiltinDriver) -> &mut Self {
+        self.register(pattern, move |b, o, t| driver.resolve(b, o, t))
+    }
+
+    /// True if no drivers are registered.
+    pub fn is_empty(&self) -> bool {
+        self.drivers.is_empty()
+    }
+
+    /// Try each driver whose pattern matches `path` (joined with `.`).
+    pub fn resolve(
+        &self,
+        path: &[String],
+        base: &Value,
+        ours: &Value,
+        theirs: &Value,
+    ) -> Option<Value> {
+        if self.drivers.is_empty() {
+            return None;
+        }
+        let dotted = path.join(".");
+        self.drivers
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, &dotted))
+            .find_map(|(_, driver)| driver(base, ours, theirs))
+    }
+}
+
+impl fmt::Debug for MergeDriverRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MergeDriverRegistry")
+            .field(
+                "patterns",
+                &self
+                    .drivers
+                    .iter()
+                    .map(|(p, _)| p.as_str())
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_builtin_numeric_drivers() {
+        let max = BuiltinDriver::NumericMax;
+        assert_eq!(max.resolve(&json!(1), &json!(5), &json!(3)), Some(json!(5)));
+        assert_eq!(max.resolve(&json!(1), &json!("x"), &json!(3)), None);
+
+        let sum = BuiltinDriver::NumericSum;
+        assert_eq!(
+            sum.resolve(&json!(10), &json!(12), &json!(15)),
+            Some(json!(17))
+        );
+        assert_eq!(
+            sum.resolve(&Value::Null, &json!(2), &json!(3)),
+            Some(json!(5))
+        );
+        assert_eq!(
+            sum.resolve(&json!(1.0), &json!(1.5), &json!(2.0)),
+            Some(json!(2.5))
+        );
+    }
+
+    #[test]
+    fn test_builtin_latest_timestamp() {
+        let driver = BuiltinDriver::LatestTimestamp {
+            field: "updated_at".to_string(),
+        };
+        let ours = json!({"v": 1, "updated_at": "2026-01-02T00:00:00Z"});
+        let theirs = json!({"v": 2, "updated_at": "2026-01-01T00:00:00Z"});
+        assert_eq!(
+            driver.resolve(&json!({}), &ours, &theirs),
+            Some(ours.clone())
+        );
+        assert_eq!(driver.resolve(&json!({}), &theirs, &ours), Some(ours));
+        assert_eq!(driver.resolve(&json!({}), &json!(1), &json!(2)), None);
+    }
+
+    #[test]
+    fn test_registry_first_match_wins() {
+        let mut registry = MergeDriverRegistry::new();
+        registry
+            .register_builtin("memory.counters.*", BuiltinDriver::NumericMax)
+            .register_builtin("memory.*", BuiltinDriver::TakeTheirs);
+        let path = |p: &str| p.split('.').map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            registry.resolve(&path("memory.counters.a"), &json!(0), &json!(9), &json!(4)),
+            Some(json!(9))
+        );
+        // NumericMax declines non-numbers; the next matching driver applies
+        assert_eq!(
+            registry.resolve(
+                &path("memory.counters.b"),
+                &json!(0),
+                &json!("x"),
+                &json!("y")
+            ),
+            Some(json!("y"))
+        );
+        assert_eq!(
+            registry.resolve(&path("world_state.x"), &json!(0), &json!(1), &json!(2)),
+            None
+        );
+    }
+}
@@ -2,9 +2,29 @@
 //!
 //! Provides tools to migrate data between storage backends (e.g., SQLite → PostgreSQL).
 
+use tokio_util::sync::CancellationToken;
+
 use crate::error::Result;
+use crate::objects::infer_object_type;
 use crate::storage::StorageBackend;
-use crate::types::ObjectType;
+
+/// Objects copied per batch when `MigrationOptions` doesn't say otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 1_000;
+
+/// Options for `migrate_with_options`.
+#[derive(Debug, Clone)]
+pub struct MigrationOptions {
+    /// Objects read from the source and written to the target per batch.
+    pub batch_size: usize,
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        MigrationOptions {
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
 
 /// Migrate all data from one storage backend to another.
 ///
@@ -13,7 +33,50 @@ use crate::types::ObjectType;
 pub async fn migrate<F>(
     source: &dyn StorageBackend,
     target: &dyn StorageBackend,
+    on_progress: Option<F>,
+) -> Result<MigrationResult>
+where
+    F: FnMut(MigrationProgress),
+{
+    migrate_with_options(source, target, &MigrationOptions::default(), on_progress).await
+}
+
+/// Migrate all data from one storage backend to another, copying objects in
+/// batches of `options.batch_size`.
+///
+/// Targets with `BulkWrite` support receive each batch in one bulk write;
+/// others get one `put_object` per missing object. Object progress is
+/// reported once per batch, ref progress once per ref.
+pub async fn migrate_with_options<F>(
+    source: &dyn StorageBackend,
+    target: &dyn StorageBackend,
+    options: &MigrationOptions,
+    on_progress: Option<F>,
+) -> Result<MigrationResult>
+where
+    F: FnMut(MigrationProgress),
+{
+    migrate_cancellable(
+        source,
+        target,
+        options,
+        on_progress,
+        &CancellationToken::new(),
+    )
+    .await
+}
+
+/// [`migrate_with_options`], stopping once `cancel` fires.
+///
+/// Cancellation is checked before each object batch and each ref, so a
+/// cancelled migration returns a partial result with `cancelled` set.
+/// Running the migration again picks up where it stopped.
+pub async fn migrate_cancellable<F>(
+    source: &dyn StorageBackend,
+    target: &dyn StorageBackend,
+    options: &MigrationOptions,
     mut on_progress: Option<F>,
+    cancel: &CancellationToken,
 ) -> Result<MigrationResult>
 where
     F: FnMut(MigrationProgress),
@@ -25,36 +88,60 @@ where
     let total_objects = objects.len();
     let mut migrated_objects = 0;
     let mut skipped_objects = 0;
+    let mut current = 0;
+    let mut cancelled = false;
 
-    for (i, hash) in objects.iter().enumerate() {
-        if target.has_object(hash).await? {
-            skipped_objects += 1;
-        } else if let Some(data) = source.get_object(hash).await? {
-            // Try to determine type by attempting to parse as commit
-            let obj_type = if serde_json::from_slice::<crate::objects::Commit>(&data).is_ok() {
-                ObjectType::Commit
-            } else {
-                ObjectType::Blob
-            };
-            target.put_object(hash, obj_type, &data).await?;
-            migrated_objects += 1;
+    for batch in objects.chunks(options.batch_size.max(1)) {
+        if cancel.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        match target.as_bulk_write() {
+            Some(bulk) => {
+                let mut loaded = Vec::with_capacity(batch.len());
+                for hash in batch {
+                    if let Some(data) = source.get_object(hash).await? {
+                        let obj_type = infer_object_type(hash, &data);
+                        loaded.push((hash.clone(), obj_type, data));
+                    }
+                }
+                let added = bulk.put_objects_bulk(&loaded).await?;
+                migrated_objects += added;
+                skipped_objects += loaded.len() - added;
+            }
+            None => {
+                for hash in batch {
+                    if target.has_object(hash).await? {
+                        skipped_objects += 1;
+                    } else if let Some(data) = source.get_object(hash).await? {
+                        let obj_type = infer_object_type(hash, &data);
+                        target.put_object(hash, obj_type, &data).await?;
+                        migrated_objects += 1;
+                    }
+                }
+            }
         }
 
+        current += batch.len();
         if let Some(ref mut cb) = on_progress {
             cb(MigrationProgress {
                 phase: "objects",
-                current: i + 1,
+                current,
                 total: total_objects,
             });
         }
     }
 
-    // Migrate refs
+    // Migrate refs, only once every object they may point at is copied
     let refs = source.list_refs().await?;
     let total_refs = refs.len();
     let mut migrated_refs = 0;
 
     for (i, (name, hash)) in refs.iter().enumerate() {
+        if cancelled || cancel.is_cancelled() {
+            cancelled = true;
+            break;
+        }
         target.set_ref(name, hash).await?;
         migrated_refs += 1;
 
@@ -73,6 +160,7 @@ where
         skipped_objects,
         total_refs,
         migrated_refs,
+        cancelled,
     })
 }
 
@@ -91,4 +179,108 @@ pub struct MigrationResult {
     pub skipped_objects: usize,
     pub total_refs: usize,
     pub migrated_refs: usize,
+    /// Whether the migration stopped early because it was cancelled.
+    pub cancelled: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, SqliteStorage};
+    use crate::types::ObjectType;
+
+    #[tokio::test]
+    async fn test_migrate_reports_progress_per_batch() {
+        let source = MemoryStorage::new();
+        for i in 0..7 {
+            source
+                .put_object(
+                    &format!("{i:064x}"),
+                    ObjectType::Blob,
+                    format!("blob {i}").as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+        source
+            .set_ref("main", &format!("{:064x}", 0))
+            .await
+            .unwrap();
+        let target = SqliteStorage::new(":memory:").await.unwrap();
+        let options = MigrationOptions { batch_size: 3 };
+
+        let mut seen = Vec::new();
+        let result = migrate_with_options(
+            &source,
+            &target,
+            &options,
+            Some(|p: MigrationProgress| {
+                if p.phase == "objects" {
+                    seen.push((p.current, p.total));
+                }
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(seen, vec![(3, 7), (6, 7), (7, 7)]);
+        assert_eq!(result.migrated_objects, 7);
+        assert_eq!(result.migrated_refs, 1);
+        assert_eq!(target.list_objects().await.unwrap().len(), 7);
+
+        let again = migrate_with_options(&source, &target, &options, None::<fn(MigrationProgress)>)
+            .await
+            .unwrap();
+        assert_eq!(again.migrated_objects, 0);
+        assert_eq!(again.skipped_objects, 7);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_cancelled_between_batches() {
+        let source = MemoryStorage::new();
+        for i in 0..7 {
+            source
+                .put_object(
+                    &format!("{i:064x}"),
+                    ObjectType::Blob,
+                    format!("blob {i}").as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+        source
+            .set_ref("main", &format!("{:064x}", 0))
+            .await
+            .unwrap();
+        let target = MemoryStorage::new();
+        let options = MigrationOptions { batch_size: 3 };
+
+        let cancel = CancellationToken::new();
+        let result = migrate_cancellable(
+            &source,
+            &target,
+            &options,
+            Some(|p: MigrationProgress| {
+                if p.current >= 3 {
+                    cancel.cancel();
+                }
+            }),
+            &cancel,
+        )
+        .await
+        .unwrap();
+        assert!(result.cancelled);
+        assert_eq!(result.migrated_objects, 3);
+        assert_eq!(result.migrated_refs, 0);
+        assert_eq!(target.list_objects().await.unwrap().len(), 3);
+        assert!(target.list_refs().await.unwrap().is_empty());
+
+        // Resuming copies the rest
+        let result =
+            migrate_with_options(&source, &target, &options, None::<fn(MigrationProgress)>)
+                .await
+                .unwrap();
+        assert!(!result.cancelled);
+        assert_eq!(result.migrated_objects, 4);
+        assert_eq!(result.migrated_refs, 1);
+    }
 }
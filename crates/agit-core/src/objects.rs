@@ -25,6 +25,26 @@ impl Blob {
     }
 }
 
+/// Suffix appended to a blob hash to form the key of its cached Merkle tree.
+pub const TREE_KEY_SUFFIX: &str = ".tree";
+
+/// Storage key of the Merkle tree cached for the blob `blob_hash`.
+pub fn tree_key(blob_hash: &str) -> String {
+    format!("{}{}", blob_hash, TREE_KEY_SUFFIX)
+}
+
+/// Infer the type of a stored object from its key and contents, for
+/// backends that do not record it.
+pub fn infer_object_type(key: &str, data: &[u8]) -> ObjectType {
+    if key.ends_with(TREE_KEY_SUFFIX) {
+        ObjectType::Tree
+    } else if serde_json::from_slice::<Commit>(data).is_ok() {
+        ObjectType::Commit
+    } else {
+        ObjectType::Blob
+    }
+}
+
 /// A commit pointing to a state blob, with parent links forming a DAG.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commit {
@@ -109,6 +129,27 @@ mod tests {
         // Verify it's valid JSON
         let parsed: Value = serde_json::from_slice(&serialized).unwrap();
         // Keys should be sorted
-        assert_eq!(parsed, json!({"memory": {"facts": [1, 2, 3]}, "world": "state"}));
+        assert_eq!(
+            parsed,
+            json!({"memory": {"facts": [1, 2, 3]}, "world": "state"})
+        );
+    }
+
+    #[test]
+    fn test_infer_object_type() {
+        let commit = Commit {
+            tree_hash: Hash::from("abc123"),
+            parent_hashes: vec![],
+            message: "test".to_string(),
+            author: "agent".to_string(),
+            timestamp: Utc::now(),
+            action_type: ActionType::ToolCall,
+            metadata: serde_json::Map::new(),
+        };
+        let data = serde_json::to_vec(&commit).unwrap();
+        assert_eq!(infer_object_type("c", &data), ObjectType::Commit);
+        let blob = Blob::new(json!({"memory": {}})).serialize();
+        assert_eq!(infer_object_type("b", &blob), ObjectType::Blob);
+        assert_eq!(infer_object_type(&tree_key("b"), b"{}"), ObjectType::Tree);
     }
 }
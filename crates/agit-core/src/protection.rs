@@ -0,0 +1,186 @@
+//! Branch protection rules enforced by the `Repository`.
+//!
+//! Rules are matched against branch names with simple `*` globs and persisted
+//! as a blob referenced from the `config/protection` ref.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AgitError, Result};
+use crate::types::ActionType;
+
+/// Ref under which the serialized `BranchProtection` blob is stored.
+pub const PROTECTION_REF: &str = "config/protection";
+
+/// A protection rule applied to every branch whose name matches `pattern`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProtectionRule {
+    /// Branch name pattern; `*` matches any sequence of characters.
+    pub pattern: String,
+    /// Allow deleting matching branches.
+    #[serde(default)]
+    pub allow_delete: bool,
+    /// Allow moving matching branches to arbitrary commits (reset, squash).
+    #[serde(default)]
+    pub allow_reset: bool,
+    /// If non-empty, commits to matching branches must use one of these action types.
+    #[serde(default)]
+    pub required_action_types: Vec<ActionType>,
+}
+
+impl ProtectionRule {
+    /// A rule that forbids deleting or resetting matching branches.
+    pub fn new(pattern: &str) -> Self {
+        ProtectionRule {
+            pattern: pattern.to_string(),
+            allow_delete: false,
+            allow_reset: false,
+            required_action_types: Vec::new(),
+        }
+    }
+}
+
+/// Set of branch protection rules for a repository.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BranchProtection {
+    pub rules: Vec<ProtectionRule>,
+}
+
+impl BranchProtection {
+    /// Rules whose pattern matches `branch`.
+    pub fn rules_for<'a>(&'a self, branch: &'a str) -> impl Iterator<Item = &'a ProtectionRule> {
+        self.rules
+            .iter()
+            .filter(move |r| glob_match(&r.pattern, branch))
+    }
+
+    /// Fail if `branch` may not be deleted.
+    pub fn check_delete(&self, branch: &str) -> Result<()> {
+        match self.rules_for(branch).find(|r| !r.allow_delete) {
+            Some(rule) => Err(violation(branch, rule, "deletion is not allowed")),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail if `branch` may not be moved to an arbitrary commit.
+    pub fn check_reset(&self, branch: &str) -> Result<()> {
+        match self.rules_for(branch).find(|r| !r.allow_reset) {
+            Some(rule) => Err(violation(branch, rule, "history rewrites are not allowed")),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail if a commit with `action_type` may not be made on `branch`.
+    pub fn check_commit(&self, branch: &str, action_type: &ActionType) -> Result<()> {
+        let violated = self.rules_for(branch).find(|r| {
+            !r.required_action_types.is_empty() && !r.required_action_types.contains(action_type)
+        });
+        match violated {
+            Some(rule) => Err(violation(
+                branch,
+                rule,
+                &format!("action type '{}' is not permitted", action_type),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+fn violation(branch: &str, rule: &ProtectionRule, reason: &str) -> AgitError {
+    AgitError::ProtectedBranch {
+        branch: branch.to_string(),
+        rule: format!("{} (pattern '{}')", reason, rule.pattern),
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any (possibly empty)
+/// sequence of characters and everything else matches literally.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut mark = 0;
+
+    while ti < t.len() {
+        if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            mark = ti;
+            pi += 1;
+        } else if pi < p.len() && p[pi] == t[ti] {
+            pi += 1;
+            ti += 1;
+        } else if let Some(s) = star {
+            pi = s + 1;
+            mark += 1;
+            ti = mark;
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("main", "main"));
+        assert!(!glob_match("main", "mainline"));
+        assert!(glob_match("release/*", "release/1.0"));
+        assert!(!glob_match("release/*", "releases/1.0"));
+        assert!(glob_match("*", "anything/at/all"));
+        assert!(glob_match("feat*-x", "feature-x"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "x"));
+    }
+
+    #[test]
+    fn test_check_delete_and_reset() {
+        let protection = BranchProtection {
+            rules: vec![ProtectionRule::new("main")],
+        };
+        assert!(protection.check_delete("main").is_err());
+        assert!(protection.check_reset("main").is_err());
+        assert!(protection.check_delete("feature").is_ok());
+    }
+
+    #[test]
+    fn test_check_commit_required_action_types() {
+        let protection = BranchProtection {
+            rules: vec![ProtectionRule {
+                required_action_types: vec![ActionType::Checkpoint],
+                ..ProtectionRule::new("release/*")
+            }],
+        };
+        assert!(protection
+            .check_commit("release/1.0", &ActionType::Checkpoint)
+            .is_ok());
+        let err = protection
+            .check_commit("release/1.0", &ActionType::ToolCall)
+            .unwrap_err();
+        assert!(matches!(err, AgitError::ProtectedBranch { .. }));
+        assert!(protection
+            .check_commit("main", &ActionType::ToolCall)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_roundtrip_serialization() {
+        let protection = BranchProtection {
+            rules: vec![ProtectionRule {
+                required_action_types: vec![ActionType::Custom("deploy".to_string())],
+                ..ProtectionRule::new("release/*")
+            }],
+        };
+        let json = serde_json::to_value(&protection).unwrap();
+        let back: BranchProtection = serde_json::from_value(json).unwrap();
+        assert_eq!(back, protection);
+    }
+}
@@ -5,6 +5,12 @@ use serde::{Deserialize, Serialize};
 use crate::error::{AgitError, Result};
 use crate::types::Hash;
 
+/// Prefix for refs holding repository configuration rather than branches.
+pub const CONFIG_REF_PREFIX: &str = "config/";
+
+/// Prefix for remote-tracking refs, named `remotes/<remote>/<branch>`.
+pub const REMOTE_REF_PREFIX: &str = "remotes/";
+
 /// HEAD can point to a branch (attached) or directly to a commit (detached).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Head {
@@ -12,11 +18,12 @@ pub enum Head {
     Detached(Hash),
 }
 
-/// In-memory reference store for HEAD and branches.
+/// In-memory reference store for HEAD, branches and remote-tracking refs.
 #[derive(Debug, Clone)]
 pub struct RefStore {
     head: Head,
     branches: HashMap<String, Hash>,
+    tracking: HashMap<String, Hash>,
 }
 
 impl RefStore {
@@ -24,6 +31,7 @@ impl RefStore {
         RefStore {
             head: Head::Attached("main".to_string()),
             branches: HashMap::new(),
+            tracking: HashMap::new(),
         }
     }
 
@@ -40,6 +48,11 @@ impl RefStore {
         }
     }
 
+    /// Replace HEAD with an existing `Head` value.
+    pub fn set_head_from(&mut self, head: Head) {
+        self.head = head;
+    }
+
     /// Get the name of the current branch, if HEAD is attached.
     pub fn current_branch(&self) -> Option<&str> {
         match &self.head {
@@ -48,8 +61,14 @@ impl RefStore {
         }
     }
 
+    /// Whether HEAD is attached to the branch `name`.
+    pub fn is_checked_out(&self, name: &str) -> bool {
+        self.current_branch() == Some(name)
+    }
+
     /// Create a new branch pointing to the given hash.
     pub fn create_branch(&mut self, name: &str, at: Hash) -> Result<()> {
+        check_branch_name(name)?;
         if self.branches.contains_key(name) {
             return Err(AgitError::BranchExists {
                 name: name.to_string(),
@@ -59,18 +78,49 @@ impl RefStore {
         Ok(())
     }
 
-    /// Delete a branch by name.
+    /// Delete a branch by name. The branch HEAD is attached to cannot be
+    /// deleted.
     pub fn delete_branch(&mut self, name: &str) -> Result<()> {
         if name == "main" {
             return Err(AgitError::InvalidArgument(
                 "cannot delete main branch".to_string(),
             ));
         }
-        if self.branches.remove(name).is_none() {
+        if !self.branches.contains_key(name) {
             return Err(AgitError::BranchNotFound {
                 name: name.to_string(),
             });
         }
+        if self.is_checked_out(name) {
+            return Err(AgitError::BranchCheckedOut {
+                name: name.to_string(),
+            });
+        }
+        self.branches.remove(name);
+        Ok(())
+    }
+
+    /// Rename a branch, keeping its tip and re-attaching HEAD if it pointed
+    /// at the old name. Renaming `main` requires `force`.
+    pub fn rename_branch(&mut self, old: &str, new: &str, force: bool) -> Result<()> {
+        if old == "main" && !force {
+            return Err(AgitError::InvalidArgument(
+                "cannot rename main branch without force".to_string(),
+            ));
+        }
+        check_branch_name(new)?;
+        if self.branches.contains_key(new) {
+            return Err(AgitError::BranchExists {
+                name: new.to_string(),
+            });
+        }
+        let hash = self.branches.remove(old).ok_or(AgitError::BranchNotFound {
+            name: old.to_string(),
+        })?;
+        self.branches.insert(new.to_string(), hash);
+        if matches!(&self.head, Head::Attached(name) if name == old) {
+            self.head = Head::Attached(new.to_string());
+        }
         Ok(())
     }
 
@@ -89,7 +139,12 @@ impl RefStore {
         &self.branches
     }
 
-    /// Resolve a ref name (branch or HEAD) to a commit hash.
+    /// Remote-tracking refs, keyed by their full `remotes/<remote>/<branch>` name.
+    pub fn list_tracking_refs(&self) -> &HashMap<String, Hash> {
+        &self.tracking
+    }
+
+    /// Resolve a ref name (branch, remote-tracking ref or HEAD) to a commit hash.
     pub fn resolve_ref(&self, name: &str) -> Result<Hash> {
         if name == "HEAD" {
             return match &self.head {
@@ -103,6 +158,7 @@ impl RefStore {
         }
         self.branches
             .get(name)
+            .or_else(|| self.tracking.get(name))
             .cloned()
             .ok_or(AgitError::BranchNotFound {
                 name: name.to_string(),
@@ -119,7 +175,9 @@ impl RefStore {
                 } else {
                     self.head = Head::Detached(Hash::from(hash));
                 }
-            } else {
+            } else if name.starts_with(REMOTE_REF_PREFIX) {
+                self.tracking.insert(name, Hash::from(hash));
+            } else if !name.starts_with(CONFIG_REF_PREFIX) {
                 self.branches.insert(name, Hash::from(hash));
             }
         }
@@ -143,6 +201,18 @@ impl RefStore {
     }
 }
 
+/// Reject names reserved for HEAD, configuration and remote-tracking refs.
+fn check_branch_name(name: &str) -> Result<()> {
+    if name == "HEAD" || name.starts_with(CONFIG_REF_PREFIX) || name.starts_with(REMOTE_REF_PREFIX)
+    {
+        return Err(AgitError::InvalidArgument(format!(
+            "'{}' is a reserved ref name",
+            name
+        )));
+    }
+    Ok(())
+}
+
 impl Default for RefStore {
     fn default() -> Self {
         Self::new()
@@ -184,6 +254,22 @@ mod tests {
         assert!(store.list_branches().is_empty());
     }
 
+    #[test]
+    fn test_cannot_delete_checked_out_branch() {
+        let mut store = RefStore::new();
+        store.create_branch("feature", Hash::from("abc")).unwrap();
+        store.set_head("feature", false);
+        assert!(store.is_checked_out("feature"));
+        assert!(matches!(
+            store.delete_branch("feature"),
+            Err(AgitError::BranchCheckedOut { .. })
+        ));
+
+        store.set_head("abc", true);
+        assert!(!store.is_checked_out("feature"));
+        store.delete_branch("feature").unwrap();
+    }
+
     #[test]
     fn test_cannot_delete_main() {
         let mut store = RefStore::new();
@@ -225,13 +311,53 @@ mod tests {
 
         let mut store2 = RefStore::new();
         store2.load_from_map(map);
-        assert_eq!(
-            store2.resolve_ref("main").unwrap().0,
-            "abc"
-        );
-        assert_eq!(
-            store2.resolve_ref("dev").unwrap().0,
-            "def"
-        );
+        assert_eq!(store2.resolve_ref("main").unwrap().0, "abc");
+        assert_eq!(store2.resolve_ref("dev").unwrap().0, "def");
+    }
+    #[test]
+    fn test_rename_branch_moves_head() {
+        let mut store = RefStore::new();
+        store.create_branch("feature", Hash::from("abc")).unwrap();
+        store.set_head("feature", false);
+        store.rename_branch("feature", "renamed", false).unwrap();
+        assert_eq!(store.current_branch(), Some("renamed"));
+        assert_eq!(store.resolve_ref("renamed").unwrap().0, "abc");
+        assert!(store.resolve_ref("feature").is_err());
+    }
+
+    #[test]
+    fn test_rename_branch_errors() {
+        let mut store = RefStore::new();
+        store.create_branch("main", Hash::from("abc")).unwrap();
+        store.create_branch("dev", Hash::from("def")).unwrap();
+        assert!(matches!(
+            store.rename_branch("dev", "main", false),
+            Err(AgitError::BranchExists { .. })
+        ));
+        assert!(matches!(
+            store.rename_branch("missing", "other", false),
+            Err(AgitError::BranchNotFound { .. })
+        ));
+        assert!(store.rename_branch("main", "trunk", false).is_err());
+        store.rename_branch("main", "trunk", true).unwrap();
+        assert_eq!(store.current_branch(), Some("trunk"));
+    }
+
+    #[test]
+    fn test_tracking_refs_are_not_branches() {
+        let mut store = RefStore::new();
+        let mut map = HashMap::new();
+        map.insert("main".to_string(), "abc".to_string());
+        map.insert("remotes/origin/main".to_string(), "def".to_string());
+        store.load_from_map(map);
+
+        assert_eq!(store.list_branches().len(), 1);
+        assert_eq!(store.list_tracking_refs().len(), 1);
+        assert_eq!(store.resolve_ref("remotes/origin/main").unwrap().0, "def");
+        assert!(!store.to_map().contains_key("remotes/origin/main"));
+        assert!(store
+            .create_branch("remotes/origin/dev", Hash::from("abc"))
+            .is_err());
+        assert!(store.rename_branch("main", "remotes/x", true).is_err());
     }
 }
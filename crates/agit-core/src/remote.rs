@@ -0,0 +1,228 @@
+//! Remotes and remote-tracking refs.
+//!
+//! A remote is a named storage URL, persisted as a blob referenced from the
+//! `config/remotes` ref. Fetching records the remote's branch tips as
+//! `remotes/<remote>/<branch>` refs and copies the commit objects they reach,
+//! but not the state blobs those commits point at, so history can be
+//! compared before anything larger is transferred.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use crate::error::{AgitError, Result};
+use crate::objects::{Blob, Commit};
+use crate::refs::{RefStore, REMOTE_REF_PREFIX};
+use crate::storage::{RefUpdate, SqliteStorage, StorageBackend};
+use crate::types::{Hash, ObjectType};
+
+/// Ref under which the serialized remote name → URL map is stored.
+pub const REMOTES_REF: &str = "config/remotes";
+
+/// Outcome of `Repository::fetch`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchReport {
+    /// Name of the remote fetched from.
+    pub remote: String,
+    /// Remote branches whose tracking ref was created or moved, with their new tip.
+    pub updated: BTreeMap<String, Hash>,
+    /// Remote branches that no longer exist and whose tracking ref was removed.
+    pub pruned: Vec<String>,
+    /// Commit objects copied into local storage.
+    pub commits_fetched: usize,
+}
+
+/// Full name of the ref tracking `branch` on `remote`.
+pub fn tracking_ref(remote: &str, branch: &str) -> String {
+    format!("{}{}/{}", REMOTE_REF_PREFIX, remote, branch)
+}
+
+/// Fail unless `name` can be used as a remote name.
+pub(crate) fn check_remote_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        return Err(AgitError::InvalidArgument(format!(
+            "invalid remote name '{}'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Load the configured remotes, if any.
+pub(crate) async fn load_remotes(storage: &dyn StorageBackend) -> Result<BTreeMap<String, String>> {
+    let Some(hash) = storage.get_ref(REMOTES_REF).await? else {
+        return Ok(BTreeMap::new());
+    };
+    let data = storage
+        .get_object(&hash)
+        .await?
+        .ok_or(AgitError::ObjectNotFound { hash })?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Persist `remotes` under `REMOTES_REF`.
+pub(crate) async fn save_remotes(
+    storage: &dyn StorageBackend,
+    remotes: &BTreeMap<String, String>,
+) -> Result<()> {
+    let blob = Blob::new(serde_json::to_value(remotes)?);
+    let hash = blob.hash();
+    storage
+        .put_object(hash.as_str(), ObjectType::Blob, &blob.serialize())
+        .await?;
+    storage.set_ref(REMOTES_REF, hash.as_str()).await
+}
+
+/// Open the storage a remote URL points at: `postgres://…` or
+/// `postgresql://…` (a `namespace` query parameter selects the tenant),
+/// `s3://bucket/prefix`, `sqlite://path`, or a plain SQLite path. A path
+/// that does not name a `.db` file refers to the `agit.db` inside it.
+pub async fn connect(url: &str) -> Result<Box<dyn StorageBackend>> {
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        return connect_postgres(url).await;
+    }
+    if let Some(rest) = url.strip_prefix("s3://") {
+        return connect_s3(rest).await;
+    }
+    let path = url.strip_prefix("sqlite://").unwrap_or(url);
+    let path = if path.ends_with(".db") || path == ":memory:" {
+        path.to_string()
+    } else {
+        format!("{}/agit.db", path.trim_end_matches('/'))
+    };
+    // Opening a missing file would create an empty repository
+    if path != ":memory:" && !std::path::Path::new(&path).exists() {
+        return Err(AgitError::InvalidArgument(format!(
+            "no agit repository at '{}'",
+            path
+        )));
+    }
+    Ok(Box::new(SqliteStorage::new(&path).await?))
+}
+
+#[cfg(feature = "postgres")]
+async fn connect_postgres(url: &str) -> Result<Box<dyn StorageBackend>> {
+    use crate::storage::PostgresStorage;
+
+    // The driver rejects unknown query parameters, so `namespace` is split out
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+    let mut namespace = "";
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| match pair.strip_prefix("namespace=") {
+            Some(value) => {
+                namespace = value;
+                false
+            }
+            None => !pair.is_empty(),
+        })
+        .collect();
+    let dsn = if rest.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, rest.join("&"))
+    };
+    Ok(Box::new(
+        PostgresStorage::new_scoped(&dsn, namespace).await?,
+    ))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn connect_postgres(_url: &str) -> Result<Box<dyn StorageBackend>> {
+    Err(AgitError::InvalidArgument(
+        "postgres remotes require the `postgres` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "s3")]
+async fn connect_s3(location: &str) -> Result<Box<dyn StorageBackend>> {
+    use crate::storage::S3Storage;
+
+    let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+    if bucket.is_empty() {
+        return Err(AgitError::InvalidArgument(format!(
+            "no bucket in 's3://{}'",
+            location
+        )));
+    }
+    let prefix = prefix.trim_matches('/');
+    let prefix = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", prefix)
+    };
+    Ok(Box::new(S3Storage::new(bucket, prefix, None).await?))
+}
+
+#[cfg(not(feature = "s3"))]
+async fn connect_s3(_location: &str) -> Result<Box<dyn StorageBackend>> {
+    Err(AgitError::InvalidArgument(
+        "s3 remotes require the `s3` feature".to_string(),
+    ))
+}
+
+/// Copy the commits reachable from every branch of `source` into `target`
+/// and point `target`'s `remotes/<remote>/*` refs at the branch tips.
+///
+/// The walk stops at commits `target` already has, since their history was
+/// copied with them. State blobs are never copied.
+pub async fn fetch(
+    target: &dyn StorageBackend,
+    remote: &str,
+    source: &dyn StorageBackend,
+) -> Result<FetchReport> {
+    let mut remote_refs = RefStore::new();
+    remote_refs.load_from_map(source.list_refs().await?);
+    let branches = remote_refs.list_branches();
+
+    let mut report = FetchReport {
+        remote: remote.to_string(),
+        ..Default::default()
+    };
+    let mut visited: HashSet<Hash> = HashSet::new();
+    let mut queue: VecDeque<Hash> = branches.values().cloned().collect();
+    while let Some(hash) = queue.pop_front() {
+        if !visited.insert(hash.clone()) || target.has_object(hash.as_str()).await? {
+            continue;
+        }
+        let data =
+            source
+                .get_object(hash.as_str())
+                .await?
+                .ok_or_else(|| AgitError::ObjectNotFound {
+                    hash: hash.0.clone(),
+                })?;
+        let commit: Commit = serde_json::from_slice(&data)?;
+        target
+            .put_object(hash.as_str(), ObjectType::Commit, &data)
+            .await?;
+        report.commits_fetched += 1;
+        queue.extend(commit.parent_hashes);
+    }
+
+    // Move every tracking ref of this remote in one update
+    let prefix = tracking_ref(remote, "");
+    let current: BTreeMap<String, String> = target
+        .list_refs()
+        .await?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(&prefix))
+        .collect();
+    let mut updates = Vec::new();
+    for (branch, tip) in branches {
+        let name = tracking_ref(remote, branch);
+        if current.get(&name) != Some(&tip.0) {
+            updates.push(RefUpdate::set(name, tip.as_str()));
+            report.updated.insert(branch.clone(), tip.clone());
+        }
+    }
+    for name in current.keys() {
+        let branch = &name[prefix.len()..];
+        if !branches.contains_key(branch) {
+            updates.push(RefUpdate::delete(name.as_str()));
+            report.pruned.push(branch.to_string());
+        }
+    }
+    if !updates.is_empty() {
+        target.update_refs(&updates).await?;
+    }
+    Ok(report)
+}
@@ -1,27 +1,219 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
-use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use crate::audit::{self, compute_audit_hash_v2, AuditVerification, CommitProvenance};
+use crate::cache::{CacheStats, ObjectCache};
+use crate::chunking::{self, ChunkingOptions};
+use crate::config;
+use crate::encryption::{has_encrypted_fields, is_encrypted_value, EncryptionMode};
 use crate::error::{AgitError, Result};
-use crate::hash::compute_state_hash;
-use crate::objects::{Blob, Commit};
-use crate::refs::{Head, RefStore};
-use crate::state::{merkle_diff, three_way_merge, AgentState, StateDiff};
-use crate::storage::{LogEntry, LogFilter, StorageBackend};
+use crate::fsck::{self, FsckOptions, FsckReport};
 use crate::gc;
-use crate::types::{ActionType, Hash, MergeStrategy, ObjectType};
+#[cfg(feature = "git-export")]
+use crate::git_export::{GitExportOptions, GitExportReport, GitExporter};
+use crate::hash::{
+    canonical_serialize, canonical_serialize_with_stats, compute_hash, compute_state_hash,
+    SerializeStats,
+};
+use crate::history::{CommitCursor, CommitFilter};
+use crate::objects::{tree_key, Blob, Commit};
+use crate::protection::{BranchProtection, PROTECTION_REF};
+use crate::refs::{Head, RefStore};
+use crate::remote::{self, FetchReport};
+use crate::retention::{self, LogRetentionResult, RetentionPolicy, RetentionResult};
+#[cfg(feature = "encryption")]
+use crate::rotation;
+use crate::scan::{scan_path, PathScan};
+use crate::signing::{self, VerificationReport};
+use crate::state::{
+    merkle_diff_stats, merkle_diff_with_options, merkle_diff_with_trees, remove_value_at_path,
+    set_value_at_path, three_way_merge_traced, value_at_path, AgentState, DiffOptions, DiffStats,
+    MergeConfig, MergeReport, MergeResolution, MerkleNode, MerkleProof, StateDiff,
+};
+use crate::storage::{
+    BackupReport, LogCursor, LogDeleteFilter, LogEntry, LogFilter, RefUpdate, StorageBackend,
+    StorageStats,
+};
+use crate::types::{ActionType, Hash, LogLevel, MergeStrategy, ObjectType};
+
+#[cfg(feature = "encryption")]
+use crate::encryption::{
+    random_salt, EncryptionConfig, StateEncryptor, ENCRYPTED_PREFIX, ENCRYPTION_CONFIG_REF,
+};
+#[cfg(feature = "encryption")]
+use crate::hash::hex;
+
+/// Upper bound on commits visited by ancestry traversals.
+const MAX_DEPTH: usize = 10_000;
+
+/// When storage signals log appends, `watch_logs` re-queries on each signal
+/// and polls only this many times less often, in case signals were missed.
+const SIGNALLED_POLL_FACTOR: u32 = 10;
 
+/// Times a commit is retried after a concurrent ref update, re-reading
+/// refs before each attempt.
+const REF_UPDATE_RETRIES: usize = 3;
+
+/// Storage lock held by operations that rewrite refs and delete objects,
+/// so gc and squash never run concurrently against the same storage.
+const MAINTENANCE_LOCK: &str = "maintenance";
+
+/// How long gc and squash wait for `MAINTENANCE_LOCK`.
+const MAINTENANCE_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Storage lock serializing creation of the encryption salt.
 #[cfg(feature = "encryption")]
-use crate::encryption::StateEncryptor;
+const ENCRYPTION_CONFIG_LOCK: &str = "encryption_config";
+
+/// Storage lock serializing auto-GC bookkeeping across processes.
+const AUTO_GC_LOCK: &str = "auto_gc";
+
+/// How long a commit waits for `AUTO_GC_LOCK` before skipping auto-GC.
+const AUTO_GC_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// First byte range read by `get_state_path`; doubled until the path is found.
+const STATE_PATH_WINDOW: usize = 64 * 1024;
+
+/// Options controlling repository behaviour, passed to `Repository::init_with_options`.
+#[derive(Debug, Clone)]
+pub struct RepoOptions {
+    /// Re-read refs from storage before `checkout`, `merge`, and `log` so that
+    /// commits made by other processes sharing the storage become visible.
+    pub auto_refresh: bool,
+    /// Split state values larger than a threshold into content-defined
+    /// chunks stored as separate blobs. Disabled when `None`; chunked
+    /// states are read back regardless of this setting.
+    pub chunking: Option<ChunkingOptions>,
+    /// Largest canonical serialization of a committed state, in bytes (0 = unlimited).
+    pub max_state_bytes: usize,
+    /// Deepest nesting of objects and arrays in a committed state (0 = unlimited).
+    pub max_depth: usize,
+    /// Most object keys, counted at all levels, in a committed state (0 = unlimited).
+    pub max_keys: usize,
+    /// Record the Merkle root of each committed state in the commit's
+    /// metadata under `MERKLE_ROOT_METADATA_KEY`, so field proofs can be
+    /// checked against the commit hash. In encrypted repositories the root
+    /// is that of the plaintext state.
+    pub embed_merkle_root: bool,
+    /// Most commits and state blobs kept in the in-memory object cache
+    /// (0 = no cache). Read when the repository is opened.
+    pub cache_max_entries: usize,
+    /// Most serialized bytes kept in the in-memory object cache
+    /// (0 = no cache). Read when the repository is opened.
+    pub cache_max_bytes: usize,
+    /// Run garbage collection from `commit_with_metadata` when the policy's
+    /// thresholds trip. Disabled when `None`.
+    pub auto_gc: Option<gc::AutoGcPolicy>,
+    /// Nonce scheme for encryption keys set on the repository.
+    /// `EncryptionMode::Convergent` lets identical encrypted states share a
+    /// blob at the cost of revealing which ones are identical.
+    pub encryption_mode: EncryptionMode,
+}
+
+impl Default for RepoOptions {
+    fn default() -> Self {
+        Self {
+            auto_refresh: false,
+            chunking: None,
+            max_state_bytes: 64 * 1024 * 1024,
+            // Deeper states could not be parsed back by serde_json
+            max_depth: 100,
+            max_keys: 1_000_000,
+            embed_merkle_root: false,
+            cache_max_entries: 4096,
+            cache_max_bytes: 64 * 1024 * 1024,
+            auto_gc: None,
+            encryption_mode: EncryptionMode::Randomized,
+        }
+    }
+}
+
+impl RepoOptions {
+    fn check_state_limits(&self, stats: &SerializeStats) -> Result<()> {
+        if self.max_state_bytes > 0 && stats.bytes > self.max_state_bytes {
+            return Err(AgitError::StateTooLarge {
+                bytes: stats.bytes,
+                limit: self.max_state_bytes,
+            });
+        }
+        if self.max_depth > 0 && stats.depth > self.max_depth {
+            return Err(AgitError::StateTooDeep {
+                depth: stats.depth,
+                limit: self.max_depth,
+            });
+        }
+        if self.max_keys > 0 && stats.keys > self.max_keys {
+            return Err(AgitError::StateTooManyKeys {
+                keys: stats.keys,
+                limit: self.max_keys,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Options for `Repository::merge_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Always create a merge commit, even when a fast-forward is possible.
+    pub no_ff: bool,
+}
+
+/// Commit metadata key caching the committed state's `cost`.
+pub(crate) const COST_METADATA_KEY: &str = "cost";
+
+/// Commit metadata key holding the `MergeReport` of a merge commit.
+pub(crate) const MERGE_REPORT_METADATA_KEY: &str = "merge_report";
+
+/// Commit metadata key holding the state's Merkle root hash, recorded when
+/// `RepoOptions::embed_merkle_root` is set.
+pub const MERKLE_ROOT_METADATA_KEY: &str = "merkle_root";
+
+/// Commit metadata key holding the id of the key that encrypted the
+/// commit's state (see `StateEncryptor::key_id`).
+pub const ENCRYPTION_KEY_ID_METADATA_KEY: &str = "encryption_key_id";
+
+/// Commit metadata key holding the commit's generation number: 1 for a root
+/// commit, otherwise one more than its highest parent. Commits written
+/// before it existed, or rewritten onto new parents, have it computed on
+/// demand.
+pub const GENERATION_METADATA_KEY: &str = "generation";
+
+/// Number of commits listed in `CostSummary::top_commits`.
+const COST_TOP_N: usize = 10;
+
+/// Aggregated cost over a branch's history, returned by `Repository::cost_summary`.
+#[derive(Debug, Clone, Default)]
+pub struct CostSummary {
+    /// Number of commits included.
+    pub commits: usize,
+    /// Sum of every included commit's state cost.
+    pub total: f64,
+    /// Cost per action type, keyed by its display name (e.g. `tool_call`).
+    pub by_action_type: HashMap<String, f64>,
+    /// Cost per commit author.
+    pub by_author: HashMap<String, f64>,
+    /// Most expensive commits, highest cost first.
+    pub top_commits: Vec<(Hash, f64)>,
+}
 
 /// The main VCS repository, orchestrating storage, refs, and object model.
 pub struct Repository {
     storage: Box<dyn StorageBackend>,
     refs: RefStore,
     agent_id: String,
+    options: RepoOptions,
+    protection: BranchProtection,
+    signing_key: Option<Vec<u8>>,
+    cache: ObjectCache,
     #[cfg(feature = "encryption")]
     encryptor: Option<StateEncryptor>,
 }
@@ -29,6 +221,14 @@ pub struct Repository {
 impl Repository {
     /// Initialize a new repository with the given storage backend.
     pub async fn init(storage: Box<dyn StorageBackend>) -> Result<Self> {
+        Self::init_with_options(storage, RepoOptions::default()).await
+    }
+
+    /// Initialize a repository with explicit options.
+    pub async fn init_with_options(
+        storage: Box<dyn StorageBackend>,
+        options: RepoOptions,
+    ) -> Result<Self> {
         storage.initialize().await?;
 
         let mut refs = RefStore::new();
@@ -39,24 +239,295 @@ impl Repository {
             refs.load_from_map(stored_refs);
         }
 
+        let protection = load_protection(&*storage).await?;
+
+        let cache = ObjectCache::new(options.cache_max_entries, options.cache_max_bytes);
         Ok(Repository {
             storage,
             refs,
             agent_id: "default".to_string(),
+            options,
+            protection,
+            signing_key: None,
+            cache,
             #[cfg(feature = "encryption")]
             encryptor: None,
         })
     }
 
+    /// Re-read all refs (including HEAD) from storage and replace the
+    /// in-memory ref store with them.
+    ///
+    /// Storage is authoritative: if the local HEAD differs from the stored
+    /// one, the stored value wins and the change is recorded in the audit log.
+    pub async fn refresh_refs(&mut self) -> Result<()> {
+        let stored_refs = self.storage.list_refs().await?;
+        let local_head = self.refs.to_map().remove("HEAD");
+        let stored_head = stored_refs.get("HEAD").cloned();
+
+        self.refs = self.reconcile_refs(stored_refs);
+        self.protection = load_protection(&*self.storage).await?;
+
+        if let (Some(local), Some(stored)) = (local_head, stored_head) {
+            if local != stored {
+                self.log_action(
+                    "refs_refreshed",
+                    &format!("HEAD changed in storage: {} -> {}", local, stored),
+                    None,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close the storage, releasing its connections and files.
+    ///
+    /// Refs, HEAD included, are written to storage as they change, so
+    /// nothing is pending when this runs.
+    pub async fn close(self) -> Result<()> {
+        self.storage.close().await
+    }
+
+    /// Replace the branch protection rules and persist them under the
+    /// `config/protection` ref so every process sharing the storage sees them.
+    pub async fn set_branch_protection(&mut self, protection: BranchProtection) -> Result<()> {
+        let blob = Blob::new(serde_json::to_value(&protection)?);
+        let hash = blob.hash();
+        self.storage
+            .put_object(hash.as_str(), ObjectType::Blob, &blob.serialize())
+            .await?;
+        self.storage.set_ref(PROTECTION_REF, hash.as_str()).await?;
+        self.protection = protection;
+        self.log_action(
+            "set_branch_protection",
+            &format!(
+                "updated branch protection ({} rules)",
+                self.protection.rules.len()
+            ),
+            None,
+        )
+        .await
+    }
+
+    /// Current branch protection rules.
+    pub fn branch_protection(&self) -> &BranchProtection {
+        &self.protection
+    }
+
+    /// The configuration value under `key`, if set.
+    pub async fn config_get(&self, key: &str) -> Result<Option<Value>> {
+        config::check_config_key(key)?;
+        Ok(config::load_config(&*self.storage, key)
+            .await?
+            .map(|(_, value)| value))
+    }
+
+    /// The configuration value under `key` deserialized as `T`, if set.
+    pub async fn config_get_as<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.config_get(key).await? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every configuration value, by key, including those agit manages
+    /// itself such as `protection` and `remotes`.
+    pub async fn config_list(&self) -> Result<BTreeMap<String, Value>> {
+        config::list_config(&*self.storage).await
+    }
+
+    /// Set the configuration value under `key`, persisted as a blob
+    /// referenced from the `config/<key>` ref so every process sharing the
+    /// storage sees it.
+    ///
+    /// The ref is swapped only if it still holds the value read just
+    /// before, retrying if another writer got there first, so the audit
+    /// entry recording the change names the value it really replaced.
+    /// Setting the current value again changes and logs nothing.
+    pub async fn config_set(&self, key: &str, value: Value) -> Result<()> {
+        config::check_writable_key(key)?;
+        let blob = Blob::new(value);
+        let hash = blob.hash();
+        self.storage
+            .put_object(hash.as_str(), ObjectType::Blob, &blob.serialize())
+            .await?;
+        let name = config::config_ref(key);
+        let mut attempt = 0;
+        let old = loop {
+            let old = self.storage.get_ref(&name).await?;
+            if old.as_deref() == Some(hash.as_str()) {
+                return Ok(());
+            }
+            let update = RefUpdate::set(name.as_str(), hash.as_str());
+            let update = match &old {
+                Some(old) => update.expecting(old.as_str()),
+                None => update.expecting_absent(),
+            };
+            match self.storage.update_refs(&[update]).await {
+                Ok(()) => break old,
+                Err(AgitError::ConcurrentUpdate { .. }) if attempt < REF_UPDATE_RETRIES => {
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        let details = serde_json::json!({
+            "key": key,
+            "old_hash": old,
+            "new_hash": hash.as_str(),
+        });
+        let message = format!("set config '{}'", key);
+        self.log_entry(LogLevel::Info, "config_set", &message, None, details)
+            .await
+    }
+
     /// Set the agent ID for audit logging.
     pub fn set_agent_id(&mut self, id: &str) {
         self.agent_id = id.to_string();
     }
 
+    /// Sign every new commit with an HMAC-SHA256 of `key`.
+    pub fn set_signing_key(&mut self, key: &[u8]) {
+        self.signing_key = Some(key.to_vec());
+    }
+
+    /// Check a single commit's signature against `key`.
+    ///
+    /// Returns `Ok(false)` for unsigned or tampered commits.
+    pub async fn verify_commit(&self, hash: &str, key: &[u8]) -> Result<bool> {
+        let commit = self
+            .get_commit(hash)
+            .await?
+            .ok_or_else(|| AgitError::ObjectNotFound {
+                hash: hash.to_string(),
+            })?;
+        Ok(signing::verify_commit_signature(&commit, key))
+    }
+
+    /// Verify every commit reachable from `branch` against `key`.
+    pub async fn verify_history(&self, branch: &str, key: &[u8]) -> Result<VerificationReport> {
+        let tip = self.refs.resolve_ref(branch)?;
+        let mut report = VerificationReport::default();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([tip]);
+
+        while let Some(hash) = queue.pop_front() {
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+            if visited.len() > MAX_DEPTH {
+                return Err(AgitError::DepthLimitExceeded(format!(
+                    "verify_history visited more than {} commits",
+                    MAX_DEPTH
+                )));
+            }
+            let commit =
+                self.get_commit(hash.as_str())
+                    .await?
+                    .ok_or_else(|| AgitError::ObjectNotFound {
+                        hash: hash.to_string(),
+                    })?;
+
+            if !commit.metadata.contains_key(signing::SIGNATURE_KEY) {
+                report.unsigned.push(hash);
+            } else if signing::verify_commit_signature(&commit, key) {
+                report.verified.push(hash);
+            } else {
+                report.invalid.push(hash);
+            }
+            queue.extend(commit.parent_hashes);
+        }
+
+        Ok(report)
+    }
+
     /// Set an encryption key to encrypt/decrypt agent state fields at rest.
+    ///
+    /// The key is derived with the repository's random salt, generated and
+    /// stored under `ENCRYPTION_CONFIG_REF` the first time any process sets
+    /// a key. States encrypted before the salt existed still decrypt.
+    #[cfg(feature = "encryption")]
+    pub async fn set_encryption_key(&mut self, key: &str) -> Result<()> {
+        self.encryptor = Some(self.derive_encryptor(key).await?);
+        Ok(())
+    }
+
+    /// Set a raw 256-bit encryption key, e.g. a data key handed out by a
+    /// KMS, skipping passphrase derivation. Use
+    /// `StateEncryptor::generate_key` to create one.
+    #[cfg(feature = "encryption")]
+    pub async fn set_encryption_key_bytes(&mut self, key: [u8; 32]) -> Result<()> {
+        let key = zeroize::Zeroizing::new(key);
+        let mut encryptor = StateEncryptor::from_key_bytes(&key);
+        if self.options.encryption_mode == EncryptionMode::Convergent {
+            encryptor = self.with_encryption_mode(encryptor, &self.encryption_salt().await?);
+        }
+        self.encryptor = Some(encryptor);
+        Ok(())
+    }
+
+    /// Drop the encryption key, wiping its key material now rather than when
+    /// the repository is dropped. States written afterwards are stored
+    /// unencrypted, and encrypted states are no longer decrypted.
+    #[cfg(feature = "encryption")]
+    pub fn clear_encryption_key(&mut self) {
+        drop(self.encryptor.take());
+    }
+
+    /// Derive the encryptor for `key` from the repository salt, falling
+    /// back to the agent-scoped derivation for payloads without an envelope.
+    #[cfg(feature = "encryption")]
+    async fn derive_encryptor(&self, key: &str) -> Result<StateEncryptor> {
+        let salt = self.encryption_salt().await?;
+        let encryptor = StateEncryptor::with_salt(key, &salt)
+            .with_legacy(StateEncryptor::with_context(key, &self.agent_id));
+        Ok(self.with_encryption_mode(encryptor, &salt))
+    }
+
+    /// Apply `RepoOptions::encryption_mode`, scoping convergent nonces to
+    /// the repository's `salt` so equal states only match within it.
+    #[cfg(feature = "encryption")]
+    fn with_encryption_mode(&self, encryptor: StateEncryptor, salt: &[u8]) -> StateEncryptor {
+        match self.options.encryption_mode {
+            EncryptionMode::Randomized => encryptor,
+            EncryptionMode::Convergent => encryptor.convergent(salt),
+        }
+    }
+
+    /// The repository's key derivation salt, created on first use.
     #[cfg(feature = "encryption")]
-    pub fn set_encryption_key(&mut self, key: &str) {
-        self.encryptor = Some(StateEncryptor::with_context(key, &self.agent_id));
+    async fn encryption_salt(&self) -> Result<Vec<u8>> {
+        // Two processes setting a key at once must agree on one salt
+        let _lock = self
+            .storage
+            .acquire_lock(ENCRYPTION_CONFIG_LOCK, MAINTENANCE_LOCK_TIMEOUT)
+            .await?;
+        if let Some(hash) = self.storage.get_ref(ENCRYPTION_CONFIG_REF).await? {
+            let data = self
+                .storage
+                .get_object(&hash)
+                .await?
+                .ok_or(AgitError::ObjectNotFound { hash })?;
+            let config: EncryptionConfig = serde_json::from_slice(&data)?;
+            return hex::decode(&config.salt).ok_or_else(|| {
+                AgitError::EncryptionError(format!("malformed salt in {}", ENCRYPTION_CONFIG_REF))
+            });
+        }
+
+        let salt = random_salt();
+        let config = EncryptionConfig {
+            salt: hex::encode(salt),
+        };
+        let blob = Blob::new(serde_json::to_value(&config)?);
+        let hash = blob.hash();
+        self.storage
+            .put_object(hash.as_str(), ObjectType::Blob, &blob.serialize())
+            .await?;
+        self.storage
+            .set_ref(ENCRYPTION_CONFIG_REF, hash.as_str())
+            .await?;
+        Ok(salt.to_vec())
     }
 
     /// Commit agent state, returning the commit hash.
@@ -71,7 +542,13 @@ impl Repository {
     }
 
     /// Commit with additional metadata.
-    #[cfg_attr(feature = "observability", tracing::instrument(skip(self, state, metadata)))]
+    ///
+    /// With `RepoOptions::auto_gc` set, the commit is counted towards the
+    /// policy and garbage collection runs before returning once it trips.
+    #[cfg_attr(
+        feature = "observability",
+        tracing::instrument(skip(self, state, metadata))
+    )]
     pub async fn commit_with_metadata(
         &mut self,
         state: &AgentState,
@@ -79,20 +556,128 @@ impl Repository {
         action_type: ActionType,
         metadata: serde_json::Map<String, Value>,
     ) -> Result<Hash> {
-        // Optional encryption
-        let final_state = match self.get_encryptor() {
-            #[cfg(feature = "encryption")]
-            Some(enc) => enc.encrypt_state(state)?,
-            _ => state.clone(),
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .commit_once(state, message, &action_type, metadata.clone())
+                .await;
+            match result {
+                Err(AgitError::ConcurrentUpdate { .. }) if attempt < REF_UPDATE_RETRIES => {
+                    attempt += 1;
+                    // Commit again on top of whatever the other writer left
+                    self.refresh_refs().await?;
+                }
+                Ok(hash) => {
+                    self.maybe_auto_gc().await;
+                    return Ok(hash);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Count a commit towards `RepoOptions::auto_gc` and collect garbage
+    /// if the policy says so. Failures are logged rather than returned,
+    /// since the commit itself succeeded.
+    async fn maybe_auto_gc(&self) {
+        let Some(policy) = self.options.auto_gc.clone() else {
+            return;
+        };
+        if let Err(e) = self.auto_gc(&policy).await {
+            self.log_failure(LogLevel::Warn, "auto_gc_failed", e).await;
+        }
+    }
+
+    async fn auto_gc(&self, policy: &gc::AutoGcPolicy) -> Result<()> {
+        // One process at a time; commits made while another process holds
+        // the lock for a long run go uncounted
+        let _lock = match self
+            .storage
+            .acquire_lock(AUTO_GC_LOCK, AUTO_GC_LOCK_TIMEOUT)
+            .await
+        {
+            Ok(lock) => lock,
+            Err(AgitError::LockTimeout { .. }) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let mut state = match self.storage.get_ref(gc::GC_STATE_REF).await? {
+            Some(hash) => match self.storage.get_object(&hash).await? {
+                Some(data) => serde_json::from_slice(&data)?,
+                None => gc::AutoGcState::default(),
+            },
+            None => gc::AutoGcState::default(),
         };
+        state.commits_since_gc += 1;
+
+        let now = Utc::now();
+        let mut trigger = None;
+        if gc::AutoGcState::elapsed(state.last_run, policy.min_interval, now) {
+            if policy.every_n_commits > 0 && state.commits_since_gc >= policy.every_n_commits {
+                trigger = Some("commit_count");
+            } else if let Some(max_ratio) = policy.max_unreachable_ratio {
+                if gc::AutoGcState::elapsed(state.last_check, policy.min_interval, now) {
+                    state.last_check = Some(now);
+                    let options = gc::GcOptions {
+                        dry_run: true,
+                        keep_last_n: policy.keep_last_n,
+                        ..Default::default()
+                    };
+                    let dry = self.gc_with_options(&options).await?;
+                    let ratio = dry.unreachable_count as f64 / dry.objects_before.max(1) as f64;
+                    if ratio > max_ratio {
+                        trigger = Some("unreachable_ratio");
+                    }
+                }
+            }
+        }
 
-        // Store the state as a blob
-        let state_value = final_state.to_value();
-        let blob = Blob::new(state_value);
-        let tree_hash = blob.hash();
+        if let Some(trigger) = trigger {
+            let options = gc::GcOptions {
+                keep_last_n: policy.keep_last_n,
+                ..Default::default()
+            };
+            let result = self.gc_with_options(&options).await?;
+            state.commits_since_gc = 0;
+            state.last_run = Some(now);
+            self.log_entry(
+                LogLevel::Info,
+                "auto_gc",
+                &format!(
+                    "auto gc ({}) removed {} of {} objects",
+                    trigger, result.objects_removed, result.objects_before
+                ),
+                None,
+                serde_json::json!({
+                    "trigger": trigger,
+                    "objects_before": result.objects_before,
+                    "objects_removed": result.objects_removed,
+                    "bytes_freed": result.freed.bytes,
+                }),
+            )
+            .await?;
+        }
+
+        let blob = Blob::new(serde_json::to_value(&state)?);
+        let hash = blob.hash();
         self.storage
-            .put_object(tree_hash.as_str(), ObjectType::Blob, &blob.serialize())
+            .put_object(hash.as_str(), ObjectType::Blob, &blob.serialize())
             .await?;
+        self.storage.set_ref(gc::GC_STATE_REF, hash.as_str()).await
+    }
+
+    async fn commit_once(
+        &mut self,
+        state: &AgentState,
+        message: &str,
+        action_type: &ActionType,
+        metadata: serde_json::Map<String, Value>,
+    ) -> Result<Hash> {
+        if let Head::Attached(branch) = self.refs.get_head() {
+            if let Err(e) = self.protection.check_commit(branch, action_type) {
+                return Err(self.log_failure(LogLevel::Warn, "commit_denied", e).await);
+            }
+        }
 
         // Determine parent(s)
         let parent_hashes = match self.refs.resolve_ref("HEAD") {
@@ -101,53 +686,123 @@ impl Repository {
             Err(e) => return Err(e),
         };
 
-        // Create the commit
-        let commit = Commit {
-            tree_hash: tree_hash.clone(),
-            parent_hashes,
-            message: message.to_string(),
-            author: self.agent_id.clone(),
-            timestamp: Utc::now(),
-            action_type: action_type.clone(),
-            metadata,
-        };
-        let commit_hash = commit.hash();
-        let commit_data = serde_json::to_vec(&commit)?;
-        self.storage
-            .put_object(commit_hash.as_str(), ObjectType::Commit, &commit_data)
+        let (commit_hash, provenance) = self
+            .write_commit(state, message, action_type, metadata, parent_hashes)
             .await?;
 
-        // Update branch ref
-        match self.refs.get_head() {
+        // Move the branch ref and HEAD together, in memory only once stored
+        let mut refs = self.refs.clone();
+        let mut updates = Vec::new();
+        match refs.get_head() {
             Head::Attached(branch) => {
                 let branch = branch.clone();
-                if self.refs.list_branches().contains_key(&branch) {
-                    self.refs.update_branch(&branch, commit_hash.clone())?;
+                let update = RefUpdate::set(&branch, commit_hash.as_str());
+                if let Some(old) = refs.list_branches().get(&branch) {
+                    updates.push(update.expecting(old.as_str()));
+                    refs.update_branch(&branch, commit_hash.clone())?;
                 } else {
-                    self.refs.create_branch(&branch, commit_hash.clone())?;
+                    updates.push(update.expecting_absent());
+                    refs.create_branch(&branch, commit_hash.clone())?;
                 }
-                self.storage
-                    .set_ref(&branch, commit_hash.as_str())
-                    .await?;
             }
             Head::Detached(_) => {
-                self.refs.set_head(commit_hash.as_str(), true);
+                refs.set_head(commit_hash.as_str(), true);
             }
         }
-
-        // Persist HEAD
-        let refs_map = self.refs.to_map();
-        if let Some(head_val) = refs_map.get("HEAD") {
-            self.storage.set_ref("HEAD", head_val).await?;
+        if let Some(head_val) = refs.to_map().get("HEAD") {
+            updates.push(RefUpdate::set("HEAD", head_val));
         }
+        self.storage.update_refs(&updates).await?;
+        self.refs = refs;
 
         // Audit log
-        self.log_action(
-            &action_type.to_string(),
-            message,
-            Some(commit_hash.as_str()),
-        )
-        .await?;
+        self.log_commit(&action_type.to_string(), message, &commit_hash, &provenance)
+            .await?;
+
+        Ok(commit_hash)
+    }
+
+    /// Commit agent state directly onto `branch` without checking it out.
+    ///
+    /// The branch tip becomes the sole parent and only that branch ref is
+    /// updated; HEAD is left untouched. If the branch does not exist it is
+    /// created from HEAD when `create_if_missing` is set, otherwise
+    /// `BranchNotFound` is returned.
+    pub async fn commit_to_branch(
+        &mut self,
+        branch: &str,
+        state: &AgentState,
+        message: &str,
+        action_type: ActionType,
+        create_if_missing: bool,
+    ) -> Result<Hash> {
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .commit_to_branch_once(branch, state, message, &action_type, create_if_missing)
+                .await;
+            match result {
+                Err(AgitError::ConcurrentUpdate { .. }) if attempt < REF_UPDATE_RETRIES => {
+                    attempt += 1;
+                    self.refresh_refs().await?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn commit_to_branch_once(
+        &mut self,
+        branch: &str,
+        state: &AgentState,
+        message: &str,
+        action_type: &ActionType,
+        create_if_missing: bool,
+    ) -> Result<Hash> {
+        if let Err(e) = self.protection.check_commit(branch, action_type) {
+            return Err(self.log_failure(LogLevel::Warn, "commit_denied", e).await);
+        }
+        let tip = self.refs.list_branches().get(branch).cloned();
+        let exists = tip.is_some();
+        let parent_hashes = if exists {
+            vec![self.refs.resolve_ref(branch)?]
+        } else if create_if_missing {
+            match self.refs.resolve_ref("HEAD") {
+                Ok(hash) => vec![hash],
+                Err(AgitError::NoCommits) => vec![],
+                Err(e) => return Err(e),
+            }
+        } else {
+            return Err(AgitError::BranchNotFound {
+                name: branch.to_string(),
+            });
+        };
+
+        let (commit_hash, provenance) = self
+            .write_commit(
+                state,
+                message,
+                action_type,
+                serde_json::Map::new(),
+                parent_hashes,
+            )
+            .await?;
+
+        // Only move the branch if no other writer has moved or created it since
+        let update = RefUpdate::set(branch, commit_hash.as_str());
+        let update = match &tip {
+            Some(tip) => update.expecting(tip.as_str()),
+            None => update.expecting_absent(),
+        };
+        self.storage.update_refs(&[update]).await?;
+        if tip.is_some() {
+            self.refs.update_branch(branch, commit_hash.clone())?;
+        } else {
+            self.refs.create_branch(branch, commit_hash.clone())?;
+        }
+
+        self.log_commit(&action_type.to_string(), message, &commit_hash, &provenance)
+            .await?;
 
         Ok(commit_hash)
     }
@@ -155,16 +810,31 @@ impl Repository {
     /// Create a new branch at the given source (or HEAD).
     pub async fn branch(&mut self, name: &str, from: Option<&str>) -> Result<()> {
         let source_hash = match from {
-            Some(src) => self.resolve(src)?,
+            Some(src) => self.resolve(src).await?,
             None => self.refs.resolve_ref("HEAD")?,
         };
-        self.refs.create_branch(name, source_hash.clone())?;
-        self.storage.set_ref(name, source_hash.as_str()).await?;
-        Ok(())
+        let mut refs = self.refs.clone();
+        refs.create_branch(name, source_hash.clone())?;
+        let update = RefUpdate::set(name, source_hash.as_str()).expecting_absent();
+        match self.storage.update_refs(&[update]).await {
+            // Another writer created it since the refs were loaded
+            Err(AgitError::ConcurrentUpdate { .. }) => Err(AgitError::BranchExists {
+                name: name.to_string(),
+            }),
+            result => {
+                result?;
+                self.refs = refs;
+                Ok(())
+            }
+        }
     }
 
     /// Checkout a branch or commit, returning the state at that point.
     pub async fn checkout(&mut self, target: &str) -> Result<AgentState> {
+        if self.options.auto_refresh {
+            self.refresh_refs().await?;
+        }
+
         // Try as branch first
         if self.refs.list_branches().contains_key(target) {
             self.refs.set_head(target, false);
@@ -176,30 +846,104 @@ impl Repository {
             return self.get_state(hash.as_str()).await;
         }
 
-        // Try as commit hash
-        if self.storage.has_object(target).await? {
-            self.refs.set_head(target, true);
-            let refs_map = self.refs.to_map();
-            if let Some(head_val) = refs_map.get("HEAD") {
-                self.storage.set_ref("HEAD", head_val).await?;
+        // Otherwise detach at a tracking ref or commit hash
+        let hash = self.resolve(target).await?;
+        self.refs.set_head(hash.as_str(), true);
+        let refs_map = self.refs.to_map();
+        if let Some(head_val) = refs_map.get("HEAD") {
+            self.storage.set_ref("HEAD", head_val).await?;
+        }
+        self.get_state(hash.as_str()).await
+    }
+
+    /// State of `branch` as of `at`: the newest commit on the branch's
+    /// first-parent line whose timestamp is not after `at`.
+    ///
+    /// Walking first parents (rather than sorting all ancestors by time) keeps
+    /// commits merged in later from other branches, and skewed merge-commit
+    /// clocks, from being reported as the branch's state at that instant.
+    pub async fn state_at(&self, branch: &str, at: DateTime<Utc>) -> Result<(Hash, AgentState)> {
+        let hash = self.commit_at(branch, at).await?;
+        let state = self.get_state(hash.as_str()).await?;
+        Ok((hash, state))
+    }
+
+    /// Check out `branch` as of `at` in detached HEAD mode. See `state_at`.
+    pub async fn checkout_at(
+        &mut self,
+        branch: &str,
+        at: DateTime<Utc>,
+    ) -> Result<(Hash, AgentState)> {
+        let hash = self.commit_at(branch, at).await?;
+        let state = self.get_state(hash.as_str()).await?;
+        self.refs.set_head(hash.as_str(), true);
+        let refs_map = self.refs.to_map();
+        if let Some(head_val) = refs_map.get("HEAD") {
+            self.storage.set_ref("HEAD", head_val).await?;
+        }
+        Ok((hash, state))
+    }
+
+    async fn commit_at(&self, branch: &str, at: DateTime<Utc>) -> Result<Hash> {
+        let mut current = Some(self.refs.resolve_ref(branch)?);
+        let mut steps = 0;
+
+        while let Some(hash) = current {
+            steps += 1;
+            if steps > MAX_DEPTH {
+                return Err(AgitError::DepthLimitExceeded(format!(
+                    "state_at walked more than {} commits",
+                    MAX_DEPTH
+                )));
+            }
+            let commit =
+                self.get_commit(hash.as_str())
+                    .await?
+                    .ok_or_else(|| AgitError::ObjectNotFound {
+                        hash: hash.to_string(),
+                    })?;
+            if commit.timestamp <= at {
+                return Ok(hash);
             }
-            return self.get_state(target).await;
+            current = commit.parent_hashes.into_iter().next();
         }
 
-        Err(AgitError::RefNotFound {
-            name: target.to_string(),
-        })
+        Err(AgitError::NoCommits)
     }
 
     /// Compute the diff between two commits.
     /// Uses Merkle trees for O(log N) performance on large states.
     #[cfg_attr(feature = "observability", tracing::instrument(skip(self)))]
     pub async fn diff(&self, hash1: &str, hash2: &str) -> Result<StateDiff> {
+        self.diff_with_options(hash1, hash2, &DiffOptions::default())
+            .await
+    }
+
+    /// Count the changes between two commits without materializing the
+    /// changed values.
+    pub async fn diff_stats(&self, hash1: &str, hash2: &str) -> Result<DiffStats> {
         let state1 = self.get_state(hash1).await?;
         let state2 = self.get_state(hash2).await?;
-        
-        let entries = merkle_diff(&state1.to_value(), &state2.to_value());
-        
+        Ok(merkle_diff_stats(&state1.to_value(), &state2.to_value()))
+    }
+
+    /// Compute the diff between two commits, skipping paths ignored by `options`.
+    pub async fn diff_with_options(
+        &self,
+        hash1: &str,
+        hash2: &str,
+        options: &DiffOptions,
+    ) -> Result<StateDiff> {
+        let entries = if options.is_empty() {
+            let (tree1, value1) = self.state_with_tree(hash1).await?;
+            let (tree2, value2) = self.state_with_tree(hash2).await?;
+            merkle_diff_with_trees(&tree1, &tree2, &value1, &value2)
+        } else {
+            let state1 = self.get_state(hash1).await?;
+            let state2 = self.get_state(hash2).await?;
+            merkle_diff_with_options(&state1.to_value(), &state2.to_value(), options)
+        };
+
         Ok(StateDiff {
             base_hash: hash1.to_string(),
             target_hash: hash2.to_string(),
@@ -207,29 +951,143 @@ impl Repository {
         })
     }
 
-    /// Merge a branch into the current branch.
-    #[cfg_attr(feature = "observability", tracing::instrument(skip(self)))]
-    pub async fn merge(&mut self, branch: &str, strategy: MergeStrategy) -> Result<Hash> {
-        let current_branch = match self.refs.get_head() {
-            Head::Attached(name) => name.clone(),
-            Head::Detached(_) => return Err(AgitError::DetachedHead),
+    /// Apply `diff` to the HEAD state and commit the result.
+    ///
+    /// The top-level state `timestamp` is not patched; the new state is
+    /// stamped with the current time so patches apply across repositories.
+    pub async fn apply_diff(&mut self, diff: &StateDiff, message: &str) -> Result<Hash> {
+        let head = self.head()?;
+        let base = self.get_state(head.as_str()).await?.to_value();
+
+        let patch = StateDiff {
+            base_hash: diff.base_hash.clone(),
+            target_hash: diff.target_hash.clone(),
+            entries: diff
+                .entries
+                .iter()
+                .filter(|e| e.path != ["timestamp"])
+                .cloned()
+                .collect(),
+        };
+        let mut state: AgentState = serde_json::from_value(patch.apply(&base)?)?;
+        state.timestamp = Utc::now();
+
+        let mut metadata = serde_json::Map::new();
+        metadata.insert(
+            "patch_base".to_string(),
+            Value::from(diff.base_hash.clone()),
+        );
+        metadata.insert(
+            "patch_target".to_string(),
+            Value::from(diff.target_hash.clone()),
+        );
+        self.commit_with_metadata(&state, message, ActionType::SystemEvent, metadata)
+            .await
+    }
+
+    /// Merge a branch into the current branch.
+    pub async fn merge(&mut self, branch: &str, strategy: MergeStrategy) -> Result<Hash> {
+        self.merge_with_options(branch, strategy, MergeOptions::default())
+            .await
+    }
+
+    /// Merge a branch into the current branch with explicit options.
+    ///
+    /// When the current branch is an ancestor of `branch` the ref is simply
+    /// fast-forwarded unless `options.no_ff` is set.
+    #[cfg_attr(feature = "observability", tracing::instrument(skip(self)))]
+    pub async fn merge_with_options(
+        &mut self,
+        branch: &str,
+        strategy: MergeStrategy,
+        options: MergeOptions,
+    ) -> Result<Hash> {
+        self.merge_impl(branch, strategy, options, &MergeConfig::default())
+            .await
+    }
+
+    /// Three-way merge a branch into the current branch, resolving arrays
+    /// changed on both sides and conflicts covered by merge drivers
+    /// according to `config`.
+    pub async fn merge_with_config(
+        &mut self,
+        branch: &str,
+        options: MergeOptions,
+        config: &MergeConfig,
+    ) -> Result<Hash> {
+        self.merge_impl(branch, MergeStrategy::ThreeWay, options, config)
+            .await
+    }
+
+    async fn merge_impl(
+        &mut self,
+        branch: &str,
+        strategy: MergeStrategy,
+        options: MergeOptions,
+        config: &MergeConfig,
+    ) -> Result<Hash> {
+        if self.options.auto_refresh {
+            self.refresh_refs().await?;
+        }
+
+        let current_branch = match self.refs.get_head() {
+            Head::Attached(name) => name.clone(),
+            Head::Detached(_) => return Err(AgitError::DetachedHead),
         };
 
         let ours_hash = self.refs.resolve_ref(&current_branch)?;
         let theirs_hash = self.refs.resolve_ref(branch)?;
 
-        // Fast-forward check
+        // Nothing to do if theirs is already part of our history
         if ours_hash == theirs_hash {
             return Ok(ours_hash);
         }
+        if self
+            .is_ancestor(theirs_hash.as_str(), ours_hash.as_str())
+            .await?
+        {
+            return Ok(ours_hash);
+        }
+
+        // Both a fast-forward and a merge commit move the branch
+        if let Err(e) = self
+            .protection
+            .check_commit(&current_branch, &ActionType::Merge)
+        {
+            return Err(self.log_failure(LogLevel::Warn, "merge_denied", e).await);
+        }
+
+        // Fast-forward check
+        if !options.no_ff
+            && self
+                .is_ancestor(ours_hash.as_str(), theirs_hash.as_str())
+                .await?
+        {
+            self.storage
+                .update_refs(&[RefUpdate::set(&current_branch, theirs_hash.as_str())
+                    .expecting(ours_hash.as_str())])
+                .await?;
+            self.refs
+                .update_branch(&current_branch, theirs_hash.clone())?;
+            self.log_action(
+                "fast_forward",
+                &format!("fast-forwarded '{}' to '{}'", current_branch, branch),
+                Some(theirs_hash.as_str()),
+            )
+            .await?;
+            return Ok(theirs_hash);
+        }
 
         // Find merge base
-        let base_hash = self.find_merge_base(ours_hash.as_str(), theirs_hash.as_str()).await?;
+        let base_hash = self
+            .find_merge_base(ours_hash.as_str(), theirs_hash.as_str())
+            .await?;
 
         let base_state = self.get_state(base_hash.as_str()).await?;
         let ours_state = self.get_state(ours_hash.as_str()).await?;
         let theirs_state = self.get_state(theirs_hash.as_str()).await?;
 
+        let mut resolution = MergeResolution::default();
         let merged_state = match strategy {
             MergeStrategy::Ours => ours_state.clone(),
             MergeStrategy::Theirs => theirs_state.clone(),
@@ -238,16 +1096,15 @@ impl Repository {
                 let ours_val = ours_state.to_value();
                 let theirs_val = theirs_state.to_value();
 
-                let (merged_val, conflicts) = three_way_merge(&base_val, &ours_val, &theirs_val);
+                let (merged_val, conflicts, traced) =
+                    three_way_merge_traced(&base_val, &ours_val, &theirs_val, config);
+                resolution = traced;
 
                 if !conflicts.is_empty() {
-                    let conflict_paths: Vec<String> = conflicts
-                        .iter()
-                        .map(|c| c.path.join("."))
-                        .collect();
-                    return Err(AgitError::MergeConflict {
-                        details: format!("conflicts at: {}", conflict_paths.join(", ")),
-                    });
+                    let err = AgitError::MergeConflict { conflicts };
+                    return Err(self
+                        .log_failure(LogLevel::Warn, "merge_conflict", err)
+                        .await);
                 }
 
                 serde_json::from_value::<AgentState>(merged_val)
@@ -255,82 +1112,193 @@ impl Repository {
             }
         };
 
-        // Create merge commit with two parents
-        let blob = Blob::new(merged_state.to_value());
-        let tree_hash = blob.hash();
-        self.storage
-            .put_object(tree_hash.as_str(), ObjectType::Blob, &blob.serialize())
-            .await?;
-
-        let commit = Commit {
-            tree_hash,
-            parent_hashes: vec![ours_hash, theirs_hash],
-            message: format!("merge branch '{}' into '{}'", branch, current_branch),
-            author: self.agent_id.clone(),
-            timestamp: Utc::now(),
-            action_type: ActionType::Merge,
-            metadata: serde_json::Map::new(),
+        // Create merge commit with two parents, stored and encrypted the
+        // same way as any other commit
+        let report = MergeReport {
+            base_hash: base_hash.0.clone(),
+            strategy,
+            auto_merged: resolution.len(),
+            from_ours: resolution.from_ours,
+            from_theirs: resolution.from_theirs,
+            resolved: resolution.resolved,
         };
-
-        let commit_hash = commit.hash();
-        let commit_data = serde_json::to_vec(&commit)?;
-        self.storage
-            .put_object(commit_hash.as_str(), ObjectType::Commit, &commit_data)
-            .await?;
-
-        // Update current branch
-        self.refs.update_branch(&current_branch, commit_hash.clone())?;
-        self.storage
-            .set_ref(&current_branch, commit_hash.as_str())
+        let mut metadata = serde_json::Map::new();
+        metadata.insert(
+            MERGE_REPORT_METADATA_KEY.to_string(),
+            serde_json::to_value(&report)?,
+        );
+        let (commit_hash, provenance) = self
+            .write_commit(
+                &merged_state,
+                &format!("merge branch '{}' into '{}'", branch, current_branch),
+                &ActionType::Merge,
+                metadata,
+                vec![ours_hash.clone(), theirs_hash],
+            )
             .await?;
 
-        let refs_map = self.refs.to_map();
-        if let Some(head_val) = refs_map.get("HEAD") {
-            self.storage.set_ref("HEAD", head_val).await?;
+        // Move the current branch and HEAD together
+        let mut updates = vec![
+            RefUpdate::set(&current_branch, commit_hash.as_str()).expecting(ours_hash.as_str())
+        ];
+        if let Some(head_val) = self.refs.to_map().get("HEAD") {
+            updates.push(RefUpdate::set("HEAD", head_val));
         }
+        if let Err(e) = self.storage.update_refs(&updates).await {
+            return Err(self.log_failure(LogLevel::Error, "merge_failed", e).await);
+        }
+        self.refs
+            .update_branch(&current_branch, commit_hash.clone())?;
 
-        self.log_action(
+        self.log_commit(
             "merge",
             &format!("merged '{}' into '{}'", branch, current_branch),
-            Some(commit_hash.as_str()),
+            &commit_hash,
+            &provenance,
         )
         .await?;
 
         Ok(commit_hash)
     }
 
-    /// Get commit history for a branch (or HEAD).
-    pub async fn log(&self, branch: Option<&str>, limit: usize) -> Result<Vec<Commit>> {
+    /// Read the merge report recorded on a merge commit.
+    ///
+    /// Returns `None` for commits that are not merges or predate merge reports.
+    pub async fn merge_report(&self, hash: &str) -> Result<Option<MergeReport>> {
+        let commit = self
+            .get_commit(hash)
+            .await?
+            .ok_or_else(|| AgitError::ObjectNotFound {
+                hash: hash.to_string(),
+            })?;
+        commit
+            .metadata
+            .get(MERGE_REPORT_METADATA_KEY)
+            .map(|v| {
+                serde_json::from_value(v.clone())
+                    .map_err(|e| AgitError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Get commit history for a branch (or HEAD), newest first, with each
+    /// commit's hash.
+    ///
+    /// The order is topological: commits are listed by descending
+    /// generation, so every commit comes before its parents. Among commits
+    /// of the same generation the newest comes first and the hash breaks
+    /// ties, so the order is deterministic even when timestamps collide or
+    /// contradict the DAG. The walk stops once `limit` commits are listed.
+    pub async fn log(&self, branch: Option<&str>, limit: usize) -> Result<Vec<(Hash, Commit)>> {
+        // `log` only needs to resolve the start ref, so read a fresh copy
+        // from storage instead of mutating the local ref store.
+        let fresh = if self.options.auto_refresh {
+            Some(self.reconcile_refs(self.storage.list_refs().await?))
+        } else {
+            None
+        };
+        let refs = fresh.as_ref().unwrap_or(&self.refs);
         let start_hash = match branch {
-            Some(b) => self.refs.resolve_ref(b)?,
-            None => self.refs.resolve_ref("HEAD")?,
+            Some(b) => refs.resolve_ref(b)?,
+            None => refs.resolve_ref("HEAD")?,
         };
 
+        let mut generations = HashMap::new();
+        let mut visited = HashSet::from([start_hash.clone()]);
+        let mut loaded = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        if let Some(commit) = self.get_commit(start_hash.as_str()).await? {
+            let generation = self
+                .commit_generation(&start_hash, &commit, &mut generations)
+                .await?;
+            queue.push((generation, commit.timestamp, start_hash.clone()));
+            loaded.insert(start_hash, commit);
+        }
+
         let mut commits = Vec::new();
-        let mut queue = VecDeque::new();
-        let mut visited = HashSet::new();
+        while commits.len() < limit {
+            let Some((_, _, hash)) = queue.pop() else {
+                break;
+            };
+            let commit = loaded.remove(&hash).expect("queued commits are loaded");
+            for parent in &commit.parent_hashes {
+                if !visited.insert(parent.clone()) {
+                    continue;
+                }
+                if visited.len() > MAX_DEPTH {
+                    return Err(AgitError::DepthLimitExceeded(format!(
+                        "log visited more than {} commits",
+                        MAX_DEPTH
+                    )));
+                }
+                if let Some(parent_commit) = self.get_commit(parent.as_str()).await? {
+                    let generation = self
+                        .commit_generation(parent, &parent_commit, &mut generations)
+                        .await?;
+                    queue.push((generation, parent_commit.timestamp, parent.clone()));
+                    loaded.insert(parent.clone(), parent_commit);
+                }
+            }
+            commits.push((hash, commit));
+        }
+        Ok(commits)
+    }
 
-        queue.push_back(start_hash);
+    /// Start a newest-first walk of the history of `branch` (or HEAD),
+    /// yielding the commits that match `filter`. Advance it with
+    /// `next_commits`.
+    pub async fn commit_cursor(
+        &self,
+        branch: Option<&str>,
+        filter: CommitFilter,
+    ) -> Result<CommitCursor> {
+        let fresh = if self.options.auto_refresh {
+            Some(self.reconcile_refs(self.storage.list_refs().await?))
+        } else {
+            None
+        };
+        let refs = fresh.as_ref().unwrap_or(&self.refs);
+        let start_hash = refs.resolve_ref(branch.unwrap_or("HEAD"))?;
 
-        while let Some(hash) = queue.pop_front() {
-            if commits.len() >= limit || visited.contains(&hash) {
-                continue;
-            }
-            visited.insert(hash.clone());
+        let mut cursor = CommitCursor::new(filter);
+        cursor.visit(&start_hash);
+        if let Some(commit) = self.get_commit(start_hash.as_str()).await? {
+            cursor.push(start_hash, commit);
+        }
+        Ok(cursor)
+    }
 
-            if let Some(commit) = self.get_commit(hash.as_str()).await? {
-                for parent in &commit.parent_hashes {
-                    if !visited.contains(parent) {
-                        queue.push_back(parent.clone());
+    /// The next `n` matching commits of `cursor`'s walk, newest first,
+    /// with their hashes. Fewer are returned only once the walk is done.
+    pub async fn next_commits(
+        &self,
+        cursor: &mut CommitCursor,
+        n: usize,
+    ) -> Result<Vec<(Hash, Commit)>> {
+        let mut commits = Vec::new();
+        while commits.len() < n {
+            let Some((hash, commit)) = cursor.pop() else {
+                break;
+            };
+            if cursor
+                .filter()
+                .since
+                .is_some_and(|since| commit.timestamp < since)
+            {
+                cursor.finish();
+                break;
+            }
+            for parent in &commit.parent_hashes {
+                if cursor.visit(parent) {
+                    if let Some(parent_commit) = self.get_commit(parent.as_str()).await? {
+                        cursor.push(parent.clone(), parent_commit);
                     }
                 }
-                commits.push(commit);
+            }
+            if cursor.filter().matches(&commit) {
+                commits.push((hash, commit));
             }
         }
-
-        // Sort by timestamp descending
-        commits.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-        commits.truncate(limit);
         Ok(commits)
     }
 
@@ -343,31 +1311,66 @@ impl Repository {
         Ok(state)
     }
 
-    /// Find the merge base (lowest common ancestor) of two commits using BFS.
-    pub async fn find_merge_base(&self, h1: &str, h2: &str) -> Result<Hash> {
-        const MAX_DEPTH: usize = 10_000;
+    /// Restore only the given state paths (e.g. `["world_state", "browser"]`)
+    /// from `to_hash`, keeping everything else from HEAD. Paths absent at
+    /// `to_hash` are removed. Commits with `ActionType::Rollback`.
+    pub async fn revert_paths(&mut self, to_hash: &str, paths: &[Vec<String>]) -> Result<Hash> {
+        if paths.is_empty() || paths.iter().any(|p| p.is_empty()) {
+            return Err(AgitError::InvalidArgument(
+                "revert_paths requires at least one non-empty path".to_string(),
+            ));
+        }
+
+        let target = self.get_state(to_hash).await?.to_value();
+        let head = self.head()?;
+        let mut merged = self.get_state(head.as_str()).await?.to_value();
 
-        // BFS from both commits, find first intersection
-        let ancestors1 = self.collect_ancestors(h1, MAX_DEPTH).await?;
+        for path in paths {
+            match value_at_path(&target, path) {
+                Some(value) => set_value_at_path(&mut merged, path, value.clone())?,
+                None => {
+                    remove_value_at_path(&mut merged, path);
+                }
+            }
+        }
+        let state: AgentState = serde_json::from_value(merged)?;
+
+        let mut metadata = serde_json::Map::new();
+        metadata.insert("reverted_from".to_string(), Value::from(to_hash));
+        metadata.insert("reverted_paths".to_string(), serde_json::to_value(paths)?);
+        let message = format!(
+            "revert {} to {}",
+            paths
+                .iter()
+                .map(|p| p.join("."))
+                .collect::<Vec<_>>()
+                .join(", "),
+            &to_hash[..8.min(to_hash.len())]
+        );
+        self.commit_with_metadata(&state, &message, ActionType::Rollback, metadata)
+            .await
+    }
 
+    /// Check whether `ancestor` is reachable from `descendant` by following
+    /// parent links. A commit counts as its own ancestor.
+    ///
+    /// The BFS stops as soon as `ancestor` is found.
+    pub async fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        let target = Hash::parse(ancestor)?;
         let mut queue = VecDeque::new();
         let mut visited = HashSet::new();
-        let mut depth = 0usize;
-        queue.push_back(Hash::from(h2));
+        queue.push_back(Hash::parse(descendant)?);
 
         while let Some(hash) = queue.pop_front() {
-            if ancestors1.contains(&hash) {
-                return Ok(hash);
+            if hash == target {
+                return Ok(true);
             }
-            if visited.contains(&hash) {
+            if !visited.insert(hash.clone()) {
                 continue;
             }
-            visited.insert(hash.clone());
-
-            depth += 1;
-            if depth > MAX_DEPTH {
+            if visited.len() > MAX_DEPTH {
                 return Err(AgitError::DepthLimitExceeded(
-                    "merge base depth limit exceeded".to_string(),
+                    "ancestor query depth limit exceeded".to_string(),
                 ));
             }
 
@@ -380,8 +1383,273 @@ impl Repository {
             }
         }
 
-        // If no common ancestor, return h1 (initial commit scenario)
-        Ok(Hash::from(h1))
+        Ok(false)
+    }
+
+    /// Find the merge base of two commits: the lowest common ancestor, or
+    /// when there are several (as after a criss-cross merge), the one with
+    /// the highest generation, ties broken by the smallest hash.
+    ///
+    /// Fails with `NoCommonAncestor` for unrelated histories.
+    pub async fn find_merge_base(&self, h1: &str, h2: &str) -> Result<Hash> {
+        Ok(self.find_merge_bases(h1, h2).await?.remove(0))
+    }
+
+    /// All lowest common ancestors of two commits, i.e. common ancestors that
+    /// are not ancestors of another common ancestor, ordered by generation
+    /// (highest first) and then by hash.
+    ///
+    /// Both histories are walked together in descending generation order,
+    /// marking each commit with the sides that reach it. A commit reached
+    /// from both sides is a merge base, and everything below it is marked
+    /// stale; the walk ends once only stale commits remain.
+    pub async fn find_merge_bases(&self, h1: &str, h2: &str) -> Result<Vec<Hash>> {
+        const OURS: u8 = 1;
+        const THEIRS: u8 = 2;
+        const STALE: u8 = 4;
+
+        let (h1, h2) = (Hash::parse(h1)?, Hash::parse(h2)?);
+        if h1 == h2 {
+            return Ok(vec![h1]);
+        }
+
+        let mut generations = HashMap::new();
+        let mut flags: HashMap<Hash, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for (hash, side) in [(&h1, OURS), (&h2, THEIRS)] {
+            flags.insert(hash.clone(), side);
+            queue.push((self.generation(hash, &mut generations).await?, hash.clone()));
+        }
+
+        let mut bases = Vec::new();
+        let mut visited = HashSet::new();
+        while queue.iter().any(|(_, h)| flags[h] & STALE == 0) {
+            let Some((generation, hash)) = queue.pop() else {
+                break;
+            };
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+            if visited.len() > MAX_DEPTH {
+                return Err(AgitError::DepthLimitExceeded(
+                    "merge base depth limit exceeded".to_string(),
+                ));
+            }
+
+            // Parents always have a lower generation, so every child has
+            // already passed its flags down by the time a commit is popped
+            let mut mark = flags[&hash];
+            if mark & (OURS | THEIRS) == OURS | THEIRS && mark & STALE == 0 {
+                bases.push((generation, hash.clone()));
+                mark |= STALE;
+            }
+            if let Some(commit) = self.get_commit(hash.as_str()).await? {
+                for parent in commit.parent_hashes {
+                    let parent_mark = flags.entry(parent.clone()).or_default();
+                    if *parent_mark & mark == mark {
+                        continue;
+                    }
+                    *parent_mark |= mark;
+                    let generation = self.generation(&parent, &mut generations).await?;
+                    queue.push((generation, parent));
+                }
+            }
+        }
+
+        if bases.is_empty() {
+            return Err(AgitError::NoCommonAncestor {
+                ours: h1.0,
+                theirs: h2.0,
+            });
+        }
+        bases.sort_by(|(g1, a), (g2, b)| g2.cmp(g1).then_with(|| a.cmp(b)));
+        Ok(bases.into_iter().map(|(_, hash)| hash).collect())
+    }
+
+    /// Value at `path` (e.g. `["memory", "confidence"]`) for each commit,
+    /// newest first, keeping only commits that changed it relative to their
+    /// first parent. `None` means the path was absent in that commit.
+    ///
+    /// Commits are walked in generation order, so every commit comes
+    /// before its ancestors whatever their timestamps, and the walk stops
+    /// once `limit` changes are found. Changes are detected by comparing
+    /// subtree hashes in the cached Merkle trees; only the values of
+    /// commits that changed the path are read.
+    pub async fn path_history(
+        &self,
+        path: &[String],
+        branch: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(Hash, DateTime<Utc>, Option<Value>)>> {
+        let start = match branch {
+            Some(b) => self.refs.resolve_ref(b)?,
+            None => self.refs.resolve_ref("HEAD")?,
+        };
+
+        let mut generations = HashMap::new();
+        let mut visited = HashSet::from([start.clone()]);
+        let mut commits = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        if let Some(commit) = self.get_commit(start.as_str()).await? {
+            let generation = self
+                .commit_generation(&start, &commit, &mut generations)
+                .await?;
+            queue.push((generation, commit.timestamp, start.clone()));
+            commits.insert(start, commit);
+        }
+
+        // Hash of the subtree at `path`, per state blob
+        let mut subtrees: HashMap<Hash, Option<String>> = HashMap::new();
+        let mut history = Vec::new();
+
+        while history.len() < limit {
+            let Some((_, _, hash)) = queue.pop() else {
+                break;
+            };
+            let Some(commit) = commits.remove(&hash) else {
+                continue;
+            };
+            let mut first_parent = None;
+            for (i, parent) in commit.parent_hashes.iter().enumerate() {
+                let Some(parent_commit) = self.get_commit(parent.as_str()).await? else {
+                    continue;
+                };
+                if i == 0 {
+                    first_parent = Some(parent_commit.tree_hash.clone());
+                }
+                if !visited.insert(parent.clone()) {
+                    continue;
+                }
+                if visited.len() > MAX_DEPTH {
+                    return Err(AgitError::DepthLimitExceeded(format!(
+                        "path_history visited more than {} commits",
+                        MAX_DEPTH
+                    )));
+                }
+                let generation = self
+                    .commit_generation(parent, &parent_commit, &mut generations)
+                    .await?;
+                queue.push((generation, parent_commit.timestamp, parent.clone()));
+                commits.insert(parent.clone(), parent_commit);
+            }
+
+            // Identical state blob: the path cannot have changed
+            if first_parent.as_ref() == Some(&commit.tree_hash) {
+                continue;
+            }
+            let current = self
+                .path_subtree(&commit.tree_hash, path, &mut subtrees)
+                .await?;
+            let changed = match &first_parent {
+                Some(tree) => self.path_subtree(tree, path, &mut subtrees).await? != current,
+                None => current.is_some(),
+            };
+            if changed {
+                let value = match current {
+                    Some(_) => self.get_state_path(hash.as_str(), path).await?,
+                    None => None,
+                };
+                history.push((hash, commit.timestamp, value));
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Merkle hash of the subtree at `path` in a state blob, or `None` if
+    /// the path is absent.
+    ///
+    /// Walks the cached Merkle tree of the blob. The state is only loaded
+    /// when there is no cached tree, as in encrypted repositories, or the
+    /// path leads into an array or other leaf the tree does not split.
+    async fn path_subtree(
+        &self,
+        tree_hash: &Hash,
+        path: &[String],
+        cache: &mut HashMap<Hash, Option<String>>,
+    ) -> Result<Option<String>> {
+        if let Some(cached) = cache.get(tree_hash) {
+            return Ok(cached.clone());
+        }
+        let mut found = None;
+        if self.get_encryptor().is_none() {
+            let key = tree_key(tree_hash.as_str());
+            if let Some(data) = self.storage.get_object(&key).await? {
+                if let Ok(tree) = serde_json::from_slice::<MerkleNode>(&data) {
+                    found = merkle_subtree(&tree, path);
+                }
+            }
+        }
+        let subtree = match found {
+            Some(subtree) => subtree,
+            None => {
+                let state = self.load_state(tree_hash).await?.to_value();
+                value_at_path(&state, path).map(|v| MerkleNode::from_value(v).hash)
+            }
+        };
+        cache.insert(tree_hash.clone(), subtree.clone());
+        Ok(subtree)
+    }
+
+    /// Sum state cost over the history of `branch` (or HEAD), optionally
+    /// restricted to commits made at or after `since`.
+    pub async fn cost_summary(
+        &self,
+        branch: Option<&str>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<CostSummary> {
+        let start = match branch {
+            Some(b) => self.refs.resolve_ref(b)?,
+            None => self.refs.resolve_ref("HEAD")?,
+        };
+
+        let mut summary = CostSummary::default();
+        let mut costs: Vec<(Hash, f64)> = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(hash) = queue.pop_front() {
+            if !visited.insert(hash.clone()) {
+                continue;
+            }
+            if visited.len() > MAX_DEPTH {
+                return Err(AgitError::DepthLimitExceeded(format!(
+                    "cost_summary visited more than {} commits",
+                    MAX_DEPTH
+                )));
+            }
+            let Some(commit) = self.get_commit(hash.as_str()).await? else {
+                continue;
+            };
+            queue.extend(commit.parent_hashes.iter().cloned());
+            if since.is_some_and(|t| commit.timestamp < t) {
+                continue;
+            }
+
+            // Commits written before the cost was cached need a blob load
+            let cost = match commit
+                .metadata
+                .get(COST_METADATA_KEY)
+                .and_then(Value::as_f64)
+            {
+                Some(cost) => cost,
+                None => self.load_state(&commit.tree_hash).await?.cost,
+            };
+
+            summary.commits += 1;
+            summary.total += cost;
+            *summary
+                .by_action_type
+                .entry(commit.action_type.to_string())
+                .or_default() += cost;
+            *summary.by_author.entry(commit.author.clone()).or_default() += cost;
+            costs.push((hash, cost));
+        }
+
+        costs.sort_by(|a, b| b.1.total_cmp(&a.1));
+        costs.truncate(COST_TOP_N);
+        summary.top_commits = costs;
+        Ok(summary)
     }
 
     /// Get the agent state stored at a commit.
@@ -392,25 +1660,213 @@ impl Repository {
             .ok_or_else(|| AgitError::ObjectNotFound {
                 hash: hash.to_string(),
             })?;
+        self.load_state(&commit.tree_hash).await
+    }
 
-        let blob_data = self
-            .storage
-            .get_object(commit.tree_hash.as_str())
+    /// Whether the state stored at a commit is encrypted, whatever key (if
+    /// any) this repository was opened with.
+    pub async fn is_encrypted(&self, hash: &str) -> Result<bool> {
+        let commit = self
+            .get_commit(hash)
+            .await?
+            .ok_or_else(|| AgitError::ObjectNotFound {
+                hash: hash.to_string(),
+            })?;
+        Ok(has_encrypted_fields(
+            &self.load_stored_state(&commit.tree_hash).await?,
+        ))
+    }
+
+    /// Read the value at `path` in a commit's state, or `None` if the path
+    /// is absent.
+    ///
+    /// The state blob is fetched in growing byte ranges until the path is
+    /// located, so fields near the start of a large state are read without
+    /// loading the rest. Only chunks under the located value are fetched.
+    /// Encrypted repositories load the full state.
+    pub async fn get_state_path(&self, hash: &str, path: &[String]) -> Result<Option<Value>> {
+        let commit = self
+            .get_commit(hash)
+            .await?
+            .ok_or_else(|| AgitError::ObjectNotFound {
+                hash: hash.to_string(),
+            })?;
+
+        if self.get_encryptor().is_some() {
+            let value = self.load_state(&commit.tree_hash).await?.to_value();
+            return Ok(value_at_path(&value, path).cloned());
+        }
+
+        let blob_hash = commit.tree_hash.as_str();
+        let mut buf = Vec::new();
+        let mut window = STATE_PATH_WINDOW;
+        loop {
+            let data = self
+                .storage
+                .get_object_range(blob_hash, buf.len(), window)
+                .await?
+                .ok_or_else(|| AgitError::ObjectNotFound {
+                    hash: blob_hash.to_string(),
+                })?;
+            let complete = data.len() < window;
+            buf.extend_from_slice(&data);
+
+            let (mut value, rest) = match scan_path(&buf, path) {
+                Some(PathScan::Absent) => return Ok(None),
+                Some(PathScan::Found { span, depth }) => {
+                    (serde_json::from_slice::<Value>(&buf[span])?, &path[depth..])
+                }
+                // Unexpected layout; parse the whole blob
+                None if complete => (serde_json::from_slice::<Value>(&buf)?, path),
+                None => {
+                    window *= 2;
+                    continue;
+                }
+            };
+            // An encrypted field can only be decrypted whole
+            let field = match path.len() - rest.len() {
+                0 => path.first().and_then(|key| value.get(key)),
+                1 => Some(&value),
+                _ => None,
+            };
+            if field.is_some_and(is_encrypted_value) {
+                return Err(AgitError::EncryptedState {
+                    hash: blob_hash.to_string(),
+                });
+            }
+            chunking::reassemble(self.storage.as_ref(), &mut value).await?;
+            return Ok(value_at_path(&value, rest).cloned());
+        }
+    }
+
+    /// Build a Merkle inclusion proof for the value at `path` in a commit's
+    /// state. Returns the value and its proof, or `None` if the path is
+    /// absent. The proof verifies against the root recorded under
+    /// `MERKLE_ROOT_METADATA_KEY` when the commit embeds one.
+    pub async fn merkle_proof(
+        &self,
+        hash: &str,
+        path: &[String],
+    ) -> Result<Option<(Value, MerkleProof)>> {
+        let (tree, value) = self.state_with_tree(hash).await?;
+        Ok(tree
+            .prove(path)
+            .and_then(|proof| Some((value_at_path(&value, path)?.clone(), proof))))
+    }
+
+    /// Load a commit's state value and its Merkle tree, using the cached
+    /// tree object when present.
+    async fn state_with_tree(&self, hash: &str) -> Result<(MerkleNode, Value)> {
+        let commit = self
+            .get_commit(hash)
             .await?
             .ok_or_else(|| AgitError::ObjectNotFound {
-                hash: commit.tree_hash.to_string(),
+                hash: hash.to_string(),
             })?;
+        let value = self.load_state(&commit.tree_hash).await?.to_value();
 
-        let state: AgentState = serde_json::from_slice(&blob_data)?;
+        if self.get_encryptor().is_none() {
+            let key = tree_key(commit.tree_hash.as_str());
+            if let Some(data) = self.storage.get_object(&key).await? {
+                if let Ok(tree) = serde_json::from_slice::<MerkleNode>(&data) {
+                    return Ok((tree, value));
+                }
+            }
+        }
+        Ok((MerkleNode::from_value(&value), value))
+    }
+
+    /// Store a state value as a blob, chunking oversized values if enabled,
+    /// along with its Merkle tree. `serialized` may carry the value's
+    /// canonical serialization if already computed. Returns the blob hash.
+    async fn store_state(&self, state_value: Value, serialized: Option<Vec<u8>>) -> Result<Hash> {
+        let tree_hash = self.store_blob(state_value.clone(), serialized).await?;
+        // The tree describes the reassembled state that diffs operate on
+        self.store_tree(&tree_hash, &state_value).await?;
+        Ok(tree_hash)
+    }
+
+    /// Store a state value as a blob, chunking oversized values if enabled,
+    /// without its Merkle tree. Returns the blob hash.
+    async fn store_blob(&self, state_value: Value, serialized: Option<Vec<u8>>) -> Result<Hash> {
+        let mut stored = state_value;
+        let mut serialized = serialized;
+        if let Some(options) = &self.options.chunking {
+            let chunks = chunking::chunk_value(&mut stored, options);
+            if !chunks.is_empty() {
+                serialized = None;
+            }
+            for (hash, chunk) in chunks {
+                self.storage
+                    .put_object(&hash, ObjectType::Blob, &chunk)
+                    .await?;
+            }
+        }
+
+        let data = serialized.unwrap_or_else(|| Blob::new(stored).serialize());
+        let tree_hash = compute_hash(ObjectType::Blob, &data);
+        self.storage
+            .put_object(tree_hash.as_str(), ObjectType::Blob, &data)
+            .await?;
+        Ok(tree_hash)
+    }
 
-        // Optional decryption
+    /// Cache the Merkle tree of a state blob so diffs need not rebuild it.
+    /// Skipped for encrypted repositories: per-field hashes of the plaintext
+    /// would be stored unencrypted.
+    async fn store_tree(&self, blob_hash: &Hash, value: &Value) -> Result<()> {
+        if self.get_encryptor().is_some() {
+            return Ok(());
+        }
+        let tree = MerkleNode::from_value(value);
+        self.storage
+            .put_object(
+                &tree_key(blob_hash.as_str()),
+                ObjectType::Tree,
+                &serde_json::to_vec(&tree)?,
+            )
+            .await
+    }
+
+    /// Load and decrypt the state blob with the given tree hash. Without an
+    /// encryptor, encrypted states fail with `EncryptedState` rather than
+    /// being returned as ciphertext.
+    async fn load_state(&self, tree_hash: &Hash) -> Result<AgentState> {
+        let state = self.load_stored_state(tree_hash).await?;
         match self.get_encryptor() {
             #[cfg(feature = "encryption")]
             Some(enc) => enc.decrypt_state(&state),
+            _ if has_encrypted_fields(&state) => Err(AgitError::EncryptedState {
+                hash: tree_hash.to_string(),
+            }),
             _ => Ok(state),
         }
     }
 
+    /// Load the state blob with the given tree hash as stored, without
+    /// decrypting it.
+    async fn load_stored_state(&self, tree_hash: &Hash) -> Result<AgentState> {
+        let blob_data = match self.cache.get_blob(tree_hash.as_str()) {
+            Some(data) => data,
+            None => {
+                let data = Arc::new(
+                    self.storage
+                        .get_object(tree_hash.as_str())
+                        .await?
+                        .ok_or_else(|| AgitError::ObjectNotFound {
+                            hash: tree_hash.to_string(),
+                        })?,
+                );
+                self.cache.insert_blob(tree_hash.as_str(), data.clone());
+                data
+            }
+        };
+
+        let mut value: Value = serde_json::from_slice(&blob_data)?;
+        chunking::reassemble(self.storage.as_ref(), &mut value).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+
     /// Helper to get encryptor if feature is enabled.
     #[cfg(feature = "encryption")]
     fn get_encryptor(&self) -> Option<&StateEncryptor> {
@@ -437,312 +1893,4393 @@ impl Repository {
         self.refs.list_branches()
     }
 
-    /// Delete a branch.
-    pub async fn delete_branch(&mut self, name: &str) -> Result<()> {
-        self.refs.delete_branch(name)?;
-        self.storage.delete_ref(name).await?;
-        Ok(())
+    /// List remote-tracking refs, keyed by `remotes/<remote>/<branch>`.
+    pub fn tracking_refs(&self) -> &HashMap<String, Hash> {
+        self.refs.list_tracking_refs()
     }
 
-    /// Query audit logs.
-    pub async fn audit_log(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
-        self.storage.query_logs(filter).await
-    }
+    /// Delete a branch. Deleting the checked-out branch fails with
+    /// `BranchCheckedOut` unless `force` is set, in which case HEAD is first
+    /// detached at the branch's tip.
+    pub async fn delete_branch(&mut self, name: &str, force: bool) -> Result<()> {
+        if let Err(e) = self.protection.check_delete(name) {
+            return Err(self
+                .log_failure(LogLevel::Warn, "delete_branch_denied", e)
+                .await);
+        }
+        // Apply to a copy, so memory only changes once storage has
+        let mut refs = self.refs.clone();
+        let tip = refs.list_branches().get(name).cloned();
+        let detach = force && name != "main" && refs.is_checked_out(name);
+        if let (true, Some(tip)) = (detach, &tip) {
+            refs.set_head(tip.as_str(), true);
+        }
+        refs.delete_branch(name)?;
+        let tip = tip.expect("delete_branch checked the branch exists");
 
-    /// Get the state hash for content addressing.
+        let mut updates = Vec::new();
+        if detach {
+            updates.push(RefUpdate::set("HEAD", tip.as_str()));
+        }
+        updates.push(RefUpdate::delete(name).expecting(tip.as_str()));
+        self.storage.update_refs(&updates).await?;
+        self.refs = refs;
+        Ok(())
+    }
+
+    /// Move `branch` to point at `target` (a branch name or commit hash)
+    /// without creating a commit. Subject to branch protection; a protected
+    /// branch that is checked out cannot be reset, since that would move
+    /// the state under HEAD, so check out another branch first.
+    pub async fn reset(&mut self, branch: &str, target: &str) -> Result<Hash> {
+        if let Err(e) = self.protection.check_reset(branch) {
+            return Err(self.log_failure(LogLevel::Warn, "reset_denied", e).await);
+        }
+        if self.refs.is_checked_out(branch) && self.protection.rules_for(branch).next().is_some() {
+            let e = AgitError::BranchCheckedOut {
+                name: branch.to_string(),
+            };
+            return Err(self.log_failure(LogLevel::Warn, "reset_denied", e).await);
+        }
+        let hash = self.resolve(target).await?;
+        if self.get_commit(hash.as_str()).await?.is_none() {
+            return Err(AgitError::ObjectNotFound {
+                hash: hash.to_string(),
+            });
+        }
+        let old = self
+            .refs
+            .list_branches()
+            .get(branch)
+            .cloned()
+            .ok_or_else(|| AgitError::BranchNotFound {
+                name: branch.to_string(),
+            })?;
+        self.storage
+            .update_refs(&[RefUpdate::set(branch, hash.as_str()).expecting(old.as_str())])
+            .await?;
+        self.refs.update_branch(branch, hash.clone())?;
+        self.log_action(
+            "reset",
+            &format!("reset '{}' to {}", branch, hash.short()),
+            Some(hash.as_str()),
+        )
+        .await?;
+        Ok(hash)
+    }
+
+    /// Rename a branch, carrying HEAD along if it is attached to it.
+    /// Renaming `main` requires `force`, and a branch protected from
+    /// deletion cannot be renamed away.
+    ///
+    /// The new ref, the removal of the old one and HEAD are written as one
+    /// batch, which fails if either name changed in storage meanwhile.
+    pub async fn rename_branch(&mut self, old: &str, new: &str, force: bool) -> Result<()> {
+        if let Err(e) = self.protection.check_delete(old) {
+            return Err(self
+                .log_failure(LogLevel::Warn, "rename_branch_denied", e)
+                .await);
+        }
+        // Apply to a copy, so memory only changes once storage has
+        let mut refs = self.refs.clone();
+        let attached = refs.is_checked_out(old);
+        refs.rename_branch(old, new, force)?;
+
+        let hash = refs.resolve_ref(new)?;
+        let mut updates = vec![
+            RefUpdate::set(new, hash.as_str()).expecting_absent(),
+            RefUpdate::delete(old).expecting(hash.as_str()),
+        ];
+        if attached {
+            updates.push(RefUpdate::set("HEAD", format!("ref:{}", new)));
+        }
+        self.storage.update_refs(&updates).await?;
+        self.refs = refs;
+
+        self.log_action(
+            "rename_branch",
+            &format!("renamed branch '{}' to '{}'", old, new),
+            Some(hash.as_str()),
+        )
+        .await
+    }
+
+    /// Configured remotes, by name, with their storage URLs.
+    pub async fn remotes(&self) -> Result<BTreeMap<String, String>> {
+        remote::load_remotes(&*self.storage).await
+    }
+
+    /// Add or replace the remote `name`, persisting it under the
+    /// `config/remotes` ref. `url` is any location `remote::connect` accepts.
+    pub async fn add_remote(&mut self, name: &str, url: &str) -> Result<()> {
+        remote::check_remote_name(name)?;
+        let mut remotes = self.remotes().await?;
+        remotes.insert(name.to_string(), url.to_string());
+        remote::save_remotes(&*self.storage, &remotes).await?;
+        self.log_action(
+            "add_remote",
+            &format!("added remote '{}' at {}", name, url),
+            None,
+        )
+        .await
+    }
+
+    /// Remove the remote `name` along with its tracking refs.
+    pub async fn remove_remote(&mut self, name: &str) -> Result<()> {
+        let mut remotes = self.remotes().await?;
+        if remotes.remove(name).is_none() {
+            return Err(AgitError::InvalidArgument(format!(
+                "no remote named '{}'",
+                name
+            )));
+        }
+        remote::save_remotes(&*self.storage, &remotes).await?;
+        let prefix = remote::tracking_ref(name, "");
+        let updates: Vec<RefUpdate> = self
+            .refs
+            .list_tracking_refs()
+            .keys()
+            .filter(|r| r.starts_with(&prefix))
+            .map(RefUpdate::delete)
+            .collect();
+        if !updates.is_empty() {
+            self.storage.update_refs(&updates).await?;
+        }
+        self.refresh_refs().await?;
+        self.log_action("remove_remote", &format!("removed remote '{}'", name), None)
+            .await
+    }
+
+    /// Fetch from the configured remote `name`: see `fetch_from`.
+    pub async fn fetch(&mut self, name: &str) -> Result<FetchReport> {
+        let url = self
+            .remotes()
+            .await?
+            .remove(name)
+            .ok_or_else(|| AgitError::InvalidArgument(format!("no remote named '{}'", name)))?;
+        let source = remote::connect(&url).await?;
+        let report = self.fetch_from(name, &*source).await;
+        source.close().await?;
+        report
+    }
+
+    /// Update the `remotes/<name>/*` tracking refs from the branches of
+    /// `source`, copying the commits they reach but none of the state
+    /// blobs. `log`, `ahead_behind` and `is_ancestor` work on the fetched
+    /// history; reading a fetched commit's state fails until its blob is
+    /// transferred.
+    pub async fn fetch_from(
+        &mut self,
+        name: &str,
+        source: &dyn StorageBackend,
+    ) -> Result<FetchReport> {
+        remote::check_remote_name(name)?;
+        let report = remote::fetch(&*self.storage, name, source).await?;
+        self.refresh_refs().await?;
+        self.log_action(
+            "fetch",
+            &format!(
+                "fetched {} commits from '{}' ({} refs updated, {} pruned)",
+                report.commits_fetched,
+                name,
+                report.updated.len(),
+                report.pruned.len()
+            ),
+            None,
+        )
+        .await?;
+        Ok(report)
+    }
+
+    /// Count the commits reachable from `branch` but not `upstream` (ahead)
+    /// and from `upstream` but not `branch` (behind). Either side may be a
+    /// branch, a tracking ref such as `remotes/origin/main`, or a commit hash.
+    pub async fn ahead_behind(&self, branch: &str, upstream: &str) -> Result<(usize, usize)> {
+        let ours = self.resolve(branch).await?;
+        let theirs = self.resolve(upstream).await?;
+        let ours = self.collect_ancestors(ours.as_str(), MAX_DEPTH).await?;
+        let theirs = self.collect_ancestors(theirs.as_str(), MAX_DEPTH).await?;
+        Ok((
+            ours.difference(&theirs).count(),
+            theirs.difference(&ours).count(),
+        ))
+    }
+
+    /// Query audit logs. `filter.message_query` searches entry messages and
+    /// details.
+    ///
+    /// Entries encrypted at rest are decrypted with the repository's key.
+    /// Without it, or under a key since rotated away, their message and
+    /// details come back as ciphertext; `message_query` cannot match inside
+    /// them either way.
+    pub async fn audit_log(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        let entries = self.storage.query_logs(filter).await?;
+        Ok(self.open_log_entries(entries))
+    }
+
+    /// Count the audit log entries matching `filter`, ignoring its `limit`
+    /// and `offset`.
+    pub async fn count_audit_log(&self, filter: &LogFilter) -> Result<usize> {
+        self.storage.count_logs(filter).await
+    }
+
+    /// Record a warning in the audit log, chained like every other entry.
+    /// An object in `details` is merged into the entry's details.
+    pub async fn log_warning(&self, message: &str, details: Option<Value>) -> Result<()> {
+        self.log_entry(
+            LogLevel::Warn,
+            "warning",
+            message,
+            None,
+            details.unwrap_or(Value::Null),
+        )
+        .await
+    }
+
+    /// Record an error in the audit log, chained like every other entry.
+    /// An object in `details` is merged into the entry's details.
+    pub async fn log_error(&self, message: &str, details: Option<Value>) -> Result<()> {
+        self.log_entry(
+            LogLevel::Error,
+            "error",
+            message,
+            None,
+            details.unwrap_or(Value::Null),
+        )
+        .await
+    }
+
+    /// New audit log entries matching `cursor`'s filter since its last
+    /// poll, oldest first.
+    pub async fn poll_logs(&self, cursor: &mut LogCursor) -> Result<Vec<LogEntry>> {
+        let entries = cursor.poll(self.storage.as_ref()).await?;
+        Ok(self.open_log_entries(entries))
+    }
+
+    /// Follow the audit log like `tail -f`, yielding each entry matching
+    /// `filter` once as it appears. Entries before the watch starts are
+    /// skipped unless `filter.since` asks for them.
+    ///
+    /// Storage is re-queried every `poll_interval`, or on each log signal
+    /// from backends that have them (Postgres `LISTEN`). Failed queries are
+    /// retried at the next interval. The stream never ends; drop it to stop
+    /// watching.
+    pub fn watch_logs(
+        &self,
+        filter: LogFilter,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = LogEntry> + '_ {
+        struct Watch {
+            cursor: LogCursor,
+            pending: VecDeque<LogEntry>,
+            signals: Option<BoxStream<'static, ()>>,
+            started: bool,
+        }
+
+        let watch = Watch {
+            cursor: LogCursor::new(filter),
+            pending: VecDeque::new(),
+            signals: None,
+            started: false,
+        };
+        stream::unfold(watch, move |mut watch| async move {
+            loop {
+                if let Some(entry) = watch.pending.pop_front() {
+                    return Some((entry, watch));
+                }
+                if !watch.started {
+                    watch.started = true;
+                    // Backends without signals, or failing to listen, are polled
+                    watch.signals = self.storage.log_signals().await.ok().flatten();
+                } else if let Some(signals) = watch.signals.as_mut() {
+                    tokio::select! {
+                        signal = signals.next() => {
+                            if signal.is_none() {
+                                watch.signals = None;
+                            }
+                        }
+                        _ = tokio::time::sleep(poll_interval * SIGNALLED_POLL_FACTOR) => {}
+                    }
+                } else {
+                    tokio::time::sleep(poll_interval).await;
+                }
+
+                match watch.cursor.poll(self.storage.as_ref()).await {
+                    Ok(entries) => watch.pending.extend(self.open_log_entries(entries)),
+                    Err(_e) => {
+                        #[cfg(feature = "observability")]
+                        tracing::warn!(error = %_e, "failed to poll audit log");
+                    }
+                }
+            }
+        })
+    }
+
+    /// Verify the audit log hash chain for one agent, or for every agent.
+    ///
+    /// Chain hashes cover plaintext messages, so entries encrypted at rest
+    /// verify only with the repository's key set; without it the first of
+    /// them is reported as the break.
+    pub async fn verify_audit_chain(&self, agent_id: Option<&str>) -> Result<AuditVerification> {
+        let filter = LogFilter {
+            agent_id: agent_id.map(|s| s.to_string()),
+            ..Default::default()
+        };
+        let mut entries = self.audit_log(&filter).await?;
+        // Storage returns newest first
+        entries.reverse();
+        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        let anchors = match agent_id {
+            None => audit::retention_anchors(&entries),
+            // Retention summaries may have been written by another agent
+            Some(_) => {
+                let filter = LogFilter {
+                    action: Some(audit::RETENTION_ACTION.to_string()),
+                    ..Default::default()
+                };
+                audit::retention_anchors(&self.audit_log(&filter).await?)
+            }
+        };
+        Ok(audit::verify_chain_anchored(&entries, &anchors))
+    }
+
+    /// Truncate branch histories to what `policy` retains and delete the
+    /// objects that leaves unreachable.
+    ///
+    /// On each branch outside `policy.keep_branches` and not protected by a
+    /// branch protection rule, the retained commits (see
+    /// [`retention::plan_retention`]) are rewritten onto a new root commit
+    /// that keeps the oldest retained state but has no parents, and the
+    /// branch moves to the rewritten tip. Rewritten merge commits keep only
+    /// their first parent. History still reachable from other branches
+    /// survives the sweep. A `retention` audit entry records the counts.
+    ///
+    /// Holds the storage's maintenance lock while running.
+    pub async fn apply_retention(&mut self, policy: &RetentionPolicy) -> Result<RetentionResult> {
+        let _lock = self
+            .storage
+            .acquire_lock(MAINTENANCE_LOCK, MAINTENANCE_LOCK_TIMEOUT)
+            .await?;
+        let plans = retention::plan_retention(&*self.storage, &self.refs, policy).await?;
+
+        let mut result = RetentionResult::default();
+        for plan in plans {
+            if self.protection.check_reset(&plan.branch).is_err() {
+                continue;
+            }
+            result.commits_retained += plan.retained.len();
+            if plan.expired == 0 {
+                continue;
+            }
+
+            // Rebuild the retained chain oldest first on a fresh root
+            let mut parent: Option<Hash> = None;
+            for (_, commit) in plan.retained.iter().rev() {
+                let mut commit = commit.clone();
+                commit.parent_hashes = parent.into_iter().collect();
+                commit.metadata.remove(GENERATION_METADATA_KEY);
+                self.sign(&mut commit);
+                let hash = commit.hash();
+                self.storage
+                    .put_object(
+                        hash.as_str(),
+                        ObjectType::Commit,
+                        &serde_json::to_vec(&commit)?,
+                    )
+                    .await?;
+                parent = Some(hash);
+            }
+            let Some(new_tip) = parent else {
+                continue;
+            };
+
+            self.storage
+                .update_refs(&[
+                    RefUpdate::set(&plan.branch, new_tip.as_str()).expecting(plan.tip.as_str())
+                ])
+                .await?;
+            self.refs.update_branch(&plan.branch, new_tip)?;
+            result.commits_expired += plan.expired;
+            result.branches_truncated.push(plan.branch);
+        }
+
+        if !result.branches_truncated.is_empty() {
+            result.objects_removed = self
+                .gc_locked(
+                    &gc::GcOptions::default(),
+                    &mut |_| {},
+                    &CancellationToken::new(),
+                )
+                .await?
+                .objects_removed;
+            self.log_entry(
+                LogLevel::Info,
+                "retention",
+                &format!(
+                    "retention truncated {} branches: {} commits expired, {} objects removed",
+                    result.branches_truncated.len(),
+                    result.commits_expired,
+                    result.objects_removed
+                ),
+                None,
+                serde_json::json!({
+                    "commits_expired": result.commits_expired,
+                    "commits_retained": result.commits_retained,
+                    "branches_truncated": result.branches_truncated,
+                    "objects_removed": result.objects_removed,
+                }),
+            )
+            .await?;
+        }
+        Ok(result)
+    }
+
+    /// Delete audit log entries older than `policy.max_log_age` and beyond
+    /// each agent's newest `policy.max_log_entries`.
+    ///
+    /// When anything was deleted, a `log_retention` entry records the counts
+    /// and the chain anchors that keep `verify_audit_chain` passing.
+    pub async fn apply_log_retention(
+        &self,
+        policy: &RetentionPolicy,
+    ) -> Result<LogRetentionResult> {
+        let mut result = LogRetentionResult::default();
+        if let Some(cutoff) = policy
+            .max_log_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+        {
+            let filter = LogDeleteFilter {
+                before: Some(cutoff.to_rfc3339()),
+                ..Default::default()
+            };
+            result.expired_by_age = self.storage.delete_logs(&filter).await?;
+        }
+        if let Some(max) = policy.max_log_entries {
+            let filter = LogDeleteFilter {
+                keep_last: Some(max),
+                ..Default::default()
+            };
+            result.over_limit = self.storage.delete_logs(&filter).await?;
+        }
+        if result.total() == 0 {
+            return Ok(result);
+        }
+
+        // Anchor each agent's chain at its oldest surviving entry
+        let mut entries = self.storage.query_logs(&LogFilter::default()).await?;
+        entries.reverse();
+        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        let mut seen = HashSet::new();
+        let mut anchors = serde_json::Map::new();
+        for entry in &entries {
+            if !seen.insert(entry.agent_id.as_str()) {
+                continue;
+            }
+            if let Some(prev) = entry
+                .details
+                .as_ref()
+                .and_then(|d| d.get(audit::PREV_HASH_KEY))
+                .filter(|v| v.is_string())
+            {
+                anchors.insert(entry.agent_id.clone(), prev.clone());
+            }
+        }
+
+        self.log_entry(
+            LogLevel::Info,
+            audit::RETENTION_ACTION,
+            &format!(
+                "log retention deleted {} entries ({} by age, {} over limit)",
+                result.total(),
+                result.expired_by_age,
+                result.over_limit
+            ),
+            None,
+            serde_json::json!({
+                "expired_by_age": result.expired_by_age,
+                "over_limit": result.over_limit,
+                audit::RETENTION_ANCHORS_KEY: anchors,
+            }),
+        )
+        .await?;
+        Ok(result)
+    }
+
+    /// Get the state hash for content addressing.
     pub fn compute_state_hash(state: &AgentState) -> Hash {
         compute_state_hash(&state.to_value())
     }
 
     /// Run garbage collection to remove unreachable objects.
+    ///
+    /// Holds the storage's maintenance lock while running.
     pub async fn gc(&self, keep_last_n: usize) -> Result<gc::GcResult> {
-        gc::gc(&*self.storage, &self.refs, keep_last_n).await
+        let options = gc::GcOptions {
+            keep_last_n,
+            ..Default::default()
+        };
+        self.gc_with_options(&options).await
+    }
+
+    /// Run garbage collection, then compact storage if `options` asks for it
+    /// and enough objects were removed. A dry run deletes and compacts
+    /// nothing, only reporting what would be removed.
+    pub async fn gc_with_options(&self, options: &gc::GcOptions) -> Result<gc::GcResult> {
+        self.gc_with_progress(options, |_| {}, &CancellationToken::new())
+            .await
+    }
+
+    /// Like [`Repository::gc_with_options`], reporting progress to
+    /// `on_progress` and stopping once `cancel` fires. A cancelled run
+    /// returns a partial result with `cancelled` set; only whole batches
+    /// have been deleted and compaction is skipped.
+    pub async fn gc_with_progress(
+        &self,
+        options: &gc::GcOptions,
+        mut on_progress: impl FnMut(gc::GcProgress) + Send,
+        cancel: &CancellationToken,
+    ) -> Result<gc::GcResult> {
+        let _lock = self
+            .storage
+            .acquire_lock(MAINTENANCE_LOCK, MAINTENANCE_LOCK_TIMEOUT)
+            .await?;
+        self.gc_locked(options, &mut on_progress, cancel).await
+    }
+
+    /// Garbage collection body; the caller holds `MAINTENANCE_LOCK`.
+    async fn gc_locked(
+        &self,
+        options: &gc::GcOptions,
+        on_progress: &mut (dyn FnMut(gc::GcProgress) + Send),
+        cancel: &CancellationToken,
+    ) -> Result<gc::GcResult> {
+        let mut result =
+            gc::gc_with_progress(&*self.storage, &self.refs, options, on_progress, cancel).await?;
+        self.cache.remove(&result.removed);
+        // A failed batch may still have been partially deleted
+        for failure in &result.failures {
+            self.cache.remove(&failure.hashes);
+        }
+        if options.compact
+            && !result.cancelled
+            && result.objects_removed >= options.compact_threshold.max(1)
+        {
+            result.compaction = Some(self.storage.compact().await?);
+        }
+        Ok(result)
+    }
+
+    /// Hit and miss counts of the in-memory object cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Object count and stored bytes, overall and per object type.
+    pub async fn storage_stats(&self) -> Result<StorageStats> {
+        self.storage.storage_stats().await
+    }
+
+    /// Write a consistent copy of the repository's storage to the file at
+    /// `path`. Refs are written through on every change, so the copy holds
+    /// every branch as stored, including other writers' updates. See
+    /// `StorageBackend::backup_to`.
+    pub async fn snapshot(&self, path: &str) -> Result<BackupReport> {
+        self.storage.backup_to(path).await
+    }
+
+    /// Check object integrity and DAG consistency without modifying storage.
+    pub async fn fsck(&self) -> Result<FsckReport> {
+        self.fsck_with_options(FsckOptions::default()).await
     }
 
-    /// Squash a range of commits into a single commit.
+    /// Check object integrity, optionally repairing missing objects.
+    pub async fn fsck_with_options(&self, options: FsckOptions) -> Result<FsckReport> {
+        fsck::fsck(&*self.storage, &self.refs, &options).await
+    }
+
+    /// Export branch history as a Git repository at `path`, creating a bare
+    /// one if needed; see `git_export`.
+    ///
+    /// Commits an earlier export already wrote are skipped, along with
+    /// their history, so re-running only writes new commits.
+    #[cfg(feature = "git-export")]
+    pub async fn export_git(
+        &self,
+        path: &std::path::Path,
+        options: &GitExportOptions,
+    ) -> Result<GitExportReport> {
+        let mut tips: Vec<(String, Hash)> = self
+            .refs
+            .list_branches()
+            .iter()
+            .filter(|(name, _)| options.branches.is_empty() || options.branches.contains(name))
+            .map(|(name, hash)| (name.clone(), hash.clone()))
+            .collect();
+        tips.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Some(name) = options
+            .branches
+            .iter()
+            .find(|name| !tips.iter().any(|(tip, _)| tip == *name))
+        {
+            return Err(AgitError::BranchNotFound { name: name.clone() });
+        }
+
+        let mut exporter = GitExporter::open(path, options)?;
+        let mut report = GitExportReport::default();
+        // Depth-first, writing each commit once all its parents are written
+        let mut pending: HashMap<Hash, Commit> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut stack: Vec<(Hash, bool)> = tips
+            .iter()
+            .rev()
+            .map(|(_, hash)| (hash.clone(), false))
+            .collect();
+        while let Some((hash, parents_done)) = stack.pop() {
+            if parents_done {
+                let commit = pending.remove(&hash).expect("pending until written");
+                let state = self.load_state(&commit.tree_hash).await?;
+                exporter.write_commit(&hash, &commit, &state)?;
+                report.commits_exported += 1;
+                continue;
+            }
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            if exporter.is_exported(&hash) {
+                report.commits_skipped += 1;
+                continue;
+            }
+            let commit =
+                self.get_commit(hash.as_str())
+                    .await?
+                    .ok_or_else(|| AgitError::ObjectNotFound {
+                        hash: hash.to_string(),
+                    })?;
+            stack.push((hash.clone(), true));
+            stack.extend(
+                commit
+                    .parent_hashes
+                    .iter()
+                    .rev()
+                    .map(|p| (p.clone(), false)),
+            );
+            pending.insert(hash, commit);
+        }
+        exporter.finish(&tips, self.refs.current_branch(), report)
+    }
+
+    /// Squash a range of commits into a single commit, refusing ranges
+    /// that contain a merge.
+    ///
+    /// Holds the storage's maintenance lock while running.
     pub async fn squash(
         &mut self,
         branch: &str,
         from_hash: &str,
         to_hash: &str,
     ) -> Result<gc::SquashResult> {
-        gc::squash(
+        self.squash_with_options(branch, from_hash, to_hash, &gc::SquashOptions::default())
+            .await
+    }
+
+    /// Squash a range of commits into a single commit. Commits on the
+    /// branch after `to_hash` are replayed on top of it.
+    ///
+    /// Holds the storage's maintenance lock while running.
+    pub async fn squash_with_options(
+        &mut self,
+        branch: &str,
+        from_hash: &str,
+        to_hash: &str,
+        options: &gc::SquashOptions,
+    ) -> Result<gc::SquashResult> {
+        if let Err(e) = self.protection.check_reset(branch) {
+            return Err(self.log_failure(LogLevel::Warn, "squash_denied", e).await);
+        }
+        let _lock = self
+            .storage
+            .acquire_lock(MAINTENANCE_LOCK, MAINTENANCE_LOCK_TIMEOUT)
+            .await?;
+        let result = gc::squash(
             &*self.storage,
             &mut self.refs,
             &self.agent_id,
+            self.signing_key.as_deref(),
             branch,
             from_hash,
             to_hash,
+            options,
         )
-        .await
+        .await?;
+
+        let provenance = self.commit_provenance(result.new_hash.as_str()).await?;
+        self.log_entry(
+            LogLevel::Info,
+            "squash",
+            &format!(
+                "squashed {} commits on '{}' into {}",
+                result.commits_squashed, branch, result.new_hash
+            ),
+            Some((result.new_hash.as_str(), &provenance)),
+            serde_json::json!({
+                "branch": branch,
+                "squashed_hashes": result.squashed_hashes,
+                "aggregated_cost": result.aggregated_cost,
+                "new_tip": result.new_tip,
+            }),
+        )
+        .await?;
+        Ok(result)
     }
 
-    // --- Private helpers ---
+    /// Re-encrypt the repository's history from `old_key` to `new_key`.
+    ///
+    /// Every state reachable from a branch or a detached HEAD whose fields
+    /// are encrypted is decrypted with `old_key` and re-encrypted with
+    /// `new_key`; unencrypted states are left alone. Commits are rewritten
+    /// onto the new blobs, which changes their hashes and those of all
+    /// their descendants, and refs move to the rewritten commits in one
+    /// atomic update. Protected branches are rewritten too, since leaving
+    /// them under a compromised key would defeat the rotation. A
+    /// `rotate_encryption_key` audit entry records the old-to-new commit
+    /// hash mapping, and the repository switches to `new_key`. Audit
+    /// entries already encrypted stay under `old_key`.
+    ///
+    /// Nothing is written unless every encrypted state opens under one of
+    /// the two keys. States already under `new_key` are skipped, and blobs
+    /// re-encrypted by an interrupted run are checkpointed under
+    /// `ROTATION_STATE_REF`, so running the rotation again resumes it.
+    ///
+    /// Holds the storage's maintenance lock while running.
+    #[cfg(feature = "encryption")]
+    pub async fn rotate_encryption_key(
+        &mut self,
+        old_key: &str,
+        new_key: &str,
+        mut on_progress: impl FnMut(rotation::RotationProgress) + Send,
+    ) -> Result<rotation::RotationResult> {
+        use rotation::{RotationCheckpoint, RotationPhase, RotationProgress, ROTATION_STATE_REF};
 
-    fn resolve(&self, name: &str) -> Result<Hash> {
-        // Try as branch, then as raw hash
-        self.refs.resolve_ref(name).or_else(|_| {
-            // Assume it's a commit hash
-            Ok(Hash::from(name))
-        })
-    }
+        let _lock = self
+            .storage
+            .acquire_lock(MAINTENANCE_LOCK, MAINTENANCE_LOCK_TIMEOUT)
+            .await?;
+        let old = self.derive_encryptor(old_key).await?;
+        let new = self.derive_encryptor(new_key).await?;
 
-    async fn get_commit(&self, hash: &str) -> Result<Option<Commit>> {
-        let data = self.storage.get_object(hash).await?;
-        match data {
-            Some(bytes) => {
-                let commit: Commit = serde_json::from_slice(&bytes)?;
-                Ok(Some(commit))
-            }
-            None => Ok(None),
+        let mut roots: Vec<Hash> = self.refs.list_branches().values().cloned().collect();
+        if let Head::Detached(hash) = self.refs.get_head() {
+            roots.push(hash.clone());
         }
-    }
+        let commits = rotation::collect_commits(&*self.storage, &roots).await?;
 
-    async fn collect_ancestors(&self, hash: &str, max_depth: usize) -> Result<HashSet<Hash>> {
-        let mut ancestors = HashSet::new();
-        let mut queue = VecDeque::new();
-        queue.push_back(Hash::from(hash));
+        let mut checkpoint = match self.storage.get_ref(ROTATION_STATE_REF).await? {
+            Some(hash) => match self.storage.get_object(&hash).await? {
+                Some(data) => serde_json::from_slice(&data)?,
+                None => RotationCheckpoint::default(),
+            },
+            None => RotationCheckpoint::default(),
+        };
 
-        while let Some(h) = queue.pop_front() {
-            if ancestors.contains(&h) {
+        // Verify every state before writing anything
+        let mut result = rotation::RotationResult::default();
+        let mut seen = HashSet::new();
+        let trees: Vec<&Hash> = commits
+            .iter()
+            .map(|(_, c)| &c.tree_hash)
+            .filter(|h| seen.insert(*h))
+            .collect();
+        let mut pending = Vec::new();
+        for (i, tree_hash) in trees.iter().enumerate() {
+            on_progress(RotationProgress {
+                phase: RotationPhase::Verifying,
+                processed: i,
+                total: trees.len(),
+            });
+            if let Some(rotated) = checkpoint.blobs.get(tree_hash.as_str()) {
+                // Trust the checkpoint only for blobs that still open under new_key
+                let rotated = Hash::from(rotated.as_str());
+                let state = self.load_encrypted_state(&rotated).await.ok().flatten();
+                if state.is_some_and(|state| new.decrypt_state(&state).is_ok()) {
+                    result.states_already_rotated += 1;
+                    continue;
+                }
+                checkpoint.blobs.remove(tree_hash.as_str());
+            }
+            let Some(state) = self.load_encrypted_state(tree_hash).await? else {
+                result.states_unencrypted += 1;
                 continue;
+            };
+            if old.decrypt_state(&state).is_ok() {
+                pending.push(*tree_hash);
+            } else if new.decrypt_state(&state).is_ok() {
+                result.states_already_rotated += 1;
+            } else {
+                return Err(AgitError::EncryptionError(format!(
+                    "state {} does not decrypt under the old or the new key",
+                    tree_hash
+                )));
             }
-            ancestors.insert(h.clone());
+        }
 
-            if ancestors.len() > max_depth {
-                return Err(AgitError::DepthLimitExceeded(
-                    "ancestor traversal depth limit exceeded".to_string(),
-                ));
+        // Re-encrypt, checkpointing as we go
+        for (i, tree_hash) in pending.iter().enumerate() {
+            on_progress(RotationProgress {
+                phase: RotationPhase::Reencrypting,
+                processed: i,
+                total: pending.len(),
+            });
+            let state = self.load_encrypted_state(tree_hash).await?.ok_or_else(|| {
+                AgitError::EncryptionError(format!("state {} is no longer encrypted", tree_hash))
+            })?;
+            let reencrypted = new.encrypt_state(&old.decrypt_state(&state)?)?;
+            let rotated = self.store_blob(reencrypted.to_value(), None).await?;
+            checkpoint.blobs.insert(tree_hash.to_string(), rotated.0);
+            result.states_reencrypted += 1;
+            if result.states_reencrypted % rotation::CHECKPOINT_INTERVAL == 0 {
+                self.save_rotation_checkpoint(&checkpoint).await?;
+            }
+        }
+        if !pending.is_empty() {
+            self.save_rotation_checkpoint(&checkpoint).await?;
+        }
+
+        // Rewrite commits parents first, so each sees its rewritten parents
+        for (i, (hash, commit)) in commits.iter().enumerate() {
+            on_progress(RotationProgress {
+                phase: RotationPhase::Rewriting,
+                processed: i,
+                total: commits.len(),
+            });
+            let tree_hash = checkpoint
+                .blobs
+                .get(commit.tree_hash.as_str())
+                .map(|h| Hash::from(h.as_str()))
+                .unwrap_or_else(|| commit.tree_hash.clone());
+            let parent_hashes: Vec<Hash> = commit
+                .parent_hashes
+                .iter()
+                .map(|p| result.commit_map.get(p).unwrap_or(p).clone())
+                .collect();
+            if tree_hash == commit.tree_hash && parent_hashes == commit.parent_hashes {
+                continue;
+            }
+            let mut commit = commit.clone();
+            if tree_hash != commit.tree_hash {
+                commit.metadata.insert(
+                    ENCRYPTION_KEY_ID_METADATA_KEY.to_string(),
+                    Value::String(new.key_id().to_string()),
+                );
             }
+            commit.tree_hash = tree_hash;
+            commit.parent_hashes = parent_hashes;
+            self.sign(&mut commit);
+            let new_hash = commit.hash();
+            self.storage
+                .put_object(
+                    new_hash.as_str(),
+                    ObjectType::Commit,
+                    &serde_json::to_vec(&commit)?,
+                )
+                .await?;
+            result.commit_map.insert(hash.clone(), new_hash);
+        }
 
-            if let Some(commit) = self.get_commit(h.as_str()).await? {
-                for parent in commit.parent_hashes {
-                    queue.push_back(parent);
-                }
+        // Move every ref at once
+        let mut updates = Vec::new();
+        let mut moved = Vec::new();
+        for (name, tip) in self.refs.list_branches() {
+            if let Some(new_tip) = result.commit_map.get(tip) {
+                updates.push(RefUpdate::set(name, new_tip.as_str()).expecting(tip.as_str()));
+                moved.push((name.clone(), new_tip.clone()));
+            }
+        }
+        let mut new_head = None;
+        if let Head::Detached(hash) = self.refs.get_head() {
+            if let Some(new_hash) = result.commit_map.get(hash) {
+                updates.push(RefUpdate::set("HEAD", new_hash.as_str()).expecting(hash.as_str()));
+                new_head = Some(new_hash.clone());
             }
         }
+        if !updates.is_empty() {
+            self.storage.update_refs(&updates).await?;
+        }
+        for (name, new_tip) in moved {
+            self.refs.update_branch(&name, new_tip)?;
+            result.refs_updated.push(name);
+        }
+        if let Some(hash) = new_head {
+            self.refs.set_head(hash.as_str(), true);
+            result.refs_updated.push("HEAD".to_string());
+        }
+        result.refs_updated.sort();
+        self.storage.delete_ref(ROTATION_STATE_REF).await?;
+        self.encryptor = Some(new);
 
-        Ok(ancestors)
+        let commit_map: serde_json::Map<String, Value> = result
+            .commit_map
+            .iter()
+            .map(|(old, new)| (old.to_string(), Value::String(new.to_string())))
+            .collect();
+        self.log_entry(
+            LogLevel::Info,
+            "rotate_encryption_key",
+            &format!(
+                "rotated encryption key: {} states re-encrypted, {} commits rewritten",
+                result.states_reencrypted,
+                result.commit_map.len()
+            ),
+            None,
+            serde_json::json!({
+                "states_reencrypted": result.states_reencrypted,
+                "states_already_rotated": result.states_already_rotated,
+                "states_unencrypted": result.states_unencrypted,
+                "refs_updated": result.refs_updated,
+                "commit_map": commit_map,
+            }),
+        )
+        .await?;
+        Ok(result)
     }
 
-    async fn log_action(
-        &self,
-        action: &str,
-        message: &str,
-        commit_hash: Option<&str>,
-    ) -> Result<()> {
-        let mut filter = LogFilter::default();
-        filter.agent_id = Some(self.agent_id.clone());
-        filter.limit = Some(1);
-        let prev_hash = self
+    /// The state stored at `tree_hash` as written, if any of its fields
+    /// are encrypted.
+    #[cfg(feature = "encryption")]
+    async fn load_encrypted_state(&self, tree_hash: &Hash) -> Result<Option<AgentState>> {
+        let data = self
             .storage
-            .query_logs(&filter)
+            .get_object(tree_hash.as_str())
             .await?
-            .first()
-            .and_then(|e| e.details.as_ref())
-            .and_then(|d| d.get("integrity_hash"))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
+            .ok_or_else(|| AgitError::ObjectNotFound {
+                hash: tree_hash.to_string(),
+            })?;
+        let mut value: Value = serde_json::from_slice(&data)?;
+        chunking::reassemble(self.storage.as_ref(), &mut value).await?;
+        let state: AgentState = serde_json::from_value(value)?;
+        Ok(has_encrypted_fields(&state).then_some(state))
+    }
+
+    #[cfg(feature = "encryption")]
+    async fn save_rotation_checkpoint(
+        &self,
+        checkpoint: &rotation::RotationCheckpoint,
+    ) -> Result<()> {
+        let blob = Blob::new(serde_json::to_value(checkpoint)?);
+        let hash = blob.hash();
+        self.storage
+            .put_object(hash.as_str(), ObjectType::Blob, &blob.serialize())
+            .await?;
+        self.storage
+            .set_ref(rotation::ROTATION_STATE_REF, hash.as_str())
+            .await
+    }
+
+    // --- Private helpers ---
+
+    /// Build a ref store from persisted refs. Without a persisted HEAD there
+    /// is nothing to prefer, so the local HEAD is kept.
+    fn reconcile_refs(&self, stored_refs: HashMap<String, String>) -> RefStore {
+        let mut refs = RefStore::new();
+        refs.set_head_from(self.refs.get_head().clone());
+        refs.load_from_map(stored_refs);
+        refs
+    }
+
+    /// Store the (optionally encrypted) state blob and a commit object with
+    /// the given parents, returning the new commit hash. Refs are not touched.
+    async fn write_commit(
+        &self,
+        state: &AgentState,
+        message: &str,
+        action_type: &ActionType,
+        mut metadata: serde_json::Map<String, Value>,
+        parent_hashes: Vec<Hash>,
+    ) -> Result<(Hash, CommitProvenance)> {
+        // Ciphertext read back from an encrypted repository must not be
+        // stored as plaintext, nor encrypted a second time
+        #[cfg(feature = "encryption")]
+        let decrypted;
+        let state = if has_encrypted_fields(state) {
+            match self.get_encryptor() {
+                #[cfg(feature = "encryption")]
+                Some(enc) => {
+                    decrypted = enc.decrypt_state(state)?;
+                    &decrypted
+                }
+                _ => {
+                    return Err(AgitError::InvalidArgument(
+                        "state holds encrypted fields but no encryption key is configured"
+                            .to_string(),
+                    ))
+                }
+            }
+        } else {
+            state
+        };
+
+        // Cache the cost so `cost_summary` need not load the blob
+        metadata.insert(COST_METADATA_KEY.to_string(), Value::from(state.cost));
+        self.stamp_generation(&mut metadata, &parent_hashes).await?;
+
+        // Enforce size limits on the plaintext state; the serialization is
+        // reused for the blob when it is stored as-is
+        let state_value = state.to_value();
+        let (serialized, stats) = canonical_serialize_with_stats(&state_value);
+        self.options.check_state_limits(&stats)?;
+        let state_size = serialized.len() as u64;
+        self.embed_merkle_root(&mut metadata, &state_value);
+
+        // Optional encryption
+        let tree_hash = match self.get_encryptor() {
+            #[cfg(feature = "encryption")]
+            Some(enc) => {
+                let encrypted = enc.encrypt_state(state)?;
+                metadata.insert(
+                    ENCRYPTION_KEY_ID_METADATA_KEY.to_string(),
+                    Value::String(enc.key_id().to_string()),
+                );
+                self.store_state(encrypted.to_value(), None).await?
+            }
+            _ => self.store_state(state_value, Some(serialized)).await?,
+        };
+
+        let mut commit = Commit {
+            tree_hash,
+            parent_hashes,
+            message: message.to_string(),
+            author: self.agent_id.clone(),
+            timestamp: Utc::now(),
+            action_type: action_type.clone(),
+            metadata,
+        };
+        self.sign(&mut commit);
+        let provenance = CommitProvenance::of(&commit, state_size);
+        let commit_hash = commit.hash();
+        let commit_data = serde_json::to_vec(&commit)?;
+        self.storage
+            .put_object(commit_hash.as_str(), ObjectType::Commit, &commit_data)
+            .await?;
+
+        Ok((commit_hash, provenance))
+    }
+
+    /// Record the state's Merkle root if `RepoOptions::embed_merkle_root` is set.
+    fn embed_merkle_root(&self, metadata: &mut serde_json::Map<String, Value>, state: &Value) {
+        if self.options.embed_merkle_root {
+            let root = MerkleNode::from_value(state).hash;
+            metadata.insert(MERKLE_ROOT_METADATA_KEY.to_string(), Value::String(root));
+        }
+    }
+
+    /// Attach an HMAC signature if a signing key is configured.
+    fn sign(&self, commit: &mut Commit) {
+        signing::sign_or_strip(commit, self.signing_key.as_deref());
+    }
+
+    /// Resolve a branch, remote-tracking ref, HEAD, or the full hash of a
+    /// stored object. Anything else is `RefNotFound`.
+    async fn resolve(&self, name: &str) -> Result<Hash> {
+        match self.refs.resolve_ref(name) {
+            Ok(hash) => return Ok(hash),
+            Err(e) if name == "HEAD" => return Err(e),
+            Err(_) => {}
+        }
+        match Hash::parse(name) {
+            Ok(hash) if self.storage.has_object(hash.as_str()).await? => Ok(hash),
+            _ => Err(AgitError::RefNotFound {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    async fn get_commit(&self, hash: &str) -> Result<Option<Commit>> {
+        if let Some(commit) = self.cache.get_commit(hash) {
+            return Ok(Some(Commit::clone(&commit)));
+        }
+        let data = self.storage.get_object(hash).await?;
+        match data {
+            Some(bytes) => {
+                let commit: Commit = serde_json::from_slice(&bytes)?;
+                self.cache
+                    .insert_commit(hash, Arc::new(commit.clone()), bytes.len());
+                Ok(Some(commit))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Generation number of the already loaded `commit` at `hash`, without
+    /// reading it again when it is stamped. See `generation`.
+    async fn commit_generation(
+        &self,
+        hash: &Hash,
+        commit: &Commit,
+        memo: &mut HashMap<Hash, u64>,
+    ) -> Result<u64> {
+        match commit
+            .metadata
+            .get(GENERATION_METADATA_KEY)
+            .and_then(Value::as_u64)
+        {
+            Some(generation) => Ok(generation),
+            None => self.generation(hash, memo).await,
+        }
+    }
+
+    /// Generation number of `hash`, read from its metadata or, for commits
+    /// without one, computed from its ancestors. A commit missing from
+    /// storage counts as generation 0. `memo` caches results across calls.
+    async fn generation(&self, hash: &Hash, memo: &mut HashMap<Hash, u64>) -> Result<u64> {
+        // Post-order walk without recursion, so long unstamped histories
+        // cannot overflow the stack
+        let mut stack = vec![(hash.clone(), false)];
+        while let Some((h, parents_done)) = stack.pop() {
+            if memo.contains_key(&h) {
+                continue;
+            }
+            let Some(commit) = self.get_commit(h.as_str()).await? else {
+                memo.insert(h, 0);
+                continue;
+            };
+            let stamped = commit
+                .metadata
+                .get(GENERATION_METADATA_KEY)
+                .and_then(Value::as_u64);
+            if let Some(generation) = stamped {
+                memo.insert(h, generation);
+            } else if parents_done {
+                let highest = commit.parent_hashes.iter().map(|p| memo[p]).max();
+                memo.insert(h, highest.map_or(1, |g| g + 1));
+            } else {
+                stack.push((h, true));
+                for parent in commit.parent_hashes {
+                    if !memo.contains_key(&parent) {
+                        stack.push((parent, false));
+                    }
+                }
+            }
+        }
+        Ok(memo[hash])
+    }
+
+    /// Record the generation of a new commit with `parents` in `metadata`.
+    async fn stamp_generation(
+        &self,
+        metadata: &mut serde_json::Map<String, Value>,
+        parents: &[Hash],
+    ) -> Result<()> {
+        let mut memo = HashMap::new();
+        let mut generation = 1;
+        for parent in parents {
+            generation = generation.max(self.generation(parent, &mut memo).await? + 1);
+        }
+        metadata.insert(GENERATION_METADATA_KEY.to_string(), Value::from(generation));
+        Ok(())
+    }
+
+    async fn collect_ancestors(&self, hash: &str, max_depth: usize) -> Result<HashSet<Hash>> {
+        let mut ancestors = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(Hash::from(hash));
+
+        while let Some(h) = queue.pop_front() {
+            if ancestors.contains(&h) {
+                continue;
+            }
+            ancestors.insert(h.clone());
+
+            if ancestors.len() > max_depth {
+                return Err(AgitError::DepthLimitExceeded(
+                    "ancestor traversal depth limit exceeded".to_string(),
+                ));
+            }
+
+            if let Some(commit) = self.get_commit(h.as_str()).await? {
+                for parent in commit.parent_hashes {
+                    queue.push_back(parent);
+                }
+            }
+        }
+
+        Ok(ancestors)
+    }
+
+    /// Append an info entry. An entry referencing a commit records its
+    /// provenance, loading the commit and its state to compute it.
+    async fn log_action(
+        &self,
+        action: &str,
+        message: &str,
+        commit_hash: Option<&str>,
+    ) -> Result<()> {
+        let Some(hash) = commit_hash else {
+            return self
+                .log_entry(LogLevel::Info, action, message, None, Value::Null)
+                .await;
+        };
+        let provenance = self.commit_provenance(hash).await?;
+        self.log_entry(
+            LogLevel::Info,
+            action,
+            message,
+            Some((hash, &provenance)),
+            Value::Null,
+        )
+        .await
+    }
+
+    /// Provenance of the stored commit `hash`, for audit entries about a
+    /// commit the caller did not just write.
+    async fn commit_provenance(&self, hash: &str) -> Result<CommitProvenance> {
+        let commit = self
+            .get_commit(hash)
+            .await?
+            .ok_or_else(|| AgitError::ObjectNotFound {
+                hash: hash.to_string(),
+            })?;
+        let state = self.load_state(&commit.tree_hash).await?;
+        let state_size = canonical_serialize(&state.to_value()).len() as u64;
+        Ok(CommitProvenance::of(&commit, state_size))
+    }
+
+    /// Append an info entry for a commit just written, whose provenance the
+    /// caller already has.
+    async fn log_commit(
+        &self,
+        action: &str,
+        message: &str,
+        commit_hash: &Hash,
+        provenance: &CommitProvenance,
+    ) -> Result<()> {
+        let commit = Some((commit_hash.as_str(), provenance));
+        self.log_entry(LogLevel::Info, action, message, commit, Value::Null)
+            .await
+    }
+
+    /// Record a refused or failed operation at `level`, then hand `err`
+    /// back for the caller to return. A failure to log is dropped rather
+    /// than masking `err`.
+    async fn log_failure(&self, level: LogLevel, action: &str, err: AgitError) -> AgitError {
+        let message = err.to_string();
+        let details = serde_json::json!({ "error": message });
+        if let Err(_e) = self.log_entry(level, action, &message, None, details).await {
+            #[cfg(feature = "observability")]
+            tracing::warn!(error = %_e, action, "failed to log failed operation");
+        }
+        err
+    }
+
+    /// Append a chained audit entry at `level`. The fields of an `extra`
+    /// object are merged into the entry's details alongside its chain
+    /// hashes and the provenance of `commit`; any other non-null value is
+    /// stored under `value`.
+    async fn log_entry(
+        &self,
+        level: LogLevel,
+        action: &str,
+        message: &str,
+        commit: Option<(&str, &CommitProvenance)>,
+        extra: Value,
+    ) -> Result<()> {
+        let commit_hash = commit.map(|(hash, _)| hash);
+        let provenance = commit.map(|(_, provenance)| provenance);
+        let filter = LogFilter {
+            agent_id: Some(self.agent_id.clone()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let prev_hash = self
+            .storage
+            .query_logs(&filter)
+            .await?
+            .first()
+            .and_then(|e| e.details.as_ref())
+            .and_then(|d| d.get(audit::INTEGRITY_HASH_KEY))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        let timestamp = Utc::now().to_rfc3339();
+        let id = Uuid::new_v4().to_string();
+        let chain_hash = compute_audit_hash_v2(
+            &id,
+            &timestamp,
+            &self.agent_id,
+            action,
+            message,
+            commit_hash.unwrap_or(""),
+            prev_hash.as_deref(),
+            provenance,
+        );
+
+        let details = match extra {
+            Value::Object(fields) => fields,
+            Value::Null => serde_json::Map::new(),
+            value => serde_json::Map::from_iter([("value".to_string(), value)]),
+        };
+        let (stored_message, mut details) = self.seal_log_fields(message, details)?;
+        if let Some(Value::Object(fields)) = provenance.map(serde_json::to_value).transpose()? {
+            details.extend(fields);
+        }
+        details.insert(
+            audit::CHAIN_VERSION_KEY.to_string(),
+            Value::from(audit::CHAIN_VERSION),
+        );
+        details.insert(
+            audit::INTEGRITY_HASH_KEY.to_string(),
+            Value::from(chain_hash),
+        );
+        details.insert(audit::PREV_HASH_KEY.to_string(), Value::from(prev_hash));
+        let details = Value::Object(details);
+
+        let entry = LogEntry {
+            id,
+            timestamp,
+            agent_id: self.agent_id.clone(),
+            action: action.to_string(),
+            message: stored_message,
+            commit_hash: commit_hash.map(|s| s.to_string()),
+            details: Some(details),
+            level,
+        };
+        self.storage.append_log(&entry).await
+    }
+
+    /// Encrypt an audit entry's message and caller-supplied details when an
+    /// encryptor is set. Provenance and chain fields are added afterwards
+    /// and stay in plaintext.
+    fn seal_log_fields(
+        &self,
+        message: &str,
+        details: serde_json::Map<String, Value>,
+    ) -> Result<(String, serde_json::Map<String, Value>)> {
+        match self.get_encryptor() {
+            #[cfg(feature = "encryption")]
+            Some(enc) => {
+                let message = Value::String(message.to_string());
+                let message = format!("{}{}", ENCRYPTED_PREFIX, enc.encrypt_value(&message)?);
+                let mut sealed = serde_json::Map::new();
+                if !details.is_empty() {
+                    let fields = enc.encrypt_value(&Value::Object(details))?;
+                    sealed.insert(
+                        audit::ENCRYPTED_DETAILS_KEY.to_string(),
+                        Value::String(format!("{}{}", ENCRYPTED_PREFIX, fields)),
+                    );
+                }
+                Ok((message, sealed))
+            }
+            _ => Ok((message.to_string(), details)),
+        }
+    }
+
+    /// Decrypt an audit entry sealed by `seal_log_fields` in place. Without
+    /// an encryptor the entry keeps its ciphertext; on failure it is left
+    /// untouched.
+    #[cfg(feature = "encryption")]
+    fn open_log_entry(&self, entry: &mut LogEntry) -> Result<()> {
+        let Some(enc) = self.get_encryptor() else {
+            return Ok(());
+        };
+        let message = match entry.message.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encrypted) => match enc.decrypt_value(encrypted)? {
+                Value::String(message) => Some(message),
+                other => Some(other.to_string()),
+            },
+            None => None,
+        };
+        let sealed = entry
+            .details
+            .as_ref()
+            .and_then(|d| d.get(audit::ENCRYPTED_DETAILS_KEY))
+            .and_then(Value::as_str)
+            .and_then(|s| s.strip_prefix(ENCRYPTED_PREFIX));
+        let fields = match sealed {
+            Some(encrypted) => match enc.decrypt_value(encrypted)? {
+                Value::Object(fields) => Some(fields),
+                _ => {
+                    return Err(AgitError::EncryptionError(format!(
+                        "encrypted details of log entry {} are not an object",
+                        entry.id
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        if let Some(message) = message {
+            entry.message = message;
+        }
+        if let (Some(fields), Some(Value::Object(details))) = (fields, entry.details.as_mut()) {
+            details.remove(audit::ENCRYPTED_DETAILS_KEY);
+            details.extend(fields);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn open_log_entry(&self, _entry: &mut LogEntry) -> Result<()> {
+        Ok(())
+    }
+
+    /// `open_log_entry` over a batch of entries, passing on those that fail
+    /// to decrypt as stored.
+    fn open_log_entries(&self, mut entries: Vec<LogEntry>) -> Vec<LogEntry> {
+        for entry in &mut entries {
+            let _ = self.open_log_entry(entry);
+        }
+        entries
+    }
+}
+
+/// Hash of the node at `path` in `tree`: `Some(None)` if the path is
+/// absent, `None` if it leads through a leaf, whose contents the tree does
+/// not describe.
+fn merkle_subtree(tree: &MerkleNode, path: &[String]) -> Option<Option<String>> {
+    let mut node = tree;
+    for segment in path {
+        if node.children.is_empty() {
+            // A scalar, an array or an empty object; only the value can tell
+            return None;
+        }
+        match node.children.get(segment) {
+            Some(child) => node = child,
+            None => return Some(None),
+        }
+    }
+    Some(Some(node.hash.clone()))
+}
+
+/// Load persisted branch protection rules, if any.
+async fn load_protection(storage: &dyn StorageBackend) -> Result<BranchProtection> {
+    let Some(hash) = storage.get_ref(PROTECTION_REF).await? else {
+        return Ok(BranchProtection::default());
+    };
+    let data = storage
+        .get_object(&hash)
+        .await?
+        .ok_or(AgitError::ObjectNotFound { hash })?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite::SqliteStorage;
+    use serde_json::json;
+
+    async fn test_repo() -> Repository {
+        let storage = SqliteStorage::new(":memory:").await.unwrap();
+        Repository::init(Box::new(storage)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_init() {
+        let repo = test_repo().await;
+        assert_eq!(repo.current_branch(), Some("main"));
+    }
+
+    #[tokio::test]
+    async fn test_commit_and_log() {
+        let mut repo = test_repo().await;
+        let state = AgentState::new(json!({"counter": 1}), json!({}));
+        let hash = repo
+            .commit(&state, "first commit", ActionType::ToolCall)
+            .await
+            .unwrap();
+        assert_eq!(hash.0.len(), 64);
+
+        let commits = repo.log(None, 10).await.unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].1.message, "first commit");
+    }
+
+    #[tokio::test]
+    async fn test_multiple_commits() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"step": 1}), json!({}));
+        repo.commit(&s1, "step 1", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let s2 = AgentState::new(json!({"step": 2}), json!({}));
+        repo.commit(&s2, "step 2", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let s3 = AgentState::new(json!({"step": 3}), json!({}));
+        repo.commit(&s3, "step 3", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let commits = repo.log(None, 10).await.unwrap();
+        assert_eq!(commits.len(), 3);
+    }
+
+    /// Store a commit with an explicit timestamp and point `main` at it.
+    async fn commit_at_time(
+        repo: &mut Repository,
+        parents: &[&Hash],
+        message: &str,
+        timestamp: DateTime<Utc>,
+    ) -> Hash {
+        let state = AgentState::new(json!({ "message": message }), json!({}));
+        let commit = Commit {
+            tree_hash: repo.store_state(state.to_value(), None).await.unwrap(),
+            parent_hashes: parents.iter().map(|h| (*h).clone()).collect(),
+            message: message.to_string(),
+            author: "default".to_string(),
+            timestamp,
+            action_type: ActionType::ToolCall,
+            metadata: serde_json::Map::new(),
+        };
+        let hash = commit.hash();
+        repo.storage
+            .put_object(
+                hash.as_str(),
+                ObjectType::Commit,
+                &serde_json::to_vec(&commit).unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.storage.set_ref("main", hash.as_str()).await.unwrap();
+        repo.refresh_refs().await.unwrap();
+        hash
+    }
+
+    fn messages(commits: &[(Hash, Commit)]) -> Vec<&str> {
+        commits.iter().map(|(_, c)| c.message.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_log_same_timestamp_follows_dag() {
+        let mut repo = test_repo().await;
+        let now = Utc::now();
+        let mut hashes: Vec<Hash> = Vec::new();
+        for i in 0..6 {
+            let parents: Vec<&Hash> = hashes.last().into_iter().collect();
+            let hash = commit_at_time(&mut repo, &parents, &format!("c{}", i), now).await;
+            hashes.push(hash);
+        }
+
+        let commits = repo.log(None, 10).await.unwrap();
+        assert_eq!(messages(&commits), ["c5", "c4", "c3", "c2", "c1", "c0"]);
+        hashes.reverse();
+        assert_eq!(
+            commits.iter().map(|(h, _)| h.clone()).collect::<Vec<_>>(),
+            hashes
+        );
+        assert!(commits.iter().all(|(h, c)| *h == c.hash()));
+        assert_eq!(messages(&repo.log(None, 2).await.unwrap()), ["c5", "c4"]);
+    }
+
+    #[tokio::test]
+    async fn test_log_merge_with_skewed_clocks() {
+        let mut repo = test_repo().await;
+        let t = |secs: i64| DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+        // Every child is older than its parents, and the sides disagree
+        let base = commit_at_time(&mut repo, &[], "base", t(100)).await;
+        let ours = commit_at_time(&mut repo, &[&base], "ours", t(20)).await;
+        let theirs = commit_at_time(&mut repo, &[&base], "theirs", t(50)).await;
+        commit_at_time(&mut repo, &[&ours, &theirs], "merge", t(5)).await;
+
+        let commits = repo.log(None, 10).await.unwrap();
+        assert_eq!(messages(&commits), ["merge", "theirs", "ours", "base"]);
+        let first = repo.log(None, 3).await.unwrap();
+        assert_eq!(messages(&first), ["merge", "theirs", "ours"]);
+    }
+
+    #[tokio::test]
+    async fn test_log_stops_at_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        for step in 0..20 {
+            let state = AgentState::new(json!({ "step": step }), json!({}));
+            repo.commit(&state, "step", ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
+
+        // A cold handle loads the tip and its parent, not the whole history
+        let cold = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(cold.log(None, 1).await.unwrap().len(), 1);
+        assert_eq!(cold.cache_stats().misses, 2);
+    }
+
+    #[tokio::test]
+    async fn test_branch_and_checkout() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.branch("feature", None).await.unwrap();
+        let state = repo.checkout("feature").await.unwrap();
+        assert_eq!(state.memory, json!({"v": 1}));
+        assert_eq!(repo.current_branch(), Some("feature"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_checked_out_branch() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let tip = repo
+            .commit(&s2, "feature", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            repo.delete_branch("feature", false).await,
+            Err(AgitError::BranchCheckedOut { ref name }) if name == "feature"
+        ));
+        assert_eq!(repo.current_branch(), Some("feature"));
+        assert!(repo.list_branches().contains_key("feature"));
+
+        // Forcing detaches HEAD at the old tip, and stays that way on reload
+        repo.delete_branch("feature", true).await.unwrap();
+        assert!(!repo.list_branches().contains_key("feature"));
+        assert_eq!(repo.current_branch(), None);
+        assert_eq!(repo.head().unwrap(), tip);
+        repo.refresh_refs().await.unwrap();
+        assert_eq!(repo.head().unwrap(), tip);
+        // Committing no longer recreates the deleted branch
+        let s3 = AgentState::new(json!({"v": 3}), json!({}));
+        let detached = repo
+            .commit(&s3, "detached", ActionType::ToolCall)
+            .await
+            .unwrap();
+        assert_eq!(repo.head().unwrap(), detached);
+        assert!(!repo.list_branches().contains_key("feature"));
+
+        // main stays undeletable, forced or not
+        repo.checkout("main").await.unwrap();
+        assert!(repo.delete_branch("main", true).await.is_err());
+        assert_eq!(repo.current_branch(), Some("main"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_force_delete_keeps_head_attached() {
+        use std::sync::atomic::Ordering;
+        let head_fails = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let storage = ContendedStorage {
+            inner: SqliteStorage::new(":memory:").await.unwrap(),
+            conflicts: 0.into(),
+            head_fails: head_fails.clone(),
+        };
+        let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+
+        head_fails.store(true, Ordering::SeqCst);
+        repo.delete_branch("feature", true).await.unwrap_err();
+        assert_eq!(repo.current_branch(), Some("feature"));
+        assert!(repo.list_branches().contains_key("feature"));
+        assert!(repo.storage.get_ref("feature").await.unwrap().is_some());
+        assert_eq!(
+            repo.storage.get_ref("HEAD").await.unwrap(),
+            Some("ref:feature".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset_refuses_checked_out_protected_branch() {
+        use crate::protection::ProtectionRule;
+        let mut repo = test_repo().await;
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        let first = repo
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 2}), json!({}));
+        let second = repo
+            .commit(&state, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let mut rule = ProtectionRule::new("main");
+        rule.allow_reset = true;
+        repo.set_branch_protection(BranchProtection { rules: vec![rule] })
+            .await
+            .unwrap();
+
+        let err = repo.reset("main", first.as_str()).await.unwrap_err();
+        assert!(matches!(err, AgitError::BranchCheckedOut { ref name } if name == "main"));
+        assert_eq!(repo.head().unwrap(), second);
+
+        repo.branch("other", None).await.unwrap();
+        repo.checkout("other").await.unwrap();
+        assert_eq!(repo.reset("main", first.as_str()).await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rejects_unknown_refs() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let missing = "0".repeat(64);
+        for target in ["tpyo", h1.short(), &h1.as_str().to_uppercase(), &missing] {
+            assert!(
+                matches!(
+                    repo.branch("new", Some(target)).await,
+                    Err(AgitError::RefNotFound { ref name }) if name == target
+                ),
+                "{}",
+                target
+            );
+            assert!(matches!(
+                repo.reset("main", target).await,
+                Err(AgitError::RefNotFound { .. })
+            ));
+            assert!(matches!(
+                repo.checkout(target).await,
+                Err(AgitError::RefNotFound { .. })
+            ));
+        }
+        assert!(!repo.list_branches().contains_key("new"));
+        assert!(matches!(
+            repo.is_ancestor("tpyo", h1.as_str()).await,
+            Err(AgitError::InvalidArgument(_))
+        ));
+
+        repo.branch("new", Some(h1.as_str())).await.unwrap();
+        assert_eq!(repo.list_branches()["new"], h1);
+        assert_eq!(Hash::parse(h1.as_str()).unwrap(), h1);
+        assert!(Hash::parse(h1.short()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_diff() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"a": 1, "b": 2}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let s2 = AgentState::new(json!({"a": 1, "b": 3, "c": 4}), json!({}));
+        let h2 = repo
+            .commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let diff = repo.diff(h1.as_str(), h2.as_str()).await.unwrap();
+        assert!(!diff.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_revert() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let reverted = repo.revert(h1.as_str()).await.unwrap();
+        assert_eq!(reverted.memory, json!({"v": 1}));
+
+        // Should now have 3 commits
+        let commits = repo.log(None, 10).await.unwrap();
+        assert_eq!(commits.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_merge_ours() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "feature work", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.checkout("main").await.unwrap();
+
+        let s3 = AgentState::new(json!({"v": 3}), json!({}));
+        repo.commit(&s3, "main work", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let merge_hash = repo.merge("feature", MergeStrategy::Ours).await.unwrap();
+        let merged_state = repo.get_state(merge_hash.as_str()).await.unwrap();
+        assert_eq!(merged_state.memory, json!({"v": 3}));
+    }
+
+    #[tokio::test]
+    async fn test_get_state() {
+        let mut repo = test_repo().await;
+        let state = AgentState::new(json!({"data": "hello"}), json!({"world": true}));
+        let hash = repo
+            .commit(&state, "test", ActionType::Checkpoint)
+            .await
+            .unwrap();
+
+        let retrieved = repo.get_state(hash.as_str()).await.unwrap();
+        assert_eq!(retrieved.memory, json!({"data": "hello"}));
+        assert_eq!(retrieved.world_state, json!({"world": true}));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let mut repo = test_repo().await;
+        let state = AgentState::new(json!({}), json!({}));
+        repo.commit(&state, "test action", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let filter = LogFilter {
+            limit: Some(10),
+            ..Default::default()
+        };
+        let logs = repo.audit_log(&filter).await.unwrap();
+        assert!(!logs.is_empty());
+    }
+    #[tokio::test]
+    async fn test_commit_to_branch_leaves_head() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("experiments/run-42", None).await.unwrap();
+
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let h2 = repo
+            .commit_to_branch(
+                "experiments/run-42",
+                &s2,
+                "side work",
+                ActionType::ToolCall,
+                false,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(repo.current_branch(), Some("main"));
+        assert_eq!(repo.head().unwrap(), h1);
+        assert_eq!(repo.list_branches()["experiments/run-42"], h2);
+
+        let commits = repo.log(Some("experiments/run-42"), 10).await.unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].1.parent_hashes, vec![h1]);
+    }
+
+    #[tokio::test]
+    async fn test_commit_to_branch_missing() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let err = repo
+            .commit_to_branch("nope", &s2, "x", ActionType::ToolCall, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::BranchNotFound { .. }));
+
+        let h2 = repo
+            .commit_to_branch("nope", &s2, "x", ActionType::ToolCall, true)
+            .await
+            .unwrap();
+        let commits = repo.log(Some("nope"), 10).await.unwrap();
+        assert_eq!(commits[0].1.parent_hashes, vec![h1.clone()]);
+        assert_eq!(repo.list_branches()["nope"], h2);
+        assert_eq!(repo.head().unwrap(), h1);
+    }
+
+    #[tokio::test]
+    async fn test_commit_to_branch_rebases_on_concurrent_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let mut stale = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        stale
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let mut other = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 2}), json!({}));
+        let theirs = other
+            .commit_to_branch("main", &state, "other", ActionType::ToolCall, false)
+            .await
+            .unwrap();
+
+        let state = AgentState::new(json!({"v": 3}), json!({}));
+        let ours = stale
+            .commit_to_branch("main", &state, "stale", ActionType::ToolCall, false)
+            .await
+            .unwrap();
+        let commits = stale.log(Some("main"), 10).await.unwrap();
+        assert_eq!(commits[0].0, ours);
+        assert_eq!(commits[0].1.parent_hashes, vec![theirs]);
+    }
+    #[tokio::test]
+    async fn test_refresh_refs_across_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db");
+        let db = db.to_str().unwrap();
+
+        let mut a = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        let mut b = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = a.commit(&s1, "from a", ActionType::ToolCall).await.unwrap();
+
+        // b doesn't see a's commit until it refreshes
+        assert!(b.head().is_err());
+        b.refresh_refs().await.unwrap();
+        assert_eq!(b.head().unwrap(), h1);
+
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let h2 = b.commit(&s2, "from b", ActionType::ToolCall).await.unwrap();
+        a.refresh_refs().await.unwrap();
+        assert_eq!(a.head().unwrap(), h2);
+    }
+
+    #[tokio::test]
+    async fn test_auto_refresh_log_and_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db");
+        let db = db.to_str().unwrap();
+
+        let mut a = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        let mut b = Repository::init_with_options(
+            Box::new(SqliteStorage::new(db).await.unwrap()),
+            RepoOptions {
+                auto_refresh: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        a.commit(&s1, "first", ActionType::ToolCall).await.unwrap();
+        a.branch("feature", None).await.unwrap();
+
+        assert_eq!(b.log(None, 10).await.unwrap().len(), 1);
+        let state = b.checkout("feature").await.unwrap();
+        assert_eq!(state.memory, json!({"v": 1}));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_prefers_stored_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db");
+        let db = db.to_str().unwrap();
+
+        let mut a = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        a.commit(&s1, "first", ActionType::ToolCall).await.unwrap();
+        a.branch("feature", None).await.unwrap();
+
+        let mut b = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        a.checkout("feature").await.unwrap();
+
+        b.refresh_refs().await.unwrap();
+        assert_eq!(b.current_branch(), Some("feature"));
+        let filter = LogFilter {
+            action: Some("refs_refreshed".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(b.audit_log(&filter).await.unwrap().len(), 1);
+    }
+    #[tokio::test]
+    async fn test_merge_fast_forward() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let h2 = repo
+            .commit(&s2, "feature work", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+
+        let merged = repo
+            .merge("feature", MergeStrategy::ThreeWay)
+            .await
+            .unwrap();
+        assert_eq!(merged, h2);
+        assert_eq!(repo.head().unwrap(), h2);
+        assert_eq!(repo.log(None, 10).await.unwrap().len(), 2);
+
+        let filter = LogFilter {
+            action: Some("fast_forward".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(repo.audit_log(&filter).await.unwrap().len(), 1);
+
+        // Merging again is a no-op
+        let again = repo
+            .merge("feature", MergeStrategy::ThreeWay)
+            .await
+            .unwrap();
+        assert_eq!(again, h2);
+    }
+
+    #[tokio::test]
+    async fn test_merge_no_ff() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let h2 = repo
+            .commit(&s2, "feature work", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+
+        let merged = repo
+            .merge_with_options(
+                "feature",
+                MergeStrategy::ThreeWay,
+                MergeOptions { no_ff: true },
+            )
+            .await
+            .unwrap();
+        assert_ne!(merged, h2);
+        let commits = repo.log(None, 1).await.unwrap();
+        assert_eq!(commits[0].1.parent_hashes, vec![h1, h2]);
+        let state = repo.get_state(merged.as_str()).await.unwrap();
+        assert_eq!(state.memory, json!({"v": 2}));
+    }
+    #[tokio::test]
+    async fn test_is_ancestor_across_merge() {
+        let mut repo = test_repo().await;
+
+        let s1 = AgentState::new(json!({"a": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "root", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.branch("feature", None).await.unwrap();
+        let s2 = AgentState::new(json!({"a": 1, "b": 2}), json!({}));
+        let h2 = repo
+            .commit_to_branch("feature", &s2, "feature", ActionType::ToolCall, false)
+            .await
+            .unwrap();
+
+        let s3 = AgentState::new(json!({"a": 3}), json!({}));
+        let h3 = repo
+            .commit(&s3, "main", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let m = repo.merge("feature", MergeStrategy::Ours).await.unwrap();
+
+        // Both parents (and the root) are ancestors of the merge commit
+        assert!(repo.is_ancestor(h1.as_str(), m.as_str()).await.unwrap());
+        assert!(repo.is_ancestor(h2.as_str(), m.as_str()).await.unwrap());
+        assert!(repo.is_ancestor(h3.as_str(), m.as_str()).await.unwrap());
+        assert!(repo.is_ancestor(m.as_str(), m.as_str()).await.unwrap());
+
+        // Siblings and descendants are not
+        assert!(!repo.is_ancestor(h2.as_str(), h3.as_str()).await.unwrap());
+        assert!(!repo.is_ancestor(m.as_str(), h1.as_str()).await.unwrap());
+
+        let base = repo
+            .find_merge_base(h2.as_str(), h3.as_str())
+            .await
+            .unwrap();
+        assert_eq!(base, h1);
+    }
+
+    #[tokio::test]
+    async fn test_commits_record_generation() {
+        let mut repo = test_repo().await;
+        let generation = |c: Commit| c.metadata[GENERATION_METADATA_KEY].as_u64().unwrap();
+
+        // A commit written without a generation has it computed for its child
+        let root = commit_at_time(&mut repo, &[], "root", Utc::now()).await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo.commit(&s1, "one", ActionType::ToolCall).await.unwrap();
+        assert_eq!(
+            generation(repo.get_commit(h1.as_str()).await.unwrap().unwrap()),
+            2
+        );
+
+        repo.branch("feature", Some(root.as_str())).await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit_to_branch("feature", &s2, "two", ActionType::ToolCall, false)
+            .await
+            .unwrap();
+        let m = repo.merge("feature", MergeStrategy::Ours).await.unwrap();
+        assert_eq!(
+            generation(repo.get_commit(m.as_str()).await.unwrap().unwrap()),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_base_criss_cross() {
+        let mut repo = test_repo().await;
+        let now = Utc::now();
+        let root = commit_at_time(&mut repo, &[], "root", now).await;
+        let a1 = commit_at_time(&mut repo, &[&root], "a1", now).await;
+        let b1 = commit_at_time(&mut repo, &[&root], "b1", now).await;
+        // Each side merges the other, so both a1 and b1 are lowest common ancestors
+        let a2 = commit_at_time(&mut repo, &[&a1, &b1], "a2", now).await;
+        let b2 = commit_at_time(&mut repo, &[&b1, &a1], "b2", now).await;
+        let a3 = commit_at_time(&mut repo, &[&a2], "a3", now).await;
+
+        let mut expected = vec![a1.clone(), b1.clone()];
+        expected.sort();
+        let bases = repo
+            .find_merge_bases(a3.as_str(), b2.as_str())
+            .await
+            .unwrap();
+        assert_eq!(bases, expected);
+        let reversed = repo
+            .find_merge_bases(b2.as_str(), a3.as_str())
+            .await
+            .unwrap();
+        assert_eq!(reversed, expected);
+        let base = repo
+            .find_merge_base(a3.as_str(), b2.as_str())
+            .await
+            .unwrap();
+        assert_eq!(base, expected[0]);
+
+        // Once one side merges the other there is a single base again
+        let b3 = commit_at_time(&mut repo, &[&b2, &a3], "b3", now).await;
+        let bases = repo
+            .find_merge_bases(a3.as_str(), b3.as_str())
+            .await
+            .unwrap();
+        assert_eq!(bases, std::slice::from_ref(&a3));
+        let bases = repo
+            .find_merge_bases(a3.as_str(), a3.as_str())
+            .await
+            .unwrap();
+        assert_eq!(bases, [a3]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_base_unbalanced() {
+        let mut repo = test_repo().await;
+        let now = Utc::now();
+        let root = commit_at_time(&mut repo, &[], "root", now).await;
+        let x1 = commit_at_time(&mut repo, &[&root], "x1", now).await;
+        let x2 = commit_at_time(&mut repo, &[&x1], "x2", now).await;
+        let ours = commit_at_time(&mut repo, &[&x2], "ours", now).await;
+        // `theirs` reaches root in two steps through `side` but x2 only through
+        // `q`, so a plain BFS from it meets root first
+        let side = commit_at_time(&mut repo, &[&root], "side", now).await;
+        let q = commit_at_time(&mut repo, &[&x2], "q", now).await;
+        let theirs = commit_at_time(&mut repo, &[&side, &q], "theirs", now).await;
+
+        let bases = repo
+            .find_merge_bases(ours.as_str(), theirs.as_str())
+            .await
+            .unwrap();
+        assert_eq!(bases, std::slice::from_ref(&x2));
+        let base = repo
+            .find_merge_base(theirs.as_str(), ours.as_str())
+            .await
+            .unwrap();
+        assert_eq!(base, x2);
+        let base = repo
+            .find_merge_base(side.as_str(), ours.as_str())
+            .await
+            .unwrap();
+        assert_eq!(base, root);
+    }
+
+    #[tokio::test]
+    async fn test_merge_base_unrelated_histories() {
+        let mut repo = test_repo().await;
+        let other = commit_at_time(&mut repo, &[], "other", Utc::now()).await;
+        let main = commit_at_time(&mut repo, &[], "main", Utc::now()).await;
+        repo.storage.set_ref("other", other.as_str()).await.unwrap();
+        repo.refresh_refs().await.unwrap();
+
+        assert!(matches!(
+            repo.find_merge_base(main.as_str(), other.as_str()).await,
+            Err(AgitError::NoCommonAncestor { ref ours, ref theirs })
+                if *ours == main.0 && *theirs == other.0
+        ));
+        assert!(matches!(
+            repo.merge("other", MergeStrategy::ThreeWay).await,
+            Err(AgitError::NoCommonAncestor { .. })
+        ));
+        assert_eq!(repo.refs.resolve_ref("main").unwrap(), main);
+    }
+    #[tokio::test]
+    async fn test_rename_branch_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db");
+        let db = db.to_str().unwrap();
+
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+
+        repo.rename_branch("feature", "feature-2", false)
+            .await
+            .unwrap();
+        assert_eq!(repo.current_branch(), Some("feature-2"));
+
+        let reopened = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(reopened.current_branch(), Some("feature-2"));
+        assert!(!reopened.list_branches().contains_key("feature"));
+        assert_eq!(reopened.list_branches()["feature-2"], h1);
+
+        let filter = LogFilter {
+            action: Some("rename_branch".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(reopened.audit_log(&filter).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rename_branch_onto_existing_fails() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+
+        let err = repo
+            .rename_branch("feature", "main", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::BranchExists { .. }));
+        assert!(repo.rename_branch("main", "trunk", false).await.is_err());
+        repo.rename_branch("main", "trunk", true).await.unwrap();
+        assert_eq!(repo.current_branch(), Some("trunk"));
+    }
+
+    #[tokio::test]
+    async fn test_rename_branch_is_one_batch() {
+        use crate::protection::ProtectionRule;
+        use std::sync::atomic::Ordering;
+
+        let head_fails = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let storage = ContendedStorage {
+            inner: SqliteStorage::new(":memory:").await.unwrap(),
+            conflicts: 0.into(),
+            head_fails: head_fails.clone(),
+        };
+        let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        let tip = repo
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+
+        // A failed HEAD write leaves both storage and memory as they were
+        head_fails.store(true, Ordering::SeqCst);
+        repo.rename_branch("feature", "renamed", false)
+            .await
+            .unwrap_err();
+        head_fails.store(false, Ordering::SeqCst);
+        assert_eq!(repo.current_branch(), Some("feature"));
+        assert!(!repo.list_branches().contains_key("renamed"));
+        assert_eq!(
+            repo.storage.get_ref("feature").await.unwrap(),
+            Some(tip.to_string())
+        );
+        assert_eq!(repo.storage.get_ref("renamed").await.unwrap(), None);
+
+        // Another handle created the new name in storage first
+        repo.storage.set_ref("renamed", tip.as_str()).await.unwrap();
+        let err = repo
+            .rename_branch("feature", "renamed", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::ConcurrentUpdate { ref name } if name == "renamed"));
+        assert_eq!(repo.current_branch(), Some("feature"));
+        repo.storage.delete_ref("renamed").await.unwrap();
+
+        // Renaming is a deletion of the old name as far as protection goes
+        repo.set_branch_protection(BranchProtection {
+            rules: vec![ProtectionRule::new("feature")],
+        })
+        .await
+        .unwrap();
+        let err = repo
+            .rename_branch("feature", "renamed", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::ProtectedBranch { .. }));
+        assert!(repo.list_branches().contains_key("feature"));
+    }
+    #[tokio::test]
+    async fn test_branch_protection_enforced() {
+        use crate::protection::ProtectionRule;
+
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("release/1.0", None).await.unwrap();
+
+        repo.set_branch_protection(BranchProtection {
+            rules: vec![
+                ProtectionRule::new("main"),
+                ProtectionRule {
+                    allow_delete: true,
+                    required_action_types: vec![ActionType::Checkpoint],
+                    ..ProtectionRule::new("release/*")
+                },
+            ],
+        })
+        .await
+        .unwrap();
+
+        let err = repo.reset("main", h1.as_str()).await.unwrap_err();
+        assert!(matches!(err, AgitError::ProtectedBranch { .. }));
+
+        let s3 = AgentState::new(json!({"v": 3}), json!({}));
+        let err = repo
+            .commit_to_branch("release/1.0", &s3, "x", ActionType::ToolCall, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::ProtectedBranch { .. }));
+        repo.commit_to_branch("release/1.0", &s3, "x", ActionType::Checkpoint, false)
+            .await
+            .unwrap();
+
+        repo.delete_branch("release/1.0", false).await.unwrap();
+
+        // Unprotected branches can still be reset
+        repo.branch("scratch", None).await.unwrap();
+        assert_eq!(repo.reset("scratch", h1.as_str()).await.unwrap(), h1);
+    }
+
+    #[tokio::test]
+    async fn test_merge_into_protected_branch() {
+        use crate::protection::ProtectionRule;
+
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "feature work", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+
+        let requiring = |types| BranchProtection {
+            rules: vec![ProtectionRule {
+                required_action_types: types,
+                ..ProtectionRule::new("main")
+            }],
+        };
+        repo.set_branch_protection(requiring(vec![ActionType::Checkpoint]))
+            .await
+            .unwrap();
+
+        // Neither a fast-forward nor a merge commit gets through
+        for no_ff in [false, true] {
+            let err = repo
+                .merge_with_options("feature", MergeStrategy::Theirs, MergeOptions { no_ff })
+                .await
+                .unwrap_err();
+            assert!(matches!(err, AgitError::ProtectedBranch { .. }));
+            assert_eq!(repo.list_branches()["main"], h1);
+        }
+        let denied = repo
+            .audit_log(&LogFilter {
+                action: Some("merge_denied".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(denied.len(), 2);
+
+        repo.set_branch_protection(requiring(vec![ActionType::Merge]))
+            .await
+            .unwrap();
+        let merged = repo.merge("feature", MergeStrategy::Theirs).await.unwrap();
+        assert_eq!(repo.list_branches()["main"], merged);
+    }
+
+    #[tokio::test]
+    async fn test_branch_protection_persisted() {
+        use crate::protection::ProtectionRule;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db");
+        let db = db.to_str().unwrap();
+
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.set_branch_protection(BranchProtection {
+            rules: vec![ProtectionRule::new("main")],
+        })
+        .await
+        .unwrap();
+        repo.gc(0).await.unwrap();
+
+        let reopened = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(reopened.branch_protection().rules.len(), 1);
+        assert!(!reopened.list_branches().contains_key("config/protection"));
+    }
+    #[tokio::test]
+    async fn test_signed_commits_verify() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let unsigned = repo
+            .commit(&s1, "unsigned", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.set_signing_key(b"runtime-key");
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let signed = repo
+            .commit(&s2, "signed", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        assert!(repo
+            .verify_commit(signed.as_str(), b"runtime-key")
+            .await
+            .unwrap());
+        assert!(!repo
+            .verify_commit(signed.as_str(), b"wrong-key")
+            .await
+            .unwrap());
+        assert!(!repo
+            .verify_commit(unsigned.as_str(), b"runtime-key")
+            .await
+            .unwrap());
+
+        let report = repo.verify_history("main", b"runtime-key").await.unwrap();
+        assert_eq!(report.verified, vec![signed.clone()]);
+        assert_eq!(report.unsigned, vec![unsigned]);
+        assert!(report.invalid.is_empty());
+        assert!(!report.is_valid());
+
+        let report = repo.verify_history("main", b"wrong-key").await.unwrap();
+        assert_eq!(report.invalid, vec![signed]);
+    }
+
+    #[tokio::test]
+    async fn test_signed_merge_commit() {
+        let mut repo = test_repo().await;
+        repo.set_signing_key(b"k");
+        let s1 = AgentState::new(json!({"a": 1}), json!({}));
+        repo.commit(&s1, "base", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        let s2 = AgentState::new(json!({"a": 2}), json!({}));
+        repo.commit(&s2, "main", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s3 = AgentState::new(json!({"a": 3}), json!({}));
+        repo.commit(&s3, "feature", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+        repo.merge("feature", MergeStrategy::Ours).await.unwrap();
+
+        let report = repo.verify_history("main", b"k").await.unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.verified.len(), 4);
+    }
+    #[tokio::test]
+    async fn test_fsck_clean_repo() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let report = repo.fsck().await.unwrap();
+        assert!(report.is_ok());
+        // Two commits, two blobs, and their cached Merkle trees
+        assert_eq!(report.objects_checked, 6);
+        assert!(report.orphans.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fsck_detects_corruption() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let h2 = repo
+            .commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        // Drop the first commit's state blob
+        let c1 = repo.get_commit(h1.as_str()).await.unwrap().unwrap();
+        repo.storage
+            .delete_object(c1.tree_hash.as_str())
+            .await
+            .unwrap();
+
+        // Overwrite the second commit's blob with different content
+        let c2 = repo.get_commit(h2.as_str()).await.unwrap().unwrap();
+        repo.storage
+            .delete_object(c2.tree_hash.as_str())
+            .await
+            .unwrap();
+        let bogus = Blob::new(json!({"tampered": true}));
+        repo.storage
+            .put_object(c2.tree_hash.as_str(), ObjectType::Blob, &bogus.serialize())
+            .await
+            .unwrap();
+
+        // An unreferenced object
+        let orphan = Blob::new(json!({"orphan": true}));
+        repo.storage
+            .put_object(
+                orphan.hash().as_str(),
+                ObjectType::Blob,
+                &orphan.serialize(),
+            )
+            .await
+            .unwrap();
+
+        let report = repo.fsck().await.unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.missing, vec![c1.tree_hash.to_string()]);
+        // The blob no longer matches its key, nor its cached Merkle tree
+        let mut mismatches = report.hash_mismatches.clone();
+        mismatches.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].key, c2.tree_hash.to_string());
+        assert_eq!(mismatches[0].actual, bogus.hash().to_string());
+        assert_eq!(mismatches[1].key, tree_key(c2.tree_hash.as_str()));
+        assert_eq!(report.orphans, vec![orphan.hash().to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_fsck_repair_from_duplicate() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let c1 = repo.get_commit(h1.as_str()).await.unwrap().unwrap();
+
+        // Move the blob to the wrong key, as a botched migration might
+        let data = repo
+            .storage
+            .get_object(c1.tree_hash.as_str())
+            .await
+            .unwrap()
+            .unwrap();
+        repo.storage
+            .delete_object(c1.tree_hash.as_str())
+            .await
+            .unwrap();
+        repo.storage
+            .put_object("misplaced", ObjectType::Blob, &data)
+            .await
+            .unwrap();
+
+        let report = repo.fsck().await.unwrap();
+        assert_eq!(report.missing, vec![c1.tree_hash.to_string()]);
+        assert!(report.repaired.is_empty());
+
+        let report = repo
+            .fsck_with_options(FsckOptions { repair: true })
+            .await
+            .unwrap();
+        assert!(report.missing.is_empty());
+        assert_eq!(report.repaired, vec![c1.tree_hash.to_string()]);
+        assert!(repo.get_state(h1.as_str()).await.is_ok());
+
+        let report = repo.fsck().await.unwrap();
+        assert!(report.missing.is_empty());
+        assert_eq!(report.orphans, vec!["misplaced".to_string()]);
+    }
+    #[tokio::test]
+    async fn test_verify_audit_chain() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        repo.commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.set_agent_id("other");
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.set_agent_id("default");
+        repo.rename_branch("main", "trunk", true).await.unwrap();
+
+        let result = repo.verify_audit_chain(None).await.unwrap();
+        assert!(result.is_valid());
+        assert_eq!(result.entries_checked, 3);
+
+        let result = repo.verify_audit_chain(Some("other")).await.unwrap();
+        assert_eq!(result.entries_checked, 1);
+
+        // A forged entry without a valid chain hash breaks the chain
+        let forged = LogEntry {
+            id: "forged".to_string(),
+            timestamp: Utc::now().to_rfc3339(),
+            agent_id: "default".to_string(),
+            action: "delete_branch".to_string(),
+            message: "deleted branch 'main'".to_string(),
+            commit_hash: None,
+            details: Some(json!({"integrity_hash": "0000"})),
+            level: LogLevel::Info,
+        };
+        repo.storage.append_log(&forged).await.unwrap();
+        let result = repo.verify_audit_chain(None).await.unwrap();
+        let brk = result.broken.unwrap();
+        assert_eq!(brk.id, "forged");
+        assert_eq!(brk.agent_id, "default");
+        assert_eq!(brk.index, 2);
+    }
+
+    #[tokio::test]
+    async fn test_audit_entries_record_provenance() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2, "feature": true}), json!({}));
+        let h2 = repo
+            .commit(&s2, "feature work", ActionType::Checkpoint)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+        let merged = repo
+            .merge_with_options(
+                "feature",
+                MergeStrategy::ThreeWay,
+                MergeOptions { no_ff: true },
+            )
+            .await
+            .unwrap();
+
+        let entry_for = |hash: &Hash| {
+            let filter = LogFilter {
+                commit_hash: Some(hash.to_string()),
+                ..Default::default()
+            };
+            let repo = &repo;
+            async move { repo.audit_log(&filter).await.unwrap().remove(0) }
+        };
+
+        let commit = entry_for(&h2).await;
+        let details = commit.details.unwrap();
+        assert_eq!(details[audit::CHAIN_VERSION_KEY], 2);
+        let provenance = CommitProvenance::from_details(&details).unwrap();
+        let stored = repo.get_commit(h2.as_str()).await.unwrap().unwrap();
+        assert_eq!(provenance.tree_hash, stored.tree_hash.to_string());
+        assert_eq!(provenance.parent_hashes, vec![h1.to_string()]);
+        assert_eq!(provenance.action_type, ActionType::Checkpoint);
+        assert_eq!(
+            provenance.state_size,
+            canonical_serialize(&s2.to_value()).len() as u64
+        );
+
+        let merge = entry_for(&merged).await;
+        let provenance = CommitProvenance::from_details(merge.details.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            provenance.parent_hashes,
+            vec![h1.to_string(), h2.to_string()]
+        );
+        assert_eq!(provenance.action_type, ActionType::Merge);
+        let merged_state = repo.get_state(merged.as_str()).await.unwrap();
+        assert_eq!(
+            provenance.state_size,
+            canonical_serialize(&merged_state.to_value()).len() as u64
+        );
+
+        // Entries that reference existing commits record them too
+        repo.reset("feature", h1.as_str()).await.unwrap();
+        let reset = repo
+            .audit_log(&LogFilter::default())
+            .await
+            .unwrap()
+            .remove(0);
+        assert_eq!(reset.action, "reset");
+        let provenance = CommitProvenance::from_details(reset.details.as_ref().unwrap()).unwrap();
+        assert_eq!(provenance.parent_hashes, Vec::<String>::new());
+
+        let result = repo.verify_audit_chain(None).await.unwrap();
+        assert!(result.is_valid());
+        assert_eq!(result.entries_checked, 4);
+    }
+
+    #[tokio::test]
+    async fn test_apply_log_retention() {
+        let mut repo = test_repo().await;
+        for v in 0..4 {
+            let state = AgentState::new(json!({"v": v}), json!({}));
+            repo.commit(&state, "step", ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
+        repo.set_agent_id("other");
+        for v in 4..7 {
+            let state = AgentState::new(json!({"v": v}), json!({}));
+            repo.commit(&state, "step", ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
+        let stale = LogEntry {
+            id: "stale".to_string(),
+            timestamp: "2020-01-01T00:00:00+00:00".to_string(),
+            agent_id: "other".to_string(),
+            action: "tool_call".to_string(),
+            message: "written before chaining".to_string(),
+            commit_hash: None,
+            details: None,
+            level: LogLevel::Info,
+        };
+        repo.storage.append_log(&stale).await.unwrap();
+        repo.set_agent_id("default");
+
+        let policy = RetentionPolicy {
+            max_log_age: Some(Duration::from_secs(30 * 24 * 3600)),
+            max_log_entries: Some(2),
+            ..Default::default()
+        };
+        let result = repo.apply_log_retention(&policy).await.unwrap();
+        assert_eq!(result.expired_by_age, 1);
+        assert_eq!(result.over_limit, 3);
+
+        let count = |agent: &str| LogFilter {
+            agent_id: Some(agent.to_string()),
+            ..Default::default()
+        };
+        let other = repo.audit_log(&count("other")).await.unwrap();
+        assert_eq!(other.len(), 2);
+        assert!(other.iter().all(|e| e.id != "stale"));
+        // The newest two plus the retention summary
+        let default = repo.audit_log(&count("default")).await.unwrap();
+        assert_eq!(default.len(), 3);
+        assert_eq!(default[0].action, audit::RETENTION_ACTION);
+
+        assert!(repo.verify_audit_chain(None).await.unwrap().is_valid());
+        assert!(repo
+            .verify_audit_chain(Some("other"))
+            .await
+            .unwrap()
+            .is_valid());
+
+        // Later runs keep the truncated chains verifiable
+        let result = repo.apply_log_retention(&policy).await.unwrap();
+        assert_eq!(result.total(), 1);
+        assert!(repo.verify_audit_chain(None).await.unwrap().is_valid());
+
+        let result = repo
+            .apply_log_retention(&RetentionPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(result, LogRetentionResult::default());
+    }
+
+    /// Commit `v` onto `branch` with a timestamp `age` in the past.
+    async fn commit_aged(
+        repo: &mut Repository,
+        branch: &str,
+        v: i64,
+        age: chrono::Duration,
+    ) -> Hash {
+        let state = AgentState::new(json!({ "v": v }), json!({}));
+        let commit = Commit {
+            tree_hash: repo.store_state(state.to_value(), None).await.unwrap(),
+            parent_hashes: repo.refs.resolve_ref(branch).ok().into_iter().collect(),
+            message: format!("v{}", v),
+            author: "default".to_string(),
+            timestamp: Utc::now() - age,
+            action_type: ActionType::ToolCall,
+            metadata: serde_json::Map::new(),
+        };
+        let hash = commit.hash();
+        repo.storage
+            .put_object(
+                hash.as_str(),
+                ObjectType::Commit,
+                &serde_json::to_vec(&commit).unwrap(),
+            )
+            .await
+            .unwrap();
+        if repo.refs.list_branches().contains_key(branch) {
+            repo.reset(branch, hash.as_str()).await.unwrap();
+        } else {
+            repo.refs.create_branch(branch, hash.clone()).unwrap();
+            repo.storage.set_ref(branch, hash.as_str()).await.unwrap();
+        }
+        hash
+    }
+
+    #[tokio::test]
+    async fn test_apply_retention_truncates_by_count() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let mut feature = Vec::new();
+        for v in 2..7 {
+            let state = AgentState::new(json!({"v": v}), json!({}));
+            feature.push(
+                repo.commit(&state, "work", ActionType::ToolCall)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let policy = RetentionPolicy {
+            max_commits: Some(2),
+            ..Default::default()
+        };
+        let result = repo.apply_retention(&policy).await.unwrap();
+        assert_eq!(result.branches_truncated, vec!["feature".to_string()]);
+        assert_eq!(result.commits_retained, 2);
+        // v4, v3, v2 and the shared initial commit
+        assert_eq!(result.commits_expired, 4);
+        // Five replaced commits plus the blobs and trees of v2..v4
+        assert_eq!(result.objects_removed, 11);
+
+        let history = repo.log(Some("feature"), 10).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history[1].1.parent_hashes.is_empty());
+        let tip = repo.list_branches()["feature"].clone();
+        assert_eq!(
+            repo.get_state(tip.as_str()).await.unwrap().memory,
+            json!({"v": 6})
+        );
+        for hash in &feature {
+            assert!(repo.get_commit(hash.as_str()).await.unwrap().is_none());
+        }
+
+        // main is kept whole and still owns the initial commit
+        assert_eq!(repo.list_branches()["main"], h1);
+        assert!(repo.get_commit(h1.as_str()).await.unwrap().is_some());
+
+        let entry = repo
+            .audit_log(&LogFilter::default())
+            .await
+            .unwrap()
+            .remove(0);
+        assert_eq!(entry.action, "retention");
+        assert_eq!(entry.details.unwrap()["objects_removed"], 11);
+        assert!(repo.verify_audit_chain(None).await.unwrap().is_valid());
+
+        // Already within the policy: nothing to do
+        let result = repo.apply_retention(&policy).await.unwrap();
+        assert!(result.branches_truncated.is_empty());
+        assert_eq!(result.objects_removed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_apply_retention_age_wins_over_count() {
+        let mut repo = test_repo().await;
+        let hours = chrono::Duration::hours;
+        commit_aged(&mut repo, "main", 1, hours(3)).await;
+        let main_tip = commit_aged(&mut repo, "main", 2, hours(2)).await;
+        repo.branch("feature", None).await.unwrap();
+        commit_aged(&mut repo, "feature", 3, hours(2)).await;
+        commit_aged(&mut repo, "feature", 4, hours(0)).await;
+        commit_aged(&mut repo, "feature", 5, hours(0)).await;
+
+        // The old feature commit is within the count limit but too old
+        let policy = RetentionPolicy {
+            max_commits: Some(5),
+            max_age: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let result = repo.apply_retention(&policy).await.unwrap();
+        assert_eq!(result.branches_truncated, vec!["feature".to_string()]);
+        assert_eq!(result.commits_retained, 2);
+        assert_eq!(result.commits_expired, 3);
+        let history = repo.log(Some("feature"), 10).await.unwrap();
+        let versions: Vec<_> = history.iter().map(|(_, c)| c.message.as_str()).collect();
+        assert_eq!(versions, vec!["v5", "v4"]);
+
+        // Protected by keep_branches, main keeps its old commits
+        assert_eq!(repo.list_branches()["main"], main_tip);
+        assert_eq!(repo.log(Some("main"), 10).await.unwrap().len(), 2);
+
+        // Unprotected, main is cut down to its tip, which is always kept
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(3600)),
+            keep_branches: Vec::new(),
+            ..Default::default()
+        };
+        let result = repo.apply_retention(&policy).await.unwrap();
+        assert_eq!(result.branches_truncated, vec!["main".to_string()]);
+        assert_eq!(result.commits_expired, 1);
+        let history = repo.log(Some("main"), 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].1.message, "v2");
+        assert!(history[0].1.parent_hashes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_log_warning_and_error() {
+        let repo = test_repo().await;
+        repo.log_warning("disk nearly full", Some(json!({"free_mb": 12})))
+            .await
+            .unwrap();
+        repo.log_error("tool crashed", None).await.unwrap();
+
+        let warnings = repo
+            .audit_log(&LogFilter {
+                level: Some(LogLevel::Warn),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].action, "warning");
+        assert_eq!(warnings[0].details.as_ref().unwrap()["free_mb"], 12);
+
+        let errors = repo
+            .audit_log(&LogFilter {
+                level: Some(LogLevel::Error),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "tool crashed");
+
+        assert!(repo.verify_audit_chain(None).await.unwrap().is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_denied_operations_logged_as_warnings() {
+        use crate::protection::ProtectionRule;
+
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "initial", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.set_branch_protection(BranchProtection {
+            rules: vec![ProtectionRule::new("main")],
+        })
+        .await
+        .unwrap();
+
+        repo.reset("main", h1.as_str()).await.unwrap_err();
+        repo.delete_branch("main", false).await.unwrap_err();
+
+        let warnings = repo
+            .audit_log(&LogFilter {
+                level: Some(LogLevel::Warn),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let actions: Vec<_> = warnings.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["delete_branch_denied", "reset_denied"]);
+        assert!(warnings[0].details.as_ref().unwrap()["error"].is_string());
+        assert!(repo.verify_audit_chain(None).await.unwrap().is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_watch_logs() {
+        let repo = test_repo().await;
+        let entry = |id: &str, timestamp: &str, agent_id: &str| LogEntry {
+            id: id.to_string(),
+            timestamp: timestamp.to_string(),
+            agent_id: agent_id.to_string(),
+            action: "tool_call".to_string(),
+            message: id.to_string(),
+            commit_hash: None,
+            details: None,
+            level: LogLevel::Info,
+        };
+        let ts = "2030-01-01T00:00:00+00:00";
+        repo.storage
+            .append_log(&entry("old", ts, "watched"))
+            .await
+            .unwrap();
+
+        let filter = LogFilter {
+            agent_id: Some("watched".to_string()),
+            ..Default::default()
+        };
+        let mut logs = Box::pin(repo.watch_logs(filter.clone(), Duration::from_millis(10)));
+        let append = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // Same timestamp as the existing entry, which must not repeat
+            repo.storage
+                .append_log(&entry("new-1", ts, "watched"))
+                .await
+                .unwrap();
+            repo.storage
+                .append_log(&entry("other", ts, "someone"))
+                .await
+                .unwrap();
+            repo.storage
+                .append_log(&entry("new-2", "2030-01-01T00:00:01+00:00", "watched"))
+                .await
+                .unwrap();
+        };
+        let next_two = async { [logs.next().await.unwrap().id, logs.next().await.unwrap().id] };
+        let (_, ids) = tokio::join!(append, next_two);
+        assert_eq!(ids, ["new-1", "new-2"]);
+        let more = tokio::time::timeout(Duration::from_millis(50), logs.next()).await;
+        assert!(more.is_err());
+
+        // `since` replays earlier entries first
+        let filter = LogFilter {
+            since: Some(ts.to_string()),
+            ..filter
+        };
+        let replay: Vec<String> = repo
+            .watch_logs(filter, Duration::from_millis(10))
+            .take(3)
+            .map(|e| e.id)
+            .collect()
+            .await;
+        assert_eq!(replay, ["old", "new-1", "new-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_path_history() {
+        let mut repo = test_repo().await;
+        let path = vec!["memory".to_string(), "confidence".to_string()];
+
+        let s1 = AgentState::new(json!({"step": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "no confidence yet", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(json!({"step": 2, "confidence": 0.5}), json!({}));
+        let h2 = repo.commit(&s2, "set", ActionType::ToolCall).await.unwrap();
+        let s3 = AgentState::new(json!({"step": 3, "confidence": 0.5}), json!({}));
+        repo.commit(&s3, "unrelated change", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s4 = AgentState::new(json!({"step": 4, "confidence": 0.9}), json!({}));
+        let h4 = repo
+            .commit(&s4, "raise", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s5 = AgentState::new(json!({"step": 5}), json!({}));
+        let h5 = repo
+            .commit(&s5, "drop", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let history = repo.path_history(&path, None, 10).await.unwrap();
+        let summary: Vec<(Hash, Option<Value>)> =
+            history.into_iter().map(|(h, _, v)| (h, v)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (h5, None),
+                (h4.clone(), Some(json!(0.9))),
+                (h2, Some(json!(0.5))),
+            ]
+        );
+        assert!(!summary.iter().any(|(h, _)| *h == h1));
+
+        let limited = repo.path_history(&path, Some("main"), 2).await.unwrap();
+        assert_eq!(limited.len(), 2);
+        assert_eq!(limited[1].0, h4);
+    }
+
+    #[tokio::test]
+    async fn test_path_history_follows_ancestry_not_clock() {
+        let mut repo = test_repo().await;
+        let path = vec!["memory".to_string(), "message".to_string()];
+        let t = |secs: i64| DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+        let c1 = commit_at_time(&mut repo, &[], "c1", t(300)).await;
+        let c2 = commit_at_time(&mut repo, &[&c1], "c2", t(200)).await;
+        let c3 = commit_at_time(&mut repo, &[&c2], "c3", t(200)).await;
+
+        let history = repo.path_history(&path, None, 10).await.unwrap();
+        let hashes: Vec<Hash> = history.into_iter().map(|(h, _, _)| h).collect();
+        assert_eq!(hashes, vec![c3.clone(), c2, c1]);
+
+        let limited = repo.path_history(&path, None, 1).await.unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].0, c3);
+        assert_eq!(limited[0].2, Some(json!("c3")));
+    }
+    #[tokio::test]
+    async fn test_cost_summary() {
+        let mut repo = test_repo().await;
+        let mut s1 = AgentState::new(json!({"v": 1}), json!({}));
+        s1.cost = 0.5;
+        repo.commit(&s1, "llm", ActionType::LlmResponse)
+            .await
+            .unwrap();
+        repo.set_agent_id("tools");
+        let mut s2 = AgentState::new(json!({"v": 2}), json!({}));
+        s2.cost = 2.0;
+        let h2 = repo
+            .commit(&s2, "tool", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let cutoff = Utc::now();
+        let mut s3 = AgentState::new(json!({"v": 3}), json!({}));
+        s3.cost = 1.0;
+        let h3 = repo
+            .commit(&s3, "tool again", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let summary = repo.cost_summary(None, None).await.unwrap();
+        assert_eq!(summary.commits, 3);
+        assert_eq!(summary.total, 3.5);
+        assert_eq!(summary.by_action_type["tool_call"], 3.0);
+        assert_eq!(summary.by_action_type["llm_response"], 0.5);
+        assert_eq!(summary.by_author["tools"], 3.0);
+        assert_eq!(summary.by_author["default"], 0.5);
+        assert_eq!(summary.top_commits[0], (h2, 2.0));
+
+        let recent = repo.cost_summary(Some("main"), Some(cutoff)).await.unwrap();
+        assert_eq!(recent.commits, 1);
+        assert_eq!(recent.top_commits, vec![(h3, 1.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_cost_summary_without_cached_cost() {
+        let mut repo = test_repo().await;
+        let mut state = AgentState::new(json!({}), json!({}));
+        state.cost = 4.0;
+        let hash = repo
+            .commit(&state, "old", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        // Rewrite the commit as it would have been stored before cost caching
+        let mut commit = repo.get_commit(hash.as_str()).await.unwrap().unwrap();
+        commit.metadata.remove(COST_METADATA_KEY);
+        let legacy = commit.hash();
+        repo.storage
+            .put_object(
+                legacy.as_str(),
+                ObjectType::Commit,
+                &serde_json::to_vec(&commit).unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.branch("legacy", Some(legacy.as_str())).await.unwrap();
+
+        let summary = repo.cost_summary(Some("legacy"), None).await.unwrap();
+        assert_eq!(summary.total, 4.0);
+    }
+    #[tokio::test]
+    async fn test_state_at_follows_first_parent() {
+        let mut repo = test_repo().await;
+        let before = Utc::now();
+        let s1 = AgentState::new(json!({"v": "main-1"}), json!({}));
+        let h1 = repo
+            .commit(&s1, "main 1", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.branch("feature", None).await.unwrap();
+        repo.checkout("feature").await.unwrap();
+        let s2 = AgentState::new(json!({"v": "feature-1"}), json!({}));
+        repo.commit(&s2, "feature 1", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let after_feature = Utc::now();
+
+        repo.checkout("main").await.unwrap();
+        let merge = repo
+            .merge_with_options(
+                "feature",
+                MergeStrategy::Theirs,
+                MergeOptions { no_ff: true },
+            )
+            .await
+            .unwrap();
+
+        // The feature commit is the newest ancestor before the merge, but it
+        // was not on main at that time
+        let (hash, state) = repo.state_at("main", after_feature).await.unwrap();
+        assert_eq!(hash, h1);
+        assert_eq!(state.memory, json!({"v": "main-1"}));
+
+        let (hash, _) = repo.state_at("main", Utc::now()).await.unwrap();
+        assert_eq!(hash, merge);
+
+        let err = repo.state_at("main", before).await.unwrap_err();
+        assert!(matches!(err, AgitError::NoCommits));
+    }
+
+    #[tokio::test]
+    async fn test_checkout_at_detaches_head() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let t1 = Utc::now();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let (hash, state) = repo.checkout_at("main", t1).await.unwrap();
+        assert_eq!(hash, h1);
+        assert_eq!(state.memory, json!({"v": 1}));
+        assert_eq!(repo.current_branch(), None);
+        assert_eq!(repo.head().unwrap(), h1);
+    }
+    #[tokio::test]
+    async fn test_revert_paths() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(
+            json!({"notes": ["a", "b"], "plan": {"step": 1}}),
+            json!({"browser": {"url": "https://a.example"}, "files": 1}),
+        );
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(
+            json!({"notes": ["a", "changed"], "plan": {"step": 2}, "scratch": true}),
+            json!({"browser": {"url": "https://b.example", "tabs": 3}, "files": 2}),
+        );
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let hash = repo
+            .revert_paths(
+                h1.as_str(),
+                &[
+                    path(&["world_state", "browser"]),
+                    path(&["memory", "notes", "1"]),
+                    // Absent at the target: removed
+                    path(&["memory", "scratch"]),
+                    // Absent on both sides: no-op
+                    path(&["memory", "missing", "deep"]),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let state = repo.get_state(hash.as_str()).await.unwrap();
+        assert_eq!(
+            state.memory,
+            json!({"notes": ["a", "b"], "plan": {"step": 2}})
+        );
+        assert_eq!(
+            state.world_state,
+            json!({"browser": {"url": "https://a.example"}, "files": 2})
+        );
+
+        let commit = repo.get_commit(hash.as_str()).await.unwrap().unwrap();
+        assert_eq!(commit.action_type, ActionType::Rollback);
+        assert_eq!(
+            commit.metadata["reverted_paths"][0],
+            json!(["world_state", "browser"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revert_paths_creates_missing_intermediates() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"config": {"retry": {"max": 3}}}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(json!({}), json!({}));
+        repo.commit(&s2, "cleared", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        let path: Vec<String> = ["memory", "config", "retry", "max"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let hash = repo.revert_paths(h1.as_str(), &[path]).await.unwrap();
+        let state = repo.get_state(hash.as_str()).await.unwrap();
+        assert_eq!(state.memory, json!({"config": {"retry": {"max": 3}}}));
+
+        assert!(repo.revert_paths(h1.as_str(), &[]).await.is_err());
+    }
+    #[tokio::test]
+    async fn test_apply_diff_between_repos() {
+        let mut source = test_repo().await;
+        let s1 = AgentState::new(json!({"plan": {"step": 1}}), json!({"cwd": "/"}));
+        let h1 = source
+            .commit(&s1, "one", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(
+            json!({"plan": {"step": 2}, "note": "hi"}),
+            json!({"cwd": "/"}),
+        );
+        let h2 = source
+            .commit(&s2, "two", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let diff = source.diff(h1.as_str(), h2.as_str()).await.unwrap();
+
+        let mut dest = test_repo().await;
+        let base = AgentState::new(json!({"plan": {"step": 1}}), json!({"cwd": "/"}));
+        dest.commit(&base, "base", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let hash = dest.apply_diff(&diff, "apply patch").await.unwrap();
+
+        let state = dest.get_state(hash.as_str()).await.unwrap();
+        assert_eq!(state.memory, s2.memory);
+        let commit = dest.get_commit(hash.as_str()).await.unwrap().unwrap();
+        assert_eq!(commit.metadata["patch_target"], json!(h2.as_str()));
+
+        // Re-applying conflicts: the base no longer matches
+        let err = dest.apply_diff(&diff, "again").await.unwrap_err();
+        assert!(matches!(err, AgitError::PatchConflict { .. }));
+    }
+    #[tokio::test]
+    async fn test_merge_with_config_appends() {
+        use crate::state::ArrayMergeStrategy;
+
+        let mut repo = test_repo().await;
+        let base = AgentState::new(json!({"messages": ["hello"]}), json!({}));
+        repo.commit(&base, "base", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+
+        let mut ours = base.clone();
+        ours.memory = json!({"messages": ["hello", "from main"]});
+        repo.commit(&ours, "main msg", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.checkout("feature").await.unwrap();
+        let mut theirs = base.clone();
+        theirs.memory = json!({"messages": ["hello", "from feature"]});
+        repo.commit(&theirs, "feature msg", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+
+        let err = repo
+            .merge("feature", MergeStrategy::ThreeWay)
+            .await
+            .unwrap_err();
+        match err {
+            AgitError::MergeConflict { conflicts } => {
+                assert_eq!(conflicts.len(), 1);
+                assert_eq!(conflicts[0].dotted_path(), "memory.messages");
+                assert_eq!(conflicts[0].ours_value, Some(json!(["hello", "from main"])));
+                assert_eq!(
+                    conflicts[0].theirs_value,
+                    Some(json!(["hello", "from feature"]))
+                );
+            }
+            other => panic!("expected a merge conflict, got {other:?}"),
+        }
+
+        let config = MergeConfig::default().with_array_strategy_at(
+            vec!["memory".to_string(), "messages".to_string()],
+            ArrayMergeStrategy::ConcatOursFirst,
+        );
+        let hash = repo
+            .merge_with_config("feature", MergeOptions::default(), &config)
+            .await
+            .unwrap();
+        let state = repo.get_state(hash.as_str()).await.unwrap();
+        assert_eq!(
+            state.memory,
+            json!({"messages": ["hello", "from main", "from feature"]})
+        );
+    }
+    #[tokio::test]
+    async fn test_merge_with_config_drivers() {
+        use crate::merge_driver::{BuiltinDriver, MergeDriverRegistry};
+
+        let mut repo = test_repo().await;
+        let base = AgentState::new(
+            json!({"scratchpad": "base", "goal": "a"}),
+            json!({"counters": {"n": 1}}),
+        );
+        repo.commit(&base, "base", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+
+        let mut ours = base.clone();
+        ours.memory = json!({"scratchpad": "ours", "goal": "b"});
+        ours.world_state = json!({"counters": {"n": 3}});
+        repo.commit(&ours, "ours", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.checkout("feature").await.unwrap();
+        let mut theirs = base.clone();
+        theirs.memory = json!({"scratchpad": "theirs", "goal": "c"});
+        theirs.world_state = json!({"counters": {"n": 7}});
+        repo.commit(&theirs, "theirs", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+
+        let mut drivers = MergeDriverRegistry::new();
+        drivers
+            .register_builtin("memory.scratchpad", BuiltinDriver::TakeTheirs)
+            .register_builtin("world_state.counters.*", BuiltinDriver::NumericMax);
+        let config = MergeConfig::default().with_drivers(drivers.clone());
+
+        // `memory.goal` has no driver, so the merge still conflicts
+        let err = repo
+            .merge_with_config("feature", MergeOptions::default(), &config)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("memory.goal"));
+
+        drivers.register("memory.goal", |_, ours, theirs| {
+            Some(json!(format!("{}+{}", ours.as_str()?, theirs.as_str()?)))
+        });
+        let config = MergeConfig::default().with_drivers(drivers);
+        let hash = repo
+            .merge_with_config("feature", MergeOptions::default(), &config)
+            .await
+            .unwrap();
+        let state = repo.get_state(hash.as_str()).await.unwrap();
+        assert_eq!(state.memory, json!({"scratchpad": "theirs", "goal": "b+c"}));
+        assert_eq!(state.world_state, json!({"counters": {"n": 7}}));
+    }
+    #[tokio::test]
+    async fn test_diff_with_options_ignores_volatile_fields() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"answer": 1}), json!({"req": {"request_id": "a"}}));
+        let h1 = repo.commit(&s1, "one", ActionType::ToolCall).await.unwrap();
+        let s2 = AgentState::new(json!({"answer": 2}), json!({"req": {"request_id": "b"}}));
+        let h2 = repo.commit(&s2, "two", ActionType::ToolCall).await.unwrap();
+
+        let options = DiffOptions {
+            ignore_paths: vec![vec!["timestamp".to_string()]],
+            ignore_globs: vec!["world_state.*.request_id".to_string()],
+        };
+        let diff = repo
+            .diff_with_options(h1.as_str(), h2.as_str(), &options)
+            .await
+            .unwrap();
+        assert_eq!(diff.entries.len(), 1);
+        assert_eq!(
+            diff.entries[0].path,
+            vec!["memory".to_string(), "answer".to_string()]
+        );
+    }
+    #[tokio::test]
+    async fn test_diff_stats_matches_diff() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"a": 1, "b": 2}), json!({}));
+        let h1 = repo.commit(&s1, "one", ActionType::ToolCall).await.unwrap();
+        let s2 = AgentState::new(json!({"a": 1, "c": 3}), json!({"x": 1}));
+        let h2 = repo.commit(&s2, "two", ActionType::ToolCall).await.unwrap();
+
+        let stats = repo.diff_stats(h1.as_str(), h2.as_str()).await.unwrap();
+        let diff = repo.diff(h1.as_str(), h2.as_str()).await.unwrap();
+        assert_eq!(stats, diff.stats());
+        assert_eq!(stats.by_top_level_key["memory"], 2);
+        assert_eq!(stats.by_top_level_key["world_state"], 1);
+    }
+    #[tokio::test]
+    async fn test_merge_report() {
+        let mut repo = test_repo().await;
+        let base = AgentState::new(json!({"a": 1, "b": 1}), json!({"x": 1}));
+        let base_hash = repo
+            .commit(&base, "base", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("feature", None).await.unwrap();
+
+        let mut ours = base.clone();
+        ours.memory = json!({"a": 2, "b": 1});
+        repo.commit(&ours, "ours", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        repo.checkout("feature").await.unwrap();
+        let mut theirs = base.clone();
+        theirs.memory = json!({"a": 1, "b": 3});
+        theirs.world_state = json!({"x": 2});
+        repo.commit(&theirs, "theirs", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+
+        let hash = repo
+            .merge("feature", MergeStrategy::ThreeWay)
+            .await
+            .unwrap();
+        let report = repo.merge_report(hash.as_str()).await.unwrap().unwrap();
+        assert_eq!(report.base_hash, base_hash.0);
+        assert_eq!(report.strategy, MergeStrategy::ThreeWay);
+        assert_eq!(report.auto_merged, 3);
+        assert_eq!(
+            report.from_ours,
+            vec![vec!["memory".to_string(), "a".to_string()]]
+        );
+        assert_eq!(
+            report.from_theirs,
+            vec![
+                vec!["memory".to_string(), "b".to_string()],
+                vec!["world_state".to_string()],
+            ]
+        );
+        assert!(report.resolved.is_empty());
+
+        // Non-merge commits carry no report
+        assert!(repo
+            .merge_report(base_hash.as_str())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            repo.merge_report("missing").await,
+            Err(AgitError::ObjectNotFound { .. })
+        ));
+    }
+    #[tokio::test]
+    async fn test_diff_uses_cached_trees() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"a": 1, "b": {"c": 1}}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let mut s2 = s1.clone();
+        s2.memory = json!({"a": 1, "b": {"c": 2}});
+        let h2 = repo
+            .commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        // Each committed blob has a tree object stored alongside it
+        let c1 = repo.get_commit(h1.as_str()).await.unwrap().unwrap();
+        let key = tree_key(c1.tree_hash.as_str());
+        let data = repo.storage.get_object(&key).await.unwrap().unwrap();
+        let tree: MerkleNode = serde_json::from_slice(&data).unwrap();
+        assert_eq!(tree.hash, MerkleNode::from_value(&s1.to_value()).hash);
+
+        let cached = repo.diff(h1.as_str(), h2.as_str()).await.unwrap();
+        assert_eq!(cached.entries.len(), 1);
+        assert_eq!(cached.entries[0].path, vec!["memory", "b", "c"]);
+
+        // Missing trees fall back to construction with the same result
+        repo.storage.delete_object(&key).await.unwrap();
+        let rebuilt = repo.diff(h1.as_str(), h2.as_str()).await.unwrap();
+        assert_eq!(rebuilt.entries.len(), 1);
+        assert_eq!(rebuilt.entries[0].path, cached.entries[0].path);
+    }
+    #[tokio::test]
+    async fn test_gc_keeps_reachable_trees() {
+        let mut repo = test_repo().await;
+        let s1 = AgentState::new(json!({"v": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.branch("scratch", None).await.unwrap();
+        repo.checkout("scratch").await.unwrap();
+        let s2 = AgentState::new(json!({"v": 2}), json!({}));
+        let h2 = repo
+            .commit(&s2, "dropped", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.checkout("main").await.unwrap();
+        repo.delete_branch("scratch", false).await.unwrap();
+
+        let c1 = repo.get_commit(h1.as_str()).await.unwrap().unwrap();
+        let c2 = repo.get_commit(h2.as_str()).await.unwrap().unwrap();
+        repo.gc(0).await.unwrap();
+
+        let kept = tree_key(c1.tree_hash.as_str());
+        let dropped = tree_key(c2.tree_hash.as_str());
+        assert!(repo.storage.has_object(&kept).await.unwrap());
+        assert!(!repo.storage.has_object(&dropped).await.unwrap());
+    }
+    #[tokio::test]
+    async fn test_state_size_limits() {
+        let storage = SqliteStorage::new(":memory:").await.unwrap();
+        let mut repo = Repository::init_with_options(
+            Box::new(storage),
+            RepoOptions {
+                max_state_bytes: 1024,
+                max_depth: 5,
+                max_keys: 20,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let big = AgentState::new(json!({"blob": "x".repeat(2048)}), json!({}));
+        let err = repo
+            .commit(&big, "big", ActionType::ToolCall)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::StateTooLarge { limit: 1024, .. }));
+
+        let deep = AgentState::new(json!({"a": {"b": {"c": {"d": {"e": 1}}}}}), json!({}));
+        let err = repo
+            .commit(&deep, "deep", ActionType::ToolCall)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AgitError::StateTooDeep { depth: 6, limit: 5 }
+        ));
+
+        let keys: serde_json::Map<String, Value> =
+            (0..30).map(|i| (format!("k{}", i), json!(i))).collect();
+        let wide = AgentState::new(Value::Object(keys), json!({}));
+        let err = repo
+            .commit(&wide, "wide", ActionType::ToolCall)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::StateTooManyKeys { limit: 20, .. }));
 
-        let timestamp = Utc::now().to_rfc3339();
-        let id = Uuid::new_v4().to_string();
-        let chain_hash = compute_audit_hash(
-            &id,
-            &timestamp,
-            &self.agent_id,
-            action,
-            message,
-            commit_hash.unwrap_or(""),
-            prev_hash.as_deref(),
+        // Nothing was committed
+        assert!(matches!(repo.head(), Err(AgitError::NoCommits)));
+
+        let ok = AgentState::new(json!({"a": 1}), json!({}));
+        let hash = repo.commit(&ok, "ok", ActionType::ToolCall).await.unwrap();
+        assert_eq!(
+            repo.get_state(hash.as_str()).await.unwrap().memory,
+            json!({"a": 1})
         );
 
-        let entry = LogEntry {
-            id,
-            timestamp,
-            agent_id: self.agent_id.clone(),
-            action: action.to_string(),
-            message: message.to_string(),
-            commit_hash: commit_hash.map(|s| s.to_string()),
-            details: Some(serde_json::json!({
-                "integrity_hash": chain_hash,
-                "prev_integrity_hash": prev_hash,
-            })),
-            level: "info".to_string(),
-        };
-        self.storage.append_log(&entry).await
+        // Zero disables a limit
+        repo.options.max_state_bytes = 0;
+        repo.commit(&big, "big", ActionType::ToolCall)
+            .await
+            .unwrap();
     }
-}
 
-fn compute_audit_hash(
-    id: &str,
-    timestamp: &str,
-    agent_id: &str,
-    action: &str,
-    message: &str,
-    commit_hash: &str,
-    prev_hash: Option<&str>,
-) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(id.as_bytes());
-    hasher.update(b"|");
-    hasher.update(timestamp.as_bytes());
-    hasher.update(b"|");
-    hasher.update(agent_id.as_bytes());
-    hasher.update(b"|");
-    hasher.update(action.as_bytes());
-    hasher.update(b"|");
-    hasher.update(message.as_bytes());
-    hasher.update(b"|");
-    hasher.update(commit_hash.as_bytes());
-    hasher.update(b"|");
-    hasher.update(prev_hash.unwrap_or("").as_bytes());
-    format!("{:x}", hasher.finalize())
-}
+    #[tokio::test]
+    async fn test_get_state_path() {
+        async fn get(repo: &Repository, hash: &Hash, p: &str) -> Result<Option<Value>> {
+            let path: Vec<String> = p.split('.').map(String::from).collect();
+            repo.get_state_path(hash.as_str(), &path).await
+        }
+        let page = "x".repeat(300 * 1024);
+        let state = AgentState::new(
+            json!({"page": page, "notes": ["a", {"b": 2}]}),
+            json!({"cursor_position": {"line": 3}}),
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::storage::sqlite::SqliteStorage;
-    use serde_json::json;
+        // The target sits after a field larger than the first read window
+        let mut repo = test_repo().await;
+        let hash = repo
+            .commit(&state, "big", ActionType::ToolCall)
+            .await
+            .unwrap();
+        assert_eq!(
+            get(&repo, &hash, "world_state.cursor_position")
+                .await
+                .unwrap(),
+            Some(json!({"line": 3}))
+        );
+        assert_eq!(
+            get(&repo, &hash, "memory.notes.1.b").await.unwrap(),
+            Some(json!(2))
+        );
+        assert_eq!(get(&repo, &hash, "memory.missing").await.unwrap(), None);
+        assert_eq!(get(&repo, &hash, "memory.notes.5").await.unwrap(), None);
+        assert!(matches!(
+            get(&repo, &Hash("deadbeef".into()), "memory").await,
+            Err(AgitError::ObjectNotFound { .. })
+        ));
 
-    async fn test_repo() -> Repository {
+        // Chunked fields are reassembled, including when the path ends inside one
         let storage = SqliteStorage::new(":memory:").await.unwrap();
-        Repository::init(Box::new(storage)).await.unwrap()
+        let mut repo = Repository::init_with_options(
+            Box::new(storage),
+            RepoOptions {
+                chunking: Some(ChunkingOptions {
+                    threshold: 1024,
+                    min_chunk_size: 256,
+                    avg_chunk_size: 1024,
+                    max_chunk_size: 4096,
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let chunked = AgentState::new(
+            json!({"big": {"items": (0..500).collect::<Vec<_>>()}, "page": page}),
+            json!({"cursor_position": 7}),
+        );
+        let hash = repo
+            .commit(&chunked, "chunked", ActionType::ToolCall)
+            .await
+            .unwrap();
+        assert_eq!(
+            get(&repo, &hash, "memory.page").await.unwrap(),
+            Some(json!(page))
+        );
+        assert_eq!(
+            get(&repo, &hash, "memory.big.items.499").await.unwrap(),
+            Some(json!(499))
+        );
+        assert_eq!(
+            get(&repo, &hash, "world_state.cursor_position")
+                .await
+                .unwrap(),
+            Some(json!(7))
+        );
     }
 
     #[tokio::test]
-    async fn test_init() {
-        let repo = test_repo().await;
-        assert_eq!(repo.current_branch(), Some("main"));
+    async fn test_merkle_root_in_commit() {
+        let path = vec!["world_state".to_string(), "cursor".to_string()];
+        let mut repo = test_repo().await;
+        let state = AgentState::new(json!({"a": 1}), json!({"cursor": 3}));
+        let hash = repo
+            .commit(&state, "plain", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let commit = repo.get_commit(hash.as_str()).await.unwrap().unwrap();
+        assert!(!commit.metadata.contains_key(MERKLE_ROOT_METADATA_KEY));
+
+        repo.options.embed_merkle_root = true;
+        let hash = repo
+            .commit(&state, "rooted", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let commit = repo.get_commit(hash.as_str()).await.unwrap().unwrap();
+        let root = commit.metadata[MERKLE_ROOT_METADATA_KEY].as_str().unwrap();
+        assert_eq!(root, MerkleNode::from_value(&state.to_value()).hash);
+
+        let (value, proof) = repo
+            .merkle_proof(hash.as_str(), &path)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, json!(3));
+        assert!(proof.verify(root, &path, &value));
+        assert!(!proof.verify(root, &path, &json!(4)));
+        let missing = vec!["world_state".to_string(), "nope".to_string()];
+        assert!(repo
+            .merkle_proof(hash.as_str(), &missing)
+            .await
+            .unwrap()
+            .is_none());
     }
 
     #[tokio::test]
-    async fn test_commit_and_log() {
+    async fn test_storage_stats() {
         let mut repo = test_repo().await;
-        let state = AgentState::new(json!({"counter": 1}), json!({}));
+        let state = AgentState::new(json!({"a": 1}), json!({}));
         let hash = repo
-            .commit(&state, "first commit", ActionType::ToolCall)
+            .commit(&state, "one", ActionType::ToolCall)
             .await
             .unwrap();
-        assert_eq!(hash.0.len(), 64);
 
-        let commits = repo.log(None, 10).await.unwrap();
-        assert_eq!(commits.len(), 1);
-        assert_eq!(commits[0].message, "first commit");
-    }
+        let stats = repo.storage_stats().await.unwrap();
+        assert_eq!(stats.count, 3);
+        for obj_type in ["blob", "commit", "tree"] {
+            assert_eq!(stats.by_type[obj_type].count, 1, "{}", obj_type);
+        }
+        let commit_bytes = repo
+            .storage
+            .get_object(hash.as_str())
+            .await
+            .unwrap()
+            .unwrap()
+            .len();
+        assert_eq!(stats.by_type["commit"].bytes, commit_bytes as u64);
+        assert_eq!(
+            stats.bytes,
+            stats.by_type.values().map(|t| t.bytes).sum::<u64>()
+        );
 
+        let stat = repo
+            .storage
+            .stat_object(hash.as_str())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.obj_type, ObjectType::Commit);
+        assert_eq!(stat.size, commit_bytes as u64);
+        let age = Utc::now() - stat.created_at.unwrap();
+        assert!(age.num_seconds().abs() < 60);
+        assert!(repo.storage.stat_object("missing").await.unwrap().is_none());
+    }
     #[tokio::test]
-    async fn test_multiple_commits() {
+    async fn test_object_cache() {
         let mut repo = test_repo().await;
+        for step in 0..3 {
+            let state = AgentState::new(json!({"step": step}), json!({}));
+            repo.commit(&state, "step", ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
 
-        let s1 = AgentState::new(json!({"step": 1}), json!({}));
-        repo.commit(&s1, "step 1", ActionType::ToolCall).await.unwrap();
+        // Committing read each parent to stamp its child's generation, so
+        // only the tip is loaded from storage
+        let committed = repo.cache_stats();
+        repo.log(None, 10).await.unwrap();
+        let first = repo.cache_stats();
+        assert_eq!(first.misses, committed.misses + 1);
+        assert_eq!(first.entries, 3);
 
-        let s2 = AgentState::new(json!({"step": 2}), json!({}));
-        repo.commit(&s2, "step 2", ActionType::ToolCall).await.unwrap();
+        // A second walk is served from the cache
+        repo.log(None, 10).await.unwrap();
+        let second = repo.cache_stats();
+        assert_eq!(second.hits, first.hits + 3);
+        assert_eq!(second.misses, first.misses);
 
-        let s3 = AgentState::new(json!({"step": 3}), json!({}));
-        repo.commit(&s3, "step 3", ActionType::ToolCall).await.unwrap();
+        // Objects deleted by gc leave the cache
+        repo.branch("scratch", None).await.unwrap();
+        repo.checkout("scratch").await.unwrap();
+        let state = AgentState::new(json!({"scratch": true}), json!({}));
+        let scratch = repo
+            .commit(&state, "scratch", ActionType::ToolCall)
+            .await
+            .unwrap();
+        repo.get_state(scratch.as_str()).await.unwrap();
+        repo.checkout("main").await.unwrap();
+        repo.delete_branch("scratch", false).await.unwrap();
+        let before = repo.cache_stats().entries;
+        let result = repo.gc(0).await.unwrap();
+        assert!(result.removed.contains(&scratch.to_string()));
+        assert_eq!(repo.cache_stats().entries, before - 2);
 
-        let commits = repo.log(None, 10).await.unwrap();
-        assert_eq!(commits.len(), 3);
+        let mut disabled = Repository::init_with_options(
+            Box::new(SqliteStorage::new(":memory:").await.unwrap()),
+            RepoOptions {
+                cache_max_entries: 0,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let state = AgentState::new(json!({"step": 0}), json!({}));
+        disabled
+            .commit(&state, "step", ActionType::ToolCall)
+            .await
+            .unwrap();
+        disabled.log(None, 10).await.unwrap();
+        assert_eq!(disabled.cache_stats(), CacheStats::default());
+    }
+    /// Sqlite storage whose branch ref updates fail with `ConcurrentUpdate`
+    /// a set number of times, as if another writer kept getting there first.
+    /// Writes to HEAD fail while `head_fails` is set. Ref transactions use
+    /// the default `update_refs`, built on `set_ref`.
+    struct ContendedStorage {
+        inner: SqliteStorage,
+        conflicts: std::sync::atomic::AtomicUsize,
+        head_fails: Arc<std::sync::atomic::AtomicBool>,
     }
 
-    #[tokio::test]
-    async fn test_branch_and_checkout() {
-        let mut repo = test_repo().await;
-
-        let s1 = AgentState::new(json!({"v": 1}), json!({}));
-        repo.commit(&s1, "initial", ActionType::ToolCall).await.unwrap();
+    #[async_trait::async_trait]
+    impl StorageBackend for ContendedStorage {
+        async fn initialize(&self) -> Result<()> {
+            self.inner.initialize().await
+        }
+        async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+            self.inner.put_object(hash, obj_type, data).await
+        }
+        async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+            self.inner.get_object(hash).await
+        }
+        async fn has_object(&self, hash: &str) -> Result<bool> {
+            self.inner.has_object(hash).await
+        }
+        async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+            use std::sync::atomic::Ordering;
+            let contended = name != "HEAD"
+                && self
+                    .conflicts
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok();
+            if contended {
+                return Err(AgitError::ConcurrentUpdate {
+                    name: name.to_string(),
+                });
+            }
+            if name == "HEAD" && self.head_fails.load(Ordering::SeqCst) {
+                return Err(AgitError::storage("HEAD write failed"));
+            }
+            self.inner.set_ref(name, hash).await
+        }
+        async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+            self.inner.get_ref(name).await
+        }
+        async fn list_refs(&self) -> Result<HashMap<String, String>> {
+            self.inner.list_refs().await
+        }
+        async fn delete_ref(&self, name: &str) -> Result<bool> {
+            self.inner.delete_ref(name).await
+        }
+        async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+            self.inner.append_log(entry).await
+        }
+        async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+            self.inner.query_logs(filter).await
+        }
+        async fn delete_object(&self, hash: &str) -> Result<bool> {
+            self.inner.delete_object(hash).await
+        }
+        async fn list_objects(&self) -> Result<Vec<String>> {
+            self.inner.list_objects().await
+        }
+    }
 
-        repo.branch("feature", None).await.unwrap();
-        let state = repo.checkout("feature").await.unwrap();
-        assert_eq!(state.memory, json!({"v": 1}));
-        assert_eq!(repo.current_branch(), Some("feature"));
+    async fn contended_repo(conflicts: usize) -> Repository {
+        let storage = ContendedStorage {
+            inner: SqliteStorage::new(":memory:").await.unwrap(),
+            conflicts: conflicts.into(),
+            head_fails: Default::default(),
+        };
+        Repository::init(Box::new(storage)).await.unwrap()
     }
 
     #[tokio::test]
-    async fn test_diff() {
-        let mut repo = test_repo().await;
+    async fn test_commit_retries_concurrent_ref_updates() {
+        let mut repo = contended_repo(REF_UPDATE_RETRIES).await;
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        let hash = repo
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        assert_eq!(repo.head().unwrap(), hash);
+        assert_eq!(
+            repo.storage.get_ref("main").await.unwrap(),
+            Some(hash.to_string())
+        );
 
-        let s1 = AgentState::new(json!({"a": 1, "b": 2}), json!({}));
-        let h1 = repo.commit(&s1, "first", ActionType::ToolCall).await.unwrap();
+        let mut repo = contended_repo(REF_UPDATE_RETRIES + 1).await;
+        let err = repo
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgitError::ConcurrentUpdate { name } if name == "main"));
+    }
 
-        let s2 = AgentState::new(json!({"a": 1, "b": 3, "c": 4}), json!({}));
-        let h2 = repo.commit(&s2, "second", ActionType::ToolCall).await.unwrap();
+    #[tokio::test]
+    async fn test_failed_head_write_rolls_back_branch() {
+        use std::sync::atomic::Ordering;
+        let head_fails = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let storage = ContendedStorage {
+            inner: SqliteStorage::new(":memory:").await.unwrap(),
+            conflicts: 0.into(),
+            head_fails: head_fails.clone(),
+        };
+        let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        let first = repo
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
 
-        let diff = repo.diff(h1.as_str(), h2.as_str()).await.unwrap();
-        assert!(!diff.entries.is_empty());
+        head_fails.store(true, Ordering::SeqCst);
+        let state = AgentState::new(json!({"v": 2}), json!({}));
+        repo.commit(&state, "second", ActionType::ToolCall)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            repo.storage.get_ref("main").await.unwrap(),
+            Some(first.to_string())
+        );
+        assert_eq!(repo.refs.resolve_ref("main").unwrap(), first);
     }
 
     #[tokio::test]
-    async fn test_revert() {
-        let mut repo = test_repo().await;
+    async fn test_commit_to_branch_does_not_clobber_new_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let mut stale = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        stale
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
 
-        let s1 = AgentState::new(json!({"v": 1}), json!({}));
-        let h1 = repo.commit(&s1, "first", ActionType::ToolCall).await.unwrap();
+        // Another writer creates the branch after `stale` loaded its refs
+        let mut other = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 2}), json!({}));
+        let theirs = other
+            .commit_to_branch("feature", &state, "theirs", ActionType::ToolCall, true)
+            .await
+            .unwrap();
 
-        let s2 = AgentState::new(json!({"v": 2}), json!({}));
-        repo.commit(&s2, "second", ActionType::ToolCall).await.unwrap();
+        let state = AgentState::new(json!({"v": 3}), json!({}));
+        let ours = stale
+            .commit_to_branch("feature", &state, "ours", ActionType::ToolCall, true)
+            .await
+            .unwrap();
+        let commit = stale.get_commit(ours.as_str()).await.unwrap().unwrap();
+        assert_eq!(commit.parent_hashes, vec![theirs]);
+        assert_eq!(stale.refs.resolve_ref("feature").unwrap(), ours);
+    }
 
-        let reverted = repo.revert(h1.as_str()).await.unwrap();
-        assert_eq!(reverted.memory, json!({"v": 1}));
+    #[tokio::test]
+    async fn test_branch_does_not_clobber_concurrent_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let mut stale = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        stale
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
 
-        // Should now have 3 commits
-        let commits = repo.log(None, 10).await.unwrap();
-        assert_eq!(commits.len(), 3);
+        let mut other = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 2}), json!({}));
+        let theirs = other
+            .commit_to_branch("feature", &state, "theirs", ActionType::ToolCall, true)
+            .await
+            .unwrap();
+
+        let err = stale.branch("feature", None).await.unwrap_err();
+        assert!(matches!(err, AgitError::BranchExists { name } if name == "feature"));
+        assert!(!stale.list_branches().contains_key("feature"));
+        assert_eq!(
+            stale.storage.get_ref("feature").await.unwrap(),
+            Some(theirs.to_string())
+        );
     }
 
     #[tokio::test]
-    async fn test_merge_ours() {
-        let mut repo = test_repo().await;
+    async fn test_reset_rejects_stale_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let mut stale = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 1}), json!({}));
+        let first = stale
+            .commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
 
-        let s1 = AgentState::new(json!({"v": 1}), json!({}));
-        repo.commit(&s1, "initial", ActionType::ToolCall).await.unwrap();
+        let mut other = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        let state = AgentState::new(json!({"v": 2}), json!({}));
+        let second = other
+            .commit(&state, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
 
-        repo.branch("feature", None).await.unwrap();
-        repo.checkout("feature").await.unwrap();
+        let err = stale.reset("main", first.as_str()).await.unwrap_err();
+        assert!(matches!(err, AgitError::ConcurrentUpdate { name } if name == "main"));
+        assert_eq!(
+            stale.storage.get_ref("main").await.unwrap(),
+            Some(second.to_string())
+        );
+    }
 
-        let s2 = AgentState::new(json!({"v": 2}), json!({}));
-        repo.commit(&s2, "feature work", ActionType::ToolCall).await.unwrap();
+    #[tokio::test]
+    async fn test_config_roundtrip() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Retention {
+            keep: usize,
+        }
 
-        repo.checkout("main").await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(repo.config_get("retention").await.unwrap(), None);
 
-        let s3 = AgentState::new(json!({"v": 3}), json!({}));
-        repo.commit(&s3, "main work", ActionType::ToolCall).await.unwrap();
+        repo.config_set("retention", json!({"keep": 5}))
+            .await
+            .unwrap();
+        repo.config_set("retention", json!({"keep": 10}))
+            .await
+            .unwrap();
+        repo.config_set("retention", json!({"keep": 10}))
+            .await
+            .unwrap();
+        repo.config_set("schemas/main", json!(true)).await.unwrap();
 
-        let merge_hash = repo.merge("feature", MergeStrategy::Ours).await.unwrap();
-        let merged_state = repo.get_state(merge_hash.as_str()).await.unwrap();
-        assert_eq!(merged_state.memory, json!({"v": 3}));
+        let other = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(
+            other.config_get_as::<Retention>("retention").await.unwrap(),
+            Some(Retention { keep: 10 })
+        );
+        let all = other.config_list().await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all["schemas/main"], json!(true));
+        assert!(!other.list_branches().contains_key("config/retention"));
+
+        let filter = LogFilter {
+            action: Some("config_set".to_string()),
+            ..Default::default()
+        };
+        let entries = other.audit_log(&filter).await.unwrap();
+        let changes: Vec<(Value, Value)> = entries
+            .iter()
+            .rev()
+            .map(|e| e.details.clone().unwrap())
+            .filter(|d| d["key"] == "retention")
+            .map(|d| (d["old_hash"].clone(), d["new_hash"].clone()))
+            .collect();
+        assert_eq!(
+            changes.len(),
+            2,
+            "setting the same value again is not logged"
+        );
+        assert_eq!(changes[0].0, Value::Null);
+        assert_eq!(changes[1].0, changes[0].1);
+        let stored = other.storage.get_ref("config/retention").await.unwrap();
+        assert_eq!(changes[1].1, json!(stored));
     }
 
     #[tokio::test]
-    async fn test_get_state() {
+    async fn test_config_rejects_reserved_and_invalid_keys() {
         let mut repo = test_repo().await;
-        let state = AgentState::new(json!({"data": "hello"}), json!({"world": true}));
-        let hash = repo
-            .commit(&state, "test", ActionType::Checkpoint)
+        repo.set_branch_protection(BranchProtection::default())
             .await
             .unwrap();
+        let err = repo.config_set("protection", json!({})).await.unwrap_err();
+        assert!(matches!(err, AgitError::InvalidArgument(_)));
+        assert!(repo.config_get("protection").await.unwrap().is_some());
 
-        let retrieved = repo.get_state(hash.as_str()).await.unwrap();
-        assert_eq!(retrieved.memory, json!({"data": "hello"}));
-        assert_eq!(retrieved.world_state, json!({"world": true}));
+        for key in ["", "../main", "a b"] {
+            let err = repo.config_set(key, json!(1)).await.unwrap_err();
+            assert!(matches!(err, AgitError::InvalidArgument(_)), "{key:?}");
+        }
     }
 
     #[tokio::test]
-    async fn test_audit_log() {
-        let mut repo = test_repo().await;
-        let state = AgentState::new(json!({}), json!({}));
-        repo.commit(&state, "test action", ActionType::ToolCall)
+    async fn test_config_set_retries_concurrent_updates() {
+        let repo = contended_repo(REF_UPDATE_RETRIES).await;
+        repo.config_set("mode", json!("fast")).await.unwrap();
+        assert_eq!(repo.config_get("mode").await.unwrap(), Some(json!("fast")));
+
+        let repo = contended_repo(REF_UPDATE_RETRIES + 1).await;
+        let err = repo.config_set("mode", json!("fast")).await.unwrap_err();
+        assert!(matches!(err, AgitError::ConcurrentUpdate { name } if name == "config/mode"));
+        assert_eq!(repo.config_get("mode").await.unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_config_sets_form_one_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
             .await
             .unwrap();
 
+        let mut tasks = Vec::new();
+        for i in 0..4 {
+            let db = db.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+                    .await
+                    .unwrap();
+                repo.set_agent_id(&format!("writer-{i}"));
+                for round in 0..5 {
+                    // Losing every retry is allowed; a lost update is not
+                    match repo.config_set("leader", json!([i, round])).await {
+                        Ok(()) | Err(AgitError::ConcurrentUpdate { .. }) => {}
+                        Err(e) => panic!("config_set failed: {e}"),
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
         let filter = LogFilter {
-            limit: Some(10),
+            action: Some("config_set".to_string()),
             ..Default::default()
         };
-        let logs = repo.audit_log(&filter).await.unwrap();
-        assert!(!logs.is_empty());
+        let mut next: HashMap<Value, Value> = HashMap::new();
+        for entry in repo.audit_log(&filter).await.unwrap() {
+            let details = entry.details.unwrap();
+            let replaced = next.insert(details["old_hash"].clone(), details["new_hash"].clone());
+            assert!(replaced.is_none(), "two writers replaced the same value");
+        }
+        // Following old -> new from the first write reaches the stored value
+        let mut current = Value::Null;
+        for _ in 0..next.len() {
+            current = next[&current].clone();
+        }
+        let stored = repo.storage.get_ref("config/leader").await.unwrap();
+        assert_eq!(current, json!(stored));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn test_rotate_encryption_key_resumes_from_checkpoint() {
+        let mut repo = test_repo().await;
+        repo.set_encryption_key("old-key").await.unwrap();
+        let s1 = AgentState::new(json!({"step": 1}), json!({}));
+        let h1 = repo
+            .commit(&s1, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        let s2 = AgentState::new(json!({"step": 2}), json!({}));
+        repo.commit(&s2, "second", ActionType::ToolCall)
+            .await
+            .unwrap();
+
+        // An interrupted run re-encrypted the first state
+        let old_tree = repo
+            .get_commit(h1.as_str())
+            .await
+            .unwrap()
+            .unwrap()
+            .tree_hash;
+        let new = repo.derive_encryptor("new-key").await.unwrap();
+        let rotated = repo
+            .store_blob(new.encrypt_state(&s1).unwrap().to_value(), None)
+            .await
+            .unwrap();
+        let mut checkpoint = rotation::RotationCheckpoint::default();
+        checkpoint.blobs.insert(old_tree.0, rotated.0.clone());
+        repo.save_rotation_checkpoint(&checkpoint).await.unwrap();
+
+        let result = repo
+            .rotate_encryption_key("old-key", "new-key", |_| {})
+            .await
+            .unwrap();
+        assert_eq!(result.states_reencrypted, 1);
+        assert_eq!(result.states_already_rotated, 1);
+        let commit = repo
+            .get_commit(result.commit_map[&h1].as_str())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(commit.tree_hash.0, rotated.0);
+        assert!(repo
+            .storage
+            .get_ref(rotation::ROTATION_STATE_REF)
+            .await
+            .unwrap()
+            .is_none());
     }
 }
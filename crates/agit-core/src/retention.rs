@@ -1,14 +1,17 @@
 //! Retention policy support for automatic cleanup of old commits and logs.
 
-use std::collections::{HashSet, VecDeque};
 use std::time::Duration;
 
 use chrono::Utc;
 
-use crate::error::Result;
+use crate::error::{AgitError, Result};
 use crate::objects::Commit;
 use crate::refs::RefStore;
 use crate::storage::StorageBackend;
+use crate::types::Hash;
+
+/// Upper bound on commits visited per branch when planning retention.
+const MAX_HISTORY: usize = 10_000;
 
 /// Configurable retention policy for repository data.
 #[derive(Debug, Clone)]
@@ -21,7 +24,7 @@ pub struct RetentionPolicy {
     pub keep_branches: Vec<String>,
     /// Maximum age for log entries (None = no limit).
     pub max_log_age: Option<Duration>,
-    /// Maximum number of log entries (None = no limit).
+    /// Maximum number of log entries per agent (None = no limit).
     pub max_log_entries: Option<usize>,
 }
 
@@ -37,80 +40,104 @@ impl Default for RetentionPolicy {
     }
 }
 
-/// Result of applying a retention policy.
-#[derive(Debug, Clone)]
+/// Result of `Repository::apply_retention`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct RetentionResult {
-    /// Number of commits marked for removal.
+    /// Commits dropped from the first-parent history of truncated branches.
     pub commits_expired: usize,
-    /// Number of commits retained.
+    /// Commits kept on the branches the policy applies to.
     pub commits_retained: usize,
+    /// Branches whose history was truncated.
+    pub branches_truncated: Vec<String>,
+    /// Objects deleted once the expired history became unreachable.
+    pub objects_removed: usize,
+}
+
+/// What a retention policy keeps of one branch.
+#[derive(Debug, Clone)]
+pub struct BranchRetention {
+    /// Branch name.
+    pub branch: String,
+    /// Current tip of the branch.
+    pub tip: Hash,
+    /// Commits kept along the first-parent history, newest first, with
+    /// their hashes. Never empty: the tip is always kept.
+    pub retained: Vec<(Hash, Commit)>,
+    /// Commits beyond `retained` along the first-parent history.
+    pub expired: usize,
+}
+
+/// Result of `Repository::apply_log_retention`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogRetentionResult {
+    /// Log entries deleted for being older than `max_log_age`.
+    pub expired_by_age: usize,
+    /// Log entries deleted beyond each agent's newest `max_log_entries`.
+    pub over_limit: usize,
+}
+
+impl LogRetentionResult {
+    /// Total log entries deleted.
+    pub fn total(&self) -> usize {
+        self.expired_by_age + self.over_limit
+    }
 }
 
-/// Apply a retention policy, returning hashes that should be considered
-/// unreachable (and thus eligible for GC).
-pub async fn apply_retention(
+/// Work out which commits `policy` keeps on each branch it applies to.
+///
+/// Branches in `policy.keep_branches` are skipped. Elsewhere the newest
+/// commits along first-parent history are kept while they are within
+/// `max_commits` and no older than `max_age`, so age wins over count: a
+/// commit inside the count limit but past the age limit expires, along
+/// with everything older. The tip is always kept.
+pub async fn plan_retention(
     storage: &dyn StorageBackend,
     refs: &RefStore,
     policy: &RetentionPolicy,
-) -> Result<RetentionResult> {
-    let branches = refs.list_branches();
+) -> Result<Vec<BranchRetention>> {
     let now = Utc::now();
-    let mut retained = HashSet::new();
-    let mut total_seen = 0usize;
+    let mut branches: Vec<_> = refs.list_branches().iter().collect();
+    branches.sort_by(|a, b| a.0.cmp(b.0));
 
-    for (branch_name, tip) in branches {
-        let is_protected = policy.keep_branches.contains(branch_name);
-
-        let mut queue = VecDeque::new();
-        let mut visited = HashSet::new();
-        let mut branch_count = 0usize;
-        queue.push_back(tip.clone());
+    let mut plans = Vec::new();
+    for (branch, tip) in branches {
+        if policy.keep_branches.contains(branch) {
+            continue;
+        }
 
-        while let Some(hash) = queue.pop_front() {
-            if visited.contains(&hash) {
-                continue;
+        let mut retained = Vec::new();
+        let mut expired = 0usize;
+        let mut current = Some(tip.clone());
+        while let Some(hash) = current.take() {
+            if retained.len() + expired >= MAX_HISTORY {
+                break;
             }
-            visited.insert(hash.clone());
-            total_seen += 1;
-
-            if let Some(data) = storage.get_object(hash.as_str()).await? {
-                if let Ok(commit) = serde_json::from_slice::<Commit>(&data) {
-                    let mut keep = is_protected;
-
-                    // Check max_commits
-                    if let Some(max) = policy.max_commits {
-                        if branch_count < max {
-                            keep = true;
-                        }
-                    } else {
-                        keep = true;
-                    }
-
-                    // Check max_age
-                    if let Some(max_age) = policy.max_age {
-                        let age = now.signed_duration_since(commit.timestamp);
-                        if age.num_seconds() > max_age.as_secs() as i64 && !is_protected {
-                            keep = false;
-                        }
-                    }
-
-                    if keep {
-                        retained.insert(hash.0.clone());
-                        // Also retain the tree blob
-                        retained.insert(commit.tree_hash.0.clone());
-                    }
-
-                    branch_count += 1;
-                    for parent in &commit.parent_hashes {
-                        queue.push_back(parent.clone());
-                    }
+            let data = storage.get_object(hash.as_str()).await?.ok_or_else(|| {
+                AgitError::ObjectNotFound {
+                    hash: hash.to_string(),
                 }
+            })?;
+            let commit: Commit = serde_json::from_slice(&data)?;
+            current = commit.parent_hashes.first().cloned();
+
+            let within_count = policy.max_commits.is_none_or(|max| retained.len() < max);
+            let within_age = policy.max_age.is_none_or(|max_age| {
+                now.signed_duration_since(commit.timestamp).num_seconds()
+                    <= max_age.as_secs() as i64
+            });
+            if retained.is_empty() || (expired == 0 && within_count && within_age) {
+                retained.push((hash, commit));
+            } else {
+                expired += 1;
             }
         }
-    }
 
-    Ok(RetentionResult {
-        commits_expired: total_seen.saturating_sub(retained.len()),
-        commits_retained: retained.len(),
-    })
+        plans.push(BranchRetention {
+            branch: branch.clone(),
+            tip: tip.clone(),
+            retained,
+            expired,
+        });
+    }
+    Ok(plans)
 }
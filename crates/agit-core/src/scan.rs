@@ -0,0 +1,285 @@
+//! Locate a path inside serialized JSON without parsing the whole document.
+//!
+//! Used to read a single field of a state blob from a prefix of its bytes:
+//! values before the target are skipped byte-wise, and only the target's
+//! span is handed to `serde_json`.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::chunking::CHUNKS_KEY;
+use crate::encryption::ENCRYPTED_PREFIX;
+
+/// Outcome of scanning for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathScan {
+    /// The value at `path[..depth]` spans these bytes. `depth` is short of
+    /// the full path when a chunk manifest or an encrypted field was reached
+    /// first; the remaining segments apply to the reassembled or decrypted
+    /// value.
+    Found { span: Range<usize>, depth: usize },
+    /// The path does not exist in the document.
+    Absent,
+}
+
+/// Scan `buf` for the value at `path`.
+///
+/// Object keys are matched by name and array elements by index. Returns
+/// `None` if `buf` ends before the path is resolved, or if the bytes are
+/// not well-formed JSON; callers should read more or fall back to a full
+/// parse.
+pub fn scan_path(buf: &[u8], path: &[String]) -> Option<PathScan> {
+    let mut cursor = Cursor { buf, pos: 0 };
+    for (depth, segment) in path.iter().enumerate() {
+        let start = cursor.peek()?;
+        match buf[start] {
+            b'{' => {
+                cursor.pos += 1;
+                let mut first = true;
+                loop {
+                    let next = cursor.peek()?;
+                    match buf[next] {
+                        b'}' => return Some(PathScan::Absent),
+                        b',' if !first => cursor.pos += 1,
+                        b'"' if first => {}
+                        _ => return None,
+                    }
+                    let key_start = cursor.peek()?;
+                    if buf[key_start] != b'"' {
+                        return None;
+                    }
+                    cursor.skip_string()?;
+                    let key = decode_key(&buf[key_start..cursor.pos])?;
+                    if first && key == CHUNKS_KEY {
+                        cursor.pos = start;
+                        cursor.skip_value()?;
+                        return Some(PathScan::Found {
+                            span: start..cursor.pos,
+                            depth,
+                        });
+                    }
+                    first = false;
+                    cursor.expect(b':')?;
+                    if key == segment.as_str() {
+                        break;
+                    }
+                    cursor.skip_value()?;
+                }
+            }
+            b'[' => {
+                let Ok(index) = segment.parse::<usize>() else {
+                    return Some(PathScan::Absent);
+                };
+                cursor.pos += 1;
+                for i in 0..=index {
+                    let next = cursor.peek()?;
+                    if buf[next] == b']' {
+                        return Some(PathScan::Absent);
+                    }
+                    if i > 0 {
+                        cursor.expect(b',')?;
+                    }
+                    if i < index {
+                        cursor.skip_value()?;
+                    }
+                }
+            }
+            // Only whole top-level fields are encrypted
+            b'"' if depth == 1 && buf[start + 1..].starts_with(ENCRYPTED_PREFIX.as_bytes()) => {
+                cursor.skip_value()?;
+                return Some(PathScan::Found {
+                    span: start..cursor.pos,
+                    depth,
+                });
+            }
+            _ => return Some(PathScan::Absent),
+        }
+    }
+
+    let start = cursor.peek()?;
+    cursor.skip_value()?;
+    Some(PathScan::Found {
+        span: start..cursor.pos,
+        depth: path.len(),
+    })
+}
+
+/// Decode a quoted key, unescaping only when needed.
+fn decode_key(raw: &[u8]) -> Option<Cow<'_, str>> {
+    if raw.contains(&b'\\') {
+        serde_json::from_slice::<String>(raw).ok().map(Cow::Owned)
+    } else {
+        std::str::from_utf8(&raw[1..raw.len() - 1])
+            .ok()
+            .map(Cow::Borrowed)
+    }
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    /// Skip whitespace and return the position of the next byte.
+    fn peek(&mut self) -> Option<usize> {
+        while self.buf.get(self.pos)?.is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        Some(self.pos)
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        let pos = self.peek()?;
+        if self.buf[pos] != byte {
+            return None;
+        }
+        self.pos += 1;
+        Some(())
+    }
+
+    /// Skip a string starting at the current opening quote.
+    fn skip_string(&mut self) -> Option<()> {
+        let mut i = self.pos + 1;
+        loop {
+            match *self.buf.get(i)? {
+                b'"' => break,
+                b'\\' => i += 2,
+                _ => i += 1,
+            }
+        }
+        self.pos = i + 1;
+        Some(())
+    }
+
+    /// Skip one complete value of any type.
+    fn skip_value(&mut self) -> Option<()> {
+        let start = self.peek()?;
+        match self.buf[start] {
+            b'"' => self.skip_string(),
+            b'{' | b'[' => {
+                let mut depth = 0usize;
+                loop {
+                    match *self.buf.get(self.pos)? {
+                        b'"' => {
+                            self.skip_string()?;
+                            continue;
+                        }
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                self.pos += 1;
+                                return Some(());
+                            }
+                        }
+                        _ => {}
+                    }
+                    self.pos += 1;
+                }
+            }
+            _ => {
+                // Scalars end at a delimiter; reaching the end of the
+                // buffer means the scalar may continue
+                let len = self.buf[start..]
+                    .iter()
+                    .position(|b| matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace())?;
+                if len == 0 {
+                    return None;
+                }
+                self.pos = start + len;
+                Some(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::canonical_serialize;
+    use crate::state::value_at_path;
+    use serde_json::{json, Value};
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.').map(String::from).collect()
+    }
+
+    fn found(buf: &[u8], p: &str) -> Value {
+        match scan_path(buf, &path(p)) {
+            Some(PathScan::Found { span, .. }) => serde_json::from_slice(&buf[span]).unwrap(),
+            other => panic!("{}: {:?}", p, other),
+        }
+    }
+
+    #[test]
+    fn test_scan_matches_value_at_path() {
+        let value = json!({
+            "memory": {"a": [1, {"b": "x,]}\"y"}, null], "key": true},
+            "world_state": {"cursor": {"line": 3, "col": -1.5e3}},
+        });
+        let buf = canonical_serialize(&value);
+        for p in [
+            "memory.a.1.b",
+            "memory.a.2",
+            "memory.key",
+            "world_state.cursor",
+            "world_state.cursor.col",
+        ] {
+            assert_eq!(&found(&buf, p), value_at_path(&value, &path(p)).unwrap());
+        }
+        for p in [
+            "memory.z",
+            "memory.a.3",
+            "memory.a.x",
+            "world_state.cursor.line.0",
+        ] {
+            assert_eq!(scan_path(&buf, &path(p)), Some(PathScan::Absent), "{}", p);
+        }
+    }
+
+    #[test]
+    fn test_scan_prefix_and_manifest() {
+        let value = json!({"memory": {"n": 12345}, "world_state": {"x": 1}});
+        let buf = canonical_serialize(&value);
+        // The scalar might continue past the end of a prefix
+        let end = buf.windows(5).position(|w| w == b"12345").unwrap() + 5;
+        assert_eq!(scan_path(&buf[..end], &path("memory.n")), None);
+        assert_eq!(found(&buf[..end + 1], "memory.n"), json!(12345));
+
+        let chunked = json!({"memory": {CHUNKS_KEY: ["h1", "h2"]}});
+        let buf = canonical_serialize(&chunked);
+        match scan_path(&buf, &path("memory.n")) {
+            Some(PathScan::Found { span, depth }) => {
+                assert_eq!(depth, 1);
+                let v: Value = serde_json::from_slice(&buf[span]).unwrap();
+                assert_eq!(v, chunked["memory"]);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_scan_stops_at_encrypted_field() {
+        let value = json!({"memory": "ENC:v:2|kid:00|salt:|AAAA", "world_state": "plain"});
+        let buf = canonical_serialize(&value);
+        match scan_path(&buf, &path("memory.a.b")) {
+            Some(PathScan::Found { span, depth }) => {
+                assert_eq!(depth, 1);
+                assert_eq!(&buf[span], br#""ENC:v:2|kid:00|salt:|AAAA""#);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            scan_path(&buf, &path("world_state.a")),
+            Some(PathScan::Absent)
+        );
+        let nested = canonical_serialize(&json!({"memory": {"a": "ENC:x"}}));
+        assert_eq!(
+            scan_path(&nested, &path("memory.a.b")),
+            Some(PathScan::Absent)
+        );
+        // Reading a truncated ciphertext needs more bytes
+        assert_eq!(scan_path(&buf[..20], &path("memory.a")), None);
+    }
+}
@@ -3,7 +3,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 
-use crate::types::ChangeType;
+use crate::error::{AgitError, Result};
+use crate::merge_driver::MergeDriverRegistry;
+use crate::protection::glob_match;
+use crate::types::{ChangeType, MergeStrategy};
 
 /// Full agent state at a point in time.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,10 +31,147 @@ impl AgentState {
         }
     }
 
+    /// Start building a state with the conventional agent layout.
+    pub fn builder() -> AgentStateBuilder {
+        AgentStateBuilder::default()
+    }
+
     /// Convert to a flat JSON value for hashing and storage.
     pub fn to_value(&self) -> Value {
         serde_json::to_value(self).unwrap_or(Value::Null)
     }
+
+    /// Conversation messages at `memory.messages`.
+    pub fn messages(&self) -> Option<&Value> {
+        self.memory.get(MESSAGES_KEY)
+    }
+
+    /// Plan at `memory.plan`.
+    pub fn plan(&self) -> Option<&Value> {
+        self.memory.get(PLAN_KEY)
+    }
+
+    /// Scratchpad at `memory.scratchpad`.
+    pub fn scratchpad(&self) -> Option<&Value> {
+        self.memory.get(SCRATCHPAD_KEY)
+    }
+
+    /// State of tool `name` at `world_state.tools.<name>`.
+    pub fn tool_state(&self, name: &str) -> Option<&Value> {
+        self.world_state.get(TOOLS_KEY)?.get(name)
+    }
+}
+
+/// Memory key holding the conversation message list.
+pub const MESSAGES_KEY: &str = "messages";
+/// Memory key holding the current plan.
+pub const PLAN_KEY: &str = "plan";
+/// Memory key holding free-form working notes.
+pub const SCRATCHPAD_KEY: &str = "scratchpad";
+/// World state key holding per-tool state, keyed by tool name.
+pub const TOOLS_KEY: &str = "tools";
+
+/// Builds an `AgentState` with the conventional layout:
+///
+/// ```text
+/// memory.messages        conversation messages
+/// memory.plan            current plan
+/// memory.scratchpad      working notes
+/// world_state.tools.<n>  state of tool <n>
+/// ```
+///
+/// Arbitrary keys can still be set with `memory` and `world_state`.
+#[derive(Debug, Clone, Default)]
+pub struct AgentStateBuilder {
+    memory: serde_json::Map<String, Value>,
+    world_state: serde_json::Map<String, Value>,
+    metadata: serde_json::Map<String, Value>,
+    cost: f64,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl AgentStateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(mut self, messages: Vec<Value>) -> Self {
+        self.memory
+            .insert(MESSAGES_KEY.to_string(), Value::Array(messages));
+        self
+    }
+
+    /// Append one message to `memory.messages`.
+    pub fn message(mut self, message: Value) -> Self {
+        match self.memory.get_mut(MESSAGES_KEY) {
+            Some(Value::Array(messages)) => messages.push(message),
+            _ => {
+                self.memory
+                    .insert(MESSAGES_KEY.to_string(), Value::Array(vec![message]));
+            }
+        }
+        self
+    }
+
+    pub fn plan(self, plan: Value) -> Self {
+        self.memory(PLAN_KEY, plan)
+    }
+
+    pub fn scratchpad(self, scratchpad: Value) -> Self {
+        self.memory(SCRATCHPAD_KEY, scratchpad)
+    }
+
+    pub fn tool_state(mut self, name: &str, state: Value) -> Self {
+        let tools = self
+            .world_state
+            .entry(TOOLS_KEY)
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+        if !tools.is_object() {
+            *tools = Value::Object(serde_json::Map::new());
+        }
+        if let Value::Object(tools) = tools {
+            tools.insert(name.to_string(), state);
+        }
+        self
+    }
+
+    /// Set an arbitrary top-level memory key.
+    pub fn memory(mut self, key: &str, value: Value) -> Self {
+        self.memory.insert(key.to_string(), value);
+        self
+    }
+
+    /// Set an arbitrary top-level world state key.
+    pub fn world_state(mut self, key: &str, value: Value) -> Self {
+        self.world_state.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn metadata(mut self, key: &str, value: Value) -> Self {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn cost(mut self, cost: f64) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Override the timestamp; defaults to the time of `build`.
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn build(self) -> AgentState {
+        AgentState {
+            memory: Value::Object(self.memory),
+            world_state: Value::Object(self.world_state),
+            timestamp: self.timestamp.unwrap_or_else(Utc::now),
+            cost: self.cost,
+            metadata: self.metadata,
+        }
+    }
 }
 
 /// A single entry in a state diff.
@@ -51,6 +191,208 @@ pub struct StateDiff {
     pub entries: Vec<DiffEntry>,
 }
 
+impl StateDiff {
+    /// Replay this diff onto `base`, like `git apply`.
+    ///
+    /// Fails with `PatchConflict` if an added path already holds a different
+    /// value, or a removed/changed path is absent or does not hold the entry's
+    /// `old_value` (when one is recorded).
+    pub fn apply(&self, base: &Value) -> Result<Value> {
+        let mut result = base.clone();
+        for entry in &self.entries {
+            let current = value_at_path(&result, &entry.path);
+            let conflict = |reason: String| AgitError::PatchConflict {
+                path: entry.path.join("."),
+                reason,
+            };
+            match entry.change_type {
+                ChangeType::Added => {
+                    if let Some(existing) = current {
+                        if Some(existing) != entry.new_value.as_ref() {
+                            return Err(conflict(format!(
+                                "already exists with value {}",
+                                existing
+                            )));
+                        }
+                    }
+                }
+                ChangeType::Removed | ChangeType::Changed => match (&entry.old_value, current) {
+                    (Some(old), current) if Some(old) != current => {
+                        return Err(conflict(format!(
+                            "expected {}, found {}",
+                            old,
+                            display_opt(current)
+                        )));
+                    }
+                    (None, None) => return Err(conflict("path does not exist".to_string())),
+                    _ => {}
+                },
+            }
+
+            match (&entry.change_type, &entry.new_value) {
+                (ChangeType::Removed, _) => {
+                    remove_value_at_path(&mut result, &entry.path);
+                }
+                (_, Some(new_value)) => {
+                    set_value_at_path(&mut result, &entry.path, new_value.clone())?;
+                }
+                (_, None) => {
+                    return Err(conflict("entry has no new value".to_string()));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// One line per entry, with the path joined by dots: `+ path: new` for
+    /// added, `- path: old` for removed and `~ path: old -> new` for
+    /// changed values.
+    pub fn render(&self) -> String {
+        let value = |v: &Option<Value>| {
+            v.as_ref()
+                .map_or_else(|| "null".to_string(), Value::to_string)
+        };
+        self.entries
+            .iter()
+            .map(|e| {
+                let path = e.path.join(".");
+                match e.change_type {
+                    ChangeType::Added => format!("+ {}: {}", path, value(&e.new_value)),
+                    ChangeType::Removed => format!("- {}: {}", path, value(&e.old_value)),
+                    ChangeType::Changed => format!(
+                        "~ {}: {} -> {}",
+                        path,
+                        value(&e.old_value),
+                        value(&e.new_value)
+                    ),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Convert to an RFC 6902 JSON Patch document of `add`, `remove`, and
+    /// `replace` operations.
+    pub fn to_json_patch(&self) -> Value {
+        let ops = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let path = Value::String(to_json_pointer(&entry.path));
+                match entry.change_type {
+                    ChangeType::Added => serde_json::json!({
+                        "op": "add",
+                        "path": path,
+                        "value": entry.new_value.clone().unwrap_or(Value::Null),
+                    }),
+                    ChangeType::Removed => serde_json::json!({"op": "remove", "path": path}),
+                    ChangeType::Changed => serde_json::json!({
+                        "op": "replace",
+                        "path": path,
+                        "value": entry.new_value.clone().unwrap_or(Value::Null),
+                    }),
+                }
+            })
+            .collect();
+        Value::Array(ops)
+    }
+
+    /// Build a diff from an RFC 6902 JSON Patch document.
+    ///
+    /// Only `add`, `remove`, and `replace` are supported. JSON Patch does not
+    /// carry previous values, so `old_value` is `None` on every entry.
+    pub fn from_json_patch(base_hash: &str, target_hash: &str, patch: &Value) -> Result<Self> {
+        let ops = patch.as_array().ok_or_else(|| {
+            AgitError::InvalidArgument("JSON Patch must be an array of operations".to_string())
+        })?;
+
+        let entries = ops
+            .iter()
+            .map(|op| {
+                let field = |name: &str| {
+                    op.get(name).ok_or_else(|| {
+                        AgitError::InvalidArgument(format!(
+                            "JSON Patch operation missing '{}'",
+                            name
+                        ))
+                    })
+                };
+                let path = field("path")?
+                    .as_str()
+                    .ok_or_else(|| {
+                        AgitError::InvalidArgument("'path' must be a string".to_string())
+                    })
+                    .and_then(from_json_pointer)?;
+                let (change_type, new_value) = match field("op")?.as_str() {
+                    Some("add") => (ChangeType::Added, Some(field("value")?.clone())),
+                    Some("remove") => (ChangeType::Removed, None),
+                    Some("replace") => (ChangeType::Changed, Some(field("value")?.clone())),
+                    other => {
+                        return Err(AgitError::InvalidArgument(format!(
+                            "unsupported JSON Patch op: {}",
+                            other.unwrap_or("<non-string>")
+                        )))
+                    }
+                };
+                Ok(DiffEntry {
+                    path,
+                    change_type,
+                    old_value: None,
+                    new_value,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(StateDiff {
+            base_hash: base_hash.to_string(),
+            target_hash: target_hash.to_string(),
+            entries,
+        })
+    }
+}
+
+/// Encode a path as an RFC 6901 JSON Pointer.
+pub fn to_json_pointer(path: &[String]) -> String {
+    path.iter()
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Decode an RFC 6901 JSON Pointer into path segments.
+pub fn from_json_pointer(pointer: &str) -> Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = pointer.strip_prefix('/').ok_or_else(|| {
+        AgitError::InvalidArgument(format!("JSON Pointer must start with '/': {}", pointer))
+    })?;
+    Ok(rest
+        .split('/')
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn display_opt(value: Option<&Value>) -> String {
+    value.map_or_else(|| "nothing".to_string(), |v| v.to_string())
+}
+
+/// Summary of a successful merge, stored in the merge commit's metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// Merge base the two sides were compared against.
+    pub base_hash: String,
+    pub strategy: MergeStrategy,
+    /// Number of paths merged automatically.
+    pub auto_merged: usize,
+    /// Paths only our side changed.
+    pub from_ours: Vec<Vec<String>>,
+    /// Paths only their side changed.
+    pub from_theirs: Vec<Vec<String>>,
+    /// Paths both sides changed, resolved by a merge driver or array strategy.
+    #[serde(default)]
+    pub resolved: Vec<Vec<String>>,
+}
+
 /// A conflict encountered during three-way merge.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MergeConflict {
@@ -60,6 +402,13 @@ pub struct MergeConflict {
     pub theirs_value: Option<Value>,
 }
 
+impl MergeConflict {
+    /// The conflicting path, dot-joined, as used for resolution keys.
+    pub fn dotted_path(&self) -> String {
+        self.path.join(".")
+    }
+}
+
 /// Compute a recursive diff between two JSON values.
 pub fn diff_states(base: &AgentState, target: &AgentState) -> StateDiff {
     let mut entries = Vec::new();
@@ -73,12 +422,7 @@ pub fn diff_states(base: &AgentState, target: &AgentState) -> StateDiff {
     }
 }
 
-fn diff_values(
-    base: &Value,
-    target: &Value,
-    path: &mut Vec<String>,
-    entries: &mut Vec<DiffEntry>,
-) {
+fn diff_values(base: &Value, target: &Value, path: &mut Vec<String>, entries: &mut Vec<DiffEntry>) {
     if base == target {
         return;
     }
@@ -126,23 +470,132 @@ fn diff_values(
     }
 }
 
+/// How to merge arrays that both sides changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrayMergeStrategy {
+    /// Treat the array as an opaque value: differing changes conflict.
+    #[default]
+    Replace,
+    /// Ours followed by theirs' items not already present (structural equality).
+    Union,
+    /// Ours followed by the items theirs added relative to base.
+    ConcatOursFirst,
+    /// Theirs followed by the items ours added relative to base.
+    ConcatTheirsFirst,
+}
+
+/// Configuration for `three_way_merge_with_config`.
+#[derive(Debug, Clone, Default)]
+pub struct MergeConfig {
+    /// Array strategy used where no path-specific rule matches.
+    pub array_strategy: ArrayMergeStrategy,
+    /// Array strategies for path prefixes; the longest matching prefix wins.
+    pub array_path_strategies: Vec<(Vec<String>, ArrayMergeStrategy)>,
+    /// Per-path conflict resolvers, consulted before a conflict is recorded.
+    pub drivers: MergeDriverRegistry,
+}
+
+impl MergeConfig {
+    /// Set the default array strategy.
+    pub fn with_array_strategy(mut self, strategy: ArrayMergeStrategy) -> Self {
+        self.array_strategy = strategy;
+        self
+    }
+
+    /// Use `strategy` for arrays at or below `prefix`.
+    pub fn with_array_strategy_at(
+        mut self,
+        prefix: Vec<String>,
+        strategy: ArrayMergeStrategy,
+    ) -> Self {
+        self.array_path_strategies.push((prefix, strategy));
+        self
+    }
+
+    /// Use `drivers` to resolve conflicts.
+    pub fn with_drivers(mut self, drivers: MergeDriverRegistry) -> Self {
+        self.drivers = drivers;
+        self
+    }
+
+    /// Array strategy that applies at `path`.
+    pub fn array_strategy_for(&self, path: &[String]) -> ArrayMergeStrategy {
+        self.array_path_strategies
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.array_strategy, |(_, strategy)| *strategy)
+    }
+}
+
 /// Three-way merge of JSON values. Returns merged result and any conflicts.
-pub fn three_way_merge(
+pub fn three_way_merge(base: &Value, ours: &Value, theirs: &Value) -> (Value, Vec<MergeConflict>) {
+    three_way_merge_with_config(base, ours, theirs, &MergeConfig::default())
+}
+
+/// Three-way merge using `config` to resolve arrays changed on both sides.
+pub fn three_way_merge_with_config(
     base: &Value,
     ours: &Value,
     theirs: &Value,
+    config: &MergeConfig,
 ) -> (Value, Vec<MergeConflict>) {
-    let mut conflicts = Vec::new();
-    let merged = merge_values(base, ours, theirs, &mut vec![], &mut conflicts);
+    let (merged, conflicts, _) = three_way_merge_traced(base, ours, theirs, config);
     (merged, conflicts)
 }
 
+/// Paths auto-merged by a three-way merge, grouped by where the value came from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MergeResolution {
+    /// Paths only our side changed.
+    pub from_ours: Vec<Vec<String>>,
+    /// Paths only their side changed.
+    pub from_theirs: Vec<Vec<String>>,
+    /// Paths both sides changed, resolved by a merge driver or array strategy.
+    pub resolved: Vec<Vec<String>>,
+}
+
+impl MergeResolution {
+    /// Total number of auto-merged paths.
+    pub fn len(&self) -> usize {
+        self.from_ours.len() + self.from_theirs.len() + self.resolved.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Three-way merge that also reports how each auto-merged path was resolved.
+pub fn three_way_merge_traced(
+    base: &Value,
+    ours: &Value,
+    theirs: &Value,
+    config: &MergeConfig,
+) -> (Value, Vec<MergeConflict>, MergeResolution) {
+    let mut conflicts = Vec::new();
+    let mut resolution = MergeResolution::default();
+    let merged = merge_values(
+        base,
+        ours,
+        theirs,
+        config,
+        &mut vec![],
+        &mut conflicts,
+        &mut resolution,
+    );
+    (merged, conflicts, resolution)
+}
+
 fn merge_values(
     base: &Value,
     ours: &Value,
     theirs: &Value,
+    config: &MergeConfig,
     path: &mut Vec<String>,
     conflicts: &mut Vec<MergeConflict>,
+    resolution: &mut MergeResolution,
 ) -> Value {
     // If both sides are the same, no conflict
     if ours == theirs {
@@ -151,17 +604,26 @@ fn merge_values(
 
     // If only one side changed from base, take that side
     if ours == base {
+        resolution.from_theirs.push(path.clone());
         return theirs.clone();
     }
     if theirs == base {
+        resolution.from_ours.push(path.clone());
         return ours.clone();
     }
 
-    // Both sides changed differently from base
+    // Both sides changed differently from base: a registered driver may
+    // resolve the whole subtree (e.g. pick the newer of two objects)
+    if let Some(resolved) = config.drivers.resolve(path, base, ours, theirs) {
+        resolution.resolved.push(path.clone());
+        return resolved;
+    }
+
     match (base, ours, theirs) {
         (Value::Object(base_map), Value::Object(ours_map), Value::Object(theirs_map)) => {
             let mut result = serde_json::Map::new();
-            let mut all_keys: std::collections::BTreeSet<String> = std::collections::BTreeSet::new();
+            let mut all_keys: std::collections::BTreeSet<String> =
+                std::collections::BTreeSet::new();
             all_keys.extend(base_map.keys().cloned());
             all_keys.extend(ours_map.keys().cloned());
             all_keys.extend(theirs_map.keys().cloned());
@@ -171,14 +633,32 @@ fn merge_values(
                 let base_val = base_map.get(&key).unwrap_or(&Value::Null);
                 let ours_val = ours_map.get(&key).unwrap_or(&Value::Null);
                 let theirs_val = theirs_map.get(&key).unwrap_or(&Value::Null);
-                let merged = merge_values(base_val, ours_val, theirs_val, path, conflicts);
-                if merged != Value::Null || ours_map.contains_key(&key) || theirs_map.contains_key(&key) {
+                let merged = merge_values(
+                    base_val, ours_val, theirs_val, config, path, conflicts, resolution,
+                );
+                if merged != Value::Null
+                    || ours_map.contains_key(&key)
+                    || theirs_map.contains_key(&key)
+                {
                     result.insert(key, merged);
                 }
                 path.pop();
             }
             Value::Object(result)
         }
+        (_, Value::Array(ours_arr), Value::Array(theirs_arr))
+            if config.array_strategy_for(path) != ArrayMergeStrategy::Replace =>
+        {
+            let empty = Vec::new();
+            let base_arr = base.as_array().unwrap_or(&empty);
+            resolution.resolved.push(path.clone());
+            Value::Array(merge_arrays(
+                base_arr,
+                ours_arr,
+                theirs_arr,
+                config.array_strategy_for(path),
+            ))
+        }
         _ => {
             // Leaf conflict: both changed differently
             conflicts.push(MergeConflict {
@@ -193,6 +673,46 @@ fn merge_values(
     }
 }
 
+fn merge_arrays(
+    base: &[Value],
+    ours: &[Value],
+    theirs: &[Value],
+    strategy: ArrayMergeStrategy,
+) -> Vec<Value> {
+    match strategy {
+        ArrayMergeStrategy::Replace => ours.to_vec(),
+        ArrayMergeStrategy::Union => {
+            let mut result = ours.to_vec();
+            for item in theirs {
+                if !result.contains(item) {
+                    result.push(item.clone());
+                }
+            }
+            result
+        }
+        ArrayMergeStrategy::ConcatOursFirst => {
+            let mut result = ours.to_vec();
+            result.extend(added_items(base, theirs));
+            result
+        }
+        ArrayMergeStrategy::ConcatTheirsFirst => {
+            let mut result = theirs.to_vec();
+            result.extend(added_items(base, ours));
+            result
+        }
+    }
+}
+
+/// Items `side` added relative to `base`: the tail if `side` extends `base`,
+/// otherwise every item not present in `base`.
+fn added_items(base: &[Value], side: &[Value]) -> Vec<Value> {
+    if side.starts_with(base) {
+        side[base.len()..].to_vec()
+    } else {
+        side.iter().filter(|v| !base.contains(v)).cloned().collect()
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Merkle Tree Optimization
 // ---------------------------------------------------------------------------
@@ -207,59 +727,369 @@ pub struct MerkleNode {
     pub children: std::collections::BTreeMap<String, MerkleNode>,
 }
 
+/// Objects with at least this many keys have their children built in
+/// parallel by `MerkleNode::from_value_parallel`.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_KEYS: usize = 256;
+
 impl MerkleNode {
-    /// Build a Merkle tree from a JSON value.
+    /// Build a Merkle tree from a JSON value. Uses parallel construction
+    /// when the `parallel` feature is enabled.
     pub fn from_value(value: &Value) -> Self {
+        #[cfg(feature = "parallel")]
+        return Self::from_value_parallel(value);
+        #[cfg(not(feature = "parallel"))]
+        Self::from_value_sequential(value)
+    }
+
+    /// Build a Merkle tree on the current thread.
+    pub fn from_value_sequential(value: &Value) -> Self {
         match value {
-            Value::Object(map) => {
-                let mut children = std::collections::BTreeMap::new();
-                let mut hasher = Sha256::new();
-                hasher.update(b"object{");
-                for (key, val) in map {
-                    let child = MerkleNode::from_value(val);
-                    hasher.update(key.as_bytes());
-                    hasher.update(b":");
-                    hasher.update(child.hash.as_bytes());
-                    hasher.update(b",");
-                    children.insert(key.clone(), child);
+            Value::Object(map) => Self::object(
+                map.iter()
+                    .map(|(key, val)| (key.clone(), Self::from_value_sequential(val)))
+                    .collect(),
+            ),
+            _ => Self::leaf(value),
+        }
+    }
+
+    /// Build a Merkle tree, constructing the children of large objects in
+    /// parallel. The result is identical to `from_value_sequential`.
+    #[cfg(feature = "parallel")]
+    pub fn from_value_parallel(value: &Value) -> Self {
+        use rayon::prelude::*;
+
+        match value {
+            Value::Object(map) if map.len() >= PARALLEL_MIN_KEYS => {
+                let entries: Vec<(&String, &Value)> = map.iter().collect();
+                Self::object(
+                    entries
+                        .into_par_iter()
+                        .map(|(key, val)| (key.clone(), Self::from_value_parallel(val)))
+                        .collect::<Vec<_>>()
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            Value::Object(map) => Self::object(
+                map.iter()
+                    .map(|(key, val)| (key.clone(), Self::from_value_parallel(val)))
+                    .collect(),
+            ),
+            _ => Self::leaf(value),
+        }
+    }
+
+    /// Combine child hashes in key order.
+    fn object(children: std::collections::BTreeMap<String, MerkleNode>) -> Self {
+        let hash = object_hash(children.iter().map(|(k, c)| (k.as_str(), c.hash.as_str())));
+        MerkleNode { hash, children }
+    }
+
+    /// Leaf node: hash the canonical JSON representation.
+    fn leaf(value: &Value) -> Self {
+        let mut hasher = Sha256::new();
+        let serialized = serde_json::to_string(value).unwrap_or_default();
+        hasher.update(serialized.as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        MerkleNode {
+            hash,
+            children: std::collections::BTreeMap::new(),
+        }
+    }
+
+    /// Build an inclusion proof for the value at `path`.
+    ///
+    /// Only object keys can be proven; arrays and scalars are leaves.
+    /// Returns `None` if the path is absent.
+    pub fn prove(&self, path: &[String]) -> Option<MerkleProof> {
+        let mut siblings = Vec::with_capacity(path.len());
+        let mut node = self;
+        for segment in path {
+            let child = node.children.get(segment)?;
+            siblings.push(
+                node.children
+                    .iter()
+                    .filter(|(key, _)| *key != segment)
+                    .map(|(key, sibling)| (key.clone(), sibling.hash.clone()))
+                    .collect(),
+            );
+            node = child;
+        }
+        Some(MerkleProof { siblings })
+    }
+}
+
+/// Hash of an object node from its children's keys and hashes, in key order.
+fn object_hash<'a>(children: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"object{");
+    for (key, hash) in children {
+        hasher.update(key.as_bytes());
+        hasher.update(b":");
+        hasher.update(hash.as_bytes());
+        hasher.update(b",");
+    }
+    hasher.update(b"}");
+    format!("{:x}", hasher.finalize())
+}
+
+/// Proof that a value is present at a path in a Merkle tree, produced by
+/// `MerkleNode::prove`. Holds only hashes, so it can be checked without
+/// the rest of the state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Keys and hashes of the other children of each object along the
+    /// path, root first.
+    pub siblings: Vec<std::collections::BTreeMap<String, String>>,
+}
+
+impl MerkleProof {
+    /// Check that `value` is at `path` in the tree whose root hash is
+    /// `root_hash`.
+    pub fn verify(&self, root_hash: &str, path: &[String], value: &Value) -> bool {
+        if path.len() != self.siblings.len() {
+            return false;
+        }
+        let mut hash = MerkleNode::from_value(value).hash;
+        for (segment, siblings) in path.iter().zip(&self.siblings).rev() {
+            if siblings.contains_key(segment) {
+                return false;
+            }
+            let mut children: Vec<(&str, &str)> = siblings
+                .iter()
+                .map(|(key, h)| (key.as_str(), h.as_str()))
+                .collect();
+            let at = children.partition_point(|(key, _)| *key < segment.as_str());
+            children.insert(at, (segment, &hash));
+            let parent = object_hash(children.into_iter());
+            hash = parent;
+        }
+        hash == root_hash
+    }
+}
+
+/// Look up the value at `path`, descending into objects by key and into
+/// arrays by numeric index. Returns `None` if any segment is absent.
+pub fn value_at_path<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(value, |current, segment| match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(arr) => segment.parse::<usize>().ok().and_then(|i| arr.get(i)),
+            _ => None,
+        })
+}
+
+/// Set the value at `path`, creating intermediate objects as needed.
+///
+/// Array segments must be an existing index or the array length (append).
+/// A scalar found where a container is needed is replaced by an empty object.
+pub fn set_value_at_path(root: &mut Value, path: &[String], new_value: Value) -> Result<()> {
+    let Some((last, parents)) = path.split_last() else {
+        *root = new_value;
+        return Ok(());
+    };
+
+    let mut current = root;
+    for segment in parents {
+        if !current.is_object() && !current.is_array() {
+            *current = Value::Object(serde_json::Map::new());
+        }
+        current = match current {
+            Value::Object(map) => map
+                .entry(segment.clone())
+                .or_insert_with(|| Value::Object(serde_json::Map::new())),
+            Value::Array(arr) => {
+                let index = array_index(segment, arr.len())?;
+                if index == arr.len() {
+                    arr.push(Value::Object(serde_json::Map::new()));
                 }
-                hasher.update(b"}");
-                let hash = format!("{:x}", hasher.finalize());
-                MerkleNode { hash, children }
+                &mut arr[index]
             }
-            _ => {
-                // Leaf node: hash the canonical JSON representation
-                let mut hasher = Sha256::new();
-                let serialized = serde_json::to_string(value).unwrap_or_default();
-                hasher.update(serialized.as_bytes());
-                let hash = format!("{:x}", hasher.finalize());
-                MerkleNode {
-                    hash,
-                    children: std::collections::BTreeMap::new(),
+            _ => unreachable!("converted to an object above"),
+        };
+    }
+
+    if !current.is_object() && !current.is_array() {
+        *current = Value::Object(serde_json::Map::new());
+    }
+    match current {
+        Value::Object(map) => {
+            map.insert(last.clone(), new_value);
+        }
+        Value::Array(arr) => {
+            let index = array_index(last, arr.len())?;
+            if index == arr.len() {
+                arr.push(new_value);
+            } else {
+                arr[index] = new_value;
+            }
+        }
+        _ => unreachable!("converted to an object above"),
+    }
+    Ok(())
+}
+
+/// Remove the value at `path`, returning it if it existed.
+pub fn remove_value_at_path(root: &mut Value, path: &[String]) -> Option<Value> {
+    let (last, parents) = path.split_last()?;
+    let mut current = root;
+    for segment in parents {
+        current = match current {
+            Value::Object(map) => map.get_mut(segment)?,
+            Value::Array(arr) => arr.get_mut(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    match current {
+        Value::Object(map) => map.remove(last),
+        Value::Array(arr) => {
+            let index = last.parse::<usize>().ok()?;
+            (index < arr.len()).then(|| arr.remove(index))
+        }
+        _ => None,
+    }
+}
+
+fn array_index(segment: &str, len: usize) -> Result<usize> {
+    // `-` is the JSON Pointer token for "one past the end"
+    if segment == "-" {
+        return Ok(len);
+    }
+    match segment.parse::<usize>() {
+        Ok(index) if index <= len => Ok(index),
+        _ => Err(AgitError::InvalidArgument(format!(
+            "array index '{}' out of range (length {})",
+            segment, len
+        ))),
+    }
+}
+
+/// Paths excluded from a diff.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Exact paths whose subtrees are ignored.
+    pub ignore_paths: Vec<Vec<String>>,
+    /// Dotted path globs (e.g. `world_state.*.request_id`) whose subtrees are ignored.
+    pub ignore_globs: Vec<String>,
+}
+
+impl DiffOptions {
+    /// True if nothing is ignored.
+    pub fn is_empty(&self) -> bool {
+        self.ignore_paths.is_empty() && self.ignore_globs.is_empty()
+    }
+
+    fn ignores(&self, path: &[String]) -> bool {
+        if self.ignore_paths.iter().any(|p| p == path) {
+            return true;
+        }
+        if self.ignore_globs.is_empty() {
+            return false;
+        }
+        let dotted = path.join(".");
+        self.ignore_globs.iter().any(|g| glob_match(g, &dotted))
+    }
+}
+
+/// Clone of `value` with every subtree ignored by `options` removed.
+fn prune_ignored(value: &Value, options: &DiffOptions, path: &mut Vec<String>) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut pruned = serde_json::Map::new();
+            for (key, child) in map {
+                path.push(key.clone());
+                if !options.ignores(path) {
+                    pruned.insert(key.clone(), prune_ignored(child, options, path));
                 }
+                path.pop();
             }
+            Value::Object(pruned)
         }
+        _ => value.clone(),
     }
 }
 
+/// Merkle-optimized diff that skips paths ignored by `options`. Ignored
+/// subtrees are pruned before the Merkle trees are built, so they never make
+/// an enclosing object look changed.
+pub fn merkle_diff_with_options(
+    base: &Value,
+    target: &Value,
+    options: &DiffOptions,
+) -> Vec<DiffEntry> {
+    if options.is_empty() {
+        return merkle_diff(base, target);
+    }
+    let base = prune_ignored(base, options, &mut vec![]);
+    let target = prune_ignored(target, options, &mut vec![]);
+    merkle_diff(&base, &target)
+}
+
 /// Merkle-optimized diff: skips entire subtrees whose hashes match.
 /// Falls back to leaf comparison only where hashes differ.
 /// This is O(changes * log N) instead of O(N) for large states with few changes.
 pub fn merkle_diff(base: &Value, target: &Value) -> Vec<DiffEntry> {
     let base_tree = MerkleNode::from_value(base);
     let target_tree = MerkleNode::from_value(target);
+    merkle_diff_with_trees(&base_tree, &target_tree, base, target)
+}
+
+/// Merkle diff using prebuilt trees, e.g. ones cached in storage.
+/// Each tree must have been built from the value passed alongside it.
+pub fn merkle_diff_with_trees(
+    base_tree: &MerkleNode,
+    target_tree: &MerkleNode,
+    base: &Value,
+    target: &Value,
+) -> Vec<DiffEntry> {
     let mut entries = Vec::new();
-    merkle_diff_nodes(&base_tree, &target_tree, base, target, &mut vec![], &mut entries);
+    merkle_diff_nodes(
+        base_tree,
+        target_tree,
+        base,
+        target,
+        &mut vec![],
+        &mut |path, change_type, old, new| {
+            entries.push(DiffEntry {
+                path: path.to_vec(),
+                change_type,
+                old_value: old.cloned(),
+                new_value: new.cloned(),
+            })
+        },
+    );
     entries
 }
 
+/// Merkle-optimized diff statistics. Walks the same subtrees as
+/// `merkle_diff` but never clones changed values.
+pub fn merkle_diff_stats(base: &Value, target: &Value) -> DiffStats {
+    let base_tree = MerkleNode::from_value(base);
+    let target_tree = MerkleNode::from_value(target);
+    let mut stats = DiffStats::default();
+    merkle_diff_nodes(
+        &base_tree,
+        &target_tree,
+        base,
+        target,
+        &mut vec![],
+        &mut |path, change_type, old, new| stats.record(path, &change_type, old, new),
+    );
+    stats
+}
+
+/// Receives each change found by `merkle_diff_nodes`: path, kind, old and new value.
+type DiffSink<'a> = dyn FnMut(&[String], ChangeType, Option<&Value>, Option<&Value>) + 'a;
+
 fn merkle_diff_nodes(
     base_node: &MerkleNode,
     target_node: &MerkleNode,
     base_val: &Value,
     target_val: &Value,
     path: &mut Vec<String>,
-    entries: &mut Vec<DiffEntry>,
+    sink: &mut DiffSink<'_>,
 ) {
     // Fast path: if hashes match, entire subtree is identical
     if base_node.hash == target_node.hash {
@@ -276,15 +1106,10 @@ fn merkle_diff_nodes(
                     if base_child.hash != target_child.hash {
                         let bv = base_map.get(key).unwrap_or(&Value::Null);
                         let tv = target_map.get(key).unwrap_or(&Value::Null);
-                        merkle_diff_nodes(base_child, target_child, bv, tv, path, entries);
+                        merkle_diff_nodes(base_child, target_child, bv, tv, path, sink);
                     }
                 } else {
-                    entries.push(DiffEntry {
-                        path: path.clone(),
-                        change_type: ChangeType::Removed,
-                        old_value: base_map.get(key).cloned(),
-                        new_value: None,
-                    });
+                    sink(path, ChangeType::Removed, base_map.get(key), None);
                 }
                 path.pop();
             }
@@ -292,28 +1117,85 @@ fn merkle_diff_nodes(
             for key in target_node.children.keys() {
                 if !base_node.children.contains_key(key) {
                     path.push(key.clone());
-                    entries.push(DiffEntry {
-                        path: path.clone(),
-                        change_type: ChangeType::Added,
-                        old_value: None,
-                        new_value: target_map.get(key).cloned(),
-                    });
+                    sink(path, ChangeType::Added, None, target_map.get(key));
                     path.pop();
                 }
             }
         }
         _ => {
             // Leaf value changed (hashes already differ)
-            entries.push(DiffEntry {
-                path: path.clone(),
-                change_type: ChangeType::Changed,
-                old_value: Some(base_val.clone()),
-                new_value: Some(target_val.clone()),
-            });
+            sink(path, ChangeType::Changed, Some(base_val), Some(target_val));
         }
     }
 }
 
+/// Summary counts for a diff, without the changed values.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffStats {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    /// Entry count per top-level state key (`memory`, `world_state`, ...).
+    pub by_top_level_key: std::collections::BTreeMap<String, usize>,
+    /// Serialized JSON length of added and new values.
+    pub bytes_added: usize,
+    /// Serialized JSON length of removed and old values.
+    pub bytes_removed: usize,
+}
+
+impl DiffStats {
+    fn record(
+        &mut self,
+        path: &[String],
+        change_type: &ChangeType,
+        old: Option<&Value>,
+        new: Option<&Value>,
+    ) {
+        match change_type {
+            ChangeType::Added => self.added += 1,
+            ChangeType::Removed => self.removed += 1,
+            ChangeType::Changed => self.changed += 1,
+        }
+        let key = path.first().cloned().unwrap_or_default();
+        *self.by_top_level_key.entry(key).or_default() += 1;
+        self.bytes_removed += old.map_or(0, serialized_len);
+        self.bytes_added += new.map_or(0, serialized_len);
+    }
+}
+
+impl StateDiff {
+    /// Summary counts for this diff.
+    pub fn stats(&self) -> DiffStats {
+        let mut stats = DiffStats::default();
+        for entry in &self.entries {
+            stats.record(
+                &entry.path,
+                &entry.change_type,
+                entry.old_value.as_ref(),
+                entry.new_value.as_ref(),
+            );
+        }
+        stats
+    }
+}
+
+/// Length of `value` serialized as compact JSON, without allocating it.
+fn serialized_len(value: &Value) -> usize {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,7 +1206,9 @@ mod tests {
         let base = AgentState::new(json!({}), json!({}));
         let target = AgentState::new(json!({"key": "value"}), json!({}));
         let diff = diff_states(&base, &target);
-        let memory_entries: Vec<_> = diff.entries.iter()
+        let memory_entries: Vec<_> = diff
+            .entries
+            .iter()
             .filter(|e| e.path.first().map(|s| s.as_str()) == Some("memory"))
             .collect();
         assert!(!memory_entries.is_empty());
@@ -335,7 +1219,9 @@ mod tests {
         let base = AgentState::new(json!({"key": "value"}), json!({}));
         let target = AgentState::new(json!({}), json!({}));
         let diff = diff_states(&base, &target);
-        let removed: Vec<_> = diff.entries.iter()
+        let removed: Vec<_> = diff
+            .entries
+            .iter()
             .filter(|e| e.change_type == ChangeType::Removed)
             .collect();
         assert!(!removed.is_empty());
@@ -346,7 +1232,9 @@ mod tests {
         let base = AgentState::new(json!({"counter": 1}), json!({}));
         let target = AgentState::new(json!({"counter": 2}), json!({}));
         let diff = diff_states(&base, &target);
-        let changed: Vec<_> = diff.entries.iter()
+        let changed: Vec<_> = diff
+            .entries
+            .iter()
             .filter(|e| e.change_type == ChangeType::Changed)
             .collect();
         assert!(!changed.is_empty());
@@ -362,8 +1250,8 @@ mod tests {
     #[test]
     fn test_three_way_merge_no_conflict() {
         let base = json!({"a": 1, "b": 2});
-        let ours = json!({"a": 10, "b": 2});    // changed a
-        let theirs = json!({"a": 1, "b": 20});   // changed b
+        let ours = json!({"a": 10, "b": 2}); // changed a
+        let theirs = json!({"a": 1, "b": 20}); // changed b
         let (merged, conflicts) = three_way_merge(&base, &ours, &theirs);
         assert!(conflicts.is_empty());
         assert_eq!(merged, json!({"a": 10, "b": 20}));
@@ -431,8 +1319,14 @@ mod tests {
         let base = json!({"a": 1, "b": 2});
         let target = json!({"a": 1, "c": 3});
         let entries = merkle_diff(&base, &target);
-        let removed: Vec<_> = entries.iter().filter(|e| e.change_type == ChangeType::Removed).collect();
-        let added: Vec<_> = entries.iter().filter(|e| e.change_type == ChangeType::Added).collect();
+        let removed: Vec<_> = entries
+            .iter()
+            .filter(|e| e.change_type == ChangeType::Removed)
+            .collect();
+        let added: Vec<_> = entries
+            .iter()
+            .filter(|e| e.change_type == ChangeType::Added)
+            .collect();
         assert_eq!(removed.len(), 1);
         assert_eq!(removed[0].path, vec!["b"]);
         assert_eq!(added.len(), 1);
@@ -466,4 +1360,492 @@ mod tests {
         // Same number of changes detected
         assert_eq!(recursive.entries.len(), merkle.len());
     }
+    #[test]
+    fn test_value_at_path() {
+        let v = json!({"memory": {"scores": [1, {"x": 2}]}});
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            value_at_path(&v, &path(&["memory", "scores", "1", "x"])),
+            Some(&json!(2))
+        );
+        assert_eq!(value_at_path(&v, &path(&[])), Some(&v));
+        assert_eq!(value_at_path(&v, &path(&["memory", "missing"])), None);
+        assert_eq!(value_at_path(&v, &path(&["memory", "scores", "9"])), None);
+    }
+    #[test]
+    fn test_set_value_at_path() {
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut v = json!({"a": {"list": [1, 2]}, "n": 5});
+
+        set_value_at_path(&mut v, &path(&["a", "list", "1"]), json!(20)).unwrap();
+        set_value_at_path(&mut v, &path(&["a", "list", "2"]), json!(30)).unwrap();
+        set_value_at_path(&mut v, &path(&["x", "y", "z"]), json!(true)).unwrap();
+        set_value_at_path(&mut v, &path(&["n", "inner"]), json!(1)).unwrap();
+        assert_eq!(
+            v,
+            json!({"a": {"list": [1, 20, 30]}, "n": {"inner": 1}, "x": {"y": {"z": true}}})
+        );
+
+        let err = set_value_at_path(&mut v, &path(&["a", "list", "7"]), json!(0));
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_remove_value_at_path() {
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut v = json!({"a": {"b": 1, "list": [1, 2, 3]}});
+        assert_eq!(
+            remove_value_at_path(&mut v, &path(&["a", "b"])),
+            Some(json!(1))
+        );
+        assert_eq!(
+            remove_value_at_path(&mut v, &path(&["a", "list", "0"])),
+            Some(json!(1))
+        );
+        assert_eq!(
+            remove_value_at_path(&mut v, &path(&["a", "missing", "x"])),
+            None
+        );
+        assert_eq!(v, json!({"a": {"list": [2, 3]}}));
+    }
+    #[test]
+    fn test_apply_round_trip() {
+        let a = json!({
+            "memory": {"plan": {"steps": [1, 2], "done": false}, "old": "x"},
+            "world_state": {"cwd": "/tmp"}
+        });
+        let b = json!({
+            "memory": {"plan": {"steps": [1, 2, 3], "done": true}, "new": {"deep": 1}},
+            "world_state": {"cwd": "/tmp"}
+        });
+        let diff = StateDiff {
+            base_hash: String::new(),
+            target_hash: String::new(),
+            entries: merkle_diff(&a, &b),
+        };
+        assert_eq!(diff.apply(&a).unwrap(), b);
+
+        let mut recursive = Vec::new();
+        diff_values(&b, &a, &mut vec![], &mut recursive);
+        let reverse = StateDiff {
+            base_hash: String::new(),
+            target_hash: String::new(),
+            entries: recursive,
+        };
+        assert_eq!(reverse.apply(&b).unwrap(), a);
+    }
+
+    #[test]
+    fn test_apply_conflict() {
+        let a = json!({"count": 1, "gone": true});
+        let b = json!({"count": 2, "added": "x"});
+        let diff = StateDiff {
+            base_hash: String::new(),
+            target_hash: String::new(),
+            entries: merkle_diff(&a, &b),
+        };
+
+        let err = diff.apply(&json!({"count": 5, "gone": true})).unwrap_err();
+        match err {
+            AgitError::PatchConflict { path, .. } => assert_eq!(path, "count"),
+            other => panic!("unexpected error: {other}"),
+        }
+        assert!(diff
+            .apply(&json!({"count": 1, "gone": true, "added": "y"}))
+            .is_err());
+        assert!(diff.apply(&json!({"count": 1})).is_err());
+    }
+    #[test]
+    fn test_render() {
+        let diff = StateDiff {
+            base_hash: String::new(),
+            target_hash: String::new(),
+            entries: merkle_diff(
+                &json!({"count": 1, "gone": true}),
+                &json!({"count": 2, "added": {"x": "y"}}),
+            ),
+        };
+        let rendered = diff.render();
+        let mut lines: Vec<&str> = rendered.lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["+ added: {\"x\":\"y\"}", "- gone: true", "~ count: 1 -> 2"]
+        );
+    }
+    #[test]
+    fn test_json_pointer_escaping() {
+        let path = vec!["a/b".to_string(), "c~d".to_string(), "0".to_string()];
+        let pointer = to_json_pointer(&path);
+        assert_eq!(pointer, "/a~1b/c~0d/0");
+        assert_eq!(from_json_pointer(&pointer).unwrap(), path);
+        assert_eq!(from_json_pointer("").unwrap(), Vec::<String>::new());
+        assert_eq!(from_json_pointer("/~01").unwrap(), vec!["~1".to_string()]);
+        assert!(from_json_pointer("no-slash").is_err());
+    }
+
+    #[test]
+    fn test_json_patch_round_trip() {
+        let a = json!({"memory": {"list": [1, 2], "k/ey": "x", "gone": 1}});
+        let b = json!({"memory": {"list": [1, 2, 3], "k/ey": "y", "new": {"n": true}}});
+        let diff = StateDiff {
+            base_hash: "a".to_string(),
+            target_hash: "b".to_string(),
+            entries: merkle_diff(&a, &b),
+        };
+
+        let patch = diff.to_json_patch();
+        let ops = patch.as_array().unwrap();
+        assert!(ops.contains(&json!({"op": "replace", "path": "/memory/k~1ey", "value": "y"})));
+        assert!(ops.contains(&json!({"op": "remove", "path": "/memory/gone"})));
+        assert!(ops.contains(&json!({"op": "add", "path": "/memory/new", "value": {"n": true}})));
+
+        let imported = StateDiff::from_json_patch("a", "b", &patch).unwrap();
+        assert_eq!(imported.entries.len(), diff.entries.len());
+        assert_eq!(imported.apply(&a).unwrap(), b);
+    }
+
+    #[test]
+    fn test_json_patch_array_indices() {
+        let patch = json!([
+            {"op": "replace", "path": "/items/0", "value": "first"},
+            {"op": "add", "path": "/items/-", "value": "last"},
+            {"op": "remove", "path": "/items/1"}
+        ]);
+        let diff = StateDiff::from_json_patch("", "", &patch).unwrap();
+        assert_eq!(
+            diff.entries[0].path,
+            vec!["items".to_string(), "0".to_string()]
+        );
+        let result = diff.apply(&json!({"items": ["a", "b"]})).unwrap();
+        assert_eq!(result, json!({"items": ["first", "last"]}));
+
+        let bad = json!([{"op": "move", "from": "/a", "path": "/b"}]);
+        assert!(StateDiff::from_json_patch("", "", &bad).is_err());
+    }
+    #[test]
+    fn test_array_merge_disjoint_appends() {
+        let base = json!({"messages": ["hi"]});
+        let ours = json!({"messages": ["hi", "a"]});
+        let theirs = json!({"messages": ["hi", "b"]});
+
+        let (_, conflicts) = three_way_merge(&base, &ours, &theirs);
+        assert_eq!(conflicts.len(), 1);
+
+        let merge = |strategy| {
+            let config = MergeConfig::default().with_array_strategy(strategy);
+            let (merged, conflicts) = three_way_merge_with_config(&base, &ours, &theirs, &config);
+            assert!(conflicts.is_empty());
+            merged["messages"].clone()
+        };
+        assert_eq!(merge(ArrayMergeStrategy::Union), json!(["hi", "a", "b"]));
+        assert_eq!(
+            merge(ArrayMergeStrategy::ConcatOursFirst),
+            json!(["hi", "a", "b"])
+        );
+        assert_eq!(
+            merge(ArrayMergeStrategy::ConcatTheirsFirst),
+            json!(["hi", "b", "a"])
+        );
+    }
+
+    #[test]
+    fn test_array_merge_same_item_appended() {
+        let base = json!({"messages": [{"id": 1}]});
+        let ours = json!({"messages": [{"id": 1}, {"id": 2}, {"id": 3}]});
+        let theirs = json!({"messages": [{"id": 1}, {"id": 2}, {"id": 4}]});
+
+        let config = MergeConfig::default().with_array_strategy(ArrayMergeStrategy::Union);
+        let (merged, _) = three_way_merge_with_config(&base, &ours, &theirs, &config);
+        assert_eq!(
+            merged["messages"],
+            json!([{"id": 1}, {"id": 2}, {"id": 3}, {"id": 4}])
+        );
+
+        let config =
+            MergeConfig::default().with_array_strategy(ArrayMergeStrategy::ConcatOursFirst);
+        let (merged, _) = three_way_merge_with_config(&base, &ours, &theirs, &config);
+        assert_eq!(
+            merged["messages"],
+            json!([{"id": 1}, {"id": 2}, {"id": 3}, {"id": 2}, {"id": 4}])
+        );
+
+        // Identical appends on both sides never conflict
+        let same = json!({"messages": [{"id": 1}, {"id": 2}]});
+        let (merged, conflicts) = three_way_merge(&base, &same, &same);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, same);
+    }
+
+    #[test]
+    fn test_array_strategy_per_path_prefix() {
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let config = MergeConfig::default()
+            .with_array_strategy_at(path(&["memory"]), ArrayMergeStrategy::Union)
+            .with_array_strategy_at(
+                path(&["memory", "log"]),
+                ArrayMergeStrategy::ConcatTheirsFirst,
+            );
+        assert_eq!(
+            config.array_strategy_for(&path(&["world_state", "x"])),
+            ArrayMergeStrategy::Replace
+        );
+        assert_eq!(
+            config.array_strategy_for(&path(&["memory", "tags"])),
+            ArrayMergeStrategy::Union
+        );
+        assert_eq!(
+            config.array_strategy_for(&path(&["memory", "log"])),
+            ArrayMergeStrategy::ConcatTheirsFirst
+        );
+
+        let base = json!({"memory": {"tags": []}, "world_state": {"list": [1]}});
+        let ours = json!({"memory": {"tags": ["x"]}, "world_state": {"list": [1, 2]}});
+        let theirs = json!({"memory": {"tags": ["y"]}, "world_state": {"list": [1, 3]}});
+        let (merged, conflicts) = three_way_merge_with_config(&base, &ours, &theirs, &config);
+        assert_eq!(merged["memory"]["tags"], json!(["x", "y"]));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, path(&["world_state", "list"]));
+    }
+    #[test]
+    fn test_merge_drivers_resolve_conflicts() {
+        use crate::merge_driver::BuiltinDriver;
+
+        let base = json!({
+            "scratchpad": {"note": "base"},
+            "counters": {"calls": 10, "tokens": 100},
+            "facts": {"city": {"value": "Paris", "updated_at": "2026-01-01T00:00:00Z"}},
+            "other": 1
+        });
+        let ours = json!({
+            "scratchpad": {"note": "ours"},
+            "counters": {"calls": 12, "tokens": 150},
+            "facts": {"city": {"value": "Rome", "updated_at": "2026-01-03T00:00:00Z"}},
+            "other": 2
+        });
+        let theirs = json!({
+            "scratchpad": {"note": "theirs"},
+            "counters": {"calls": 15, "tokens": 120},
+            "facts": {"city": {"value": "Oslo", "updated_at": "2026-01-02T00:00:00Z"}},
+            "other": 3
+        });
+
+        let mut drivers = MergeDriverRegistry::new();
+        drivers
+            .register_builtin("scratchpad.*", BuiltinDriver::TakeTheirs)
+            .register_builtin("counters.calls", BuiltinDriver::NumericMax)
+            .register_builtin("counters.tokens", BuiltinDriver::NumericSum)
+            .register_builtin(
+                "facts.*",
+                BuiltinDriver::LatestTimestamp {
+                    field: "updated_at".to_string(),
+                },
+            );
+        let config = MergeConfig::default().with_drivers(drivers);
+        let (merged, conflicts) = three_way_merge_with_config(&base, &ours, &theirs, &config);
+
+        assert_eq!(merged["scratchpad"]["note"], json!("theirs"));
+        assert_eq!(merged["counters"], json!({"calls": 15, "tokens": 170}));
+        assert_eq!(merged["facts"]["city"]["value"], json!("Rome"));
+        // No driver covers `other`
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, vec!["other".to_string()]);
+    }
+    #[test]
+    fn test_merkle_diff_with_options() {
+        let base = json!({
+            "memory": {"answer": 1, "last_updated": "t1"},
+            "world_state": {"a": {"request_id": "r1", "ok": true}, "b": {"request_id": "r2"}}
+        });
+        let target = json!({
+            "memory": {"answer": 2, "last_updated": "t2"},
+            "world_state": {"a": {"request_id": "r3", "ok": true}, "b": {"request_id": "r4"}}
+        });
+        assert_eq!(merkle_diff(&base, &target).len(), 4);
+
+        let options = DiffOptions {
+            ignore_paths: vec![vec!["memory".to_string(), "last_updated".to_string()]],
+            ignore_globs: vec!["world_state.*.request_id".to_string()],
+        };
+        let entries = merkle_diff_with_options(&base, &target, &options);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].path,
+            vec!["memory".to_string(), "answer".to_string()]
+        );
+
+        // Only ignored fields changed: no entries at all
+        let mut quiet = target.clone();
+        quiet["memory"]["answer"] = json!(1);
+        assert!(merkle_diff_with_options(&base, &quiet, &options).is_empty());
+    }
+    #[test]
+    fn test_diff_stats() {
+        let base = json!({
+            "memory": {"a": 1, "gone": "xyz"},
+            "world_state": {"w": true},
+            "metadata": {}
+        });
+        let target = json!({
+            "memory": {"a": 22, "new": [1, 2]},
+            "world_state": {"w": false},
+            "metadata": {"m": null}
+        });
+
+        let stats = merkle_diff_stats(&base, &target);
+        assert_eq!((stats.added, stats.removed, stats.changed), (2, 1, 2));
+        assert_eq!(stats.by_top_level_key["memory"], 3);
+        assert_eq!(stats.by_top_level_key["world_state"], 1);
+        assert_eq!(stats.by_top_level_key["metadata"], 1);
+        // removed "xyz" (5) + old 1 (1) + old true (4)
+        assert_eq!(stats.bytes_removed, 10);
+        // added [1,2] (5) + null (4) + new 22 (2) + false (5)
+        assert_eq!(stats.bytes_added, 16);
+
+        let diff = StateDiff {
+            base_hash: String::new(),
+            target_hash: String::new(),
+            entries: merkle_diff(&base, &target),
+        };
+        assert_eq!(diff.stats(), stats);
+    }
+    #[test]
+    fn test_three_way_merge_traced() {
+        let base = json!({"a": 1, "b": 1, "c": {"x": 1, "y": 1}, "list": [1]});
+        let ours = json!({"a": 2, "b": 1, "c": {"x": 1, "y": 2}, "list": [1, 2]});
+        let theirs = json!({"a": 1, "b": 3, "c": {"x": 3, "y": 1}, "list": [1, 3]});
+        let config = MergeConfig::default().with_array_strategy(ArrayMergeStrategy::Union);
+
+        let (merged, conflicts, resolution) =
+            three_way_merge_traced(&base, &ours, &theirs, &config);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"a": 2, "b": 3, "c": {"x": 3, "y": 2}, "list": [1, 2, 3]})
+        );
+
+        let p = |s: &str| s.split('.').map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(resolution.from_ours, vec![p("a"), p("c.y")]);
+        assert_eq!(resolution.from_theirs, vec![p("b"), p("c.x")]);
+        assert_eq!(resolution.resolved, vec![p("list")]);
+        assert_eq!(resolution.len(), 5);
+    }
+    #[cfg(feature = "parallel")]
+    fn next_random(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    /// Random JSON of small nested objects and leaves.
+    #[cfg(feature = "parallel")]
+    fn random_value(seed: &mut u64, depth: usize) -> Value {
+        match (next_random(seed) % 6, depth) {
+            (0, _) => json!(next_random(seed) % 1000),
+            (1, _) => json!(format!("s{}", next_random(seed))),
+            (2, _) => json!([next_random(seed) % 10, null, true]),
+            (3, _) | (_, 0) => Value::Null,
+            _ => {
+                let width = next_random(seed) % 8;
+                let mut map = serde_json::Map::new();
+                for i in 0..width {
+                    let key = format!("k{}_{}", i, next_random(seed) % 100);
+                    map.insert(key, random_value(seed, depth - 1));
+                }
+                Value::Object(map)
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_merkle_matches_sequential() {
+        for case in 0..16u64 {
+            let mut seed = 0x9e37_79b9_7f4a_7c15 ^ (case + 1);
+            // Force the top object, and one nested object, onto the parallel path
+            let wide = |seed: &mut u64| {
+                let mut map = serde_json::Map::new();
+                for i in 0..PARALLEL_MIN_KEYS + case as usize {
+                    map.insert(format!("k{}", i), random_value(seed, 2));
+                }
+                Value::Object(map)
+            };
+            let mut value = wide(&mut seed);
+            value["nested"] = wide(&mut seed);
+            let sequential = MerkleNode::from_value_sequential(&value);
+            let parallel = MerkleNode::from_value_parallel(&value);
+            assert_eq!(sequential.hash, parallel.hash, "case {}", case);
+            assert_eq!(
+                serde_json::to_value(&sequential).unwrap(),
+                serde_json::to_value(&parallel).unwrap()
+            );
+        }
+    }
+    #[test]
+    fn test_agent_state_builder() {
+        let state = AgentState::builder()
+            .messages(vec![json!({"role": "user", "content": "hi"})])
+            .message(json!({"role": "assistant", "content": "hello"}))
+            .plan(json!(["search", "answer"]))
+            .tool_state("browser", json!({"url": "https://example.com"}))
+            .tool_state("shell", json!({"cwd": "/"}))
+            .memory("goal", json!("help"))
+            .metadata("run", json!(7))
+            .cost(0.25)
+            .build();
+
+        assert_eq!(state.messages().unwrap().as_array().unwrap().len(), 2);
+        assert_eq!(state.plan(), Some(&json!(["search", "answer"])));
+        assert_eq!(state.scratchpad(), None);
+        assert_eq!(
+            state.tool_state("browser"),
+            Some(&json!({"url": "https://example.com"}))
+        );
+        assert_eq!(state.tool_state("missing"), None);
+        assert_eq!(state.memory["goal"], json!("help"));
+        assert_eq!(state.metadata["run"], json!(7));
+        assert_eq!(state.cost, 0.25);
+
+        // Free-form states simply have no conventional fields
+        let free = AgentState::new(json!("opaque"), json!([1, 2]));
+        assert_eq!(free.messages(), None);
+        assert_eq!(free.tool_state("browser"), None);
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let path = |p: &str| p.split('.').map(String::from).collect::<Vec<_>>();
+        let state = json!({
+            "memory": {"facts": ["a", "b"], "user": {"name": "ada", "tz": "UTC"}},
+            "world_state": {"cursor": 3},
+            "cost": 1.5,
+        });
+        let tree = MerkleNode::from_value(&state);
+
+        let proof = tree.prove(&path("memory.user.name")).unwrap();
+        assert_eq!(proof.siblings.len(), 3);
+        assert!(proof.verify(&tree.hash, &path("memory.user.name"), &json!("ada")));
+        // Subtrees and arrays are proven as a whole
+        let proof_user = tree.prove(&path("memory.user")).unwrap();
+        assert!(proof_user.verify(&tree.hash, &path("memory.user"), &state["memory"]["user"]));
+        let proof_facts = tree.prove(&path("memory.facts")).unwrap();
+        assert!(proof_facts.verify(&tree.hash, &path("memory.facts"), &json!(["a", "b"])));
+
+        // Tampered value
+        assert!(!proof.verify(&tree.hash, &path("memory.user.name"), &json!("eve")));
+        // Tampered sibling
+        let mut tampered = proof.clone();
+        tampered.siblings[2].insert("tz".into(), "0".repeat(64));
+        assert!(!tampered.verify(&tree.hash, &path("memory.user.name"), &json!("ada")));
+        // Proof replayed for another path or root
+        assert!(!proof.verify(&tree.hash, &path("memory.user.tz"), &json!("ada")));
+        assert!(!proof.verify(&tree.hash, &path("memory.user"), &json!("ada")));
+        assert!(!proof.verify(&"0".repeat(64), &path("memory.user.name"), &json!("ada")));
+
+        // Missing paths, including inside arrays and scalars
+        assert!(tree.prove(&path("memory.user.email")).is_none());
+        assert!(tree.prove(&path("memory.facts.0")).is_none());
+        assert!(tree.prove(&path("cost.x")).is_none());
+    }
 }
@@ -0,0 +1,542 @@
+//! Behaviour every `StorageBackend` must share, run against each backend
+//! through `storage_conformance_tests!`.
+
+use chrono::Utc;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use super::{LogDeleteFilter, LogEntry, LogFilter, RefUpdate, StorageBackend};
+use crate::error::AgitError;
+use crate::objects::{tree_key, Blob, Commit};
+use crate::types::{ActionType, Hash, LogLevel, ObjectType};
+
+/// Generate one `#[tokio::test]` per conformance check.
+///
+/// `$setup` is evaluated inside each async test and must yield
+/// `(storage, guard)`; the guard (e.g. a temp dir) lives until the test ends.
+macro_rules! storage_conformance_tests {
+    ($setup:expr) => {
+        mod conformance {
+            use super::*;
+
+            storage_conformance_tests!(@tests $setup;
+                put_get_object,
+                get_missing_object,
+                idempotent_put,
+                get_object_range,
+                has_object,
+                list_and_delete_objects,
+                list_objects_by_type,
+                stat_objects,
+                refs,
+                ref_transactions,
+                logs,
+                log_filters,
+                log_message_query,
+                log_pagination,
+                log_deletion,
+                locks
+            );
+        }
+    };
+    (@tests $setup:expr; $($name:ident),*) => {
+        $(
+            #[tokio::test]
+            async fn $name() {
+                let (storage, _guard) = $setup;
+                crate::storage::conformance::$name(&storage).await;
+            }
+        )*
+    };
+}
+
+fn commit_bytes() -> Vec<u8> {
+    let commit = Commit {
+        tree_hash: Hash::from("abc123"),
+        parent_hashes: vec![],
+        message: "test".to_string(),
+        author: "agent".to_string(),
+        timestamp: Utc::now(),
+        action_type: ActionType::ToolCall,
+        metadata: serde_json::Map::new(),
+    };
+    serde_json::to_vec(&commit).unwrap()
+}
+
+pub(crate) fn log_entry(id: &str, timestamp: &str, agent_id: &str, action: &str) -> LogEntry {
+    LogEntry {
+        id: id.to_string(),
+        timestamp: timestamp.to_string(),
+        agent_id: agent_id.to_string(),
+        action: action.to_string(),
+        message: format!("message {}", id),
+        commit_hash: Some("abc123".to_string()),
+        details: Some(json!({"tool": "search"})),
+        level: LogLevel::Info,
+    }
+}
+
+pub async fn put_get_object(storage: &dyn StorageBackend) {
+    let data = b"hello world";
+    storage
+        .put_object("abc123", ObjectType::Blob, data)
+        .await
+        .unwrap();
+    let result = storage.get_object("abc123").await.unwrap();
+    assert_eq!(result, Some(data.to_vec()));
+}
+
+pub async fn get_missing_object(storage: &dyn StorageBackend) {
+    let result = storage.get_object("nonexistent").await.unwrap();
+    assert!(result.is_none());
+}
+
+pub async fn idempotent_put(storage: &dyn StorageBackend) {
+    storage
+        .put_object("abc", ObjectType::Blob, b"data")
+        .await
+        .unwrap();
+    storage
+        .put_object("abc", ObjectType::Blob, b"data")
+        .await
+        .unwrap();
+    assert_eq!(storage.get_object("abc").await.unwrap().unwrap(), b"data");
+}
+
+pub async fn get_object_range(storage: &dyn StorageBackend) {
+    storage
+        .put_object("abc", ObjectType::Blob, b"hello world")
+        .await
+        .unwrap();
+    let range = |offset, len| storage.get_object_range("abc", offset, len);
+    assert_eq!(range(0, 5).await.unwrap().unwrap(), b"hello");
+    assert_eq!(range(6, 100).await.unwrap().unwrap(), b"world");
+    assert_eq!(range(20, 5).await.unwrap().unwrap(), b"");
+    assert!(storage
+        .get_object_range("missing", 0, 5)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+pub async fn has_object(storage: &dyn StorageBackend) {
+    storage
+        .put_object("abc", ObjectType::Blob, b"data")
+        .await
+        .unwrap();
+    assert!(storage.has_object("abc").await.unwrap());
+    assert!(!storage.has_object("xyz").await.unwrap());
+}
+
+pub async fn list_and_delete_objects(storage: &dyn StorageBackend) {
+    storage
+        .put_object("b1", ObjectType::Blob, b"{}")
+        .await
+        .unwrap();
+    storage
+        .put_object("b2", ObjectType::Blob, b"[]")
+        .await
+        .unwrap();
+    storage
+        .put_object("b3", ObjectType::Blob, b"1")
+        .await
+        .unwrap();
+
+    let mut all = storage.list_objects().await.unwrap();
+    all.sort();
+    assert_eq!(all, vec!["b1", "b2", "b3"]);
+
+    assert!(storage.delete_object("b1").await.unwrap());
+    assert!(!storage.delete_object("b1").await.unwrap());
+    assert!(!storage.has_object("b1").await.unwrap());
+    assert_eq!(storage.list_objects().await.unwrap().len(), 2);
+}
+
+pub async fn list_objects_by_type(storage: &dyn StorageBackend) {
+    let blob = Blob::new(json!({"memory": {}})).serialize();
+    storage
+        .put_object("b1", ObjectType::Blob, &blob)
+        .await
+        .unwrap();
+    storage
+        .put_object("c1", ObjectType::Commit, &commit_bytes())
+        .await
+        .unwrap();
+    storage
+        .put_object(&tree_key("b1"), ObjectType::Tree, b"{}")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        storage
+            .list_objects_by_type(ObjectType::Blob)
+            .await
+            .unwrap(),
+        vec!["b1"]
+    );
+    assert_eq!(
+        storage
+            .list_objects_by_type(ObjectType::Commit)
+            .await
+            .unwrap(),
+        vec!["c1"]
+    );
+    assert_eq!(
+        storage
+            .list_objects_by_type(ObjectType::Tree)
+            .await
+            .unwrap(),
+        vec![tree_key("b1")]
+    );
+}
+
+pub async fn stat_objects(storage: &dyn StorageBackend) {
+    let commit = commit_bytes();
+    storage
+        .put_object("c1", ObjectType::Commit, &commit)
+        .await
+        .unwrap();
+    storage
+        .put_object("b1", ObjectType::Blob, b"{\"a\":1}")
+        .await
+        .unwrap();
+
+    let stat = storage.stat_object("c1").await.unwrap().unwrap();
+    assert_eq!(stat.obj_type, ObjectType::Commit);
+    assert_eq!(stat.size, commit.len() as u64);
+    if let Some(created_at) = stat.created_at {
+        assert!((Utc::now() - created_at).num_seconds().abs() < 60);
+    }
+    assert!(storage.stat_object("missing").await.unwrap().is_none());
+
+    let stats = storage.storage_stats().await.unwrap();
+    assert_eq!(stats.count, 2);
+    assert_eq!(stats.bytes, commit.len() as u64 + 7);
+    assert_eq!(stats.by_type["commit"].count, 1);
+    assert_eq!(stats.by_type["blob"].bytes, 7);
+}
+
+pub async fn refs(storage: &dyn StorageBackend) {
+    storage.set_ref("main", "abc123").await.unwrap();
+    storage.set_ref("dev", "def456").await.unwrap();
+    storage.set_ref("feature/x", "0123").await.unwrap();
+
+    assert_eq!(
+        storage.get_ref("main").await.unwrap(),
+        Some("abc123".to_string())
+    );
+    assert_eq!(
+        storage.get_ref("feature/x").await.unwrap(),
+        Some("0123".to_string())
+    );
+    assert_eq!(storage.get_ref("missing").await.unwrap(), None);
+
+    storage.set_ref("main", "fff000").await.unwrap();
+    let refs = storage.list_refs().await.unwrap();
+    assert_eq!(refs.len(), 3);
+    assert_eq!(refs["main"], "fff000");
+    assert_eq!(refs["feature/x"], "0123");
+
+    assert!(storage.delete_ref("dev").await.unwrap());
+    assert!(!storage.delete_ref("dev").await.unwrap());
+    let refs = storage.list_refs().await.unwrap();
+    assert_eq!(refs.len(), 2);
+}
+
+pub async fn ref_transactions(storage: &dyn StorageBackend) {
+    storage.set_ref("main", "aaa").await.unwrap();
+    storage.set_ref("old", "bbb").await.unwrap();
+
+    storage
+        .update_refs(&[
+            RefUpdate::set("main", "ccc").expecting("aaa"),
+            RefUpdate::set("HEAD", "main"),
+            RefUpdate::delete("old"),
+        ])
+        .await
+        .unwrap();
+    let refs = storage.list_refs().await.unwrap();
+    assert_eq!(refs.len(), 2);
+    assert_eq!(refs["main"], "ccc");
+    assert_eq!(refs["HEAD"], "main");
+
+    // A stale expectation anywhere in the batch applies none of it
+    let err = storage
+        .update_refs(&[
+            RefUpdate::set("HEAD", "dev"),
+            RefUpdate::set("dev", "ddd"),
+            RefUpdate::set("main", "eee").expecting("aaa"),
+        ])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AgitError::ConcurrentUpdate { name } if name == "main"));
+    assert_eq!(storage.list_refs().await.unwrap(), refs);
+
+    // Creating a ref that must not exist yet
+    storage
+        .update_refs(&[RefUpdate::set("dev", "fff").expecting_absent()])
+        .await
+        .unwrap();
+    let err = storage
+        .update_refs(&[RefUpdate::set("dev", "ggg").expecting_absent()])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AgitError::ConcurrentUpdate { name } if name == "dev"));
+    assert_eq!(
+        storage.get_ref("dev").await.unwrap(),
+        Some("fff".to_string())
+    );
+}
+
+pub async fn logs(storage: &dyn StorageBackend) {
+    let entry = log_entry("log-1", "2026-01-01T00:00:00Z", "agent-1", "tool_call");
+    storage.append_log(&entry).await.unwrap();
+
+    let filter = LogFilter {
+        agent_id: Some("agent-1".to_string()),
+        ..Default::default()
+    };
+    let logs = storage.query_logs(&filter).await.unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].message, "message log-1");
+    assert_eq!(logs[0].details, entry.details);
+    assert_eq!(logs[0].commit_hash, entry.commit_hash);
+}
+
+pub async fn log_filters(storage: &dyn StorageBackend) {
+    let entries = [
+        log_entry("1", "2026-01-01T00:00:00Z", "a", "commit"),
+        log_entry("2", "2026-01-02T00:00:00Z", "b", "commit"),
+        log_entry("3", "2026-01-03T00:00:00Z", "a", "merge"),
+    ];
+    for entry in &entries {
+        storage.append_log(entry).await.unwrap();
+    }
+    let ids = |logs: Vec<LogEntry>| logs.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+    // Newest first
+    let all = storage.query_logs(&LogFilter::default()).await.unwrap();
+    assert_eq!(ids(all), vec!["3", "2", "1"]);
+
+    let filter = LogFilter {
+        action: Some("commit".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(storage.query_logs(&filter).await.unwrap()),
+        vec!["2", "1"]
+    );
+
+    let filter = LogFilter {
+        since: Some("2026-01-02T00:00:00Z".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(storage.query_logs(&filter).await.unwrap()),
+        vec!["3", "2"]
+    );
+
+    let filter = LogFilter {
+        since: Some("2026-01-02T00:00:00Z".to_string()),
+        until: Some("2026-01-02T00:00:00Z".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(ids(storage.query_logs(&filter).await.unwrap()), vec!["2"]);
+
+    let filter = LogFilter {
+        until: Some("2026-01-02T12:00:00Z".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(storage.query_logs(&filter).await.unwrap()),
+        vec!["2", "1"]
+    );
+
+    let filter = LogFilter {
+        agent_id: Some("a".to_string()),
+        limit: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(ids(storage.query_logs(&filter).await.unwrap()), vec!["3"]);
+}
+
+pub async fn log_message_query(storage: &dyn StorageBackend) {
+    let mut timeout = log_entry("1", "2026-01-01T00:00:00Z", "a", "tool_call");
+    timeout.message = "Request Timeout after 30s".to_string();
+    let mut in_details = log_entry("2", "2026-01-02T00:00:00Z", "a", "tool_call");
+    in_details.details = Some(json!({"error": "upstream timeout"}));
+    let other = log_entry("3", "2026-01-03T00:00:00Z", "b", "tool_call");
+    for entry in [&timeout, &in_details, &other] {
+        storage.append_log(entry).await.unwrap();
+    }
+    let ids = |logs: Vec<LogEntry>| logs.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+    // Case-insensitive, over both message and details
+    let filter = LogFilter {
+        message_query: Some("timeout".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(storage.query_logs(&filter).await.unwrap()),
+        vec!["2", "1"]
+    );
+
+    let filter = LogFilter {
+        message_query: Some("timeout".to_string()),
+        since: Some("2026-01-02T00:00:00Z".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(ids(storage.query_logs(&filter).await.unwrap()), vec!["2"]);
+
+    // Query syntax characters are matched literally
+    let filter = LogFilter {
+        message_query: Some("\"after 30s\" OR".to_string()),
+        ..Default::default()
+    };
+    assert!(storage.query_logs(&filter).await.unwrap().is_empty());
+}
+
+pub async fn log_pagination(storage: &dyn StorageBackend) {
+    for i in 1..=5 {
+        let mut entry = log_entry(
+            &i.to_string(),
+            &format!("2026-01-0{i}T00:00:00Z"),
+            "a",
+            "commit",
+        );
+        entry.commit_hash = Some(if i % 2 == 0 { "even" } else { "odd" }.to_string());
+        entry.message = format!("Step {i} done");
+        storage.append_log(&entry).await.unwrap();
+    }
+    let ids = |logs: Vec<LogEntry>| logs.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+    let page = |offset| LogFilter {
+        limit: Some(2),
+        offset: Some(offset),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(storage.query_logs(&page(0)).await.unwrap()),
+        vec!["5", "4"]
+    );
+    assert_eq!(
+        ids(storage.query_logs(&page(2)).await.unwrap()),
+        vec!["3", "2"]
+    );
+    assert_eq!(ids(storage.query_logs(&page(4)).await.unwrap()), vec!["1"]);
+    assert!(storage.query_logs(&page(5)).await.unwrap().is_empty());
+    // Counts ignore limit and offset
+    assert_eq!(storage.count_logs(&page(2)).await.unwrap(), 5);
+
+    let filter = LogFilter {
+        commit_hash: Some("even".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(storage.query_logs(&filter).await.unwrap()),
+        vec!["4", "2"]
+    );
+    assert_eq!(storage.count_logs(&filter).await.unwrap(), 2);
+
+    // Case-sensitive substring of the message only
+    let filter = LogFilter {
+        message_contains: Some("p 3".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(ids(storage.query_logs(&filter).await.unwrap()), vec!["3"]);
+    let filter = LogFilter {
+        message_contains: Some("step".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(storage.count_logs(&filter).await.unwrap(), 0);
+    let filter = LogFilter {
+        message_contains: Some("search".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(storage.count_logs(&filter).await.unwrap(), 0);
+
+    let filter = LogFilter {
+        commit_hash: Some("odd".to_string()),
+        since: Some("2026-01-02T00:00:00Z".to_string()),
+        until: Some("2026-01-04T00:00:00Z".to_string()),
+        offset: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(ids(storage.query_logs(&filter).await.unwrap()), vec!["3"]);
+    assert_eq!(storage.count_logs(&filter).await.unwrap(), 1);
+}
+
+pub async fn log_deletion(storage: &dyn StorageBackend) {
+    for day in 1..=4 {
+        for agent in ["a", "b"] {
+            let id = format!("{agent}{day}");
+            let timestamp = format!("2026-01-0{day}T00:00:00Z");
+            storage
+                .append_log(&log_entry(&id, &timestamp, agent, "commit"))
+                .await
+                .unwrap();
+        }
+    }
+    let ids = |logs: Vec<LogEntry>| logs.into_iter().map(|e| e.id).collect::<Vec<_>>();
+    let remaining = || async { ids(storage.query_logs(&LogFilter::default()).await.unwrap()) };
+
+    // Strictly before the cutoff, one agent only
+    let filter = LogDeleteFilter {
+        agent_id: Some("a".to_string()),
+        before: Some("2026-01-02T00:00:00Z".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(storage.delete_logs(&filter).await.unwrap(), 1);
+    assert_eq!(remaining().await.len(), 7);
+
+    // The newest two of each agent survive
+    let filter = LogDeleteFilter {
+        keep_last: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(storage.delete_logs(&filter).await.unwrap(), 3);
+    let mut left = remaining().await;
+    left.sort();
+    assert_eq!(left, vec!["a3", "a4", "b3", "b4"]);
+
+    // Every set condition must hold
+    let filter = LogDeleteFilter {
+        agent_id: Some("b".to_string()),
+        before: Some("2026-01-04T00:00:00Z".to_string()),
+        keep_last: Some(1),
+    };
+    assert_eq!(storage.delete_logs(&filter).await.unwrap(), 1);
+
+    let filter = LogDeleteFilter {
+        agent_id: Some("b".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(storage.delete_logs(&filter).await.unwrap(), 1);
+    assert_eq!(remaining().await, vec!["a4", "a3"]);
+}
+
+pub async fn locks(storage: &dyn StorageBackend) {
+    // Backends without their own lock share one per process
+    let name = format!("conformance-{}", uuid::Uuid::new_v4());
+    let held = storage
+        .acquire_lock(&name, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert!(matches!(
+        storage.acquire_lock(&name, Duration::from_millis(50)).await,
+        Err(AgitError::LockTimeout { .. })
+    ));
+
+    // A waiter blocks until the holder releases
+    let start = Instant::now();
+    let release = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        drop(held);
+    };
+    let ((), second) = tokio::join!(
+        release,
+        storage.acquire_lock(&name, Duration::from_secs(10))
+    );
+    second.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
@@ -0,0 +1,597 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::{
+    LockGuard, LogDeleteFilter, LogEntry, LogFilter, ObjectStat, RefUpdate, StorageBackend,
+};
+use crate::error::{AgitError, Result};
+use crate::objects::infer_object_type;
+use crate::types::ObjectType;
+
+const OBJECTS_DIR: &str = "objects";
+const REFS_DIR: &str = "refs";
+const TMP_DIR: &str = "tmp";
+const LOCKS_DIR: &str = "locks";
+const LOG_FILE: &str = "logs.jsonl";
+const REFS_LOCK: &str = "refs.lock";
+const LOGS_LOCK: &str = "logs.lock";
+
+/// How often `acquire_lock` retries a lock held by someone else.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Fan-out directory for keys too short to split.
+const SHORT_KEY_DIR: &str = "_";
+
+/// Filesystem-backed storage with no database, for local development and
+/// air-gapped environments.
+///
+/// Layout under the root directory:
+/// ```text
+/// objects/ab/cdef…   – object bytes, fanned out by the first two characters of the key
+/// refs/<name>        – target hash; characters other than [A-Za-z0-9_-] are %-encoded
+/// logs.jsonl         – one JSON log entry per line, fsynced on append
+/// refs.lock          – locked exclusively while a ref or a batch of refs is updated
+/// logs.lock          – locked exclusively while the log is appended to or rewritten
+/// locks/<name>       – locked exclusively while `acquire_lock(name)` is held, named like refs
+/// tmp/               – staging area for atomic writes
+/// ```
+///
+/// Objects and refs are written to `tmp/` and renamed into place, so
+/// readers never see partial content. Object types are not recorded and
+/// are inferred from keys and contents where needed.
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    /// Open (creating if needed) a storage directory at `root`.
+    pub async fn new(root: &Path) -> Result<Self> {
+        let storage = FsStorage {
+            root: root.to_path_buf(),
+        };
+        storage.initialize().await?;
+        Ok(storage)
+    }
+
+    fn object_path(&self, hash: &str) -> Result<PathBuf> {
+        let valid = !hash.is_empty()
+            && !hash.starts_with('.')
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+        if !valid {
+            return Err(AgitError::InvalidArgument(format!(
+                "invalid object key: {:?}",
+                hash
+            )));
+        }
+        let (dir, file) = match hash.split_at_checked(2) {
+            Some((dir, file)) if !file.is_empty() && file != "." && file != ".." => (dir, file),
+            _ => (SHORT_KEY_DIR, hash),
+        };
+        Ok(self.root.join(OBJECTS_DIR).join(dir).join(file))
+    }
+
+    fn ref_path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() {
+            return Err(AgitError::InvalidArgument("empty ref name".into()));
+        }
+        Ok(self.root.join(REFS_DIR).join(encode_ref(name)))
+    }
+}
+
+/// Percent-encode a ref name into a single safe file name.
+fn encode_ref(name: &str) -> String {
+    name.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-') {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+fn decode_ref(file_name: &str) -> Option<String> {
+    let bytes = file_name.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = file_name.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Run blocking filesystem work off the async runtime.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| AgitError::storage(e.to_string()))?
+        .map_err(|e| AgitError::storage(e.to_string()))
+}
+
+/// Map a missing file to `None`.
+fn optional<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write `data` to a temp file under `root`, fsync it, and rename it to `dest`.
+fn write_atomic(root: &Path, dest: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = root.join(TMP_DIR).join(uuid::Uuid::new_v4().to_string());
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&tmp, dest)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Open the lock file `name` under `root`, creating it if needed.
+fn open_lock(root: &Path, name: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(root.join(name))
+}
+
+/// Open and exclusively lock the lock file `name`; unlocked when dropped.
+fn lock(root: &Path, name: &str) -> io::Result<File> {
+    let file = open_lock(root, name)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Like `lock`, but `None` instead of waiting if someone else holds it.
+fn try_lock(root: &Path, name: &str) -> io::Result<Option<File>> {
+    let file = open_lock(root, name)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Point the ref file at `path` to `target`, or remove it for `None`.
+fn write_ref(root: &Path, path: &Path, target: Option<&str>) -> io::Result<()> {
+    match target {
+        Some(target) => write_atomic(root, path, target.as_bytes()),
+        None => optional(fs::remove_file(path)).map(|_| ()),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FsStorage {
+    async fn initialize(&self) -> Result<()> {
+        let root = self.root.clone();
+        blocking(move || {
+            for dir in [OBJECTS_DIR, REFS_DIR, TMP_DIR, LOCKS_DIR] {
+                fs::create_dir_all(root.join(dir))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_object(&self, hash: &str, _obj_type: ObjectType, data: &[u8]) -> Result<()> {
+        let path = self.object_path(hash)?;
+        let root = self.root.clone();
+        let data = data.to_vec();
+        blocking(move || {
+            // Content-addressed objects are immutable
+            if path.exists() {
+                return Ok(());
+            }
+            write_atomic(&root, &path, &data)
+        })
+        .await
+    }
+
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.object_path(hash)?;
+        blocking(move || optional(fs::read(path))).await
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let path = self.object_path(hash)?;
+        blocking(move || {
+            let Some(mut file) = optional(File::open(path))? else {
+                return Ok(None);
+            };
+            file.seek(SeekFrom::Start(offset as u64))?;
+            let mut data = Vec::new();
+            file.take(len as u64).read_to_end(&mut data)?;
+            Ok(Some(data))
+        })
+        .await
+    }
+
+    async fn has_object(&self, hash: &str) -> Result<bool> {
+        let path = self.object_path(hash)?;
+        blocking(move || Ok(path.is_file())).await
+    }
+
+    async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+        let path = self.ref_path(name)?;
+        let root = self.root.clone();
+        let hash = hash.to_string();
+        blocking(move || {
+            let _lock = lock(&root, REFS_LOCK)?;
+            write_atomic(&root, &path, hash.as_bytes())
+        })
+        .await
+    }
+
+    async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+        let path = self.ref_path(name)?;
+        blocking(move || Ok(optional(fs::read_to_string(path))?.map(|s| s.trim().to_string())))
+            .await
+    }
+
+    async fn list_refs(&self) -> Result<HashMap<String, String>> {
+        let dir = self.root.join(REFS_DIR);
+        blocking(move || {
+            let mut refs = HashMap::new();
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let Some(name) = entry.file_name().to_str().and_then(decode_ref) else {
+                    continue;
+                };
+                if let Some(target) = optional(fs::read_to_string(entry.path()))? {
+                    refs.insert(name, target.trim().to_string());
+                }
+            }
+            Ok(refs)
+        })
+        .await
+    }
+
+    async fn delete_ref(&self, name: &str) -> Result<bool> {
+        let path = self.ref_path(name)?;
+        let root = self.root.clone();
+        blocking(move || {
+            let _lock = lock(&root, REFS_LOCK)?;
+            Ok(optional(fs::remove_file(path))?.is_some())
+        })
+        .await
+    }
+
+    /// Checks and writes the whole batch while holding `refs.lock`, so no
+    /// other process can update a ref in between. Refs already written are
+    /// restored if a later write fails.
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let paths = updates
+            .iter()
+            .map(|update| self.ref_path(&update.name))
+            .collect::<Result<Vec<_>>>()?;
+        let updates = updates.to_vec();
+        let root = self.root.clone();
+        blocking(move || {
+            let _lock = lock(&root, REFS_LOCK)?;
+            let mut previous = Vec::with_capacity(updates.len());
+            for (update, path) in updates.iter().zip(&paths) {
+                let current = optional(fs::read_to_string(path))?.map(|s| s.trim().to_string());
+                if let Err(e) = update.check(current.as_deref()) {
+                    return Ok(Err(e));
+                }
+                previous.push(current);
+            }
+            for (i, (update, path)) in updates.iter().zip(&paths).enumerate() {
+                if let Err(e) = write_ref(&root, path, update.new.as_deref()) {
+                    // Undo newest first, so a ref updated twice ends up as it began
+                    for (path, old) in paths[..i].iter().zip(&previous).rev() {
+                        let _ = write_ref(&root, path, old.as_deref());
+                    }
+                    return Err(e);
+                }
+            }
+            Ok(Ok(()))
+        })
+        .await?
+    }
+
+    /// Holds an exclusive lock on `locks/<name>`, shared by every process
+    /// using the directory and released by the operating system if the
+    /// holder dies.
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> Result<LockGuard> {
+        let file_name = format!("{}/{}", LOCKS_DIR, encode_ref(name));
+        let deadline = Instant::now() + timeout;
+        loop {
+            let (root, file_name) = (self.root.clone(), file_name.clone());
+            if let Some(file) = blocking(move || try_lock(&root, &file_name)).await? {
+                return Ok(LockGuard::new(file));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(AgitError::LockTimeout {
+                    name: name.to_string(),
+                });
+            }
+            tokio::time::sleep(LOCK_RETRY_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let root = self.root.clone();
+        blocking(move || {
+            // Keep concurrent appends from interleaving, and from going to
+            // a log file that `delete_logs` is replacing
+            let _lock = lock(&root, LOGS_LOCK)?;
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(root.join(LOG_FILE))?;
+            file.write_all(&line)?;
+            file.sync_data()
+        })
+        .await
+    }
+
+    /// Rewrites `logs.jsonl` without the deleted entries and renames it
+    /// into place.
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        let filter = filter.clone();
+        let root = self.root.clone();
+        blocking(move || {
+            let _lock = lock(&root, LOGS_LOCK)?;
+            let path = root.join(LOG_FILE);
+            let Some(data) = optional(fs::read(&path))? else {
+                return Ok(0);
+            };
+            let lines: Vec<&[u8]> = data
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .collect();
+            let entries: Vec<LogEntry> = lines
+                .iter()
+                .filter_map(|line| serde_json::from_slice(line).ok())
+                .collect();
+            let doomed = filter.select(&entries);
+            if doomed.is_empty() {
+                return Ok(0);
+            }
+
+            let mut kept = Vec::with_capacity(data.len());
+            let mut removed = 0;
+            for line in lines {
+                let id = serde_json::from_slice::<LogEntry>(line).ok().map(|e| e.id);
+                if id.is_some_and(|id| doomed.contains(&id)) {
+                    removed += 1;
+                } else {
+                    kept.extend_from_slice(line);
+                    kept.push(b'\n');
+                }
+            }
+            write_atomic(&root, &path, &kept)?;
+            Ok(removed)
+        })
+        .await
+    }
+
+    async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        let path = self.root.join(LOG_FILE);
+        let data = blocking(move || optional(fs::read(path))).await?;
+        let Some(data) = data else {
+            return Ok(Vec::new());
+        };
+
+        let mut entries: Vec<LogEntry> = data
+            .split(|b| *b == b'\n')
+            // A torn final line from a crash mid-append is skipped
+            .filter_map(|line| serde_json::from_slice::<LogEntry>(line).ok())
+            .filter(|e| filter.matches(e))
+            .collect();
+
+        // Newest first; among equal timestamps, most recently appended first
+        entries.reverse();
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(filter.paginate(entries))
+    }
+
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        let path = self.object_path(hash)?;
+        blocking(move || Ok(optional(fs::remove_file(path))?.is_some())).await
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        let dir = self.root.join(OBJECTS_DIR);
+        blocking(move || {
+            let mut hashes = Vec::new();
+            for fan_out in fs::read_dir(dir)? {
+                let fan_out = fan_out?;
+                if !fan_out.file_type()?.is_dir() {
+                    continue;
+                }
+                let prefix = fan_out.file_name().to_string_lossy().into_owned();
+                for entry in fs::read_dir(fan_out.path())? {
+                    let name = entry?.file_name().to_string_lossy().into_owned();
+                    if prefix == SHORT_KEY_DIR {
+                        hashes.push(name);
+                    } else {
+                        hashes.push(format!("{}{}", prefix, name));
+                    }
+                }
+            }
+            Ok(hashes)
+        })
+        .await
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        let path = self.object_path(hash)?;
+        let key = hash.to_string();
+        blocking(move || {
+            let Some(data) = optional(fs::read(&path))? else {
+                return Ok(None);
+            };
+            let meta = fs::metadata(&path)?;
+            // Birth time is not available on every filesystem
+            let created = meta.created().or_else(|_| meta.modified()).ok();
+            Ok(Some(ObjectStat {
+                size: data.len() as u64,
+                obj_type: infer_object_type(&key, &data),
+                created_at: created.map(DateTime::<Utc>::from),
+            }))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+
+    async fn temp_storage() -> (FsStorage, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorage::new(dir.path()).await.unwrap();
+        (storage, dir)
+    }
+
+    storage_conformance_tests!(temp_storage().await);
+
+    #[tokio::test]
+    async fn test_layout_and_reopen() {
+        let (storage, dir) = temp_storage().await;
+        storage
+            .put_object("abcdef", ObjectType::Blob, b"data")
+            .await
+            .unwrap();
+        storage.set_ref("feature/x", "abcdef").await.unwrap();
+        assert!(dir.path().join("objects/ab/cdef").is_file());
+        assert!(dir.path().join("refs/feature%2Fx").is_file());
+        assert!(storage
+            .put_object("../x", ObjectType::Blob, b"")
+            .await
+            .is_err());
+        assert!(storage
+            .put_object("ab..", ObjectType::Blob, b"")
+            .await
+            .is_ok());
+
+        let reopened = FsStorage::new(dir.path()).await.unwrap();
+        assert_eq!(
+            reopened.get_object("abcdef").await.unwrap().unwrap(),
+            b"data"
+        );
+        assert_eq!(
+            reopened.get_ref("feature/x").await.unwrap().unwrap(),
+            "abcdef"
+        );
+        assert!(fs::read_dir(dir.path().join(TMP_DIR))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_ref_updates() {
+        let (_, dir) = temp_storage().await;
+        let mut tasks = Vec::new();
+        for i in 0..16 {
+            let root = dir.path().to_path_buf();
+            tasks.push(tokio::spawn(async move {
+                let storage = FsStorage::new(&root).await.unwrap();
+                storage
+                    .set_ref("main", &format!("hash{}", i))
+                    .await
+                    .unwrap();
+                storage
+                    .append_log(&LogEntry {
+                        id: i.to_string(),
+                        timestamp: "2026-01-01T00:00:00Z".into(),
+                        agent_id: "a".into(),
+                        action: "commit".into(),
+                        message: "x".repeat(4096),
+                        commit_hash: None,
+                        details: None,
+                        level: LogLevel::Info,
+                    })
+                    .await
+                    .unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let storage = FsStorage::new(dir.path()).await.unwrap();
+        let head = storage.get_ref("main").await.unwrap().unwrap();
+        assert!(head.starts_with("hash"));
+        assert_eq!(storage.list_refs().await.unwrap().len(), 1);
+        let logs = storage.query_logs(&LogFilter::default()).await.unwrap();
+        assert_eq!(logs.len(), 16);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_compare_and_swap() {
+        let (storage, dir) = temp_storage().await;
+        storage.set_ref("main", "initial").await.unwrap();
+
+        // Separate instances, as separate processes would be
+        let mut tasks = Vec::new();
+        for i in 0..16 {
+            let root = dir.path().to_path_buf();
+            tasks.push(tokio::spawn(async move {
+                let writer = FsStorage::new(&root).await.unwrap();
+                let read = writer.get_ref("main").await.unwrap().unwrap();
+                let target = format!("writer-{i}");
+                writer
+                    .update_refs(&[RefUpdate::set("main", &target).expecting(&read)])
+                    .await
+                    .map(|()| (read, target))
+            }));
+        }
+
+        let mut written = Vec::new();
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(update) => written.push(update),
+                Err(AgitError::ConcurrentUpdate { name }) => assert_eq!(name, "main"),
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        }
+
+        let final_target = storage.get_ref("main").await.unwrap().unwrap();
+        assert!(written.iter().any(|(_, target)| *target == final_target));
+        // Successful updates form a chain: two from the same read value
+        // would mean one silently overwrote the other
+        let reads: std::collections::HashSet<_> = written.iter().map(|(read, _)| read).collect();
+        assert_eq!(reads.len(), written.len());
+    }
+}
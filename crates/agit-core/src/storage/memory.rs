@@ -0,0 +1,174 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use super::{LogDeleteFilter, LogEntry, LogFilter, ObjectStat, RefUpdate, StorageBackend};
+use crate::error::Result;
+use crate::types::ObjectType;
+
+/// A stored object with the metadata `stat_object` reports.
+struct StoredObject {
+    obj_type: ObjectType,
+    data: Vec<u8>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Inner {
+    objects: HashMap<String, StoredObject>,
+    refs: HashMap<String, String>,
+    logs: Vec<LogEntry>,
+}
+
+/// Process-local storage that keeps everything in memory.
+///
+/// Nothing is persisted; contents are lost when the storage is dropped.
+/// Useful for tests and as the cache tier of `TieredStorage`.
+#[derive(Default)]
+pub struct MemoryStorage {
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // A panic while holding the lock cannot leave the maps half-updated
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    async fn initialize(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+        self.lock()
+            .objects
+            .entry(hash.to_string())
+            .or_insert_with(|| StoredObject {
+                obj_type,
+                data: data.to_vec(),
+                created_at: Utc::now(),
+            });
+        Ok(())
+    }
+
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.lock().objects.get(hash).map(|o| o.data.clone()))
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self.lock().objects.get(hash).map(|o| {
+            let start = offset.min(o.data.len());
+            let end = offset.saturating_add(len).min(o.data.len());
+            o.data[start..end].to_vec()
+        }))
+    }
+
+    async fn has_object(&self, hash: &str) -> Result<bool> {
+        Ok(self.lock().objects.contains_key(hash))
+    }
+
+    async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+        self.lock().refs.insert(name.to_string(), hash.to_string());
+        Ok(())
+    }
+
+    async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.lock().refs.get(name).cloned())
+    }
+
+    async fn list_refs(&self) -> Result<HashMap<String, String>> {
+        Ok(self.lock().refs.clone())
+    }
+
+    async fn delete_ref(&self, name: &str) -> Result<bool> {
+        Ok(self.lock().refs.remove(name).is_some())
+    }
+
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let mut inner = self.lock();
+        let mut refs = inner.refs.clone();
+        for update in updates {
+            update.check(refs.get(&update.name).map(String::as_str))?;
+            match &update.new {
+                Some(target) => refs.insert(update.name.clone(), target.clone()),
+                None => refs.remove(&update.name),
+            };
+        }
+        inner.refs = refs;
+        Ok(())
+    }
+
+    async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        self.lock().logs.push(entry.clone());
+        Ok(())
+    }
+
+    async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        let mut entries: Vec<LogEntry> = self
+            .lock()
+            .logs
+            .iter()
+            .filter(|e| filter.matches(e))
+            .cloned()
+            .collect();
+
+        // Newest first; among equal timestamps, most recently appended first
+        entries.reverse();
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(filter.paginate(entries))
+    }
+
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        let mut inner = self.lock();
+        let doomed = filter.select(&inner.logs);
+        let before = inner.logs.len();
+        inner.logs.retain(|e| !doomed.contains(&e.id));
+        Ok(before - inner.logs.len())
+    }
+
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        Ok(self.lock().objects.remove(hash).is_some())
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        Ok(self.lock().objects.keys().cloned().collect())
+    }
+
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        Ok(self
+            .lock()
+            .objects
+            .iter()
+            .filter(|(_, o)| o.obj_type == obj_type)
+            .map(|(k, _)| k.clone())
+            .collect())
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        Ok(self.lock().objects.get(hash).map(|o| ObjectStat {
+            size: o.data.len() as u64,
+            obj_type: o.obj_type,
+            created_at: Some(o.created_at),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    storage_conformance_tests!((MemoryStorage::new(), ()));
+}
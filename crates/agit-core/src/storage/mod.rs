@@ -1,22 +1,48 @@
+#[cfg(test)]
+#[macro_use]
+mod conformance;
+
+pub mod fs;
+pub mod memory;
+pub mod retry;
 pub mod sqlite;
+pub mod tiered;
 
 #[cfg(feature = "postgres")]
 pub mod postgres;
 
+#[cfg(feature = "redis")]
+pub mod redis;
+
 #[cfg(feature = "s3")]
 pub mod s3;
 
+pub use fs::FsStorage;
+pub use memory::MemoryStorage;
+pub use retry::{RetryPolicy, RetryingStorage};
+pub use sqlite::{SqliteOptions, SqliteStorage};
+pub use tiered::TieredStorage;
+
 #[cfg(feature = "postgres")]
-pub use postgres::PostgresStorage;
+pub use postgres::{AgitEvent, PostgresStorage};
+
+#[cfg(feature = "redis")]
+pub use redis::RedisStorage;
 
 #[cfg(feature = "s3")]
-pub use s3::S3Storage;
+pub use s3::{CompressionMode, MasterKey, S3Options, S3Storage};
 
 use async_trait::async_trait;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use crate::error::Result;
-use crate::types::ObjectType;
+use crate::error::{AgitError, Result};
+use crate::objects::infer_object_type;
+use crate::types::{LogLevel, ObjectType};
 
 /// An entry in the audit log.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -28,7 +54,7 @@ pub struct LogEntry {
     pub message: String,
     pub commit_hash: Option<String>,
     pub details: Option<serde_json::Value>,
-    pub level: String,
+    pub level: LogLevel,
 }
 
 /// Filter for querying log entries.
@@ -36,9 +62,367 @@ pub struct LogEntry {
 pub struct LogFilter {
     pub agent_id: Option<String>,
     pub action: Option<String>,
-    pub level: Option<String>,
+    pub level: Option<LogLevel>,
     pub limit: Option<usize>,
     pub since: Option<String>,
+    /// Only entries with a timestamp at or before this one.
+    pub until: Option<String>,
+    /// Only entries whose message or details mention this text. SQLite
+    /// matches it as a phrase of whole words using full-text search; other
+    /// backends match it as a case-insensitive substring.
+    pub message_query: Option<String>,
+    /// Only entries recorded against this commit.
+    pub commit_hash: Option<String>,
+    /// Only entries whose message contains this exact, case-sensitive text.
+    pub message_contains: Option<String>,
+    /// Skip this many matching entries, newest first, before applying
+    /// `limit`.
+    pub offset: Option<usize>,
+}
+
+impl LogFilter {
+    /// Whether `entry` passes every condition of the filter. `limit` and
+    /// `offset` select from the matches and are not checked here.
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.agent_id.as_ref().is_none_or(|a| &entry.agent_id == a)
+            && self.action.as_ref().is_none_or(|a| &entry.action == a)
+            && self.level.as_ref().is_none_or(|l| &entry.level == l)
+            && self.since.as_ref().is_none_or(|s| &entry.timestamp >= s)
+            && self.until.as_ref().is_none_or(|u| &entry.timestamp <= u)
+            && self
+                .commit_hash
+                .as_ref()
+                .is_none_or(|c| entry.commit_hash.as_ref() == Some(c))
+            && self
+                .message_contains
+                .as_ref()
+                .is_none_or(|m| entry.message.contains(m.as_str()))
+            && self.matches_message(entry)
+    }
+
+    /// Apply `offset` and `limit` to matching entries sorted newest first.
+    pub fn paginate(&self, entries: Vec<LogEntry>) -> Vec<LogEntry> {
+        entries
+            .into_iter()
+            .skip(self.offset.unwrap_or(0))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// Whether `entry` passes `message_query`, matched as a case-insensitive
+    /// substring of its message or serialized details.
+    pub fn matches_message(&self, entry: &LogEntry) -> bool {
+        let Some(query) = &self.message_query else {
+            return true;
+        };
+        let query = query.to_lowercase();
+        entry.message.to_lowercase().contains(&query)
+            || entry
+                .details
+                .as_ref()
+                .is_some_and(|d| d.to_string().to_lowercase().contains(&query))
+    }
+}
+
+/// Position in the audit log of a watcher that wants each matching entry
+/// once, as it appears.
+///
+/// Each poll queries from the newest timestamp seen so far and skips the
+/// entries already returned at that timestamp. Entries appended with a
+/// timestamp older than that are not picked up.
+#[derive(Debug, Clone)]
+pub struct LogCursor {
+    filter: LogFilter,
+    since: Option<String>,
+    /// IDs of the entries returned at `since`.
+    seen: HashSet<String>,
+    started: bool,
+}
+
+impl LogCursor {
+    /// Follow entries matching `filter`, whose `limit` and `offset` are
+    /// ignored. With `filter.since` set, the first poll returns the matches
+    /// from then on; otherwise it only positions the cursor after the newest
+    /// existing match.
+    pub fn new(filter: LogFilter) -> Self {
+        let started = filter.since.is_some();
+        Self {
+            since: filter.since.clone(),
+            filter: LogFilter {
+                limit: None,
+                offset: None,
+                ..filter
+            },
+            seen: HashSet::new(),
+            started,
+        }
+    }
+
+    /// New matching entries since the last poll, oldest first.
+    pub async fn poll(&mut self, storage: &dyn StorageBackend) -> Result<Vec<LogEntry>> {
+        if !self.started {
+            let newest = LogFilter {
+                limit: Some(1),
+                ..self.filter.clone()
+            };
+            self.started = true;
+            let Some(newest) = storage.query_logs(&newest).await?.into_iter().next() else {
+                return Ok(Vec::new());
+            };
+            self.since = Some(newest.timestamp);
+            let existing = storage.query_logs(&self.query_filter()).await?;
+            self.seen = existing.into_iter().map(|e| e.id).collect();
+            return Ok(Vec::new());
+        }
+
+        let mut entries = storage.query_logs(&self.query_filter()).await?;
+        entries.retain(|e| !self.seen.contains(&e.id));
+        // Storage returns newest first
+        entries.reverse();
+        if let Some(newest) = entries.last().map(|e| e.timestamp.clone()) {
+            if self.since.as_ref() != Some(&newest) {
+                self.seen.clear();
+                self.since = Some(newest.clone());
+            }
+            self.seen.extend(
+                entries
+                    .iter()
+                    .filter(|e| e.timestamp == newest)
+                    .map(|e| e.id.clone()),
+            );
+        }
+        Ok(entries)
+    }
+
+    fn query_filter(&self) -> LogFilter {
+        LogFilter {
+            since: self.since.clone(),
+            ..self.filter.clone()
+        }
+    }
+}
+
+/// Which audit log entries `StorageBackend::delete_logs` removes.
+///
+/// An entry is deleted only if it passes every condition that is set, so
+/// the default filter deletes everything.
+#[derive(Debug, Clone, Default)]
+pub struct LogDeleteFilter {
+    /// Only entries of this agent.
+    pub agent_id: Option<String>,
+    /// Only entries with a timestamp strictly before this one.
+    pub before: Option<String>,
+    /// Spare each agent's newest `keep_last` entries.
+    pub keep_last: Option<usize>,
+}
+
+impl LogDeleteFilter {
+    /// IDs of the `entries` to delete, given oldest first. Among entries
+    /// with equal timestamps, later ones count as newer.
+    pub fn select(&self, entries: &[LogEntry]) -> HashSet<String> {
+        let mut by_agent: HashMap<&str, Vec<&LogEntry>> = HashMap::new();
+        for entry in entries {
+            if self.agent_id.as_ref().is_none_or(|a| &entry.agent_id == a) {
+                by_agent.entry(&entry.agent_id).or_default().push(entry);
+            }
+        }
+
+        let mut selected = HashSet::new();
+        for mut chain in by_agent.into_values() {
+            // Newest first
+            chain.reverse();
+            chain.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+            selected.extend(
+                chain
+                    .into_iter()
+                    .skip(self.keep_last.unwrap_or(0))
+                    .filter(|e| self.before.as_ref().is_none_or(|b| &e.timestamp < b))
+                    .map(|e| e.id.clone()),
+            );
+        }
+        selected
+    }
+}
+
+/// Size, type, and creation time of a stored object.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ObjectStat {
+    /// Bytes occupied in storage, after any backend compression.
+    pub size: u64,
+    pub obj_type: ObjectType,
+    /// When the object was first stored, if the backend records it.
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Object count and stored bytes for one object type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TypeStats {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Aggregate object counts and sizes, returned by `StorageBackend::storage_stats`.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StorageStats {
+    /// Total number of objects.
+    pub count: usize,
+    /// Total bytes occupied in storage.
+    pub bytes: u64,
+    /// Totals per object type, keyed by its display name (e.g. `blob`).
+    pub by_type: HashMap<String, TypeStats>,
+}
+
+impl StorageStats {
+    /// Count one object of `obj_type` occupying `bytes`.
+    pub fn add(&mut self, obj_type: ObjectType, bytes: u64) {
+        self.add_many(obj_type, 1, bytes);
+    }
+
+    /// Count `count` objects of `obj_type` occupying `bytes` in total.
+    pub fn add_many(&mut self, obj_type: ObjectType, count: usize, bytes: u64) {
+        self.count += count;
+        self.bytes += bytes;
+        let entry = self.by_type.entry(obj_type.to_string()).or_default();
+        entry.count += count;
+        entry.bytes += bytes;
+    }
+
+    /// Add the totals of `other` to these.
+    pub fn merge(&mut self, other: &StorageStats) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+        for (obj_type, stats) in &other.by_type {
+            let entry = self.by_type.entry(obj_type.clone()).or_default();
+            entry.count += stats.count;
+            entry.bytes += stats.bytes;
+        }
+    }
+}
+
+/// One change in a `StorageBackend::update_refs` batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub name: String,
+    /// Target the ref must currently have for the batch to apply. `None`
+    /// applies the update whatever the ref points at.
+    pub expected_old: Option<String>,
+    /// Only apply the batch if the ref does not exist yet.
+    pub expect_absent: bool,
+    /// New target, or `None` to delete the ref.
+    pub new: Option<String>,
+}
+
+impl RefUpdate {
+    /// Point `name` at `target`.
+    pub fn set(name: impl Into<String>, target: impl Into<String>) -> Self {
+        RefUpdate {
+            name: name.into(),
+            expected_old: None,
+            expect_absent: false,
+            new: Some(target.into()),
+        }
+    }
+
+    /// Delete `name`.
+    pub fn delete(name: impl Into<String>) -> Self {
+        RefUpdate {
+            name: name.into(),
+            expected_old: None,
+            expect_absent: false,
+            new: None,
+        }
+    }
+
+    /// Only apply the batch if the ref currently points at `old`.
+    pub fn expecting(mut self, old: impl Into<String>) -> Self {
+        self.expected_old = Some(old.into());
+        self
+    }
+
+    /// Only apply the batch if the ref does not exist yet.
+    pub fn expecting_absent(mut self) -> Self {
+        self.expect_absent = true;
+        self
+    }
+
+    /// Fail with `ConcurrentUpdate` unless `current` is the expected target.
+    pub fn check(&self, current: Option<&str>) -> Result<()> {
+        let stale = match &self.expected_old {
+            Some(expected) => current != Some(expected.as_str()),
+            None => self.expect_absent && current.is_some(),
+        };
+        if stale {
+            return Err(AgitError::ConcurrentUpdate {
+                name: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of `StorageBackend::backup_to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupReport {
+    /// Database pages written to the backup.
+    pub pages: u64,
+    /// Wall-clock time the backup took.
+    pub duration: Duration,
+}
+
+/// Outcome of `StorageBackend::compact`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactReport {
+    /// Bytes the store occupied before compacting.
+    pub bytes_before: u64,
+    /// Bytes the store occupies after compacting.
+    pub bytes_after: u64,
+}
+
+impl CompactReport {
+    /// Bytes given back to the filesystem.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// A lock taken with `StorageBackend::acquire_lock`, released when dropped.
+pub struct LockGuard {
+    _held: Box<dyn Any + Send + Sync>,
+}
+
+impl LockGuard {
+    /// Wrap the value that keeps a lock held. Dropping it must release the
+    /// lock.
+    pub fn new(held: impl Any + Send + Sync) -> Self {
+        LockGuard {
+            _held: Box::new(held),
+        }
+    }
+}
+
+impl std::fmt::Debug for LockGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LockGuard").finish_non_exhaustive()
+    }
+}
+
+/// Take the process-local lock `name`, shared by every backend without a
+/// lock of its own.
+pub(crate) async fn local_lock(name: &str, timeout: Duration) -> Result<LockGuard> {
+    static LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+    let lock = LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .clone();
+    match tokio::time::timeout(timeout, lock.lock_owned()).await {
+        Ok(guard) => Ok(LockGuard::new(guard)),
+        Err(_) => Err(AgitError::LockTimeout {
+            name: name.to_string(),
+        }),
+    }
 }
 
 /// Trait for pluggable storage backends.
@@ -53,6 +437,24 @@ pub trait StorageBackend: Send + Sync {
     /// Retrieve an object by hash.
     async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>>;
 
+    /// Retrieve up to `len` bytes of an object starting at `offset`.
+    ///
+    /// The result is shorter than `len` when the object ends first. The
+    /// default reads the whole object; backends that can serve byte ranges
+    /// override it.
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self.get_object(hash).await?.map(|data| {
+            let start = offset.min(data.len());
+            let end = offset.saturating_add(len).min(data.len());
+            data[start..end].to_vec()
+        }))
+    }
+
     /// Check if an object exists.
     async fn has_object(&self, hash: &str) -> Result<bool>;
 
@@ -68,15 +470,203 @@ pub trait StorageBackend: Send + Sync {
     /// Delete a reference.
     async fn delete_ref(&self, name: &str) -> Result<bool>;
 
+    /// Apply several ref updates as one: either all of them take effect or
+    /// none do.
+    ///
+    /// If any ref does not have its `expected_old` target the whole batch
+    /// fails with `AgitError::ConcurrentUpdate`. The default checks every
+    /// ref, then writes them in order, restoring the ones already written
+    /// if a later write fails. That is only best effort: a crash midway
+    /// leaves the batch partly applied, and another writer can slip in
+    /// between the check and the writes. Backends with transactions
+    /// override it.
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let mut previous = Vec::with_capacity(updates.len());
+        for update in updates {
+            let current = self.get_ref(&update.name).await?;
+            update.check(current.as_deref())?;
+            previous.push(current);
+        }
+        for (i, update) in updates.iter().enumerate() {
+            let written = match &update.new {
+                Some(target) => self.set_ref(&update.name, target).await,
+                None => self.delete_ref(&update.name).await.map(|_| ()),
+            };
+            if let Err(e) = written {
+                // Undo newest first, so a ref updated twice ends up as it began
+                for (update, old) in updates[..i].iter().zip(&previous).rev() {
+                    let _ = match old {
+                        Some(target) => self.set_ref(&update.name, target).await,
+                        None => self.delete_ref(&update.name).await.map(|_| ()),
+                    };
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     /// Append an entry to the audit log.
     async fn append_log(&self, entry: &LogEntry) -> Result<()>;
 
-    /// Query audit log entries.
+    /// Query audit log entries, newest first.
     async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>>;
 
-    /// Delete an object by hash. Returns true if the object existed.
+    /// Count the audit log entries matching `filter`, ignoring its `limit`
+    /// and `offset`, so callers can size pages.
+    ///
+    /// The default queries every match; SQL backends count in the database.
+    async fn count_logs(&self, filter: &LogFilter) -> Result<usize> {
+        let filter = LogFilter {
+            limit: None,
+            offset: None,
+            ..filter.clone()
+        };
+        Ok(self.query_logs(&filter).await?.len())
+    }
+
+    /// A stream that yields whenever log entries may have been appended, by
+    /// this or any other process, so watchers can re-query right away
+    /// instead of waiting out their poll interval.
+    ///
+    /// `None`, the default, means watchers have to poll.
+    async fn log_signals(&self) -> Result<Option<BoxStream<'static, ()>>> {
+        Ok(None)
+    }
+
+    /// Delete the audit log entries selected by `filter`, returning how
+    /// many were removed.
+    ///
+    /// Used to enforce log retention. The default fails with
+    /// `AgitError::InvalidOperation`.
+    async fn delete_logs(&self, _filter: &LogDeleteFilter) -> Result<usize> {
+        Err(AgitError::InvalidOperation(
+            "this storage backend does not support deleting logs".to_string(),
+        ))
+    }
+
+    /// Delete an object by hash.
+    ///
+    /// Returns `true` if an object was removed and `false` if none was
+    /// stored under `hash`. Used by garbage collection; deleting a missing
+    /// object is not an error.
     async fn delete_object(&self, hash: &str) -> Result<bool>;
 
-    /// List all object hashes in storage.
+    /// Delete several objects, returning how many were removed.
+    ///
+    /// Missing objects are skipped like in `delete_object`. The default
+    /// deletes one object at a time; backends that support bulk deletes
+    /// override it. Backends that cannot tell whether a key existed count
+    /// every key they were asked to delete.
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        let mut removed = 0;
+        for hash in hashes {
+            if self.delete_object(hash).await? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// List the keys of all stored objects, in no particular order.
+    ///
+    /// May be expensive: implementations typically scan every object.
+    /// Used by garbage collection, fsck, and migration.
     async fn list_objects(&self) -> Result<Vec<String>>;
+
+    /// List the keys of stored objects of one type.
+    ///
+    /// The default lists all objects and infers each type from its key and
+    /// contents, reading every object; backends that record the type
+    /// should override it.
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        let mut matching = Vec::new();
+        for key in self.list_objects().await? {
+            if let Some(data) = self.get_object(&key).await? {
+                if infer_object_type(&key, &data) == obj_type {
+                    matching.push(key);
+                }
+            }
+        }
+        Ok(matching)
+    }
+
+    /// Size, type, and creation time of an object, or `None` if it is not
+    /// stored.
+    ///
+    /// The default reads the whole object and infers its type; backends
+    /// that record object metadata should override it.
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        Ok(self.get_object(hash).await?.map(|data| ObjectStat {
+            size: data.len() as u64,
+            obj_type: infer_object_type(hash, &data),
+            created_at: None,
+        }))
+    }
+
+    /// Object count and total stored bytes, overall and per type.
+    ///
+    /// The default stats every object in turn.
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let mut stats = StorageStats::default();
+        for key in self.list_objects().await? {
+            if let Some(stat) = self.stat_object(&key).await? {
+                stats.add(stat.obj_type, stat.size);
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Take the exclusive lock `name`, waiting up to `timeout` for its
+    /// current holder to release it.
+    ///
+    /// Serializes critical sections such as garbage collection across
+    /// everyone sharing the storage. The default lock only covers the
+    /// current process; backends shared between processes override it.
+    /// Fails with `AgitError::LockTimeout` if the lock stays held.
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> Result<LockGuard> {
+        local_lock(name, timeout).await
+    }
+
+    /// Write a consistent copy of the whole store to the file at `path`,
+    /// without stopping concurrent writers.
+    ///
+    /// The default fails with `AgitError::InvalidOperation`; only backends
+    /// with a single-file representation support it.
+    async fn backup_to(&self, _path: &str) -> Result<BackupReport> {
+        Err(AgitError::InvalidOperation(
+            "this storage backend does not support backups".to_string(),
+        ))
+    }
+
+    /// Reclaim space freed by deleted objects and refresh query planner
+    /// statistics. Typically run after garbage collection.
+    ///
+    /// The default does nothing and reports zero bytes.
+    async fn compact(&self) -> Result<CompactReport> {
+        Ok(CompactReport::default())
+    }
+
+    /// Release connections and file handles. Calls made after this fail.
+    ///
+    /// The default does nothing; backends holding files or connection
+    /// pools override it.
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// This backend's bulk write support, if it has any.
+    fn as_bulk_write(&self) -> Option<&dyn BulkWrite> {
+        None
+    }
+}
+
+/// Optional capability of backends that can store many objects far faster
+/// than one `put_object` call each. Exposed through
+/// `StorageBackend::as_bulk_write`.
+#[async_trait]
+pub trait BulkWrite: Send + Sync {
+    /// Store a batch of `(hash, type, data)` objects, skipping any already
+    /// stored, and return how many were newly added.
+    async fn put_objects_bulk(&self, objects: &[(String, ObjectType, Vec<u8>)]) -> Result<usize>;
 }
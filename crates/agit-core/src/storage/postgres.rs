@@ -1,29 +1,251 @@
 #[cfg(feature = "postgres")]
 use async_trait::async_trait;
 #[cfg(feature = "postgres")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "postgres")]
+use deadpool_postgres::{Config, Pool, PoolError, Runtime};
+#[cfg(feature = "postgres")]
+use futures_util::stream::BoxStream;
+#[cfg(feature = "postgres")]
+use futures_util::{stream, Stream, StreamExt};
+#[cfg(feature = "postgres")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "postgres")]
 use std::collections::HashMap;
 #[cfg(feature = "postgres")]
-use deadpool_postgres::{Config, Pool, Runtime};
+use std::time::{Duration, Instant};
+#[cfg(feature = "postgres")]
+use tokio::sync::mpsc;
+#[cfg(feature = "postgres")]
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
 #[cfg(feature = "postgres")]
-use tokio_postgres::NoTls;
+use tokio_postgres::error::SqlState;
+#[cfg(feature = "postgres")]
+use tokio_postgres::types::Type;
+#[cfg(feature = "postgres")]
+use tokio_postgres::{AsyncMessage, NoTls};
 
 #[cfg(feature = "postgres")]
-use super::{LogEntry, LogFilter, StorageBackend};
+use super::{
+    BulkWrite, CompactReport, LockGuard, LogDeleteFilter, LogEntry, LogFilter, ObjectStat,
+    RefUpdate, StorageBackend, StorageStats,
+};
 #[cfg(feature = "postgres")]
 use crate::error::{AgitError, Result};
 #[cfg(feature = "postgres")]
 use crate::types::ObjectType;
 
+/// Schema version written to `agit_schema` once `initialize` has upgraded
+/// the tables. Version 2 moved namespaces out of object keys and ref names
+/// into the `agent_id` columns.
+#[cfg(feature = "postgres")]
+const SCHEMA_VERSION: i32 = 2;
+
+/// Arbitrary key for the advisory lock serializing `initialize` across
+/// processes.
+#[cfg(feature = "postgres")]
+const INIT_LOCK_KEY: i64 = 0x6167_6974;
+
+/// One-time upgrade from version 1, where a namespaced storage stored
+/// `<namespace>:<hash>` object keys and `<namespace>:<name>` refs pointing
+/// at `<namespace>:<target>`, all with an empty `agent_id`. Hashes and ref
+/// names never contain `:`, so the namespace is everything before the last
+/// one. Unprefixed objects belonged to the default namespace.
+#[cfg(feature = "postgres")]
+const UPGRADE_NAMESPACES: &str = "
+    INSERT INTO namespace_objects (agent_id, hash, created_at)
+        SELECT '', hash, created_at FROM objects WHERE position(':' in hash) = 0
+        ON CONFLICT DO NOTHING;
+
+    INSERT INTO objects (hash, type, data, created_at)
+        SELECT substring(hash from '[^:]*$'), type, data, created_at
+        FROM objects WHERE position(':' in hash) > 0
+        ON CONFLICT (hash) DO NOTHING;
+    INSERT INTO namespace_objects (agent_id, hash, created_at)
+        SELECT left(hash, length(hash) - length(substring(hash from '[^:]*$')) - 1),
+               substring(hash from '[^:]*$'),
+               created_at
+        FROM objects WHERE position(':' in hash) > 0
+        ON CONFLICT DO NOTHING;
+    DELETE FROM objects WHERE position(':' in hash) > 0;
+
+    UPDATE refs r
+    SET agent_id = s.ns,
+        name = s.bare,
+        target = CASE WHEN starts_with(r.target, s.ns || ':')
+                      THEN substr(r.target, length(s.ns) + 2)
+                      ELSE r.target END
+    FROM (
+        SELECT name AS scoped,
+               substring(name from '[^:]*$') AS bare,
+               left(name, length(name) - length(substring(name from '[^:]*$')) - 1) AS ns
+        FROM refs WHERE agent_id = '' AND position(':' in name) > 0
+    ) s
+    WHERE r.agent_id = '' AND r.name = s.scoped;
+";
+
+/// How often `acquire_lock` retries an advisory lock held by another
+/// session.
+#[cfg(feature = "postgres")]
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Advisory lock key for the lock `name` in `namespace`.
+#[cfg(feature = "postgres")]
+fn lock_key(namespace: &str, name: &str) -> i64 {
+    let digest = Sha256::new()
+        .chain_update(namespace.as_bytes())
+        .chain_update([0])
+        .chain_update(name.as_bytes())
+        .finalize();
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Whether `e` is transient: a serialization failure, deadlock, or lost
+/// connection, after which the same statements may succeed.
+#[cfg(feature = "postgres")]
+fn is_transient(e: &tokio_postgres::Error) -> bool {
+    match e.code() {
+        Some(code) => {
+            *code == SqlState::T_R_SERIALIZATION_FAILURE
+                || *code == SqlState::T_R_DEADLOCK_DETECTED
+                || *code == SqlState::ADMIN_SHUTDOWN
+                || *code == SqlState::CANNOT_CONNECT_NOW
+                // Class 08: connection exceptions
+                || code.code().starts_with("08")
+        }
+        None => {
+            e.is_closed() || std::error::Error::source(e).is_some_and(|s| s.is::<std::io::Error>())
+        }
+    }
+}
+
+/// Map a Postgres error, marking transient ones as retryable.
+#[cfg(feature = "postgres")]
+fn pg_error(e: tokio_postgres::Error) -> AgitError {
+    AgitError::Storage {
+        message: e.to_string(),
+        retryable: is_transient(&e),
+    }
+}
+
+/// The WHERE clause shared by log queries and counts, with the namespace
+/// as `$1` followed by the returned parameter values.
+fn log_conditions(filter: &LogFilter) -> (String, Vec<String>) {
+    let mut conditions: Vec<String> = vec!["namespace = $1".to_string()];
+    let mut values: Vec<String> = Vec::new();
+    let mut push = |condition: &str, value: String| {
+        values.push(value);
+        conditions.push(condition.replace("{}", &format!("${}", values.len() + 1)));
+    };
+
+    if let Some(ref v) = filter.agent_id {
+        push("agent_id = {}", v.clone());
+    }
+    if let Some(ref v) = filter.action {
+        push("action = {}", v.clone());
+    }
+    if let Some(ref v) = filter.level {
+        push("level = {}", v.to_string());
+    }
+    if let Some(ref v) = filter.since {
+        push("timestamp >= {}", v.clone());
+    }
+    if let Some(ref v) = filter.until {
+        push("timestamp <= {}", v.clone());
+    }
+    if let Some(ref v) = filter.commit_hash {
+        push("commit_hash = {}", v.clone());
+    }
+    if let Some(ref v) = filter.message_contains {
+        // strpos is case-sensitive and takes no wildcards
+        push("strpos(message, {}) > 0", v.clone());
+    }
+    if let Some(ref v) = filter.message_query {
+        // Substring match; escape LIKE wildcards in the query
+        let escaped = v
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        push(
+            "(message ILIKE {} OR details::TEXT ILIKE {})",
+            format!("%{escaped}%"),
+        );
+    }
+
+    (format!("WHERE {}", conditions.join(" AND ")), values)
+}
+
+/// Map a failure to get a pooled connection. Timeouts and failed
+/// connection attempts are retryable.
+#[cfg(feature = "postgres")]
+fn pool_error(e: PoolError) -> AgitError {
+    let retryable = match &e {
+        PoolError::Timeout(_) => true,
+        PoolError::Backend(e) => is_transient(e),
+        _ => false,
+    };
+    AgitError::Storage {
+        message: format!("pool error: {e}"),
+        retryable,
+    }
+}
+
+/// Channel that `set_ref` and `append_log` notify and `subscribe` listens on.
+#[cfg(feature = "postgres")]
+const EVENT_CHANNEL: &str = "agit_events";
+
+/// Delay before the first reconnection attempt of a subscription; doubled
+/// after each failure up to `MAX_RECONNECT_DELAY`.
+#[cfg(feature = "postgres")]
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+#[cfg(feature = "postgres")]
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A change published on the `agit_events` channel, yielded by
+/// `PostgresStorage::subscribe`.
+#[cfg(feature = "postgres")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AgitEvent {
+    /// A ref was created or moved.
+    RefUpdated { name: String, target: String },
+    /// An entry was appended to the audit log.
+    LogAppended {
+        agent_id: String,
+        action: String,
+        commit_hash: Option<String>,
+    },
+}
+
+/// `NOTIFY` payload: an event tagged with the namespace it happened in, so
+/// subscribers can skip other tenants' events.
+#[cfg(feature = "postgres")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EventPayload {
+    namespace: String,
+    #[serde(flatten)]
+    event: AgitEvent,
+}
+
 /// PostgreSQL-backed storage with multi-tenant support and connection pooling.
 ///
 /// Uses `deadpool_postgres::Pool` for connection pooling, allowing efficient
 /// concurrent access without serializing behind a single connection.
 ///
+/// Object contents live once in `objects`, keyed by hash alone, so identical
+/// objects are shared by every namespace that stores them. Which namespaces
+/// hold an object is recorded in `namespace_objects`, and an object's content
+/// is deleted once no namespace holds it. Refs carry their namespace in
+/// `agent_id`; log entries, whose `agent_id` is the logging agent, carry it
+/// in `namespace`.
+///
 /// Enable with the `postgres` Cargo feature flag.
 #[cfg(feature = "postgres")]
 pub struct PostgresStorage {
     pool: Pool,
     namespace: String,
+    connection_str: String,
 }
 
 #[cfg(feature = "postgres")]
@@ -36,7 +258,9 @@ impl PostgresStorage {
 
     /// Connect to PostgreSQL with a storage namespace.
     ///
-    /// The namespace is used to isolate refs and objects across tenants.
+    /// The namespace isolates refs, objects, and logs across tenants; the
+    /// default namespace is `""`. Databases written by earlier versions,
+    /// which prefixed keys with the namespace, are upgraded on connect.
     pub async fn new_scoped(connection_str: &str, namespace: &str) -> Result<Self> {
         let mut cfg = Config::new();
         cfg.url = Some(connection_str.to_string());
@@ -47,52 +271,133 @@ impl PostgresStorage {
 
         let pool = cfg
             .create_pool(Some(Runtime::Tokio1), NoTls)
-            .map_err(|e| AgitError::Storage(format!("pool creation error: {e}")))?;
+            .map_err(|e| AgitError::storage(format!("pool creation error: {e}")))?;
 
         let storage = PostgresStorage {
             pool,
             namespace: namespace.to_string(),
+            connection_str: connection_str.to_string(),
         };
         storage.initialize().await?;
         Ok(storage)
     }
 
-    fn scope_hash(&self, hash: &str) -> String {
-        if self.namespace.is_empty() {
-            hash.to_string()
-        } else {
-            format!("{}:{}", self.namespace, hash)
-        }
-    }
+    /// Subscribe to ref updates and log appends in this storage's namespace,
+    /// from any process sharing the database.
+    ///
+    /// Listens on a dedicated connection outside the pool. If that
+    /// connection drops, it is re-established with exponential backoff;
+    /// events published while disconnected are not delivered. The stream
+    /// ends only if the initial connection fails, which is reported as an
+    /// error instead.
+    pub async fn subscribe(&self) -> Result<impl Stream<Item = AgitEvent>> {
+        let mut notifications = listen(&self.connection_str).await?;
+        let (tx, mut rx) = mpsc::channel(256);
+        let connection_str = self.connection_str.clone();
+        let namespace = self.namespace.clone();
 
-    fn unscope_hash(&self, scoped: &str) -> String {
-        if self.namespace.is_empty() {
-            scoped.to_string()
-        } else {
-            scoped
-                .strip_prefix(&format!("{}:", self.namespace))
-                .unwrap_or(scoped)
-                .to_string()
-        }
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    payload = notifications.recv() => match payload {
+                        Some(payload) => {
+                            let Ok(payload) = serde_json::from_str::<EventPayload>(&payload) else {
+                                continue;
+                            };
+                            if payload.namespace == namespace && tx.send(payload.event).await.is_err() {
+                                return;
+                            }
+                        }
+                        None => {
+                            let mut delay = INITIAL_RECONNECT_DELAY;
+                            loop {
+                                tokio::select! {
+                                    _ = tx.closed() => return,
+                                    _ = tokio::time::sleep(delay) => {}
+                                }
+                                match listen(&connection_str).await {
+                                    Ok(next) => {
+                                        notifications = next;
+                                        break;
+                                    }
+                                    Err(_) => delay = (delay * 2).min(MAX_RECONNECT_DELAY),
+                                }
+                            }
+                        }
+                    },
+                }
+            }
+        });
+
+        Ok(stream::poll_fn(move |cx| rx.poll_recv(cx)))
     }
 
-    fn scope_ref(&self, name: &str) -> String {
-        if self.namespace.is_empty() {
-            name.to_string()
-        } else {
-            format!("{}:{}", self.namespace, name)
-        }
+    /// Publish `event` to subscribers of this namespace.
+    async fn notify(&self, client: &tokio_postgres::Client, event: AgitEvent) -> Result<()> {
+        let payload = serde_json::to_string(&EventPayload {
+            namespace: self.namespace.clone(),
+            event,
+        })
+        .map_err(|e| AgitError::storage(e.to_string()))?;
+        client
+            .execute("SELECT pg_notify($1, $2)", &[&EVENT_CHANNEL, &payload])
+            .await
+            .map_err(pg_error)?;
+        Ok(())
     }
+}
 
-    fn unscope_ref(&self, scoped: &str) -> String {
-        if self.namespace.is_empty() {
-            scoped.to_string()
-        } else {
-            scoped
-                .strip_prefix(&format!("{}:", self.namespace))
-                .unwrap_or(scoped)
-                .to_string()
+/// Open a connection listening on `EVENT_CHANNEL` and return the payloads
+/// of its notifications. The receiver closes when the connection is lost.
+#[cfg(feature = "postgres")]
+async fn listen(connection_str: &str) -> Result<mpsc::UnboundedReceiver<String>> {
+    let (client, mut connection) = tokio_postgres::connect(connection_str, NoTls)
+        .await
+        .map_err(|e| AgitError::storage(format!("listen connection error: {e}")))?;
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    // The connection must be polled for the LISTEN below to complete, and
+    // afterwards for notifications to arrive. The client is moved in too so
+    // the connection stays open for as long as the receiver is read.
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+    let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let listening = async {
+            client
+                .batch_execute(&format!("LISTEN {EVENT_CHANNEL}"))
+                .await
+        };
+        tokio::pin!(listening);
+        let mut ready_tx = Some(ready_tx);
+        loop {
+            tokio::select! {
+                result = &mut listening, if ready_tx.is_some() => {
+                    let ok = result.is_ok();
+                    if let Some(ready) = ready_tx.take() {
+                        let _ = ready.send(result);
+                    }
+                    if !ok {
+                        return;
+                    }
+                }
+                message = messages.next() => match message {
+                    Some(Ok(AsyncMessage::Notification(n))) => {
+                        if tx.send(n.payload().to_string()).is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => return,
+                },
+            }
         }
+    });
+
+    match ready_rx.await {
+        Ok(Ok(())) => Ok(rx),
+        Ok(Err(e)) => Err(AgitError::storage(format!("listen error: {e}"))),
+        Err(_) => Err(AgitError::storage("listen connection closed".to_string())),
     }
 }
 
@@ -100,180 +405,298 @@ impl PostgresStorage {
 #[async_trait]
 impl StorageBackend for PostgresStorage {
     async fn initialize(&self) -> Result<()> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        client
-            .batch_execute(
-                "
-                CREATE TABLE IF NOT EXISTS objects (
-                    hash        TEXT        PRIMARY KEY,
-                    type        TEXT        NOT NULL,
-                    data        BYTEA       NOT NULL,
-                    agent_id    TEXT        NOT NULL DEFAULT '',
-                    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW()
-                );
-
-                CREATE TABLE IF NOT EXISTS refs (
-                    name        TEXT        NOT NULL,
-                    target      TEXT        NOT NULL,
-                    agent_id    TEXT        NOT NULL DEFAULT '',
-                    updated_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-                    PRIMARY KEY (name, agent_id)
-                );
-
-                CREATE TABLE IF NOT EXISTS logs (
-                    id          TEXT        NOT NULL,
-                    timestamp   TEXT        NOT NULL,
-                    agent_id    TEXT        NOT NULL,
-                    action      TEXT        NOT NULL,
-                    message     TEXT        NOT NULL,
-                    commit_hash TEXT,
-                    details     JSONB,
-                    level       TEXT        NOT NULL DEFAULT 'info',
-                    PRIMARY KEY (id, agent_id)
-                );
-
-                CREATE INDEX IF NOT EXISTS idx_logs_timestamp  ON logs(timestamp);
-                CREATE INDEX IF NOT EXISTS idx_logs_agent_id   ON logs(agent_id);
-                CREATE INDEX IF NOT EXISTS idx_logs_action     ON logs(action);
-                CREATE INDEX IF NOT EXISTS idx_objects_agent   ON objects(agent_id);
-                ",
+        let mut client = self.pool.get().await.map_err(pool_error)?;
+        let tx = client.transaction().await.map_err(pg_error)?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&INIT_LOCK_KEY])
+            .await
+            .map_err(pg_error)?;
+        tx.batch_execute(
+            "
+            CREATE TABLE IF NOT EXISTS objects (
+                hash        TEXT        PRIMARY KEY,
+                type        TEXT        NOT NULL,
+                data        BYTEA       NOT NULL,
+                agent_id    TEXT        NOT NULL DEFAULT '',
+                created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+
+            CREATE TABLE IF NOT EXISTS namespace_objects (
+                agent_id    TEXT        NOT NULL,
+                hash        TEXT        NOT NULL REFERENCES objects(hash),
+                created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (agent_id, hash)
+            );
+
+            CREATE TABLE IF NOT EXISTS refs (
+                name        TEXT        NOT NULL,
+                target      TEXT        NOT NULL,
+                agent_id    TEXT        NOT NULL DEFAULT '',
+                updated_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (name, agent_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS logs (
+                id          TEXT        NOT NULL,
+                timestamp   TEXT        NOT NULL,
+                agent_id    TEXT        NOT NULL,
+                action      TEXT        NOT NULL,
+                message     TEXT        NOT NULL,
+                commit_hash TEXT,
+                details     JSONB,
+                level       TEXT        NOT NULL DEFAULT 'info',
+                PRIMARY KEY (id, agent_id)
+            );
+            ALTER TABLE logs ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT '';
+
+            CREATE TABLE IF NOT EXISTS agit_schema (
+                version     INTEGER     NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_logs_timestamp  ON logs(timestamp);
+            CREATE INDEX IF NOT EXISTS idx_logs_agent_id   ON logs(agent_id);
+            CREATE INDEX IF NOT EXISTS idx_logs_action     ON logs(action);
+            CREATE INDEX IF NOT EXISTS idx_logs_namespace  ON logs(namespace);
+            CREATE INDEX IF NOT EXISTS idx_logs_agent_timestamp ON logs(namespace, agent_id, timestamp);
+            CREATE INDEX IF NOT EXISTS idx_objects_agent   ON objects(agent_id);
+            CREATE INDEX IF NOT EXISTS idx_namespace_objects_hash ON namespace_objects(hash);
+            ",
+        )
+        .await
+        .map_err(pg_error)?;
+
+        let version: i32 = tx
+            .query_one("SELECT COALESCE(MAX(version), 0) FROM agit_schema", &[])
+            .await
+            .map_err(pg_error)?
+            .get(0);
+        if version < SCHEMA_VERSION {
+            tx.batch_execute(UPGRADE_NAMESPACES)
+                .await
+                .map_err(pg_error)?;
+            tx.execute(
+                "INSERT INTO agit_schema (version) VALUES ($1)",
+                &[&SCHEMA_VERSION],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))
+            .map_err(pg_error)?;
+        }
+        tx.commit().await.map_err(pg_error)
     }
 
     async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
+        let mut client = self.pool.get().await.map_err(pool_error)?;
         let type_str = obj_type.to_string();
-        let scoped_hash = self.scope_hash(hash);
-        client
-            .execute(
-                "INSERT INTO objects (hash, type, data)
-                 VALUES ($1, $2, $3)
-                 ON CONFLICT (hash) DO NOTHING",
-                &[&scoped_hash, &type_str, &data],
+        let tx = client.transaction().await.map_err(pg_error)?;
+        tx.execute(
+            "INSERT INTO objects (hash, type, data)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (hash) DO NOTHING",
+            &[&hash, &type_str, &data],
+        )
+        .await
+        .map_err(pg_error)?;
+        tx.execute(
+            "INSERT INTO namespace_objects (agent_id, hash)
+             VALUES ($1, $2)
+             ON CONFLICT DO NOTHING",
+            &[&self.namespace, &hash],
+        )
+        .await
+        .map_err(pg_error)?;
+        tx.commit().await.map_err(pg_error)
+    }
+
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let rows = client
+            .query(
+                "SELECT o.data FROM objects o
+                 JOIN namespace_objects n ON n.hash = o.hash
+                 WHERE n.agent_id = $1 AND o.hash = $2",
+                &[&self.namespace, &hash],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        Ok(())
+            .map_err(pg_error)?;
+        Ok(rows.first().map(|row| row.get::<_, Vec<u8>>(0)))
     }
 
-    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        let scoped_hash = self.scope_hash(hash);
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        // substring() on bytea is 1-based; values past the end are clamped
+        let start = i32::try_from(offset).unwrap_or(i32::MAX - 1) + 1;
+        let len = i32::try_from(len).unwrap_or(i32::MAX);
         let rows = client
             .query(
-                "SELECT data FROM objects WHERE hash = $1",
-                &[&scoped_hash],
+                "SELECT substring(o.data FROM $3 FOR $4) FROM objects o
+                 JOIN namespace_objects n ON n.hash = o.hash
+                 WHERE n.agent_id = $1 AND o.hash = $2",
+                &[&self.namespace, &hash, &start, &len],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+            .map_err(pg_error)?;
         Ok(rows.first().map(|row| row.get::<_, Vec<u8>>(0)))
     }
 
     async fn has_object(&self, hash: &str) -> Result<bool> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        let scoped_hash = self.scope_hash(hash);
+        let client = self.pool.get().await.map_err(pool_error)?;
         let rows = client
             .query(
-                "SELECT 1 FROM objects WHERE hash = $1 LIMIT 1",
-                &[&scoped_hash],
+                "SELECT 1 FROM namespace_objects WHERE agent_id = $1 AND hash = $2 LIMIT 1",
+                &[&self.namespace, &hash],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+            .map_err(pg_error)?;
         Ok(!rows.is_empty())
     }
 
     async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        let scoped_name = self.scope_ref(name);
-        let scoped_hash = self.scope_hash(hash);
+        let client = self.pool.get().await.map_err(pool_error)?;
         client
             .execute(
                 "INSERT INTO refs (name, target, agent_id)
-                 VALUES ($1, $2, '')
+                 VALUES ($1, $2, $3)
                  ON CONFLICT (name, agent_id)
                  DO UPDATE SET target = EXCLUDED.target, updated_at = NOW()",
-                &[&scoped_name, &scoped_hash],
+                &[&name, &hash, &self.namespace],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+            .map_err(pg_error)?;
+        self.notify(
+            &client,
+            AgitEvent::RefUpdated {
+                name: name.to_string(),
+                target: hash.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// Runs in one transaction, locking each ref row it reads.
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let mut client = self.pool.get().await.map_err(pool_error)?;
+        let tx = client.transaction().await.map_err(pg_error)?;
+        for update in updates {
+            let rows = tx
+                .query(
+                    "SELECT target FROM refs WHERE name = $1 AND agent_id = $2 FOR UPDATE",
+                    &[&update.name, &self.namespace],
+                )
+                .await
+                .map_err(pg_error)?;
+            let current = rows.first().map(|row| row.get::<_, String>(0));
+            // Dropping the transaction rolls it back
+            update.check(current.as_deref())?;
+            match &update.new {
+                // FOR UPDATE cannot lock a row that does not exist yet, so a
+                // concurrent creator is caught by the insert instead
+                Some(target) if update.expect_absent => {
+                    let inserted = tx
+                        .execute(
+                            "INSERT INTO refs (name, target, agent_id) VALUES ($1, $2, $3)
+                             ON CONFLICT (name, agent_id) DO NOTHING",
+                            &[&update.name, target, &self.namespace],
+                        )
+                        .await
+                        .map_err(pg_error)?;
+                    if inserted == 0 {
+                        return Err(AgitError::ConcurrentUpdate {
+                            name: update.name.clone(),
+                        });
+                    }
+                    inserted
+                }
+                Some(target) => tx
+                    .execute(
+                        "INSERT INTO refs (name, target, agent_id)
+                         VALUES ($1, $2, $3)
+                         ON CONFLICT (name, agent_id)
+                         DO UPDATE SET target = EXCLUDED.target, updated_at = NOW()",
+                        &[&update.name, target, &self.namespace],
+                    )
+                    .await
+                    .map_err(pg_error)?,
+                None => tx
+                    .execute(
+                        "DELETE FROM refs WHERE name = $1 AND agent_id = $2",
+                        &[&update.name, &self.namespace],
+                    )
+                    .await
+                    .map_err(pg_error)?,
+            };
+        }
+        tx.commit().await.map_err(pg_error)?;
+
+        for update in updates {
+            if let Some(target) = &update.new {
+                self.notify(
+                    &client,
+                    AgitEvent::RefUpdated {
+                        name: update.name.clone(),
+                        target: target.clone(),
+                    },
+                )
+                .await?;
+            }
+        }
         Ok(())
     }
 
     async fn get_ref(&self, name: &str) -> Result<Option<String>> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        let scoped_name = self.scope_ref(name);
+        let client = self.pool.get().await.map_err(pool_error)?;
         let rows = client
             .query(
-                "SELECT target FROM refs WHERE name = $1 AND agent_id = ''",
-                &[&scoped_name],
+                "SELECT target FROM refs WHERE name = $1 AND agent_id = $2",
+                &[&name, &self.namespace],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        Ok(rows
-            .first()
-            .map(|row| self.unscope_hash(&row.get::<_, String>(0))))
+            .map_err(pg_error)?;
+        Ok(rows.first().map(|row| row.get::<_, String>(0)))
     }
 
     async fn list_refs(&self) -> Result<HashMap<String, String>> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
+        let client = self.pool.get().await.map_err(pool_error)?;
         let rows = client
             .query(
-                "SELECT name, target FROM refs WHERE agent_id = ''",
-                &[],
+                "SELECT name, target FROM refs WHERE agent_id = $1",
+                &[&self.namespace],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        let mut map = HashMap::new();
-        for row in rows {
-            let scoped_name: String = row.get(0);
-            if !self.namespace.is_empty() && !scoped_name.starts_with(&format!("{}:", self.namespace)) {
-                continue;
-            }
-            let scoped_target: String = row.get(1);
-            map.insert(
-                self.unscope_ref(&scoped_name),
-                self.unscope_hash(&scoped_target),
-            );
-        }
-        Ok(map)
+            .map_err(pg_error)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
     }
 
     async fn delete_ref(&self, name: &str) -> Result<bool> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        let scoped_name = self.scope_ref(name);
+        let client = self.pool.get().await.map_err(pool_error)?;
         let count = client
             .execute(
-                "DELETE FROM refs WHERE name = $1 AND agent_id = ''",
-                &[&scoped_name],
+                "DELETE FROM refs WHERE name = $1 AND agent_id = $2",
+                &[&name, &self.namespace],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+            .map_err(pg_error)?;
         Ok(count > 0)
     }
 
     async fn append_log(&self, entry: &LogEntry) -> Result<()> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
+        let client = self.pool.get().await.map_err(pool_error)?;
         let details_json: Option<String> = entry
             .details
             .as_ref()
             .map(|v| serde_json::to_string(v))
             .transpose()
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+            .map_err(|e| AgitError::storage(e.to_string()))?;
         client
             .execute(
-                "INSERT INTO logs (id, timestamp, agent_id, action, message, commit_hash, details, level)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7::jsonb, $8)",
+                "INSERT INTO logs (id, timestamp, agent_id, action, message, commit_hash, details, level, namespace)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7::TEXT::jsonb, $8, $9)",
                 &[
                     &entry.id,
                     &entry.timestamp,
@@ -282,94 +705,56 @@ impl StorageBackend for PostgresStorage {
                     &entry.message,
                     &entry.commit_hash,
                     &details_json,
-                    &entry.level,
+                    &entry.level.as_str(),
+                    &self.namespace,
                 ],
             )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        Ok(())
+            .map_err(pg_error)?;
+        self.notify(
+            &client,
+            AgitEvent::LogAppended {
+                agent_id: entry.agent_id.clone(),
+                action: entry.action.clone(),
+                commit_hash: entry.commit_hash.clone(),
+            },
+        )
+        .await
     }
 
     async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-
-        // Build a parameterised query dynamically.  We use $1, $2, … style
-        // placeholders.  Collect the actual parameter values as trait objects
-        // so we can pass them to tokio-postgres.
-        let mut conditions: Vec<String> = Vec::new();
-        // We'll store owned strings/Options and then borrow them.
-        let mut p_agent_id: Option<String> = None;
-        let mut p_action: Option<String> = None;
-        let mut p_level: Option<String> = None;
-        let mut p_since: Option<String> = None;
-
-        let mut param_idx: usize = 1;
-
-        if let Some(ref v) = filter.agent_id {
-            p_agent_id = Some(v.clone());
-            conditions.push(format!("agent_id = ${}", param_idx));
-            param_idx += 1;
-        }
-        if let Some(ref v) = filter.action {
-            p_action = Some(v.clone());
-            conditions.push(format!("action = ${}", param_idx));
-            param_idx += 1;
-        }
-        if let Some(ref v) = filter.level {
-            p_level = Some(v.clone());
-            conditions.push(format!("level = ${}", param_idx));
-            param_idx += 1;
-        }
-        if let Some(ref v) = filter.since {
-            p_since = Some(v.clone());
-            conditions.push(format!("timestamp >= ${}", param_idx));
-            param_idx += 1;
-        }
-
-        let where_clause = if conditions.is_empty() {
-            String::new()
-        } else {
-            format!("WHERE {}", conditions.join(" AND "))
-        };
-
-        let mut p_limit: Option<i64> = None;
-        let limit_clause = if let Some(l) = filter.limit {
-            p_limit = Some(l as i64);
-            format!(" LIMIT ${}", param_idx)
-        } else {
-            String::new()
-        };
-
-        let sql = format!(
-            "SELECT id, timestamp, agent_id, action, message, commit_hash, details, level
-             FROM logs
-             {} ORDER BY timestamp DESC{}",
-            where_clause, limit_clause
-        );
+        let client = self.pool.get().await.map_err(pool_error)?;
 
-        // Build the params slice dynamically.
-        let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = Vec::new();
-        if let Some(ref v) = p_agent_id {
-            params.push(v);
-        }
-        if let Some(ref v) = p_action {
-            params.push(v);
-        }
-        if let Some(ref v) = p_level {
+        let (where_clause, values) = log_conditions(filter);
+        let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![&self.namespace];
+        for v in &values {
             params.push(v);
         }
-        if let Some(ref v) = p_since {
+
+        let p_limit = filter.limit.map(|l| l as i64);
+        let p_offset = filter.offset.map(|o| o as i64);
+        let mut page_clause = String::new();
+        if let Some(ref v) = p_limit {
             params.push(v);
+            page_clause.push_str(&format!(" LIMIT ${}", params.len()));
         }
-        if let Some(ref v) = p_limit {
+        if let Some(ref v) = p_offset {
             params.push(v);
+            page_clause.push_str(&format!(" OFFSET ${}", params.len()));
         }
 
+        // id breaks timestamp ties so pages never overlap
+        let sql = format!(
+            "SELECT id, timestamp, agent_id, action, message, commit_hash, details::TEXT, level
+             FROM logs
+             {} ORDER BY timestamp DESC, id DESC{}",
+            where_clause, page_clause
+        );
+
         let rows = client
             .query(sql.as_str(), params.as_slice())
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+            .map_err(pg_error)?;
 
         let entries = rows
             .into_iter()
@@ -377,8 +762,7 @@ impl StorageBackend for PostgresStorage {
                 let details_raw: Option<String> = row.get(6);
                 let details = match details_raw {
                     Some(s) => Some(
-                        serde_json::from_str(&s)
-                            .map_err(|e| AgitError::Storage(e.to_string()))?,
+                        serde_json::from_str(&s).map_err(|e| AgitError::storage(e.to_string()))?,
                     ),
                     None => None,
                 };
@@ -390,7 +774,7 @@ impl StorageBackend for PostgresStorage {
                     message: row.get(4),
                     commit_hash: row.get(5),
                     details,
-                    level: row.get(7),
+                    level: row.get::<_, String>(7).parse().unwrap_or_default(),
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -398,33 +782,295 @@ impl StorageBackend for PostgresStorage {
         Ok(entries)
     }
 
-    async fn delete_object(&self, hash: &str) -> Result<bool> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
-        let scoped_hash = self.scope_hash(hash);
+    async fn count_logs(&self, filter: &LogFilter) -> Result<usize> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let (where_clause, values) = log_conditions(filter);
+        let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![&self.namespace];
+        for v in &values {
+            params.push(v);
+        }
+        let row = client
+            .query_one(
+                format!("SELECT COUNT(*) FROM logs {where_clause}").as_str(),
+                params.as_slice(),
+            )
+            .await
+            .map_err(pg_error)?;
+        let count: i64 = row.get(0);
+        Ok(count as usize)
+    }
+
+    /// Signals each `LogAppended` event in this namespace, received over
+    /// `LISTEN` as described in [`PostgresStorage::subscribe`].
+    async fn log_signals(&self) -> Result<Option<BoxStream<'static, ()>>> {
+        let events = self.subscribe().await?;
+        Ok(Some(
+            events
+                .filter_map(|event| async move {
+                    matches!(event, AgitEvent::LogAppended { .. }).then_some(())
+                })
+                .boxed(),
+        ))
+    }
+
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        let keep_last = i64::try_from(filter.keep_last.unwrap_or(0)).unwrap_or(i64::MAX);
+        let client = self.pool.get().await.map_err(pool_error)?;
         let count = client
-            .execute("DELETE FROM objects WHERE hash = $1", &[&scoped_hash])
+            .execute(
+                "DELETE FROM logs l USING (
+                     SELECT id, agent_id, timestamp, ROW_NUMBER() OVER (
+                         PARTITION BY agent_id ORDER BY timestamp DESC, id DESC
+                     ) AS rank
+                     FROM logs
+                     WHERE namespace = $1 AND ($2::TEXT IS NULL OR agent_id = $2)
+                 ) ranked
+                 WHERE l.namespace = $1
+                   AND l.id = ranked.id
+                   AND l.agent_id = ranked.agent_id
+                   AND ranked.rank > $3
+                   AND ($4::TEXT IS NULL OR ranked.timestamp < $4)",
+                &[
+                    &self.namespace,
+                    &filter.agent_id,
+                    &keep_last,
+                    &filter.before,
+                ],
+            )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        Ok(count > 0)
+            .map_err(pg_error)?;
+        Ok(count as usize)
+    }
+
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        let hashes = [hash.to_string()];
+        Ok(self.delete_objects(&hashes).await? > 0)
+    }
+
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        if hashes.is_empty() {
+            return Ok(0);
+        }
+        let mut client = self.pool.get().await.map_err(pool_error)?;
+        let tx = client.transaction().await.map_err(pg_error)?;
+        let count = tx
+            .execute(
+                "DELETE FROM namespace_objects WHERE agent_id = $1 AND hash = ANY($2)",
+                &[&self.namespace, &hashes],
+            )
+            .await
+            .map_err(pg_error)?;
+        // Drop contents no other namespace still holds
+        tx.execute(
+            "DELETE FROM objects o
+             WHERE o.hash = ANY($1)
+               AND NOT EXISTS (SELECT 1 FROM namespace_objects n WHERE n.hash = o.hash)",
+            &[&hashes],
+        )
+        .await
+        .map_err(pg_error)?;
+        tx.commit().await.map_err(pg_error)?;
+        Ok(count as usize)
     }
 
     async fn list_objects(&self) -> Result<Vec<String>> {
-        let client = self.pool.get().await
-            .map_err(|e| AgitError::Storage(format!("pool error: {e}")))?;
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let rows = client
+            .query(
+                "SELECT hash FROM namespace_objects WHERE agent_id = $1",
+                &[&self.namespace],
+            )
+            .await
+            .map_err(pg_error)?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let type_str = obj_type.to_string();
         let rows = client
-            .query("SELECT hash FROM objects", &[])
+            .query(
+                "SELECT o.hash FROM objects o
+                 JOIN namespace_objects n ON n.hash = o.hash
+                 WHERE n.agent_id = $1 AND o.type = $2",
+                &[&self.namespace, &type_str],
+            )
             .await
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        let mut objects = Vec::new();
+            .map_err(pg_error)?;
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let rows = client
+            .query(
+                "SELECT o.type, octet_length(o.data)::BIGINT, EXTRACT(EPOCH FROM n.created_at)::FLOAT8
+                 FROM objects o
+                 JOIN namespace_objects n ON n.hash = o.hash
+                 WHERE n.agent_id = $1 AND o.hash = $2",
+                &[&self.namespace, &hash],
+            )
+            .await
+            .map_err(pg_error)?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        let obj_type: String = row.get(0);
+        let size: i64 = row.get(1);
+        let epoch: f64 = row.get(2);
+        Ok(Some(ObjectStat {
+            size: size as u64,
+            obj_type: obj_type.parse()?,
+            created_at: DateTime::<Utc>::from_timestamp_micros((epoch * 1e6) as i64),
+        }))
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        // Shared contents count toward every namespace holding them
+        let rows = client
+            .query(
+                "SELECT o.type, COUNT(*), COALESCE(SUM(octet_length(o.data)), 0)::BIGINT
+                 FROM objects o
+                 JOIN namespace_objects n ON n.hash = o.hash
+                 WHERE n.agent_id = $1
+                 GROUP BY o.type",
+                &[&self.namespace],
+            )
+            .await
+            .map_err(pg_error)?;
+        let mut stats = StorageStats::default();
         for row in rows {
-            let scoped_hash: String = row.get(0);
-            if self.namespace.is_empty()
-                || scoped_hash.starts_with(&format!("{}:", self.namespace))
-            {
-                objects.push(self.unscope_hash(&scoped_hash));
+            let obj_type: String = row.get(0);
+            let count: i64 = row.get(1);
+            let bytes: i64 = row.get(2);
+            stats.add_many(obj_type.parse()?, count as usize, bytes as u64);
+        }
+        Ok(stats)
+    }
+
+    /// Takes a session-level `pg_advisory_lock` keyed by the namespace and
+    /// `name`, so the lock is shared by every process using the database.
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> Result<LockGuard> {
+        let key = lock_key(&self.namespace, name);
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let acquired: bool = client
+                .query_one("SELECT pg_try_advisory_lock($1)", &[&key])
+                .await
+                .map_err(pg_error)?
+                .get(0);
+            if acquired {
+                // Detached from the pool, the session closes when the guard
+                // drops, which releases the lock even if unlocking would fail
+                return Ok(LockGuard::new(deadpool_postgres::Object::take(client)));
             }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(AgitError::LockTimeout {
+                    name: name.to_string(),
+                });
+            }
+            tokio::time::sleep(LOCK_RETRY_INTERVAL.min(deadline - now)).await;
         }
-        Ok(objects)
+    }
+
+    /// Runs `VACUUM (ANALYZE)` on the agit tables. Plain `VACUUM` makes
+    /// dead rows' space reusable but only returns trailing pages to the
+    /// filesystem, so the reported sizes may barely change, or grow as
+    /// visibility and free space maps are created.
+    async fn compact(&self) -> Result<CompactReport> {
+        let client = self.pool.get().await.map_err(pool_error)?;
+        let size_sql = "SELECT COALESCE(SUM(pg_total_relation_size(t)), 0)::BIGINT
+                        FROM unnest(ARRAY['objects', 'namespace_objects', 'refs', 'logs']::regclass[]) t";
+        let bytes_before: i64 = client
+            .query_one(size_sql, &[])
+            .await
+            .map_err(pg_error)?
+            .get(0);
+        // VACUUM cannot run inside a transaction, so it must be the only
+        // statement sent
+        client
+            .batch_execute("VACUUM (ANALYZE) objects, namespace_objects, refs, logs")
+            .await
+            .map_err(pg_error)?;
+        let bytes_after: i64 = client
+            .query_one(size_sql, &[])
+            .await
+            .map_err(pg_error)?
+            .get(0);
+        Ok(CompactReport {
+            bytes_before: bytes_before as u64,
+            bytes_after: bytes_after as u64,
+        })
+    }
+
+    /// Closes the pool; pooled connections are dropped as they are returned.
+    async fn close(&self) -> Result<()> {
+        self.pool.close();
+        Ok(())
+    }
+
+    fn as_bulk_write(&self) -> Option<&dyn BulkWrite> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl BulkWrite for PostgresStorage {
+    /// Streams the batch into a temporary table with a binary `COPY`, then
+    /// merges it into `objects` and this namespace's memberships in the same
+    /// transaction.
+    async fn put_objects_bulk(&self, objects: &[(String, ObjectType, Vec<u8>)]) -> Result<usize> {
+        if objects.is_empty() {
+            return Ok(0);
+        }
+        let mut client = self.pool.get().await.map_err(pool_error)?;
+        let tx = client.transaction().await.map_err(pg_error)?;
+        tx.batch_execute(
+            "CREATE TEMP TABLE bulk_objects (hash TEXT, type TEXT, data BYTEA) ON COMMIT DROP",
+        )
+        .await
+        .map_err(pg_error)?;
+
+        let sink = tx
+            .copy_in("COPY bulk_objects (hash, type, data) FROM STDIN (FORMAT binary)")
+            .await
+            .map_err(pg_error)?;
+        let mut writer = std::pin::pin!(BinaryCopyInWriter::new(
+            sink,
+            &[Type::TEXT, Type::TEXT, Type::BYTEA],
+        ));
+        for (hash, obj_type, data) in objects {
+            let type_str = obj_type.to_string();
+            writer
+                .as_mut()
+                .write(&[hash, &type_str, data])
+                .await
+                .map_err(pg_error)?;
+        }
+        writer.finish().await.map_err(pg_error)?;
+
+        tx.execute(
+            "INSERT INTO objects (hash, type, data)
+             SELECT hash, type, data FROM bulk_objects
+             ON CONFLICT (hash) DO NOTHING",
+            &[],
+        )
+        .await
+        .map_err(pg_error)?;
+        let added = tx
+            .execute(
+                "INSERT INTO namespace_objects (agent_id, hash)
+                 SELECT $1, hash FROM bulk_objects
+                 ON CONFLICT DO NOTHING",
+                &[&self.namespace],
+            )
+            .await
+            .map_err(pg_error)?;
+        tx.commit().await.map_err(pg_error)?;
+        Ok(added as usize)
     }
 }
@@ -0,0 +1,617 @@
+#[cfg(feature = "redis")]
+use async_trait::async_trait;
+#[cfg(feature = "redis")]
+use chrono::DateTime;
+#[cfg(feature = "redis")]
+use deadpool_redis::{Config, Connection, Pool, PoolConfig, PoolError, Runtime};
+#[cfg(feature = "redis")]
+use redis::streams::StreamRangeReply;
+#[cfg(feature = "redis")]
+use redis::AsyncCommands;
+#[cfg(feature = "redis")]
+use std::collections::HashMap;
+#[cfg(feature = "redis")]
+use std::time::Duration;
+
+#[cfg(feature = "redis")]
+use super::{
+    LogDeleteFilter, LogEntry, LogFilter, ObjectStat, RefUpdate, StorageBackend, StorageStats,
+};
+#[cfg(feature = "redis")]
+use crate::error::{AgitError, Result};
+#[cfg(feature = "redis")]
+use crate::types::ObjectType;
+
+/// Field holding the serialized `LogEntry` in each stream entry.
+#[cfg(feature = "redis")]
+const LOG_ENTRY_FIELD: &str = "entry";
+
+/// Applies a batch of ref updates to the refs hash `KEYS[1]` atomically.
+/// Each update is five arguments: the ref name, `any`, `absent` or `is`
+/// with the expected target, then `set` with the new target or `delete`
+/// with an empty one. Returns the name of the first ref whose expectation
+/// fails, without writing anything.
+#[cfg(feature = "redis")]
+const UPDATE_REFS_SCRIPT: &str = r"
+for i = 1, #ARGV, 5 do
+    local current = redis.call('HGET', KEYS[1], ARGV[i])
+    if (ARGV[i + 1] == 'absent' and current)
+        or (ARGV[i + 1] == 'is' and current ~= ARGV[i + 2]) then
+        return ARGV[i]
+    end
+end
+for i = 1, #ARGV, 5 do
+    if ARGV[i + 3] == 'set' then
+        redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 4])
+    else
+        redis.call('HDEL', KEYS[1], ARGV[i])
+    end
+end
+return false
+";
+
+/// Redis-backed storage with connection pooling.
+///
+/// Key layout, under an optional namespace prefix:
+/// - `agit:objects:<hash>`: object bytes as a string key
+/// - `agit:object_types`: hash of object key to object type
+/// - `agit:refs`: hash of ref name to target
+/// - `agit:logs:<agent_id>`: one stream of log entries per agent
+/// - `agit:log_agents`: set of agent ids with a log stream
+///
+/// Stream entry ids carry the entry's timestamp, so `query_logs` can
+/// narrow the `since` filter with `XRANGE`. `update_refs` checks and
+/// applies its batch in one Lua script, so it is atomic across clients.
+/// Requires Redis 7 or later.
+///
+/// Enable with the `redis` Cargo feature flag.
+#[cfg(feature = "redis")]
+pub struct RedisStorage {
+    pool: Pool,
+    prefix: String,
+    object_ttl: Option<Duration>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStorage {
+    /// Connect to Redis using a URL, e.g. `"redis://localhost:6379/0"`.
+    pub async fn new(url: &str) -> Result<Self> {
+        Self::new_scoped(url, "").await
+    }
+
+    /// Connect to Redis with a storage namespace.
+    ///
+    /// The namespace is used to isolate refs, objects, and logs across
+    /// tenants sharing one Redis database.
+    pub async fn new_scoped(url: &str, namespace: &str) -> Result<Self> {
+        let mut cfg = Config::from_url(url);
+        cfg.pool = Some(PoolConfig::new(16));
+
+        let pool = cfg
+            .create_pool(Some(Runtime::Tokio1))
+            .map_err(|e| AgitError::storage(format!("pool creation error: {e}")))?;
+
+        let prefix = if namespace.is_empty() {
+            "agit:".to_string()
+        } else {
+            format!("agit:{namespace}:")
+        };
+        let storage = RedisStorage {
+            pool,
+            prefix,
+            object_ttl: None,
+        };
+        storage.initialize().await?;
+        Ok(storage)
+    }
+
+    /// Expire objects `ttl` after they are first stored.
+    ///
+    /// Objects never expire by default. Only suitable for caches: an
+    /// expired object leaves dangling references in the commit graph.
+    pub fn with_object_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.object_ttl = ttl;
+        self
+    }
+
+    async fn conn(&self) -> Result<Connection> {
+        self.pool.get().await.map_err(|e| {
+            let retryable = match &e {
+                PoolError::Timeout(_) => true,
+                PoolError::Backend(e) => is_transient(e),
+                _ => false,
+            };
+            AgitError::Storage {
+                message: format!("pool error: {e}"),
+                retryable,
+            }
+        })
+    }
+
+    fn object_key(&self, hash: &str) -> String {
+        format!("{}objects:{}", self.prefix, hash)
+    }
+
+    fn object_types_key(&self) -> String {
+        format!("{}object_types", self.prefix)
+    }
+
+    fn refs_key(&self) -> String {
+        format!("{}refs", self.prefix)
+    }
+
+    fn log_key(&self, agent_id: &str) -> String {
+        format!("{}logs:{}", self.prefix, agent_id)
+    }
+
+    fn log_agents_key(&self) -> String {
+        format!("{}log_agents", self.prefix)
+    }
+
+    /// `SCAN` every object key, returning hashes without the key prefix.
+    async fn scan_objects(&self, conn: &mut Connection) -> Result<Vec<String>> {
+        let key_prefix = self.object_key("");
+        let pattern = format!("{}*", glob_escape(&key_prefix));
+        let mut cursor: u64 = 0;
+        let mut hashes = Vec::new();
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(conn)
+                .await
+                .map_err(redis_err)?;
+            hashes.extend(
+                keys.iter()
+                    .filter_map(|k| k.strip_prefix(&key_prefix))
+                    .map(str::to_string),
+            );
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        // SCAN may return a key more than once
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+}
+
+/// Whether `e` is transient: a dropped or refused connection, a timeout,
+/// or a server asking clients to wait.
+#[cfg(feature = "redis")]
+fn is_transient(e: &redis::RedisError) -> bool {
+    e.is_io_error()
+        || e.is_timeout()
+        || e.is_connection_dropped()
+        || e.is_connection_refusal()
+        || matches!(
+            e.kind(),
+            redis::ErrorKind::TryAgain
+                | redis::ErrorKind::BusyLoadingError
+                | redis::ErrorKind::MasterDown
+                | redis::ErrorKind::ClusterDown
+        )
+}
+
+#[cfg(feature = "redis")]
+fn redis_err(e: redis::RedisError) -> AgitError {
+    AgitError::Storage {
+        message: e.to_string(),
+        retryable: is_transient(&e),
+    }
+}
+
+/// Escape glob metacharacters for a `SCAN MATCH` pattern.
+#[cfg(feature = "redis")]
+fn glob_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Milliseconds since the epoch of an RFC 3339 timestamp, if it parses
+/// and is not before the epoch.
+#[cfg(feature = "redis")]
+fn timestamp_millis(timestamp: &str) -> Option<u64> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .and_then(|t| u64::try_from(t.timestamp_millis()).ok())
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl StorageBackend for RedisStorage {
+    async fn initialize(&self) -> Result<()> {
+        // Nothing to create; just check the server is reachable.
+        let mut conn = self.conn().await?;
+        redis::cmd("PING")
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(redis_err)
+    }
+
+    async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+        let mut conn = self.conn().await?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.object_key(hash)).arg(data).arg("NX");
+        if let Some(ttl) = self.object_ttl {
+            cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+        }
+        redis::pipe()
+            .add_command(cmd)
+            .ignore()
+            .hset(self.object_types_key(), hash, obj_type.to_string())
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(redis_err)
+    }
+
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let mut conn = self.conn().await?;
+        conn.get(self.object_key(hash)).await.map_err(redis_err)
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut conn = self.conn().await?;
+        let key = self.object_key(hash);
+        let data: Vec<u8> = if len == 0 {
+            Vec::new()
+        } else {
+            // GETRANGE end is inclusive and clamped to the value length
+            let end = offset.saturating_add(len - 1).min(isize::MAX as usize);
+            conn.getrange(&key, offset as isize, end as isize)
+                .await
+                .map_err(redis_err)?
+        };
+        // GETRANGE returns "" for a missing key too
+        if data.is_empty() && !conn.exists::<_, bool>(&key).await.map_err(redis_err)? {
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+
+    async fn has_object(&self, hash: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+        conn.exists(self.object_key(hash)).await.map_err(redis_err)
+    }
+
+    async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+        let mut conn = self.conn().await?;
+        conn.hset::<_, _, _, ()>(self.refs_key(), name, hash)
+            .await
+            .map_err(redis_err)
+    }
+
+    async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+        let mut conn = self.conn().await?;
+        conn.hget(self.refs_key(), name).await.map_err(redis_err)
+    }
+
+    async fn list_refs(&self) -> Result<HashMap<String, String>> {
+        let mut conn = self.conn().await?;
+        conn.hgetall(self.refs_key()).await.map_err(redis_err)
+    }
+
+    async fn delete_ref(&self, name: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+        let removed: i64 = conn.hdel(self.refs_key(), name).await.map_err(redis_err)?;
+        Ok(removed > 0)
+    }
+
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let script = redis::Script::new(UPDATE_REFS_SCRIPT);
+        let mut invocation = script.key(self.refs_key());
+        for update in updates {
+            let (check, expected) = match (&update.expected_old, update.expect_absent) {
+                (Some(old), _) => ("is", old.as_str()),
+                (None, true) => ("absent", ""),
+                (None, false) => ("any", ""),
+            };
+            let (op, target) = match &update.new {
+                Some(target) => ("set", target.as_str()),
+                None => ("delete", ""),
+            };
+            invocation
+                .arg(&update.name)
+                .arg(check)
+                .arg(expected)
+                .arg(op)
+                .arg(target);
+        }
+        let mut conn = self.conn().await?;
+        let stale: Option<String> = invocation
+            .invoke_async(&mut conn)
+            .await
+            .map_err(redis_err)?;
+        match stale {
+            Some(name) => Err(AgitError::ConcurrentUpdate { name }),
+            None => Ok(()),
+        }
+    }
+
+    async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        let mut conn = self.conn().await?;
+        let json = serde_json::to_string(entry)?;
+        let key = self.log_key(&entry.agent_id);
+
+        // Prefer an id at the entry's timestamp so XRANGE can filter on it.
+        // Stream ids must increase, so an entry older than the stream's
+        // newest falls back to an id at the current time.
+        let mut added = false;
+        if let Some(ms) = timestamp_millis(&entry.timestamp) {
+            let result: redis::RedisResult<String> = conn
+                .xadd(&key, format!("{ms}-*"), &[(LOG_ENTRY_FIELD, &json)])
+                .await;
+            match result {
+                Ok(_) => added = true,
+                Err(e) if e.to_string().contains("equal or smaller") => {}
+                Err(e) => return Err(redis_err(e)),
+            }
+        }
+        if !added {
+            conn.xadd::<_, _, _, _, String>(&key, "*", &[(LOG_ENTRY_FIELD, &json)])
+                .await
+                .map_err(redis_err)?;
+        }
+        conn.sadd::<_, _, ()>(self.log_agents_key(), &entry.agent_id)
+            .await
+            .map_err(redis_err)
+    }
+
+    async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        let mut conn = self.conn().await?;
+        let agents: Vec<String> = match &filter.agent_id {
+            Some(agent_id) => vec![agent_id.clone()],
+            None => conn
+                .smembers(self.log_agents_key())
+                .await
+                .map_err(redis_err)?,
+        };
+
+        // An entry's stream id is never earlier than its timestamp, so
+        // starting at `since` skips only entries the filter rejects. The
+        // same does not hold for `until`, which is checked per entry.
+        let start = filter
+            .since
+            .as_deref()
+            .and_then(timestamp_millis)
+            .map_or_else(|| "-".to_string(), |ms| ms.to_string());
+
+        let mut entries = Vec::new();
+        for agent_id in agents {
+            let reply: StreamRangeReply = conn
+                .xrange(self.log_key(&agent_id), &start, "+")
+                .await
+                .map_err(redis_err)?;
+            for stream_id in reply.ids {
+                let Some(json) = stream_id.get::<String>(LOG_ENTRY_FIELD) else {
+                    continue;
+                };
+                let entry: LogEntry = serde_json::from_str(&json)?;
+                if filter.matches(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        // Newest first, like the SQL backends' ORDER BY timestamp DESC
+        entries.reverse();
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(filter.paginate(entries))
+    }
+
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        let mut conn = self.conn().await?;
+        let agents: Vec<String> = match &filter.agent_id {
+            Some(agent_id) => vec![agent_id.clone()],
+            None => conn
+                .smembers(self.log_agents_key())
+                .await
+                .map_err(redis_err)?,
+        };
+
+        let mut removed = 0;
+        for agent_id in agents {
+            let key = self.log_key(&agent_id);
+            let reply: StreamRangeReply = conn.xrange(&key, "-", "+").await.map_err(redis_err)?;
+            let mut stream_ids = Vec::new();
+            let mut entries = Vec::new();
+            for stream_id in reply.ids {
+                let Some(json) = stream_id.get::<String>(LOG_ENTRY_FIELD) else {
+                    continue;
+                };
+                entries.push(serde_json::from_str::<LogEntry>(&json)?);
+                stream_ids.push(stream_id.id);
+            }
+            let selected = filter.select(&entries);
+            let doomed: Vec<String> = stream_ids
+                .into_iter()
+                .zip(&entries)
+                .filter(|(_, entry)| selected.contains(&entry.id))
+                .map(|(stream_id, _)| stream_id)
+                .collect();
+            if !doomed.is_empty() {
+                removed += conn
+                    .xdel::<_, _, usize>(&key, &doomed)
+                    .await
+                    .map_err(redis_err)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+        let (removed, _): (i64, i64) = redis::pipe()
+            .del(self.object_key(hash))
+            .hdel(self.object_types_key(), hash)
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_err)?;
+        Ok(removed > 0)
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        let mut conn = self.conn().await?;
+        self.scan_objects(&mut conn).await
+    }
+
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        let mut conn = self.conn().await?;
+        let type_str = obj_type.to_string();
+        let types: HashMap<String, String> = conn
+            .hgetall(self.object_types_key())
+            .await
+            .map_err(redis_err)?;
+        // Scan rather than trust the type map alone: objects with a TTL
+        // expire without removing their type entry.
+        Ok(self
+            .scan_objects(&mut conn)
+            .await?
+            .into_iter()
+            .filter(|hash| types.get(hash) == Some(&type_str))
+            .collect())
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        let mut conn = self.conn().await?;
+        let (exists, size, obj_type): (bool, u64, Option<String>) = redis::pipe()
+            .exists(self.object_key(hash))
+            .strlen(self.object_key(hash))
+            .hget(self.object_types_key(), hash)
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_err)?;
+        if !exists {
+            return Ok(None);
+        }
+        let obj_type = match obj_type {
+            Some(t) => t.parse()?,
+            None => ObjectType::Blob,
+        };
+        Ok(Some(ObjectStat {
+            size,
+            obj_type,
+            created_at: None,
+        }))
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let mut conn = self.conn().await?;
+        let hashes = self.scan_objects(&mut conn).await?;
+        let types: HashMap<String, String> = conn
+            .hgetall(self.object_types_key())
+            .await
+            .map_err(redis_err)?;
+
+        let mut stats = StorageStats::default();
+        for batch in hashes.chunks(1000) {
+            let mut pipe = redis::pipe();
+            for hash in batch {
+                pipe.strlen(self.object_key(hash));
+            }
+            let sizes: Vec<u64> = pipe.query_async(&mut conn).await.map_err(redis_err)?;
+            for (hash, size) in batch.iter().zip(sizes) {
+                let obj_type = match types.get(hash) {
+                    Some(t) => t.parse()?,
+                    None => ObjectType::Blob,
+                };
+                stats.add(obj_type, size);
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+
+    /// Storage in a fresh namespace on the Redis at `AGIT_TEST_REDIS_URL`,
+    /// or `None` to skip when the variable is unset.
+    async fn test_storage() -> Option<(RedisStorage, ())> {
+        let url = std::env::var("AGIT_TEST_REDIS_URL").ok()?;
+        let namespace = format!("test-{}", uuid::Uuid::new_v4());
+        Some((
+            RedisStorage::new_scoped(&url, &namespace).await.unwrap(),
+            (),
+        ))
+    }
+
+    storage_conformance_tests!(match test_storage().await {
+        Some(setup) => setup,
+        None => return,
+    });
+
+    #[tokio::test]
+    async fn test_log_ids_follow_timestamps() {
+        let Some((storage, ())) = test_storage().await else {
+            return;
+        };
+        let mut entry = LogEntry {
+            id: "1".to_string(),
+            timestamp: "2026-01-02T00:00:00Z".to_string(),
+            agent_id: "a".to_string(),
+            action: "commit".to_string(),
+            message: "m".to_string(),
+            commit_hash: None,
+            details: None,
+            level: LogLevel::Info,
+        };
+        storage.append_log(&entry).await.unwrap();
+        // Older than the stream's newest id, so it gets a fallback id
+        entry.id = "2".to_string();
+        entry.timestamp = "2026-01-01T00:00:00Z".to_string();
+        storage.append_log(&entry).await.unwrap();
+
+        let filter = LogFilter {
+            since: Some("2026-01-01T12:00:00Z".to_string()),
+            ..Default::default()
+        };
+        let logs = storage.query_logs(&filter).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].id, "1");
+        assert_eq!(
+            storage
+                .query_logs(&LogFilter::default())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_are_isolated() {
+        let Some((a, ())) = test_storage().await else {
+            return;
+        };
+        let Some((b, ())) = test_storage().await else {
+            return;
+        };
+        a.put_object("abc", ObjectType::Blob, b"data")
+            .await
+            .unwrap();
+        a.set_ref("main", "abc").await.unwrap();
+        assert!(!b.has_object("abc").await.unwrap());
+        assert!(b.list_objects().await.unwrap().is_empty());
+        assert!(b.list_refs().await.unwrap().is_empty());
+    }
+}
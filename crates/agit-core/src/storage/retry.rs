@@ -0,0 +1,420 @@
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use super::{
+    BackupReport, BulkWrite, CompactReport, LockGuard, LogDeleteFilter, LogEntry, LogFilter,
+    ObjectStat, RefUpdate, StorageBackend, StorageStats,
+};
+use crate::error::{AgitError, Result};
+use crate::types::ObjectType;
+
+/// When and how often `RetryingStorage` retries a failed operation.
+///
+/// The delay before the n-th retry is `base_delay * 2^(n-1)`, capped at
+/// `max_delay`, with up to a `jitter` fraction of it taken off at random
+/// so that clients failing together do not retry in lockstep.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first. Default 5.
+    pub max_attempts: u32,
+    /// Delay before the first retry. Default 50 ms.
+    pub base_delay: Duration,
+    /// Longest delay between two attempts. Default 5 s.
+    pub max_delay: Duration,
+    /// Fraction of each delay to randomize, from 0 to 1. Default 0.5.
+    pub jitter: f64,
+    /// Which errors are worth retrying. Default `is_transient`, which
+    /// trusts the backend's classification: busy or locked SQLite
+    /// databases, Postgres serialization failures and lost connections,
+    /// S3 5xx and throttling responses, and Redis connection errors.
+    pub is_retryable: fn(&AgitError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.5,
+            is_retryable: is_transient,
+        }
+    }
+}
+
+/// Whether the backend marked `e` as a transient storage failure. Unlike
+/// `AgitError::is_retryable`, a stale ref or a busy lock does not count:
+/// repeating the same call would fail the same way.
+pub fn is_transient(e: &AgitError) -> bool {
+    matches!(
+        e,
+        AgitError::Storage {
+            retryable: true,
+            ..
+        }
+    )
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `retry`, counting from 1.
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * random_fraction();
+        backoff.mul_f64(1.0 - jitter)
+    }
+}
+
+/// A random number in `[0, 1)`.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A backend wrapper that retries operations failing with transient
+/// errors, backing off between attempts.
+///
+/// Every operation is retried, so it relies on the backend leaving no
+/// partial effects behind when it reports a transient error. Errors the
+/// policy does not consider retryable, and the last error once attempts
+/// run out, are returned as is.
+pub struct RetryingStorage<B: StorageBackend> {
+    inner: B,
+    policy: RetryPolicy,
+}
+
+impl<B: StorageBackend> RetryingStorage<B> {
+    /// Wrap `inner`, retrying its operations according to `policy`.
+    pub fn new(inner: B, policy: RetryPolicy) -> Self {
+        RetryingStorage { inner, policy }
+    }
+
+    /// The wrapped backend.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Run `op` until it succeeds, fails with an error the policy does not
+    /// retry, or runs out of attempts.
+    async fn retry<T, F, Fut>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.policy.max_attempts && (self.policy.is_retryable)(&e) => {
+                    let delay = self.policy.delay(attempt);
+                    #[cfg(feature = "observability")]
+                    tracing::debug!(attempt, ?delay, error = %e, "retrying storage operation");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for RetryingStorage<B> {
+    async fn initialize(&self) -> Result<()> {
+        self.retry(|| self.inner.initialize()).await
+    }
+
+    async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+        self.retry(|| self.inner.put_object(hash, obj_type, data))
+            .await
+    }
+
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        self.retry(|| self.inner.get_object(hash)).await
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        self.retry(|| self.inner.get_object_range(hash, offset, len))
+            .await
+    }
+
+    async fn has_object(&self, hash: &str) -> Result<bool> {
+        self.retry(|| self.inner.has_object(hash)).await
+    }
+
+    async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+        self.retry(|| self.inner.set_ref(name, hash)).await
+    }
+
+    async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+        self.retry(|| self.inner.get_ref(name)).await
+    }
+
+    async fn list_refs(&self) -> Result<HashMap<String, String>> {
+        self.retry(|| self.inner.list_refs()).await
+    }
+
+    async fn delete_ref(&self, name: &str) -> Result<bool> {
+        self.retry(|| self.inner.delete_ref(name)).await
+    }
+
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        self.retry(|| self.inner.update_refs(updates)).await
+    }
+
+    async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        self.retry(|| self.inner.append_log(entry)).await
+    }
+
+    async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        self.retry(|| self.inner.query_logs(filter)).await
+    }
+
+    async fn count_logs(&self, filter: &LogFilter) -> Result<usize> {
+        self.retry(|| self.inner.count_logs(filter)).await
+    }
+
+    async fn log_signals(&self) -> Result<Option<BoxStream<'static, ()>>> {
+        self.retry(|| self.inner.log_signals()).await
+    }
+
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        self.retry(|| self.inner.delete_logs(filter)).await
+    }
+
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        self.retry(|| self.inner.delete_object(hash)).await
+    }
+
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        self.retry(|| self.inner.delete_objects(hashes)).await
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        self.retry(|| self.inner.list_objects()).await
+    }
+
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        self.retry(|| self.inner.list_objects_by_type(obj_type))
+            .await
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        self.retry(|| self.inner.stat_object(hash)).await
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        self.retry(|| self.inner.storage_stats()).await
+    }
+
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> Result<LockGuard> {
+        self.retry(|| self.inner.acquire_lock(name, timeout)).await
+    }
+
+    async fn backup_to(&self, path: &str) -> Result<BackupReport> {
+        self.retry(|| self.inner.backup_to(path)).await
+    }
+
+    async fn compact(&self) -> Result<CompactReport> {
+        self.retry(|| self.inner.compact()).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+
+    /// Bulk writes go straight to the wrapped backend, without retries.
+    fn as_bulk_write(&self) -> Option<&dyn BulkWrite> {
+        self.inner.as_bulk_write()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::Repository;
+    use crate::state::AgentState;
+    use crate::storage::MemoryStorage;
+    use crate::types::ActionType;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    storage_conformance_tests!((
+        RetryingStorage::new(MemoryStorage::new(), RetryPolicy::default()),
+        ()
+    ));
+
+    /// Memory storage whose writes fail with `error` until `failures`
+    /// attempts have been made.
+    struct FlakyStorage {
+        inner: MemoryStorage,
+        failures: u32,
+        error: fn() -> AgitError,
+        attempts: AtomicU32,
+    }
+
+    impl FlakyStorage {
+        fn new(failures: u32, error: fn() -> AgitError) -> Self {
+            FlakyStorage {
+                inner: MemoryStorage::new(),
+                failures,
+                error,
+                attempts: AtomicU32::new(0),
+            }
+        }
+
+        fn check(&self) -> Result<()> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err((self.error)())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for FlakyStorage {
+        async fn initialize(&self) -> Result<()> {
+            self.inner.initialize().await
+        }
+        async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+            self.check()?;
+            self.inner.put_object(hash, obj_type, data).await
+        }
+        async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+            self.inner.get_object(hash).await
+        }
+        async fn has_object(&self, hash: &str) -> Result<bool> {
+            self.inner.has_object(hash).await
+        }
+        async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+            self.inner.set_ref(name, hash).await
+        }
+        async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+            self.inner.get_ref(name).await
+        }
+        async fn list_refs(&self) -> Result<HashMap<String, String>> {
+            self.inner.list_refs().await
+        }
+        async fn delete_ref(&self, name: &str) -> Result<bool> {
+            self.inner.delete_ref(name).await
+        }
+        async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+            self.inner.append_log(entry).await
+        }
+        async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+            self.inner.query_logs(filter).await
+        }
+        async fn delete_object(&self, hash: &str) -> Result<bool> {
+            self.inner.delete_object(hash).await
+        }
+        async fn list_objects(&self) -> Result<Vec<String>> {
+            self.inner.list_objects().await
+        }
+    }
+
+    fn busy() -> AgitError {
+        AgitError::transient_storage("database is locked")
+    }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_errors_are_retried() {
+        let storage = RetryingStorage::new(FlakyStorage::new(3, busy), fast_policy());
+        storage
+            .put_object("abc", ObjectType::Blob, b"data")
+            .await
+            .unwrap();
+        assert_eq!(storage.inner().attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            storage.get_object("abc").await.unwrap(),
+            Some(b"data".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            ..fast_policy()
+        };
+        let storage = RetryingStorage::new(FlakyStorage::new(10, busy), policy);
+        let err = storage
+            .put_object("abc", ObjectType::Blob, b"data")
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable());
+        assert_eq!(storage.inner().attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_not_retried() {
+        let storage = RetryingStorage::new(
+            FlakyStorage::new(1, || AgitError::storage("disk full")),
+            fast_policy(),
+        );
+        let err = storage
+            .put_object("abc", ObjectType::Blob, b"data")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AgitError::Storage {
+                retryable: false,
+                ..
+            }
+        ));
+        assert_eq!(storage.inner().attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_delays_back_off_with_jitter() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for (retry, full) in [(1, 100), (2, 200), (3, 350), (10, 350)] {
+            let delay = policy.delay(retry);
+            let full = Duration::from_millis(full);
+            assert!(
+                delay <= full && delay >= full / 2,
+                "retry {retry}: {delay:?}"
+            );
+        }
+        let fixed = RetryPolicy {
+            jitter: 0.0,
+            ..policy
+        };
+        assert_eq!(fixed.delay(2), Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_repository_over_retrying_storage() {
+        let storage = RetryingStorage::new(FlakyStorage::new(2, busy), fast_policy());
+        let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+        let state = AgentState::new(json!({"step": 1}), json!({}));
+        repo.commit(&state, "first", ActionType::ToolCall)
+            .await
+            .unwrap();
+        assert_eq!(repo.log(None, 10).await.unwrap()[0].1.message, "first");
+    }
+}
@@ -1,22 +1,452 @@
 #[cfg(feature = "s3")]
 use async_trait::async_trait;
 #[cfg(feature = "s3")]
+use aws_sdk_kms::Client as KmsClient;
+#[cfg(feature = "s3")]
+use aws_sdk_s3::config::http::HttpResponse;
+#[cfg(feature = "s3")]
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+#[cfg(feature = "s3")]
 use aws_sdk_s3::Client as S3Client;
 #[cfg(feature = "s3")]
 use aws_sdk_sqs::Client as SqsClient;
 #[cfg(feature = "s3")]
+use chrono::{DateTime, Days, Months, NaiveDate, Utc};
+#[cfg(feature = "s3")]
 use std::collections::HashMap;
+#[cfg(feature = "s3")]
+use std::sync::Mutex;
 
 #[cfg(feature = "s3")]
-use super::{LogEntry, LogFilter, StorageBackend};
+use super::{
+    LogDeleteFilter, LogEntry, LogFilter, ObjectStat, RefUpdate, StorageBackend, StorageStats,
+};
+#[cfg(feature = "s3")]
+use crate::encryption::{base64_decode, base64_encode, decrypt_bytes, encrypt_bytes, random_key};
 #[cfg(feature = "s3")]
 use crate::error::{AgitError, Result};
 #[cfg(feature = "s3")]
+use crate::objects::{infer_object_type, TREE_KEY_SUFFIX};
+#[cfg(feature = "s3")]
 use crate::types::ObjectType;
 
-/// Minimum byte size above which objects are zstd-compressed before upload.
+/// S3 error codes asking the client to slow down or try again.
+#[cfg(feature = "s3")]
+const TRANSIENT_ERROR_CODES: &[&str] = &[
+    "SlowDown",
+    "RequestTimeout",
+    "InternalError",
+    "ServiceUnavailable",
+    "Throttling",
+    "ThrottlingException",
+];
+
+/// Whether a failed SDK call may succeed if retried: it timed out, never
+/// reached the service, got a 5xx response, or was throttled.
+#[cfg(feature = "s3")]
+fn is_transient<E: ProvideErrorMetadata>(e: &SdkError<E, HttpResponse>) -> bool {
+    match e {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        _ => {
+            e.raw_response().is_some_and(|r| r.status().as_u16() >= 500)
+                || e.code()
+                    .is_some_and(|code| TRANSIENT_ERROR_CODES.contains(&code))
+        }
+    }
+}
+
+/// Map a failed SDK call, marking transient failures as retryable.
+#[cfg(feature = "s3")]
+fn sdk_error<E>(e: SdkError<E, HttpResponse>) -> AgitError
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    AgitError::Storage {
+        retryable: is_transient(&e),
+        message: DisplayErrorContext(&e).to_string(),
+    }
+}
+
+/// User metadata key recording an object's type.
+#[cfg(feature = "s3")]
+const TYPE_METADATA_KEY: &str = "agit-type";
+
+/// User metadata key recording how an object's body is encoded: `zstd` or
+/// `identity`. Sent as the `x-amz-meta-agit-encoding` header.
+#[cfg(feature = "s3")]
+const ENCODING_METADATA_KEY: &str = "agit-encoding";
+
+/// Whether a stored body is zstd-compressed. The `agit-encoding` metadata
+/// decides when present; objects written before it existed, or by other
+/// tools, are recognised by their `Content-Encoding` or `Content-Type`.
+#[cfg(feature = "s3")]
+fn is_zstd(
+    metadata: Option<&HashMap<String, String>>,
+    content_type: Option<&str>,
+    content_encoding: Option<&str>,
+) -> bool {
+    match metadata.and_then(|m| m.get(ENCODING_METADATA_KEY)) {
+        Some(encoding) => encoding == "zstd",
+        None => content_encoding == Some("zstd") || content_type == Some("application/zstd"),
+    }
+}
+
+/// Body of the SQS message announcing a log entry stored at `key`.
+#[cfg(feature = "s3")]
+fn log_notification(entry: &LogEntry, key: &str) -> serde_json::Value {
+    serde_json::json!({
+        "event": "log_append",
+        "key": key,
+        "id": &entry.id,
+        "agent_id": &entry.agent_id,
+        "action": &entry.action,
+        "level": &entry.level,
+        "commit_hash": &entry.commit_hash,
+        "timestamp": &entry.timestamp,
+    })
+}
+
+/// User metadata keys of a client-side encrypted body: the wrapped data
+/// key, the body's AES-GCM nonce, and what wrapped the data key (`local`
+/// or `kms`).
+#[cfg(feature = "s3")]
+const CSE_KEY_METADATA_KEY: &str = "agit-cse-key";
+#[cfg(feature = "s3")]
+const CSE_NONCE_METADATA_KEY: &str = "agit-cse-nonce";
+#[cfg(feature = "s3")]
+const CSE_WRAP_METADATA_KEY: &str = "agit-cse-wrap";
+
+/// Master key for client-side envelope encryption of stored bodies.
+///
+/// Each body is encrypted with its own random AES-256-GCM data key, and
+/// the data key is stored alongside it, wrapped by the master key.
+#[cfg(feature = "s3")]
+#[derive(Clone)]
+pub enum MasterKey {
+    /// A 256-bit key held by the application, wrapping data keys with
+    /// AES-256-GCM.
+    Local([u8; 32]),
+    /// An AWS KMS key id, ARN, or alias: data keys come from KMS
+    /// `GenerateDataKey` and are unwrapped with `Decrypt`.
+    Kms(String),
+}
+
+#[cfg(feature = "s3")]
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MasterKey::Local(_) => f.write_str("Local(..)"),
+            MasterKey::Kms(key_id) => f.debug_tuple("Kms").field(key_id).finish(),
+        }
+    }
+}
+
+/// How an encrypted body's data key is stored, read from and written to
+/// its object metadata.
+#[cfg(feature = "s3")]
+struct Envelope {
+    /// The data key, wrapped by the master key.
+    wrapped_key: Vec<u8>,
+    /// AES-GCM nonce of the body.
+    nonce: Vec<u8>,
+    /// Whether KMS wrapped the data key, rather than a local master key.
+    kms: bool,
+}
+
+#[cfg(feature = "s3")]
+impl Envelope {
+    fn write_metadata(&self, metadata: &mut HashMap<String, String>) {
+        metadata.insert(
+            CSE_KEY_METADATA_KEY.to_string(),
+            base64_encode(&self.wrapped_key),
+        );
+        metadata.insert(
+            CSE_NONCE_METADATA_KEY.to_string(),
+            base64_encode(&self.nonce),
+        );
+        let wrap = if self.kms { "kms" } else { "local" };
+        metadata.insert(CSE_WRAP_METADATA_KEY.to_string(), wrap.to_string());
+    }
+
+    /// The envelope recorded in `metadata`, or `None` for a body that is
+    /// not client-side encrypted.
+    fn from_metadata(metadata: Option<&HashMap<String, String>>) -> Result<Option<Self>> {
+        let Some(metadata) = metadata else {
+            return Ok(None);
+        };
+        let Some(wrapped_key) = metadata.get(CSE_KEY_METADATA_KEY) else {
+            return Ok(None);
+        };
+        let decode = |value: Option<&String>, what: &str| {
+            value
+                .ok_or_else(|| AgitError::EncryptionError(format!("missing {what} metadata")))
+                .and_then(|v| {
+                    base64_decode(v)
+                        .map_err(|e| AgitError::EncryptionError(format!("bad {what}: {e}")))
+                })
+        };
+        Ok(Some(Envelope {
+            wrapped_key: decode(Some(wrapped_key), "data key")?,
+            nonce: decode(metadata.get(CSE_NONCE_METADATA_KEY), "nonce")?,
+            kms: metadata.get(CSE_WRAP_METADATA_KEY).map(String::as_str) == Some("kms"),
+        }))
+    }
+}
+
+/// A 32-byte data key from unwrapped key material.
+#[cfg(feature = "s3")]
+fn data_key(bytes: &[u8]) -> Result<[u8; 32]> {
+    bytes
+        .try_into()
+        .map_err(|_| AgitError::EncryptionError("data key is not 256 bits".to_string()))
+}
+
+/// User metadata for a body: its encoding and, if encrypted, its envelope.
+#[cfg(feature = "s3")]
+fn body_metadata(compressed: bool, envelope: Option<&Envelope>) -> HashMap<String, String> {
+    let mut metadata = HashMap::from([(
+        ENCODING_METADATA_KEY.to_string(),
+        encoding_name(compressed).to_string(),
+    )]);
+    if let Some(envelope) = envelope {
+        envelope.write_metadata(&mut metadata);
+    }
+    metadata
+}
+
+/// Longest `since`..`until` span, in days, whose partitions are enumerated
+/// rather than discovered by listing.
+#[cfg(feature = "s3")]
+const MAX_ENUMERATED_PARTITIONS: i64 = 62;
+
+/// UTC date of an RFC 3339 timestamp.
+#[cfg(feature = "s3")]
+fn utc_date(timestamp: &str) -> Option<NaiveDate> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|t| t.with_timezone(&Utc).date_naive())
+}
+
+/// Date partition of a log entry: `yyyy/mm/dd/` of its UTC timestamp.
+#[cfg(feature = "s3")]
+fn log_partition(timestamp: &str) -> Option<String> {
+    utc_date(timestamp).map(|d| d.format("%Y/%m/%d/").to_string())
+}
+
+/// Partitions `yyyy/mm/dd/` for every day from `since` to `until`, newest first.
+#[cfg(feature = "s3")]
+fn day_partitions(since: NaiveDate, until: NaiveDate) -> Vec<String> {
+    let mut days = Vec::new();
+    let mut day = until;
+    while day >= since {
+        days.push(day.format("%Y/%m/%d/").to_string());
+        match day.pred_opt() {
+            Some(prev) => day = prev,
+            None => break,
+        }
+    }
+    days
+}
+
+/// First and last day covered by a partition path such as `2026/`,
+/// `2026/02/`, or `2026/02/14/`.
+#[cfg(feature = "s3")]
+fn partition_span(path: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let parts = path
+        .trim_end_matches('/')
+        .split('/')
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [y] => Some((
+            NaiveDate::from_ymd_opt(y as i32, 1, 1)?,
+            NaiveDate::from_ymd_opt(y as i32, 12, 31)?,
+        )),
+        [y, m] => {
+            let first = NaiveDate::from_ymd_opt(y as i32, m, 1)?;
+            let next = first.checked_add_months(Months::new(1))?;
+            Some((first, next.pred_opt()?))
+        }
+        [y, m, d] => {
+            let day = NaiveDate::from_ymd_opt(y as i32, m, d)?;
+            Some((day, day))
+        }
+        _ => None,
+    }
+}
+
+/// Keep the partition prefixes overlapping `since`..`until`, newest first.
+/// Prefixes that are not date partitions are dropped.
+#[cfg(feature = "s3")]
+fn prune_partitions(
+    agent_prefix: &str,
+    prefixes: Vec<String>,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> Vec<String> {
+    let mut kept: Vec<String> = prefixes
+        .into_iter()
+        .filter(|prefix| {
+            let path = prefix.strip_prefix(agent_prefix).unwrap_or(prefix);
+            partition_span(path).is_some_and(|(first, last)| {
+                since.is_none_or(|s| last >= s) && until.is_none_or(|u| first <= u)
+            })
+        })
+        .collect();
+    kept.sort_unstable_by(|a, b| b.cmp(a));
+    kept
+}
+
+/// The file name of a log entry key, which begins with the entry's
+/// timestamp (colons replaced by dashes).
 #[cfg(feature = "s3")]
-const COMPRESS_THRESHOLD: usize = 1024;
+fn log_file_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap_or(key)
+}
+
+/// Most keys a single `DeleteObjects` request accepts.
+#[cfg(feature = "s3")]
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// Smallest part S3 accepts in a multipart upload, other than the last.
+#[cfg(feature = "s3")]
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// How `S3Storage` encodes the objects and log entries it writes.
+#[cfg(feature = "s3")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionMode {
+    /// zstd-compress bodies of at least `S3Options::threshold` bytes.
+    #[default]
+    Zstd,
+    /// Store every body as is, e.g. to inspect raw JSON in the bucket.
+    Disabled,
+}
+
+/// Tuning for how `S3Storage` encodes and moves objects.
+///
+/// Bodies of at least `threshold` bytes are zstd-compressed unless
+/// `compression` is disabled. Reads do not depend on these settings: each
+/// object records its own encoding, so data written with other settings
+/// stays readable, except encrypted data without its master key.
+///
+/// Objects at or above `multipart_threshold` are uploaded in parts of
+/// `part_size`, compressing as they go, and every object is downloaded in
+/// ranged GETs of `range_size`, decompressing as they arrive. Either way
+/// at most about one part or range is buffered besides the object itself.
+/// Encrypted objects are sealed and opened whole, so they take about twice
+/// their size in memory.
+#[cfg(feature = "s3")]
+#[derive(Debug, Clone)]
+pub struct S3Options {
+    /// Whether to compress what is written. Default zstd.
+    pub compression: CompressionMode,
+    /// zstd compression level. Default 3.
+    pub zstd_level: i32,
+    /// Minimum body size in bytes to compress. Default 1 KiB.
+    pub threshold: usize,
+    /// Client-side envelope encryption of objects and log entries, applied
+    /// after compression. Refs stay plaintext. Default none.
+    pub encryption: Option<MasterKey>,
+    /// Body size in bytes from which uploads use multipart upload.
+    /// Default 16 MiB.
+    pub multipart_threshold: usize,
+    /// Size of each uploaded part, raised to S3's 5 MiB minimum.
+    /// Default 8 MiB.
+    pub part_size: usize,
+    /// Size of each ranged GET. Default 8 MiB.
+    pub range_size: usize,
+}
+
+#[cfg(feature = "s3")]
+impl Default for S3Options {
+    fn default() -> Self {
+        S3Options {
+            compression: CompressionMode::Zstd,
+            zstd_level: 3,
+            threshold: 1024,
+            encryption: None,
+            multipart_threshold: 16 * 1024 * 1024,
+            part_size: 8 * 1024 * 1024,
+            range_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// Value of the `agit-encoding` metadata for a body.
+#[cfg(feature = "s3")]
+fn encoding_name(compressed: bool) -> &'static str {
+    if compressed {
+        "zstd"
+    } else {
+        "identity"
+    }
+}
+
+/// Content type of a stored body whose plaintext has type `plain`.
+#[cfg(feature = "s3")]
+fn body_content_type(compressed: bool, encrypted: bool, plain: &'static str) -> &'static str {
+    if encrypted {
+        "application/octet-stream"
+    } else if compressed {
+        "application/zstd"
+    } else {
+        plain
+    }
+}
+
+/// Accumulates a downloaded object, decompressing it on the fly.
+#[cfg(feature = "s3")]
+enum ObjectSink {
+    Raw(Vec<u8>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+#[cfg(feature = "s3")]
+impl ObjectSink {
+    fn new(compressed: bool) -> Result<Self> {
+        if compressed {
+            let decoder = zstd::stream::write::Decoder::new(Vec::new())
+                .map_err(|e| AgitError::storage(format!("zstd decompress: {e}")))?;
+            Ok(ObjectSink::Zstd(decoder))
+        } else {
+            Ok(ObjectSink::Raw(Vec::new()))
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        use std::io::Write;
+        match self {
+            ObjectSink::Raw(data) => data.extend_from_slice(bytes),
+            ObjectSink::Zstd(decoder) => decoder
+                .write_all(bytes)
+                .map_err(|e| AgitError::storage(format!("zstd decompress: {e}")))?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        use std::io::Write;
+        match self {
+            ObjectSink::Raw(data) => Ok(data),
+            ObjectSink::Zstd(mut decoder) => {
+                decoder
+                    .flush()
+                    .map_err(|e| AgitError::storage(format!("zstd decompress: {e}")))?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Total object size from a `Content-Range` header such as
+/// `bytes 0-99/1234`.
+#[cfg(feature = "s3")]
+fn content_range_total(content_range: &str) -> Option<u64> {
+    content_range.rsplit('/').next()?.parse().ok()
+}
 
 /// S3-backed storage backend.
 ///
@@ -24,9 +454,24 @@ const COMPRESS_THRESHOLD: usize = 1024;
 /// ```text
 /// objects/<hash>                              – raw (or zstd-compressed) object bytes
 /// refs/<name>                                 – small JSON file: {"target": "<hash>"}
-/// logs/<agent_id>/<timestamp>_<uuid>.json     – one object per log entry (atomic append)
+/// logs/<agent_id>/<yyyy>/<mm>/<dd>/<timestamp>_<uuid>.json
+///                                             – one object per log entry (atomic append),
+///                                               partitioned by the entry's UTC date
 /// ```
 ///
+/// `query_logs` only lists the date partitions that can match `since` and
+/// `until`, newest first, and stops once `limit` entries are found. Log
+/// entries written before partitioning, directly under `logs/<agent_id>/`,
+/// are still read.
+///
+/// `set_ref` and `delete_ref` write unconditionally. `update_refs` is
+/// compare-and-swap: each write only applies to the version of the ref it
+/// checked, using `If-Match` on its ETag, or `If-None-Match` when the ref
+/// was absent, so a ref changed by another writer in the meantime fails
+/// with `AgitError::ConcurrentUpdate`. There is no transaction to run the
+/// batch in: the refs are written one by one, and the ones already written
+/// are restored if a later write fails.
+///
 /// Enable with the `s3` Cargo feature flag.
 #[cfg(feature = "s3")]
 pub struct S3Storage {
@@ -35,7 +480,13 @@ pub struct S3Storage {
     prefix: String,
     sqs_queue_url: Option<String>,
     sqs_client: Option<SqsClient>,
-    compress: bool,
+    /// Fail `append_log` when the SQS notification cannot be sent.
+    sqs_strict: bool,
+    /// Set when `options.encryption` is a KMS key.
+    kms_client: Option<KmsClient>,
+    options: S3Options,
+    /// ETag of each ref as last read or written by this instance.
+    ref_etags: Mutex<HashMap<String, String>>,
 }
 
 #[cfg(feature = "s3")]
@@ -44,7 +495,9 @@ impl S3Storage {
     ///
     /// `bucket` – the S3 bucket name.
     /// `prefix` – optional key prefix (e.g. `"agit/"`) – use `""` for none.
-    /// `sqs_queue_url` – optional SQS queue URL for real-time log streaming.
+    /// `sqs_queue_url` – optional SQS queue URL for real-time log streaming;
+    /// each appended log entry is announced with a message (see
+    /// `log_notification`).
     ///
     /// AWS credentials / region are resolved via the standard SDK chain
     /// (env vars, `~/.aws/credentials`, instance profile, etc.).
@@ -52,22 +505,73 @@ impl S3Storage {
         bucket: impl Into<String>,
         prefix: impl Into<String>,
         sqs_queue_url: Option<String>,
+    ) -> Result<Self> {
+        Self::with_options(bucket, prefix, sqs_queue_url, S3Options::default()).await
+    }
+
+    /// Like `new`, with explicit compression, encryption, and large-object
+    /// tuning.
+    pub async fn with_options(
+        bucket: impl Into<String>,
+        prefix: impl Into<String>,
+        sqs_queue_url: Option<String>,
+        options: S3Options,
     ) -> Result<Self> {
         let config = aws_config::load_from_env().await;
         let client = S3Client::new(&config);
         let sqs_client = sqs_queue_url.as_ref().map(|_| SqsClient::new(&config));
+        let kms_client =
+            matches!(options.encryption, Some(MasterKey::Kms(_))).then(|| KmsClient::new(&config));
         let storage = S3Storage {
             client,
             bucket: bucket.into(),
             prefix: prefix.into(),
             sqs_queue_url,
             sqs_client,
-            compress: true,
+            sqs_strict: false,
+            kms_client,
+            options,
+            ref_etags: Mutex::new(HashMap::new()),
         };
         storage.initialize().await?;
         Ok(storage)
     }
 
+    /// Make a failure to publish a log entry to SQS fail `append_log`.
+    ///
+    /// By default such failures are only logged, as a `tracing` warning
+    /// with the `observability` feature: the entry is already stored, and a
+    /// missed notification must not fail the commit.
+    pub fn with_sqs_strict(mut self, strict: bool) -> Self {
+        self.sqs_strict = strict;
+        self
+    }
+
+    /// Announce a stored log entry on the SQS queue, if one is configured.
+    ///
+    /// FIFO queues (URL ending in `.fifo`) get the log id as deduplication
+    /// id and the agent id as message group, keeping each agent's entries
+    /// in order.
+    async fn publish_log(&self, entry: &LogEntry, key: &str) -> Result<()> {
+        let (Some(queue_url), Some(sqs)) = (&self.sqs_queue_url, &self.sqs_client) else {
+            return Ok(());
+        };
+        let mut req = sqs
+            .send_message()
+            .queue_url(queue_url)
+            .message_body(log_notification(entry, key).to_string());
+        if queue_url.ends_with(".fifo") {
+            req = req
+                .message_deduplication_id(&entry.id)
+                .message_group_id(&entry.agent_id);
+        }
+        req.send().await.map_err(|e| AgitError::Storage {
+            message: format!("SQS error: {}", DisplayErrorContext(&e)),
+            retryable: is_transient(&e),
+        })?;
+        Ok(())
+    }
+
     fn object_key(&self, hash: &str) -> String {
         format!("{}objects/{}", self.prefix, hash)
     }
@@ -84,15 +588,32 @@ impl S3Storage {
         format!("{}logs/{}/", self.prefix, agent_id)
     }
 
+    /// Build the S3 key for a log entry, under its date partition when the
+    /// timestamp parses.
+    fn log_key(&self, entry: &LogEntry) -> String {
+        format!(
+            "{}{}{}_{}.json",
+            self.log_prefix(&entry.agent_id),
+            log_partition(&entry.timestamp).unwrap_or_default(),
+            entry.timestamp.replace(':', "-"),
+            entry.id,
+        )
+    }
+
     /// Build the S3 key prefix for all log entries.
     fn all_logs_prefix(&self) -> String {
         format!("{}logs/", self.prefix)
     }
 
-    /// Download a key and return its raw bytes, or `None` if not found.
-    /// Unlike `get_bytes`, this returns `Ok(None)` for any SDK error (for
+    /// Download a key and return its stored bytes, user metadata, and
+    /// whether it is zstd-compressed, or `None` if not found.
+    /// Unlike `get_bytes_with_etag`, this returns `Ok(None)` for any SDK error (for
     /// resilient log scanning).
-    async fn get_raw_object(&self, key: &str) -> Result<Option<Vec<u8>>> {
+    #[allow(clippy::type_complexity)]
+    async fn get_raw_object(
+        &self,
+        key: &str,
+    ) -> Result<Option<(Vec<u8>, Option<HashMap<String, String>>, bool)>> {
         match self
             .client
             .get_object()
@@ -102,19 +623,25 @@ impl S3Storage {
             .await
         {
             Ok(resp) => {
+                let compressed = is_zstd(
+                    resp.metadata(),
+                    resp.content_type(),
+                    resp.content_encoding(),
+                );
+                let metadata = resp.metadata().cloned();
                 let bytes = resp
                     .body
                     .collect()
                     .await
-                    .map_err(|e| AgitError::Storage(e.to_string()))?;
-                Ok(Some(bytes.into_bytes().to_vec()))
+                    .map_err(|e| AgitError::transient_storage(e.to_string()))?;
+                Ok(Some((bytes.into_bytes().to_vec(), metadata, compressed)))
             }
             Err(_) => Ok(None),
         }
     }
 
-    /// Download a key and return its bytes, or `None` if not found.
-    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+    /// Download a key and return its bytes and ETag, or `None` if not found.
+    async fn get_bytes_with_etag(&self, key: &str) -> Result<Option<(Vec<u8>, Option<String>)>> {
         match self
             .client
             .get_object()
@@ -124,43 +651,680 @@ impl S3Storage {
             .await
         {
             Ok(resp) => {
+                let etag = resp.e_tag().map(|s| s.to_string());
                 let bytes = resp
                     .body
                     .collect()
                     .await
-                    .map_err(|e| AgitError::Storage(e.to_string()))?
+                    .map_err(|e| AgitError::transient_storage(e.to_string()))?
                     .into_bytes()
                     .to_vec();
-                Ok(Some(bytes))
+                Ok(Some((bytes, etag)))
             }
             Err(e) => {
                 // The SDK wraps NoSuchKey inside SdkError; check the service error.
+                let retryable = is_transient(&e);
                 let service_err = e.into_service_error();
                 if service_err.is_no_such_key() {
                     Ok(None)
                 } else {
-                    Err(AgitError::Storage(service_err.to_string()))
+                    Err(AgitError::Storage {
+                        message: service_err.to_string(),
+                        retryable,
+                    })
                 }
             }
         }
     }
 
-    /// Upload bytes to a key, replacing any existing content.
+    /// Point ref `name` at `target`, or delete it when `target` is `None`.
+    ///
+    /// With `conditional`, the write only applies to the version of the ref
+    /// this instance last read or wrote: `If-Match` on its ETag, or
+    /// `If-None-Match` when the ref was absent. A ref changed since fails
+    /// with `AgitError::ConcurrentUpdate`.
+    async fn write_ref(&self, name: &str, target: Option<&str>, conditional: bool) -> Result<()> {
+        let key = self.ref_key(name);
+        let expected = self.ref_etags().get(name).cloned();
+        let Some(target) = target else {
+            let mut req = self.client.delete_object().bucket(&self.bucket).key(&key);
+            if conditional {
+                let Some(etag) = expected else {
+                    // Seen absent, so there is nothing to delete
+                    return Ok(());
+                };
+                req = req.if_match(etag);
+            }
+            req.send()
+                .await
+                .map_err(|e| self.ref_update_error(name, e))?;
+            self.ref_etags().remove(name);
+            return Ok(());
+        };
+
+        let body = serde_json::to_vec(&serde_json::json!({ "target": target }))
+            .map_err(|e| AgitError::storage(e.to_string()))?;
+        let mut req = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(aws_sdk_s3::primitives::ByteStream::from(body))
+            .content_type("application/json")
+            .server_side_encryption(aws_sdk_s3::types::ServerSideEncryption::Aes256);
+        if conditional {
+            req = match expected {
+                Some(etag) => req.if_match(etag),
+                None => req.if_none_match("*"),
+            };
+        }
+        let resp = req
+            .send()
+            .await
+            .map_err(|e| self.ref_update_error(name, e))?;
+        let mut etags = self.ref_etags();
+        match resp.e_tag() {
+            Some(etag) => etags.insert(name.to_string(), etag.to_string()),
+            None => etags.remove(name),
+        };
+        Ok(())
+    }
+
+    /// Parse a ref object, remembering its ETag for the next update.
+    fn read_ref(&self, name: &str, bytes: &[u8], etag: Option<String>) -> Option<String> {
+        let v: serde_json::Value = serde_json::from_slice(bytes).unwrap_or(serde_json::Value::Null);
+        let mut etags = self.ref_etags();
+        match etag {
+            Some(etag) => etags.insert(name.to_string(), etag),
+            None => etags.remove(name),
+        };
+        v["target"].as_str().map(|s| s.to_string())
+    }
+
+    fn ref_etags(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.ref_etags.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Map a failed conditional ref write: a failed precondition (412) or a
+    /// conflicting conditional write in flight (409) means another writer
+    /// got there first.
+    fn ref_update_error<E>(&self, name: &str, e: SdkError<E, HttpResponse>) -> AgitError
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        let status = e.raw_response().map(|r| r.status().as_u16());
+        if matches!(status, Some(409 | 412)) {
+            // Force a re-read before the next update
+            self.ref_etags().remove(name);
+            AgitError::ConcurrentUpdate {
+                name: name.to_string(),
+            }
+        } else {
+            sdk_error(e)
+        }
+    }
+
+    /// Upload bytes to a key, replacing any existing content, with the
+    /// given user metadata (see `body_metadata`).
     /// Enforces AES-256 server-side encryption on all objects.
-    async fn put_bytes(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<()> {
+    async fn put_bytes(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        content_type: &str,
+        metadata: HashMap<String, String>,
+    ) -> Result<()> {
+        if data.len() >= self.options.multipart_threshold {
+            return self
+                .put_multipart(key, &data, false, content_type, metadata)
+                .await;
+        }
         self.client
             .put_object()
             .bucket(&self.bucket)
             .key(key)
             .body(aws_sdk_s3::primitives::ByteStream::from(data))
             .content_type(content_type)
+            .set_metadata(Some(metadata))
             .server_side_encryption(aws_sdk_s3::types::ServerSideEncryption::Aes256)
             .send()
             .await
-            .map_err(|e| AgitError::Storage(e.into_service_error().to_string()))?;
+            .map_err(sdk_error)?;
         Ok(())
     }
 
+    /// Upload `data` to a key in parts, zstd-compressing it on the way with
+    /// `compress`. The upload is aborted if any part fails.
+    async fn put_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        compress: bool,
+        content_type: &str,
+        metadata: HashMap<String, String>,
+    ) -> Result<()> {
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .set_metadata(Some(metadata))
+            .server_side_encryption(aws_sdk_s3::types::ServerSideEncryption::Aes256)
+            .send()
+            .await
+            .map_err(sdk_error)?
+            .upload_id()
+            .ok_or_else(|| AgitError::storage("multipart upload without an id".to_string()))?
+            .to_string();
+
+        let result = match self.upload_parts(key, &upload_id, data, compress).await {
+            Ok(parts) => self
+                .client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .multipart_upload(
+                    aws_sdk_s3::types::CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(sdk_error),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            // Incomplete uploads keep their parts (and their cost) until aborted
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+        }
+        result
+    }
+
+    /// Upload the parts of a multipart upload. Compressed output is cut
+    /// into parts as the encoder produces it, so only about one part is
+    /// buffered at a time.
+    async fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        data: &[u8],
+        compress: bool,
+    ) -> Result<Vec<aws_sdk_s3::types::CompletedPart>> {
+        use std::io::Write;
+
+        let part_size = self.options.part_size.max(MIN_PART_SIZE);
+        let mut parts = Vec::new();
+        if !compress {
+            for chunk in data.chunks(part_size) {
+                let part = self
+                    .upload_part(key, upload_id, parts.len() + 1, chunk.to_vec())
+                    .await?;
+                parts.push(part);
+            }
+            return Ok(parts);
+        }
+
+        let zstd_err = |e: std::io::Error| AgitError::storage(format!("zstd compress: {e}"));
+        let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), self.options.zstd_level)
+            .map_err(zstd_err)?;
+        for chunk in data.chunks(part_size) {
+            encoder.write_all(chunk).map_err(zstd_err)?;
+            while encoder.get_ref().len() >= part_size {
+                let body: Vec<u8> = encoder.get_mut().drain(..part_size).collect();
+                let part = self
+                    .upload_part(key, upload_id, parts.len() + 1, body)
+                    .await?;
+                parts.push(part);
+            }
+        }
+        let rest = encoder.finish().map_err(zstd_err)?;
+        if !rest.is_empty() || parts.is_empty() {
+            let part = self
+                .upload_part(key, upload_id, parts.len() + 1, rest)
+                .await?;
+            parts.push(part);
+        }
+        Ok(parts)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: usize,
+        body: Vec<u8>,
+    ) -> Result<aws_sdk_s3::types::CompletedPart> {
+        let number = number as i32;
+        let resp = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(number)
+            .body(aws_sdk_s3::primitives::ByteStream::from(body))
+            .send()
+            .await
+            .map_err(sdk_error)?;
+        Ok(aws_sdk_s3::types::CompletedPart::builder()
+            .part_number(number)
+            .set_e_tag(resp.e_tag().map(str::to_string))
+            .build())
+    }
+
+    /// Download an object in ranged GETs of `range_size`, decompressing
+    /// zstd bodies as they arrive. Encrypted bodies are collected whole,
+    /// then decrypted and decompressed.
+    async fn download_object(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let range_size = self.options.range_size.max(1) as u64;
+        let mut offset = 0u64;
+        let mut total = None;
+        let mut etag: Option<String> = None;
+        let mut sink = None;
+        let mut envelope = None;
+        let mut compressed = false;
+
+        while total.is_none_or(|total| offset < total) {
+            let mut req = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .range(format!("bytes={}-{}", offset, offset + range_size - 1));
+            // Fail rather than stitch together two versions of the key
+            if let Some(ref etag) = etag {
+                req = req.if_match(etag);
+            }
+            let resp = match req.send().await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    let retryable = is_transient(&e);
+                    let service_err = e.into_service_error();
+                    if service_err.is_no_such_key() && sink.is_none() {
+                        return Ok(None);
+                    }
+                    if service_err.meta().code() == Some("InvalidRange") && sink.is_none() {
+                        // Only an empty object has no byte at offset 0
+                        return Ok(Some(Vec::new()));
+                    }
+                    return Err(AgitError::Storage {
+                        message: service_err.to_string(),
+                        retryable,
+                    });
+                }
+            };
+            if sink.is_none() {
+                compressed = is_zstd(
+                    resp.metadata(),
+                    resp.content_type(),
+                    resp.content_encoding(),
+                );
+                envelope = Envelope::from_metadata(resp.metadata())?;
+                sink = Some(ObjectSink::new(compressed && envelope.is_none())?);
+                etag = resp.e_tag().map(str::to_string);
+                // Without a Content-Range the whole object was returned
+                total = Some(
+                    resp.content_range()
+                        .and_then(content_range_total)
+                        .unwrap_or(resp.content_length().unwrap_or(0) as u64),
+                );
+            }
+            let bytes = resp
+                .body
+                .collect()
+                .await
+                .map_err(|e| AgitError::transient_storage(e.to_string()))?
+                .into_bytes();
+            if bytes.is_empty() {
+                break;
+            }
+            offset += bytes.len() as u64;
+            if let Some(sink) = sink.as_mut() {
+                sink.write(&bytes)?;
+            }
+        }
+        let body = match sink {
+            Some(sink) => sink.finish()?,
+            None => Vec::new(),
+        };
+        match envelope {
+            Some(envelope) => {
+                let body = self.open_body(body, &envelope).await?;
+                Self::maybe_decompress(body, compressed).map(Some)
+            }
+            None => Ok(Some(body)),
+        }
+    }
+
+    /// Envelope-encrypt a body under a fresh data key, if a master key is
+    /// configured.
+    async fn seal_body(&self, body: Vec<u8>) -> Result<(Vec<u8>, Option<Envelope>)> {
+        let Some(master) = &self.options.encryption else {
+            return Ok((body, None));
+        };
+        let (key, wrapped_key, kms) = match master {
+            MasterKey::Local(master) => {
+                let key = random_key();
+                let (nonce, wrapped) = encrypt_bytes(master, &key)?;
+                (key, [nonce.as_slice(), &wrapped].concat(), false)
+            }
+            MasterKey::Kms(key_id) => {
+                let resp = self
+                    .kms()?
+                    .generate_data_key()
+                    .key_id(key_id)
+                    .key_spec(aws_sdk_kms::types::DataKeySpec::Aes256)
+                    .send()
+                    .await
+                    .map_err(|e| AgitError::Storage {
+                        message: format!("KMS error: {}", DisplayErrorContext(&e)),
+                        retryable: is_transient(&e),
+                    })?;
+                let (Some(plaintext), Some(wrapped)) = (resp.plaintext(), resp.ciphertext_blob())
+                else {
+                    return Err(AgitError::EncryptionError(
+                        "KMS returned no data key".to_string(),
+                    ));
+                };
+                (
+                    data_key(plaintext.as_ref())?,
+                    wrapped.as_ref().to_vec(),
+                    true,
+                )
+            }
+        };
+        let (nonce, ciphertext) = encrypt_bytes(&key, &body)?;
+        Ok((
+            ciphertext,
+            Some(Envelope {
+                wrapped_key,
+                nonce: nonce.to_vec(),
+                kms,
+            }),
+        ))
+    }
+
+    /// Decrypt a body sealed by `seal_body`.
+    async fn open_body(&self, body: Vec<u8>, envelope: &Envelope) -> Result<Vec<u8>> {
+        let key = if envelope.kms {
+            let resp = self
+                .kms()?
+                .decrypt()
+                .ciphertext_blob(aws_sdk_kms::primitives::Blob::new(
+                    envelope.wrapped_key.clone(),
+                ))
+                .send()
+                .await
+                .map_err(|e| AgitError::Storage {
+                    message: format!("KMS error: {}", DisplayErrorContext(&e)),
+                    retryable: is_transient(&e),
+                })?;
+            let plaintext = resp.plaintext().ok_or_else(|| {
+                AgitError::EncryptionError("KMS returned no data key".to_string())
+            })?;
+            data_key(plaintext.as_ref())?
+        } else {
+            let Some(MasterKey::Local(master)) = &self.options.encryption else {
+                return Err(AgitError::EncryptionError(
+                    "object is encrypted with a local master key, but none is configured"
+                        .to_string(),
+                ));
+            };
+            if envelope.wrapped_key.len() < 12 {
+                return Err(AgitError::EncryptionError(
+                    "wrapped data key too short".into(),
+                ));
+            }
+            let (nonce, wrapped) = envelope.wrapped_key.split_at(12);
+            data_key(&decrypt_bytes(master, nonce, wrapped)?)?
+        };
+        decrypt_bytes(&key, &envelope.nonce, &body)
+    }
+
+    fn kms(&self) -> Result<&KmsClient> {
+        self.kms_client.as_ref().ok_or_else(|| {
+            AgitError::EncryptionError(
+                "object key is wrapped by KMS, but no KMS key is configured".to_string(),
+            )
+        })
+    }
+
+    /// Type of a stored object: the recorded `agit-type` metadata if
+    /// present, else inferred from its key and, for objects written before
+    /// the metadata existed, its contents.
+    async fn resolve_object_type(&self, hash: &str, recorded: Option<&str>) -> Result<ObjectType> {
+        if let Some(obj_type) = recorded.and_then(|t| t.parse().ok()) {
+            return Ok(obj_type);
+        }
+        if hash.ends_with(TREE_KEY_SUFFIX) {
+            return Ok(ObjectType::Tree);
+        }
+        let data = self.get_object(hash).await?.unwrap_or_default();
+        Ok(infer_object_type(hash, &data))
+    }
+
+    /// List every object hash with its stored size in bytes.
+    async fn list_object_sizes(&self) -> Result<Vec<(String, u64)>> {
+        let prefix = format!("{}objects/", self.prefix);
+        let mut objects = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut req = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix);
+
+            if let Some(ref token) = continuation_token {
+                req = req.continuation_token(token);
+            }
+
+            let resp = req.send().await.map_err(sdk_error)?;
+
+            for obj in resp.contents() {
+                if let Some(key) = obj.key() {
+                    // Extract hash from key: prefix/objects/<hash>
+                    if let Some(hash) = key.strip_prefix(&prefix) {
+                        objects.push((hash.to_string(), obj.size().unwrap_or(0) as u64));
+                    }
+                }
+            }
+
+            if resp.is_truncated().unwrap_or(false) {
+                continuation_token = resp.next_continuation_token().map(|s| s.to_string());
+            } else {
+                break;
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// List one level under `prefix`: the keys directly under it and the
+    /// sub-prefixes ("directories") below it.
+    async fn list_level(&self, prefix: &str) -> Result<(Vec<String>, Vec<String>)> {
+        let mut keys = Vec::new();
+        let mut prefixes = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut req = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .delimiter("/");
+            if let Some(ref token) = continuation {
+                req = req.continuation_token(token);
+            }
+            let resp = req.send().await.map_err(sdk_error)?;
+            keys.extend(
+                resp.contents()
+                    .iter()
+                    .filter_map(|o| o.key())
+                    .map(str::to_string),
+            );
+            prefixes.extend(
+                resp.common_prefixes()
+                    .iter()
+                    .filter_map(|p| p.prefix())
+                    .map(str::to_string),
+            );
+            if resp.is_truncated().unwrap_or(false) {
+                continuation = resp.next_continuation_token().map(|s| s.to_string());
+            } else {
+                break;
+            }
+        }
+        Ok((keys, prefixes))
+    }
+
+    /// Date partitions under `agent_prefix` that can hold entries between
+    /// `since` and `until`, newest first.
+    async fn log_partitions(
+        &self,
+        agent_prefix: &str,
+        years: Vec<String>,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<Vec<String>> {
+        // A bounded range names its partitions without listing them
+        if let Some(since) = since {
+            let until = until.unwrap_or_else(|| {
+                // Allow for writers whose clocks run ahead
+                Utc::now().date_naive() + Days::new(1)
+            });
+            if until.signed_duration_since(since).num_days() <= MAX_ENUMERATED_PARTITIONS {
+                return Ok(day_partitions(since, until)
+                    .into_iter()
+                    .map(|day| format!("{agent_prefix}{day}"))
+                    .collect());
+            }
+        }
+
+        let mut days = Vec::new();
+        for year in prune_partitions(agent_prefix, years, since, until) {
+            let months = self.list_level(&year).await?.1;
+            for month in prune_partitions(agent_prefix, months, since, until) {
+                let month_days = self.list_level(&month).await?.1;
+                days.extend(prune_partitions(agent_prefix, month_days, since, until));
+            }
+        }
+        Ok(days)
+    }
+
+    /// Matching log entries of one agent, newest first, stopping once
+    /// enough are found to fill `filter.limit` after skipping `filter.offset`.
+    async fn query_agent_logs(
+        &self,
+        agent_prefix: &str,
+        filter: &LogFilter,
+    ) -> Result<Vec<LogEntry>> {
+        let since = filter.since.as_deref().and_then(utc_date);
+        let until = filter.until.as_deref().and_then(utc_date);
+        let limit = filter.limit.map_or(usize::MAX, |limit| {
+            limit.saturating_add(filter.offset.unwrap_or(0))
+        });
+
+        // Keys directly under the agent prefix predate partitioning
+        let (legacy_keys, years) = self.list_level(agent_prefix).await?;
+        let partitions = self
+            .log_partitions(agent_prefix, years, since, until)
+            .await?;
+
+        let mut entries = Vec::new();
+        for partition in partitions {
+            let mut keys = self.list_level(&partition).await?.0;
+            // Keys start with the timestamp, so they sort newest last
+            keys.sort_unstable_by(|a, b| b.cmp(a));
+            for key in keys {
+                if let Some(entry) = self.read_log_entry(&key).await? {
+                    if filter.matches(&entry) {
+                        entries.push(entry);
+                        if entries.len() >= limit {
+                            return Ok(entries);
+                        }
+                    }
+                }
+            }
+        }
+        for key in legacy_keys {
+            if let Some(entry) = self.read_log_entry(&key).await? {
+                if filter.matches(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Delete `keys` in `DeleteObjects` requests of up to 1,000 keys,
+    /// returning how many were removed.
+    async fn delete_keys(&self, keys: &[String]) -> Result<usize> {
+        let mut removed = 0;
+        for chunk in keys.chunks(DELETE_BATCH_SIZE) {
+            let objects = chunk
+                .iter()
+                .map(|key| {
+                    aws_sdk_s3::types::ObjectIdentifier::builder()
+                        .key(key)
+                        .build()
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| AgitError::storage(e.to_string()))?;
+            let delete = aws_sdk_s3::types::Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()
+                .map_err(|e| AgitError::storage(e.to_string()))?;
+            let output = self
+                .client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+            // Quiet mode only reports the keys that failed
+            if let Some(error) = output.errors().first() {
+                return Err(AgitError::storage(format!(
+                    "failed to delete {} of {} objects, first {}: {}",
+                    output.errors().len(),
+                    chunk.len(),
+                    error.key().unwrap_or_default(),
+                    error.message().or(error.code()).unwrap_or("unknown error"),
+                )));
+            }
+            removed += chunk.len();
+        }
+        Ok(removed)
+    }
+
+    /// Download and parse a log entry object, skipping unreadable ones.
+    async fn read_log_entry(&self, key: &str) -> Result<Option<LogEntry>> {
+        let Some((raw, metadata, compressed)) = self.get_raw_object(key).await? else {
+            return Ok(None);
+        };
+        let raw = match Envelope::from_metadata(metadata.as_ref())? {
+            Some(envelope) => self.open_body(raw, &envelope).await?,
+            None => raw,
+        };
+        let Ok(bytes) = Self::maybe_decompress(raw, compressed) else {
+            return Ok(None);
+        };
+        Ok(serde_json::from_slice(&bytes).ok())
+    }
+
     /// Check whether a key exists using a cheap HEAD request.
     async fn key_exists(&self, key: &str) -> Result<bool> {
         match self
@@ -173,37 +1337,46 @@ impl S3Storage {
         {
             Ok(_) => Ok(true),
             Err(e) => {
+                let retryable = is_transient(&e);
                 let service_err = e.into_service_error();
                 if service_err.is_not_found() {
                     Ok(false)
                 } else {
-                    Err(AgitError::Storage(service_err.to_string()))
+                    Err(AgitError::Storage {
+                        message: service_err.to_string(),
+                        retryable,
+                    })
                 }
             }
         }
     }
 
-    /// Compress `data` with zstd (level 3) if it exceeds the threshold.
-    /// Returns `(possibly_compressed_bytes, was_compressed)`.
-    fn maybe_compress(data: &[u8]) -> Result<(Vec<u8>, bool)> {
-        if data.len() >= COMPRESS_THRESHOLD {
-            let compressed = zstd::stream::encode_all(data, 3)
-                .map_err(|e| AgitError::Storage(format!("zstd compress: {e}")))?;
-            Ok((compressed, true))
-        } else {
-            Ok((data.to_vec(), false))
-        }
-    }
-
     /// Decompress `data` with zstd if `compressed` is true.
     fn maybe_decompress(data: Vec<u8>, compressed: bool) -> Result<Vec<u8>> {
         if compressed {
             zstd::stream::decode_all(data.as_slice())
-                .map_err(|e| AgitError::Storage(format!("zstd decompress: {e}")))
+                .map_err(|e| AgitError::storage(format!("zstd decompress: {e}")))
         } else {
             Ok(data)
         }
     }
+
+    /// Whether a body of `len` bytes should be compressed.
+    fn should_compress(&self, len: usize) -> bool {
+        self.options.compression == CompressionMode::Zstd && len >= self.options.threshold
+    }
+
+    /// Compress `data` with zstd if the options call for it.
+    /// Returns `(possibly_compressed_bytes, was_compressed)`.
+    fn maybe_compress(&self, data: &[u8]) -> Result<(Vec<u8>, bool)> {
+        if self.should_compress(data.len()) {
+            let compressed = zstd::stream::encode_all(data, self.options.zstd_level)
+                .map_err(|e| AgitError::storage(format!("zstd compress: {e}")))?;
+            Ok((compressed, true))
+        } else {
+            Ok((data.to_vec(), false))
+        }
+    }
 }
 
 #[cfg(feature = "s3")]
@@ -217,17 +1390,18 @@ impl StorageBackend for S3Storage {
             .bucket(&self.bucket)
             .send()
             .await
-            .map_err(|e| {
-                AgitError::Storage(format!(
+            .map_err(|e| AgitError::Storage {
+                retryable: is_transient(&e),
+                message: format!(
                     "S3 bucket '{}' not accessible: {}",
                     self.bucket,
-                    e.into_service_error()
-                ))
+                    DisplayErrorContext(&e)
+                ),
             })?;
         Ok(())
     }
 
-    async fn put_object(&self, hash: &str, _obj_type: ObjectType, data: &[u8]) -> Result<()> {
+    async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
         let key = self.object_key(hash);
 
         // Skip upload if the object already exists (content-addressed → immutable).
@@ -235,46 +1409,91 @@ impl StorageBackend for S3Storage {
             return Ok(());
         }
 
-        let (body, compressed) = Self::maybe_compress(data)?;
-        let content_type = if compressed {
-            "application/zstd"
-        } else {
-            "application/octet-stream"
-        };
-        self.put_bytes(&key, body, content_type).await
+        // Unencrypted large objects are compressed part by part as they upload
+        if self.options.encryption.is_none() && data.len() >= self.options.multipart_threshold {
+            let compress = self.should_compress(data.len());
+            let mut metadata = body_metadata(compress, None);
+            metadata.insert(TYPE_METADATA_KEY.to_string(), obj_type.to_string());
+            let content_type = body_content_type(compress, false, "application/octet-stream");
+            return self
+                .put_multipart(&key, data, compress, content_type, metadata)
+                .await;
+        }
+
+        // Compress, then encrypt
+        let (body, compressed) = self.maybe_compress(data)?;
+        let (body, envelope) = self.seal_body(body).await?;
+        let mut metadata = body_metadata(compressed, envelope.as_ref());
+        metadata.insert(TYPE_METADATA_KEY.to_string(), obj_type.to_string());
+        let content_type =
+            body_content_type(compressed, envelope.is_some(), "application/octet-stream");
+        self.put_bytes(&key, body, content_type, metadata).await
     }
 
     async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        self.download_object(&self.object_key(hash)).await
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        if len == 0 {
+            return Ok(self.has_object(hash).await?.then(Vec::new));
+        }
         let key = self.object_key(hash);
+        let range = format!("bytes={}-{}", offset, offset.saturating_add(len) - 1);
         match self
             .client
             .get_object()
             .bucket(&self.bucket)
             .key(&key)
+            .range(range)
             .send()
             .await
         {
             Ok(resp) => {
-                let compressed = resp
-                    .content_type()
-                    .map(|ct| ct == "application/zstd")
-                    .unwrap_or(false);
+                let compressed = is_zstd(
+                    resp.metadata(),
+                    resp.content_type(),
+                    resp.content_encoding(),
+                );
+                let encrypted = resp
+                    .metadata()
+                    .is_some_and(|m| m.contains_key(CSE_KEY_METADATA_KEY));
+                if compressed || encrypted {
+                    // Byte offsets refer to the decoded object, so a ranged
+                    // read of compressed or encrypted bytes is useless
+                    return Ok(self.get_object(hash).await?.map(|data| {
+                        let start = offset.min(data.len());
+                        let end = offset.saturating_add(len).min(data.len());
+                        data[start..end].to_vec()
+                    }));
+                }
                 let bytes = resp
                     .body
                     .collect()
                     .await
-                    .map_err(|e| AgitError::Storage(e.to_string()))?
+                    .map_err(|e| AgitError::transient_storage(e.to_string()))?
                     .into_bytes()
                     .to_vec();
-                let out = Self::maybe_decompress(bytes, compressed)?;
-                Ok(Some(out))
+                Ok(Some(bytes))
             }
             Err(e) => {
+                let retryable = is_transient(&e);
                 let service_err = e.into_service_error();
                 if service_err.is_no_such_key() {
                     Ok(None)
+                } else if service_err.meta().code() == Some("InvalidRange") {
+                    // Offset is past the end of the object
+                    Ok(Some(Vec::new()))
                 } else {
-                    Err(AgitError::Storage(service_err.to_string()))
+                    Err(AgitError::Storage {
+                        message: service_err.to_string(),
+                        retryable,
+                    })
                 }
             }
         }
@@ -285,20 +1504,20 @@ impl StorageBackend for S3Storage {
     }
 
     async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
-        let key = self.ref_key(name);
-        let body = serde_json::to_vec(&serde_json::json!({ "target": hash }))
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
-        self.put_bytes(&key, body, "application/json").await
+        self.write_ref(name, Some(hash), false).await
     }
 
     async fn get_ref(&self, name: &str) -> Result<Option<String>> {
         let key = self.ref_key(name);
-        match self.get_bytes(&key).await? {
-            None => Ok(None),
-            Some(bytes) => {
-                let v: serde_json::Value = serde_json::from_slice(&bytes)
-                    .map_err(|e| AgitError::Storage(e.to_string()))?;
-                Ok(v["target"].as_str().map(|s| s.to_string()))
+        match self.get_bytes_with_etag(&key).await? {
+            None => {
+                self.ref_etags().remove(name);
+                Ok(None)
+            }
+            Some((bytes, etag)) => {
+                serde_json::from_slice::<serde_json::Value>(&bytes)
+                    .map_err(|e| AgitError::storage(e.to_string()))?;
+                Ok(self.read_ref(name, &bytes, etag))
             }
         }
     }
@@ -317,24 +1536,16 @@ impl StorageBackend for S3Storage {
             if let Some(ref token) = continuation {
                 req = req.continuation_token(token);
             }
-            let resp = req
-                .send()
-                .await
-                .map_err(|e| AgitError::Storage(e.into_service_error().to_string()))?;
+            let resp = req.send().await.map_err(sdk_error)?;
 
             for obj in resp.contents() {
                 let key = obj.key().unwrap_or("");
                 // Strip prefix + "refs/" and restore `/` from `|`.
-                let raw_name = key
-                    .strip_prefix(&prefix)
-                    .unwrap_or(key)
-                    .replace('|', "/");
-
-                if let Some(bytes) = self.get_bytes(key).await? {
-                    let v: serde_json::Value =
-                        serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
-                    if let Some(target) = v["target"].as_str() {
-                        map.insert(raw_name, target.to_string());
+                let raw_name = key.strip_prefix(&prefix).unwrap_or(key).replace('|', "/");
+
+                if let Some((bytes, etag)) = self.get_bytes_with_etag(key).await? {
+                    if let Some(target) = self.read_ref(&raw_name, &bytes, etag) {
+                        map.insert(raw_name, target);
                     }
                 }
             }
@@ -349,202 +1560,792 @@ impl StorageBackend for S3Storage {
     }
 
     async fn delete_ref(&self, name: &str) -> Result<bool> {
-        let key = self.ref_key(name);
-        if !self.key_exists(&key).await? {
+        if !self.key_exists(&self.ref_key(name)).await? {
+            self.ref_etags().remove(name);
             return Ok(false);
         }
-        self.client
-            .delete_object()
-            .bucket(&self.bucket)
-            .key(&key)
-            .send()
-            .await
-            .map_err(|e| AgitError::Storage(e.into_service_error().to_string()))?;
+        self.write_ref(name, None, false).await?;
         Ok(true)
     }
 
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let mut previous = Vec::with_capacity(updates.len());
+        for update in updates {
+            // Also records the ETag the write below is conditional on
+            let current = self.get_ref(&update.name).await?;
+            update.check(current.as_deref())?;
+            previous.push(current);
+        }
+        for (i, update) in updates.iter().enumerate() {
+            if let Err(e) = self
+                .write_ref(&update.name, update.new.as_deref(), true)
+                .await
+            {
+                // Undo newest first, so a ref updated twice ends up as it began
+                for (update, old) in updates[..i].iter().zip(&previous).rev() {
+                    let _ = self.write_ref(&update.name, old.as_deref(), false).await;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     /// Append a log entry as an individual S3 object.
     ///
-    /// Key pattern: `{prefix}/logs/{agent_id}/{timestamp}_{id}.json`
+    /// Key pattern: `{prefix}logs/{agent_id}/{yyyy}/{mm}/{dd}/{timestamp}_{id}.json`,
+    /// partitioned by the entry's UTC date, with `:` in the timestamp
+    /// replaced by `-`. An entry whose timestamp does not parse goes
+    /// directly under `logs/{agent_id}/`.
     ///
     /// Each entry is its own object, making concurrent writes fully atomic –
-    /// no read-modify-write race.  Optional zstd compression is applied when
-    /// `self.compress` is true.
+    /// no read-modify-write race.  Entries are zstd-compressed and encrypted
+    /// per the storage's `S3Options`.
     async fn append_log(&self, entry: &LogEntry) -> Result<()> {
-        let key = format!(
-            "{}logs/{}/{}_{}.json",
-            self.prefix,
-            entry.agent_id,
-            entry.timestamp.replace(':', "-"),
-            entry.id,
-        );
+        let key = self.log_key(entry);
 
-        let data = serde_json::to_vec(entry)
-            .map_err(|e| AgitError::Storage(e.to_string()))?;
+        let data = serde_json::to_vec(entry).map_err(|e| AgitError::storage(e.to_string()))?;
 
-        let body = if self.compress {
-            zstd::stream::encode_all(data.as_slice(), 3)
-                .map_err(|e| AgitError::Storage(format!("compression error: {e}")))?
-        } else {
-            data
-        };
+        let (body, compressed) = self.maybe_compress(&data)?;
+        let (body, envelope) = self.seal_body(body).await?;
+        let content_type = body_content_type(compressed, envelope.is_some(), "application/json");
+        let metadata = body_metadata(compressed, envelope.as_ref());
 
-        let content_type = if self.compress {
-            "application/zstd"
-        } else {
-            "application/json"
-        };
+        self.put_bytes(&key, body, content_type, metadata).await?;
 
-        self.put_bytes(&key, body, content_type).await?;
-
-        // Send SQS notification for real-time log streaming
-        if let (Some(queue_url), Some(sqs)) = (&self.sqs_queue_url, &self.sqs_client) {
-            let msg = serde_json::json!({
-                "event": "log_append",
-                "agent_id": &entry.agent_id,
-                "action": &entry.action,
-                "commit_hash": &entry.commit_hash,
-                "timestamp": &entry.timestamp,
-                "key": &key,
-            });
-            let _ = sqs
-                .send_message()
-                .queue_url(queue_url)
-                .message_body(msg.to_string())
-                .send()
-                .await;
+        if let Err(_e) = self.publish_log(entry, &key).await {
+            if self.sqs_strict {
+                return Err(_e);
+            }
+            #[cfg(feature = "observability")]
+            tracing::warn!(key = %key, error = %_e, "failed to publish log entry to SQS");
         }
 
         Ok(())
     }
 
-    /// Query log entries by listing per-entry S3 objects and fetching each.
-    ///
-    /// When `filter.agent_id` is set the list is scoped to that agent's
-    /// prefix; otherwise all agents are scanned.  Remaining filters (action,
-    /// level, since) are applied in-memory after deserialization.
     async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
-        let prefix = match &filter.agent_id {
-            Some(agent_id) => self.log_prefix(agent_id),
-            None => self.all_logs_prefix(),
+        let agent_prefixes = match &filter.agent_id {
+            Some(agent_id) => vec![self.log_prefix(agent_id)],
+            None => self.list_level(&self.all_logs_prefix()).await?.1,
         };
 
         let mut entries = Vec::new();
-        let mut continuation_token: Option<String> = None;
+        for agent_prefix in agent_prefixes {
+            entries.extend(self.query_agent_logs(&agent_prefix, filter).await?);
+        }
 
-        loop {
-            let mut req = self
-                .client
-                .list_objects_v2()
-                .bucket(&self.bucket)
-                .prefix(&prefix);
+        // Newest first, like the SQL backends' ORDER BY timestamp DESC
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(filter.paginate(entries))
+    }
 
-            if let Some(ref token) = continuation_token {
-                req = req.continuation_token(token);
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        let key = self.object_key(hash);
+        // DeleteObject succeeds for missing keys, so check existence first
+        if !self.key_exists(&key).await? {
+            return Ok(false);
+        }
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(sdk_error)?;
+        Ok(true)
+    }
+
+    /// Deletes in `DeleteObjects` requests of up to 1,000 keys. S3 reports
+    /// missing keys as deleted, so every key counts as removed.
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        let keys: Vec<String> = hashes.iter().map(|hash| self.object_key(hash)).collect();
+        self.delete_keys(&keys).await
+    }
+
+    /// Lists every entry key of the matching agents, then removes the
+    /// selected ones with batched `DeleteObjects` requests. Keys begin with
+    /// the entry's timestamp, so no entry has to be downloaded.
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        let agent_prefixes = match &filter.agent_id {
+            Some(agent_id) => vec![self.log_prefix(agent_id)],
+            None => self.list_level(&self.all_logs_prefix()).await?.1,
+        };
+        let before = filter.before.as_ref().map(|b| b.replace(':', "-"));
+
+        let mut doomed = Vec::new();
+        for agent_prefix in agent_prefixes {
+            let (mut keys, years) = self.list_level(&agent_prefix).await?;
+            for partition in self
+                .log_partitions(&agent_prefix, years, None, None)
+                .await?
+            {
+                keys.extend(self.list_level(&partition).await?.0);
             }
+            // Newest first by the timestamp that starts each file name
+            keys.sort_unstable_by(|a, b| log_file_name(b).cmp(log_file_name(a)));
+            doomed.extend(
+                keys.into_iter()
+                    .skip(filter.keep_last.unwrap_or(0))
+                    .filter(|key| before.as_deref().is_none_or(|b| log_file_name(key) < b)),
+            );
+        }
+        self.delete_keys(&doomed).await
+    }
 
-            let resp = req
-                .send()
-                .await
-                .map_err(|e| AgitError::Storage(e.into_service_error().to_string()))?;
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        Ok(self
+            .list_object_sizes()
+            .await?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect())
+    }
 
-            for obj in resp.contents() {
-                let key = obj.key().unwrap_or("");
-                if let Ok(Some(raw)) = self.get_raw_object(key).await {
-                    let bytes = if self.compress {
-                        zstd::stream::decode_all(raw.as_slice()).unwrap_or(raw)
-                    } else {
-                        raw
-                    };
-                    if let Ok(entry) = serde_json::from_slice::<LogEntry>(&bytes) {
-                        // Apply filters
-                        if let Some(ref action) = filter.action {
-                            if &entry.action != action {
-                                continue;
-                            }
-                        }
-                        if let Some(ref level) = filter.level {
-                            if &entry.level != level {
-                                continue;
-                            }
-                        }
-                        if let Some(ref since) = filter.since {
-                            if entry.timestamp < *since {
-                                continue;
-                            }
-                        }
-                        entries.push(entry);
-                    }
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        let key = self.object_key(hash);
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(resp) => {
+                let recorded = resp.metadata().and_then(|m| m.get(TYPE_METADATA_KEY));
+                let obj_type = self
+                    .resolve_object_type(hash, recorded.map(String::as_str))
+                    .await?;
+                let created_at = resp
+                    .last_modified()
+                    .and_then(|t| chrono::DateTime::from_timestamp(t.secs(), t.subsec_nanos()));
+                Ok(Some(ObjectStat {
+                    size: resp.content_length().unwrap_or(0) as u64,
+                    obj_type,
+                    created_at,
+                }))
+            }
+            Err(e) => {
+                let retryable = is_transient(&e);
+                let service_err = e.into_service_error();
+                if service_err.is_not_found() {
+                    Ok(None)
+                } else {
+                    Err(AgitError::Storage {
+                        message: service_err.to_string(),
+                        retryable,
+                    })
                 }
             }
+        }
+    }
 
-            if resp.is_truncated().unwrap_or(false) {
-                continuation_token = resp.next_continuation_token().map(|s| s.to_string());
+    /// Sizes come from a single LIST; types need a HEAD per object unless
+    /// the key identifies a tree.
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let mut stats = StorageStats::default();
+        for (hash, size) in self.list_object_sizes().await? {
+            let obj_type = if hash.ends_with(TREE_KEY_SUFFIX) {
+                ObjectType::Tree
             } else {
-                break;
-            }
+                match self.stat_object(&hash).await? {
+                    Some(stat) => stat.obj_type,
+                    // Deleted since the listing
+                    None => continue,
+                }
+            };
+            stats.add(obj_type, size);
         }
+        Ok(stats)
+    }
+}
 
-        // Sort by timestamp (ascending).
-        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+#[cfg(all(test, feature = "s3"))]
+mod tests {
+    use super::*;
+    use crate::types::LogLevel;
+    use std::collections::HashSet;
+
+    /// SDK config for the LocalStack at `AGIT_TEST_S3_ENDPOINT`, or `None`
+    /// to skip when it is unset.
+    async fn localstack_config() -> Option<aws_config::SdkConfig> {
+        let endpoint = std::env::var("AGIT_TEST_S3_ENDPOINT").ok()?;
+        let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .region(aws_config::Region::new("us-east-1"))
+            .credentials_provider(aws_sdk_s3::config::Credentials::new(
+                "test",
+                "test",
+                None,
+                None,
+                "localstack",
+            ))
+            .load()
+            .await;
+        Some(config)
+    }
+
+    /// Storage under `prefix` on LocalStack S3, in bucket
+    /// `AGIT_TEST_S3_BUCKET` (default `agit-test`, created if missing), or
+    /// `None` to skip when LocalStack is not configured.
+    async fn localstack_storage(prefix: &str) -> Option<S3Storage> {
+        let config = localstack_config().await?;
+        let bucket =
+            std::env::var("AGIT_TEST_S3_BUCKET").unwrap_or_else(|_| "agit-test".to_string());
+        let s3_config = aws_sdk_s3::config::Builder::from(&config)
+            .force_path_style(true)
+            .build();
+        let client = S3Client::from_conf(s3_config);
+        let _ = client.create_bucket().bucket(&bucket).send().await;
+        Some(S3Storage {
+            client,
+            bucket,
+            prefix: prefix.to_string(),
+            sqs_queue_url: None,
+            sqs_client: None,
+            sqs_strict: false,
+            kms_client: None,
+            options: S3Options::default(),
+            ref_etags: Mutex::new(HashMap::new()),
+        })
+    }
 
-        // Apply limit.
-        if let Some(limit) = filter.limit {
-            entries.truncate(limit);
+    /// Storage with the given options whose client is never used to reach
+    /// S3, for exercising the local parts of the backend.
+    fn offline_storage(options: S3Options) -> S3Storage {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_config::BehaviorVersion::latest())
+            .region(aws_config::Region::new("us-east-1"))
+            .build();
+        S3Storage {
+            client: S3Client::from_conf(config),
+            bucket: "offline".to_string(),
+            prefix: String::new(),
+            sqs_queue_url: None,
+            sqs_client: None,
+            sqs_strict: false,
+            kms_client: None,
+            options,
+            ref_etags: Mutex::new(HashMap::new()),
         }
+    }
 
-        Ok(entries)
+    fn local_key(byte: u8) -> S3Options {
+        S3Options {
+            encryption: Some(MasterKey::Local([byte; 32])),
+            ..Default::default()
+        }
     }
 
-    async fn delete_object(&self, hash: &str) -> Result<bool> {
-        let key = self.object_key(hash);
-        self.client
-            .delete_object()
-            .bucket(&self.bucket)
-            .key(&key)
+    /// Write a key the way releases before the `agit-encoding` metadata
+    /// did: compression is only signalled by the content type.
+    async fn put_legacy(storage: &S3Storage, key: &str, body: Vec<u8>, content_type: &str) {
+        storage
+            .client
+            .put_object()
+            .bucket(&storage.bucket)
+            .key(key)
+            .body(aws_sdk_s3::primitives::ByteStream::from(body))
+            .content_type(content_type)
             .send()
             .await
-            .map_err(|e| AgitError::Storage(e.into_service_error().to_string()))?;
-        Ok(true)
+            .unwrap();
     }
 
-    async fn list_objects(&self) -> Result<Vec<String>> {
-        let prefix = format!("{}objects/", self.prefix);
-        let mut hashes = Vec::new();
-        let mut continuation_token: Option<String> = None;
+    #[tokio::test]
+    async fn test_concurrent_update_refs() {
+        let prefix = format!("test-{}/", uuid::Uuid::new_v4());
+        let Some(storage) = localstack_storage(&prefix).await else {
+            return;
+        };
+        storage.set_ref("main", "initial").await.unwrap();
 
-        loop {
-            let mut req = self
-                .client
-                .list_objects_v2()
-                .bucket(&self.bucket)
-                .prefix(&prefix);
+        // Separate instances, as separate processes would be
+        let mut tasks = Vec::new();
+        for i in 0..20 {
+            let writer = localstack_storage(&prefix).await.unwrap();
+            tasks.push(tokio::spawn(async move {
+                let read = writer.get_ref("main").await.unwrap().unwrap();
+                let target = format!("writer-{i}");
+                writer
+                    .update_refs(&[RefUpdate::set("main", &target).expecting(&read)])
+                    .await
+                    .map(|()| (read, target))
+            }));
+        }
 
-            if let Some(ref token) = continuation_token {
-                req = req.continuation_token(token);
+        let mut written = Vec::new();
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(update) => written.push(update),
+                Err(AgitError::ConcurrentUpdate { name }) => assert_eq!(name, "main"),
+                Err(e) => panic!("unexpected error: {e}"),
             }
+        }
+
+        let final_target = storage.get_ref("main").await.unwrap().unwrap();
+        assert!(written.iter().any(|(_, target)| *target == final_target));
+        // Successful writes form a chain: two updates from the same read
+        // value would mean one silently overwrote the other
+        let reads: HashSet<_> = written.iter().map(|(read, _)| read).collect();
+        assert_eq!(reads.len(), written.len());
+    }
+    #[tokio::test]
+    async fn test_append_log_publishes_to_sqs() {
+        let prefix = format!("test-{}/", uuid::Uuid::new_v4());
+        let Some(mut storage) = localstack_storage(&prefix).await else {
+            return;
+        };
+        let config = localstack_config().await.unwrap();
+        let sqs = SqsClient::new(&config);
+        let queue_url = sqs
+            .create_queue()
+            .queue_name(format!("agit-test-{}", uuid::Uuid::new_v4()))
+            .send()
+            .await
+            .unwrap()
+            .queue_url()
+            .unwrap()
+            .to_string();
+        storage.sqs_queue_url = Some(queue_url.clone());
+        storage.sqs_client = Some(sqs.clone());
+        let storage = storage.with_sqs_strict(true);
+
+        let entries: Vec<LogEntry> = (0..2)
+            .map(|i| LogEntry {
+                id: format!("log-{i}"),
+                timestamp: format!("2026-01-0{}T00:00:00Z", i + 1),
+                agent_id: "agent-1".to_string(),
+                action: "commit".to_string(),
+                message: "m".to_string(),
+                commit_hash: Some(format!("hash-{i}")),
+                details: None,
+                level: LogLevel::Info,
+            })
+            .collect();
+        for entry in &entries {
+            storage.append_log(entry).await.unwrap();
+        }
 
-            let resp = req
+        let mut messages = Vec::new();
+        while messages.len() < entries.len() {
+            let resp = sqs
+                .receive_message()
+                .queue_url(&queue_url)
+                .max_number_of_messages(10)
+                .wait_time_seconds(1)
                 .send()
                 .await
-                .map_err(|e| AgitError::Storage(e.into_service_error().to_string()))?;
-
-            for obj in resp.contents() {
-                if let Some(key) = obj.key() {
-                    // Extract hash from key: prefix/objects/<hash>
-                    if let Some(hash) = key.strip_prefix(&prefix) {
-                        hashes.push(hash.to_string());
-                    }
-                }
+                .unwrap();
+            let received = resp.messages();
+            assert!(!received.is_empty(), "queue drained early");
+            for message in received {
+                let body: serde_json::Value =
+                    serde_json::from_str(message.body().unwrap()).unwrap();
+                messages.push(body);
             }
+        }
+        messages.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
 
-            if resp.is_truncated().unwrap_or(false) {
-                continuation_token = resp.next_continuation_token().map(|s| s.to_string());
-            } else {
-                break;
-            }
+        for (message, entry) in messages.iter().zip(&entries) {
+            assert_eq!(message["event"], "log_append");
+            assert_eq!(message["id"], entry.id.as_str());
+            assert_eq!(message["agent_id"], "agent-1");
+            assert_eq!(message["action"], "commit");
+            assert_eq!(message["level"], "info");
+            assert_eq!(
+                message["commit_hash"],
+                entry.commit_hash.as_deref().unwrap()
+            );
+            assert_eq!(message["timestamp"], entry.timestamp.as_str());
+            let key = message["key"].as_str().unwrap();
+            assert!(key.starts_with(&format!("{prefix}logs/agent-1/")));
+            assert!(storage.key_exists(key).await.unwrap());
+        }
+    }
+    #[test]
+    fn test_log_partitions_across_month_boundary() {
+        assert_eq!(
+            log_partition("2026-01-31T23:30:00-02:00").as_deref(),
+            Some("2026/02/01/")
+        );
+        assert_eq!(log_partition("not a timestamp"), None);
+
+        let since = NaiveDate::from_ymd_opt(2026, 1, 30).unwrap();
+        let until = NaiveDate::from_ymd_opt(2026, 2, 2).unwrap();
+        assert_eq!(
+            day_partitions(since, until),
+            vec!["2026/02/02/", "2026/02/01/", "2026/01/31/", "2026/01/30/"]
+        );
+
+        assert_eq!(
+            partition_span("2024/02/"),
+            Some((
+                NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+            ))
+        );
+
+        let prefix = "logs/a/";
+        let listed = |paths: &[&str]| paths.iter().map(|p| format!("{prefix}{p}")).collect();
+        let since = NaiveDate::from_ymd_opt(2025, 12, 31);
+        let until = NaiveDate::from_ymd_opt(2026, 1, 1);
+        assert_eq!(
+            prune_partitions(
+                prefix,
+                listed(&["2024/", "2025/", "2026/", "2027/", "tmp/"]),
+                since,
+                until
+            ),
+            vec!["logs/a/2026/", "logs/a/2025/"]
+        );
+        assert_eq!(
+            prune_partitions(prefix, listed(&["2025/11/", "2025/12/"]), since, until),
+            vec!["logs/a/2025/12/"]
+        );
+        assert_eq!(
+            prune_partitions(
+                prefix,
+                listed(&["2026/01/01/", "2026/01/02/"]),
+                since,
+                until
+            ),
+            vec!["logs/a/2026/01/01/"]
+        );
+        // Unbounded: every partition, newest first
+        assert_eq!(
+            prune_partitions(prefix, listed(&["2025/", "2026/"]), None, None),
+            vec!["logs/a/2026/", "logs/a/2025/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_log_queries() {
+        let prefix = format!("test-{}/", uuid::Uuid::new_v4());
+        let Some(storage) = localstack_storage(&prefix).await else {
+            return;
+        };
+        let entry = |id: &str, timestamp: &str| LogEntry {
+            id: id.to_string(),
+            timestamp: timestamp.to_string(),
+            agent_id: "agent-1".to_string(),
+            action: "commit".to_string(),
+            message: "m".to_string(),
+            commit_hash: None,
+            details: None,
+            level: LogLevel::Info,
+        };
+        for (id, timestamp) in [
+            ("1", "2026-01-30T12:00:00Z"),
+            ("2", "2026-01-31T23:00:00Z"),
+            ("3", "2026-02-01T01:00:00Z"),
+            ("4", "2026-02-03T12:00:00Z"),
+        ] {
+            storage.append_log(&entry(id, timestamp)).await.unwrap();
+        }
+        // An entry in the pre-partitioning layout
+        let legacy = entry("0", "2025-12-01T00:00:00Z");
+        let legacy_key = format!("{prefix}logs/agent-1/2025-12-01T00-00-00Z_0.json");
+        let data =
+            zstd::stream::encode_all(serde_json::to_vec(&legacy).unwrap().as_slice(), 3).unwrap();
+        put_legacy(&storage, &legacy_key, data, "application/zstd").await;
+
+        let ids = |logs: Vec<LogEntry>| logs.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        let query = |filter: LogFilter| {
+            let storage = &storage;
+            async move { ids(storage.query_logs(&filter).await.unwrap()) }
+        };
+
+        assert_eq!(
+            query(LogFilter::default()).await,
+            vec!["4", "3", "2", "1", "0"]
+        );
+        assert_eq!(
+            query(LogFilter {
+                since: Some("2026-01-31T00:00:00Z".to_string()),
+                until: Some("2026-02-01T23:59:59Z".to_string()),
+                ..Default::default()
+            })
+            .await,
+            vec!["3", "2"]
+        );
+        assert_eq!(
+            query(LogFilter {
+                agent_id: Some("agent-1".to_string()),
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await,
+            vec!["4", "3"]
+        );
+        assert_eq!(
+            query(LogFilter {
+                until: Some("2026-01-01T00:00:00Z".to_string()),
+                ..Default::default()
+            })
+            .await,
+            vec!["0"]
+        );
+    }
+    #[test]
+    fn test_content_range_total() {
+        assert_eq!(content_range_total("bytes 0-99/1234"), Some(1234));
+        assert_eq!(content_range_total("bytes 0-99/*"), None);
+    }
+
+    #[tokio::test]
+    async fn test_large_object_multipart_round_trip() {
+        let prefix = format!("test-{}/", uuid::Uuid::new_v4());
+        let Some(storage) = localstack_storage(&prefix).await else {
+            return;
+        };
+        // Incompressible, so the upload really spans several parts
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let data: Vec<u8> = (0..64 * 1024 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        storage
+            .put_object("large", ObjectType::Blob, &data)
+            .await
+            .unwrap();
+        let read = storage.get_object("large").await.unwrap().unwrap();
+        assert!(read == data, "round-tripped object differs");
+        let stat = storage.stat_object("large").await.unwrap().unwrap();
+        assert_eq!(stat.obj_type, ObjectType::Blob);
+
+        // Nothing is left behind as an incomplete upload
+        let uploads = storage
+            .client
+            .list_multipart_uploads()
+            .bucket(&storage.bucket)
+            .prefix(&prefix)
+            .send()
+            .await
+            .unwrap();
+        assert!(uploads.uploads().is_empty());
+    }
+    #[test]
+    fn test_is_zstd() {
+        let meta = |encoding: &str| {
+            HashMap::from([(ENCODING_METADATA_KEY.to_string(), encoding.to_string())])
+        };
+        // Metadata wins over the headers
+        assert!(is_zstd(Some(&meta("zstd")), Some("application/json"), None));
+        assert!(!is_zstd(
+            Some(&meta("identity")),
+            Some("application/zstd"),
+            None
+        ));
+        // Fallbacks for objects without the metadata
+        assert!(is_zstd(None, Some("application/zstd"), None));
+        assert!(is_zstd(
+            Some(&HashMap::new()),
+            Some("application/json"),
+            Some("zstd")
+        ));
+        assert!(!is_zstd(None, Some("application/octet-stream"), None));
+    }
+
+    #[tokio::test]
+    async fn test_compression_modes_and_legacy_objects() {
+        let prefix = format!("test-{}/", uuid::Uuid::new_v4());
+        let Some(mut storage) = localstack_storage(&prefix).await else {
+            return;
+        };
+        let large = vec![b'a'; 4096];
+        let entry = |id: &str| LogEntry {
+            id: id.to_string(),
+            timestamp: "2026-03-01T00:00:00Z".to_string(),
+            agent_id: "agent-1".to_string(),
+            action: "commit".to_string(),
+            message: "m".repeat(2000),
+            commit_hash: None,
+            details: None,
+            level: LogLevel::Info,
+        };
+
+        // Objects and log entries as written before the encoding metadata
+        let compressed = zstd::stream::encode_all(large.as_slice(), 3).unwrap();
+        put_legacy(
+            &storage,
+            &storage.object_key("old-zstd"),
+            compressed,
+            "application/zstd",
+        )
+        .await;
+        put_legacy(
+            &storage,
+            &storage.object_key("old-raw"),
+            b"raw".to_vec(),
+            "application/octet-stream",
+        )
+        .await;
+        let old = serde_json::to_vec(&entry("old")).unwrap();
+        let old_key = format!("{prefix}logs/agent-1/2026/03/01/2026-03-01T00-00-00Z_old.json");
+        put_legacy(
+            &storage,
+            &old_key,
+            zstd::stream::encode_all(old.as_slice(), 3).unwrap(),
+            "application/zstd",
+        )
+        .await;
+        assert_eq!(
+            storage.get_object("old-zstd").await.unwrap().unwrap(),
+            large
+        );
+        assert_eq!(
+            storage.get_object("old-raw").await.unwrap().unwrap(),
+            b"raw"
+        );
+
+        // Compressed by default, recorded in the metadata
+        storage
+            .put_object("new-zstd", ObjectType::Blob, &large)
+            .await
+            .unwrap();
+        let head = storage
+            .client
+            .head_object()
+            .bucket(&storage.bucket)
+            .key(storage.object_key("new-zstd"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            head.metadata().and_then(|m| m.get(ENCODING_METADATA_KEY)),
+            Some(&"zstd".to_string())
+        );
+        assert!((head.content_length().unwrap() as usize) < large.len());
+
+        // Disabled: stored as is, and everything stays readable
+        storage.options.compression = CompressionMode::Disabled;
+        storage
+            .put_object("new-raw", ObjectType::Blob, &large)
+            .await
+            .unwrap();
+        storage.append_log(&entry("new")).await.unwrap();
+        let stat = storage.stat_object("new-raw").await.unwrap().unwrap();
+        assert_eq!(stat.size, large.len() as u64);
+        for hash in ["old-zstd", "new-zstd", "new-raw"] {
+            assert_eq!(storage.get_object(hash).await.unwrap().unwrap(), large);
         }
+        let ids: Vec<String> = storage
+            .query_logs(&LogFilter::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"old".to_string()) && ids.contains(&"new".to_string()));
+    }
+    #[tokio::test]
+    async fn test_envelope_encryption_with_local_key() {
+        let storage = offline_storage(local_key(7));
+        let plaintext = b"agent state".to_vec();
+        let (sealed, envelope) = storage.seal_body(plaintext.clone()).await.unwrap();
+        let envelope = envelope.unwrap();
+        assert!(!envelope.kms);
+        assert_ne!(sealed, plaintext);
+
+        // The envelope survives a trip through object metadata
+        let metadata = body_metadata(false, Some(&envelope));
+        assert_eq!(metadata[CSE_WRAP_METADATA_KEY], "local");
+        let read = Envelope::from_metadata(Some(&metadata)).unwrap().unwrap();
+        assert_eq!(
+            storage.open_body(sealed.clone(), &read).await.unwrap(),
+            plaintext
+        );
+
+        // Every body gets its own data key and nonce
+        let (again, _) = storage.seal_body(plaintext.clone()).await.unwrap();
+        assert_ne!(again, sealed);
+
+        // Without the right master key the body stays sealed
+        let wrong = offline_storage(local_key(8));
+        assert!(matches!(
+            wrong.open_body(sealed.clone(), &read).await,
+            Err(AgitError::EncryptionError(_))
+        ));
+        let none = offline_storage(S3Options::default());
+        assert!(none.open_body(sealed, &read).await.is_err());
+        assert!(Envelope::from_metadata(Some(&body_metadata(true, None)))
+            .unwrap()
+            .is_none());
+
+        // Unencrypted storage leaves bodies alone
+        let (body, envelope) = none.seal_body(plaintext.clone()).await.unwrap();
+        assert_eq!(body, plaintext);
+        assert!(envelope.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_side_encryption_round_trip() {
+        let prefix = format!("test-{}/", uuid::Uuid::new_v4());
+        let Some(mut storage) = localstack_storage(&prefix).await else {
+            return;
+        };
+        storage.options.encryption = Some(MasterKey::Local([7; 32]));
+        let data = serde_json::to_vec(&serde_json::json!({"secret": "x".repeat(4096)})).unwrap();
+        storage
+            .put_object("sealed", ObjectType::Blob, &data)
+            .await
+            .unwrap();
+        let entry = LogEntry {
+            id: "1".to_string(),
+            timestamp: "2026-03-01T00:00:00Z".to_string(),
+            agent_id: "agent-1".to_string(),
+            action: "commit".to_string(),
+            message: "secret message".to_string(),
+            commit_hash: None,
+            details: None,
+            level: LogLevel::Info,
+        };
+        storage.append_log(&entry).await.unwrap();
+
+        assert_eq!(storage.get_object("sealed").await.unwrap().unwrap(), data);
+        assert_eq!(
+            storage
+                .get_object_range("sealed", 2, 6)
+                .await
+                .unwrap()
+                .unwrap(),
+            &data[2..8]
+        );
+        let logs = storage.query_logs(&LogFilter::default()).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "secret message");
+
+        // Compressed, then encrypted: the stored body is neither plaintext
+        // nor a zstd frame
+        let (stored, metadata, compressed) = storage
+            .get_raw_object(&storage.object_key("sealed"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(compressed);
+        assert!(stored.len() < data.len());
+        assert!(zstd::stream::decode_all(stored.as_slice()).is_err());
+        assert_eq!(
+            metadata
+                .unwrap()
+                .get(CSE_WRAP_METADATA_KEY)
+                .map(String::as_str),
+            Some("local")
+        );
 
-        Ok(hashes)
+        // A reader without the master key cannot decrypt
+        storage.options.encryption = None;
+        assert!(matches!(
+            storage.get_object("sealed").await,
+            Err(AgitError::EncryptionError(_))
+        ));
     }
 }
@@ -1,32 +1,236 @@
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use futures_util::FutureExt;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tokio_rusqlite::Connection;
 
-use super::{LogEntry, LogFilter, StorageBackend};
+use super::{
+    BackupReport, CompactReport, LockGuard, LogDeleteFilter, LogEntry, LogFilter, ObjectStat,
+    RefUpdate, StorageBackend, StorageStats,
+};
 use crate::error::{AgitError, Result};
 use crate::types::ObjectType;
 
+/// Most hashes bound in one `DELETE ... IN (...)`, below SQLite's
+/// historical limit of 999 parameters per statement.
+const DELETE_BATCH_SIZE: usize = 900;
+
+/// How often `acquire_lock` retries a lock held by someone else.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Millisecond timestamps for the `locks` table, which `datetime('now')`
+/// would truncate to the second.
+const LOCK_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%f";
+
+/// How many times a held lock is renewed per lease.
+const LOCK_RENEWALS_PER_LEASE: u32 = 3;
+
+/// Pages copied per backup step; writers can run between steps.
+const BACKUP_PAGES_PER_STEP: i32 = 256;
+
+/// Pause between backup steps.
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(1);
+
+/// Tuning for `SqliteStorage`.
+#[derive(Debug, Clone)]
+pub struct SqliteOptions {
+    /// Read-only connections serving reads alongside the single write
+    /// connection. Zero sends reads through the write connection, as does
+    /// an in-memory database, which other connections cannot see.
+    /// Default 4.
+    pub read_pool_size: usize,
+    /// How long a lock taken with `acquire_lock` lasts without being
+    /// renewed. Its holder renews it while it is held, so only the lock of
+    /// a process that died can expire and be taken over. Default one
+    /// minute.
+    pub lock_lease: Duration,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            read_pool_size: 4,
+            lock_lease: Duration::from_secs(60),
+        }
+    }
+}
+
 /// SQLite-backed storage using bundled SQLite (zero system dependencies).
+///
+/// Writes are serialized through one connection. Reads go round-robin to a
+/// pool of read-only connections, which WAL mode lets run alongside the
+/// writer and each other.
 pub struct SqliteStorage {
     conn: Connection,
+    readers: Vec<Connection>,
+    next_reader: AtomicUsize,
+    path: String,
+    lock_lease: Duration,
 }
 
 impl SqliteStorage {
     pub async fn new(path: &str) -> Result<Self> {
+        Self::with_options(path, SqliteOptions::default()).await
+    }
+
+    pub async fn with_options(path: &str, options: SqliteOptions) -> Result<Self> {
         let conn = if path == ":memory:" {
-            Connection::open_in_memory()
-                .await
-                .map_err(|e: rusqlite::Error| AgitError::Storage(e.to_string()))?
+            Connection::open_in_memory().await.map_err(sqlite_error)?
         } else {
-            Connection::open(path)
-                .await
-                .map_err(|e: rusqlite::Error| AgitError::Storage(e.to_string()))?
+            Connection::open(path).await.map_err(sqlite_error)?
         };
 
-        let storage = SqliteStorage { conn };
+        let mut storage = SqliteStorage {
+            conn,
+            readers: Vec::new(),
+            next_reader: AtomicUsize::new(0),
+            path: path.to_string(),
+            lock_lease: options.lock_lease,
+        };
         storage.initialize().await?;
+
+        // Opened after initialize so the file and its tables exist.
+        if path != ":memory:" {
+            for _ in 0..options.read_pool_size {
+                storage.readers.push(open_reader(path).await?);
+            }
+        }
         Ok(storage)
     }
+
+    /// The connection for the next read: a pooled reader, or the write
+    /// connection if there are none.
+    fn reader(&self) -> &Connection {
+        if self.readers.is_empty() {
+            return &self.conn;
+        }
+        let i = self.next_reader.fetch_add(1, Ordering::Relaxed);
+        &self.readers[i % self.readers.len()]
+    }
+
+    /// Replace this database's contents with the backup at `path`.
+    ///
+    /// Other connections to the same file see the restored contents on
+    /// their next read.
+    pub async fn restore_from(&self, path: &str) -> Result<BackupReport> {
+        let path = path.to_string();
+        let start = Instant::now();
+        let pages = self
+            .conn
+            .call(move |conn| -> std::result::Result<u64, rusqlite::Error> {
+                let source = rusqlite::Connection::open_with_flags(
+                    &path,
+                    rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+                )?;
+                copy_database(&source, conn)
+            })
+            .await
+            .map_err(call_error)?;
+        Ok(BackupReport {
+            pages,
+            duration: start.elapsed(),
+        })
+    }
+}
+
+/// Map a SQLite error, marking a busy or locked database as retryable.
+fn sqlite_error(e: rusqlite::Error) -> AgitError {
+    let busy = matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    );
+    AgitError::Storage {
+        message: e.to_string(),
+        retryable: busy,
+    }
+}
+
+/// Map an error from a `Connection::call`.
+fn call_error(e: tokio_rusqlite::Error) -> AgitError {
+    match e {
+        tokio_rusqlite::Error::Error(e) => sqlite_error(e),
+        e => AgitError::storage(e.to_string()),
+    }
+}
+
+/// Open a read-only connection to the database file at `path`.
+async fn open_reader(path: &str) -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        path,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .await
+    .map_err(sqlite_error)?;
+    conn.call(|conn| -> std::result::Result<(), rusqlite::Error> {
+        conn.execute_batch("PRAGMA busy_timeout = 5000;")
+    })
+    .await
+    .map_err(call_error)?;
+    Ok(conn)
+}
+
+/// Quote `query` as a single FTS5 phrase, so it matches its words in order
+/// rather than being parsed as FTS5 query syntax.
+fn fts_phrase(query: &str) -> String {
+    format!("\"{}\"", query.replace('"', "\"\""))
+}
+
+/// The WHERE conditions shared by log queries and counts, with their
+/// positional parameters.
+fn log_conditions(filter: &LogFilter) -> (String, Vec<Box<dyn rusqlite::types::ToSql>>) {
+    let mut sql = "1=1".to_string();
+    let mut params: Vec<Box<dyn rusqlite::types::ToSql>> = Vec::new();
+
+    if let Some(ref agent_id) = filter.agent_id {
+        sql.push_str(&format!(" AND agent_id = ?{}", params.len() + 1));
+        params.push(Box::new(agent_id.clone()));
+    }
+    if let Some(ref action) = filter.action {
+        sql.push_str(&format!(" AND action = ?{}", params.len() + 1));
+        params.push(Box::new(action.clone()));
+    }
+    if let Some(ref level) = filter.level {
+        sql.push_str(&format!(" AND level = ?{}", params.len() + 1));
+        params.push(Box::new(level.to_string()));
+    }
+    if let Some(ref since) = filter.since {
+        sql.push_str(&format!(" AND timestamp >= ?{}", params.len() + 1));
+        params.push(Box::new(since.clone()));
+    }
+    if let Some(ref until) = filter.until {
+        sql.push_str(&format!(" AND timestamp <= ?{}", params.len() + 1));
+        params.push(Box::new(until.clone()));
+    }
+    if let Some(ref commit_hash) = filter.commit_hash {
+        sql.push_str(&format!(" AND commit_hash = ?{}", params.len() + 1));
+        params.push(Box::new(commit_hash.clone()));
+    }
+    if let Some(ref text) = filter.message_contains {
+        // instr is case-sensitive, unlike LIKE
+        sql.push_str(&format!(" AND instr(message, ?{}) > 0", params.len() + 1));
+        params.push(Box::new(text.clone()));
+    }
+    if let Some(query) = filter.message_query.as_ref().filter(|q| !q.is_empty()) {
+        sql.push_str(&format!(
+            " AND id IN (SELECT id FROM logs_fts WHERE logs_fts MATCH ?{})",
+            params.len() + 1
+        ));
+        params.push(Box::new(fts_phrase(query)));
+    }
+    (sql, params)
+}
+
+/// Copy `source` into `dest` with SQLite's online backup API, stepping
+/// `BACKUP_PAGES_PER_STEP` pages at a time. Returns the pages copied.
+fn copy_database(
+    source: &rusqlite::Connection,
+    dest: &mut rusqlite::Connection,
+) -> std::result::Result<u64, rusqlite::Error> {
+    let backup = rusqlite::backup::Backup::new(source, dest)?;
+    backup.run_to_completion(BACKUP_PAGES_PER_STEP, BACKUP_STEP_PAUSE, None)?;
+    Ok(backup.progress().pagecount as u64)
 }
 
 #[async_trait]
@@ -34,9 +238,11 @@ impl StorageBackend for SqliteStorage {
     async fn initialize(&self) -> Result<()> {
         self.conn
             .call(|conn| -> std::result::Result<(), rusqlite::Error> {
-                // Performance pragmas: WAL mode for concurrent reads, larger cache
+                // Performance pragmas: WAL mode for concurrent reads, larger cache.
+                // Incremental auto-vacuum only takes effect on a new database.
                 conn.execute_batch(
                     "
+                    PRAGMA auto_vacuum = INCREMENTAL;
                     PRAGMA journal_mode = WAL;
                     PRAGMA synchronous = NORMAL;
                     PRAGMA cache_size = -64000;
@@ -70,12 +276,54 @@ impl StorageBackend for SqliteStorage {
                     CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs(timestamp);
                     CREATE INDEX IF NOT EXISTS idx_logs_agent_id ON logs(agent_id);
                     CREATE INDEX IF NOT EXISTS idx_logs_action ON logs(action);
+                    CREATE INDEX IF NOT EXISTS idx_logs_agent_timestamp ON logs(agent_id, timestamp);
+                    CREATE TABLE IF NOT EXISTS locks (
+                        name TEXT PRIMARY KEY,
+                        owner TEXT NOT NULL,
+                        acquired_at TEXT NOT NULL DEFAULT (datetime('now'))
+                    );
+                    ",
+                )?;
+
+                // Full-text index over log messages and details, keyed by log
+                // id since VACUUM may renumber the rowids of `logs`
+                let fts_exists: bool = conn.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'logs_fts')",
+                    [],
+                    |row| row.get(0),
+                )?;
+                conn.execute_batch(
+                    "
+                    CREATE VIRTUAL TABLE IF NOT EXISTS logs_fts USING fts5(
+                        id UNINDEXED,
+                        message,
+                        details
+                    );
+                    CREATE TRIGGER IF NOT EXISTS logs_fts_insert AFTER INSERT ON logs BEGIN
+                        INSERT INTO logs_fts (id, message, details)
+                        VALUES (new.id, new.message, CAST(new.details AS TEXT));
+                    END;
+                    CREATE TRIGGER IF NOT EXISTS logs_fts_delete AFTER DELETE ON logs BEGIN
+                        DELETE FROM logs_fts WHERE id = old.id;
+                    END;
+                    CREATE TRIGGER IF NOT EXISTS logs_fts_update AFTER UPDATE ON logs BEGIN
+                        DELETE FROM logs_fts WHERE id = old.id;
+                        INSERT INTO logs_fts (id, message, details)
+                        VALUES (new.id, new.message, CAST(new.details AS TEXT));
+                    END;
                     ",
                 )?;
+                if !fts_exists {
+                    conn.execute(
+                        "INSERT INTO logs_fts (id, message, details)
+                         SELECT id, message, CAST(details AS TEXT) FROM logs",
+                        [],
+                    )?;
+                }
                 Ok(())
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
@@ -92,35 +340,65 @@ impl StorageBackend for SqliteStorage {
                 Ok(())
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
         let hash = hash.to_string();
 
-        self.conn
-            .call(move |conn| -> std::result::Result<Option<Vec<u8>>, rusqlite::Error> {
-                let mut stmt = conn.prepare("SELECT data FROM objects WHERE hash = ?1")?;
-                let result = stmt
-                    .query_row(rusqlite::params![hash], |row| row.get::<_, Vec<u8>>(0))
-                    .optional()?;
-                Ok(result)
-            })
+        self.reader()
+            .call(
+                move |conn| -> std::result::Result<Option<Vec<u8>>, rusqlite::Error> {
+                    let mut stmt = conn.prepare("SELECT data FROM objects WHERE hash = ?1")?;
+                    let result = stmt
+                        .query_row(rusqlite::params![hash], |row| row.get::<_, Vec<u8>>(0))
+                        .optional()?;
+                    Ok(result)
+                },
+            )
+            .await
+            .map_err(call_error)
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let hash = hash.to_string();
+        // substr() is 1-based and reads only the requested bytes of the blob
+        let start = i64::try_from(offset).unwrap_or(i64::MAX - 1) + 1;
+        let len = i64::try_from(len).unwrap_or(i64::MAX);
+
+        self.reader()
+            .call(
+                move |conn| -> std::result::Result<Option<Vec<u8>>, rusqlite::Error> {
+                    let mut stmt =
+                        conn.prepare("SELECT substr(data, ?2, ?3) FROM objects WHERE hash = ?1")?;
+                    let result = stmt
+                        .query_row(rusqlite::params![hash, start, len], |row| {
+                            row.get::<_, Vec<u8>>(0)
+                        })
+                        .optional()?;
+                    Ok(result)
+                },
+            )
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn has_object(&self, hash: &str) -> Result<bool> {
         let hash = hash.to_string();
 
-        self.conn
+        self.reader()
             .call(move |conn| -> std::result::Result<bool, rusqlite::Error> {
                 let mut stmt = conn.prepare("SELECT COUNT(*) FROM objects WHERE hash = ?1")?;
                 let count: i64 = stmt.query_row(rusqlite::params![hash], |row| row.get(0))?;
                 Ok(count > 0)
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
@@ -136,40 +414,44 @@ impl StorageBackend for SqliteStorage {
                 Ok(())
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn get_ref(&self, name: &str) -> Result<Option<String>> {
         let name = name.to_string();
 
-        self.conn
-            .call(move |conn| -> std::result::Result<Option<String>, rusqlite::Error> {
-                let mut stmt = conn.prepare("SELECT target FROM refs WHERE name = ?1")?;
-                let result = stmt
-                    .query_row(rusqlite::params![name], |row| row.get::<_, String>(0))
-                    .optional()?;
-                Ok(result)
-            })
+        self.reader()
+            .call(
+                move |conn| -> std::result::Result<Option<String>, rusqlite::Error> {
+                    let mut stmt = conn.prepare("SELECT target FROM refs WHERE name = ?1")?;
+                    let result = stmt
+                        .query_row(rusqlite::params![name], |row| row.get::<_, String>(0))
+                        .optional()?;
+                    Ok(result)
+                },
+            )
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn list_refs(&self) -> Result<HashMap<String, String>> {
-        self.conn
-            .call(|conn| -> std::result::Result<HashMap<String, String>, rusqlite::Error> {
-                let mut stmt = conn.prepare("SELECT name, target FROM refs")?;
-                let rows = stmt.query_map([], |row| {
-                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-                })?;
-                let mut map = HashMap::new();
-                for row in rows {
-                    let (name, target) = row?;
-                    map.insert(name, target);
-                }
-                Ok(map)
-            })
+        self.reader()
+            .call(
+                |conn| -> std::result::Result<HashMap<String, String>, rusqlite::Error> {
+                    let mut stmt = conn.prepare("SELECT name, target FROM refs")?;
+                    let rows = stmt.query_map([], |row| {
+                        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                    })?;
+                    let mut map = HashMap::new();
+                    for row in rows {
+                        let (name, target) = row?;
+                        map.insert(name, target);
+                    }
+                    Ok(map)
+                },
+            )
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn delete_ref(&self, name: &str) -> Result<bool> {
@@ -177,14 +459,53 @@ impl StorageBackend for SqliteStorage {
 
         self.conn
             .call(move |conn| -> std::result::Result<bool, rusqlite::Error> {
-                let count = conn.execute(
-                    "DELETE FROM refs WHERE name = ?1",
-                    rusqlite::params![name],
-                )?;
+                let count =
+                    conn.execute("DELETE FROM refs WHERE name = ?1", rusqlite::params![name])?;
                 Ok(count > 0)
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
+    }
+
+    /// Runs as one `BEGIN IMMEDIATE` transaction, so the expected targets
+    /// are checked under the write lock.
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        let updates = updates.to_vec();
+
+        self.conn
+            .call(
+                move |conn| -> std::result::Result<Result<()>, rusqlite::Error> {
+                    let tx =
+                        conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+                    for update in &updates {
+                        let current: Option<String> = tx
+                            .query_row(
+                                "SELECT target FROM refs WHERE name = ?1",
+                                rusqlite::params![update.name],
+                                |row| row.get(0),
+                            )
+                            .optional()?;
+                        // Dropping the transaction rolls it back
+                        if let Err(e) = update.check(current.as_deref()) {
+                            return Ok(Err(e));
+                        }
+                        match &update.new {
+                            Some(target) => tx.execute(
+                                "INSERT OR REPLACE INTO refs (name, target) VALUES (?1, ?2)",
+                                rusqlite::params![update.name, target],
+                            )?,
+                            None => tx.execute(
+                                "DELETE FROM refs WHERE name = ?1",
+                                rusqlite::params![update.name],
+                            )?,
+                        };
+                    }
+                    tx.commit()?;
+                    Ok(Ok(()))
+                },
+            )
+            .await
+            .map_err(call_error)?
     }
 
     async fn append_log(&self, entry: &LogEntry) -> Result<()> {
@@ -207,45 +528,37 @@ impl StorageBackend for SqliteStorage {
                         entry.message,
                         entry.commit_hash,
                         details_bytes,
-                        entry.level,
+                        entry.level.as_str(),
                     ],
                 )?;
                 Ok(())
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
     async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
         let filter = filter.clone();
 
-        self.conn
+        self.reader()
             .call(move |conn| -> std::result::Result<Vec<LogEntry>, rusqlite::Error> {
-                let mut sql = "SELECT id, timestamp, agent_id, action, message, commit_hash, details, level FROM logs WHERE 1=1".to_string();
-                let mut params: Vec<Box<dyn rusqlite::types::ToSql>> = Vec::new();
+                let (conditions, mut params) = log_conditions(&filter);
+                let mut sql = format!(
+                    "SELECT id, timestamp, agent_id, action, message, commit_hash, details, level FROM logs WHERE {conditions}"
+                );
 
-                if let Some(ref agent_id) = filter.agent_id {
-                    sql.push_str(&format!(" AND agent_id = ?{}", params.len() + 1));
-                    params.push(Box::new(agent_id.clone()));
-                }
-                if let Some(ref action) = filter.action {
-                    sql.push_str(&format!(" AND action = ?{}", params.len() + 1));
-                    params.push(Box::new(action.clone()));
-                }
-                if let Some(ref level) = filter.level {
-                    sql.push_str(&format!(" AND level = ?{}", params.len() + 1));
-                    params.push(Box::new(level.clone()));
-                }
-                if let Some(ref since) = filter.since {
-                    sql.push_str(&format!(" AND timestamp >= ?{}", params.len() + 1));
-                    params.push(Box::new(since.clone()));
-                }
-
-                sql.push_str(" ORDER BY timestamp DESC");
+                // Among equal timestamps, the later insert comes first
+                sql.push_str(" ORDER BY timestamp DESC, rowid DESC");
 
-                if let Some(limit) = filter.limit {
-                    sql.push_str(&format!(" LIMIT ?{}", params.len() + 1));
-                    params.push(Box::new(limit as i64));
+                if filter.limit.is_some() || filter.offset.is_some() {
+                    // A negative LIMIT means no limit
+                    sql.push_str(&format!(
+                        " LIMIT ?{} OFFSET ?{}",
+                        params.len() + 1,
+                        params.len() + 2
+                    ));
+                    params.push(Box::new(filter.limit.map_or(-1, |l| l as i64)));
+                    params.push(Box::new(filter.offset.unwrap_or(0) as i64));
                 }
 
                 let mut stmt = conn.prepare(&sql)?;
@@ -264,7 +577,7 @@ impl StorageBackend for SqliteStorage {
                         message: row.get(4)?,
                         commit_hash: row.get(5)?,
                         details,
-                        level: row.get(7)?,
+                        level: row.get::<_, String>(7)?.parse().unwrap_or_default(),
                     })
                 })?;
 
@@ -275,8 +588,55 @@ impl StorageBackend for SqliteStorage {
                 Ok(entries)
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
+    }
+
+    async fn count_logs(&self, filter: &LogFilter) -> Result<usize> {
+        let filter = filter.clone();
+
+        self.reader()
+            .call(move |conn| -> std::result::Result<usize, rusqlite::Error> {
+                let (conditions, params) = log_conditions(&filter);
+                let param_refs: Vec<&dyn rusqlite::types::ToSql> =
+                    params.iter().map(|p| p.as_ref()).collect();
+                let count: i64 = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM logs WHERE {conditions}"),
+                    param_refs.as_slice(),
+                    |row| row.get(0),
+                )?;
+                Ok(count as usize)
+            })
+            .await
+            .map_err(call_error)
+    }
+
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        let agent_id = filter.agent_id.clone();
+        let before = filter.before.clone();
+        let keep_last = i64::try_from(filter.keep_last.unwrap_or(0)).unwrap_or(i64::MAX);
+
+        self.conn
+            .call(move |conn| -> std::result::Result<usize, rusqlite::Error> {
+                // Rank each agent's entries newest first; among equal
+                // timestamps the later insert is newer
+                conn.execute(
+                    "DELETE FROM logs WHERE id IN (
+                         SELECT id FROM (
+                             SELECT id, timestamp, ROW_NUMBER() OVER (
+                                 PARTITION BY agent_id ORDER BY timestamp DESC, rowid DESC
+                             ) AS rank
+                             FROM logs
+                             WHERE ?1 IS NULL OR agent_id = ?1
+                         )
+                         WHERE rank > ?2 AND (?3 IS NULL OR timestamp < ?3)
+                     )",
+                    rusqlite::params![agent_id, keep_last, before],
+                )
+            })
+            .await
+            .map_err(call_error)
     }
+
     async fn delete_object(&self, hash: &str) -> Result<bool> {
         let hash = hash.to_string();
 
@@ -289,22 +649,313 @@ impl StorageBackend for SqliteStorage {
                 Ok(count > 0)
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
     }
 
-    async fn list_objects(&self) -> Result<Vec<String>> {
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        if hashes.is_empty() {
+            return Ok(0);
+        }
+        let hashes = hashes.to_vec();
+
         self.conn
-            .call(|conn| -> std::result::Result<Vec<String>, rusqlite::Error> {
-                let mut stmt = conn.prepare("SELECT hash FROM objects")?;
-                let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
-                let mut hashes = Vec::new();
-                for row in rows {
-                    hashes.push(row?);
+            .call(move |conn| -> std::result::Result<usize, rusqlite::Error> {
+                let tx = conn.transaction()?;
+                let mut removed = 0;
+                for chunk in hashes.chunks(DELETE_BATCH_SIZE) {
+                    let placeholders = vec!["?"; chunk.len()].join(", ");
+                    removed += tx.execute(
+                        &format!("DELETE FROM objects WHERE hash IN ({placeholders})"),
+                        rusqlite::params_from_iter(chunk),
+                    )?;
                 }
-                Ok(hashes)
+                tx.commit()?;
+                Ok(removed)
             })
             .await
-            .map_err(|e: tokio_rusqlite::Error| AgitError::Storage(e.to_string()))
+            .map_err(call_error)
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        self.reader()
+            .call(
+                |conn| -> std::result::Result<Vec<String>, rusqlite::Error> {
+                    let mut stmt = conn.prepare("SELECT hash FROM objects")?;
+                    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+                    let mut hashes = Vec::new();
+                    for row in rows {
+                        hashes.push(row?);
+                    }
+                    Ok(hashes)
+                },
+            )
+            .await
+            .map_err(call_error)
+    }
+
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        let type_str = obj_type.to_string();
+
+        self.reader()
+            .call(
+                move |conn| -> std::result::Result<Vec<String>, rusqlite::Error> {
+                    let mut stmt = conn.prepare("SELECT hash FROM objects WHERE type = ?1")?;
+                    let rows =
+                        stmt.query_map(rusqlite::params![type_str], |row| row.get::<_, String>(0))?;
+                    let mut hashes = Vec::new();
+                    for row in rows {
+                        hashes.push(row?);
+                    }
+                    Ok(hashes)
+                },
+            )
+            .await
+            .map_err(call_error)
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        let hash = hash.to_string();
+
+        let row = self
+            .reader()
+            .call(
+                move |conn| -> std::result::Result<Option<(String, i64, String)>, rusqlite::Error> {
+                    let mut stmt = conn.prepare(
+                        "SELECT type, length(data), created_at FROM objects WHERE hash = ?1",
+                    )?;
+                    stmt.query_row(rusqlite::params![hash], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                    })
+                    .optional()
+                },
+            )
+            .await
+            .map_err(call_error)?;
+
+        row.map(|(obj_type, size, created_at)| {
+            Ok(ObjectStat {
+                size: size as u64,
+                obj_type: obj_type.parse()?,
+                // datetime('now') is UTC without an offset
+                created_at: NaiveDateTime::parse_from_str(&created_at, "%Y-%m-%d %H:%M:%S")
+                    .ok()
+                    .map(|t| t.and_utc()),
+            })
+        })
+        .transpose()
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        let rows = self
+            .reader()
+            .call(|conn| -> std::result::Result<Vec<(String, i64, i64)>, rusqlite::Error> {
+                let mut stmt = conn.prepare(
+                    "SELECT type, COUNT(*), COALESCE(SUM(length(data)), 0) FROM objects GROUP BY type",
+                )?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+                rows.collect()
+            })
+            .await
+            .map_err(call_error)?;
+
+        let mut stats = StorageStats::default();
+        for (obj_type, count, bytes) in rows {
+            stats.add_many(obj_type.parse()?, count as usize, bytes as u64);
+        }
+        Ok(stats)
+    }
+
+    /// Copies a file-backed database through a separate read-only
+    /// connection holding one read transaction, so the copy is a consistent
+    /// snapshot and, in WAL mode, writers on other connections keep going.
+    /// An in-memory database is only visible to this storage's connection,
+    /// which is busy until the copy finishes.
+    async fn backup_to(&self, path: &str) -> Result<BackupReport> {
+        let dest_path = path.to_string();
+        let start = Instant::now();
+        let pages = if self.path == ":memory:" {
+            self.conn
+                .call(move |conn| -> std::result::Result<u64, rusqlite::Error> {
+                    let mut dest = rusqlite::Connection::open(&dest_path)?;
+                    copy_database(conn, &mut dest)
+                })
+                .await
+                .map_err(call_error)?
+        } else {
+            let source_path = self.path.clone();
+            tokio::task::spawn_blocking(move || -> std::result::Result<u64, rusqlite::Error> {
+                let source = rusqlite::Connection::open_with_flags(
+                    &source_path,
+                    rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+                )?;
+                source.execute_batch("BEGIN; SELECT COUNT(*) FROM sqlite_schema;")?;
+                let mut dest = rusqlite::Connection::open(&dest_path)?;
+                let pages = copy_database(&source, &mut dest)?;
+                source.execute_batch("COMMIT")?;
+                Ok(pages)
+            })
+            .await
+            .map_err(|e| AgitError::storage(e.to_string()))?
+            .map_err(sqlite_error)?
+        };
+        Ok(BackupReport {
+            pages,
+            duration: start.elapsed(),
+        })
+    }
+
+    /// Frees unused pages with `PRAGMA incremental_vacuum` on databases
+    /// created with incremental auto-vacuum, or a full `VACUUM` otherwise,
+    /// then runs `PRAGMA optimize`. Sizes are measured with the WAL
+    /// checkpointed into the main file and truncated.
+    async fn compact(&self) -> Result<CompactReport> {
+        self.conn
+            .call(
+                |conn| -> std::result::Result<CompactReport, rusqlite::Error> {
+                    let size =
+                        |conn: &rusqlite::Connection| -> std::result::Result<u64, rusqlite::Error> {
+                            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+                            let pages: i64 =
+                                conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+                            let page_size: i64 =
+                                conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+                            Ok((pages * page_size) as u64)
+                        };
+                    let bytes_before = size(conn)?;
+                    let auto_vacuum: i64 =
+                        conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+                    // 2 is INCREMENTAL
+                    if auto_vacuum == 2 {
+                        // Frees one page per step
+                        let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+                        let mut rows = stmt.query([])?;
+                        while rows.next()?.is_some() {}
+                    } else {
+                        conn.execute_batch("VACUUM;")?;
+                    }
+                    conn.execute_batch("PRAGMA optimize;")?;
+                    Ok(CompactReport {
+                        bytes_before,
+                        bytes_after: size(conn)?,
+                    })
+                },
+            )
+            .await
+            .map_err(call_error)
+    }
+
+    /// Closes the readers before the writer: the last connection to close
+    /// checkpoints the WAL and deletes the `-wal` and `-shm` files.
+    async fn close(&self) -> Result<()> {
+        for reader in &self.readers {
+            reader.clone().close().await.map_err(call_error)?;
+        }
+        self.conn.clone().close().await.map_err(call_error)
+    }
+
+    /// Holds a row in the `locks` table, so the lock is shared by every
+    /// process using the database file. The row records its holder and
+    /// when it was last renewed; the holder renews it while the lock is
+    /// held, and a row older than `SqliteOptions::lock_lease`, left by a
+    /// process that died, is taken over.
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> Result<LockGuard> {
+        let owner = format!("{}:{}", std::process::id(), uuid::Uuid::new_v4());
+        let expiry = format!("-{:.3} seconds", self.lock_lease.as_secs_f64());
+        let deadline = Instant::now() + timeout;
+        loop {
+            let (lock_name, lock_owner, expiry) = (name.to_string(), owner.clone(), expiry.clone());
+            let acquired = self
+                .conn
+                .call(move |conn| -> std::result::Result<bool, rusqlite::Error> {
+                    let tx = conn.transaction()?;
+                    tx.execute(
+                        "DELETE FROM locks
+                         WHERE name = ?1 AND acquired_at <= strftime(?3, 'now', ?2)",
+                        rusqlite::params![lock_name, expiry, LOCK_TIME_FORMAT],
+                    )?;
+                    let inserted = tx.execute(
+                        "INSERT OR IGNORE INTO locks (name, owner, acquired_at)
+                         VALUES (?1, ?2, strftime(?3, 'now'))",
+                        rusqlite::params![lock_name, lock_owner, LOCK_TIME_FORMAT],
+                    )?;
+                    tx.commit()?;
+                    Ok(inserted == 1)
+                })
+                .await
+                .map_err(call_error)?;
+            if acquired {
+                let renewal = tokio::spawn(renew_lock(
+                    self.conn.clone(),
+                    name.to_string(),
+                    owner.clone(),
+                    self.lock_lease / LOCK_RENEWALS_PER_LEASE,
+                ));
+                return Ok(LockGuard::new(SqliteLock {
+                    conn: self.conn.clone(),
+                    name: name.to_string(),
+                    owner,
+                    renewal,
+                }));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(AgitError::LockTimeout {
+                    name: name.to_string(),
+                });
+            }
+            tokio::time::sleep(LOCK_RETRY_INTERVAL.min(deadline - now)).await;
+        }
+    }
+}
+
+/// Refresh the `acquired_at` of a held lock every `interval`, until the
+/// row is gone.
+async fn renew_lock(conn: Connection, name: String, owner: String, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let (name, owner) = (name.clone(), owner.clone());
+        let renewed = conn
+            .call(move |conn| -> std::result::Result<usize, rusqlite::Error> {
+                conn.execute(
+                    "UPDATE locks SET acquired_at = strftime(?3, 'now')
+                     WHERE name = ?1 AND owner = ?2",
+                    rusqlite::params![name, owner, LOCK_TIME_FORMAT],
+                )
+            })
+            .await;
+        if !matches!(renewed, Ok(1)) {
+            return;
+        }
+    }
+}
+
+/// A row held in the `locks` table, deleted on drop.
+struct SqliteLock {
+    conn: Connection,
+    name: String,
+    owner: String,
+    renewal: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for SqliteLock {
+    /// Deletes the row before returning, whether or not a runtime is
+    /// running.
+    fn drop(&mut self) {
+        self.renewal.abort();
+        let name = std::mem::take(&mut self.name);
+        let owner = std::mem::take(&mut self.owner);
+        let (done, released) = std::sync::mpsc::channel();
+        let release = self.conn.call_raw(move |conn| {
+            let _ = conn.execute(
+                "DELETE FROM locks WHERE name = ?1 AND owner = ?2",
+                rusqlite::params![name, owner],
+            );
+            let _ = done.send(());
+        });
+        // The first poll hands the statement to the connection's thread,
+        // which runs it whether or not the future is polled again
+        let _ = release.now_or_never();
+        let _ = released.recv();
     }
 }
 
@@ -313,97 +964,418 @@ use rusqlite::OptionalExtension;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::repo::Repository;
+    use crate::state::AgentState;
+    use crate::storage::MemoryStorage;
+    use crate::types::ActionType;
+    use serde_json::json;
 
-    #[tokio::test]
-    async fn test_put_get_object() {
-        let storage = SqliteStorage::new(":memory:").await.unwrap();
-        let data = b"hello world";
-        storage.put_object("abc123", ObjectType::Blob, data).await.unwrap();
-        let result = storage.get_object("abc123").await.unwrap();
-        assert_eq!(result, Some(data.to_vec()));
+    storage_conformance_tests!((SqliteStorage::new(":memory:").await.unwrap(), ()));
+
+    mod file_backed {
+        use super::*;
+
+        async fn temp_storage() -> (SqliteStorage, tempfile::TempDir) {
+            let dir = tempfile::tempdir().unwrap();
+            let db = dir.path().join("agit.db");
+            let storage = SqliteStorage::new(db.to_str().unwrap()).await.unwrap();
+            (storage, dir)
+        }
+
+        storage_conformance_tests!(temp_storage().await);
     }
 
     #[tokio::test]
-    async fn test_get_missing_object() {
+    async fn test_wal_mode_active() {
         let storage = SqliteStorage::new(":memory:").await.unwrap();
-        let result = storage.get_object("nonexistent").await.unwrap();
-        assert!(result.is_none());
+        let mode: String = storage
+            .conn
+            .call(|conn| -> std::result::Result<String, rusqlite::Error> {
+                let mut stmt = conn.prepare("PRAGMA journal_mode")?;
+                let mode: String = stmt.query_row([], |row| row.get(0))?;
+                Ok(mode)
+            })
+            .await
+            .unwrap();
+        // In-memory databases may report "memory" instead of "wal", but
+        // file-backed databases will report "wal". Accept both.
+        assert!(
+            mode == "wal" || mode == "memory",
+            "expected WAL or memory journal mode, got: {mode}"
+        );
     }
 
     #[tokio::test]
-    async fn test_has_object() {
-        let storage = SqliteStorage::new(":memory:").await.unwrap();
-        storage.put_object("abc", ObjectType::Blob, b"data").await.unwrap();
-        assert!(storage.has_object("abc").await.unwrap());
-        assert!(!storage.has_object("xyz").await.unwrap());
+    async fn test_close_removes_wal_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db");
+        let storage = SqliteStorage::new(db.to_str().unwrap()).await.unwrap();
+        let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+        commit_n(&mut repo, 3).await;
+        assert!(dir.path().join("agit.db-wal").exists());
+
+        repo.close().await.unwrap();
+        assert!(!dir.path().join("agit.db-wal").exists());
+        assert!(!dir.path().join("agit.db-shm").exists());
+
+        let storage = SqliteStorage::new(db.to_str().unwrap()).await.unwrap();
+        let repo = Repository::init(Box::new(storage)).await.unwrap();
+        assert_eq!(repo.log(None, 10).await.unwrap().len(), 3);
+    }
+
+    async fn commit_n(repo: &mut Repository, n: usize) {
+        for i in 0..n {
+            let state = AgentState::new(json!({"step": i}), json!({}));
+            repo.commit(&state, &format!("step {i}"), ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_backup_during_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("live.db").to_str().unwrap().to_string();
+        let copy = dir.path().join("copy.db").to_str().unwrap().to_string();
+
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        commit_n(&mut repo, 20).await;
+        let writer = tokio::spawn(async move {
+            commit_n(&mut repo, 200).await;
+        });
+
+        // A second connection, as another process would use
+        let reader = SqliteStorage::new(&db).await.unwrap();
+        let report = reader.backup_to(&copy).await.unwrap();
+        assert!(report.pages > 0);
+        writer.await.unwrap();
+
+        let restored = Repository::init(Box::new(SqliteStorage::new(&copy).await.unwrap()))
+            .await
+            .unwrap();
+        assert!(restored.fsck().await.unwrap().is_ok());
+        assert!(!restored.log(None, 1000).await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_refs() {
-        let storage = SqliteStorage::new(":memory:").await.unwrap();
-        storage.set_ref("main", "abc123").await.unwrap();
-        storage.set_ref("dev", "def456").await.unwrap();
+    async fn test_memory_snapshot_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let copy = dir.path().join("snapshot.db").to_str().unwrap().to_string();
 
-        assert_eq!(storage.get_ref("main").await.unwrap(), Some("abc123".to_string()));
-        assert_eq!(storage.get_ref("missing").await.unwrap(), None);
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(":memory:").await.unwrap()))
+            .await
+            .unwrap();
+        commit_n(&mut repo, 3).await;
+        let report = repo.snapshot(&copy).await.unwrap();
+        assert!(report.pages > 0);
 
-        let refs = storage.list_refs().await.unwrap();
-        assert_eq!(refs.len(), 2);
+        let target = SqliteStorage::new(":memory:").await.unwrap();
+        target.restore_from(&copy).await.unwrap();
+        let refs = target.list_refs().await.unwrap();
+        assert_eq!(refs.get("HEAD").map(String::as_str), Some("ref:main"));
+        assert!(refs.contains_key("main"));
+        let restored = Repository::init(Box::new(target)).await.unwrap();
+        assert_eq!(restored.log(None, 10).await.unwrap().len(), 3);
+        assert!(restored.fsck().await.unwrap().is_ok());
+    }
 
-        storage.delete_ref("dev").await.unwrap();
-        let refs = storage.list_refs().await.unwrap();
-        assert_eq!(refs.len(), 1);
+    #[tokio::test]
+    async fn test_snapshot_from_stale_handle_keeps_refs() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let copy = dir.path().join("snapshot.db").to_str().unwrap().to_string();
+
+        let mut stale = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        commit_n(&mut stale, 1).await;
+        let mut other = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+            .await
+            .unwrap();
+        commit_n(&mut other, 1).await;
+        let tip = other.list_branches()["main"].clone();
+
+        stale.snapshot(&copy).await.unwrap();
+        let storage = SqliteStorage::new(&db).await.unwrap();
+        assert_eq!(
+            storage.get_ref("main").await.unwrap(),
+            Some(tip.to_string())
+        );
+        let copied = SqliteStorage::new(&copy).await.unwrap();
+        assert_eq!(copied.get_ref("main").await.unwrap(), Some(tip.to_string()));
     }
 
     #[tokio::test]
-    async fn test_logs() {
-        let storage = SqliteStorage::new(":memory:").await.unwrap();
+    async fn test_lock_left_by_dead_holder_expires() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let storage = SqliteStorage::new(&db).await.unwrap();
+        storage
+            .conn
+            .call(|conn| -> std::result::Result<usize, rusqlite::Error> {
+                conn.execute(
+                    "INSERT INTO locks (name, owner, acquired_at)
+                     VALUES ('gc', 'dead', datetime('now', '-2 minutes'))",
+                    [],
+                )
+            })
+            .await
+            .unwrap();
+
+        let held = storage
+            .acquire_lock("gc", Duration::from_millis(100))
+            .await
+            .unwrap();
+        let other = SqliteStorage::new(&db).await.unwrap();
+        assert!(matches!(
+            other.acquire_lock("gc", Duration::from_millis(50)).await,
+            Err(AgitError::LockTimeout { .. })
+        ));
 
-        let entry = LogEntry {
-            id: "log-1".to_string(),
-            timestamp: "2026-01-01T00:00:00Z".to_string(),
-            agent_id: "agent-1".to_string(),
-            action: "tool_call".to_string(),
-            message: "called search".to_string(),
-            commit_hash: Some("abc123".to_string()),
-            details: Some(serde_json::json!({"tool": "search"})),
-            level: "info".to_string(),
+        // Released even when dropped outside any runtime
+        std::thread::spawn(move || drop(held)).join().unwrap();
+        other
+            .acquire_lock("gc", Duration::from_millis(50))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_held_lock_is_renewed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let options = SqliteOptions {
+            lock_lease: Duration::from_secs(2),
+            ..Default::default()
         };
+        let storage = SqliteStorage::with_options(&db, options.clone())
+            .await
+            .unwrap();
+        let _held = storage
+            .acquire_lock("gc", Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let other = SqliteStorage::with_options(&db, options).await.unwrap();
+        assert!(matches!(
+            other.acquire_lock("gc", Duration::from_millis(50)).await,
+            Err(AgitError::LockTimeout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_backup_unsupported_elsewhere() {
+        let storage = MemoryStorage::new();
+        assert!(matches!(
+            storage.backup_to("/tmp/unused.db").await,
+            Err(AgitError::InvalidOperation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_log_search_indexes_existing_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("old.db").to_str().unwrap().to_string();
+        let storage = SqliteStorage::new(&db).await.unwrap();
+        let mut entry =
+            crate::storage::conformance::log_entry("1", "2026-01-01T00:00:00Z", "a", "commit");
+        entry.message = "disk quota exceeded".to_string();
         storage.append_log(&entry).await.unwrap();
+        // Simulate a database from before the index existed
+        storage
+            .conn
+            .call(|conn| -> std::result::Result<(), rusqlite::Error> {
+                conn.execute_batch(
+                    "DROP TRIGGER logs_fts_insert; DROP TRIGGER logs_fts_delete;
+                     DROP TRIGGER logs_fts_update; DROP TABLE logs_fts;",
+                )
+            })
+            .await
+            .unwrap();
+        drop(storage);
 
+        let storage = SqliteStorage::new(&db).await.unwrap();
         let filter = LogFilter {
-            agent_id: Some("agent-1".to_string()),
+            message_query: Some("quota".to_string()),
             ..Default::default()
         };
-        let logs = storage.query_logs(&filter).await.unwrap();
-        assert_eq!(logs.len(), 1);
-        assert_eq!(logs[0].message, "called search");
+        let found = storage.query_logs(&filter).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "1");
     }
 
     #[tokio::test]
-    async fn test_wal_mode_active() {
-        let storage = SqliteStorage::new(":memory:").await.unwrap();
-        let mode: String = storage
-            .conn
-            .call(|conn| {
-                let mut stmt = conn.prepare("PRAGMA journal_mode")?;
-                let mode: String = stmt.query_row([], |row| row.get(0))?;
-                Ok(mode)
-            })
+    async fn test_compact_after_gc_shrinks_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("gc.db").to_str().unwrap().to_string();
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
             .await
             .unwrap();
-        // In-memory databases may report "memory" instead of "wal", but
-        // file-backed databases will report "wal". Accept both.
+        commit_n(&mut repo, 1).await;
+        // Large states on a branch that is deleted below
+        repo.branch("scratch", None).await.unwrap();
+        repo.checkout("scratch").await.unwrap();
+        for i in 0..8 {
+            let payload = format!("{i}").repeat(512 * 1024);
+            let state = AgentState::new(json!({"payload": payload}), json!({}));
+            repo.commit(&state, &format!("big {i}"), ActionType::ToolCall)
+                .await
+                .unwrap();
+        }
+        repo.checkout("main").await.unwrap();
+        repo.delete_branch("scratch", false).await.unwrap();
+
+        let options = crate::gc::GcOptions {
+            compact: true,
+            compact_threshold: 1,
+            ..Default::default()
+        };
+        let result = repo.gc_with_options(&options).await.unwrap();
+        let report = result.compaction.expect("compaction ran");
+        assert!(report.bytes_reclaimed() > 2 * 1024 * 1024, "{report:?}");
+        let on_disk = std::fs::metadata(&db).unwrap().len();
+        assert_eq!(on_disk, report.bytes_after);
+        assert!(on_disk < report.bytes_before);
+    }
+
+    #[tokio::test]
+    async fn test_gc_skips_compaction_below_threshold() {
+        let mut repo = Repository::init(Box::new(SqliteStorage::new(":memory:").await.unwrap()))
+            .await
+            .unwrap();
+        commit_n(&mut repo, 2).await;
+        let options = crate::gc::GcOptions {
+            compact: true,
+            ..Default::default()
+        };
+        let result = repo.gc_with_options(&options).await.unwrap();
+        assert!(result.compaction.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compact_vacuums_legacy_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("legacy.db").to_str().unwrap().to_string();
+        // Created before incremental auto-vacuum was enabled
+        rusqlite::Connection::open(&db)
+            .unwrap()
+            .execute_batch("CREATE TABLE legacy (x INTEGER);")
+            .unwrap();
+        let storage = SqliteStorage::new(&db).await.unwrap();
+        let hashes: Vec<String> = (0..4).map(|i| format!("{i:064x}")).collect();
+        for hash in &hashes {
+            storage
+                .put_object(hash, ObjectType::Blob, &vec![7u8; 1024 * 1024])
+                .await
+                .unwrap();
+        }
+        storage.delete_objects(&hashes).await.unwrap();
+
+        let report = storage.compact().await.unwrap();
+        assert!(report.bytes_reclaimed() > 3 * 1024 * 1024, "{report:?}");
+        assert_eq!(std::fs::metadata(&db).unwrap().len(), report.bytes_after);
+    }
+
+    /// Time 50 parallel `get_object` calls made while writers keep storing
+    /// large objects, reading through `read_pool_size` pooled connections.
+    async fn parallel_reads_during_writes(read_pool_size: usize) -> Duration {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+        let storage = std::sync::Arc::new(
+            SqliteStorage::with_options(
+                &db,
+                SqliteOptions {
+                    read_pool_size,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap(),
+        );
+        storage
+            .put_object("target", ObjectType::Blob, b"hello")
+            .await
+            .unwrap();
+
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let mut writers = Vec::new();
+        for w in 0..4 {
+            let storage = storage.clone();
+            let stop = stop.clone();
+            writers.push(tokio::spawn(async move {
+                let mut i = 0u32;
+                while !stop.load(Ordering::Relaxed) {
+                    let data = vec![(w * 31 + i) as u8; 4 << 20];
+                    storage
+                        .put_object(&format!("big-{w}-{i}"), ObjectType::Blob, &data)
+                        .await
+                        .unwrap();
+                    i += 1;
+                }
+            }));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = Instant::now();
+        let readers: Vec<_> = (0..50)
+            .map(|_| {
+                let storage = storage.clone();
+                tokio::spawn(async move { storage.get_object("target").await.unwrap() })
+            })
+            .collect();
+        for reader in readers {
+            assert_eq!(reader.await.unwrap().as_deref(), Some(&b"hello"[..]));
+        }
+        let elapsed = start.elapsed();
+
+        stop.store(true, Ordering::Relaxed);
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        elapsed
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_read_pool_serves_reads_during_writes() {
+        let single = parallel_reads_during_writes(0).await;
+        let pooled = parallel_reads_during_writes(4).await;
         assert!(
-            mode == "wal" || mode == "memory",
-            "expected WAL or memory journal mode, got: {mode}"
+            pooled * 2 < single,
+            "pooled reads took {pooled:?}, single connection {single:?}"
         );
     }
 
     #[tokio::test]
-    async fn test_idempotent_put() {
-        let storage = SqliteStorage::new(":memory:").await.unwrap();
-        storage.put_object("abc", ObjectType::Blob, b"data").await.unwrap();
-        storage.put_object("abc", ObjectType::Blob, b"data").await.unwrap();
+    async fn test_memory_database_reads_through_writer() {
+        let storage = SqliteStorage::with_options(
+            ":memory:",
+            SqliteOptions {
+                read_pool_size: 4,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(storage.readers.is_empty());
+        storage
+            .put_object("abc", ObjectType::Blob, b"x")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_object("abc").await.unwrap(),
+            Some(b"x".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_busy_errors_are_retryable() {
+        let failure = |code| rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None);
+        assert!(sqlite_error(failure(rusqlite::ffi::SQLITE_BUSY)).is_retryable());
+        assert!(sqlite_error(failure(rusqlite::ffi::SQLITE_LOCKED)).is_retryable());
+        assert!(!sqlite_error(failure(rusqlite::ffi::SQLITE_CONSTRAINT)).is_retryable());
+        assert!(!call_error(tokio_rusqlite::Error::ConnectionClosed).is_retryable());
     }
 }
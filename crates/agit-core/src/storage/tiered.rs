@@ -0,0 +1,425 @@
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{
+    CompactReport, LockGuard, LogDeleteFilter, LogEntry, LogFilter, ObjectStat, RefUpdate,
+    StorageBackend, StorageStats,
+};
+use crate::error::Result;
+use crate::gc::collect_reachable;
+use crate::objects::infer_object_type;
+use crate::types::{Hash, ObjectType};
+
+/// Recency order of the objects held in the cache tier.
+#[derive(Default)]
+struct LruIndex {
+    /// Key to (size in bytes, last-use tick).
+    entries: HashMap<String, (u64, u64)>,
+    /// Last-use tick to key, oldest first.
+    order: BTreeMap<u64, String>,
+    bytes: u64,
+    tick: u64,
+}
+
+impl LruIndex {
+    fn touch(&mut self, key: &str, size: u64) {
+        self.tick += 1;
+        if let Some((old_size, old_tick)) = self.entries.insert(key.to_string(), (size, self.tick))
+        {
+            self.order.remove(&old_tick);
+            self.bytes -= old_size;
+        }
+        self.order.insert(self.tick, key.to_string());
+        self.bytes += size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((size, tick)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+            self.bytes -= size;
+        }
+    }
+
+    /// Drop least recently used keys until within the limits, returning them.
+    fn evict(&mut self, max_objects: Option<usize>, max_bytes: Option<u64>) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while max_objects.is_some_and(|max| self.entries.len() > max)
+            || max_bytes.is_some_and(|max| self.bytes > max)
+        {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((size, _)) = self.entries.remove(&key) {
+                self.bytes -= size;
+            }
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+/// A cache backend in front of a remote backend.
+///
+/// Objects are written through to both tiers and read from the cache
+/// first, falling back to the remote and populating the cache on a miss.
+/// The remote is authoritative: refs, logs, and object listings always go
+/// to it, and an object counts as stored once the remote has it.
+///
+/// The cache can be bounded by object count and bytes, evicting least
+/// recently used objects. Only objects this wrapper has written or read
+/// count toward the limits; objects already in a persistent cache are
+/// tracked once they are first read.
+pub struct TieredStorage<C: StorageBackend, R: StorageBackend> {
+    cache: C,
+    remote: R,
+    max_objects: Option<usize>,
+    max_bytes: Option<u64>,
+    lru: Mutex<LruIndex>,
+}
+
+impl<C: StorageBackend, R: StorageBackend> TieredStorage<C, R> {
+    /// Put `cache` in front of `remote`, with no eviction limits.
+    pub fn new(cache: C, remote: R) -> Self {
+        TieredStorage {
+            cache,
+            remote,
+            max_objects: None,
+            max_bytes: None,
+            lru: Mutex::new(LruIndex::default()),
+        }
+    }
+
+    /// Keep at most `max` objects in the cache.
+    pub fn with_max_objects(mut self, max: usize) -> Self {
+        self.max_objects = Some(max);
+        self
+    }
+
+    /// Keep at most `max` bytes of objects in the cache.
+    pub fn with_max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// The cache tier.
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    /// The remote tier.
+    pub fn remote(&self) -> &R {
+        &self.remote
+    }
+
+    /// Prefetch every object reachable from the remote's refs into the
+    /// cache, returning how many objects are reachable.
+    ///
+    /// With `refs`, the remote's refs are also copied into the cache tier,
+    /// so it can be opened on its own as a snapshot of the remote. Eviction
+    /// limits still apply, so a bounded cache may not hold everything
+    /// afterwards.
+    pub async fn sync_down(&self, refs: bool) -> Result<usize> {
+        let remote_refs = self.remote.list_refs().await?;
+        // Symbolic refs (an attached HEAD) point at another ref, not an object
+        let roots: Vec<Hash> = remote_refs
+            .values()
+            .filter(|target| !target.starts_with("ref:"))
+            .map(|target| Hash::from(target.as_str()))
+            .collect();
+
+        // Walking through `self` caches every commit and blob it reads
+        let reachable = collect_reachable(self, &roots).await?;
+        for key in &reachable {
+            if !self.cache.has_object(key).await? {
+                self.get_object(key).await?;
+            }
+        }
+
+        if refs {
+            for (name, target) in &remote_refs {
+                self.cache.set_ref(name, target).await?;
+            }
+        }
+        Ok(reachable.len())
+    }
+
+    fn lru(&self) -> MutexGuard<'_, LruIndex> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a use of `key` in the cache and evict past the limits.
+    async fn touch(&self, key: &str, size: u64) -> Result<()> {
+        let evicted = {
+            let mut lru = self.lru();
+            lru.touch(key, size);
+            lru.evict(self.max_objects, self.max_bytes)
+        };
+        for key in evicted {
+            self.cache.delete_object(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<C: StorageBackend, R: StorageBackend> StorageBackend for TieredStorage<C, R> {
+    async fn initialize(&self) -> Result<()> {
+        self.cache.initialize().await?;
+        self.remote.initialize().await
+    }
+
+    async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+        // Remote first, so the cache never holds an object the remote lacks
+        self.remote.put_object(hash, obj_type, data).await?;
+        self.cache.put_object(hash, obj_type, data).await?;
+        self.touch(hash, data.len() as u64).await
+    }
+
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.cache.get_object(hash).await? {
+            self.touch(hash, data.len() as u64).await?;
+            return Ok(Some(data));
+        }
+        let Some(data) = self.remote.get_object(hash).await? else {
+            return Ok(None);
+        };
+        let obj_type = infer_object_type(hash, &data);
+        self.cache.put_object(hash, obj_type, &data).await?;
+        self.touch(hash, data.len() as u64).await?;
+        Ok(Some(data))
+    }
+
+    async fn get_object_range(
+        &self,
+        hash: &str,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        // A partial read does not populate the cache
+        match self.cache.get_object_range(hash, offset, len).await? {
+            Some(data) => Ok(Some(data)),
+            None => self.remote.get_object_range(hash, offset, len).await,
+        }
+    }
+
+    async fn has_object(&self, hash: &str) -> Result<bool> {
+        Ok(self.cache.has_object(hash).await? || self.remote.has_object(hash).await?)
+    }
+
+    async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+        self.remote.set_ref(name, hash).await
+    }
+
+    async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+        self.remote.get_ref(name).await
+    }
+
+    async fn list_refs(&self) -> Result<HashMap<String, String>> {
+        self.remote.list_refs().await
+    }
+
+    async fn delete_ref(&self, name: &str) -> Result<bool> {
+        self.remote.delete_ref(name).await
+    }
+
+    async fn update_refs(&self, updates: &[RefUpdate]) -> Result<()> {
+        self.remote.update_refs(updates).await
+    }
+
+    async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        self.remote.append_log(entry).await
+    }
+
+    async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        self.remote.query_logs(filter).await
+    }
+
+    async fn count_logs(&self, filter: &LogFilter) -> Result<usize> {
+        self.remote.count_logs(filter).await
+    }
+
+    async fn log_signals(&self) -> Result<Option<BoxStream<'static, ()>>> {
+        self.remote.log_signals().await
+    }
+
+    async fn delete_logs(&self, filter: &LogDeleteFilter) -> Result<usize> {
+        self.remote.delete_logs(filter).await
+    }
+
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        self.lru().remove(hash);
+        self.cache.delete_object(hash).await?;
+        self.remote.delete_object(hash).await
+    }
+
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        {
+            let mut lru = self.lru();
+            for hash in hashes {
+                lru.remove(hash);
+            }
+        }
+        self.cache.delete_objects(hashes).await?;
+        self.remote.delete_objects(hashes).await
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        self.remote.list_objects().await
+    }
+
+    async fn list_objects_by_type(&self, obj_type: ObjectType) -> Result<Vec<String>> {
+        self.remote.list_objects_by_type(obj_type).await
+    }
+
+    async fn stat_object(&self, hash: &str) -> Result<Option<ObjectStat>> {
+        self.remote.stat_object(hash).await
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats> {
+        self.remote.storage_stats().await
+    }
+
+    async fn compact(&self) -> Result<CompactReport> {
+        let cache = self.cache.compact().await?;
+        let remote = self.remote.compact().await?;
+        Ok(CompactReport {
+            bytes_before: cache.bytes_before + remote.bytes_before,
+            bytes_after: cache.bytes_after + remote.bytes_after,
+        })
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.cache.close().await?;
+        self.remote.close().await
+    }
+
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> Result<LockGuard> {
+        // The remote tier is the one shared between processes
+        self.remote.acquire_lock(name, timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::Repository;
+    use crate::state::AgentState;
+    use crate::storage::{MemoryStorage, SqliteStorage};
+    use crate::types::ActionType;
+    use serde_json::json;
+
+    async fn tiered() -> TieredStorage<MemoryStorage, SqliteStorage> {
+        let remote = SqliteStorage::new(":memory:").await.unwrap();
+        TieredStorage::new(MemoryStorage::new(), remote)
+    }
+
+    storage_conformance_tests!((tiered().await, ()));
+
+    #[tokio::test]
+    async fn test_read_through_populates_cache() {
+        let storage = tiered().await;
+        storage
+            .remote()
+            .put_object("abc", ObjectType::Blob, b"data")
+            .await
+            .unwrap();
+        assert!(!storage.cache().has_object("abc").await.unwrap());
+
+        assert_eq!(storage.get_object("abc").await.unwrap().unwrap(), b"data");
+        assert!(storage.cache().has_object("abc").await.unwrap());
+
+        // Writes go to both tiers
+        storage
+            .put_object("def", ObjectType::Blob, b"more")
+            .await
+            .unwrap();
+        assert!(storage.cache().has_object("def").await.unwrap());
+        assert!(storage.remote().has_object("def").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_remote_is_authoritative_for_refs_and_logs() {
+        let storage = tiered().await;
+        storage.cache().set_ref("main", "stale").await.unwrap();
+        storage.remote().set_ref("main", "fresh").await.unwrap();
+        assert_eq!(
+            storage.get_ref("main").await.unwrap(),
+            Some("fresh".to_string())
+        );
+
+        storage.set_ref("dev", "abc").await.unwrap();
+        assert!(storage.cache().get_ref("dev").await.unwrap().is_none());
+        assert!(storage.remote().get_ref("dev").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let storage = tiered().await.with_max_objects(2);
+        for key in ["a", "b"] {
+            storage
+                .put_object(key, ObjectType::Blob, b"x")
+                .await
+                .unwrap();
+        }
+        // Use "a" so "b" is least recently used
+        storage.get_object("a").await.unwrap();
+        storage
+            .put_object("c", ObjectType::Blob, b"x")
+            .await
+            .unwrap();
+
+        let cache = storage.cache();
+        assert!(cache.has_object("a").await.unwrap());
+        assert!(!cache.has_object("b").await.unwrap());
+        assert!(cache.has_object("c").await.unwrap());
+        // Evicted objects are still served from the remote
+        assert_eq!(storage.get_object("b").await.unwrap().unwrap(), b"x");
+
+        let storage = tiered().await.with_max_bytes(10);
+        storage
+            .put_object("a", ObjectType::Blob, b"123456")
+            .await
+            .unwrap();
+        storage
+            .put_object("b", ObjectType::Blob, b"123456")
+            .await
+            .unwrap();
+        assert!(!storage.cache().has_object("a").await.unwrap());
+        assert!(storage.cache().has_object("b").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sync_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("remote.db");
+        let path = path.to_str().unwrap();
+        {
+            let storage = SqliteStorage::new(path).await.unwrap();
+            let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+            for step in 0..3 {
+                let state = AgentState::new(json!({"step": step}), json!({}));
+                repo.commit(&state, "step", ActionType::ToolCall)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let remote = SqliteStorage::new(path).await.unwrap();
+        let storage = TieredStorage::new(MemoryStorage::new(), remote);
+        let reachable = storage.sync_down(true).await.unwrap();
+        assert!(reachable >= 6);
+
+        let mut cached = storage.cache().list_objects().await.unwrap();
+        let mut remote = storage.remote().list_objects().await.unwrap();
+        cached.sort();
+        remote.sort();
+        assert_eq!(cached, remote);
+        assert_eq!(
+            storage.cache().list_refs().await.unwrap(),
+            storage.remote().list_refs().await.unwrap()
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::error::AgitError;
+
 /// A SHA-256 hash represented as a 64-character hex string.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Hash(pub String);
 
 impl Hash {
@@ -14,6 +16,18 @@ impl Hash {
     pub fn short(&self) -> &str {
         &self.0[..8.min(self.0.len())]
     }
+
+    /// Parse user input as a full hash: 64 lowercase hex characters.
+    pub fn parse(s: &str) -> Result<Hash, AgitError> {
+        if s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            Ok(Hash(s.to_string()))
+        } else {
+            Err(AgitError::InvalidArgument(format!(
+                "invalid hash '{}': expected 64 lowercase hex characters",
+                s
+            )))
+        }
+    }
 }
 
 impl fmt::Display for Hash {
@@ -40,6 +54,8 @@ impl From<&str> for Hash {
 pub enum ObjectType {
     Blob,
     Commit,
+    /// Serialized `MerkleNode` cached for a state blob.
+    Tree,
 }
 
 impl fmt::Display for ObjectType {
@@ -47,6 +63,23 @@ impl fmt::Display for ObjectType {
         match self {
             ObjectType::Blob => write!(f, "blob"),
             ObjectType::Commit => write!(f, "commit"),
+            ObjectType::Tree => write!(f, "tree"),
+        }
+    }
+}
+
+impl std::str::FromStr for ObjectType {
+    type Err = AgitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(ObjectType::Blob),
+            "commit" => Ok(ObjectType::Commit),
+            "tree" => Ok(ObjectType::Tree),
+            _ => Err(AgitError::InvalidArgument(format!(
+                "unknown object type: {}",
+                s
+            ))),
         }
     }
 }
@@ -82,6 +115,54 @@ impl fmt::Display for ActionType {
     }
 }
 
+/// Severity of an audit log entry, stored as its lowercase name.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    #[serde(alias = "warning")]
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = AgitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(AgitError::InvalidArgument(format!(
+                "unknown log level: {}",
+                s
+            ))),
+        }
+    }
+}
+
 /// Strategy for merging two branches.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
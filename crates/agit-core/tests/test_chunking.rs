@@ -0,0 +1,110 @@
+//! Tests for chunked storage of large state values.
+#![cfg(feature = "storage")]
+
+use agit_core::storage::sqlite::SqliteStorage;
+use agit_core::storage::StorageBackend;
+use agit_core::types::ActionType;
+use agit_core::{AgentState, ChunkingOptions, RepoOptions, Repository};
+use serde_json::json;
+
+const PAGE_SIZE: usize = 20 * 1024 * 1024;
+
+fn page(len: usize) -> String {
+    let mut x: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (b'a' + (x % 26) as u8) as char
+        })
+        .collect()
+}
+
+async fn stored_bytes(path: &str) -> usize {
+    let storage = SqliteStorage::new(path).await.unwrap();
+    let mut total = 0;
+    for key in storage.list_objects().await.unwrap() {
+        total += storage.get_object(&key).await.unwrap().unwrap().len();
+    }
+    total
+}
+
+#[tokio::test]
+async fn test_large_field_stored_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+
+    let mut repo = Repository::init_with_options(
+        Box::new(SqliteStorage::new(db).await.unwrap()),
+        RepoOptions {
+            chunking: Some(ChunkingOptions::default()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let html = page(PAGE_SIZE);
+    let mut hashes = Vec::new();
+    for step in 0..3 {
+        let state = AgentState::new(json!({"step": step}), json!({"html": html}));
+        let hash = repo
+            .commit(&state, &format!("step {}", step), ActionType::ToolCall)
+            .await
+            .unwrap();
+        hashes.push(hash);
+    }
+
+    // The page is stored once despite three commits
+    let total = stored_bytes(db).await;
+    assert!(total > PAGE_SIZE);
+    assert!(total < PAGE_SIZE + PAGE_SIZE / 2, "stored {} bytes", total);
+
+    // Reads reassemble transparently
+    let state = repo.get_state(hashes[1].as_str()).await.unwrap();
+    assert_eq!(state.memory, json!({"step": 1}));
+    assert_eq!(state.world_state["html"].as_str().unwrap(), html);
+
+    // A small edit to the page only stores the chunks around it
+    let mut edited = html.clone();
+    edited.replace_range(PAGE_SIZE / 2..PAGE_SIZE / 2 + 5, "EDIT!");
+    let state = AgentState::new(json!({"step": 3}), json!({"html": edited}));
+    let h4 = repo
+        .commit(&state, "edit", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert!(stored_bytes(db).await < total + PAGE_SIZE / 2);
+
+    let diff = repo.diff(hashes[2].as_str(), h4.as_str()).await.unwrap();
+    assert!(diff
+        .entries
+        .iter()
+        .any(|e| e.path == vec!["world_state".to_string(), "html".to_string()]));
+
+    // GC and fsck follow chunk manifests
+    repo.gc(0).await.unwrap();
+    let report = repo.fsck().await.unwrap();
+    assert!(report.is_ok(), "{:?}", report);
+    assert!(report.orphans.is_empty());
+    let state = repo.get_state(h4.as_str()).await.unwrap();
+    assert_eq!(state.world_state["html"].as_str().unwrap(), edited);
+}
+
+#[tokio::test]
+async fn test_chunking_disabled_by_default() {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    let html = page(2 * 1024 * 1024);
+    let state = AgentState::new(json!({}), json!({"html": html}));
+    let hash = repo
+        .commit(&state, "big", ActionType::ToolCall)
+        .await
+        .unwrap();
+    let report = repo.fsck().await.unwrap();
+    // Commit, blob, and Merkle tree only
+    assert_eq!(report.objects_checked, 3);
+    let state = repo.get_state(hash.as_str()).await.unwrap();
+    assert_eq!(state.world_state["html"].as_str().unwrap(), html);
+}
@@ -1,10 +1,23 @@
 //! Tests for field-level encryption.
-#![cfg(feature = "encryption")]
+#![cfg(all(feature = "encryption", feature = "storage"))]
 
+use agit_core::audit;
 use agit_core::encryption::StateEncryptor;
+use agit_core::error::AgitError;
 use agit_core::state::AgentState;
+use agit_core::storage::sqlite::SqliteStorage;
+use agit_core::types::{ActionType, MergeStrategy};
+use agit_core::{
+    EncryptionMode, LogFilter, RepoOptions, Repository, ENCRYPTION_KEY_ID_METADATA_KEY,
+};
 use serde_json::json;
 
+/// The base64 payload of an enveloped ciphertext, as written before the
+/// envelope existed.
+fn strip_envelope(encrypted: &str) -> &str {
+    encrypted.rsplit('|').next().unwrap()
+}
+
 #[test]
 fn test_encrypt_decrypt_value() {
     let enc = StateEncryptor::new("test-key-123");
@@ -73,3 +86,511 @@ fn test_large_state() {
     let decrypted = enc.decrypt_state(&encrypted).unwrap();
     assert_eq!(decrypted.memory, state.memory);
 }
+
+#[test]
+fn test_ciphertext_envelope() {
+    let enc = StateEncryptor::with_salt("key", b"0123456789abcdef");
+    let encrypted = enc.encrypt_value(&json!({"a": 1})).unwrap();
+    let prefix = format!(
+        "v:2|kid:{}|salt:{}|",
+        enc.key_id(),
+        "30313233343536373839616263646566"
+    );
+    assert!(encrypted.starts_with(&prefix));
+    assert_eq!(enc.key_id().len(), 16);
+
+    // Same passphrase, different salt: a different key and key id
+    let other = StateEncryptor::with_salt("key", b"fedcba9876543210");
+    assert_ne!(enc.key_id(), other.key_id());
+    let err = other.decrypt_value(&encrypted).unwrap_err().to_string();
+    assert!(err.contains(&format!("unknown key id {}", enc.key_id())));
+}
+
+#[test]
+fn test_legacy_payload_without_envelope() {
+    let legacy = StateEncryptor::with_context("key", "agent");
+    let bare = legacy.encrypt_value(&json!({"old": true})).unwrap();
+    let bare = strip_envelope(&bare);
+
+    let enc = StateEncryptor::with_salt("key", b"0123456789abcdef")
+        .with_legacy(StateEncryptor::with_context("key", "agent"));
+    assert_eq!(enc.decrypt_value(bare).unwrap(), json!({"old": true}));
+    // Without the legacy key the bare payload does not open
+    let no_legacy = StateEncryptor::with_salt("key", b"0123456789abcdef");
+    assert!(no_legacy.decrypt_value(bare).is_err());
+}
+
+#[tokio::test]
+async fn test_repository_salt_shared_and_key_id_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key("passphrase").await.unwrap();
+    let state = AgentState::new(json!({"secret": 1}), json!({}));
+    let hash = repo
+        .commit(&state, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
+    let (_, commit) = &repo.log(None, 1).await.unwrap()[0];
+    let key_id = commit.metadata[ENCRYPTION_KEY_ID_METADATA_KEY].clone();
+    assert!(key_id.is_string());
+
+    // Another process sharing the storage derives the same key
+    let mut other = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    other.set_encryption_key("passphrase").await.unwrap();
+    assert_eq!(
+        other.get_state(hash.as_str()).await.unwrap().memory,
+        state.memory
+    );
+    let hash = other
+        .commit(&state, "second", ActionType::ToolCall)
+        .await
+        .unwrap();
+    let (_, commit) = &other.log(None, 1).await.unwrap()[0];
+    assert_eq!(commit.metadata[ENCRYPTION_KEY_ID_METADATA_KEY], key_id);
+    assert!(repo.get_state(hash.as_str()).await.is_ok());
+
+    // A separate repository gets its own salt, so the same passphrase
+    // yields a different key
+    let mut separate = Repository::init(Box::new(SqliteStorage::new(":memory:").await.unwrap()))
+        .await
+        .unwrap();
+    separate.set_encryption_key("passphrase").await.unwrap();
+    separate
+        .commit(&state, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
+    let (_, commit) = &separate.log(None, 1).await.unwrap()[0];
+    assert_ne!(commit.metadata[ENCRYPTION_KEY_ID_METADATA_KEY], key_id);
+}
+
+#[tokio::test]
+async fn test_audit_log_encrypted_at_rest() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key("passphrase").await.unwrap();
+    let state = AgentState::new(json!({"secret": 1}), json!({}));
+    repo.commit(&state, "call the tool", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.log_warning("prompt leaked?", Some(json!({"prompt": "top secret"})))
+        .await
+        .unwrap();
+
+    // With the key, entries read back as written
+    let filter = LogFilter {
+        action: Some("warning".to_string()),
+        ..Default::default()
+    };
+    let entries = repo.audit_log(&filter).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].message, "prompt leaked?");
+    let details = entries[0].details.as_ref().unwrap();
+    assert_eq!(details["prompt"], "top secret");
+    assert!(details.get(audit::ENCRYPTED_DETAILS_KEY).is_none());
+    assert!(repo.verify_audit_chain(None).await.unwrap().is_valid());
+
+    // Without it, only ciphertext markers and the plaintext chain fields
+    // are visible, and filtering on plaintext columns still works
+    let keyless = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    let entries = keyless.audit_log(&filter).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].agent_id, "default");
+    assert!(entries[0].message.starts_with("ENC:v:2|"));
+    let details = entries[0].details.as_ref().unwrap();
+    assert!(details.get("prompt").is_none());
+    assert!(details[audit::ENCRYPTED_DETAILS_KEY]
+        .as_str()
+        .unwrap()
+        .starts_with("ENC:"));
+    assert!(details[audit::INTEGRITY_HASH_KEY].is_string());
+    let all = keyless.audit_log(&LogFilter::default()).await.unwrap();
+    assert!(all.iter().all(|e| !e.message.contains("call the tool")));
+
+    // The chain covers the plaintext, so it only verifies with the key
+    let verification = keyless.verify_audit_chain(None).await.unwrap();
+    assert!(!verification.is_valid());
+}
+
+/// Ciphertexts written by the hand-rolled base64 codec, before the move to
+/// the `base64` crate.
+const RAW_KEY_VECTORS: [(&str, &str); 3] = [
+    (
+        "v:2|kid:ba17e1c5c2c073a4|salt:|PATY/VIN7ynnK1lkhG+9CplP6YEwQv1YZGQw19x7MzQ3UgtnA2HM3AiFC1dHKOXsuFVv+Q==",
+        r#"{"secret": "data", "n": 42}"#,
+    ),
+    (
+        "v:2|kid:ba17e1c5c2c073a4|salt:|PDaUCq45A8DpH544Y9nWw0GwIdMQp53l/I9C62DMBA==",
+        r#""x""#,
+    ),
+    (
+        "v:2|kid:ba17e1c5c2c073a4|salt:|NrJAMDg3pA+WAaQsHZuB+9kJKgIYO1//F4KuMf2T9hdU",
+        "[1, 2]",
+    ),
+];
+
+#[test]
+fn test_ciphertext_from_previous_codec_decrypts() {
+    let enc = StateEncryptor::from_key_bytes(&[7u8; 32]);
+    for (encrypted, expected) in RAW_KEY_VECTORS {
+        let expected: serde_json::Value = serde_json::from_str(expected).unwrap();
+        assert_eq!(enc.decrypt_value(encrypted).unwrap(), expected);
+        // Bare legacy payloads, padded or not, decode the same way
+        let payload = strip_envelope(encrypted);
+        assert_eq!(enc.decrypt_value(payload).unwrap(), expected);
+        assert_eq!(
+            enc.decrypt_value(payload.trim_end_matches('=')).unwrap(),
+            expected
+        );
+    }
+
+    let enc = StateEncryptor::with_salt("passphrase", b"0123456789abcdef");
+    let encrypted = "v:2|kid:2ceb19dc2dcf5838|salt:30313233343536373839616263646566|vg55kfxfJVu+p5nsLw+ckHCbM+2dmyubnqWNFBs1foiKOzoLYZWaDEBVAjhk";
+    assert_eq!(
+        enc.decrypt_value(encrypted).unwrap(),
+        json!({"secret": "data"})
+    );
+}
+
+#[test]
+fn test_ciphertext_format_unchanged() {
+    let enc = StateEncryptor::from_key_bytes(&[7u8; 32]);
+    let encrypted = enc
+        .encrypt_value(&json!({"secret": "data", "n": 42}))
+        .unwrap();
+    let (envelope, payload) = encrypted.rsplit_once('|').unwrap();
+    assert_eq!(envelope, "v:2|kid:ba17e1c5c2c073a4|salt:");
+    // 12-byte nonce + 24-byte plaintext + 16-byte tag, padded
+    assert_eq!(payload.len(), 72);
+    assert!(payload.ends_with('='));
+    assert!(payload
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"+/=".contains(&b)));
+}
+
+#[tokio::test]
+async fn test_set_encryption_key_bytes() {
+    let key = StateEncryptor::generate_key();
+    assert_ne!(key, StateEncryptor::generate_key());
+
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key_bytes(key).await.unwrap();
+    let state = AgentState::new(json!({"secret": "kms"}), json!({}));
+    let hash = repo
+        .commit(&state, "raw key", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    let (_, commit) = &repo.log(None, 1).await.unwrap()[0];
+    assert_eq!(
+        commit.metadata[ENCRYPTION_KEY_ID_METADATA_KEY],
+        json!(StateEncryptor::from_key_bytes(&key).key_id())
+    );
+
+    let mut reopened = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    reopened
+        .set_encryption_key_bytes(StateEncryptor::generate_key())
+        .await
+        .unwrap();
+    assert!(reopened.get_state(hash.as_str()).await.is_err());
+    reopened.set_encryption_key_bytes(key).await.unwrap();
+    assert_eq!(
+        reopened.get_state(hash.as_str()).await.unwrap().memory,
+        state.memory
+    );
+}
+
+#[tokio::test]
+async fn test_clear_encryption_key() {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    repo.set_encryption_key("wipe-me").await.unwrap();
+    let secret = AgentState::new(json!({"secret": "data"}), json!({}));
+    let h1 = repo
+        .commit(&secret, "encrypted", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.get_state(h1.as_str()).await.unwrap().memory,
+        secret.memory
+    );
+
+    repo.clear_encryption_key();
+    // Nothing decrypts without the key, and the ciphertext is not handed out
+    assert!(matches!(
+        repo.get_state(h1.as_str()).await,
+        Err(AgitError::EncryptedState { .. })
+    ));
+    let plain = AgentState::new(json!({"public": true}), json!({}));
+    let h2 = repo
+        .commit(&plain, "plain", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert!(repo.log(None, 1).await.unwrap()[0]
+        .1
+        .metadata
+        .get(ENCRYPTION_KEY_ID_METADATA_KEY)
+        .is_none());
+
+    repo.set_encryption_key("wipe-me").await.unwrap();
+    assert_eq!(
+        repo.get_state(h1.as_str()).await.unwrap().memory,
+        secret.memory
+    );
+    assert_eq!(
+        repo.get_state(h2.as_str()).await.unwrap().memory,
+        plain.memory
+    );
+}
+
+#[tokio::test]
+async fn test_open_without_key_reports_encrypted_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key("passphrase").await.unwrap();
+    let state = AgentState::new(json!({"secret": 1}), json!({}));
+    let h1 = repo
+        .commit(&state, "sealed", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert!(repo.is_encrypted(h1.as_str()).await.unwrap());
+
+    let mut reopened = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    let (_, commit) = &reopened.log(None, 1).await.unwrap()[0];
+    assert!(matches!(
+        reopened.get_state(h1.as_str()).await,
+        Err(AgitError::EncryptedState { ref hash }) if *hash == commit.tree_hash.0
+    ));
+    let path = ["memory".to_string(), "secret".to_string()];
+    assert!(matches!(
+        reopened.get_state_path(h1.as_str(), &path).await,
+        Err(AgitError::EncryptedState { .. })
+    ));
+    assert!(reopened.is_encrypted(h1.as_str()).await.unwrap());
+
+    // Plaintext commits on top stay readable and are reported as such
+    let plain = AgentState::new(json!({"public": true}), json!({}));
+    let h2 = reopened
+        .commit(&plain, "plain", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert!(!reopened.is_encrypted(h2.as_str()).await.unwrap());
+    assert_eq!(
+        reopened.get_state(h2.as_str()).await.unwrap().memory,
+        plain.memory
+    );
+}
+
+#[tokio::test]
+async fn test_merge_commit_encrypted_at_rest() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key("passphrase").await.unwrap();
+    let base = AgentState::new(json!({"secret": 1}), json!({}));
+    repo.commit(&base, "base", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.branch("feature", None).await.unwrap();
+    let ours = AgentState::new(json!({"secret": 1, "ours": "a"}), json!({}));
+    repo.commit(&ours, "ours", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.checkout("feature").await.unwrap();
+    let theirs = AgentState::new(json!({"secret": 1, "theirs": "b"}), json!({}));
+    repo.commit(&theirs, "theirs", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.checkout("main").await.unwrap();
+
+    let merge = repo.merge("feature", MergeStrategy::Theirs).await.unwrap();
+    assert!(repo.is_encrypted(merge.as_str()).await.unwrap());
+    let (_, commit) = &repo.log(None, 1).await.unwrap()[0];
+    assert!(commit.metadata[ENCRYPTION_KEY_ID_METADATA_KEY].is_string());
+    assert_eq!(
+        repo.get_state(merge.as_str()).await.unwrap().memory,
+        theirs.memory
+    );
+
+    // Without the key the merged state cannot be read
+    let reopened = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        reopened.get_state(merge.as_str()).await,
+        Err(AgitError::EncryptedState { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_open_with_wrong_key_fails_to_decrypt() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key("right").await.unwrap();
+    let state = AgentState::new(json!({"secret": 1}), json!({}));
+    let hash = repo
+        .commit(&state, "sealed", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    let mut reopened = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    reopened.set_encryption_key("wrong").await.unwrap();
+    assert!(matches!(
+        reopened.get_state(hash.as_str()).await,
+        Err(AgitError::EncryptionError(_))
+    ));
+    assert!(reopened.is_encrypted(hash.as_str()).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_commit_ciphertext_state() {
+    let key = StateEncryptor::generate_key();
+    let state = AgentState::new(json!({"secret": "data"}), json!({"w": 1}));
+    let sealed = StateEncryptor::from_key_bytes(&key)
+        .encrypt_state(&state)
+        .unwrap();
+
+    // Without a key the ciphertext would be stored as if it were plaintext
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    let base = repo
+        .commit(&state, "base", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert!(matches!(
+        repo.commit(&sealed, "re-commit", ActionType::ToolCall)
+            .await,
+        Err(AgitError::InvalidArgument(_))
+    ));
+    assert_eq!(repo.head().unwrap(), base);
+
+    // With the matching key it is stored once, not encrypted again
+    repo.set_encryption_key_bytes(key).await.unwrap();
+    let hash = repo
+        .commit(&sealed, "re-commit", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert!(repo.is_encrypted(hash.as_str()).await.unwrap());
+    let read = repo.get_state(hash.as_str()).await.unwrap();
+    assert_eq!(read.memory, state.memory);
+    assert_eq!(read.world_state, state.world_state);
+
+    // Ciphertext from another key cannot be opened, so it is refused
+    let foreign = StateEncryptor::from_key_bytes(&StateEncryptor::generate_key())
+        .encrypt_state(&state)
+        .unwrap();
+    assert!(matches!(
+        repo.commit(&foreign, "foreign", ActionType::ToolCall).await,
+        Err(AgitError::EncryptionError(_))
+    ));
+    assert_eq!(repo.head().unwrap(), hash);
+}
+
+#[test]
+fn test_convergent_encryptor() {
+    let key = [9u8; 32];
+    let value = json!({"secret": "data"});
+
+    let randomized = StateEncryptor::from_key_bytes(&key);
+    assert_eq!(randomized.mode(), EncryptionMode::Randomized);
+    assert_ne!(
+        randomized.encrypt_value(&value).unwrap(),
+        randomized.encrypt_value(&value).unwrap()
+    );
+
+    let convergent = StateEncryptor::from_key_bytes(&key).convergent(b"repo-a");
+    assert_eq!(convergent.mode(), EncryptionMode::Convergent);
+    let encrypted = convergent.encrypt_value(&value).unwrap();
+    assert_eq!(encrypted, convergent.encrypt_value(&value).unwrap());
+    assert_ne!(
+        encrypted,
+        convergent
+            .encrypt_value(&json!({"secret": "other"}))
+            .unwrap()
+    );
+    // Either mode opens the other's ciphertexts
+    assert_eq!(randomized.decrypt_value(&encrypted).unwrap(), value);
+
+    let other_scope = StateEncryptor::from_key_bytes(&key).convergent(b"repo-b");
+    assert_ne!(encrypted, other_scope.encrypt_value(&value).unwrap());
+}
+
+async fn tree_hashes(mode: EncryptionMode, key: &str) -> (String, String) {
+    let options = RepoOptions {
+        encryption_mode: mode,
+        ..Default::default()
+    };
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init_with_options(Box::new(storage), options)
+        .await
+        .unwrap();
+    repo.set_encryption_key(key).await.unwrap();
+    let state = AgentState::new(json!({"secret": "data"}), json!({"tool": "search"}));
+    let h1 = repo
+        .commit(&state, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
+    let h2 = repo
+        .commit(&state, "again", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.get_state(h2.as_str()).await.unwrap().memory,
+        state.memory
+    );
+
+    let log = repo.log(None, 2).await.unwrap();
+    let tree = |hash: &agit_core::Hash| {
+        let (_, commit) = log.iter().find(|(h, _)| h == hash).unwrap();
+        commit.tree_hash.to_string()
+    };
+    (tree(&h1), tree(&h2))
+}
+
+#[tokio::test]
+async fn test_encryption_modes_and_dedup() {
+    let (first, again) = tree_hashes(EncryptionMode::Randomized, "k").await;
+    assert_ne!(first, again);
+
+    let (first, again) = tree_hashes(EncryptionMode::Convergent, "k").await;
+    assert_eq!(first, again);
+
+    // Another repository's salt scopes its nonces apart
+    let (elsewhere, _) = tree_hashes(EncryptionMode::Convergent, "k").await;
+    assert_ne!(first, elsewhere);
+}
@@ -1,8 +1,19 @@
 //! Tests for garbage collection and squash operations.
+#![cfg(feature = "storage")]
 
+use std::collections::HashMap;
+use std::time::Duration;
+
+use agit_core::error::{AgitError, Result};
+use agit_core::gc;
+use agit_core::refs::RefStore;
 use agit_core::storage::sqlite::SqliteStorage;
-use agit_core::types::ActionType;
-use agit_core::{AgentState, Repository};
+use agit_core::types::{ActionType, Hash, MergeStrategy, ObjectType};
+use agit_core::{
+    AgentState, CancellationToken, LogEntry, LogFilter, MemoryStorage, RepoOptions, Repository,
+    StorageBackend,
+};
+use async_trait::async_trait;
 use serde_json::json;
 
 async fn test_repo() -> Repository {
@@ -23,10 +34,14 @@ async fn test_gc_with_commits() {
     let mut repo = test_repo().await;
 
     let s1 = AgentState::new(json!({"v": 1}), json!({}));
-    repo.commit(&s1, "first", ActionType::ToolCall).await.unwrap();
+    repo.commit(&s1, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let s2 = AgentState::new(json!({"v": 2}), json!({}));
-    repo.commit(&s2, "second", ActionType::ToolCall).await.unwrap();
+    repo.commit(&s2, "second", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let result = repo.gc(100).await.unwrap();
     // Should find reachable objects (commits + blobs)
@@ -39,31 +54,89 @@ async fn test_gc_preserves_branch_tips() {
     let mut repo = test_repo().await;
 
     let s1 = AgentState::new(json!({"v": 1}), json!({}));
-    repo.commit(&s1, "initial", ActionType::ToolCall).await.unwrap();
+    repo.commit(&s1, "initial", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     repo.branch("feature", None).await.unwrap();
     repo.checkout("feature").await.unwrap();
 
     let s2 = AgentState::new(json!({"v": 2}), json!({}));
-    repo.commit(&s2, "feature work", ActionType::ToolCall).await.unwrap();
+    repo.commit(&s2, "feature work", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let result = repo.gc(100).await.unwrap();
     // Both branch tips should be reachable
     assert!(result.objects_before >= 4); // at least 2 commits + 2 blobs
 }
 
+#[tokio::test]
+async fn test_gc_keeps_detached_head() {
+    let mut repo = test_repo().await;
+    let s1 = AgentState::new(json!({"v": 1}), json!({}));
+    let h1 = repo
+        .commit(&s1, "initial", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    // A commit made on a detached HEAD belongs to no branch
+    repo.checkout(h1.as_str()).await.unwrap();
+    let detached_state = AgentState::new(json!({"v": "detached"}), json!({}));
+    let detached = repo
+        .commit(&detached_state, "experiment", ActionType::ToolCall)
+        .await
+        .unwrap();
+    assert_eq!(repo.current_branch(), None);
+
+    for v in 2..5 {
+        let state = AgentState::new(json!({"v": v}), json!({}));
+        repo.commit_to_branch("main", &state, "more", ActionType::ToolCall, false)
+            .await
+            .unwrap();
+    }
+
+    let result = repo.gc(0).await.unwrap();
+    assert_eq!(result.objects_removed, 0);
+    assert!(!result.removed.contains(&detached.0));
+    let state = repo.get_state(detached.as_str()).await.unwrap();
+    assert_eq!(state.memory, json!({"v": "detached"}));
+}
+
+#[tokio::test]
+async fn test_gc_keeps_persisted_refs() {
+    let storage = MemoryStorage::new();
+    let refs = synthetic_objects(&storage, 3).await;
+    // Refs other than branches, such as tags, are roots too
+    storage.set_ref("tags/v1", "garbage-00001").await.unwrap();
+    let result = gc::gc(&storage, &refs, &gc::GcOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(result.objects_removed, 2);
+    assert!(storage.has_object("garbage-00001").await.unwrap());
+}
+
 #[tokio::test]
 async fn test_squash_commits() {
     let mut repo = test_repo().await;
 
     let s1 = AgentState::new(json!({"v": 1}), json!({}));
-    let h1 = repo.commit(&s1, "first", ActionType::ToolCall).await.unwrap();
+    let h1 = repo
+        .commit(&s1, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let s2 = AgentState::new(json!({"v": 2}), json!({}));
-    let _h2 = repo.commit(&s2, "second", ActionType::ToolCall).await.unwrap();
+    let _h2 = repo
+        .commit(&s2, "second", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let s3 = AgentState::new(json!({"v": 3}), json!({}));
-    let h3 = repo.commit(&s3, "third", ActionType::ToolCall).await.unwrap();
+    let h3 = repo
+        .commit(&s3, "third", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     // Squash h1..h3 (all 3 commits)
     let result = repo.squash("main", h1.as_str(), h3.as_str()).await.unwrap();
@@ -81,13 +154,22 @@ async fn test_squash_preserves_final_state() {
     let mut repo = test_repo().await;
 
     let s1 = AgentState::new(json!({"data": "initial"}), json!({"count": 0}));
-    let h1 = repo.commit(&s1, "start", ActionType::Checkpoint).await.unwrap();
+    let h1 = repo
+        .commit(&s1, "start", ActionType::Checkpoint)
+        .await
+        .unwrap();
 
     let s2 = AgentState::new(json!({"data": "middle"}), json!({"count": 1}));
-    let _h2 = repo.commit(&s2, "progress", ActionType::ToolCall).await.unwrap();
+    let _h2 = repo
+        .commit(&s2, "progress", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let s3 = AgentState::new(json!({"data": "final", "extra": true}), json!({"count": 2}));
-    let h3 = repo.commit(&s3, "done", ActionType::ToolCall).await.unwrap();
+    let h3 = repo
+        .commit(&s3, "done", ActionType::ToolCall)
+        .await
+        .unwrap();
 
     let result = repo.squash("main", h1.as_str(), h3.as_str()).await.unwrap();
 
@@ -95,3 +177,542 @@ async fn test_squash_preserves_final_state() {
     assert_eq!(state.memory, json!({"data": "final", "extra": true}));
     assert_eq!(state.world_state, json!({"count": 2}));
 }
+
+#[tokio::test]
+async fn test_squash_rejects_range_off_first_parent_chain() {
+    let mut repo = test_repo().await;
+    let s1 = AgentState::new(json!({"v": 1}), json!({}));
+    repo.commit(&s1, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.branch("side", None).await.unwrap();
+    let s2 = AgentState::new(json!({"v": 2}), json!({}));
+    let h2 = repo
+        .commit(&s2, "second", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    repo.checkout("side").await.unwrap();
+    let s3 = AgentState::new(json!({"side": 1}), json!({}));
+    let side = repo
+        .commit(&s3, "side", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    // from_hash is not an ancestor of to_hash
+    let err = repo
+        .squash("main", side.as_str(), h2.as_str())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AgitError::InvalidOperation(_)));
+    // to_hash is not on the branch
+    let err = repo
+        .squash("main", side.as_str(), side.as_str())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AgitError::InvalidOperation(_)));
+    assert_eq!(repo.list_branches()["main"], h2);
+}
+
+#[tokio::test]
+async fn test_squash_range_with_merge() {
+    let mut repo = test_repo().await;
+    let s1 = AgentState::new(json!({"a": 1}), json!({}));
+    let h1 = repo
+        .commit(&s1, "first", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.branch("side", None).await.unwrap();
+    let s2 = AgentState::new(json!({"a": 2}), json!({}));
+    repo.commit(&s2, "second", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    repo.checkout("side").await.unwrap();
+    let s3 = AgentState::new(json!({"a": 1, "b": 1}), json!({}));
+    let side = repo
+        .commit(&s3, "side", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    repo.checkout("main").await.unwrap();
+    let merge = repo.merge("side", MergeStrategy::Theirs).await.unwrap();
+
+    let err = repo
+        .squash("main", h1.as_str(), merge.as_str())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AgitError::InvalidOperation(_)));
+
+    let options = gc::SquashOptions { allow_merges: true };
+    let result = repo
+        .squash_with_options("main", h1.as_str(), merge.as_str(), &options)
+        .await
+        .unwrap();
+    assert_eq!(result.commits_squashed, 3);
+    assert_eq!(result.new_tip, result.new_hash);
+    let log = repo.log(Some("main"), 10).await.unwrap();
+    let (_, squashed) = log
+        .iter()
+        .find(|(_, c)| c.message.starts_with("squash"))
+        .unwrap();
+    // h1 is the root, so only the merged-in side commit remains a parent
+    assert_eq!(squashed.parent_hashes, vec![side]);
+}
+
+#[tokio::test]
+async fn test_squash_prefix_replays_later_commits() {
+    let mut repo = test_repo().await;
+    let mut hashes = Vec::new();
+    for i in 0..5 {
+        let state = AgentState::new(json!({"v": i}), json!({}));
+        let hash = repo
+            .commit(&state, &format!("c{}", i), ActionType::ToolCall)
+            .await
+            .unwrap();
+        hashes.push(hash);
+    }
+
+    let result = repo
+        .squash("main", hashes[1].as_str(), hashes[3].as_str())
+        .await
+        .unwrap();
+    assert_eq!(result.commits_squashed, 3);
+    assert_eq!(repo.list_branches()["main"], result.new_tip);
+
+    let log = repo.log(Some("main"), 10).await.unwrap();
+    let parents: HashMap<&str, &[Hash]> = log
+        .iter()
+        .map(|(_, c)| (c.message.as_str(), c.parent_hashes.as_slice()))
+        .collect();
+    assert_eq!(parents.len(), 3);
+    assert_eq!(parents["c4"], std::slice::from_ref(&result.new_hash));
+    assert_eq!(parents["squash 3 commits: c1; c2; c3"], [hashes[0].clone()]);
+    assert!(parents["c0"].is_empty());
+
+    let state = repo.get_state(result.new_tip.as_str()).await.unwrap();
+    assert_eq!(state.memory, json!({"v": 4}));
+    let state = repo.get_state(result.new_hash.as_str()).await.unwrap();
+    assert_eq!(state.memory, json!({"v": 3}));
+}
+
+#[tokio::test]
+async fn test_squash_resigns_rewritten_commits() {
+    let mut repo = test_repo().await;
+    repo.set_signing_key(b"key");
+    let mut hashes = Vec::new();
+    for i in 0..4 {
+        let state = AgentState::new(json!({"v": i}), json!({}));
+        let hash = repo
+            .commit(&state, &format!("c{}", i), ActionType::ToolCall)
+            .await
+            .unwrap();
+        hashes.push(hash);
+    }
+
+    repo.squash("main", hashes[1].as_str(), hashes[2].as_str())
+        .await
+        .unwrap();
+    let report = repo.verify_history("main", b"key").await.unwrap();
+    assert_eq!(report.verified.len(), 3);
+    assert!(report.invalid.is_empty());
+    assert!(report.unsigned.is_empty());
+}
+
+#[tokio::test]
+async fn test_squash_aggregates_metadata() {
+    let mut repo = test_repo().await;
+    let mut hashes = Vec::new();
+    let steps = [
+        (1.5, json!({"run_id": "a", "step": 1})),
+        (2.0, json!({"run_id": "a", "model": "small"})),
+        (0.25, json!({"run_id": "b"})),
+    ];
+    for (i, (cost, metadata)) in steps.into_iter().enumerate() {
+        let mut state = AgentState::new(json!({"v": i}), json!({}));
+        state.cost = cost;
+        let action = if i == 2 {
+            ActionType::LlmResponse
+        } else {
+            ActionType::ToolCall
+        };
+        let hash = repo
+            .commit_with_metadata(
+                &state,
+                "step",
+                action,
+                metadata.as_object().unwrap().clone(),
+            )
+            .await
+            .unwrap();
+        hashes.push(hash);
+    }
+
+    let result = repo
+        .squash("main", hashes[0].as_str(), hashes[2].as_str())
+        .await
+        .unwrap();
+    assert_eq!(result.squashed_hashes, hashes);
+    assert_eq!(result.aggregated_cost, 3.75);
+
+    let (_, commit) = &repo.log(Some("main"), 1).await.unwrap()[0];
+    assert_eq!(commit.action_type, ActionType::LlmResponse);
+    assert_eq!(
+        commit.metadata[gc::AGGREGATED_COST_METADATA_KEY],
+        json!(3.75)
+    );
+    assert_eq!(
+        commit.metadata[gc::SQUASHED_HASHES_METADATA_KEY],
+        json!([hashes[0], hashes[1], hashes[2]])
+    );
+    assert_eq!(commit.metadata["run_id"], "b");
+    assert_eq!(commit.metadata["step"], 1);
+    assert_eq!(commit.metadata["model"], "small");
+    assert_eq!(commit.metadata["cost"], 0.25);
+
+    let entries = repo
+        .audit_log(&LogFilter {
+            action: Some("squash".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0].commit_hash.as_deref(),
+        Some(result.new_hash.as_str())
+    );
+    assert_eq!(
+        entries[0].details.as_ref().unwrap()["aggregated_cost"],
+        3.75
+    );
+}
+
+/// Store `count` unreachable blobs plus one blob that `main` points at.
+async fn synthetic_objects(storage: &dyn StorageBackend, count: usize) -> RefStore {
+    for i in 0..count {
+        storage
+            .put_object(&format!("garbage-{i:05}"), ObjectType::Blob, b"{}")
+            .await
+            .unwrap();
+    }
+    storage
+        .put_object("live", ObjectType::Blob, b"{}")
+        .await
+        .unwrap();
+    let mut refs = RefStore::new();
+    refs.create_branch("main", Hash::from("live")).unwrap();
+    refs
+}
+
+#[tokio::test]
+async fn test_gc_batches_many_objects() {
+    let sqlite = SqliteStorage::new(":memory:").await.unwrap();
+    let memory = MemoryStorage::new();
+    for storage in [&sqlite as &dyn StorageBackend, &memory] {
+        let refs = synthetic_objects(storage, 5000).await;
+        let result = gc::gc(storage, &refs, &gc::GcOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.objects_before, 5001);
+        assert_eq!(result.objects_removed, 5000);
+        assert_eq!(result.objects_after, 1);
+        assert_eq!(result.removed.len(), 5000);
+        assert!(result.failures.is_empty());
+        assert_eq!(storage.list_objects().await.unwrap(), vec!["live"]);
+    }
+}
+
+#[tokio::test]
+async fn test_gc_dry_run_reports_without_deleting() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db").to_str().unwrap().to_string();
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(&db).await.unwrap()))
+        .await
+        .unwrap();
+    // A second handle on the same database to inspect what is stored
+    let storage = SqliteStorage::new(&db).await.unwrap();
+    let s1 = AgentState::new(json!({"v": 1}), json!({}));
+    repo.commit(&s1, "initial", ActionType::ToolCall)
+        .await
+        .unwrap();
+    repo.branch("scratch", None).await.unwrap();
+    repo.checkout("scratch").await.unwrap();
+    for v in 2..5 {
+        let state = AgentState::new(json!({"v": v}), json!({}));
+        repo.commit(&state, "scratch work", ActionType::ToolCall)
+            .await
+            .unwrap();
+    }
+    repo.checkout("main").await.unwrap();
+    repo.delete_branch("scratch", false).await.unwrap();
+
+    let before = storage.list_objects().await.unwrap();
+    let options = gc::GcOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    let dry = repo.gc_with_options(&options).await.unwrap();
+    assert!(dry.dry_run);
+    assert_eq!(storage.list_objects().await.unwrap(), before);
+    assert_eq!(dry.objects_removed, 0);
+    assert_eq!(dry.objects_after, dry.objects_before);
+    assert!(dry.removed.is_empty());
+    // Three commits, their blobs and cached Merkle trees
+    assert_eq!(dry.unreachable_count, 9);
+    assert_eq!(dry.freed.count, 9);
+    assert_eq!(dry.freed.by_type["commit"].count, 3);
+    assert!(dry.freed.bytes > 0);
+
+    let real = repo.gc(0).await.unwrap();
+    assert!(!real.dry_run);
+    let mut candidates = dry.unreachable.clone();
+    let mut removed = real.removed.clone();
+    candidates.sort();
+    removed.sort();
+    assert_eq!(candidates, removed);
+    assert_eq!(real.freed, dry.freed);
+    assert_eq!(
+        storage.list_objects().await.unwrap().len(),
+        before.len() - 9
+    );
+}
+
+#[tokio::test]
+async fn test_gc_caps_reported_hashes() {
+    let storage = MemoryStorage::new();
+    let refs = synthetic_objects(&storage, gc::GC_REPORT_LIMIT + 5).await;
+    let options = gc::GcOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    let result = gc::gc(&storage, &refs, &options).await.unwrap();
+    assert_eq!(result.unreachable.len(), gc::GC_REPORT_LIMIT);
+    assert_eq!(result.unreachable_count, gc::GC_REPORT_LIMIT + 5);
+    assert_eq!(result.freed.bytes, 2 * (gc::GC_REPORT_LIMIT as u64 + 5));
+    assert_eq!(
+        storage.list_objects().await.unwrap().len(),
+        gc::GC_REPORT_LIMIT + 6
+    );
+}
+
+#[tokio::test]
+async fn test_gc_reports_progress_by_phase() {
+    let mut repo = test_repo().await;
+    for v in 0..3 {
+        let state = AgentState::new(json!({"v": v}), json!({}));
+        repo.commit(&state, "step", ActionType::ToolCall)
+            .await
+            .unwrap();
+    }
+    let mut phases = Vec::new();
+    let result = repo
+        .gc_with_progress(
+            &gc::GcOptions::default(),
+            |p| phases.push((p.phase, p.processed, p.total)),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+    assert!(!result.cancelled);
+    let listed = result.objects_before;
+    assert_eq!(
+        phases,
+        vec![
+            (gc::GcPhase::Marking, listed, Some(listed)),
+            (gc::GcPhase::Listing, 0, None),
+            (gc::GcPhase::Listing, listed, Some(listed)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_gc_cancelled_between_batches() {
+    let storage = MemoryStorage::new();
+    let refs = synthetic_objects(&storage, 2500).await;
+    let cancel = CancellationToken::new();
+    let mut sweeps = Vec::new();
+    let result = gc::gc_with_progress(
+        &storage,
+        &refs,
+        &gc::GcOptions::default(),
+        &mut |p| {
+            if p.phase == gc::GcPhase::Sweeping {
+                sweeps.push((p.processed, p.total));
+                cancel.cancel();
+            }
+        },
+        &cancel,
+    )
+    .await
+    .unwrap();
+
+    // Only the first whole batch was deleted
+    assert!(result.cancelled);
+    assert_eq!(sweeps, vec![(1000, Some(2500))]);
+    assert_eq!(result.objects_removed, 1000);
+    assert_eq!(result.unreachable_count, 2500);
+    assert_eq!(storage.list_objects().await.unwrap().len(), 1501);
+
+    // Cancelled before it starts, nothing is deleted
+    let result = gc::gc_with_progress(
+        &storage,
+        &refs,
+        &gc::GcOptions::default(),
+        &mut |_| {},
+        &cancel,
+    )
+    .await
+    .unwrap();
+    assert!(result.cancelled);
+    assert_eq!(result.objects_removed, 0);
+    assert_eq!(storage.list_objects().await.unwrap().len(), 1501);
+}
+
+/// Storage whose bulk deletes fail for any batch containing `poisoned`.
+struct FailingDeletes {
+    inner: MemoryStorage,
+    poisoned: String,
+}
+
+#[async_trait]
+impl StorageBackend for FailingDeletes {
+    async fn initialize(&self) -> Result<()> {
+        self.inner.initialize().await
+    }
+    async fn put_object(&self, hash: &str, obj_type: ObjectType, data: &[u8]) -> Result<()> {
+        self.inner.put_object(hash, obj_type, data).await
+    }
+    async fn get_object(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get_object(hash).await
+    }
+    async fn has_object(&self, hash: &str) -> Result<bool> {
+        self.inner.has_object(hash).await
+    }
+    async fn set_ref(&self, name: &str, hash: &str) -> Result<()> {
+        self.inner.set_ref(name, hash).await
+    }
+    async fn get_ref(&self, name: &str) -> Result<Option<String>> {
+        self.inner.get_ref(name).await
+    }
+    async fn list_refs(&self) -> Result<HashMap<String, String>> {
+        self.inner.list_refs().await
+    }
+    async fn delete_ref(&self, name: &str) -> Result<bool> {
+        self.inner.delete_ref(name).await
+    }
+    async fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        self.inner.append_log(entry).await
+    }
+    async fn query_logs(&self, filter: &LogFilter) -> Result<Vec<LogEntry>> {
+        self.inner.query_logs(filter).await
+    }
+    async fn delete_object(&self, hash: &str) -> Result<bool> {
+        self.inner.delete_object(hash).await
+    }
+    async fn delete_objects(&self, hashes: &[String]) -> Result<usize> {
+        if hashes.contains(&self.poisoned) {
+            return Err(AgitError::storage("delete rejected".to_string()));
+        }
+        self.inner.delete_objects(hashes).await
+    }
+    async fn list_objects(&self) -> Result<Vec<String>> {
+        self.inner.list_objects().await
+    }
+}
+
+#[tokio::test]
+async fn test_gc_reports_failed_batches() {
+    let storage = FailingDeletes {
+        inner: MemoryStorage::new(),
+        poisoned: "garbage-00000".to_string(),
+    };
+    let refs = synthetic_objects(&storage, 2500).await;
+    let result = gc::gc(&storage, &refs, &gc::GcOptions::default())
+        .await
+        .unwrap();
+
+    // One of the three batches fails; the other two are still deleted
+    assert_eq!(result.failures.len(), 1);
+    let failure = &result.failures[0];
+    assert!(failure.hashes.contains(&storage.poisoned));
+    assert!(failure.error.contains("delete rejected"));
+    assert_eq!(result.objects_removed, 2500 - failure.hashes.len());
+    assert_eq!(result.objects_after, 1 + failure.hashes.len());
+    assert_eq!(
+        storage.list_objects().await.unwrap().len(),
+        result.objects_after
+    );
+}
+
+async fn auto_gc_repo(policy: Option<gc::AutoGcPolicy>) -> Repository {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let options = RepoOptions {
+        auto_gc: policy,
+        ..Default::default()
+    };
+    Repository::init_with_options(Box::new(storage), options)
+        .await
+        .unwrap()
+}
+
+async fn auto_gc_entries(repo: &Repository) -> Vec<LogEntry> {
+    repo.audit_log(&LogFilter {
+        action: Some("auto_gc".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_auto_gc_every_n_commits() {
+    let mut repo = auto_gc_repo(Some(gc::AutoGcPolicy {
+        every_n_commits: 25,
+        min_interval: Duration::ZERO,
+        ..Default::default()
+    }))
+    .await;
+    for i in 0..100 {
+        let state = AgentState::new(json!({"step": i}), json!({}));
+        repo.commit(&state, &format!("step {}", i), ActionType::ToolCall)
+            .await
+            .unwrap();
+    }
+
+    let entries = auto_gc_entries(&repo).await;
+    assert_eq!(entries.len(), 4);
+    let details = entries[0].details.as_ref().unwrap();
+    assert_eq!(details["trigger"], "commit_count");
+}
+
+#[tokio::test]
+async fn test_auto_gc_respects_min_interval() {
+    let mut repo = auto_gc_repo(Some(gc::AutoGcPolicy {
+        every_n_commits: 5,
+        min_interval: Duration::from_secs(3600),
+        ..Default::default()
+    }))
+    .await;
+    for i in 0..20 {
+        let state = AgentState::new(json!({"step": i}), json!({}));
+        repo.commit(&state, "step", ActionType::ToolCall)
+            .await
+            .unwrap();
+    }
+
+    // Only the first threshold runs; the rest fall inside the interval
+    assert_eq!(auto_gc_entries(&repo).await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_auto_gc_disabled_by_default() {
+    let mut repo = auto_gc_repo(None).await;
+    for i in 0..10 {
+        let state = AgentState::new(json!({"step": i}), json!({}));
+        repo.commit(&state, "step", ActionType::ToolCall)
+            .await
+            .unwrap();
+    }
+    assert!(auto_gc_entries(&repo).await.is_empty());
+}
@@ -0,0 +1,178 @@
+//! Tests for exporting history as a Git repository.
+#![cfg(feature = "git-export")]
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+
+use agit_core::storage::sqlite::SqliteStorage;
+use agit_core::types::{ActionType, Hash, MergeStrategy};
+use agit_core::{AgentState, GitExportOptions, MergeOptions, Repository};
+use serde_json::json;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("git is installed");
+    assert!(
+        out.status.success(),
+        "git {:?}: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+async fn commit(repo: &mut Repository, step: i64, message: &str) -> Hash {
+    let state = AgentState::new(json!({"step": step, "by": message}), json!({"env": "test"}));
+    repo.commit(&state, message, ActionType::ToolCall)
+        .await
+        .unwrap()
+}
+
+/// main: base - m1 - merge(f2); feature: base - f1 - f2
+async fn branchy_repo() -> Repository {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    repo.set_agent_id("exporter");
+    commit(&mut repo, 0, "base").await;
+    repo.branch("feature", None).await.unwrap();
+    repo.checkout("feature").await.unwrap();
+    commit(&mut repo, 1, "f1").await;
+    commit(&mut repo, 2, "f2").await;
+    repo.checkout("main").await.unwrap();
+    commit(&mut repo, 3, "m1").await;
+    repo.merge_with_options("feature", MergeStrategy::Ours, MergeOptions::default())
+        .await
+        .unwrap();
+    repo
+}
+
+/// Agit hash → agit parent hashes, read back from `git log` on `branch`.
+fn git_topology(dir: &Path, branch: &str) -> HashMap<String, Vec<String>> {
+    let log = git(
+        dir,
+        &[
+            "log",
+            "--format=%H %P%x00%(trailers:key=Agit-Hash,valueonly,separator=)",
+            branch,
+        ],
+    );
+    let mut by_oid = HashMap::new();
+    let mut parents = Vec::new();
+    for record in log.lines().filter(|l| !l.is_empty()) {
+        let (oids, agit) = record.split_once('\0').unwrap();
+        let mut oids = oids.split_whitespace().map(String::from);
+        let oid = oids.next().unwrap();
+        by_oid.insert(oid.clone(), agit.trim().to_string());
+        parents.push((agit.trim().to_string(), oids.collect::<Vec<_>>()));
+    }
+    parents
+        .into_iter()
+        .map(|(agit, oids)| (agit, oids.iter().map(|o| by_oid[o].clone()).collect()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_export_matches_history() {
+    let repo = branchy_repo().await;
+    let dir = tempfile::tempdir().unwrap();
+    let report = repo
+        .export_git(dir.path(), &GitExportOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(report.commits_exported, 5);
+    assert_eq!(report.branches, vec!["feature", "main"]);
+
+    for branch in ["main", "feature"] {
+        let expected: HashMap<String, Vec<String>> = repo
+            .log(Some(branch), 100)
+            .await
+            .unwrap()
+            .iter()
+            .map(|(hash, c)| {
+                let parents = c.parent_hashes.iter().map(|p| p.to_string()).collect();
+                (hash.to_string(), parents)
+            })
+            .collect();
+        assert_eq!(git_topology(dir.path(), branch), expected, "{}", branch);
+    }
+
+    let graph = git(dir.path(), &["log", "--graph", "--format=%s", "main"]);
+    assert!(graph.contains("|\\"), "no merge in graph:\n{}", graph);
+    let subjects: HashSet<&str> = graph
+        .lines()
+        .map(|l| l.trim_start_matches(['*', '|', '\\', '/', ' ']))
+        .filter(|s| !s.is_empty())
+        .collect();
+    assert!(["base", "f1", "f2", "m1"]
+        .iter()
+        .all(|s| subjects.contains(s)));
+
+    let (head, commit) = repo.log(Some("main"), 1).await.unwrap().remove(0);
+    let show = |file: &str| git(dir.path(), &["show", &format!("main:{}", file)]);
+    let memory: serde_json::Value = serde_json::from_str(&show("memory.json")).unwrap();
+    assert_eq!(memory, repo.get_state(head.as_str()).await.unwrap().memory);
+    assert!(show("world_state.json").contains("\"env\": \"test\""));
+    assert!(show("metadata.json").contains("\"action_type\": \"merge\""));
+
+    let author = git(dir.path(), &["log", "-1", "--format=%an %at", "main"]);
+    assert_eq!(
+        author.trim(),
+        format!("exporter {}", commit.timestamp.timestamp())
+    );
+    assert_eq!(
+        git(dir.path(), &["symbolic-ref", "HEAD"]).trim(),
+        "refs/heads/main"
+    );
+}
+
+#[tokio::test]
+async fn test_incremental_export() {
+    let mut repo = branchy_repo().await;
+    let dir = tempfile::tempdir().unwrap();
+    let options = GitExportOptions::default();
+    let first = repo.export_git(dir.path(), &options).await.unwrap();
+
+    let again = repo.export_git(dir.path(), &options).await.unwrap();
+    assert_eq!(again.commits_exported, 0);
+    assert_eq!(again.mapping, first.mapping);
+
+    let tip = repo.head().unwrap();
+    let new = commit(&mut repo, 4, "after export").await;
+    let report = repo.export_git(dir.path(), &options).await.unwrap();
+    assert_eq!(report.commits_exported, 1);
+    // The old main tip and the feature tip stop the walk
+    assert_eq!(report.commits_skipped, 2);
+    let parent = git(dir.path(), &["log", "-1", "--format=%P", "main"]);
+    assert_eq!(parent.trim(), first.mapping[&tip]);
+    assert_eq!(
+        git(dir.path(), &["rev-parse", "main"]).trim(),
+        report.mapping[&new]
+    );
+}
+
+#[tokio::test]
+async fn test_export_selected_branch() {
+    let repo = branchy_repo().await;
+    let dir = tempfile::tempdir().unwrap();
+    let options = GitExportOptions {
+        branches: vec!["feature".to_string()],
+        ..Default::default()
+    };
+    let report = repo.export_git(dir.path(), &options).await.unwrap();
+    assert_eq!(report.commits_exported, 3);
+    assert_eq!(
+        git(dir.path(), &["branch", "--format=%(refname:short)"]).trim(),
+        "feature"
+    );
+
+    let missing = GitExportOptions {
+        branches: vec!["nope".to_string()],
+        ..Default::default()
+    };
+    assert!(repo.export_git(dir.path(), &missing).await.is_err());
+}
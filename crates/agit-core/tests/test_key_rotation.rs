@@ -0,0 +1,196 @@
+//! Tests for re-encrypting repository history under a new key.
+#![cfg(all(feature = "encryption", feature = "storage"))]
+
+use agit_core::storage::sqlite::SqliteStorage;
+use agit_core::types::{ActionType, MergeStrategy};
+use agit_core::{AgentState, AgitError, LogFilter, Repository, RotationPhase};
+use serde_json::json;
+
+async fn open(db: &str, key: &str) -> Repository {
+    let mut repo = Repository::init(Box::new(SqliteStorage::new(db).await.unwrap()))
+        .await
+        .unwrap();
+    repo.set_encryption_key(key).await.unwrap();
+    repo
+}
+
+/// main: c1 - c2 - merge, feature: c1 - f1 (merged into main), scratch: c2 - s1.
+async fn multi_branch_history(repo: &mut Repository) {
+    let c1 = AgentState::new(json!({"step": 1}), json!({"shared": true}));
+    repo.commit(&c1, "c1", ActionType::ToolCall).await.unwrap();
+    repo.branch("feature", None).await.unwrap();
+    let c2 = AgentState::new(json!({"step": 2}), json!({"shared": true}));
+    repo.commit(&c2, "c2", ActionType::ToolCall).await.unwrap();
+    repo.branch("scratch", None).await.unwrap();
+
+    repo.checkout("feature").await.unwrap();
+    let f1 = AgentState::new(json!({"step": 1, "feature": "x"}), json!({}));
+    repo.commit(&f1, "f1", ActionType::ToolCall).await.unwrap();
+
+    repo.checkout("scratch").await.unwrap();
+    let s1 = AgentState::new(json!({"scratch": 1}), json!({}));
+    repo.commit(&s1, "s1", ActionType::ToolCall).await.unwrap();
+
+    repo.checkout("main").await.unwrap();
+    repo.merge("feature", MergeStrategy::Theirs).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rotate_multi_branch_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("agit.db");
+    let db = db.to_str().unwrap();
+    let mut repo = open(db, "old-key").await;
+    multi_branch_history(&mut repo).await;
+
+    let before: Vec<_> = ["main", "feature", "scratch"]
+        .iter()
+        .map(|b| repo.list_branches()[*b].clone())
+        .collect();
+    let mut states_before = Vec::new();
+    for hash in &before {
+        states_before.push(repo.get_state(hash.as_str()).await.unwrap());
+    }
+
+    let mut phases = Vec::new();
+    let result = repo
+        .rotate_encryption_key("old-key", "new-key", |p| {
+            if phases.last() != Some(&p.phase) {
+                phases.push(p.phase);
+            }
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        phases,
+        vec![
+            RotationPhase::Verifying,
+            RotationPhase::Reencrypting,
+            RotationPhase::Rewriting
+        ]
+    );
+
+    assert_eq!(result.states_reencrypted, 5);
+    assert_eq!(result.states_unencrypted, 0);
+    assert_eq!(result.commit_map.len(), 5);
+    assert_eq!(result.refs_updated, vec!["feature", "main", "scratch"]);
+
+    for (name, (old_tip, state)) in ["main", "feature", "scratch"]
+        .iter()
+        .zip(before.iter().zip(&states_before))
+    {
+        let tip = repo.list_branches()[*name].clone();
+        assert_eq!(result.commit_map[old_tip], tip);
+        let rotated = repo.get_state(tip.as_str()).await.unwrap();
+        assert_eq!(rotated.memory, state.memory);
+        assert_eq!(rotated.world_state, state.world_state);
+    }
+
+    // History is still shared between branches after the rewrite
+    let feature_log = repo.log(Some("feature"), 10).await.unwrap();
+    let scratch_log = repo.log(Some("scratch"), 10).await.unwrap();
+    let (c1, _) = feature_log.iter().find(|(_, c)| c.message == "c1").unwrap();
+    assert!(scratch_log.iter().any(|(h, _)| h == c1));
+
+    // The old key no longer opens anything; a reopened repo with the new one does
+    let reopened = open(db, "old-key").await;
+    let tip = reopened.list_branches()["feature"].clone();
+    assert!(reopened.get_state(tip.as_str()).await.is_err());
+    let reopened = open(db, "new-key").await;
+    assert_eq!(
+        reopened.get_state(tip.as_str()).await.unwrap().memory,
+        states_before[1].memory
+    );
+
+    let entries = repo
+        .audit_log(&LogFilter {
+            action: Some("rotate_encryption_key".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    let details = entries[0].details.as_ref().unwrap();
+    assert_eq!(
+        details["commit_map"][before[1].as_str()],
+        json!(tip.as_str())
+    );
+}
+
+#[tokio::test]
+async fn test_rotate_again_is_a_no_op() {
+    let mut repo = open(":memory:", "old-key").await;
+    multi_branch_history(&mut repo).await;
+    repo.rotate_encryption_key("old-key", "new-key", |_| {})
+        .await
+        .unwrap();
+    let tips = repo.list_branches().clone();
+
+    let result = repo
+        .rotate_encryption_key("old-key", "new-key", |_| {})
+        .await
+        .unwrap();
+    assert_eq!(result.states_reencrypted, 0);
+    assert_eq!(result.states_already_rotated, 5);
+    assert!(result.commit_map.is_empty());
+    assert_eq!(repo.list_branches(), &tips);
+}
+
+#[tokio::test]
+async fn test_rotate_skips_states_already_under_new_key() {
+    let mut repo = open(":memory:", "old-key").await;
+    let s1 = AgentState::new(json!({"step": 1}), json!({}));
+    let h1 = repo.commit(&s1, "old", ActionType::ToolCall).await.unwrap();
+    repo.set_encryption_key("new-key").await.unwrap();
+    let s2 = AgentState::new(json!({"step": 2}), json!({}));
+    repo.commit(&s2, "new", ActionType::ToolCall).await.unwrap();
+
+    let result = repo
+        .rotate_encryption_key("old-key", "new-key", |_| {})
+        .await
+        .unwrap();
+    assert_eq!(result.states_reencrypted, 1);
+    assert_eq!(result.states_already_rotated, 1);
+    // The second commit keeps its state but moves onto the rewritten first
+    assert_eq!(result.commit_map.len(), 2);
+    let rotated = &result.commit_map[&h1];
+    assert_eq!(
+        repo.get_state(rotated.as_str()).await.unwrap().memory,
+        s1.memory
+    );
+}
+
+#[tokio::test]
+async fn test_rotate_refuses_undecryptable_state() {
+    let mut repo = open(":memory:", "old-key").await;
+    multi_branch_history(&mut repo).await;
+    let tips = repo.list_branches().clone();
+
+    let err = repo
+        .rotate_encryption_key("wrong-key", "new-key", |_| {})
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AgitError::EncryptionError(_)));
+    assert_eq!(repo.list_branches(), &tips);
+    let tip = tips["main"].as_str();
+    assert!(repo.get_state(tip).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotate_leaves_unencrypted_states() {
+    let storage = SqliteStorage::new(":memory:").await.unwrap();
+    let mut repo = Repository::init(Box::new(storage)).await.unwrap();
+    let s1 = AgentState::new(json!({"plain": 1}), json!({}));
+    let h1 = repo
+        .commit(&s1, "plain", ActionType::ToolCall)
+        .await
+        .unwrap();
+
+    let result = repo
+        .rotate_encryption_key("old-key", "new-key", |_| {})
+        .await
+        .unwrap();
+    assert_eq!(result.states_unencrypted, 1);
+    assert!(result.commit_map.is_empty());
+    assert_eq!(repo.list_branches()["main"], h1);
+}
@@ -0,0 +1,189 @@
+//! Tests for remotes, fetch and remote-tracking refs.
+#![cfg(feature = "storage")]
+
+use std::path::Path;
+
+use agit_core::error::AgitError;
+use agit_core::migration::{migrate, MigrationProgress};
+use agit_core::types::{ActionType, Hash};
+use agit_core::{AgentState, Repository, SqliteStorage, StorageBackend};
+use serde_json::json;
+
+async fn open(dir: &Path) -> Repository {
+    let path = dir.join("agit.db");
+    let storage = SqliteStorage::new(path.to_str().unwrap()).await.unwrap();
+    Repository::init(Box::new(storage)).await.unwrap()
+}
+
+async fn commit(repo: &mut Repository, step: u64) -> Hash {
+    let state = AgentState::new(json!({"step": step}), json!({}));
+    repo.commit(&state, &format!("step {}", step), ActionType::ToolCall)
+        .await
+        .unwrap()
+}
+
+/// An origin repository with one commit, a local copy of it that has
+/// `origin` configured, and the shared base commit.
+async fn cloned(root: &Path) -> (Repository, Repository, Hash) {
+    let (origin_dir, local_dir) = (root.join("origin"), root.join("local"));
+    std::fs::create_dir(&origin_dir).unwrap();
+    std::fs::create_dir(&local_dir).unwrap();
+
+    let mut origin = open(&origin_dir).await;
+    let base = commit(&mut origin, 0).await;
+
+    let source = SqliteStorage::new(origin_dir.join("agit.db").to_str().unwrap())
+        .await
+        .unwrap();
+    let target = SqliteStorage::new(local_dir.join("agit.db").to_str().unwrap())
+        .await
+        .unwrap();
+    migrate(&source, &target, None::<fn(MigrationProgress)>)
+        .await
+        .unwrap();
+
+    let mut local = open(&local_dir).await;
+    local
+        .add_remote("origin", origin_dir.to_str().unwrap())
+        .await
+        .unwrap();
+    (origin, local, base)
+}
+
+#[tokio::test]
+async fn test_fetch_fast_forward() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut origin, mut local, base) = cloned(dir.path()).await;
+    commit(&mut origin, 1).await;
+    let tip = commit(&mut origin, 2).await;
+
+    let report = local.fetch("origin").await.unwrap();
+    assert_eq!(report.commits_fetched, 2);
+    assert_eq!(report.updated.get("main"), Some(&tip));
+    assert_eq!(local.tracking_refs()["remotes/origin/main"], tip);
+    assert!(!local.list_branches().contains_key("remotes/origin/main"));
+
+    // Local main is behind and can fast-forward
+    assert_eq!(
+        local
+            .ahead_behind("main", "remotes/origin/main")
+            .await
+            .unwrap(),
+        (0, 2)
+    );
+    assert!(local
+        .is_ancestor(base.as_str(), tip.as_str())
+        .await
+        .unwrap());
+
+    let log = local.log(Some("remotes/origin/main"), 10).await.unwrap();
+    let messages: Vec<&str> = log.iter().map(|(_, c)| c.message.as_str()).collect();
+    assert_eq!(messages, ["step 2", "step 1", "step 0"]);
+    assert_eq!(local.log(None, 10).await.unwrap().len(), 1);
+
+    // Nothing new to fetch
+    let again = local.fetch("origin").await.unwrap();
+    assert_eq!(again.commits_fetched, 0);
+    assert!(again.updated.is_empty());
+}
+
+#[tokio::test]
+async fn test_ahead_behind_diverged() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut origin, mut local, _) = cloned(dir.path()).await;
+    commit(&mut origin, 1).await;
+    commit(&mut origin, 2).await;
+    commit(&mut local, 10).await;
+
+    local.fetch("origin").await.unwrap();
+    assert_eq!(
+        local
+            .ahead_behind("main", "remotes/origin/main")
+            .await
+            .unwrap(),
+        (1, 2)
+    );
+    assert_eq!(
+        local
+            .ahead_behind("remotes/origin/main", "main")
+            .await
+            .unwrap(),
+        (2, 1)
+    );
+    assert_eq!(local.ahead_behind("main", "main").await.unwrap(), (0, 0));
+}
+
+#[tokio::test]
+async fn test_fetch_leaves_blobs_behind() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut origin, mut local, _) = cloned(dir.path()).await;
+    let tip = commit(&mut origin, 1).await;
+    local.fetch("origin").await.unwrap();
+
+    let (_, fetched) = &local.log(Some("remotes/origin/main"), 1).await.unwrap()[0];
+    let storage = SqliteStorage::new(dir.path().join("local/agit.db").to_str().unwrap())
+        .await
+        .unwrap();
+    assert!(storage.has_object(tip.as_str()).await.unwrap());
+    assert!(!storage
+        .has_object(fetched.tree_hash.as_str())
+        .await
+        .unwrap());
+    assert!(matches!(
+        local.get_state(tip.as_str()).await,
+        Err(AgitError::ObjectNotFound { .. })
+    ));
+
+    // Missing state behind a tracking ref is expected, and gc keeps the commits
+    let report = local.fsck().await.unwrap();
+    assert!(report.is_ok(), "{:?}", report.missing);
+    assert!(report.orphans.is_empty());
+    local.gc(0).await.unwrap();
+    assert!(storage.has_object(tip.as_str()).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_fetch_prunes_deleted_branches() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut origin, mut local, base) = cloned(dir.path()).await;
+    origin.branch("feature", None).await.unwrap();
+    let report = local.fetch("origin").await.unwrap();
+    assert_eq!(report.commits_fetched, 0);
+    assert_eq!(report.updated.get("feature"), Some(&base));
+
+    origin.delete_branch("feature", false).await.unwrap();
+    let report = local.fetch("origin").await.unwrap();
+    assert_eq!(report.pruned, ["feature"]);
+    assert!(!local.tracking_refs().contains_key("remotes/origin/feature"));
+    assert!(local.tracking_refs().contains_key("remotes/origin/main"));
+}
+
+#[tokio::test]
+async fn test_remote_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_origin, mut local, _) = cloned(dir.path()).await;
+    local.fetch("origin").await.unwrap();
+    local.add_remote("backup", "/nonexistent").await.unwrap();
+
+    let reopened = open(&dir.path().join("local")).await;
+    let remotes = reopened.remotes().await.unwrap();
+    assert_eq!(remotes.keys().collect::<Vec<_>>(), ["backup", "origin"]);
+    assert!(!reopened
+        .list_branches()
+        .keys()
+        .any(|b| b.starts_with("config/")));
+
+    assert!(matches!(
+        local.fetch("backup").await,
+        Err(AgitError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        local.fetch("missing").await,
+        Err(AgitError::InvalidArgument(_))
+    ));
+    assert!(local.add_remote("a/b", "/tmp").await.is_err());
+
+    local.remove_remote("origin").await.unwrap();
+    assert!(local.tracking_refs().is_empty());
+    assert!(local.remove_remote("origin").await.is_err());
+}
@@ -12,6 +12,7 @@
 
 use agit_core::storage::S3Storage;
 use agit_core::storage::{LogEntry, LogFilter, StorageBackend};
+use agit_core::types::LogLevel;
 
 const TEST_BUCKET: &str = "agit-test";
 const TEST_PREFIX: &str = "test-concurrency";
@@ -26,7 +27,7 @@ fn make_entry(id: &str, agent: &str, message: &str) -> LogEntry {
         message: message.to_string(),
         commit_hash: Some(format!("hash-{}", id)),
         details: None,
-        level: "info".to_string(),
+        level: LogLevel::Info,
     }
 }
 
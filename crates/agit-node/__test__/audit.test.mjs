@@ -0,0 +1,47 @@
+// Integration tests for reading the audit log. Run `npm run build` first.
+import { describe, it } from "node:test";
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import { mkdtempSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+const { JsRepository } = createRequire(import.meta.url)("../index.js");
+
+async function openRepo() {
+  return JsRepository.open(mkdtempSync(join(tmpdir(), "agit-node-")));
+}
+
+describe("audit log", () => {
+  it("reads back the entry a commit wrote, attributed to the agent", async () => {
+    const repo = await openRepo();
+    await repo.setAgentId("planner");
+    const hash = await repo.commit({ memory: { goal: "ship" } }, "plan the release", "tool_call");
+
+    const entries = await repo.auditLog({ agentId: "planner", action: "tool_call" });
+    assert.equal(entries.length, 1);
+    const [entry] = entries;
+    assert.equal(entry.agentId, "planner");
+    assert.equal(entry.message, "plan the release");
+    assert.equal(entry.commitHash, hash);
+    assert.equal(entry.level, "info");
+    assert.ok(entry.id);
+    assert.ok(!Number.isNaN(Date.parse(entry.timestamp)));
+    assert.equal(typeof JSON.parse(entry.details), "object");
+
+    assert.equal((await repo.auditLog({ agentId: "someone-else" })).length, 0);
+    assert.equal(await repo.countAuditLog({ agentId: "planner" }), 1);
+  });
+
+  it("filters by level and limit", async () => {
+    const repo = await openRepo();
+    await repo.commit({ memory: {} }, "first", "tool_call");
+    await repo.logWarning("slow tool", '{"ms": 900}');
+
+    const warnings = await repo.auditLog({ level: "warn" });
+    assert.equal(warnings.length, 1);
+    assert.equal(warnings[0].message, "slow tool");
+    assert.equal(JSON.parse(warnings[0].details).ms, 900);
+    assert.equal((await repo.auditLog({ limit: 1 })).length, 1);
+  });
+});
@@ -0,0 +1,61 @@
+// Integration tests for the `code` of errors thrown by JsRepository.
+// Run `npm run build` first.
+import { describe, it } from "node:test";
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import { mkdtempSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+const { JsRepository, ErrorCode } = createRequire(import.meta.url)("../index.js");
+
+function repoPath() {
+  return mkdtempSync(join(tmpdir(), "agit-node-"));
+}
+
+describe("error codes", () => {
+  it("exports the codes", () => {
+    assert.equal(ErrorCode.MergeConflict, "AGIT_MERGE_CONFLICT");
+    assert.equal(ErrorCode.ObjectNotFound, "AGIT_OBJECT_NOT_FOUND");
+  });
+
+  it("reports merge conflicts", async () => {
+    const repo = await JsRepository.open(repoPath());
+    await repo.commit({ memory: { plan: "a" } }, "base", "tool_call");
+    await repo.branch("feature");
+    await repo.checkout("feature");
+    await repo.commit({ memory: { plan: "b" } }, "theirs", "tool_call");
+    await repo.checkout("main");
+    await repo.commit({ memory: { plan: "c" } }, "ours", "tool_call");
+
+    await assert.rejects(repo.merge("feature", "three_way"), (err) => {
+      assert.equal(err.code, ErrorCode.MergeConflict);
+      assert.match(err.message, /memory\.plan/);
+      return true;
+    });
+  });
+
+  it("reports a detached HEAD", async () => {
+    const repo = await JsRepository.open(repoPath());
+    const hash = await repo.commit({ memory: {} }, "init", "tool_call");
+    await repo.branch("feature");
+    await repo.checkout(hash);
+    await assert.rejects(repo.merge("feature", "ours"), { code: "AGIT_DETACHED_HEAD" });
+  });
+
+  it("reports missing objects", async () => {
+    const repo = await JsRepository.open(repoPath());
+    await repo.commit({ memory: {} }, "init", "tool_call");
+    await assert.rejects(repo.getState("0".repeat(64)), { code: "AGIT_OBJECT_NOT_FOUND" });
+  });
+
+  it("reports invalid arguments caught in the binding", async () => {
+    const repo = await JsRepository.open(repoPath());
+    await repo.commit({ memory: {} }, "init", "tool_call");
+    await repo.branch("feature");
+    await assert.rejects(repo.merge("feature", "sideways"), { code: "AGIT_INVALID_ARGUMENT" });
+    await assert.rejects(repo.logWarning("slow", "{not json"), {
+      code: "AGIT_INVALID_ARGUMENT",
+    });
+  });
+});
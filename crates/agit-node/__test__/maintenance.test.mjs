@@ -0,0 +1,71 @@
+// Integration tests for the maintenance APIs. Run `npm run build` first.
+import { describe, it } from "node:test";
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import { mkdtempSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+const { JsRepository } = createRequire(import.meta.url)("../index.js");
+
+async function openRepo() {
+  return JsRepository.open(mkdtempSync(join(tmpdir(), "agit-node-")));
+}
+
+async function commitSteps(repo, n) {
+  const hashes = [];
+  for (let i = 0; i < n; i++) {
+    const state = { memory: { step: i }, cost: 0.5 };
+    hashes.push(await repo.commit(state, `step ${i}`, "tool_call"));
+  }
+  return hashes;
+}
+
+describe("maintenance", () => {
+  it("gc reports and removes unreachable objects", async () => {
+    const repo = await openRepo();
+    const [first, second] = await commitSteps(repo, 3);
+    // The squashed-away commits and their states become unreachable
+    await repo.squash("main", first, second);
+
+    const dry = await repo.gc({ dryRun: true });
+    assert.equal(dry.dryRun, true);
+    assert.ok(dry.unreachableCount > 0);
+    assert.equal(dry.objectsRemoved, 0);
+    assert.equal(dry.objectsAfter, dry.objectsBefore);
+
+    const result = await repo.gc({ keepLastN: 0 });
+    assert.equal(result.objectsRemoved, dry.unreachableCount);
+    assert.equal(result.objectsAfter, result.objectsBefore - result.objectsRemoved);
+  });
+
+  it("squash collapses a range and replays later commits", async () => {
+    const repo = await openRepo();
+    const [first, , third] = await commitSteps(repo, 4);
+
+    const result = await repo.squash("main", first, third);
+    assert.equal(result.commitsSquashed, 3);
+    assert.equal(result.squashedHashes.length, 3);
+    assert.equal(result.aggregatedCost, 1.5);
+    assert.notEqual(result.newTip, result.newHash);
+    assert.equal(await repo.head(), result.newTip);
+
+    const hashes = (await repo.log("main", 10)).map((c) => c.hash).sort();
+    assert.deepEqual(hashes, [result.newHash, result.newTip].sort());
+  });
+
+  it("applyRetention truncates branches outside keepBranches", async () => {
+    const repo = await openRepo();
+    await commitSteps(repo, 2);
+    await repo.branch("agent");
+    await repo.checkout("agent");
+    await commitSteps(repo, 3);
+
+    const result = await repo.applyRetention({ maxCommits: 2 });
+    assert.deepEqual(result.branchesTruncated, ["agent"]);
+    assert.equal(result.commitsExpired, 3);
+    assert.equal(result.commitsRetained, 2);
+    assert.equal((await repo.log("agent", 10)).length, 2);
+    assert.equal((await repo.log("main", 10)).length, 2);
+  });
+});
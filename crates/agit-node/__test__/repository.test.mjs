@@ -0,0 +1,77 @@
+// Integration tests for commit metadata, branch deletion and encryption.
+// Run `npm run build` first.
+import { describe, it } from "node:test";
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import { mkdtempSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+const { JsRepository } = createRequire(import.meta.url)("../index.js");
+
+function repoPath() {
+  return mkdtempSync(join(tmpdir(), "agit-node-"));
+}
+
+describe("commitWithMetadata", () => {
+  it("records the metadata on the commit", async () => {
+    const repo = await JsRepository.open(repoPath());
+    const hash = await repo.commitWithMetadata(
+      { step: 1 },
+      { page: "home" },
+      "with metadata",
+      "tool_call",
+      { run_id: "r-42", tags: ["eval"] }
+    );
+
+    const [commit] = await repo.log(undefined, 1);
+    assert.equal(commit.hash, hash);
+    assert.equal(commit.metadata.run_id, "r-42");
+    assert.deepEqual(commit.metadata.tags, ["eval"]);
+    assert.deepEqual((await repo.getState(hash)).worldState, { page: "home" });
+  });
+});
+
+describe("deleteBranch", () => {
+  it("deletes a branch", async () => {
+    const repo = await JsRepository.open(repoPath());
+    await repo.commit({ memory: {} }, "init", "tool_call");
+    await repo.branch("scratch");
+    await repo.deleteBranch("scratch");
+    assert.deepEqual(Object.keys(await repo.listBranches()), ["main"]);
+  });
+
+  it("throws typed errors for missing branches and main", async () => {
+    const repo = await JsRepository.open(repoPath());
+    await repo.commit({ memory: {} }, "init", "tool_call");
+    await assert.rejects(repo.deleteBranch("nope"), { code: "AGIT_BRANCH_NOT_FOUND" });
+    await assert.rejects(repo.deleteBranch("main"), { code: "AGIT_INVALID_ARGUMENT" });
+  });
+
+  it("refuses the checked-out branch unless forced", async () => {
+    const repo = await JsRepository.open(repoPath());
+    const tip = await repo.commit({ memory: {} }, "init", "tool_call");
+    await repo.branch("scratch");
+    await repo.checkout("scratch");
+    await assert.rejects(repo.deleteBranch("scratch"), { code: "AGIT_BRANCH_CHECKED_OUT" });
+    await repo.deleteBranch("scratch", true);
+    assert.equal(await repo.head(), tip);
+    assert.deepEqual(Object.keys(await repo.listBranches()), ["main"]);
+  });
+});
+
+describe("setEncryptionKey", () => {
+  it("encrypts states at rest", async () => {
+    const path = repoPath();
+    const repo = await JsRepository.open(path);
+    await repo.setEncryptionKey("correct horse battery staple");
+    const hash = await repo.commit({ memory: { secret: "data" } }, "secret", "tool_call");
+    assert.deepEqual((await repo.getState(hash)).memory, { secret: "data" });
+
+    // Without the key only the ciphertext is visible
+    const keyless = await JsRepository.open(path);
+    const sealed = (await keyless.getState(hash)).memory;
+    assert.equal(typeof sealed, "string");
+    assert.ok(sealed.startsWith("ENC:"));
+  });
+});
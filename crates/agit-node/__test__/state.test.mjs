@@ -0,0 +1,64 @@
+// Integration tests for passing states as native JS objects. Run
+// `npm run build` first.
+import { describe, it } from "node:test";
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import { mkdtempSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+const { JsRepository } = createRequire(import.meta.url)("../index.js");
+
+async function openRepo() {
+  return JsRepository.open(mkdtempSync(join(tmpdir(), "agit-node-")));
+}
+
+describe("state objects", () => {
+  it("commits objects and reads them back as objects", async () => {
+    const repo = await openRepo();
+    const memory = { notes: ["a", "b"], nested: { n: 1, ok: true, none: null } };
+    const hash = await repo.commit(
+      { memory, worldState: { url: "https://example.com" }, cost: 0.25, metadata: { run: 7 } },
+      "first",
+      "tool_call"
+    );
+
+    const state = await repo.getState(hash);
+    assert.deepEqual(state.memory, memory);
+    assert.deepEqual(state.worldState, { url: "https://example.com" });
+    assert.equal(state.cost, 0.25);
+    assert.deepEqual(state.metadata, { run: 7 });
+
+    const checkedOut = await repo.checkout(hash);
+    assert.deepEqual(checkedOut.memory, memory);
+  });
+
+  it("defaults worldState and omits empty metadata", async () => {
+    const repo = await openRepo();
+    const hash = await repo.commit({ memory: { x: 1 } }, "bare", "tool_call");
+    const state = await repo.getState(hash);
+    assert.deepEqual(state.worldState, {});
+    assert.equal(state.metadata, undefined);
+  });
+
+  it("exposes parsed values on diff entries", async () => {
+    const repo = await openRepo();
+    const h1 = await repo.commit({ memory: { count: 1, tags: ["a"] } }, "one", "tool_call");
+    const h2 = await repo.commit({ memory: { count: 2, tags: ["a"] } }, "two", "tool_call");
+
+    const { entries } = await repo.diff(h1, h2);
+    const entry = entries.find((e) => e.path.join(".") === "memory.count");
+    assert.equal(entry.oldValue, 1);
+    assert.equal(entry.newValue, 2);
+  });
+
+  it("keeps the deprecated string-based aliases", async () => {
+    const repo = await openRepo();
+    const hash = await repo.commitJson('{"k": "v"}', "{}", "legacy", "tool_call", 1.5);
+    const state = await repo.getStateJson(hash);
+    assert.equal(typeof state.memory, "string");
+    assert.deepEqual(JSON.parse(state.memory), { k: "v" });
+    assert.equal(state.cost, 1.5);
+    assert.deepEqual(JSON.parse((await repo.checkoutJson(hash)).worldState), {});
+  });
+});
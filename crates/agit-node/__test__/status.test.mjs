@@ -0,0 +1,52 @@
+// Integration tests for head, currentBranch and listBranches. Run
+// `npm run build` first.
+import { describe, it } from "node:test";
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import { mkdtempSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+const { JsRepository } = createRequire(import.meta.url)("../index.js");
+
+async function openRepo() {
+  return JsRepository.open(mkdtempSync(join(tmpdir(), "agit-node-")));
+}
+
+describe("repository status", () => {
+  it("reports head and the current branch", async () => {
+    const repo = await openRepo();
+    assert.equal(await repo.head(), null);
+    assert.equal(await repo.currentBranch(), "main");
+
+    const hash = await repo.commit({ memory: { step: 1 } }, "first", "tool_call");
+    assert.equal(await repo.head(), hash);
+    assert.equal(repo.tryHead(), hash);
+    assert.equal(repo.tryCurrentBranch(), "main");
+
+    await repo.checkout(hash);
+    assert.equal(await repo.currentBranch(), null);
+  });
+
+  it("maps branches to their tips", async () => {
+    const repo = await openRepo();
+    const base = await repo.commit({ memory: { step: 1 } }, "base", "tool_call");
+    await repo.branch("feature");
+    const tip = await repo.commit({ memory: { step: 2 } }, "next", "tool_call");
+    assert.deepEqual(await repo.listBranches(), { main: tip, feature: base });
+  });
+
+  it("waits for a concurrent commit instead of returning null", async () => {
+    const repo = await openRepo();
+    const first = await repo.commit({ memory: { step: 0 } }, "first", "tool_call");
+    const memory = {
+      items: Array.from({ length: 20000 }, (_, i) => ({ id: i, text: `item ${i}` })),
+    };
+
+    const pending = repo.commit({ memory }, "large", "tool_call");
+    const head = await repo.head();
+    const second = await pending;
+    assert.ok(head === first || head === second, `unexpected head ${head}`);
+    assert.equal(await repo.head(), second);
+  });
+});
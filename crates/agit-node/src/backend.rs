@@ -0,0 +1,143 @@
+//! Storage backend selection for `JsRepository.openWithBackend`.
+
+use crate::error::{invalid_argument, to_js_error, Result};
+use crate::types::JsBackendConfig;
+use agit_core::{SqliteStorage, StorageBackend};
+
+/// A parsed backend configuration.
+#[derive(Debug)]
+pub enum Backend {
+    Sqlite {
+        path: String,
+    },
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    Postgres {
+        connection_string: String,
+        namespace: String,
+    },
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    S3 {
+        bucket: String,
+        prefix: String,
+        sqs_queue_url: Option<String>,
+    },
+}
+
+impl Backend {
+    /// Parse a URL-style shorthand: `postgres://…` or `postgresql://…`,
+    /// `s3://bucket/prefix`, `sqlite://path`, or a plain SQLite path.
+    pub fn from_url(url: &str) -> Result<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            return Ok(Backend::Postgres {
+                connection_string: url.to_string(),
+                namespace: String::new(),
+            });
+        }
+        if let Some(rest) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(invalid_argument(format!("no bucket in '{}'", url)));
+            }
+            let prefix = prefix.trim_matches('/');
+            return Ok(Backend::S3 {
+                bucket: bucket.to_string(),
+                prefix: if prefix.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", prefix)
+                },
+                sqs_queue_url: None,
+            });
+        }
+        let path = url.strip_prefix("sqlite://").unwrap_or(url);
+        Ok(Backend::Sqlite {
+            path: sqlite_db_path(path),
+        })
+    }
+
+    /// Build a backend from a `{ type, … }` config object.
+    pub fn from_config(config: JsBackendConfig) -> Result<Self> {
+        match config.backend_type.as_str() {
+            "sqlite" => {
+                let path = config
+                    .path
+                    .ok_or_else(|| invalid_argument("sqlite backend requires `path`"))?;
+                Ok(Backend::Sqlite {
+                    path: sqlite_db_path(&path),
+                })
+            }
+            "postgres" => Ok(Backend::Postgres {
+                connection_string: config.connection_string.ok_or_else(|| {
+                    invalid_argument("postgres backend requires `connectionString`")
+                })?,
+                namespace: config.namespace.unwrap_or_default(),
+            }),
+            "s3" => Ok(Backend::S3 {
+                bucket: config
+                    .bucket
+                    .ok_or_else(|| invalid_argument("s3 backend requires `bucket`"))?,
+                prefix: config.prefix.unwrap_or_default(),
+                sqs_queue_url: config.sqs_queue_url,
+            }),
+            other => Err(invalid_argument(format!(
+                "unknown backend type '{}'; use sqlite|postgres|s3",
+                other
+            ))),
+        }
+    }
+
+    /// Connect to the backend.
+    pub async fn connect(self) -> Result<Box<dyn StorageBackend>> {
+        match self {
+            Backend::Sqlite { path } => Ok(Box::new(
+                SqliteStorage::new(&path).await.map_err(to_js_error)?,
+            )),
+            #[cfg(feature = "postgres")]
+            Backend::Postgres {
+                connection_string,
+                namespace,
+            } => {
+                let storage =
+                    agit_core::storage::PostgresStorage::new_scoped(&connection_string, &namespace)
+                        .await
+                        .map_err(to_js_error)?;
+                Ok(Box::new(storage))
+            }
+            #[cfg(not(feature = "postgres"))]
+            Backend::Postgres { .. } => Err(backend_unavailable("postgres")),
+            #[cfg(feature = "s3")]
+            Backend::S3 {
+                bucket,
+                prefix,
+                sqs_queue_url,
+            } => {
+                let storage = agit_core::storage::S3Storage::new(bucket, prefix, sqs_queue_url)
+                    .await
+                    .map_err(to_js_error)?;
+                Ok(Box::new(storage))
+            }
+            #[cfg(not(feature = "s3"))]
+            Backend::S3 { .. } => Err(backend_unavailable("s3")),
+        }
+    }
+}
+
+/// `path` itself if it names a `.db` file, otherwise `agit.db` inside it.
+pub fn sqlite_db_path(path: &str) -> String {
+    if path.ends_with(".db") {
+        path.to_string()
+    } else {
+        format!("{}/agit.db", path.trim_end_matches('/'))
+    }
+}
+
+#[cfg(not(all(feature = "postgres", feature = "s3")))]
+fn backend_unavailable(feature: &str) -> napi::Error<crate::error::ErrorCode> {
+    napi::Error::new(
+        crate::error::ErrorCode::BackendUnavailable,
+        format!(
+            "{} backend not available: the native module was built without the `{}` feature",
+            feature, feature
+        ),
+    )
+}
@@ -0,0 +1,146 @@
+//! Stable `code`s for errors thrown to JS.
+
+use agit_core::AgitError;
+use napi::Error;
+use napi_derive::napi;
+
+/// `code` property of an error thrown by `JsRepository`.
+#[napi(string_enum)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    #[napi(value = "AGIT_OBJECT_NOT_FOUND")]
+    ObjectNotFound,
+    #[napi(value = "AGIT_REF_NOT_FOUND")]
+    RefNotFound,
+    #[napi(value = "AGIT_BRANCH_EXISTS")]
+    BranchExists,
+    #[napi(value = "AGIT_BRANCH_NOT_FOUND")]
+    BranchNotFound,
+    #[napi(value = "AGIT_BRANCH_CHECKED_OUT")]
+    BranchCheckedOut,
+    #[napi(value = "AGIT_MERGE_CONFLICT")]
+    MergeConflict,
+    #[napi(value = "AGIT_NO_COMMON_ANCESTOR")]
+    NoCommonAncestor,
+    #[napi(value = "AGIT_CONCURRENT_UPDATE")]
+    ConcurrentUpdate,
+    #[napi(value = "AGIT_LOCK_TIMEOUT")]
+    LockTimeout,
+    #[napi(value = "AGIT_DETACHED_HEAD")]
+    DetachedHead,
+    #[napi(value = "AGIT_STORAGE")]
+    Storage,
+    #[napi(value = "AGIT_SERIALIZATION")]
+    Serialization,
+    #[napi(value = "AGIT_INVALID_ARGUMENT")]
+    InvalidArgument,
+    #[napi(value = "AGIT_INVALID_OPERATION")]
+    InvalidOperation,
+    #[napi(value = "AGIT_NO_COMMITS")]
+    NoCommits,
+    #[napi(value = "AGIT_ENCRYPTION")]
+    Encryption,
+    #[napi(value = "AGIT_ENCRYPTED_STATE")]
+    EncryptedState,
+    /// The binding was built without the `encryption` feature.
+    #[napi(value = "AGIT_ENCRYPTION_DISABLED")]
+    EncryptionDisabled,
+    /// The binding was built without the cargo feature for a storage backend.
+    #[napi(value = "AGIT_BACKEND_UNAVAILABLE")]
+    BackendUnavailable,
+    #[napi(value = "AGIT_DEPTH_LIMIT_EXCEEDED")]
+    DepthLimitExceeded,
+    #[napi(value = "AGIT_PROTECTED_BRANCH")]
+    ProtectedBranch,
+    #[napi(value = "AGIT_SCHEMA_VIOLATION")]
+    SchemaViolation,
+    #[napi(value = "AGIT_PATCH_CONFLICT")]
+    PatchConflict,
+    #[napi(value = "AGIT_STATE_TOO_LARGE")]
+    StateTooLarge,
+    #[napi(value = "AGIT_STATE_TOO_DEEP")]
+    StateTooDeep,
+    #[napi(value = "AGIT_STATE_TOO_MANY_KEYS")]
+    StateTooManyKeys,
+    /// A core error added after this binding was built.
+    #[napi(value = "AGIT_UNKNOWN")]
+    Unknown,
+}
+
+impl AsRef<str> for ErrorCode {
+    fn as_ref(&self) -> &str {
+        match self {
+            ErrorCode::ObjectNotFound => "AGIT_OBJECT_NOT_FOUND",
+            ErrorCode::RefNotFound => "AGIT_REF_NOT_FOUND",
+            ErrorCode::BranchExists => "AGIT_BRANCH_EXISTS",
+            ErrorCode::BranchNotFound => "AGIT_BRANCH_NOT_FOUND",
+            ErrorCode::BranchCheckedOut => "AGIT_BRANCH_CHECKED_OUT",
+            ErrorCode::MergeConflict => "AGIT_MERGE_CONFLICT",
+            ErrorCode::NoCommonAncestor => "AGIT_NO_COMMON_ANCESTOR",
+            ErrorCode::ConcurrentUpdate => "AGIT_CONCURRENT_UPDATE",
+            ErrorCode::LockTimeout => "AGIT_LOCK_TIMEOUT",
+            ErrorCode::DetachedHead => "AGIT_DETACHED_HEAD",
+            ErrorCode::Storage => "AGIT_STORAGE",
+            ErrorCode::Serialization => "AGIT_SERIALIZATION",
+            ErrorCode::InvalidArgument => "AGIT_INVALID_ARGUMENT",
+            ErrorCode::InvalidOperation => "AGIT_INVALID_OPERATION",
+            ErrorCode::NoCommits => "AGIT_NO_COMMITS",
+            ErrorCode::Encryption => "AGIT_ENCRYPTION",
+            ErrorCode::EncryptedState => "AGIT_ENCRYPTED_STATE",
+            ErrorCode::EncryptionDisabled => "AGIT_ENCRYPTION_DISABLED",
+            ErrorCode::BackendUnavailable => "AGIT_BACKEND_UNAVAILABLE",
+            ErrorCode::DepthLimitExceeded => "AGIT_DEPTH_LIMIT_EXCEEDED",
+            ErrorCode::ProtectedBranch => "AGIT_PROTECTED_BRANCH",
+            ErrorCode::SchemaViolation => "AGIT_SCHEMA_VIOLATION",
+            ErrorCode::PatchConflict => "AGIT_PATCH_CONFLICT",
+            ErrorCode::StateTooLarge => "AGIT_STATE_TOO_LARGE",
+            ErrorCode::StateTooDeep => "AGIT_STATE_TOO_DEEP",
+            ErrorCode::StateTooManyKeys => "AGIT_STATE_TOO_MANY_KEYS",
+            ErrorCode::Unknown => "AGIT_UNKNOWN",
+        }
+    }
+}
+
+/// Result of a `JsRepository` method, rejecting with an `ErrorCode`.
+pub type Result<T> = napi::Result<T, ErrorCode>;
+
+/// Code of a core error; the same string as `AgitError::code`.
+pub fn error_code(e: &AgitError) -> ErrorCode {
+    match e {
+        AgitError::ObjectNotFound { .. } => ErrorCode::ObjectNotFound,
+        AgitError::RefNotFound { .. } => ErrorCode::RefNotFound,
+        AgitError::BranchExists { .. } => ErrorCode::BranchExists,
+        AgitError::BranchNotFound { .. } => ErrorCode::BranchNotFound,
+        AgitError::BranchCheckedOut { .. } => ErrorCode::BranchCheckedOut,
+        AgitError::MergeConflict { .. } => ErrorCode::MergeConflict,
+        AgitError::NoCommonAncestor { .. } => ErrorCode::NoCommonAncestor,
+        AgitError::ConcurrentUpdate { .. } => ErrorCode::ConcurrentUpdate,
+        AgitError::LockTimeout { .. } => ErrorCode::LockTimeout,
+        AgitError::DetachedHead => ErrorCode::DetachedHead,
+        AgitError::Storage { .. } => ErrorCode::Storage,
+        AgitError::Serialization(_) => ErrorCode::Serialization,
+        AgitError::InvalidArgument(_) => ErrorCode::InvalidArgument,
+        AgitError::InvalidOperation(_) => ErrorCode::InvalidOperation,
+        AgitError::NoCommits => ErrorCode::NoCommits,
+        AgitError::EncryptionError(_) => ErrorCode::Encryption,
+        AgitError::EncryptedState { .. } => ErrorCode::EncryptedState,
+        AgitError::DepthLimitExceeded(_) => ErrorCode::DepthLimitExceeded,
+        AgitError::ProtectedBranch { .. } => ErrorCode::ProtectedBranch,
+        AgitError::SchemaViolation { .. } => ErrorCode::SchemaViolation,
+        AgitError::PatchConflict { .. } => ErrorCode::PatchConflict,
+        AgitError::StateTooLarge { .. } => ErrorCode::StateTooLarge,
+        AgitError::StateTooDeep { .. } => ErrorCode::StateTooDeep,
+        AgitError::StateTooManyKeys { .. } => ErrorCode::StateTooManyKeys,
+        _ => ErrorCode::Unknown,
+    }
+}
+
+/// Convert a core error into a JS error carrying its code.
+pub fn to_js_error(e: AgitError) -> Error<ErrorCode> {
+    Error::new(error_code(&e), e.to_string())
+}
+
+/// An `AGIT_INVALID_ARGUMENT` error for a bad argument caught in the binding.
+pub fn invalid_argument(reason: impl Into<String>) -> Error<ErrorCode> {
+    Error::new(ErrorCode::InvalidArgument, reason.into())
+}
@@ -1,2 +1,4 @@
+mod backend;
+mod error;
 mod repository;
 mod types;
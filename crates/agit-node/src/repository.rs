@@ -1,11 +1,22 @@
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use agit_core::{ActionType, AgentState, MergeStrategy, Repository, SqliteStorage};
+use agit_core::{
+    ActionType, AgentState, GcOptions, LogFilter, MergeOptions, MergeStrategy, RepoOptions,
+    Repository, RetentionPolicy, StorageBackend,
+};
 
-use crate::types::{JsAgentState, JsCommit, JsStateDiff};
+use crate::backend::{sqlite_db_path, Backend};
+use crate::error::{invalid_argument, to_js_error, ErrorCode, Result};
+use crate::types::{
+    JsAgentState, JsAgentStateJson, JsBackendConfig, JsCommit, JsDiffStats, JsGcOptions,
+    JsGcResult, JsLogEntry, JsLogFilter, JsPathHistoryEntry, JsRepoOptions, JsRetentionPolicy,
+    JsRetentionResult, JsSquashResult, JsStateDiff, JsStateInput,
+};
 
 /// Napi-rs wrapper around agit_core::Repository.
 #[napi]
@@ -17,27 +28,79 @@ pub struct JsRepository {
 impl JsRepository {
     /// Open (or create) a repository at the given filesystem path.
     #[napi(factory)]
-    pub async fn open(path: String) -> Result<JsRepository> {
-        let db_path = if path.ends_with(".db") {
-            path
-        } else {
-            format!("{}/agit.db", path.trim_end_matches('/'))
+    pub async fn open(path: String, options: Option<JsRepoOptions>) -> Result<JsRepository> {
+        let storage = Backend::Sqlite {
+            path: sqlite_db_path(&path),
+        }
+        .connect()
+        .await?;
+        JsRepository::init(storage, options).await
+    }
+
+    /// Open (or create) a repository on the given storage backend.
+    ///
+    /// `backend` is a `{ type: "sqlite" | "postgres" | "s3", ... }` config,
+    /// or a URL: `postgres://...`, `s3://bucket/prefix`, or a SQLite path.
+    /// Postgres and S3 need the native module built with the matching
+    /// feature; otherwise this throws `AGIT_BACKEND_UNAVAILABLE`.
+    #[napi(factory)]
+    pub async fn open_with_backend(
+        backend: Either<String, JsBackendConfig>,
+        options: Option<JsRepoOptions>,
+    ) -> Result<JsRepository> {
+        let backend = match backend {
+            Either::A(url) => Backend::from_url(&url)?,
+            Either::B(config) => Backend::from_config(config)?,
         };
-        let storage = SqliteStorage::new(&db_path)
+        let storage = backend.connect().await?;
+        JsRepository::init(storage, options).await
+    }
+
+    /// Commit the given agent state, returning the commit hash.
+    #[napi]
+    pub async fn commit(
+        &self,
+        state: JsStateInput,
+        message: String,
+        action_type: String,
+    ) -> Result<String> {
+        let state = AgentState::from(state);
+        let action = parse_action_type(&action_type);
+        let mut repo = self.inner.lock().await;
+        let hash = repo
+            .commit(&state, &message, action)
             .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
-        let repo = Repository::init(Box::new(storage))
+            .map_err(to_js_error)?;
+        Ok(hash.0)
+    }
+
+    /// Commit the given agent state with `metadata` recorded on the commit
+    /// itself, returning the commit hash.
+    #[napi]
+    pub async fn commit_with_metadata(
+        &self,
+        memory: Value,
+        world_state: Value,
+        message: String,
+        action_type: String,
+        metadata: Map<String, Value>,
+    ) -> Result<String> {
+        let state = AgentState::new(memory, world_state);
+        let action = parse_action_type(&action_type);
+        let mut repo = self.inner.lock().await;
+        let hash = repo
+            .commit_with_metadata(&state, &message, action, metadata)
             .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
-        Ok(JsRepository {
-            inner: Arc::new(Mutex::new(repo)),
-        })
+            .map_err(to_js_error)?;
+        Ok(hash.0)
     }
 
     /// Commit the given agent state, returning the commit hash.
     /// `memory_json` and `world_state_json` are JSON strings.
+    ///
+    /// @deprecated Pass objects to `commit` instead; removed in the next release.
     #[napi]
-    pub async fn commit(
+    pub async fn commit_json(
         &self,
         memory_json: String,
         world_state_json: String,
@@ -47,14 +110,9 @@ impl JsRepository {
         metadata_json: Option<String>,
     ) -> Result<String> {
         let memory: serde_json::Value = serde_json::from_str(&memory_json)
-            .map_err(|e| Error::new(Status::InvalidArg, format!("invalid memory JSON: {}", e)))?;
+            .map_err(|e| invalid_argument(format!("invalid memory JSON: {}", e)))?;
         let world_state: serde_json::Value = serde_json::from_str(&world_state_json)
-            .map_err(|e| {
-                Error::new(
-                    Status::InvalidArg,
-                    format!("invalid world_state JSON: {}", e),
-                )
-            })?;
+            .map_err(|e| invalid_argument(format!("invalid world_state JSON: {}", e)))?;
 
         let action = parse_action_type(&action_type);
         let mut state = AgentState::new(memory, world_state);
@@ -62,12 +120,8 @@ impl JsRepository {
             state.cost = c;
         }
         if let Some(metadata_raw) = metadata_json {
-            let metadata_val = serde_json::from_str(&metadata_raw).map_err(|e| {
-                Error::new(
-                    Status::InvalidArg,
-                    format!("invalid metadata JSON: {}", e),
-                )
-            })?;
+            let metadata_val = serde_json::from_str(&metadata_raw)
+                .map_err(|e| invalid_argument(format!("invalid metadata JSON: {}", e)))?;
             state.metadata = metadata_val;
         }
 
@@ -75,7 +129,7 @@ impl JsRepository {
         let hash = repo
             .commit(&state, &message, action)
             .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+            .map_err(to_js_error)?;
         Ok(hash.0)
     }
 
@@ -85,41 +139,75 @@ impl JsRepository {
         let mut repo = self.inner.lock().await;
         repo.branch(&name, from.as_deref())
             .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))
+            .map_err(to_js_error)
+    }
+
+    /// Delete a branch. Throws with code `AGIT_BRANCH_NOT_FOUND` if it does
+    /// not exist, `AGIT_INVALID_ARGUMENT` for `main` and
+    /// `AGIT_BRANCH_CHECKED_OUT` for the checked-out branch unless `force`
+    /// is set, which detaches HEAD at its tip first.
+    #[napi]
+    pub async fn delete_branch(&self, name: String, force: Option<bool>) -> Result<()> {
+        let mut repo = self.inner.lock().await;
+        repo.delete_branch(&name, force.unwrap_or(false))
+            .await
+            .map_err(to_js_error)
     }
 
     /// Checkout a branch or commit hash, returning the restored state.
     #[napi]
     pub async fn checkout(&self, target: String) -> Result<JsAgentState> {
         let mut repo = self.inner.lock().await;
-        let state = repo
-            .checkout(&target)
-            .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+        let state = repo.checkout(&target).await.map_err(to_js_error)?;
         Ok(JsAgentState::from(state))
     }
 
+    /// Checkout a branch or commit hash, returning the restored state.
+    /// JSON fields are serialized strings.
+    ///
+    /// @deprecated Use `checkout` instead; removed in the next release.
+    #[napi]
+    pub async fn checkout_json(&self, target: String) -> Result<JsAgentStateJson> {
+        let mut repo = self.inner.lock().await;
+        let state = repo.checkout(&target).await.map_err(to_js_error)?;
+        Ok(JsAgentStateJson::from(state))
+    }
+
     /// Compute the diff between two commits identified by hash.
     #[napi]
     pub async fn diff(&self, hash1: String, hash2: String) -> Result<JsStateDiff> {
         let repo = self.inner.lock().await;
-        let diff = repo
-            .diff(&hash1, &hash2)
-            .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+        let diff = repo.diff(&hash1, &hash2).await.map_err(to_js_error)?;
         Ok(JsStateDiff::from(diff))
     }
 
+    /// Change counts and approximate byte sizes between two commits.
+    #[napi]
+    pub async fn diff_stats(&self, hash1: String, hash2: String) -> Result<JsDiffStats> {
+        let repo = self.inner.lock().await;
+        let stats = repo.diff_stats(&hash1, &hash2).await.map_err(to_js_error)?;
+        Ok(JsDiffStats::from(stats))
+    }
+
     /// Merge a branch into the current branch.
     /// `strategy`: `"ours"`, `"theirs"`, or `"three_way"`.
+    /// `noFf`: always create a merge commit even if a fast-forward is possible.
     #[napi]
-    pub async fn merge(&self, branch: String, strategy: String) -> Result<String> {
+    pub async fn merge(
+        &self,
+        branch: String,
+        strategy: String,
+        no_ff: Option<bool>,
+    ) -> Result<String> {
         let s = parse_merge_strategy(&strategy)?;
+        let options = MergeOptions {
+            no_ff: no_ff.unwrap_or(false),
+        };
         let mut repo = self.inner.lock().await;
         let hash = repo
-            .merge(&branch, s)
+            .merge_with_options(&branch, s, options)
             .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+            .map_err(to_js_error)?;
         Ok(hash.0)
     }
 
@@ -131,64 +219,328 @@ impl JsRepository {
         let commits = repo
             .log(branch.as_deref(), lim)
             .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+            .map_err(to_js_error)?;
 
         let js_commits = commits
             .into_iter()
-            .map(|c| {
-                let hash = c.hash().0.clone();
-                JsCommit::from((hash, c))
-            })
+            .map(|(hash, c)| JsCommit::from((hash.0, c)))
             .collect();
         Ok(js_commits)
     }
 
+    /// Return true if `ancestor` is reachable from `descendant` via parent links.
+    #[napi]
+    pub async fn is_ancestor(&self, ancestor: String, descendant: String) -> Result<bool> {
+        let repo = self.inner.lock().await;
+        repo.is_ancestor(&ancestor, &descendant)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Return the merge base (common ancestor) hash of two commits.
+    #[napi]
+    pub async fn find_merge_base(&self, hash1: String, hash2: String) -> Result<String> {
+        let repo = self.inner.lock().await;
+        let hash = repo
+            .find_merge_base(&hash1, &hash2)
+            .await
+            .map_err(to_js_error)?;
+        Ok(hash.0)
+    }
+
+    /// Value of a state path over time, newest first, for commits that changed it.
+    #[napi]
+    pub async fn path_history(
+        &self,
+        path: Vec<String>,
+        branch: Option<String>,
+        limit: Option<u32>,
+    ) -> Result<Vec<JsPathHistoryEntry>> {
+        let lim = limit.unwrap_or(100) as usize;
+        let repo = self.inner.lock().await;
+        let history = repo
+            .path_history(&path, branch.as_deref(), lim)
+            .await
+            .map_err(to_js_error)?;
+        Ok(history
+            .into_iter()
+            .map(|(hash, ts, value)| JsPathHistoryEntry {
+                hash: hash.0,
+                timestamp: ts.to_rfc3339(),
+                value: value.map(|v| v.to_string()),
+            })
+            .collect())
+    }
+
+    /// Audit log entries matching `filter`, newest first. Without a limit,
+    /// at most 50 are returned.
+    #[napi]
+    pub async fn audit_log(&self, filter: Option<JsLogFilter>) -> Result<Vec<JsLogEntry>> {
+        let mut filter = LogFilter::try_from(filter.unwrap_or_default())?;
+        filter.limit.get_or_insert(50);
+        let repo = self.inner.lock().await;
+        let entries = repo.audit_log(&filter).await.map_err(to_js_error)?;
+        Ok(entries.into_iter().map(JsLogEntry::from).collect())
+    }
+
+    /// Number of audit log entries matching `filter`, ignoring its limit
+    /// and offset.
+    #[napi]
+    pub async fn count_audit_log(&self, filter: Option<JsLogFilter>) -> Result<u32> {
+        let filter = LogFilter::try_from(filter.unwrap_or_default())?;
+        let repo = self.inner.lock().await;
+        let count = repo.count_audit_log(&filter).await.map_err(to_js_error)?;
+        Ok(count as u32)
+    }
+
+    /// Record a warning entry in the audit log. `details_json` is an
+    /// optional JSON string stored with the entry.
+    #[napi]
+    pub async fn log_warning(&self, message: String, details_json: Option<String>) -> Result<()> {
+        let details = parse_details(details_json)?;
+        let repo = self.inner.lock().await;
+        repo.log_warning(&message, details)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Record an error entry in the audit log. `details_json` is an
+    /// optional JSON string stored with the entry.
+    #[napi]
+    pub async fn log_error(&self, message: String, details_json: Option<String>) -> Result<()> {
+        let details = parse_details(details_json)?;
+        let repo = self.inner.lock().await;
+        repo.log_error(&message, details).await.map_err(to_js_error)
+    }
+
+    /// Remove objects unreachable from any branch. With `dryRun` nothing
+    /// is deleted and the report describes what would be.
+    #[napi]
+    pub async fn gc(&self, options: Option<JsGcOptions>) -> Result<JsGcResult> {
+        let options = GcOptions::from(options.unwrap_or_default());
+        let repo = self.inner.lock().await;
+        let result = repo.gc_with_options(&options).await.map_err(to_js_error)?;
+        Ok(result.into())
+    }
+
+    /// Squash the commits from `fromHash` to `toHash` on `branch` into one.
+    /// Commits after `toHash` are replayed on top of it.
+    #[napi]
+    pub async fn squash(
+        &self,
+        branch: String,
+        from_hash: String,
+        to_hash: String,
+    ) -> Result<JsSquashResult> {
+        let mut repo = self.inner.lock().await;
+        let result = repo
+            .squash(&branch, &from_hash, &to_hash)
+            .await
+            .map_err(to_js_error)?;
+        Ok(result.into())
+    }
+
+    /// Truncate branch histories to the policy's age and count limits and
+    /// collect the commits that fall off.
+    #[napi]
+    pub async fn apply_retention(&self, policy: JsRetentionPolicy) -> Result<JsRetentionResult> {
+        let policy = RetentionPolicy::from(policy);
+        let mut repo = self.inner.lock().await;
+        let result = repo.apply_retention(&policy).await.map_err(to_js_error)?;
+        Ok(result.into())
+    }
+
     /// Create a revert commit that restores the state from the given hash.
     #[napi]
     pub async fn revert(&self, to_hash: String) -> Result<JsAgentState> {
         let mut repo = self.inner.lock().await;
-        let state = repo
-            .revert(&to_hash)
-            .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+        let state = repo.revert(&to_hash).await.map_err(to_js_error)?;
         Ok(JsAgentState::from(state))
     }
 
+    /// Create a revert commit that restores the state from the given hash.
+    /// JSON fields are serialized strings.
+    ///
+    /// @deprecated Use `revert` instead; removed in the next release.
+    #[napi]
+    pub async fn revert_json(&self, to_hash: String) -> Result<JsAgentStateJson> {
+        let mut repo = self.inner.lock().await;
+        let state = repo.revert(&to_hash).await.map_err(to_js_error)?;
+        Ok(JsAgentStateJson::from(state))
+    }
+
     /// Retrieve the agent state stored at the given commit hash.
     #[napi]
     pub async fn get_state(&self, hash: String) -> Result<JsAgentState> {
         let repo = self.inner.lock().await;
-        let state = repo
-            .get_state(&hash)
-            .await
-            .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
+        let state = repo.get_state(&hash).await.map_err(to_js_error)?;
         Ok(JsAgentState::from(state))
     }
 
+    /// Retrieve the agent state stored at the given commit hash.
+    /// JSON fields are serialized strings.
+    ///
+    /// @deprecated Use `getState` instead; removed in the next release.
+    #[napi]
+    pub async fn get_state_json(&self, hash: String) -> Result<JsAgentStateJson> {
+        let repo = self.inner.lock().await;
+        let state = repo.get_state(&hash).await.map_err(to_js_error)?;
+        Ok(JsAgentStateJson::from(state))
+    }
+
+    /// Attribute subsequent commits and audit log entries to `id`.
+    #[napi]
+    pub async fn set_agent_id(&self, id: String) {
+        let mut repo = self.inner.lock().await;
+        repo.set_agent_id(&id);
+    }
+
+    /// Encrypt state fields at rest with a key derived from `key`.
+    #[napi]
+    pub async fn set_encryption_key(&self, key: String) -> Result<()> {
+        #[cfg(feature = "encryption")]
+        {
+            let mut repo = self.inner.lock().await;
+            repo.set_encryption_key(&key).await.map_err(to_js_error)
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            let _ = key;
+            Err(encryption_disabled())
+        }
+    }
+
+    /// Set a raw 32-byte encryption key, e.g. a data key from a KMS,
+    /// instead of deriving one from a passphrase.
+    #[napi]
+    pub async fn set_encryption_key_bytes(&self, key: Buffer) -> Result<()> {
+        #[cfg(feature = "encryption")]
+        {
+            let key: [u8; 32] = key.as_ref().try_into().map_err(|_| {
+                invalid_argument(format!(
+                    "encryption key must be 32 bytes, got {}",
+                    key.len()
+                ))
+            })?;
+            let mut repo = self.inner.lock().await;
+            repo.set_encryption_key_bytes(key)
+                .await
+                .map_err(to_js_error)
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            let _ = key;
+            Err(encryption_disabled())
+        }
+    }
+
+    /// Drop the encryption key and wipe its key material.
+    #[napi]
+    pub async fn clear_encryption_key(&self) -> Result<()> {
+        #[cfg(feature = "encryption")]
+        {
+            let mut repo = self.inner.lock().await;
+            repo.clear_encryption_key();
+            Ok(())
+        }
+        #[cfg(not(feature = "encryption"))]
+        Err(encryption_disabled())
+    }
+
+    /// Generate a random 32-byte key for `setEncryptionKeyBytes`.
+    /// Store it yourself; the repository keeps only its key id.
+    #[napi]
+    pub fn generate_encryption_key() -> Result<Buffer> {
+        #[cfg(feature = "encryption")]
+        {
+            Ok(agit_core::StateEncryptor::generate_key().to_vec().into())
+        }
+        #[cfg(not(feature = "encryption"))]
+        Err(encryption_disabled())
+    }
+
     /// Return the current HEAD hash, or null if the repo has no commits.
     #[napi]
-    pub fn head(&self) -> Option<String> {
+    pub async fn head(&self) -> Option<String> {
+        let repo = self.inner.lock().await;
+        repo.head().ok().map(|h| h.0)
+    }
+
+    /// Like `head`, but returns null instead of waiting when another
+    /// operation holds the repository.
+    #[napi]
+    pub fn try_head(&self) -> Option<String> {
         let repo = self.inner.try_lock().ok()?;
         repo.head().ok().map(|h| h.0)
     }
 
     /// Return the currently checked-out branch name, or null if detached HEAD.
     #[napi]
-    pub fn current_branch(&self) -> Option<String> {
+    pub async fn current_branch(&self) -> Option<String> {
+        let repo = self.inner.lock().await;
+        repo.current_branch().map(|s| s.to_string())
+    }
+
+    /// Like `currentBranch`, but returns null instead of waiting when
+    /// another operation holds the repository.
+    #[napi]
+    pub fn try_current_branch(&self) -> Option<String> {
         let repo = self.inner.try_lock().ok()?;
         repo.current_branch().map(|s| s.to_string())
     }
 
-    /// List all branch names.
+    /// Map every branch name to the hash of its tip commit.
     #[napi]
-    pub fn list_branches(&self) -> Vec<String> {
-        let Ok(repo) = self.inner.try_lock() else {
-            return vec![];
-        };
-        repo.list_branches().keys().cloned().collect()
+    pub async fn list_branches(&self) -> HashMap<String, String> {
+        let repo = self.inner.lock().await;
+        repo.list_branches()
+            .iter()
+            .map(|(name, hash)| (name.clone(), hash.0.clone()))
+            .collect()
+    }
+}
+
+impl JsRepository {
+    async fn init(
+        storage: Box<dyn StorageBackend>,
+        options: Option<JsRepoOptions>,
+    ) -> Result<JsRepository> {
+        let mut repo_options = RepoOptions::default();
+        if let Some(opts) = options {
+            if let Some(bytes) = opts.max_state_bytes {
+                repo_options.max_state_bytes = bytes.max(0) as usize;
+            }
+            if let Some(depth) = opts.max_depth {
+                repo_options.max_depth = depth as usize;
+            }
+            if let Some(keys) = opts.max_keys {
+                repo_options.max_keys = keys as usize;
+            }
+        }
+        let repo = Repository::init_with_options(storage, repo_options)
+            .await
+            .map_err(to_js_error)?;
+        Ok(JsRepository {
+            inner: Arc::new(Mutex::new(repo)),
+        })
     }
 }
 
+#[cfg(not(feature = "encryption"))]
+fn encryption_disabled() -> Error<ErrorCode> {
+    Error::new(
+        ErrorCode::EncryptionDisabled,
+        "encryption feature not enabled in agit-core".to_string(),
+    )
+}
+
+fn parse_details(details_json: Option<String>) -> Result<Option<serde_json::Value>> {
+    details_json
+        .map(|raw| serde_json::from_str(&raw))
+        .transpose()
+        .map_err(|e| invalid_argument(format!("invalid details JSON: {}", e)))
+}
+
 fn parse_action_type(s: &str) -> ActionType {
     match s {
         "tool_call" => ActionType::ToolCall,
@@ -208,9 +560,9 @@ fn parse_merge_strategy(s: &str) -> Result<MergeStrategy> {
         "ours" => Ok(MergeStrategy::Ours),
         "theirs" => Ok(MergeStrategy::Theirs),
         "three_way" | "3way" => Ok(MergeStrategy::ThreeWay),
-        other => Err(Error::new(
-            Status::InvalidArg,
-            format!("unknown merge strategy '{}'; use ours|theirs|three_way", other),
-        )),
+        other => Err(invalid_argument(format!(
+            "unknown merge strategy '{}'; use ours|theirs|three_way",
+            other
+        ))),
     }
 }
@@ -1,10 +1,41 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use napi_derive::napi;
+use serde_json::{Map, Value};
+
+use agit_core::{
+    AgentState, Commit, DiffEntry, DiffStats, GcOptions, GcResult, LogEntry, LogFilter, LogLevel,
+    RetentionPolicy, RetentionResult, SquashResult, StateDiff,
+};
 
-use agit_core::{AgentState, Commit, DiffEntry, StateDiff};
+use crate::error::{invalid_argument, ErrorCode};
 
-/// JS-facing wrapper for AgentState. JSON fields are serialized strings.
+/// JS-facing wrapper for AgentState.
 #[napi(object)]
 pub struct JsAgentState {
+    pub memory: Value,
+    pub world_state: Value,
+    pub timestamp: String,
+    pub cost: f64,
+    /// Metadata object, or undefined when empty
+    pub metadata: Option<Map<String, Value>>,
+}
+
+/// State to commit with `JsRepository.commit`.
+#[napi(object)]
+pub struct JsStateInput {
+    pub memory: Value,
+    /// Defaults to an empty object
+    pub world_state: Option<Value>,
+    pub cost: Option<f64>,
+    pub metadata: Option<Map<String, Value>>,
+}
+
+/// AgentState with its JSON fields as serialized strings, returned by the
+/// deprecated `*Json` methods.
+#[napi(object)]
+pub struct JsAgentStateJson {
     /// JSON string of the memory object
     pub memory: String,
     /// JSON string of the world_state object
@@ -15,6 +46,34 @@ pub struct JsAgentState {
     pub metadata: Option<String>,
 }
 
+/// Options for `JsRepository.open`. Omitted limits keep the core defaults;
+/// 0 means unlimited.
+#[napi(object)]
+pub struct JsRepoOptions {
+    pub max_state_bytes: Option<i64>,
+    pub max_depth: Option<u32>,
+    pub max_keys: Option<u32>,
+}
+
+/// Storage backend for `JsRepository.openWithBackend`.
+#[napi(object)]
+pub struct JsBackendConfig {
+    /// `"sqlite"`, `"postgres"`, or `"s3"`.
+    #[napi(js_name = "type")]
+    pub backend_type: String,
+    /// SQLite database file, or a directory to hold `agit.db`.
+    pub path: Option<String>,
+    /// Postgres connection string.
+    pub connection_string: Option<String>,
+    /// Postgres namespace isolating this repository's data.
+    pub namespace: Option<String>,
+    pub bucket: Option<String>,
+    /// S3 key prefix, e.g. `"agit/"`.
+    pub prefix: Option<String>,
+    /// SQS queue announcing each appended log entry.
+    pub sqs_queue_url: Option<String>,
+}
+
 /// JS-facing wrapper for Commit.
 #[napi(object)]
 pub struct JsCommit {
@@ -25,6 +84,8 @@ pub struct JsCommit {
     pub author: String,
     pub timestamp: String,
     pub action_type: String,
+    /// Metadata recorded on the commit, such as its cost and signature
+    pub metadata: Map<String, Value>,
 }
 
 /// A single entry in a state diff exposed to JS.
@@ -32,24 +93,200 @@ pub struct JsCommit {
 pub struct JsDiffEntry {
     pub path: Vec<String>,
     pub change_type: String,
-    pub old_value: Option<String>,
-    pub new_value: Option<String>,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
 }
 
 /// The diff between two commits exposed to JS.
-#[napi(object)]
+#[napi]
 pub struct JsStateDiff {
-    pub base_hash: String,
-    pub target_hash: String,
-    pub entries: Vec<JsDiffEntry>,
+    inner: StateDiff,
+}
+
+#[napi]
+impl JsStateDiff {
+    #[napi(getter)]
+    pub fn base_hash(&self) -> String {
+        self.inner.base_hash.clone()
+    }
+
+    #[napi(getter)]
+    pub fn target_hash(&self) -> String {
+        self.inner.target_hash.clone()
+    }
+
+    #[napi(getter)]
+    pub fn entries(&self) -> Vec<JsDiffEntry> {
+        self.inner
+            .entries
+            .iter()
+            .cloned()
+            .map(JsDiffEntry::from)
+            .collect()
+    }
+
+    /// RFC 6902 JSON Patch document, serialized as a JSON string.
+    #[napi]
+    pub fn to_json_patch(&self) -> String {
+        self.inner.to_json_patch().to_string()
+    }
+}
+
+/// Change counts between two commits exposed to JS.
+#[napi(object)]
+pub struct JsDiffStats {
+    pub added: u32,
+    pub removed: u32,
+    pub changed: u32,
+    /// Entry count per top-level state key
+    pub by_top_level_key: HashMap<String, u32>,
+    pub bytes_added: i64,
+    pub bytes_removed: i64,
+}
+
+/// Value of a state path at a commit that changed it.
+#[napi(object)]
+pub struct JsPathHistoryEntry {
+    pub hash: String,
+    pub timestamp: String,
+    /// JSON string of the value, or null if the path was absent
+    pub value: Option<String>,
+}
+
+/// Options for `JsRepository.gc`.
+#[napi(object)]
+#[derive(Default)]
+pub struct JsGcOptions {
+    /// Report what would be removed without deleting anything
+    pub dry_run: Option<bool>,
+    /// Always keep at least this many commits per branch
+    pub keep_last_n: Option<u32>,
+}
+
+/// Object count and stored bytes of one object type.
+#[napi(object)]
+pub struct JsTypeStats {
+    pub count: u32,
+    pub bytes: i64,
+}
+
+/// Garbage collection report exposed to JS.
+#[napi(object)]
+pub struct JsGcResult {
+    pub dry_run: bool,
+    pub objects_before: u32,
+    pub objects_removed: u32,
+    pub objects_after: u32,
+    /// Unreachable object keys, capped; see `unreachableCount`
+    pub unreachable: Vec<String>,
+    pub unreachable_count: u32,
+    /// Bytes freed, or that a dry run would free
+    pub bytes_freed: i64,
+    /// Freed objects per object type
+    pub freed_by_type: HashMap<String, JsTypeStats>,
+}
+
+/// Result of `JsRepository.squash`.
+#[napi(object)]
+pub struct JsSquashResult {
+    /// The squashed commit
+    pub new_hash: String,
+    /// The branch's new tip: `newHash`, or the last commit replayed on it
+    pub new_tip: String,
+    pub commits_squashed: u32,
+    /// Hashes of the squashed commits, oldest first
+    pub squashed_hashes: Vec<String>,
+    /// Sum of the squashed states' costs
+    pub aggregated_cost: f64,
+    pub message: String,
+}
+
+/// Policy for `JsRepository.applyRetention`. Omitted limits do not apply.
+#[napi(object)]
+pub struct JsRetentionPolicy {
+    /// Expire commits older than this many seconds
+    pub max_age_seconds: Option<i64>,
+    /// Keep at most this many commits per branch
+    pub max_commits: Option<u32>,
+    /// Branches never truncated; defaults to `["main"]`
+    pub keep_branches: Option<Vec<String>>,
+}
+
+/// Result of `JsRepository.applyRetention`.
+#[napi(object)]
+pub struct JsRetentionResult {
+    pub commits_expired: u32,
+    pub commits_retained: u32,
+    pub branches_truncated: Vec<String>,
+    pub objects_removed: u32,
+}
+
+/// Filter for `JsRepository.auditLog` and `countAuditLog`. Timestamps are
+/// RFC 3339 strings.
+#[napi(object)]
+#[derive(Default)]
+pub struct JsLogFilter {
+    pub agent_id: Option<String>,
+    pub action: Option<String>,
+    /// One of debug, info, warn, error
+    pub level: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub commit_hash: Option<String>,
+    /// Case-sensitive substring of the message
+    pub message_contains: Option<String>,
+    /// Text mentioned in the message or details
+    pub message_query: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// An audit log entry exposed to JS.
+#[napi(object)]
+pub struct JsLogEntry {
+    pub id: String,
+    pub timestamp: String,
+    pub agent_id: String,
+    pub action: String,
+    pub message: String,
+    pub commit_hash: Option<String>,
+    /// JSON string of the details object (or null)
+    pub details: Option<String>,
+    pub level: String,
 }
 
 // ---- Conversion helpers ----
 
 impl From<AgentState> for JsAgentState {
     fn from(s: AgentState) -> Self {
-        let metadata_str = serde_json::to_string(&s.metadata).unwrap_or_else(|_| "{}".into());
         JsAgentState {
+            memory: s.memory,
+            world_state: s.world_state,
+            timestamp: s.timestamp.to_rfc3339(),
+            cost: s.cost,
+            metadata: (!s.metadata.is_empty()).then_some(s.metadata),
+        }
+    }
+}
+
+impl From<JsStateInput> for AgentState {
+    fn from(s: JsStateInput) -> Self {
+        let world_state = s.world_state.unwrap_or_else(|| Value::Object(Map::new()));
+        let mut state = AgentState::new(s.memory, world_state);
+        if let Some(cost) = s.cost {
+            state.cost = cost;
+        }
+        if let Some(metadata) = s.metadata {
+            state.metadata = metadata;
+        }
+        state
+    }
+}
+
+impl From<AgentState> for JsAgentStateJson {
+    fn from(s: AgentState) -> Self {
+        let metadata_str = serde_json::to_string(&s.metadata).unwrap_or_else(|_| "{}".into());
+        JsAgentStateJson {
             memory: serde_json::to_string(&s.memory).unwrap_or_else(|_| "{}".into()),
             world_state: serde_json::to_string(&s.world_state).unwrap_or_else(|_| "{}".into()),
             timestamp: s.timestamp.to_rfc3339(),
@@ -73,6 +310,98 @@ impl From<(String, Commit)> for JsCommit {
             author: c.author,
             timestamp: c.timestamp.to_rfc3339(),
             action_type: c.action_type.to_string(),
+            metadata: c.metadata,
+        }
+    }
+}
+
+impl From<DiffStats> for JsDiffStats {
+    fn from(s: DiffStats) -> Self {
+        JsDiffStats {
+            added: s.added as u32,
+            removed: s.removed as u32,
+            changed: s.changed as u32,
+            by_top_level_key: s
+                .by_top_level_key
+                .into_iter()
+                .map(|(k, v)| (k, v as u32))
+                .collect(),
+            bytes_added: s.bytes_added as i64,
+            bytes_removed: s.bytes_removed as i64,
+        }
+    }
+}
+
+impl From<JsGcOptions> for GcOptions {
+    fn from(o: JsGcOptions) -> Self {
+        GcOptions {
+            dry_run: o.dry_run.unwrap_or(false),
+            keep_last_n: o.keep_last_n.unwrap_or(0) as usize,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<GcResult> for JsGcResult {
+    fn from(r: GcResult) -> Self {
+        JsGcResult {
+            dry_run: r.dry_run,
+            objects_before: r.objects_before as u32,
+            objects_removed: r.objects_removed as u32,
+            objects_after: r.objects_after as u32,
+            unreachable: r.unreachable,
+            unreachable_count: r.unreachable_count as u32,
+            bytes_freed: r.freed.bytes as i64,
+            freed_by_type: r
+                .freed
+                .by_type
+                .into_iter()
+                .map(|(k, v)| {
+                    let stats = JsTypeStats {
+                        count: v.count as u32,
+                        bytes: v.bytes as i64,
+                    };
+                    (k, stats)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<SquashResult> for JsSquashResult {
+    fn from(r: SquashResult) -> Self {
+        JsSquashResult {
+            new_hash: r.new_hash.0,
+            new_tip: r.new_tip.0,
+            commits_squashed: r.commits_squashed as u32,
+            squashed_hashes: r.squashed_hashes.into_iter().map(|h| h.0).collect(),
+            aggregated_cost: r.aggregated_cost,
+            message: r.message,
+        }
+    }
+}
+
+impl From<JsRetentionPolicy> for RetentionPolicy {
+    fn from(p: JsRetentionPolicy) -> Self {
+        let defaults = RetentionPolicy::default();
+        RetentionPolicy {
+            max_age: p
+                .max_age_seconds
+                .map(|secs| Duration::from_secs(secs.max(0) as u64)),
+            max_commits: p.max_commits.map(|n| n as usize),
+            keep_branches: p.keep_branches.unwrap_or(defaults.keep_branches),
+            ..defaults
+        }
+    }
+}
+
+impl From<RetentionResult> for JsRetentionResult {
+    fn from(r: RetentionResult) -> Self {
+        JsRetentionResult {
+            commits_expired: r.commits_expired as u32,
+            commits_retained: r.commits_retained as u32,
+            branches_truncated: r.branches_truncated,
+            objects_removed: r.objects_removed as u32,
         }
     }
 }
@@ -82,18 +411,53 @@ impl From<DiffEntry> for JsDiffEntry {
         JsDiffEntry {
             path: e.path,
             change_type: format!("{:?}", e.change_type).to_lowercase(),
-            old_value: e.old_value.map(|v| v.to_string()),
-            new_value: e.new_value.map(|v| v.to_string()),
+            old_value: e.old_value,
+            new_value: e.new_value,
         }
     }
 }
 
 impl From<StateDiff> for JsStateDiff {
     fn from(d: StateDiff) -> Self {
-        JsStateDiff {
-            base_hash: d.base_hash,
-            target_hash: d.target_hash,
-            entries: d.entries.into_iter().map(JsDiffEntry::from).collect(),
+        JsStateDiff { inner: d }
+    }
+}
+
+impl TryFrom<JsLogFilter> for LogFilter {
+    type Error = napi::Error<ErrorCode>;
+
+    fn try_from(f: JsLogFilter) -> crate::error::Result<Self> {
+        let level = f
+            .level
+            .map(|l| l.parse::<LogLevel>())
+            .transpose()
+            .map_err(|e| invalid_argument(e.to_string()))?;
+        Ok(LogFilter {
+            agent_id: f.agent_id,
+            action: f.action,
+            level,
+            limit: f.limit.map(|l| l as usize),
+            since: f.since,
+            until: f.until,
+            message_query: f.message_query,
+            commit_hash: f.commit_hash,
+            message_contains: f.message_contains,
+            offset: f.offset.map(|o| o as usize),
+        })
+    }
+}
+
+impl From<LogEntry> for JsLogEntry {
+    fn from(e: LogEntry) -> Self {
+        JsLogEntry {
+            id: e.id,
+            timestamp: e.timestamp,
+            agent_id: e.agent_id,
+            action: e.action,
+            message: e.message,
+            commit_hash: e.commit_hash,
+            details: e.details.map(|d| d.to_string()),
+            level: e.level.to_string(),
         }
     }
 }
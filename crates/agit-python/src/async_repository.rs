@@ -0,0 +1,257 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3_async_runtimes::tokio::future_into_py;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use agit_core::{GcOptions, LogFilter, MergeOptions, RepoOptions, Repository};
+
+use crate::backend::BackendArgs;
+use crate::convert::{agent_state_to_py, commit_to_py, diff_to_py, py_to_agent_state};
+use crate::errors::agit_err_to_py;
+use crate::repository::{
+    backend_from_args, diff_options, gc_result_to_py, log_entry_to_py, open_repository,
+    parse_action_type, parse_level, parse_strategy,
+};
+use crate::types::PyAgentState;
+
+/// Asyncio flavour of `Repository`: each method returns an awaitable that
+/// runs on the shared Tokio runtime, so a slow storage call never stalls
+/// the event loop.
+///
+/// Writes (commit, checkout, merge) wait for each other and for reads in
+/// flight; reads run concurrently.
+#[pyclass(name = "AsyncRepository")]
+pub struct PyAsyncRepository {
+    inner: Arc<RwLock<Repository>>,
+}
+
+#[pymethods]
+impl PyAsyncRepository {
+    /// Open or initialize a repository; takes the same arguments as
+    /// `Repository`. Opening blocks briefly with the GIL released.
+    #[new]
+    #[pyo3(signature = (path=None, agent_id=None, max_state_bytes=None, max_depth=None, max_keys=None, *, storage=None, backend=None, dsn=None, namespace=None, bucket=None, prefix=None, sqs_queue_url=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        path: Option<&str>,
+        agent_id: Option<&str>,
+        max_state_bytes: Option<usize>,
+        max_depth: Option<usize>,
+        max_keys: Option<usize>,
+        storage: Option<&str>,
+        backend: Option<String>,
+        dsn: Option<String>,
+        namespace: Option<String>,
+        bucket: Option<String>,
+        prefix: Option<String>,
+        sqs_queue_url: Option<String>,
+    ) -> PyResult<Self> {
+        let backend = backend_from_args(
+            path,
+            storage,
+            BackendArgs {
+                backend,
+                dsn,
+                namespace,
+                bucket,
+                prefix,
+                sqs_queue_url,
+            },
+        )?;
+        let defaults = RepoOptions::default();
+        let options = RepoOptions {
+            max_state_bytes: max_state_bytes.unwrap_or(defaults.max_state_bytes),
+            max_depth: max_depth.unwrap_or(defaults.max_depth),
+            max_keys: max_keys.unwrap_or(defaults.max_keys),
+            ..defaults
+        };
+        let repo = open_repository(py, backend, agent_id, options)?;
+        Ok(PyAsyncRepository {
+            inner: Arc::new(RwLock::new(repo)),
+        })
+    }
+
+    /// Commit an AgentState or dict; resolves to the commit hash string.
+    #[pyo3(signature = (state, message, action_type=None))]
+    fn commit<'py>(
+        &self,
+        py: Python<'py>,
+        state: &Bound<'py, PyAny>,
+        message: String,
+        action_type: Option<String>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let core_state = if let Ok(d) = state.downcast::<PyDict>() {
+            py_to_agent_state(&PyAgentState::from_dict(d)?)
+        } else {
+            py_to_agent_state(&*state.extract::<PyRef<'_, PyAgentState>>()?)
+        };
+        let action = parse_action_type(action_type.as_deref());
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let mut repo = repo.write().await;
+            repo.commit(&core_state, &message, action)
+                .await
+                .map(|h| h.0)
+                .map_err(agit_err_to_py)
+        })
+    }
+
+    /// Checkout a branch or commit hash; resolves to the AgentState there.
+    fn checkout<'py>(&self, py: Python<'py>, target: String) -> PyResult<Bound<'py, PyAny>> {
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let mut repo = repo.write().await;
+            let state = repo.checkout(&target).await.map_err(agit_err_to_py)?;
+            Ok(agent_state_to_py(&state))
+        })
+    }
+
+    /// Merge a branch into the current branch; resolves to the merge
+    /// commit hash. Arguments as for `Repository.merge`.
+    #[pyo3(signature = (branch, strategy=None, no_ff=false))]
+    fn merge<'py>(
+        &self,
+        py: Python<'py>,
+        branch: String,
+        strategy: Option<String>,
+        no_ff: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let strat = parse_strategy(strategy.as_deref());
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let mut repo = repo.write().await;
+            repo.merge_with_options(&branch, strat, MergeOptions { no_ff })
+                .await
+                .map(|h| h.0)
+                .map_err(agit_err_to_py)
+        })
+    }
+
+    /// Diff two commit hashes; resolves to a StateDiff. `ignore` as for
+    /// `Repository.diff`.
+    #[pyo3(signature = (hash1, hash2, ignore=None))]
+    fn diff<'py>(
+        &self,
+        py: Python<'py>,
+        hash1: String,
+        hash2: String,
+        ignore: Option<Vec<String>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let options = diff_options(ignore);
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let repo = repo.read().await;
+            let diff = repo
+                .diff_with_options(&hash1, &hash2, &options)
+                .await
+                .map_err(agit_err_to_py)?;
+            Ok(diff_to_py(&diff))
+        })
+    }
+
+    /// Resolves to the commit history as a list of Commit objects.
+    #[pyo3(signature = (branch=None, limit=None))]
+    fn log<'py>(
+        &self,
+        py: Python<'py>,
+        branch: Option<String>,
+        limit: Option<usize>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let n = limit.unwrap_or(100);
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let repo = repo.read().await;
+            let commits = repo
+                .log(branch.as_deref(), n)
+                .await
+                .map_err(agit_err_to_py)?;
+            Ok(commits
+                .iter()
+                .map(|(hash, c)| commit_to_py(hash, c))
+                .collect::<Vec<_>>())
+        })
+    }
+
+    /// Resolves to the AgentState stored at a commit hash.
+    fn get_state<'py>(&self, py: Python<'py>, hash: String) -> PyResult<Bound<'py, PyAny>> {
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let repo = repo.read().await;
+            let state = repo.get_state(&hash).await.map_err(agit_err_to_py)?;
+            Ok(agent_state_to_py(&state))
+        })
+    }
+
+    /// Garbage-collect unreachable objects; resolves to the same dict as
+    /// `Repository.gc`.
+    #[pyo3(signature = (keep_last_n, dry_run=false))]
+    fn gc<'py>(
+        &self,
+        py: Python<'py>,
+        keep_last_n: usize,
+        dry_run: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let options = GcOptions {
+            dry_run,
+            keep_last_n,
+            ..Default::default()
+        };
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let result = {
+                let repo = repo.read().await;
+                repo.gc_with_options(&options)
+                    .await
+                    .map_err(agit_err_to_py)?
+            };
+            Python::with_gil(|py| gc_result_to_py(py, result))
+        })
+    }
+
+    /// Resolves to audit log entries, newest first, as dicts. Filters as
+    /// for `Repository.audit_log`.
+    #[pyo3(signature = (limit=50, agent_id=None, action=None, level=None, since=None, until=None, message_query=None, commit_hash=None, message_contains=None, offset=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn audit_log<'py>(
+        &self,
+        py: Python<'py>,
+        limit: usize,
+        agent_id: Option<String>,
+        action: Option<String>,
+        level: Option<String>,
+        since: Option<String>,
+        until: Option<String>,
+        message_query: Option<String>,
+        commit_hash: Option<String>,
+        message_contains: Option<String>,
+        offset: Option<usize>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let filter = LogFilter {
+            agent_id,
+            action,
+            level: parse_level(level)?,
+            limit: Some(limit),
+            since,
+            until,
+            message_query,
+            commit_hash,
+            message_contains,
+            offset,
+        };
+        let repo = Arc::clone(&self.inner);
+        future_into_py(py, async move {
+            let entries = {
+                let repo = repo.read().await;
+                repo.audit_log(&filter).await.map_err(agit_err_to_py)?
+            };
+            Python::with_gil(|py| {
+                entries
+                    .into_iter()
+                    .map(|entry| log_entry_to_py(py, entry))
+                    .collect::<PyResult<Vec<_>>>()
+            })
+        })
+    }
+}
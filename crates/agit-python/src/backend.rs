@@ -0,0 +1,196 @@
+//! Storage backend selection for `Repository(...)`.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use agit_core::{SqliteStorage, StorageBackend};
+
+use crate::errors::agit_err_to_py;
+
+/// Backend keyword arguments accepted next to the storage location.
+#[derive(Debug, Default)]
+pub struct BackendArgs {
+    pub backend: Option<String>,
+    pub dsn: Option<String>,
+    pub namespace: Option<String>,
+    pub bucket: Option<String>,
+    pub prefix: Option<String>,
+    pub sqs_queue_url: Option<String>,
+}
+
+/// A parsed backend choice.
+#[derive(Debug)]
+pub enum Backend {
+    Sqlite {
+        path: String,
+    },
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    Postgres {
+        dsn: String,
+        namespace: String,
+    },
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    S3 {
+        bucket: String,
+        prefix: String,
+        sqs_queue_url: Option<String>,
+    },
+}
+
+impl Backend {
+    /// Pick a backend from a storage location and keyword arguments.
+    ///
+    /// With `backend` set the keywords describe the backend and `location`
+    /// is only used as the SQLite path; otherwise `location` is parsed
+    /// with `from_url`.
+    pub fn from_args(location: Option<&str>, args: BackendArgs) -> PyResult<Self> {
+        let Some(kind) = args.backend else {
+            let location = location
+                .ok_or_else(|| PyValueError::new_err("a path or storage URL is required"))?;
+            return match Backend::from_url(location)? {
+                Backend::Postgres { dsn, namespace } => Ok(Backend::Postgres {
+                    dsn,
+                    namespace: args.namespace.unwrap_or(namespace),
+                }),
+                Backend::S3 { bucket, prefix, .. } => Ok(Backend::S3 {
+                    bucket,
+                    prefix,
+                    sqs_queue_url: args.sqs_queue_url,
+                }),
+                sqlite => Ok(sqlite),
+            };
+        };
+        match kind.as_str() {
+            "sqlite" => {
+                let path = location
+                    .ok_or_else(|| PyValueError::new_err("sqlite backend requires a path"))?;
+                Ok(Backend::Sqlite {
+                    path: sqlite_db_path(path),
+                })
+            }
+            "postgres" => Ok(Backend::Postgres {
+                dsn: args
+                    .dsn
+                    .ok_or_else(|| PyValueError::new_err("postgres backend requires `dsn`"))?,
+                namespace: args.namespace.unwrap_or_default(),
+            }),
+            "s3" => Ok(Backend::S3 {
+                bucket: args
+                    .bucket
+                    .ok_or_else(|| PyValueError::new_err("s3 backend requires `bucket`"))?,
+                prefix: args.prefix.unwrap_or_default(),
+                sqs_queue_url: args.sqs_queue_url,
+            }),
+            other => Err(PyValueError::new_err(format!(
+                "unknown backend '{}'; use sqlite|postgres|s3",
+                other
+            ))),
+        }
+    }
+
+    /// Parse a URL-style location: `postgres://…` or `postgresql://…`
+    /// (a `namespace` query parameter selects the tenant),
+    /// `s3://bucket/prefix`, `sqlite://path`, or a plain SQLite path.
+    pub fn from_url(url: &str) -> PyResult<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            let (dsn, namespace) = split_namespace(url);
+            return Ok(Backend::Postgres {
+                dsn,
+                namespace: namespace.unwrap_or_default(),
+            });
+        }
+        if let Some(rest) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(PyValueError::new_err(format!("no bucket in '{}'", url)));
+            }
+            let prefix = prefix.trim_matches('/');
+            return Ok(Backend::S3 {
+                bucket: bucket.to_string(),
+                prefix: if prefix.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", prefix)
+                },
+                sqs_queue_url: None,
+            });
+        }
+        let path = url.strip_prefix("sqlite://").unwrap_or(url);
+        Ok(Backend::Sqlite {
+            path: sqlite_db_path(path),
+        })
+    }
+
+    /// Connect to the backend.
+    pub async fn connect(self) -> PyResult<Box<dyn StorageBackend>> {
+        match self {
+            Backend::Sqlite { path } => Ok(Box::new(
+                SqliteStorage::new(&path).await.map_err(agit_err_to_py)?,
+            )),
+            #[cfg(feature = "postgres")]
+            Backend::Postgres { dsn, namespace } => {
+                let storage = agit_core::storage::PostgresStorage::new_scoped(&dsn, &namespace)
+                    .await
+                    .map_err(agit_err_to_py)?;
+                Ok(Box::new(storage))
+            }
+            #[cfg(not(feature = "postgres"))]
+            Backend::Postgres { .. } => Err(backend_unavailable("postgres")),
+            #[cfg(feature = "s3")]
+            Backend::S3 {
+                bucket,
+                prefix,
+                sqs_queue_url,
+            } => {
+                let storage = agit_core::storage::S3Storage::new(bucket, prefix, sqs_queue_url)
+                    .await
+                    .map_err(agit_err_to_py)?;
+                Ok(Box::new(storage))
+            }
+            #[cfg(not(feature = "s3"))]
+            Backend::S3 { .. } => Err(backend_unavailable("s3")),
+        }
+    }
+}
+
+/// `path` itself if it names a `.db` file or `:memory:`, otherwise
+/// `agit.db` inside it.
+fn sqlite_db_path(path: &str) -> String {
+    if path.ends_with(".db") || path == ":memory:" {
+        path.to_string()
+    } else {
+        format!("{}/agit.db", path.trim_end_matches('/'))
+    }
+}
+
+/// Remove a `namespace` query parameter from a Postgres URL, which the
+/// driver would reject, returning the remaining URL and the namespace.
+fn split_namespace(url: &str) -> (String, Option<String>) {
+    let Some((base, query)) = url.split_once('?') else {
+        return (url.to_string(), None);
+    };
+    let mut namespace = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| match pair.strip_prefix("namespace=") {
+            Some(value) => {
+                namespace = Some(value.to_string());
+                false
+            }
+            None => !pair.is_empty(),
+        })
+        .collect();
+    if rest.is_empty() {
+        (base.to_string(), namespace)
+    } else {
+        (format!("{}?{}", base, rest.join("&")), namespace)
+    }
+}
+
+#[cfg(not(all(feature = "postgres", feature = "s3")))]
+fn backend_unavailable(feature: &str) -> PyErr {
+    pyo3::exceptions::PyRuntimeError::new_err(format!(
+        "{} backend not available: agit_core was built without the `{}` feature",
+        feature, feature
+    ))
+}
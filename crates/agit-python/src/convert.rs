@@ -1,13 +1,15 @@
+use chrono::{DateTime, Utc};
+use pyo3::exceptions::{PyTypeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString};
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
 use serde_json::Value;
 
-use agit_core::{AgentState, Commit, DiffEntry, StateDiff};
+use agit_core::{AgentState, Commit, DiffEntry, Hash, MergeConflict, StateDiff};
 
-use crate::types::{PyAgentState, PyCommit, PyDiffEntry, PyStateDiff};
+use crate::types::{PyAgentState, PyCommit, PyDiffEntry, PyMergeConflict, PyStateDiff};
 
 /// Recursively convert a Python dict/list/primitive to a serde_json::Value.
-#[allow(dead_code)]
+/// Non-string keys are converted with `str()`.
 pub fn py_dict_to_json(dict: &Bound<'_, PyDict>) -> Value {
     let mut map = serde_json::Map::new();
     for (k, v) in dict.iter() {
@@ -18,7 +20,6 @@ pub fn py_dict_to_json(dict: &Bound<'_, PyDict>) -> Value {
 }
 
 /// Convert any Python object to a serde_json::Value.
-#[allow(dead_code)]
 pub fn py_any_to_json(obj: &Bound<'_, PyAny>) -> Value {
     // None
     if obj.is_none() {
@@ -33,6 +34,9 @@ pub fn py_any_to_json(obj: &Bound<'_, PyAny>) -> Value {
         if let Ok(n) = i.extract::<i64>() {
             return Value::Number(n.into());
         }
+        if let Ok(n) = i.extract::<u64>() {
+            return Value::Number(n.into());
+        }
     }
     // float
     if let Ok(f) = obj.downcast::<PyFloat>() {
@@ -49,15 +53,37 @@ pub fn py_any_to_json(obj: &Bound<'_, PyAny>) -> Value {
     if let Ok(d) = obj.downcast::<PyDict>() {
         return py_dict_to_json(d);
     }
-    // list / tuple / any sequence
+    // list / tuple
     if let Ok(lst) = obj.downcast::<PyList>() {
         let arr: Vec<Value> = lst.iter().map(|item| py_any_to_json(&item)).collect();
         return Value::Array(arr);
     }
+    if let Ok(tup) = obj.downcast::<PyTuple>() {
+        let arr: Vec<Value> = tup.iter().map(|item| py_any_to_json(&item)).collect();
+        return Value::Array(arr);
+    }
     // fallback: try to extract as str repr
     Value::String(obj.str().map(|s| s.to_string()).unwrap_or_default())
 }
 
+/// JSON text of a state field given as a dict or as a JSON string.
+/// `None` gives an empty object; strings are passed through unchanged.
+pub fn py_state_field_to_json(obj: Option<&Bound<'_, PyAny>>) -> PyResult<String> {
+    let Some(obj) = obj.filter(|o| !o.is_none()) else {
+        return Ok("{}".to_string());
+    };
+    if let Ok(s) = obj.downcast::<PyString>() {
+        return Ok(s.to_string());
+    }
+    if let Ok(d) = obj.downcast::<PyDict>() {
+        return Ok(py_dict_to_json(d).to_string());
+    }
+    Err(PyTypeError::new_err(format!(
+        "expected a dict or a JSON string, got {}",
+        obj.get_type().name()?
+    )))
+}
+
 /// Recursively convert a serde_json::Value to a Python object.
 pub fn json_to_py_object(py: Python<'_>, value: &Value) -> PyObject {
     match value {
@@ -90,6 +116,13 @@ pub fn json_to_py_object(py: Python<'_>, value: &Value) -> PyObject {
     }
 }
 
+/// Parse an RFC 3339 timestamp as stored on the Python wrappers.
+pub fn parse_timestamp(ts: &str) -> PyResult<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(ts)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| PyValueError::new_err(format!("invalid timestamp '{}': {}", ts, e)))
+}
+
 /// Convert an agit-core AgentState to its Python wrapper.
 pub fn agent_state_to_py(state: &AgentState) -> PyAgentState {
     PyAgentState {
@@ -98,8 +131,7 @@ pub fn agent_state_to_py(state: &AgentState) -> PyAgentState {
             .unwrap_or_else(|_| "{}".to_string()),
         timestamp: state.timestamp.to_rfc3339(),
         cost: state.cost,
-        metadata_json: serde_json::to_string(&state.metadata)
-            .unwrap_or_else(|_| "{}".to_string()),
+        metadata_json: serde_json::to_string(&state.metadata).unwrap_or_else(|_| "{}".to_string()),
     }
 }
 
@@ -126,17 +158,16 @@ pub fn py_to_agent_state(py_state: &PyAgentState) -> agit_core::AgentState {
 
 /// Convert an agit-core Commit to its Python wrapper.
 /// The commit hash must be pre-computed and passed separately.
-pub fn commit_to_py(commit: &Commit) -> PyCommit {
+pub fn commit_to_py(hash: &Hash, commit: &Commit) -> PyCommit {
     PyCommit {
-        hash: commit.hash().0.clone(),
+        hash: hash.0.clone(),
         tree_hash: commit.tree_hash.0.clone(),
         parent_hashes: commit.parent_hashes.iter().map(|h| h.0.clone()).collect(),
         message: commit.message.clone(),
         author: commit.author.clone(),
         timestamp: commit.timestamp.to_rfc3339(),
         action_type: commit.action_type.to_string(),
-        metadata_json: serde_json::to_string(&commit.metadata)
-            .unwrap_or_else(|_| "{}".to_string()),
+        metadata_json: serde_json::to_string(&commit.metadata).unwrap_or_else(|_| "{}".to_string()),
     }
 }
 
@@ -166,5 +197,16 @@ pub fn diff_to_py(diff: &StateDiff) -> PyStateDiff {
         base_hash: diff.base_hash.clone(),
         target_hash: diff.target_hash.clone(),
         entries: diff.entries.iter().map(diff_entry_to_py).collect(),
+        inner: diff.clone(),
+    }
+}
+
+/// Convert an agit-core MergeConflict to its Python wrapper.
+pub fn merge_conflict_to_py(conflict: MergeConflict) -> PyMergeConflict {
+    PyMergeConflict {
+        path: conflict.dotted_path(),
+        base_value: conflict.base_value,
+        ours_value: conflict.ours_value,
+        theirs_value: conflict.theirs_value,
     }
 }
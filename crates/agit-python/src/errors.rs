@@ -0,0 +1,115 @@
+//! Exception classes raised for `agit_core::AgitError`.
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+
+use agit_core::AgitError as CoreError;
+
+use crate::convert::merge_conflict_to_py;
+
+create_exception!(
+    agit_core,
+    AgitError,
+    PyRuntimeError,
+    "Base class of errors raised by agit; `code` is a stable error code."
+);
+create_exception!(
+    agit_core,
+    MergeConflictError,
+    AgitError,
+    "A three-way merge left conflicts; `conflicts` holds a MergeConflict for each, \
+     `paths` lists them dot-joined."
+);
+create_exception!(
+    agit_core,
+    BranchNotFoundError,
+    AgitError,
+    "No branch has the name in `branch`."
+);
+create_exception!(
+    agit_core,
+    ObjectNotFoundError,
+    AgitError,
+    "No object is stored under the hash in `hash`."
+);
+create_exception!(
+    agit_core,
+    DetachedHeadError,
+    AgitError,
+    "The operation needs a checked-out branch, but HEAD is detached."
+);
+create_exception!(
+    agit_core,
+    NoCommitsError,
+    AgitError,
+    "The branch has no commits yet."
+);
+create_exception!(
+    agit_core,
+    StorageError,
+    AgitError,
+    "The storage backend failed; `retryable` marks transient failures."
+);
+
+/// Convert an agit_core::AgitError to the matching Python exception.
+pub fn agit_err_to_py(e: CoreError) -> PyErr {
+    let message = e.to_string();
+    let code = e.code();
+    Python::with_gil(|py| {
+        let (err, attrs): (PyErr, Vec<(&str, PyObject)>) = match e {
+            CoreError::MergeConflict { conflicts } => {
+                let paths: Vec<String> = conflicts.iter().map(|c| c.dotted_path()).collect();
+                let conflicts: Vec<_> = conflicts.into_iter().map(merge_conflict_to_py).collect();
+                (
+                    MergeConflictError::new_err(message),
+                    vec![
+                        ("paths", paths.into_pyobject(py)?.into_any().unbind()),
+                        (
+                            "conflicts",
+                            conflicts.into_pyobject(py)?.into_any().unbind(),
+                        ),
+                    ],
+                )
+            }
+            CoreError::BranchNotFound { name } => (
+                BranchNotFoundError::new_err(message),
+                vec![("branch", name.into_pyobject(py)?.into_any().unbind())],
+            ),
+            CoreError::ObjectNotFound { hash } => (
+                ObjectNotFoundError::new_err(message),
+                vec![("hash", hash.into_pyobject(py)?.into_any().unbind())],
+            ),
+            CoreError::DetachedHead => (DetachedHeadError::new_err(message), vec![]),
+            CoreError::NoCommits => (NoCommitsError::new_err(message), vec![]),
+            CoreError::Storage { retryable, .. } => (
+                StorageError::new_err(message),
+                vec![(
+                    "retryable",
+                    retryable.into_pyobject(py)?.to_owned().into_any().unbind(),
+                )],
+            ),
+            _ => (AgitError::new_err(message), vec![]),
+        };
+        let value = err.value(py);
+        value.setattr("code", code)?;
+        for (name, attr) in attrs {
+            value.setattr(name, attr)?;
+        }
+        Ok(err)
+    })
+    .unwrap_or_else(|e: PyErr| e)
+}
+
+/// Add the exception classes to the module.
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("AgitError", py.get_type::<AgitError>())?;
+    m.add("MergeConflictError", py.get_type::<MergeConflictError>())?;
+    m.add("BranchNotFoundError", py.get_type::<BranchNotFoundError>())?;
+    m.add("ObjectNotFoundError", py.get_type::<ObjectNotFoundError>())?;
+    m.add("DetachedHeadError", py.get_type::<DetachedHeadError>())?;
+    m.add("NoCommitsError", py.get_type::<NoCommitsError>())?;
+    m.add("StorageError", py.get_type::<StorageError>())?;
+    Ok(())
+}
@@ -3,17 +3,35 @@ use pyo3::prelude::*;
 #[pymodule]
 fn agit_core(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("__version__", "0.1.0")?;
+    // Awaitables from AsyncRepository run on the runtime the blocking calls use
+    let _ = pyo3_async_runtimes::tokio::init_with_runtime(repository::get_runtime());
     m.add_class::<PyRepository>()?;
+    m.add_class::<PyAsyncRepository>()?;
+    m.add_class::<PyLogWatch>()?;
+    m.add_class::<PyCommitIter>()?;
     m.add_class::<PyAgentState>()?;
+    m.add_class::<PyAgentStateBuilder>()?;
     m.add_class::<PyCommit>()?;
     m.add_class::<PyStateDiff>()?;
     m.add_class::<PyDiffEntry>()?;
+    m.add_class::<PyMergeConflict>()?;
+    m.add_class::<PyMergePreview>()?;
+    m.add_function(wrap_pyfunction!(migrate::migrate, m)?)?;
+    errors::register(m)?;
     Ok(())
 }
 
+mod async_repository;
+mod backend;
 mod convert;
+mod errors;
+mod migrate;
 mod repository;
 mod types;
 
-pub use repository::PyRepository;
-pub use types::{PyAgentState, PyCommit, PyDiffEntry, PyStateDiff};
+pub use async_repository::PyAsyncRepository;
+pub use repository::{PyCommitIter, PyLogWatch, PyRepository};
+pub use types::{
+    PyAgentState, PyAgentStateBuilder, PyCommit, PyDiffEntry, PyMergeConflict, PyMergePreview,
+    PyStateDiff,
+};
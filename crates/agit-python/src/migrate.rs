@@ -0,0 +1,102 @@
+//! `agit_core.migrate`: copy a repository between storage backends.
+
+use pyo3::exceptions::PyKeyboardInterrupt;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Mutex;
+
+use agit_core::migration::{migrate_cancellable, MigrationOptions, MigrationProgress};
+use agit_core::CancellationToken;
+
+use crate::backend::Backend;
+use crate::errors::agit_err_to_py;
+use crate::repository::{block_on, WATCH_SIGNAL_CHECK};
+
+/// Copy every object and ref from the `source` storage to `target`.
+///
+/// Both are storage URLs or paths as accepted by `Repository`. Objects
+/// already in the target are skipped, so an interrupted migration can be
+/// rerun. `progress_callback` is called with `(phase, current, total)`,
+/// `phase` being "objects" or "refs", after every batch of `batch_size`
+/// objects and every ref.
+///
+/// Returns a dict of `total_objects`, `migrated_objects`,
+/// `skipped_objects`, `total_refs`, `migrated_refs` and `cancelled`.
+/// Ctrl-C stops the copy after the current batch and returns the partial
+/// counts with `cancelled` set; any other exception raised by
+/// `progress_callback` stops it the same way and is re-raised.
+#[pyfunction]
+#[pyo3(signature = (source, target, progress_callback=None, batch_size=None))]
+pub fn migrate(
+    py: Python<'_>,
+    source: &str,
+    target: &str,
+    progress_callback: Option<PyObject>,
+    batch_size: Option<usize>,
+) -> PyResult<PyObject> {
+    let source = Backend::from_url(source)?;
+    let target = Backend::from_url(target)?;
+    let options = MigrationOptions {
+        batch_size: batch_size.unwrap_or(MigrationOptions::default().batch_size),
+    };
+
+    let cancel = CancellationToken::new();
+    let interrupt: Mutex<Option<PyErr>> = Mutex::new(None);
+    let stop = |err: PyErr| {
+        interrupt.lock().unwrap().get_or_insert(err);
+        cancel.cancel();
+    };
+    let on_progress = |p: MigrationProgress| {
+        let Some(callback) = &progress_callback else {
+            return;
+        };
+        Python::with_gil(|py| {
+            if let Err(e) = callback.call1(py, (p.phase, p.current, p.total)) {
+                stop(e);
+            }
+        });
+    };
+    // Ctrl-C is only seen by checking for signals with the GIL held
+    let watch_signals = async {
+        while !cancel.is_cancelled() {
+            tokio::time::sleep(WATCH_SIGNAL_CHECK).await;
+            if let Err(e) = Python::with_gil(|py| py.check_signals()) {
+                stop(e);
+            }
+        }
+        std::future::pending::<()>().await
+    };
+    let result = block_on(py, async {
+        let source = source.connect().await?;
+        let target = target.connect().await?;
+        let result = tokio::select! {
+            biased;
+            result = migrate_cancellable(
+                source.as_ref(),
+                target.as_ref(),
+                &options,
+                Some(on_progress),
+                &cancel,
+            ) => result,
+            _ = watch_signals => unreachable!(),
+        };
+        source.close().await.map_err(agit_err_to_py)?;
+        target.close().await.map_err(agit_err_to_py)?;
+        result.map_err(agit_err_to_py)
+    });
+    if let Some(err) = interrupt.into_inner().unwrap() {
+        if !err.is_instance_of::<PyKeyboardInterrupt>(py) {
+            return Err(err);
+        }
+    }
+    let result = result?;
+
+    let d = PyDict::new(py);
+    d.set_item("total_objects", result.total_objects)?;
+    d.set_item("migrated_objects", result.migrated_objects)?;
+    d.set_item("skipped_objects", result.skipped_objects)?;
+    d.set_item("total_refs", result.total_refs)?;
+    d.set_item("migrated_refs", result.migrated_refs)?;
+    d.set_item("cancelled", result.cancelled)?;
+    Ok(d.into())
+}
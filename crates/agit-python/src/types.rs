@@ -1,7 +1,13 @@
 use pyo3::prelude::*;
 use pyo3::types::{PyDict, PyList};
 
-use crate::convert::json_to_py_object;
+use agit_core::AgentStateBuilder;
+use chrono::{DateTime, Utc};
+
+use crate::convert::{
+    agent_state_to_py, json_to_py_object, parse_timestamp, py_any_to_json, py_dict_to_json,
+    py_state_field_to_json, py_to_agent_state,
+};
 
 /// Python wrapper for AgentState.
 /// Stores JSON-serialized fields internally for easy FFI crossing.
@@ -17,16 +23,51 @@ pub struct PyAgentState {
 
 #[pymethods]
 impl PyAgentState {
+    /// `memory` and `world_state` may each be a dict or a JSON string.
     #[new]
-    #[pyo3(signature = (memory=None, world_state=None, cost=0.0))]
-    fn new(memory: Option<&str>, world_state: Option<&str>, cost: f64) -> Self {
-        PyAgentState {
-            memory_json: memory.unwrap_or("{}").to_string(),
-            world_state_json: world_state.unwrap_or("{}").to_string(),
+    #[pyo3(signature = (memory=None, world_state=None, cost=0.0, *, metadata=None))]
+    fn new(
+        memory: Option<&Bound<'_, PyAny>>,
+        world_state: Option<&Bound<'_, PyAny>>,
+        cost: f64,
+        metadata: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        Ok(PyAgentState {
+            memory_json: py_state_field_to_json(memory)?,
+            world_state_json: py_state_field_to_json(world_state)?,
             timestamp: chrono::Utc::now().to_rfc3339(),
             cost,
-            metadata_json: "{}".to_string(),
+            metadata_json: metadata
+                .map(|m| py_dict_to_json(m).to_string())
+                .unwrap_or_else(|| "{}".to_string()),
+        })
+    }
+
+    /// Build a state from a dict with `memory`, `world_state`, `cost`,
+    /// `metadata` and `timestamp` keys, as returned by `to_dict`. Missing
+    /// keys take their defaults.
+    #[staticmethod]
+    pub fn from_dict(d: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let cost = match d.get_item("cost")? {
+            Some(c) if !c.is_none() => c.extract()?,
+            _ => 0.0,
+        };
+        let metadata = d.get_item("metadata")?;
+        let metadata = metadata
+            .as_ref()
+            .filter(|m| !m.is_none())
+            .map(|m| m.downcast::<PyDict>())
+            .transpose()?;
+        let mut state = Self::new(
+            d.get_item("memory")?.as_ref(),
+            d.get_item("world_state")?.as_ref(),
+            cost,
+            metadata,
+        )?;
+        if let Some(ts) = d.get_item("timestamp")?.filter(|t| !t.is_none()) {
+            state.timestamp = ts.extract()?;
         }
+        Ok(state)
     }
 
     /// Return the memory field as a Python dict.
@@ -45,16 +86,54 @@ impl PyAgentState {
         Ok(json_to_py_object(py, &value))
     }
 
+    /// Return the metadata field as a Python dict.
+    #[getter]
+    fn metadata(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let value: serde_json::Value = serde_json::from_str(&self.metadata_json)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        Ok(json_to_py_object(py, &value))
+    }
+
     #[getter]
     fn timestamp(&self) -> &str {
         &self.timestamp
     }
 
+    /// `timestamp` as a timezone-aware `datetime.datetime` in UTC.
+    #[getter]
+    fn timestamp_dt(&self) -> PyResult<DateTime<Utc>> {
+        parse_timestamp(&self.timestamp)
+    }
+
     #[getter]
     fn cost(&self) -> f64 {
         self.cost
     }
 
+    /// Conversation messages at `memory["messages"]`, or None.
+    fn messages(&self, py: Python<'_>) -> PyObject {
+        let state = py_to_agent_state(self);
+        optional_json_to_py(py, state.messages())
+    }
+
+    /// Plan at `memory["plan"]`, or None.
+    fn plan(&self, py: Python<'_>) -> PyObject {
+        let state = py_to_agent_state(self);
+        optional_json_to_py(py, state.plan())
+    }
+
+    /// Scratchpad at `memory["scratchpad"]`, or None.
+    fn scratchpad(&self, py: Python<'_>) -> PyObject {
+        let state = py_to_agent_state(self);
+        optional_json_to_py(py, state.scratchpad())
+    }
+
+    /// State of a tool at `world_state["tools"][name]`, or None.
+    fn tool_state(&self, py: Python<'_>, name: &str) -> PyObject {
+        let state = py_to_agent_state(self);
+        optional_json_to_py(py, state.tool_state(name))
+    }
+
     /// Return a Python dict representation of the full state.
     fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
         let d = PyDict::new(py);
@@ -67,6 +146,7 @@ impl PyAgentState {
         d.set_item("memory", json_to_py_object(py, &mem))?;
         d.set_item("world_state", json_to_py_object(py, &ws))?;
         d.set_item("timestamp", self.timestamp.clone())?;
+        d.set_item("timestamp_dt", parse_timestamp(&self.timestamp)?)?;
         d.set_item("cost", self.cost)?;
         d.set_item("metadata", json_to_py_object(py, &meta))?;
         Ok(d.into())
@@ -80,6 +160,110 @@ impl PyAgentState {
     }
 }
 
+fn optional_json_to_py(py: Python<'_>, value: Option<&serde_json::Value>) -> PyObject {
+    match value {
+        Some(v) => json_to_py_object(py, v),
+        None => py.None(),
+    }
+}
+
+/// Python wrapper for AgentStateBuilder. Methods return the builder for chaining.
+#[pyclass(name = "AgentStateBuilder")]
+#[derive(Clone, Default)]
+pub struct PyAgentStateBuilder {
+    inner: AgentStateBuilder,
+}
+
+impl PyAgentStateBuilder {
+    fn update(
+        mut slf: PyRefMut<'_, Self>,
+        f: impl FnOnce(AgentStateBuilder) -> AgentStateBuilder,
+    ) -> PyRefMut<'_, Self> {
+        let inner = std::mem::take(&mut slf.inner);
+        slf.inner = f(inner);
+        slf
+    }
+}
+
+#[pymethods]
+impl PyAgentStateBuilder {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn messages<'py>(
+        slf: PyRefMut<'py, Self>,
+        messages: Vec<Bound<'py, PyAny>>,
+    ) -> PyRefMut<'py, Self> {
+        let messages = messages.iter().map(py_any_to_json).collect();
+        Self::update(slf, |b| b.messages(messages))
+    }
+
+    fn message<'py>(slf: PyRefMut<'py, Self>, message: &Bound<'py, PyAny>) -> PyRefMut<'py, Self> {
+        let message = py_any_to_json(message);
+        Self::update(slf, |b| b.message(message))
+    }
+
+    fn plan<'py>(slf: PyRefMut<'py, Self>, plan: &Bound<'py, PyAny>) -> PyRefMut<'py, Self> {
+        let plan = py_any_to_json(plan);
+        Self::update(slf, |b| b.plan(plan))
+    }
+
+    fn scratchpad<'py>(
+        slf: PyRefMut<'py, Self>,
+        scratchpad: &Bound<'py, PyAny>,
+    ) -> PyRefMut<'py, Self> {
+        let scratchpad = py_any_to_json(scratchpad);
+        Self::update(slf, |b| b.scratchpad(scratchpad))
+    }
+
+    fn tool_state<'py>(
+        slf: PyRefMut<'py, Self>,
+        name: &str,
+        state: &Bound<'py, PyAny>,
+    ) -> PyRefMut<'py, Self> {
+        let state = py_any_to_json(state);
+        Self::update(slf, |b| b.tool_state(name, state))
+    }
+
+    fn memory<'py>(
+        slf: PyRefMut<'py, Self>,
+        key: &str,
+        value: &Bound<'py, PyAny>,
+    ) -> PyRefMut<'py, Self> {
+        let value = py_any_to_json(value);
+        Self::update(slf, |b| b.memory(key, value))
+    }
+
+    fn world_state<'py>(
+        slf: PyRefMut<'py, Self>,
+        key: &str,
+        value: &Bound<'py, PyAny>,
+    ) -> PyRefMut<'py, Self> {
+        let value = py_any_to_json(value);
+        Self::update(slf, |b| b.world_state(key, value))
+    }
+
+    fn metadata<'py>(
+        slf: PyRefMut<'py, Self>,
+        key: &str,
+        value: &Bound<'py, PyAny>,
+    ) -> PyRefMut<'py, Self> {
+        let value = py_any_to_json(value);
+        Self::update(slf, |b| b.metadata(key, value))
+    }
+
+    fn cost(slf: PyRefMut<'_, Self>, cost: f64) -> PyRefMut<'_, Self> {
+        Self::update(slf, |b| b.cost(cost))
+    }
+
+    /// Build the AgentState. The builder can be reused afterwards.
+    fn build(&self) -> PyAgentState {
+        agent_state_to_py(&self.inner.clone().build())
+    }
+}
+
 /// Python wrapper for a Commit object.
 #[pyclass(name = "Commit")]
 #[derive(Clone)]
@@ -101,6 +285,12 @@ impl PyCommit {
         &self.hash
     }
 
+    /// First 8 characters of the hash.
+    #[getter]
+    fn short_hash(&self) -> &str {
+        &self.hash[..8.min(self.hash.len())]
+    }
+
     #[getter]
     fn tree_hash(&self) -> &str {
         &self.tree_hash
@@ -111,6 +301,12 @@ impl PyCommit {
         self.parent_hashes.clone()
     }
 
+    /// True for commits with more than one parent.
+    #[getter]
+    fn is_merge(&self) -> bool {
+        self.parent_hashes.len() > 1
+    }
+
     #[getter]
     fn message(&self) -> &str {
         &self.message
@@ -126,6 +322,12 @@ impl PyCommit {
         &self.timestamp
     }
 
+    /// `timestamp` as a timezone-aware `datetime.datetime` in UTC.
+    #[getter]
+    fn timestamp_dt(&self) -> PyResult<DateTime<Utc>> {
+        parse_timestamp(&self.timestamp)
+    }
+
     #[getter]
     fn action_type(&self) -> &str {
         &self.action_type
@@ -148,6 +350,7 @@ impl PyCommit {
         d.set_item("message", self.message.clone())?;
         d.set_item("author", self.author.clone())?;
         d.set_item("timestamp", self.timestamp.clone())?;
+        d.set_item("timestamp_dt", parse_timestamp(&self.timestamp)?)?;
         d.set_item("action_type", self.action_type.clone())?;
         d.set_item("metadata", json_to_py_object(py, &meta))?;
         Ok(d.into())
@@ -156,7 +359,7 @@ impl PyCommit {
     fn __repr__(&self) -> String {
         format!(
             "Commit(hash={}, message={:?}, author={})",
-            &self.hash[..8.min(self.hash.len())],
+            self.short_hash(),
             self.message,
             self.author,
         )
@@ -225,6 +428,7 @@ pub struct PyStateDiff {
     pub base_hash: String,
     pub target_hash: String,
     pub entries: Vec<PyDiffEntry>,
+    pub inner: agit_core::StateDiff,
 }
 
 #[pymethods]
@@ -252,6 +456,11 @@ impl PyStateDiff {
         self.entries.len()
     }
 
+    /// Return the diff as an RFC 6902 JSON Patch (list of operation dicts).
+    fn to_json_patch(&self, py: Python<'_>) -> PyObject {
+        json_to_py_object(py, &self.inner.to_json_patch())
+    }
+
     fn __repr__(&self) -> String {
         format!(
             "StateDiff(base={}, target={}, entries={})",
@@ -261,3 +470,102 @@ impl PyStateDiff {
         )
     }
 }
+
+/// Python wrapper for a path both sides of a merge changed differently.
+#[pyclass(name = "MergeConflict")]
+#[derive(Clone)]
+pub struct PyMergeConflict {
+    pub path: String,
+    pub base_value: Option<serde_json::Value>,
+    pub ours_value: Option<serde_json::Value>,
+    pub theirs_value: Option<serde_json::Value>,
+}
+
+#[pymethods]
+impl PyMergeConflict {
+    /// Dotted state path, as used for `merge_resolved` keys.
+    #[getter]
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[getter]
+    fn base_value(&self, py: Python<'_>) -> PyObject {
+        self.base_value
+            .as_ref()
+            .map_or_else(|| py.None(), |v| json_to_py_object(py, v))
+    }
+
+    #[getter]
+    fn ours_value(&self, py: Python<'_>) -> PyObject {
+        self.ours_value
+            .as_ref()
+            .map_or_else(|| py.None(), |v| json_to_py_object(py, v))
+    }
+
+    #[getter]
+    fn theirs_value(&self, py: Python<'_>) -> PyObject {
+        self.theirs_value
+            .as_ref()
+            .map_or_else(|| py.None(), |v| json_to_py_object(py, v))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("MergeConflict(path={})", self.path)
+    }
+}
+
+/// Python wrapper for the outcome of a merge that has not been committed.
+#[pyclass(name = "MergePreview")]
+#[derive(Clone)]
+pub struct PyMergePreview {
+    pub base_hash: String,
+    pub ours_hash: String,
+    pub theirs_hash: String,
+    pub conflicts: Vec<PyMergeConflict>,
+    pub merged_state: PyAgentState,
+}
+
+#[pymethods]
+impl PyMergePreview {
+    #[getter]
+    fn base_hash(&self) -> &str {
+        &self.base_hash
+    }
+
+    #[getter]
+    fn ours_hash(&self) -> &str {
+        &self.ours_hash
+    }
+
+    #[getter]
+    fn theirs_hash(&self) -> &str {
+        &self.theirs_hash
+    }
+
+    #[getter]
+    fn conflicts(&self) -> Vec<PyMergeConflict> {
+        self.conflicts.clone()
+    }
+
+    /// Merged state, holding our value at each conflicting path.
+    #[getter]
+    fn merged_state(&self) -> PyAgentState {
+        self.merged_state.clone()
+    }
+
+    /// True when the merge would succeed without resolutions.
+    #[getter]
+    fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MergePreview(ours={}, theirs={}, conflicts={})",
+            &self.ours_hash[..8.min(self.ours_hash.len())],
+            &self.theirs_hash[..8.min(self.theirs_hash.len())],
+            self.conflicts.len()
+        )
+    }
+}
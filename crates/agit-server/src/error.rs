@@ -0,0 +1,83 @@
+//! Mapping `AgitError` to HTTP responses.
+
+use agit_core::{AgitError, MergeConflict};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+/// An error response: `{"error": {"code": ..., "message": ..., "paths": [...]}}`.
+/// `paths` and `conflicts` are only present for merge conflicts.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    conflicts: Option<Vec<MergeConflict>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            conflicts: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_argument", message)
+    }
+}
+
+impl From<AgitError> for ApiError {
+    fn from(e: AgitError) -> Self {
+        let (status, code) = match &e {
+            AgitError::ObjectNotFound { .. } => (StatusCode::NOT_FOUND, "object_not_found"),
+            AgitError::RefNotFound { .. } => (StatusCode::NOT_FOUND, "ref_not_found"),
+            AgitError::BranchNotFound { .. } => (StatusCode::NOT_FOUND, "branch_not_found"),
+            AgitError::NoCommits => (StatusCode::NOT_FOUND, "no_commits"),
+            AgitError::BranchExists { .. } => (StatusCode::CONFLICT, "branch_exists"),
+            AgitError::BranchCheckedOut { .. } => (StatusCode::CONFLICT, "branch_checked_out"),
+            AgitError::MergeConflict { .. } => (StatusCode::CONFLICT, "merge_conflict"),
+            AgitError::NoCommonAncestor { .. } => (StatusCode::CONFLICT, "no_common_ancestor"),
+            AgitError::ConcurrentUpdate { .. } => (StatusCode::CONFLICT, "concurrent_update"),
+            AgitError::ProtectedBranch { .. } => (StatusCode::FORBIDDEN, "protected_branch"),
+            AgitError::LockTimeout { .. } => (StatusCode::SERVICE_UNAVAILABLE, "lock_timeout"),
+            AgitError::InvalidArgument(_)
+            | AgitError::DepthLimitExceeded(_)
+            | AgitError::StateTooLarge { .. }
+            | AgitError::StateTooDeep { .. }
+            | AgitError::StateTooManyKeys { .. } => (StatusCode::BAD_REQUEST, "invalid_argument"),
+            AgitError::SchemaViolation { .. } => (StatusCode::BAD_REQUEST, "schema_violation"),
+            AgitError::InvalidOperation(_) | AgitError::DetachedHead => {
+                (StatusCode::CONFLICT, "invalid_operation")
+            }
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+        let message = e.to_string();
+        let conflicts = match e {
+            AgitError::MergeConflict { conflicts } => Some(conflicts),
+            _ => None,
+        };
+        ApiError {
+            status,
+            code,
+            message,
+            conflicts,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        if let Some(conflicts) = self.conflicts {
+            let paths: Vec<String> = conflicts.iter().map(MergeConflict::dotted_path).collect();
+            error["paths"] = json!(paths);
+            error["conflicts"] = json!(conflicts);
+        }
+        (self.status, Json(json!({ "error": error }))).into_response()
+    }
+}
@@ -0,0 +1,118 @@
+//! HTTP service exposing an agit repository to remote agent workers.
+//!
+//! [`router`] wraps one [`Repository`] in an axum `Router`. Request and
+//! response bodies are JSON mirroring the core types; `OPENAPI` describes
+//! them. Requests are served one at a time against the repository, and
+//! commits go through its compare-and-swap ref updates, so several server
+//! processes can share one storage without clobbering each other's refs.
+
+mod error;
+mod routes;
+
+use std::sync::Arc;
+
+use agit_core::Repository;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::Router;
+use tokio::sync::Mutex;
+
+pub use error::ApiError;
+
+/// OpenAPI 3 description of the service, also served at `/openapi.json`.
+pub const OPENAPI: &str = include_str!("../openapi.json");
+
+/// Header naming the agent a request acts for; it becomes the commit
+/// author and audit agent id.
+pub const AGENT_HEADER: &str = "x-agit-agent";
+
+/// Agent id used when a request does not send `AGENT_HEADER`.
+pub const DEFAULT_AGENT: &str = "server";
+
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub(crate) repo: Arc<Mutex<Repository>>,
+    token: Option<Arc<str>>,
+}
+
+/// Build the service around `repo`. With `token` set, every request except
+/// `/health` and `/openapi.json` must send `Authorization: Bearer <token>`.
+pub fn router(repo: Repository, token: Option<String>) -> Router {
+    let state = AppState {
+        repo: Arc::new(Mutex::new(repo)),
+        token: token.map(Arc::from),
+    };
+    let api = Router::new()
+        .route("/commits", get(routes::log).post(routes::commit))
+        .route("/commits/{hash}/state", get(routes::get_state))
+        .route("/diff", get(routes::diff))
+        .route("/checkout", post(routes::checkout))
+        .route(
+            "/branches",
+            get(routes::branches).post(routes::create_branch),
+        )
+        .route("/branches/{name}", delete(routes::delete_branch))
+        .route("/merge", post(routes::merge))
+        .route("/gc", post(routes::gc))
+        .route("/audit", get(routes::audit))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token));
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/openapi.json", get(openapi))
+        .merge(api)
+        .with_state(state)
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+async fn require_token(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(token) = &state.token else {
+        return next.run(request).await;
+    };
+    let sent = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match sent {
+        Some(sent) if constant_time_eq(sent.as_bytes(), token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "missing or invalid token",
+        )
+        .into_response(),
+    }
+}
+
+/// Compare without returning early, so response timing does not reveal
+/// how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_is_valid_json() {
+        let spec: serde_json::Value = serde_json::from_str(OPENAPI).unwrap();
+        assert_eq!(spec["openapi"], "3.0.3");
+        assert!(spec["paths"]["/commits"]["post"].is_object());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,83 @@
+//! `agit-server` — serve an agit repository over HTTP.
+
+use std::path::Path;
+
+use agit_core::{Repository, SqliteStorage, StorageBackend};
+use clap::{Arg, Command};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn cli() -> Command {
+    Command::new("agit-server")
+        .about("Serve an agit repository over HTTP")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::new("repo")
+                .short('r')
+                .long("repo")
+                .default_value(".")
+                .help("Repository directory (SQLite); created if missing"),
+        )
+        .arg(
+            Arg::new("postgres")
+                .long("postgres")
+                .conflicts_with("repo")
+                .help("Postgres connection string to use instead of SQLite"),
+        )
+        .arg(
+            Arg::new("bind")
+                .short('b')
+                .long("bind")
+                .default_value("127.0.0.1:7878")
+                .help("Address to listen on"),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Bearer token clients must send (default: $AGIT_SERVER_TOKEN)"),
+        )
+}
+
+async fn storage(matches: &clap::ArgMatches) -> Result<Box<dyn StorageBackend>> {
+    if let Some(dsn) = matches.get_one::<String>("postgres") {
+        return postgres(dsn).await;
+    }
+    let dir = Path::new(matches.get_one::<String>("repo").expect("has default"));
+    std::fs::create_dir_all(dir)?;
+    let db = dir.join("agit.db");
+    Ok(Box::new(SqliteStorage::new(&db.to_string_lossy()).await?))
+}
+
+#[cfg(feature = "postgres")]
+async fn postgres(dsn: &str) -> Result<Box<dyn StorageBackend>> {
+    Ok(Box::new(
+        agit_core::storage::PostgresStorage::new(dsn).await?,
+    ))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn postgres(_dsn: &str) -> Result<Box<dyn StorageBackend>> {
+    Err(
+        "postgres backend not available: agit-server was built without the `postgres` feature"
+            .into(),
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = cli().get_matches();
+    let token = matches
+        .get_one::<String>("token")
+        .cloned()
+        .or_else(|| std::env::var("AGIT_SERVER_TOKEN").ok())
+        .filter(|t| !t.is_empty());
+
+    let repo = Repository::init(storage(&matches).await?).await?;
+    let app = agit_server::router(repo, token);
+    let bind = matches.get_one::<String>("bind").expect("has default");
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    eprintln!("agit-server listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,324 @@
+//! Request handlers. Bodies mirror the core types; commits are returned
+//! with their `hash` added.
+
+use agit_core::gc::GcOptions;
+use agit_core::{
+    ActionType, AgentState, Commit, Hash, LogEntry, LogFilter, MergeOptions, MergeStrategy,
+    Repository, StateDiff,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::sync::MutexGuard;
+
+use crate::{ApiError, AppState, AGENT_HEADER, DEFAULT_AGENT};
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Lock the repository for one request, acting as the request's agent.
+///
+/// Refs are re-read first so that writes made through other processes
+/// sharing the storage are seen.
+async fn open<'a>(
+    app: &'a AppState,
+    headers: &HeaderMap,
+) -> Result<MutexGuard<'a, Repository>, ApiError> {
+    let mut repo = app.repo.lock().await;
+    let agent = headers
+        .get(AGENT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .unwrap_or(DEFAULT_AGENT);
+    repo.set_agent_id(agent);
+    repo.refresh_refs().await?;
+    Ok(repo)
+}
+
+fn commit_json((hash, commit): &(Hash, Commit)) -> Result<Value, ApiError> {
+    let mut value = serde_json::to_value(commit).map_err(agit_core::AgitError::from)?;
+    if let Value::Object(map) = &mut value {
+        map.insert("hash".to_string(), Value::from(hash.as_str()));
+    }
+    Ok(value)
+}
+
+/// Agent state to commit. Unlike `AgentState`, the timestamp is set by the
+/// server.
+#[derive(Deserialize)]
+pub(crate) struct StateBody {
+    memory: Value,
+    #[serde(default = "empty_object")]
+    world_state: Value,
+    #[serde(default)]
+    cost: f64,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+}
+
+fn empty_object() -> Value {
+    json!({})
+}
+
+fn default_action() -> ActionType {
+    ActionType::Checkpoint
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CommitRequest {
+    state: StateBody,
+    message: String,
+    #[serde(default = "default_action")]
+    action_type: ActionType,
+    /// Branch to commit to (default: the current branch).
+    branch: Option<String>,
+    /// Commit the branch must be at, for optimistic concurrency.
+    parent: Option<String>,
+}
+
+/// `POST /commits`. Only the first commit of a repository may create its
+/// branch; otherwise the branch must exist.
+pub(crate) async fn commit(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CommitRequest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let mut repo = open(&app, &headers).await?;
+    let branch = match req
+        .branch
+        .or_else(|| repo.current_branch().map(String::from))
+    {
+        Some(branch) => branch,
+        None => return Err(ApiError::bad_request("HEAD is detached; name a branch")),
+    };
+    let tip = repo.list_branches().get(&branch).cloned();
+    if let Some(parent) = &req.parent {
+        if tip.as_ref().map(|h| h.as_str()) != Some(parent.as_str()) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "concurrent_update",
+                format!("branch '{}' is no longer at {}", branch, parent),
+            ));
+        }
+    }
+
+    let mut state = AgentState::new(req.state.memory, req.state.world_state);
+    state.cost = req.state.cost;
+    state.metadata = req.state.metadata;
+    let create = repo.list_branches().is_empty();
+    let hash = repo
+        .commit_to_branch(&branch, &state, &req.message, req.action_type, create)
+        .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "hash": hash.as_str(), "branch": branch })),
+    ))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LogQuery {
+    branch: Option<String>,
+    #[serde(default = "default_log_limit")]
+    limit: usize,
+}
+
+fn default_log_limit() -> usize {
+    50
+}
+
+/// `GET /commits`: history of a branch (default: HEAD), newest first.
+pub(crate) async fn log(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<LogQuery>,
+) -> ApiResult<Vec<Value>> {
+    let repo = open(&app, &headers).await?;
+    let commits = repo.log(query.branch.as_deref(), query.limit).await?;
+    Ok(Json(
+        commits.iter().map(commit_json).collect::<Result<_, _>>()?,
+    ))
+}
+
+/// `GET /commits/{hash}/state`.
+pub(crate) async fn get_state(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Path(hash): Path<String>,
+) -> ApiResult<AgentState> {
+    let repo = open(&app, &headers).await?;
+    Ok(Json(repo.get_state(&hash).await?))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DiffQuery {
+    from: String,
+    to: String,
+}
+
+/// `GET /diff?from=<hash>&to=<hash>`.
+pub(crate) async fn diff(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<DiffQuery>,
+) -> ApiResult<StateDiff> {
+    let repo = open(&app, &headers).await?;
+    Ok(Json(repo.diff(&query.from, &query.to).await?))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CheckoutRequest {
+    target: String,
+}
+
+/// `POST /checkout`. HEAD is shared by every client of the server.
+pub(crate) async fn checkout(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CheckoutRequest>,
+) -> ApiResult<Value> {
+    let mut repo = open(&app, &headers).await?;
+    let state = repo.checkout(&req.target).await?;
+    Ok(Json(json!({
+        "branch": repo.current_branch(),
+        "head": repo.head()?.as_str(),
+        "state": state,
+    })))
+}
+
+/// `GET /branches`.
+pub(crate) async fn branches(State(app): State<AppState>, headers: HeaderMap) -> ApiResult<Value> {
+    let repo = open(&app, &headers).await?;
+    let branches: Map<String, Value> = repo
+        .list_branches()
+        .iter()
+        .map(|(name, hash)| (name.clone(), Value::from(hash.as_str())))
+        .collect();
+    Ok(Json(json!({
+        "current": repo.current_branch(),
+        "branches": branches,
+    })))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BranchRequest {
+    name: String,
+    from: Option<String>,
+}
+
+/// `POST /branches`: create a branch at `from` (default: HEAD).
+pub(crate) async fn create_branch(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<BranchRequest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let mut repo = open(&app, &headers).await?;
+    repo.branch(&req.name, req.from.as_deref()).await?;
+    let hash = repo
+        .list_branches()
+        .get(&req.name)
+        .map(|h| h.as_str().to_string());
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "name": req.name, "hash": hash })),
+    ))
+}
+
+/// `DELETE /branches/{name}`.
+pub(crate) async fn delete_branch(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut repo = open(&app, &headers).await?;
+    repo.delete_branch(&name, false).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn default_strategy() -> MergeStrategy {
+    MergeStrategy::ThreeWay
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MergeRequest {
+    branch: String,
+    #[serde(default = "default_strategy")]
+    strategy: MergeStrategy,
+    #[serde(default)]
+    no_ff: bool,
+}
+
+/// `POST /merge`: merge `branch` into the current branch.
+pub(crate) async fn merge(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<MergeRequest>,
+) -> ApiResult<Value> {
+    let mut repo = open(&app, &headers).await?;
+    let options = MergeOptions { no_ff: req.no_ff };
+    let hash = repo
+        .merge_with_options(&req.branch, req.strategy, options)
+        .await?;
+    Ok(Json(json!({ "hash": hash.as_str() })))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GcRequest {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    keep_last_n: usize,
+}
+
+/// `POST /gc`.
+pub(crate) async fn gc(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<GcRequest>,
+) -> ApiResult<Value> {
+    let repo = open(&app, &headers).await?;
+    let options = GcOptions {
+        dry_run: req.dry_run,
+        keep_last_n: req.keep_last_n,
+        ..Default::default()
+    };
+    let result = repo.gc_with_options(&options).await?;
+    Ok(Json(json!({
+        "dry_run": result.dry_run,
+        "objects_before": result.objects_before,
+        "objects_removed": result.objects_removed,
+        "objects_after": result.objects_after,
+        "unreachable": result.unreachable,
+        "unreachable_count": result.unreachable_count,
+        "bytes_freed": result.freed.bytes,
+    })))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AuditQuery {
+    agent_id: Option<String>,
+    action: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    commit_hash: Option<String>,
+    limit: Option<usize>,
+}
+
+/// `GET /audit`: audit log entries, newest first.
+pub(crate) async fn audit(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> ApiResult<Vec<LogEntry>> {
+    let repo = open(&app, &headers).await?;
+    let filter = LogFilter {
+        agent_id: query.agent_id,
+        action: query.action,
+        since: query.since,
+        until: query.until,
+        commit_hash: query.commit_hash,
+        limit: Some(query.limit.unwrap_or(100)),
+        ..Default::default()
+    };
+    Ok(Json(repo.audit_log(&filter).await?))
+}
@@ -0,0 +1,243 @@
+//! Runs the server in-process on a local port and talks to it over HTTP.
+
+use agit_core::{Repository, SqliteStorage};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+struct TestServer {
+    base: String,
+    client: Client,
+    _dir: TempDir,
+}
+
+impl TestServer {
+    async fn start(token: Option<&str>) -> Self {
+        let dir = TempDir::new().unwrap();
+        let db = dir.path().join("agit.db");
+        let storage = SqliteStorage::new(db.to_str().unwrap()).await.unwrap();
+        let repo = Repository::init(Box::new(storage)).await.unwrap();
+        let app = agit_server::router(repo, token.map(String::from));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        TestServer {
+            base,
+            client: Client::new(),
+            _dir: dir,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    async fn get(&self, path: &str) -> Value {
+        let resp = self.client.get(self.url(path)).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "GET {}", path);
+        resp.json().await.unwrap()
+    }
+
+    async fn post(&self, path: &str, body: Value) -> (StatusCode, Value) {
+        let resp = self
+            .client
+            .post(self.url(path))
+            .header(agit_server::AGENT_HEADER, "worker-1")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        (resp.status(), resp.json().await.unwrap())
+    }
+
+    async fn commit(&self, memory: Value, message: &str) -> String {
+        let body = json!({ "state": { "memory": memory }, "message": message });
+        let (status, resp) = self.post("/commits", body).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", resp);
+        resp["hash"].as_str().unwrap().to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_commit_log_diff_flow() {
+    let server = TestServer::start(None).await;
+    let first = server
+        .commit(json!({"step": 1, "plan": "a"}), "first")
+        .await;
+    let second = server.commit(json!({"step": 2}), "second").await;
+
+    let log = server.get("/commits?limit=10").await;
+    let log = log.as_array().unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0]["hash"], second.as_str());
+    assert_eq!(log[0]["parent_hashes"], json!([first]));
+    assert_eq!(log[0]["author"], "worker-1");
+    assert_eq!(log[0]["action_type"], "checkpoint");
+
+    let diff = server
+        .get(&format!("/diff?from={}&to={}", first, second))
+        .await;
+    assert_eq!(diff["base_hash"], first.as_str());
+    let memory: Vec<&Value> = diff["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["path"][0] == "memory")
+        .collect();
+    assert_eq!(memory.len(), 2);
+    assert!(memory
+        .iter()
+        .any(|e| e["path"] == json!(["memory", "step"]) && e["change_type"] == "changed"));
+
+    let state = server.get(&format!("/commits/{}/state", first)).await;
+    assert_eq!(state["memory"], json!({"step": 1, "plan": "a"}));
+
+    let audit = server.get("/audit?agent_id=worker-1").await;
+    assert_eq!(audit.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_branches_and_merge() {
+    let server = TestServer::start(None).await;
+    let base = server.commit(json!({"step": 1}), "base").await;
+    let (status, created) = server.post("/branches", json!({ "name": "feature" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["hash"], base.as_str());
+
+    let body = json!({
+        "state": { "memory": {"step": 1, "x": true} },
+        "message": "on feature",
+        "branch": "feature",
+    });
+    let (status, _) = server.post("/commits", body).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let branches = server.get("/branches").await;
+    assert_eq!(branches["current"], "main");
+    assert_eq!(branches["branches"]["main"], base.as_str());
+
+    let (status, merged) = server.post("/merge", json!({ "branch": "feature" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", merged);
+    let log = server.get("/commits?limit=1").await;
+    assert_eq!(log[0]["hash"], merged["hash"]);
+
+    let resp = server
+        .client
+        .delete(server.url("/branches/feature"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let (status, err) = server.post("/merge", json!({ "branch": "feature" })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(err["error"]["code"], "branch_not_found");
+}
+
+#[tokio::test]
+async fn test_merge_conflict_lists_conflicts() {
+    let server = TestServer::start(None).await;
+    server.commit(json!({"plan": "a"}), "base").await;
+    let (status, _) = server.post("/branches", json!({ "name": "feature" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body = json!({
+        "state": { "memory": {"plan": "b"} },
+        "message": "theirs",
+        "branch": "feature",
+    });
+    let (status, _) = server.post("/commits", body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    server.commit(json!({"plan": "c"}), "ours").await;
+
+    let (status, err) = server.post("/merge", json!({ "branch": "feature" })).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", err);
+    assert_eq!(err["error"]["code"], "merge_conflict");
+    let conflicts = err["error"]["conflicts"].as_array().unwrap();
+    let plan = conflicts
+        .iter()
+        .find(|c| c["path"] == json!(["memory", "plan"]))
+        .unwrap();
+    assert_eq!(plan["base_value"], "a");
+    assert_eq!(plan["ours_value"], "c");
+    assert_eq!(plan["theirs_value"], "b");
+    let paths = err["error"]["paths"].as_array().unwrap();
+    assert!(paths.contains(&json!("memory.plan")));
+}
+
+#[tokio::test]
+async fn test_stale_parent_is_rejected() {
+    let server = TestServer::start(None).await;
+    let first = server.commit(json!({"step": 1}), "first").await;
+    server.commit(json!({"step": 2}), "second").await;
+
+    let body = json!({ "state": { "memory": {"step": 3} }, "message": "late", "parent": first });
+    let (status, err) = server.post("/commits", body).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(err["error"]["code"], "concurrent_update");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_parallel_commits_all_land() {
+    let server = std::sync::Arc::new(TestServer::start(None).await);
+    server.commit(json!({"step": 0}), "base").await;
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for i in 1..=8 {
+        let server = server.clone();
+        tasks.spawn(async move { server.commit(json!({"step": i}), "parallel").await });
+    }
+    let hashes = tasks.join_all().await;
+
+    let log = server.get("/commits?limit=100").await;
+    let log = log.as_array().unwrap();
+    assert_eq!(log.len(), 9);
+    for hash in hashes {
+        assert!(log.iter().any(|c| c["hash"] == hash.as_str()));
+    }
+    // Every commit built on the one before it rather than replacing it
+    assert!(log[..8]
+        .iter()
+        .all(|c| c["parent_hashes"].as_array().unwrap().len() == 1));
+}
+
+#[tokio::test]
+async fn test_bearer_token() {
+    let server = TestServer::start(Some("s3cret")).await;
+
+    let resp = server
+        .client
+        .get(server.url("/commits"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = server
+        .client
+        .get(server.url("/branches"))
+        .bearer_auth("wrong")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = server
+        .client
+        .get(server.url("/branches"))
+        .bearer_auth("s3cret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let spec = server.get("/openapi.json").await;
+    assert_eq!(spec["info"]["title"], "agit server");
+    assert_eq!(
+        server
+            .client
+            .get(server.url("/health"))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        200
+    );
+}
@@ -0,0 +1,99 @@
+//! WebAssembly bindings for the storage-free parts of agit-core.
+//!
+//! Everything here runs locally in the browser: building agent states,
+//! diffing, three-way merging, hashing and applying diffs. Values cross
+//! the boundary as plain JS objects shaped like the core types' JSON.
+
+use agit_core::hash::{canonical_serialize, compute_state_hash};
+use agit_core::state::{diff_states, three_way_merge};
+use agit_core::{merkle_diff, AgentState, StateDiff};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use wasm_bindgen::prelude::*;
+
+fn from_js<T: DeserializeOwned>(value: JsValue) -> Result<T, JsError> {
+    Ok(serde_wasm_bindgen::from_value(value)?)
+}
+
+/// Objects become plain JS objects rather than `Map`s.
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    Ok(value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+/// An `AgentState` stamped with the current time. `worldState` and
+/// `metadata` default to `{}`.
+#[wasm_bindgen(js_name = createState)]
+pub fn create_state(
+    memory: JsValue,
+    world_state: JsValue,
+    metadata: JsValue,
+) -> Result<JsValue, JsError> {
+    let world_state: Option<Value> = from_js(world_state)?;
+    let metadata: Option<Map<String, Value>> = from_js(metadata)?;
+    let mut state = AgentState::new(from_js(memory)?, world_state.unwrap_or_else(|| json!({})));
+    state.metadata = metadata.unwrap_or_default();
+    to_js(&state)
+}
+
+/// Changed paths between two JSON values, as `DiffEntry` objects.
+#[wasm_bindgen(js_name = merkleDiff)]
+pub fn merkle_diff_js(base: JsValue, target: JsValue) -> Result<JsValue, JsError> {
+    let base: Value = from_js(base)?;
+    let target: Value = from_js(target)?;
+    to_js(&merkle_diff(&base, &target))
+}
+
+/// The `StateDiff` between two agent states, as `Repository::diff` computes
+/// it for two commits.
+#[wasm_bindgen(js_name = diffStates)]
+pub fn diff_states_js(base: JsValue, target: JsValue) -> Result<JsValue, JsError> {
+    let base: AgentState = from_js(base)?;
+    let target: AgentState = from_js(target)?;
+    to_js(&diff_states(&base, &target))
+}
+
+/// Three-way merge of JSON values, returning `{merged, conflicts}`.
+/// Conflicting paths keep `ours`.
+#[wasm_bindgen(js_name = threeWayMerge)]
+pub fn three_way_merge_js(
+    base: JsValue,
+    ours: JsValue,
+    theirs: JsValue,
+) -> Result<JsValue, JsError> {
+    let base: Value = from_js(base)?;
+    let ours: Value = from_js(ours)?;
+    let theirs: Value = from_js(theirs)?;
+    let (merged, conflicts) = three_way_merge(&base, &ours, &theirs);
+    to_js(&json!({ "merged": merged, "conflicts": conflicts }))
+}
+
+/// The canonical JSON text that state hashes are computed over.
+#[wasm_bindgen(js_name = canonicalSerialize)]
+pub fn canonical_serialize_js(value: JsValue) -> Result<String, JsError> {
+    let value: Value = from_js(value)?;
+    Ok(String::from_utf8(canonical_serialize(&value))?)
+}
+
+/// Hex SHA-256 of a state value, matching the hashes stored by agit.
+#[wasm_bindgen(js_name = computeStateHash)]
+pub fn compute_state_hash_js(value: JsValue) -> Result<String, JsError> {
+    let value: Value = from_js(value)?;
+    Ok(compute_state_hash(&value).0)
+}
+
+/// Replay a `StateDiff` onto `base`. Throws if `base` does not hold the
+/// values the diff expects.
+#[wasm_bindgen(js_name = applyDiff)]
+pub fn apply_diff(diff: JsValue, base: JsValue) -> Result<JsValue, JsError> {
+    let diff: StateDiff = from_js(diff)?;
+    let base: Value = from_js(base)?;
+    to_js(&diff.apply(&base)?)
+}
+
+/// A `StateDiff` as text, one `+`, `-` or `~` line per changed path.
+#[wasm_bindgen(js_name = renderDiff)]
+pub fn render_diff(diff: JsValue) -> Result<String, JsError> {
+    let diff: StateDiff = from_js(diff)?;
+    Ok(diff.render())
+}
@@ -0,0 +1,98 @@
+//! Checks that the JS-facing functions agree with calling agit-core
+//! directly. Run with `wasm-pack test --node crates/agit-wasm`.
+#![cfg(target_arch = "wasm32")]
+
+use agit_core::hash::compute_state_hash;
+use agit_core::state::three_way_merge;
+use agit_core::{merkle_diff, StateDiff};
+use agit_wasm::*;
+use serde_json::{json, Value};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+
+fn js(value: &Value) -> JsValue {
+    use serde::Serialize;
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap()
+}
+
+fn rust(value: JsValue) -> Value {
+    serde_wasm_bindgen::from_value(value).unwrap()
+}
+
+fn base() -> Value {
+    json!({"step": 1, "plan": ["a", "b"], "tools": {"search": {"calls": 2}}})
+}
+
+fn target() -> Value {
+    json!({"step": 2, "plan": ["a", "b", "c"], "tools": {"search": {"calls": 3}}, "done": true})
+}
+
+#[wasm_bindgen_test]
+fn test_merkle_diff_parity() {
+    let entries = rust(merkle_diff_js(js(&base()), js(&target())).unwrap());
+    let native = serde_json::to_value(merkle_diff(&base(), &target())).unwrap();
+    assert_eq!(entries, native);
+}
+
+#[wasm_bindgen_test]
+fn test_three_way_merge_parity() {
+    let ours = json!({"step": 2, "plan": ["a", "b"], "tools": {"search": {"calls": 2}}});
+    let theirs = json!({"step": 3, "plan": ["a", "b"], "tools": {"search": {"calls": 5}}});
+    let result = rust(three_way_merge_js(js(&base()), js(&ours), js(&theirs)).unwrap());
+
+    let (merged, conflicts) = three_way_merge(&base(), &ours, &theirs);
+    assert_eq!(result["merged"], merged);
+    assert_eq!(
+        result["conflicts"],
+        serde_json::to_value(&conflicts).unwrap()
+    );
+    assert_eq!(conflicts.len(), 1);
+}
+
+#[wasm_bindgen_test]
+fn test_hash_parity() {
+    let value = target();
+    assert_eq!(
+        compute_state_hash_js(js(&value)).unwrap(),
+        compute_state_hash(&value).0
+    );
+    let canonical = canonical_serialize_js(js(&value)).unwrap();
+    assert!(canonical.starts_with(r#"{"done":true,"plan""#));
+}
+
+#[wasm_bindgen_test]
+fn test_apply_and_render() {
+    let diff = StateDiff {
+        base_hash: String::new(),
+        target_hash: String::new(),
+        entries: merkle_diff(&base(), &target()),
+    };
+    let diff_js = js(&serde_json::to_value(&diff).unwrap());
+
+    assert_eq!(
+        rust(apply_diff(diff_js.clone(), js(&base())).unwrap()),
+        target()
+    );
+    assert!(apply_diff(diff_js.clone(), js(&json!({"step": 9}))).is_err());
+    assert_eq!(render_diff(diff_js).unwrap(), diff.render());
+}
+
+#[wasm_bindgen_test]
+fn test_create_and_diff_states() {
+    let a = create_state(js(&base()), JsValue::UNDEFINED, JsValue::UNDEFINED).unwrap();
+    let b = create_state(
+        js(&target()),
+        js(&json!({"env": "web"})),
+        JsValue::UNDEFINED,
+    )
+    .unwrap();
+    assert_eq!(rust(a.clone())["world_state"], json!({}));
+
+    let diff = rust(diff_states_js(a, b).unwrap());
+    let entries = diff["entries"].as_array().unwrap();
+    let paths: Vec<&Value> = entries.iter().map(|e| &e["path"]).collect();
+    assert!(paths.contains(&&json!(["memory", "done"])));
+    assert!(paths.contains(&&json!(["world_state", "env"])));
+}
@@ -19,10 +19,24 @@ try:
 
     # Native module exports names without Py prefix
     PyRepository = getattr(agit_core, "PyRepository", None) or agit_core.Repository
+    PyAsyncRepository = agit_core.AsyncRepository
     PyAgentState = getattr(agit_core, "PyAgentState", None) or agit_core.AgentState
     PyCommit = getattr(agit_core, "PyCommit", None) or agit_core.Commit
     PyStateDiff = getattr(agit_core, "PyStateDiff", None) or agit_core.StateDiff
     PyDiffEntry = getattr(agit_core, "PyDiffEntry", None) or agit_core.DiffEntry
+    PyMergePreview = agit_core.MergePreview
+    PyMergeConflict = agit_core.MergeConflict
+    from agit_core import (  # type: ignore[import]
+        AgitError,
+        BranchNotFoundError,
+        DetachedHeadError,
+        MergeConflictError,
+        NoCommitsError,
+        ObjectNotFoundError,
+        StorageError,
+        migrate,
+    )
+
     NATIVE_AVAILABLE = True
 except (ImportError, AttributeError) as exc:
     if not _ALLOW_STUBS:
@@ -34,11 +48,22 @@ except (ImportError, AttributeError) as exc:
     import warnings
 
     from agit._stubs import (  # type: ignore[import]
+        AgitError,
+        BranchNotFoundError,
+        DetachedHeadError,
+        MergeConflictError,
+        NoCommitsError,
+        ObjectNotFoundError,
         PyAgentState,
+        PyAsyncRepository,
         PyCommit,
         PyDiffEntry,
+        PyMergeConflict,
+        PyMergePreview,
         PyRepository,
         PyStateDiff,
+        StorageError,
+        migrate,
     )
 
     NATIVE_AVAILABLE = False
@@ -60,11 +85,23 @@ __all__ = [
     "ValidatorRegistry",
     # Core types (native or stub)
     "PyRepository",
+    "PyAsyncRepository",
     "PyAgentState",
     "PyCommit",
     "PyStateDiff",
     "PyDiffEntry",
+    "PyMergePreview",
+    "PyMergeConflict",
     "NATIVE_AVAILABLE",
+    "migrate",
+    # Exceptions (native or stub)
+    "AgitError",
+    "MergeConflictError",
+    "BranchNotFoundError",
+    "ObjectNotFoundError",
+    "DetachedHeadError",
+    "NoCommitsError",
+    "StorageError",
 ]
 
 __version__ = "0.1.0"
@@ -1,6 +1,7 @@
 """Pure-Python fallback stubs when agit_core native module is unavailable."""
 from __future__ import annotations
 
+import asyncio
 import hashlib
 import json
 import sqlite3
@@ -8,27 +9,135 @@ import threading
 import time
 import uuid
 from dataclasses import dataclass, field
+from datetime import datetime, timezone
 from pathlib import Path
-from typing import Any
+from typing import Any, Callable, Iterator
+
+
+# ---------------------------------------------------------------------------
+# Exceptions mirroring agit_core's
+# ---------------------------------------------------------------------------
+
+class AgitError(RuntimeError):
+    """Base class of errors raised by agit; ``code`` is a stable error code."""
+
+    code = "AGIT_UNKNOWN"
+
+    def __init__(self, message: str = "", code: str | None = None) -> None:
+        super().__init__(message)
+        if code is not None:
+            self.code = code
+
+
+class MergeConflictError(AgitError):
+    """A three-way merge left conflicts; ``conflicts`` holds a MergeConflict for
+    each, ``paths`` lists them dot-joined."""
+
+    code = "AGIT_MERGE_CONFLICT"
+
+    def __init__(
+        self,
+        message: str,
+        paths: list[str] | None = None,
+        conflicts: list[PyMergeConflict] | None = None,
+    ) -> None:
+        super().__init__(message)
+        self.paths = paths or []
+        self.conflicts = conflicts or []
+
+
+class BranchNotFoundError(AgitError):
+    """No branch has the name in ``branch``."""
+
+    code = "AGIT_BRANCH_NOT_FOUND"
+
+    def __init__(self, branch: str) -> None:
+        super().__init__(f"branch not found: {branch}")
+        self.branch = branch
+
+
+class ObjectNotFoundError(AgitError):
+    """No object is stored under the hash in ``hash``."""
+
+    code = "AGIT_OBJECT_NOT_FOUND"
+
+    def __init__(self, hash: str) -> None:  # noqa: A002
+        super().__init__(f"object not found: {hash}")
+        self.hash = hash
+
+
+class DetachedHeadError(AgitError):
+    """The operation needs a checked-out branch, but HEAD is detached."""
+
+    code = "AGIT_DETACHED_HEAD"
+
+
+class NoCommitsError(AgitError):
+    """The branch has no commits yet."""
+
+    code = "AGIT_NO_COMMITS"
+
+
+class StorageError(AgitError):
+    """The storage backend failed; ``retryable`` marks transient failures."""
+
+    code = "AGIT_STORAGE"
+
+    def __init__(self, message: str, retryable: bool = False) -> None:
+        super().__init__(message)
+        self.retryable = retryable
 
 
 # ---------------------------------------------------------------------------
 # Data classes mirroring the Rust types
 # ---------------------------------------------------------------------------
 
+def _state_field(value: dict[str, Any] | str | None) -> Any:
+    """Normalise a dict or JSON string the way the native module stores it."""
+    if value is None:
+        return {}
+    if isinstance(value, str):
+        return json.loads(value)
+    if not isinstance(value, dict):
+        raise TypeError(f"expected a dict or a JSON string, got {type(value).__name__}")
+    return json.loads(json.dumps(value, default=str))
+
+
 class PyAgentState:
     """Pure-Python equivalent of agit_core.PyAgentState."""
 
-    def __init__(self, memory: dict[str, Any], world_state: dict[str, Any]) -> None:
-        self.memory: dict[str, Any] = memory
-        self.world_state: dict[str, Any] = world_state
+    def __init__(
+        self,
+        memory: dict[str, Any] | str | None = None,
+        world_state: dict[str, Any] | str | None = None,
+        cost: float = 0.0,
+        *,
+        metadata: dict[str, Any] | None = None,
+    ) -> None:
+        self.memory: dict[str, Any] = _state_field(memory)
+        self.world_state: dict[str, Any] = _state_field(world_state)
+        self.cost = cost
+        self.metadata: dict[str, Any] = _state_field(metadata)
+        self.timestamp = datetime.now(timezone.utc).isoformat()
+
+    @property
+    def timestamp_dt(self) -> datetime:
+        return datetime.fromisoformat(self.timestamp)
 
     def to_dict(self) -> dict[str, Any]:
         return {"memory": self.memory, "world_state": self.world_state}
 
     @classmethod
     def from_dict(cls, d: dict[str, Any]) -> PyAgentState:
-        return cls(d.get("memory", {}), d.get("world_state", {}))
+        state = cls(
+            d.get("memory"),
+            d.get("world_state"),
+            d.get("cost") or 0.0,
+            metadata=d.get("metadata"),
+        )
+        if d.get("timestamp"):
+            state.timestamp = d["timestamp"]
+        return state
 
     def __repr__(self) -> str:  # pragma: no cover
         return f"PyAgentState(memory={self.memory!r})"
@@ -47,6 +156,18 @@ class PyCommit:
     tree_hash: str = ""
     metadata: dict[str, Any] = field(default_factory=dict)
 
+    @property
+    def short_hash(self) -> str:
+        return self.hash[:8]
+
+    @property
+    def is_merge(self) -> bool:
+        return len(self.parent_hashes) > 1
+
+    @property
+    def timestamp_dt(self) -> datetime:
+        return datetime.fromisoformat(self.timestamp)
+
     def __repr__(self) -> str:  # pragma: no cover
         return f"PyCommit({self.hash[:8]}…, {self.message!r})"
 
@@ -61,6 +182,31 @@ class PyDiffEntry:
     new_value: Any = None
 
 
+@dataclass
+class PyMergeConflict:
+    """A path both sides of a merge changed differently."""
+
+    path: str
+    base_value: Any = None
+    ours_value: Any = None
+    theirs_value: Any = None
+
+
+@dataclass
+class PyMergePreview:
+    """Outcome of a merge that has not been committed."""
+
+    base_hash: str
+    ours_hash: str
+    theirs_hash: str
+    merged_state: PyAgentState
+    conflicts: list[PyMergeConflict] = field(default_factory=list)
+
+    @property
+    def is_clean(self) -> bool:
+        return len(self.conflicts) == 0
+
+
 @dataclass
 class PyStateDiff:
     """Collection of diff entries between two states."""
@@ -110,10 +256,96 @@ def _diff_dicts(
     return entries
 
 
+def _merge_dicts(
+    base: Any,
+    ours: Any,
+    theirs: Any,
+    resolutions: dict[str, Any],
+    conflicts: list[PyMergeConflict],
+    prefix: str = "",
+) -> Any:
+    """Three-way merge keeping ours at unresolved conflicts, like the core."""
+    if ours == theirs:
+        return ours
+    if ours == base:
+        return theirs
+    if theirs == base:
+        return ours
+    if prefix in resolutions:
+        return resolutions[prefix]
+    if all(isinstance(v, dict) for v in (base, ours, theirs)):
+        merged = {}
+        for key in sorted(set(base) | set(ours) | set(theirs)):
+            path = f"{prefix}.{key}" if prefix else key
+            value = _merge_dicts(
+                base.get(key), ours.get(key), theirs.get(key), resolutions, conflicts, path
+            )
+            if value is not None or key in ours or key in theirs:
+                merged[key] = value
+        return merged
+    conflicts.append(PyMergeConflict(prefix, base, ours, theirs))
+    return ours
+
+
+def _stub_location(
+    path: str | None, storage: str | None, backend: str | None
+) -> str:
+    """Resolve the constructor's location; the stubs only support SQLite."""
+    if path is not None and storage is not None:
+        raise ValueError("pass either path or storage, not both")
+    location = path if path is not None else storage
+    kind = backend
+    if kind is None and location is not None:
+        if location.startswith(("postgres://", "postgresql://")):
+            kind = "postgres"
+        elif location.startswith("s3://"):
+            kind = "s3"
+    if kind in ("postgres", "s3"):
+        raise RuntimeError(
+            f"{kind} backend not available: the pure-Python stubs only support SQLite"
+        )
+    if kind not in (None, "sqlite"):
+        raise ValueError(f"unknown backend '{kind}'; use sqlite|postgres|s3")
+    if location is None:
+        raise ValueError("a path or storage URL is required")
+    return location.removeprefix("sqlite://")
+
+
+class _StubCommitIterator:
+    """Hands out pre-collected commits in batches, counting the batches."""
+
+    def __init__(self, commits: list[PyCommit], batch_size: int) -> None:
+        self._commits = commits
+        self._batch_size = batch_size
+        self._pending: list[PyCommit] = []
+        self.batches_fetched = 0
+
+    def __iter__(self) -> _StubCommitIterator:
+        return self
+
+    def __next__(self) -> PyCommit:
+        if not self._pending and self._commits:
+            self._pending = self._commits[: self._batch_size]
+            self._commits = self._commits[self._batch_size :]
+            self.batches_fetched += 1
+        if not self._pending:
+            raise StopIteration
+        return self._pending.pop(0)
+
+
 class PyRepository:
     """Pure-Python in-memory/SQLite repository stub."""
 
-    def __init__(self, path: str, agent_id: str = "default") -> None:
+    def __init__(
+        self,
+        path: str | None = None,
+        agent_id: str = "default",
+        *,
+        storage: str | None = None,
+        backend: str | None = None,
+        **_backend_options: Any,
+    ) -> None:
+        path = _stub_location(path, storage, backend)
         self._path = path
         self._agent_id = agent_id
         self._lock = threading.Lock()
@@ -131,6 +363,7 @@ class PyRepository:
             self._init_db()
         else:
             self._db_path = None  # type: ignore[assignment]
+        self._closed = False
 
     # --- Initialisation ---
 
@@ -159,7 +392,12 @@ class PyRepository:
 
     # --- Core operations ---
 
+    def _check_open(self) -> None:
+        if self._closed:
+            raise RuntimeError("repository closed")
+
     def _put(self, h: str, data: bytes) -> None:
+        self._check_open()
         with self._lock:
             self._objects[h] = data
         if self._db_path:
@@ -169,6 +407,7 @@ class PyRepository:
             con.close()
 
     def _get(self, h: str) -> bytes | None:
+        self._check_open()
         with self._lock:
             if h in self._objects:
                 return self._objects[h]
@@ -197,7 +436,9 @@ class PyRepository:
                 return self._branches[name]
             head = self._refs.get("HEAD", "main")
             if name == "HEAD":
-                return self._branches.get(head) or self._refs.get(head)
+                # A detached HEAD holds the commit hash itself
+                detached = head if len(head) == 64 else None
+                return self._branches.get(head) or self._refs.get(head) or detached
             # Check if it's a raw commit hash that exists in object store
             if name in self._objects:
                 return name
@@ -209,12 +450,25 @@ class PyRepository:
 
     # --- Public API (mirrors PyO3 bindings) ---
 
+    def close(self) -> None:
+        """Close the repository; later calls raise. Closing twice is a no-op."""
+        self._closed = True
+
+    def __enter__(self) -> PyRepository:
+        return self
+
+    def __exit__(self, *exc: object) -> bool:
+        self.close()
+        return False
+
     def commit(
         self,
-        state: PyAgentState,
+        state: PyAgentState | dict[str, Any],
         message: str,
         action_type: str = "tool_call",
     ) -> str:
+        if isinstance(state, dict):
+            state = PyAgentState.from_dict(state)
         state_dict = state.to_dict()
         if hasattr(self, "_encryptor") and self._encryptor is not None:
             state_dict = self._encrypt_state(state_dict)
@@ -249,18 +503,18 @@ class PyRepository:
     def get_state(self, commit_hash: str) -> PyAgentState:
         data = self._get(commit_hash)
         if data is None:
-            raise KeyError(f"commit not found: {commit_hash}")
+            raise ObjectNotFoundError(commit_hash)
         commit_obj = json.loads(data)
         blob = self._get(commit_obj["tree_hash"])
         if blob is None:
-            raise KeyError(f"blob not found: {commit_obj['tree_hash']}")
+            raise ObjectNotFoundError(commit_obj["tree_hash"])
         state_dict = json.loads(blob)
         if hasattr(self, "_encryptor") and self._encryptor is not None:
             state_dict = self._decrypt_state(state_dict)
         return PyAgentState.from_dict(state_dict)
 
-    def log(self, limit: int = 10) -> list[PyCommit]:
-        start = self._resolve("HEAD")
+    def log(self, limit: int = 10, branch: str | None = None) -> list[PyCommit]:
+        start = self._resolve(branch or "HEAD")
         if not start:
             return []
         commits: list[PyCommit] = []
@@ -290,10 +544,31 @@ class PyRepository:
         commits.sort(key=lambda c: c.timestamp, reverse=True)
         return commits[:limit]
 
+    def iter_log(
+        self,
+        branch: str | None = None,
+        batch_size: int = 100,
+        action_type: str | None = None,
+        author: str | None = None,
+        since: str | None = None,
+    ) -> _StubCommitIterator:
+        if batch_size < 1:
+            raise ValueError("batch_size must be at least 1")
+        commits = [
+            c
+            for c in self.log(limit=len(self._objects), branch=branch)
+            if (action_type is None or c.action_type == action_type)
+            and (author is None or c.author == author)
+            and (since is None or c.timestamp >= since)
+        ]
+        return _StubCommitIterator(commits, batch_size)
+
     def branch(self, name: str, from_ref: str | None = None) -> None:
         source = self._resolve(from_ref or "HEAD") or ""
+        if not source and from_ref:
+            raise AgitError(f"ref not found: {from_ref}", "AGIT_REF_NOT_FOUND")
         if not source:
-            raise ValueError("No commits yet; cannot create branch")
+            raise NoCommitsError("no commits yet on this branch")
         self._set_ref(name, source)
 
     def checkout(self, target: str) -> PyAgentState:
@@ -314,29 +589,36 @@ class PyRepository:
         return PyStateDiff(base_hash=hash1, target_hash=hash2, entries=entries)
 
     def merge(self, branch: str, strategy: str = "three_way") -> str:
+        with self._lock:
+            current_branch = self._refs.get("HEAD", "main")
+            detached = current_branch not in self._branches and current_branch in self._objects
+        if detached:
+            raise DetachedHeadError("detached HEAD: cannot perform operation requiring a branch")
+        if strategy not in ("ours", "theirs"):
+            return self.merge_resolved(branch, {})
+        ours_hash, theirs_hash = self._merge_heads(branch)
+        merged = self.get_state(ours_hash if strategy == "ours" else theirs_hash)
+        return self._merge_commit(branch, ours_hash, theirs_hash, merged)
+
+    def _merge_heads(self, branch: str) -> tuple[str, str]:
         with self._lock:
             current_branch = self._refs.get("HEAD", "main")
             ours_hash = self._branches.get(current_branch, "")
             theirs_hash = self._branches.get(branch, "")
-        if not ours_hash or not theirs_hash:
-            raise ValueError("Cannot merge: missing branch commit")
-        ours_state = self.get_state(ours_hash)
-        theirs_state = self.get_state(theirs_hash)
-
-        if strategy == "ours":
-            merged = ours_state
-        elif strategy == "theirs":
-            merged = theirs_state
-        else:
-            merged_dict = {**theirs_state.to_dict(), **ours_state.to_dict()}
-            merged = PyAgentState.from_dict(merged_dict)
-
+        if not theirs_hash:
+            raise BranchNotFoundError(branch)
+        if not ours_hash:
+            raise NoCommitsError("no commits yet on this branch")
+        return ours_hash, theirs_hash
+
+    def _merge_commit(
+        self, branch: str, ours_hash: str, theirs_hash: str, merged: PyAgentState
+    ) -> str:
         state_bytes = json.dumps(merged.to_dict(), sort_keys=True).encode()
         tree_hash = _sha256(state_bytes)
         self._put(tree_hash, state_bytes)
         with self._lock:
             current_branch = self._refs.get("HEAD", "main")
-            current_hash = self._branches.get(current_branch, "")
         commit_obj: dict[str, Any] = {
             "tree_hash": tree_hash,
             "parent_hashes": [ours_hash, theirs_hash],
@@ -354,6 +636,34 @@ class PyRepository:
         self._append_audit("merge", f"merged '{branch}'", commit_hash)
         return commit_hash
 
+    def _three_way(
+        self, branch: str, resolutions: dict[str, Any]
+    ) -> tuple[PyMergePreview, PyAgentState]:
+        ours_hash, theirs_hash = self._merge_heads(branch)
+        base_hash = self.find_merge_base(ours_hash, theirs_hash)
+        conflicts: list[PyMergeConflict] = []
+        merged = _merge_dicts(
+            self.get_state(base_hash).to_dict(),
+            self.get_state(ours_hash).to_dict(),
+            self.get_state(theirs_hash).to_dict(),
+            resolutions,
+            conflicts,
+        )
+        state = PyAgentState.from_dict(merged)
+        return PyMergePreview(base_hash, ours_hash, theirs_hash, state, conflicts), state
+
+    def merge_preview(self, branch: str) -> PyMergePreview:
+        return self._three_way(branch, {})[0]
+
+    def merge_resolved(self, branch: str, resolutions: dict[str, Any]) -> str:
+        preview, merged = self._three_way(branch, resolutions)
+        if preview.conflicts:
+            paths = [c.path for c in preview.conflicts]
+            raise MergeConflictError(
+                f"merge conflict: conflicts at: {', '.join(paths)}", paths, preview.conflicts
+            )
+        return self._merge_commit(branch, preview.ours_hash, preview.theirs_hash, merged)
+
     def revert(self, to_hash: str) -> PyAgentState:
         state = self.get_state(to_hash)
         self.commit(state, f"revert to {to_hash[:8]}", "rollback")
@@ -363,17 +673,231 @@ class PyRepository:
         with self._lock:
             return dict(self._branches)
 
+    def head(self) -> str:
+        h = self._resolve("HEAD")
+        if not h:
+            raise NoCommitsError("no commits yet on this branch")
+        return h
+
     def current_branch(self) -> str | None:
         with self._lock:
             head = self._refs.get("HEAD", "main")
             return head if head in self._branches else None
 
-    def audit_log(self, limit: int = 50) -> list[dict[str, Any]]:
+    def audit_log(
+        self,
+        limit: int = 50,
+        agent_id: str | None = None,
+        action: str | None = None,
+        level: str | None = None,
+        since: str | None = None,
+        until: str | None = None,
+        message_query: str | None = None,
+        commit_hash: str | None = None,
+        message_contains: str | None = None,
+        offset: int | None = None,
+    ) -> list[dict[str, Any]]:
+        entries = self._filter_audit(
+            agent_id, action, level, since, until, message_query, commit_hash, message_contains
+        )
+        # Skip the newest `offset` matches, like the native backends
+        end = len(entries) - (offset or 0)
+        return entries[max(end - limit, 0) : max(end, 0)]
+
+    def count_audit_log(
+        self,
+        agent_id: str | None = None,
+        action: str | None = None,
+        level: str | None = None,
+        since: str | None = None,
+        until: str | None = None,
+        message_query: str | None = None,
+        commit_hash: str | None = None,
+        message_contains: str | None = None,
+    ) -> int:
+        return len(
+            self._filter_audit(
+                agent_id, action, level, since, until, message_query, commit_hash, message_contains
+            )
+        )
+
+    def log_warning(self, message: str, details: dict[str, Any] | None = None) -> None:
+        self._append_audit("warning", message, None, level="warn", details=details)
+
+    def log_error(self, message: str, details: dict[str, Any] | None = None) -> None:
+        self._append_audit("error", message, None, level="error", details=details)
+
+    def watch_logs(
+        self,
+        agent_id: str | None = None,
+        action: str | None = None,
+        level: str | None = None,
+        since: str | None = None,
+        poll: float = 1.0,
+    ) -> Iterator[dict[str, Any]]:
+        """Yield new audit entries as they are logged, polling every ``poll`` seconds."""
+        if poll <= 0:
+            raise ValueError("poll must be a positive number of seconds")
+        with self._lock:
+            seen = {e["id"] for e in self._audit if since is None}
+        while True:
+            with self._lock:
+                entries = list(self._audit)
+            for entry in entries:
+                if (
+                    entry["id"] in seen
+                    or (agent_id is not None and entry["agent_id"] != agent_id)
+                    or (action is not None and entry["action"] != action)
+                    or (level is not None and entry.get("level", "info") != level)
+                    or (since is not None and entry["timestamp"] < since)
+                ):
+                    continue
+                seen.add(entry["id"])
+                yield entry
+            time.sleep(poll)
+
+    def _filter_audit(
+        self,
+        agent_id: str | None,
+        action: str | None,
+        level: str | None,
+        since: str | None,
+        until: str | None,
+        message_query: str | None,
+        commit_hash: str | None,
+        message_contains: str | None,
+    ) -> list[dict[str, Any]]:
         with self._lock:
-            return list(self._audit[-limit:])
+            entries = list(self._audit)
+        if agent_id is not None:
+            entries = [e for e in entries if e["agent_id"] == agent_id]
+        if action is not None:
+            entries = [e for e in entries if e["action"] == action]
+        if level is not None:
+            entries = [e for e in entries if e.get("level", "info") == level]
+        if since is not None:
+            entries = [e for e in entries if e["timestamp"] >= since]
+        if until is not None:
+            entries = [e for e in entries if e["timestamp"] < until]
+        if message_query is not None:
+            needle = message_query.lower()
+            entries = [e for e in entries if needle in e["message"].lower()]
+        if commit_hash is not None:
+            entries = [e for e in entries if e["commit_hash"] == commit_hash]
+        if message_contains is not None:
+            entries = [e for e in entries if message_contains in e["message"]]
+        return entries
+
+    def set_agent_id(self, agent_id: str) -> None:
+        self._agent_id = agent_id
+
+    def _ancestors(self, h: str) -> set[str]:
+        ancestors: set[str] = set()
+        queue = [h]
+        while queue:
+            h = queue.pop()
+            if h in ancestors:
+                continue
+            ancestors.add(h)
+            queue.extend(self._load_commit(h).get("parent_hashes", []))
+        return ancestors
+
+    def find_merge_base(self, h1: str, h2: str) -> str:
+        common = self._ancestors(h1) & self._ancestors(h2)
+        if not common:
+            raise AgitError(
+                f"no common ancestor between {h1} and {h2}", "AGIT_NO_COMMON_ANCESTOR"
+            )
+        # Lowest common ancestors: those no other common ancestor descends from
+        lowest = common - set().union(*(self._ancestors(h) - {h} for h in common))
+        memo: dict[str, int] = {}
+        return min(lowest, key=lambda h: (-self._generation(h, memo), h))
+
+    def _generation(self, h: str, memo: dict[str, int]) -> int:
+        """Length of the longest parent chain from `h` to a root, counting `h`."""
+        if h not in memo:
+            parents = self._load_commit(h).get("parent_hashes", [])
+            memo[h] = 1 + max((self._generation(p, memo) for p in parents), default=0)
+        return memo[h]
+
+    def squash(self, branch: str, from_hash: str, to_hash: str) -> dict[str, Any]:
+        with self._lock:
+            tip = self._branches.get(branch)
+        if tip is None:
+            raise BranchNotFoundError(branch)
+
+        # Commits after the range, newest first
+        later: list[dict[str, Any]] = []
+        current: str | None = tip
+        while current != to_hash:
+            if current is None:
+                raise ValueError(f"{to_hash} is not on the history of branch '{branch}'")
+            obj = self._load_commit(current)
+            later.append(obj)
+            current = (obj.get("parent_hashes") or [None])[0]
+
+        # The range itself, newest first
+        in_range: list[tuple[str, dict[str, Any]]] = []
+        current = to_hash
+        while True:
+            if current is None:
+                raise ValueError(f"{from_hash} is not an ancestor of {to_hash}")
+            obj = self._load_commit(current)
+            in_range.append((current, obj))
+            if current == from_hash:
+                break
+            current = (obj.get("parent_hashes") or [None])[0]
+
+        squashed = [h for h, _ in reversed(in_range)]
+        message = f"squash {len(in_range)} commits: " + "; ".join(
+            c["message"] for _, c in reversed(in_range)
+        )
+        final = in_range[0][1]
+        new_commit = {
+            **final,
+            "parent_hashes": in_range[-1][1].get("parent_hashes", [])[:1],
+            "message": message,
+            "author": self._agent_id,
+            "timestamp": time.strftime("%Y-%m-%dT%H:%M:%SZ", time.gmtime()),
+        }
+        new_hash = self._put_commit(new_commit)
+        new_tip = new_hash
+        for obj in reversed(later):
+            obj["parent_hashes"] = [new_tip, *obj.get("parent_hashes", [])[1:]]
+            new_tip = self._put_commit(obj)
+        self._set_ref(branch, new_tip)
+        self._append_audit("squash", message, new_hash)
+        return {
+            "new_hash": new_hash,
+            "new_tip": new_tip,
+            "commits_squashed": len(in_range),
+            "squashed_hashes": squashed,
+            "aggregated_cost": 0.0,
+            "message": message,
+        }
+
+    def _load_commit(self, h: str) -> dict[str, Any]:
+        data = self._get(h)
+        if data is None:
+            raise ObjectNotFoundError(h)
+        return json.loads(data)
+
+    def _put_commit(self, obj: dict[str, Any]) -> str:
+        data = json.dumps(obj, sort_keys=True).encode()
+        h = _sha256(data)
+        self._put(h, data)
+        return h
 
-    def delete_branch(self, name: str) -> None:
+    def delete_branch(self, name: str, force: bool = False) -> None:
         with self._lock:
+            if name not in self._branches:
+                raise BranchNotFoundError(name)
+            if self._refs.get("HEAD", "main") == name:
+                if not force or name == "main":
+                    raise AgitError(
+                        f"branch '{name}' is checked out", "AGIT_BRANCH_CHECKED_OUT"
+                    )
+                self._refs["HEAD"] = self._branches[name]
             self._branches.pop(name, None)
             self._refs.pop(name, None)
 
@@ -434,15 +958,62 @@ class PyRepository:
                 result[field] = json.loads(self._encryptor.decrypt(raw))
         return result
 
-    def gc(self, keep_last_n: int = 0) -> Any:
-        """Garbage collection: remove unreachable objects."""
-        # Find all reachable objects via BFS from branch tips
+    def gc(
+        self,
+        keep_last_n: int = 0,
+        dry_run: bool = False,
+        progress: Callable[[dict[str, Any]], None] | None = None,
+    ) -> dict[str, Any]:
+        """Garbage collection: remove unreachable objects.
+
+        With ``dry_run`` nothing is deleted; the report lists what would be.
+        ``progress`` is called once per phase with ``phase``, ``processed``
+        and ``total``.
+        """
+
+        def report_progress(phase: str, count: int) -> None:
+            if progress is not None:
+                progress({"phase": phase, "processed": count, "total": count})
+
+        reachable = self._reachable()
+        report_progress("marking", len(reachable))
+
+        # Remove unreachable objects
+        objects_before = len(self._objects)
+        report_progress("listing", objects_before)
+        unreachable = sorted(set(self._objects.keys()) - reachable)
+        bytes_freed = sum(len(self._objects[h]) for h in unreachable)
+        report: dict[str, Any] = {
+            "dry_run": dry_run,
+            "cancelled": False,
+            "objects_before": objects_before,
+            "objects_removed": 0 if dry_run else len(unreachable),
+            "objects_after": objects_before - (0 if dry_run else len(unreachable)),
+            "unreachable": unreachable[:1000],
+            "unreachable_count": len(unreachable),
+            "bytes_freed": bytes_freed,
+            "freed_by_type": {},
+        }
+        if dry_run:
+            return report
+        for h in unreachable:
+            del self._objects[h]
+            if self._db_path:
+                con = sqlite3.connect(self._db_path)
+                con.execute("DELETE FROM objects WHERE hash=?", (h,))
+                con.commit()
+                con.close()
+        report_progress("sweeping", len(unreachable))
+
+        return report
+
+    # --- Internal ---
+
+    def _reachable(self) -> set[str]:
+        """Hashes reachable from the branch tips, found breadth-first."""
         reachable: set[str] = set()
-        queue: list[str] = []
         with self._lock:
-            for branch_hash in self._branches.values():
-                queue.append(branch_hash)
-
+            queue = list(self._branches.values())
         while queue:
             h = queue.pop(0)
             if h in reachable or not h:
@@ -459,36 +1030,99 @@ class PyRepository:
                     queue.extend(obj.get("parent_hashes", []))
             except (json.JSONDecodeError, KeyError):
                 pass  # It's a blob, already marked reachable
+        return reachable
 
-        # Remove unreachable objects
-        objects_before = len(self._objects)
-        unreachable = set(self._objects.keys()) - reachable
-        for h in unreachable:
-            del self._objects[h]
-            if self._db_path:
-                con = sqlite3.connect(self._db_path)
-                con.execute("DELETE FROM objects WHERE hash=?", (h,))
-                con.commit()
-                con.close()
-
-        class _GcResult:
-            def __init__(self, before: int, removed: int):
-                self.objects_before = before
-                self.objects_removed = removed
-                self.objects_after = before - removed
-
-        return _GcResult(objects_before, len(unreachable))
+    def apply_retention(
+        self,
+        max_age_seconds: float | None = None,
+        max_commits: int | None = None,
+        keep_branches: list[str] | None = None,
+    ) -> dict[str, Any]:
+        """Truncate branches outside ``keep_branches`` and collect the rest."""
+        keep = ["main"] if keep_branches is None else keep_branches
+        now = datetime.now(timezone.utc)
+        result: dict[str, Any] = {
+            "commits_expired": 0,
+            "commits_retained": 0,
+            "branches_truncated": [],
+            "objects_removed": 0,
+        }
+        with self._lock:
+            branches = sorted(self._branches.items())
+        for branch, tip in branches:
+            if branch in keep:
+                continue
+            retained: list[dict[str, Any]] = []
+            expired = 0
+            current: str | None = tip
+            while current:
+                obj = self._load_commit(current)
+                age = (now - datetime.fromisoformat(obj["timestamp"])).total_seconds()
+                within = (max_commits is None or len(retained) < max_commits) and (
+                    max_age_seconds is None or age <= max_age_seconds
+                )
+                if retained and (expired or not within):
+                    expired += 1
+                else:
+                    retained.append(obj)
+                current = (obj.get("parent_hashes") or [None])[0]
+            result["commits_retained"] += len(retained)
+            if not expired:
+                continue
+            parent: str | None = None
+            for obj in reversed(retained):
+                parent = self._put_commit({**obj, "parent_hashes": [parent] if parent else []})
+            self._set_ref(branch, parent or tip)
+            result["commits_expired"] += expired
+            result["branches_truncated"].append(branch)
+        if result["branches_truncated"]:
+            result["objects_removed"] = self.gc()["objects_removed"]
+        return result
 
-    # --- Internal ---
+    def fsck(self, repair: bool = False) -> dict[str, Any]:
+        """Check stored objects against their keys and references."""
+        with self._lock:
+            objects = dict(self._objects)
+            tips = list(self._branches.values())
+        mismatches = {h: _sha256(data) for h, data in objects.items() if _sha256(data) != h}
+        referenced = set(tips)
+        for data in objects.values():
+            try:
+                obj = json.loads(data)
+            except json.JSONDecodeError:
+                continue
+            if isinstance(obj, dict) and "tree_hash" in obj:
+                referenced.add(obj["tree_hash"])
+                referenced.update(obj.get("parent_hashes", []))
+        missing = sorted(referenced - set(objects))
+        return {
+            "ok": not missing and not mismatches,
+            "objects_checked": len(objects),
+            "missing": missing,
+            "hash_mismatches": mismatches,
+            "orphans": sorted(set(objects) - self._reachable()),
+            "repaired": [],
+        }
 
-    def _append_audit(self, action: str, message: str, commit_hash: str | None) -> None:
+    def _append_audit(
+        self,
+        action: str,
+        message: str,
+        commit_hash: str | None,
+        level: str = "info",
+        details: dict[str, Any] | None = None,
+    ) -> None:
+        timestamp = time.strftime("%Y-%m-%dT%H:%M:%SZ", time.gmtime())
         entry: dict[str, Any] = {
             "id": str(uuid.uuid4()),
-            "timestamp": time.strftime("%Y-%m-%dT%H:%M:%SZ", time.gmtime()),
+            "timestamp": timestamp,
+            "timestamp_dt": datetime.fromisoformat(timestamp),
             "agent_id": self._agent_id,
             "action": action,
             "message": message,
             "commit_hash": commit_hash,
+            "level": level,
+            "details": details,
         }
         with self._lock:
             self._audit.append(entry)
@@ -507,3 +1141,79 @@ class PyRepository:
             )
             con.commit()
             con.close()
+
+
+class PyAsyncRepository:
+    """Asyncio stub: runs each ``PyRepository`` call in a worker thread."""
+
+    def __init__(
+        self, path: str | None = None, agent_id: str = "default", **kwargs: Any
+    ) -> None:
+        self._repo = PyRepository(path, agent_id, **kwargs)
+
+    async def commit(
+        self,
+        state: PyAgentState | dict[str, Any],
+        message: str,
+        action_type: str = "tool_call",
+    ) -> str:
+        return await asyncio.to_thread(self._repo.commit, state, message, action_type)
+
+    async def checkout(self, target: str) -> PyAgentState:
+        return await asyncio.to_thread(self._repo.checkout, target)
+
+    async def merge(self, branch: str, strategy: str = "three_way", no_ff: bool = False) -> str:
+        return await asyncio.to_thread(self._repo.merge, branch, strategy)
+
+    async def diff(self, hash1: str, hash2: str, ignore: list[str] | None = None) -> PyStateDiff:
+        return await asyncio.to_thread(self._repo.diff, hash1, hash2)
+
+    async def log(self, branch: str | None = None, limit: int | None = None) -> list[PyCommit]:
+        return await asyncio.to_thread(self._repo.log, limit or 100)
+
+    async def get_state(self, hash: str) -> PyAgentState:  # noqa: A002
+        return await asyncio.to_thread(self._repo.get_state, hash)
+
+    async def gc(self, keep_last_n: int, dry_run: bool = False) -> dict[str, Any]:
+        return await asyncio.to_thread(self._repo.gc, keep_last_n, dry_run)
+
+    async def audit_log(self, limit: int = 50, **filters: Any) -> list[dict[str, Any]]:
+        return await asyncio.to_thread(self._repo.audit_log, limit, **filters)
+
+
+def migrate(
+    source: str,
+    target: str,
+    progress_callback: Callable[[str, int, int], Any] | None = None,
+    batch_size: int | None = None,
+) -> dict[str, Any]:
+    """Copy every object and ref from one stub repository to another."""
+    src, dst = PyRepository(source), PyRepository(target)
+    step = max(batch_size or 1000, 1)
+    objects = sorted(src._objects.items())
+    result = {
+        "total_objects": len(objects),
+        "migrated_objects": 0,
+        "skipped_objects": 0,
+        "total_refs": len(src._refs),
+        "migrated_refs": 0,
+        "cancelled": False,
+    }
+    try:
+        for start in range(0, len(objects), step):
+            for h, data in objects[start : start + step]:
+                if h in dst._objects:
+                    result["skipped_objects"] += 1
+                else:
+                    dst._put(h, data)
+                    result["migrated_objects"] += 1
+            if progress_callback is not None:
+                progress_callback("objects", min(start + step, len(objects)), len(objects))
+        for i, (name, value) in enumerate(sorted(src._refs.items())):
+            dst._set_ref(name, value)
+            result["migrated_refs"] += 1
+            if progress_callback is not None:
+                progress_callback("refs", i + 1, len(src._refs))
+    except KeyboardInterrupt:
+        result["cancelled"] = True
+    return result
@@ -296,7 +296,7 @@ def audit(
             console.print("[dim]No audit entries.[/]")
             return
         if output_format == "json":
-            console.print(Syntax(json.dumps(logs, indent=2), "json", theme="monokai"))
+            console.print(Syntax(json.dumps(logs, indent=2, default=str), "json", theme="monokai"))
             return
         table = Table(title="Audit Log")
         table.add_column("Time", style="dim")
@@ -4,7 +4,7 @@ from __future__ import annotations
 import logging
 import os
 import time
-from typing import Any, Callable
+from typing import Any, Callable, Iterator
 
 from agit.engine.pii_masker import PiiMasker
 
@@ -74,14 +74,30 @@ class ExecutionEngine:
     # Core API
     # ------------------------------------------------------------------
 
-    def gc(self, keep_last_n: int = 0) -> dict[str, int]:
-        """Manually trigger garbage collection."""
+    def gc(
+        self,
+        keep_last_n: int = 0,
+        dry_run: bool = False,
+        progress: Callable[[dict[str, Any]], None] | None = None,
+    ) -> dict[str, Any]:
+        """Manually trigger garbage collection.
+
+        With ``dry_run`` nothing is deleted; ``unreachable`` and
+        ``bytes_freed`` describe what a real run would remove. ``progress``
+        receives ``phase``/``processed``/``total`` dicts as the run advances.
+        """
         if hasattr(self._repo, "gc"):
-            result = self._repo.gc(keep_last_n)
+            result = self._repo.gc(keep_last_n, dry_run=dry_run, progress=progress)
             return {
-                "before": getattr(result, "objects_before", 0),
-                "removed": getattr(result, "objects_removed", 0),
-                "after": getattr(result, "objects_after", 0),
+                "before": result.get("objects_before", 0),
+                "removed": result.get("objects_removed", 0),
+                "after": result.get("objects_after", 0),
+                "dry_run": result.get("dry_run", dry_run),
+                "cancelled": result.get("cancelled", False),
+                "unreachable": result.get("unreachable", []),
+                "unreachable_count": result.get("unreachable_count", 0),
+                "bytes_freed": result.get("bytes_freed", 0),
+                "freed_by_type": result.get("freed_by_type", {}),
             }
         return {"before": 0, "removed": 0, "after": 0}
 
@@ -229,29 +245,72 @@ class ExecutionEngine:
     def current_branch(self) -> str | None:
         return self._repo.current_branch()
 
-    def audit_log(self, limit: int = 50) -> list[dict[str, Any]]:
+    def audit_log(
+        self,
+        limit: int = 50,
+        message_query: str | None = None,
+        commit_hash: str | None = None,
+        message_contains: str | None = None,
+        offset: int | None = None,
+    ) -> list[dict[str, Any]]:
         if hasattr(self._repo, "audit_log"):
-            return self._repo.audit_log(limit)
-        # Native module doesn't expose audit_log directly; return empty list
+            return self._repo.audit_log(
+                limit,
+                message_query=message_query,
+                commit_hash=commit_hash,
+                message_contains=message_contains,
+                offset=offset,
+            )
+        # Older native modules don't expose audit_log; return empty list
         return []
 
+    def count_audit_log(
+        self,
+        message_query: str | None = None,
+        commit_hash: str | None = None,
+        message_contains: str | None = None,
+    ) -> int:
+        if hasattr(self._repo, "count_audit_log"):
+            return self._repo.count_audit_log(
+                message_query=message_query,
+                commit_hash=commit_hash,
+                message_contains=message_contains,
+            )
+        return 0
+
+    def log_warning(self, message: str, details: dict[str, Any] | None = None) -> None:
+        """Record a warning entry in the audit log."""
+        if hasattr(self._repo, "log_warning"):
+            self._repo.log_warning(message, details)
+
+    def log_error(self, message: str, details: dict[str, Any] | None = None) -> None:
+        """Record an error entry in the audit log."""
+        if hasattr(self._repo, "log_error"):
+            self._repo.log_error(message, details)
+
+    def watch_logs(self, agent_id: str | None = None, poll: float = 1.0) -> Iterator[dict[str, Any]]:
+        """Yield new audit log entries as they appear, like ``tail -f``.
+
+        Stop by breaking out of the loop or calling ``close()`` on the
+        returned iterator.
+        """
+        if hasattr(self._repo, "watch_logs"):
+            return self._repo.watch_logs(agent_id=agent_id, poll=poll)
+        return iter(())
+
     # ------------------------------------------------------------------
     # Internal conversion helpers
     # ------------------------------------------------------------------
 
     def _dict_to_state(self, d: dict[str, Any]) -> Any:
-        import json as _json
-
         memory = d.get("memory", d)
         world_state = d.get("world_state", {})
-        if _NATIVE:
-            # Native module expects JSON strings, not dicts
-            return _PyAgentState(_json.dumps(memory), _json.dumps(world_state))
         return _PyAgentState(memory, world_state)
 
     def _state_to_dict(self, state_obj: Any) -> dict[str, Any]:
         if hasattr(state_obj, "to_dict"):
-            return state_obj.to_dict()
+            # Drop the datetime copies so the dict stays JSON-serialisable
+            return {k: v for k, v in state_obj.to_dict().items() if not k.endswith("_dt")}
         return {"memory": getattr(state_obj, "memory", {}), "world_state": getattr(state_obj, "world_state", {})}
 
     def _commit_to_dict(self, c: Any) -> dict[str, Any]:
@@ -0,0 +1,76 @@
+"""Tests for the asyncio repository API."""
+from __future__ import annotations
+
+import asyncio
+
+import pytest
+
+from agit import ObjectNotFoundError, PyAgentState, PyAsyncRepository, PyRepository
+
+
+async def test_commit_log_and_get_state(tmp_repo_path: str) -> None:
+    repo = PyAsyncRepository(tmp_repo_path, "agent")
+    first = await repo.commit({"memory": {"step": 1}, "world_state": {}}, "first")
+    second = await repo.commit(PyAgentState({"step": 2}, {}), "second", "tool_call")
+
+    assert [c.message for c in await repo.log()] == ["second", "first"]
+    assert (await repo.get_state(first)).memory == {"step": 1}
+    assert (await repo.diff(first, second)).entries
+
+
+async def test_checkout_and_merge(tmp_repo_path: str) -> None:
+    setup = PyRepository(tmp_repo_path, "agent")
+    setup.commit({"memory": {"step": 1}}, "base")
+    setup.branch("feature")
+    setup.checkout("feature")
+    setup.commit({"memory": {"step": 2}}, "on feature")
+    setup.checkout("main")
+    del setup
+
+    repo = PyAsyncRepository(tmp_repo_path, "agent")
+    assert (await repo.checkout("main")).memory == {"step": 1}
+    merged = await repo.merge("feature")
+    assert merged in [c.hash for c in await repo.log()]
+
+
+async def test_gc_and_audit_log(tmp_repo_path: str) -> None:
+    repo = PyAsyncRepository(tmp_repo_path, "agent")
+    await repo.commit({"memory": {"step": 1}}, "first")
+
+    report = await repo.gc(1, dry_run=True)
+    assert report["dry_run"] is True
+    assert [e["message"] for e in await repo.audit_log(limit=10)] == ["first"]
+
+
+async def test_errors_raise_from_await(tmp_repo_path: str) -> None:
+    repo = PyAsyncRepository(tmp_repo_path, "agent")
+    with pytest.raises(ObjectNotFoundError):
+        await repo.get_state("0" * 64)
+
+
+async def test_event_loop_keeps_running(tmp_repo_path: str) -> None:
+    repo = PyAsyncRepository(tmp_repo_path, "agent")
+    ticks = 0
+    done = asyncio.Event()
+
+    async def ticker() -> None:
+        nonlocal ticks
+        while not done.is_set():
+            ticks += 1
+            await asyncio.sleep(0)
+
+    task = asyncio.create_task(ticker())
+    big = {"memory": {f"k{i}": "x" * 100 for i in range(2000)}}
+    for i in range(5):
+        await repo.commit(big, f"commit {i}")
+    done.set()
+    await task
+    assert ticks > 0
+
+
+async def test_concurrent_reads(tmp_repo_path: str) -> None:
+    repo = PyAsyncRepository(tmp_repo_path, "agent")
+    hashes = [await repo.commit({"memory": {"step": i}}, f"c{i}") for i in range(3)]
+
+    states = await asyncio.gather(*(repo.get_state(h) for h in hashes))
+    assert [s.memory["step"] for s in states] == [0, 1, 2]
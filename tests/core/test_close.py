@@ -0,0 +1,64 @@
+"""Tests for closing a repository and using it as a context manager."""
+from __future__ import annotations
+
+from pathlib import Path
+
+import pytest
+
+from agit import PyAgentState, PyRepository
+
+
+def _commit(repo: PyRepository, step: int) -> str:
+    return repo.commit(PyAgentState({"step": step}, {}), f"step {step}", "tool_call")
+
+
+class TestClose:
+    def test_calls_after_close_raise(self, tmp_repo_path: str) -> None:
+        repo = PyRepository(tmp_repo_path)
+        head = _commit(repo, 1)
+        repo.close()
+        with pytest.raises(RuntimeError, match="repository closed"):
+            repo.get_state(head)
+        with pytest.raises(RuntimeError, match="repository closed"):
+            _commit(repo, 2)
+
+    def test_double_close_is_noop(self, tmp_repo_path: str) -> None:
+        repo = PyRepository(tmp_repo_path)
+        repo.close()
+        repo.close()
+
+    def test_reopen_after_close(self, tmp_repo_path: str) -> None:
+        repo = PyRepository(tmp_repo_path)
+        head = _commit(repo, 1)
+        repo.close()
+        again = PyRepository(tmp_repo_path)
+        assert again.get_state(head).memory == {"step": 1}
+
+
+class TestContextManager:
+    def test_with_block_closes(self, tmp_repo_path: str) -> None:
+        with PyRepository(tmp_repo_path) as repo:
+            head = _commit(repo, 1)
+        with pytest.raises(RuntimeError, match="repository closed"):
+            repo.get_state(head)
+
+    def test_closes_when_block_raises(self, tmp_repo_path: str) -> None:
+        with pytest.raises(ValueError):
+            with PyRepository(tmp_repo_path) as repo:
+                _commit(repo, 1)
+                raise ValueError("boom")
+        with pytest.raises(RuntimeError, match="repository closed"):
+            repo.log()
+
+    def test_database_files_deletable_after_with(self, tmp_path: Path) -> None:
+        with PyRepository(str(tmp_path)) as repo:
+            for step in range(3):
+                _commit(repo, step)
+
+        assert not list(tmp_path.glob("*-wal"))
+        for path in sorted(tmp_path.rglob("*"), reverse=True):
+            if path.is_dir():
+                path.rmdir()
+            else:
+                path.unlink()
+        assert not list(tmp_path.iterdir())
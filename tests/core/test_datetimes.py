@@ -0,0 +1,64 @@
+"""Tests for datetime properties and the richer Commit/AgentState API."""
+from __future__ import annotations
+
+from datetime import datetime, timezone
+
+import pytest
+
+from agit import NATIVE_AVAILABLE, PyAgentState, PyRepository
+
+
+def _repo(path: str) -> tuple[PyRepository, str]:
+    repo = PyRepository(path, "agent")
+    h = repo.commit(PyAgentState({"step": 1}, {}, metadata={"run": "r1"}), "first", "tool_call")
+    return repo, h
+
+
+def test_commit_timestamp_dt(tmp_repo_path: str) -> None:
+    repo, _ = _repo(tmp_repo_path)
+    commit = repo.log()[0]
+    assert commit.timestamp_dt == datetime.fromisoformat(commit.timestamp)
+    assert commit.timestamp_dt.tzinfo is not None
+    assert commit.timestamp_dt.utcoffset().total_seconds() == 0
+
+
+def test_state_timestamp_dt() -> None:
+    state = PyAgentState.from_dict({"memory": {}, "timestamp": "2026-03-04T05:06:07+00:00"})
+    assert state.timestamp_dt == datetime(2026, 3, 4, 5, 6, 7, tzinfo=timezone.utc)
+    assert state.timestamp_dt == datetime.fromisoformat(state.timestamp)
+
+
+def test_log_entry_timestamp_dt(tmp_repo_path: str) -> None:
+    repo, _ = _repo(tmp_repo_path)
+    entry = repo.audit_log(limit=1)[0]
+    assert entry["timestamp_dt"] == datetime.fromisoformat(entry["timestamp"])
+
+
+def test_short_hash_and_is_merge(tmp_repo_path: str) -> None:
+    repo, h = _repo(tmp_repo_path)
+    repo.branch("feature")
+    repo.checkout("feature")
+    repo.commit(PyAgentState({"step": 1, "feature": True}, {}), "on feature", "tool_call")
+    repo.checkout("main")
+    repo.commit(PyAgentState({"step": 2}, {}), "on main", "tool_call")
+    merged = repo.merge("feature", "ours")
+
+    first = next(c for c in repo.log() if c.hash == h)
+    assert first.short_hash == h[:8]
+    assert not first.is_merge
+    assert repo.log(limit=1)[0].hash == merged
+    assert repo.log(limit=1)[0].is_merge
+
+
+def test_state_metadata() -> None:
+    state = PyAgentState({"step": 1}, {}, metadata={"run": "r1", "tags": ["a"]})
+    assert state.metadata == {"run": "r1", "tags": ["a"]}
+
+
+@pytest.mark.skipif(not NATIVE_AVAILABLE, reason="stub to_dict carries only state content")
+def test_to_dict_includes_datetimes(tmp_repo_path: str) -> None:
+    repo, h = _repo(tmp_repo_path)
+    commit = repo.log()[0].to_dict()
+    state = repo.get_state(h).to_dict()
+    assert commit["timestamp_dt"] == datetime.fromisoformat(commit["timestamp"])
+    assert state["timestamp_dt"] == datetime.fromisoformat(state["timestamp"])
@@ -0,0 +1,100 @@
+"""Tests for the typed exceptions raised by the repository."""
+from __future__ import annotations
+
+import pytest
+
+from agit import (
+    NATIVE_AVAILABLE,
+    AgitError,
+    BranchNotFoundError,
+    DetachedHeadError,
+    MergeConflictError,
+    NoCommitsError,
+    ObjectNotFoundError,
+    PyAgentState,
+    PyRepository,
+)
+
+
+def _repo_with_commit() -> tuple[PyRepository, str]:
+    repo = PyRepository(":memory:", "test-agent")
+    h = repo.commit(PyAgentState({"plan": "a"}, {}), "base", "checkpoint")
+    return repo, h
+
+
+class TestTypedExceptions:
+    def test_hierarchy(self) -> None:
+        for cls in (
+            MergeConflictError,
+            BranchNotFoundError,
+            ObjectNotFoundError,
+            DetachedHeadError,
+            NoCommitsError,
+        ):
+            assert issubclass(cls, AgitError)
+        # Code catching RuntimeError keeps working
+        assert issubclass(AgitError, RuntimeError)
+
+    def test_object_not_found(self) -> None:
+        repo, _ = _repo_with_commit()
+        missing = "0" * 64
+        with pytest.raises(ObjectNotFoundError) as exc_info:
+            repo.get_state(missing)
+        assert exc_info.value.hash == missing
+        assert exc_info.value.code == "AGIT_OBJECT_NOT_FOUND"
+
+    def test_branch_not_found(self) -> None:
+        repo, _ = _repo_with_commit()
+        with pytest.raises(BranchNotFoundError) as exc_info:
+            repo.delete_branch("nope")
+        assert exc_info.value.branch == "nope"
+        assert exc_info.value.code == "AGIT_BRANCH_NOT_FOUND"
+
+    def test_untyped_errors_carry_a_code(self) -> None:
+        repo, _ = _repo_with_commit()
+        repo.branch("feature")
+        repo.checkout("feature")
+        with pytest.raises(AgitError) as exc_info:
+            repo.delete_branch("feature")
+        assert exc_info.value.code == "AGIT_BRANCH_CHECKED_OUT"
+
+    def test_no_commits(self) -> None:
+        repo = PyRepository(":memory:", "test-agent")
+        with pytest.raises(NoCommitsError):
+            repo.branch("feature")
+
+    def test_detached_head(self) -> None:
+        repo, h = _repo_with_commit()
+        repo.branch("feature")
+        repo.checkout(h)
+        with pytest.raises(DetachedHeadError):
+            repo.merge("feature", "ours")
+
+    @pytest.mark.skipif(not NATIVE_AVAILABLE, reason="stub merges never conflict")
+    def test_merge_conflict_paths(self) -> None:
+        repo, _ = _repo_with_commit()
+        repo.branch("feature")
+        repo.checkout("feature")
+        repo.commit(PyAgentState({"plan": "b"}, {}), "theirs", "tool_call")
+        repo.checkout("main")
+        repo.commit(PyAgentState({"plan": "c"}, {}), "ours", "tool_call")
+        with pytest.raises(MergeConflictError) as exc_info:
+            repo.merge("feature", "three_way")
+        assert "memory.plan" in exc_info.value.paths
+
+    @pytest.mark.skipif(not NATIVE_AVAILABLE, reason="stub merges never conflict")
+    def test_merge_conflict_values(self) -> None:
+        repo, _ = _repo_with_commit()
+        repo.branch("feature")
+        repo.checkout("feature")
+        repo.commit(PyAgentState({"plan": "b"}, {}), "theirs", "tool_call")
+        repo.checkout("main")
+        repo.commit(PyAgentState({"plan": "c"}, {}), "ours", "tool_call")
+        with pytest.raises(MergeConflictError) as exc_info:
+            repo.merge("feature", "three_way")
+        by_path = {c.path: c for c in exc_info.value.conflicts}
+        assert sorted(by_path) == sorted(exc_info.value.paths)
+        conflict = by_path["memory.plan"]
+        assert conflict.base_value == "a"
+        assert conflict.ours_value == "c"
+        assert conflict.theirs_value == "b"
@@ -0,0 +1,57 @@
+"""Tests for lazily iterating over commit history."""
+from __future__ import annotations
+
+import pytest
+
+from agit import PyAgentState, PyRepository
+
+
+def _repo_with_history(path: str, n: int) -> PyRepository:
+    repo = PyRepository(path, "agent")
+    for step in range(n):
+        action = "tool_call" if step % 2 == 0 else "llm_response"
+        repo.commit(PyAgentState({"step": step}, {}), f"step {step}", action)
+    return repo
+
+
+class TestIterLog:
+    @pytest.mark.parametrize("batch_size", [1, 2, 3, 7, 100])
+    def test_batches_neither_skip_nor_duplicate(self, tmp_repo_path: str, batch_size: int) -> None:
+        repo = _repo_with_history(tmp_repo_path, 7)
+        expected = [c.hash for c in repo.log(limit=100)]
+
+        walked = [c.hash for c in repo.iter_log(batch_size=batch_size)]
+        assert walked == expected
+        assert len(set(walked)) == 7
+
+    def test_early_break_stops_fetching(self, tmp_repo_path: str) -> None:
+        repo = _repo_with_history(tmp_repo_path, 10)
+        commits = repo.iter_log(batch_size=3)
+        for i, _ in enumerate(commits):
+            if i == 1:
+                break
+        assert commits.batches_fetched == 1
+
+    def test_filters(self, tmp_repo_path: str) -> None:
+        repo = _repo_with_history(tmp_repo_path, 5)
+
+        llm = [c.message for c in repo.iter_log(batch_size=2, action_type="llm_response")]
+        assert llm == ["step 3", "step 1"]
+        assert len(list(repo.iter_log(author="agent"))) == 5
+        assert list(repo.iter_log(author="someone-else")) == []
+        assert list(repo.iter_log(since="9999-01-01T00:00:00Z")) == []
+
+    def test_branch(self, tmp_repo_path: str) -> None:
+        repo = _repo_with_history(tmp_repo_path, 2)
+        repo.branch("feature")
+        repo.checkout("feature")
+        repo.commit(PyAgentState({"step": 9}, {}), "on feature", "tool_call")
+        repo.checkout("main")
+
+        assert [c.message for c in repo.iter_log("feature")][0] == "on feature"
+        assert [c.message for c in repo.iter_log()] == ["step 1", "step 0"]
+
+    def test_zero_batch_size_rejected(self, tmp_repo_path: str) -> None:
+        repo = _repo_with_history(tmp_repo_path, 1)
+        with pytest.raises(ValueError):
+            repo.iter_log(batch_size=0)
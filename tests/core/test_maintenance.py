@@ -0,0 +1,82 @@
+"""Tests for gc, retention and fsck from Python."""
+from __future__ import annotations
+
+from agit import PyAgentState, PyRepository
+
+
+def _commit(repo: PyRepository, step: int) -> str:
+    return repo.commit(PyAgentState({"step": step}, {}), f"step {step}", "tool_call")
+
+
+def _with_garbage(path: str) -> PyRepository:
+    """A repo whose deleted `scratch` branch left two commits unreachable."""
+    repo = PyRepository(path, "agent")
+    _commit(repo, 0)
+    repo.branch("scratch")
+    repo.checkout("scratch")
+    _commit(repo, 1)
+    _commit(repo, 2)
+    repo.checkout("main")
+    repo.delete_branch("scratch")
+    return repo
+
+
+def test_gc_dry_run_matches_real_run(tmp_repo_path: str) -> None:
+    repo = _with_garbage(tmp_repo_path)
+
+    dry = repo.gc(0, dry_run=True)
+    assert dry["dry_run"] is True
+    assert dry["objects_removed"] == 0
+    assert dry["unreachable_count"] > 0
+
+    real = repo.gc(0)
+    assert real["objects_removed"] == dry["unreachable_count"]
+    assert real["bytes_freed"] == dry["bytes_freed"]
+    assert sorted(real["unreachable"]) == sorted(dry["unreachable"])
+    assert repo.gc(0, dry_run=True)["unreachable_count"] == 0
+
+
+def test_fsck_orphans_match_gc_dry_run(tmp_repo_path: str) -> None:
+    repo = _with_garbage(tmp_repo_path)
+
+    report = repo.fsck()
+    assert report["ok"] is True
+    assert report["missing"] == []
+    assert sorted(report["orphans"]) == sorted(repo.gc(0, dry_run=True)["unreachable"])
+
+    repo.gc(0)
+    assert repo.fsck()["orphans"] == []
+
+
+def test_apply_retention_truncates_other_branches(tmp_repo_path: str) -> None:
+    repo = PyRepository(tmp_repo_path, "agent")
+    _commit(repo, 0)
+    repo.branch("feature")
+    repo.checkout("feature")
+    for step in range(1, 5):
+        _commit(repo, step)
+    repo.checkout("main")
+
+    result = repo.apply_retention(max_commits=2)
+
+    assert result["branches_truncated"] == ["feature"]
+    assert result["commits_expired"] == 3
+    assert result["commits_retained"] == 2
+    assert result["objects_removed"] > 0
+    assert [c.message for c in repo.log(branch="feature")] == ["step 4", "step 3"]
+    assert [c.message for c in repo.log()] == ["step 0"]
+    assert repo.fsck()["ok"] is True
+
+
+def test_apply_retention_keeps_listed_branches(tmp_repo_path: str) -> None:
+    repo = PyRepository(tmp_repo_path, "agent")
+    for step in range(3):
+        _commit(repo, step)
+
+    result = repo.apply_retention(max_commits=1, keep_branches=["main"])
+    assert result["branches_truncated"] == []
+    assert len(repo.log()) == 3
+
+    result = repo.apply_retention(max_commits=1, keep_branches=[])
+    assert result["commits_expired"] == 2
+    assert [c.message for c in repo.log()] == ["step 2"]
@@ -0,0 +1,72 @@
+"""Tests for previewing merges and committing them with resolutions."""
+from __future__ import annotations
+
+import pytest
+
+from agit import BranchNotFoundError, MergeConflictError, PyRepository
+
+# Both sides keep the same timestamp so only the memory keys conflict
+TIMESTAMP = "2026-01-01T00:00:00+00:00"
+
+
+def _commit(repo: PyRepository, memory: dict, message: str) -> str:
+    return repo.commit({"memory": memory, "timestamp": TIMESTAMP}, message, "tool_call")
+
+
+def _diverged(path: str) -> tuple[PyRepository, str, str, str]:
+    """main and feature both change `a` and `b`; only main changes `c`."""
+    repo = PyRepository(path, "agent")
+    base = _commit(repo, {"a": 1, "b": 1, "c": 1}, "base")
+    repo.branch("feature")
+    repo.checkout("feature")
+    theirs = _commit(repo, {"a": 2, "b": {"items": [1]}, "c": 1}, "on feature")
+    repo.checkout("main")
+    ours = _commit(repo, {"a": 3, "b": {"items": [2]}, "c": 2}, "on main")
+    return repo, base, ours, theirs
+
+
+def test_preview_lists_conflicts(tmp_repo_path: str) -> None:
+    repo, base, ours, theirs = _diverged(tmp_repo_path)
+    preview = repo.merge_preview("feature")
+
+    assert (preview.base_hash, preview.ours_hash, preview.theirs_hash) == (base, ours, theirs)
+    assert [c.path for c in preview.conflicts] == ["memory.a", "memory.b"]
+    a = preview.conflicts[0]
+    assert (a.base_value, a.ours_value, a.theirs_value) == (1, 3, 2)
+    assert preview.conflicts[1].theirs_value == {"items": [1]}
+    assert preview.merged_state.memory == {"a": 3, "b": {"items": [2]}, "c": 2}
+    assert not preview.is_clean
+
+
+def test_preview_does_not_commit(tmp_repo_path: str) -> None:
+    repo, _, ours, _ = _diverged(tmp_repo_path)
+    repo.merge_preview("feature")
+    assert repo.log(limit=1)[0].hash == ours
+
+
+def test_merge_resolved_creates_merge_commit(tmp_repo_path: str) -> None:
+    repo, _, ours, theirs = _diverged(tmp_repo_path)
+    with pytest.raises(MergeConflictError):
+        repo.merge("feature")
+
+    merged = repo.merge_resolved("feature", {"memory.a": 4, "memory.b": {"items": [1, 2]}})
+
+    head = repo.log(limit=1)[0]
+    assert head.hash == merged
+    assert head.parent_hashes == [ours, theirs]
+    assert repo.get_state(merged).memory == {"a": 4, "b": {"items": [1, 2]}, "c": 2}
+
+
+def test_unresolved_conflict_raises(tmp_repo_path: str) -> None:
+    repo, _, ours, _ = _diverged(tmp_repo_path)
+    with pytest.raises(MergeConflictError) as exc:
+        repo.merge_resolved("feature", {"memory.a": 4})
+    assert exc.value.paths == ["memory.b"]
+    assert [c.path for c in exc.value.conflicts] == ["memory.b"]
+    assert repo.log(limit=1)[0].hash == ours
+
+
+def test_unknown_branch(tmp_repo_path: str) -> None:
+    repo, _, _, _ = _diverged(tmp_repo_path)
+    with pytest.raises(BranchNotFoundError):
+        repo.merge_preview("nope")
@@ -0,0 +1,75 @@
+"""Tests for copying a repository between storage locations."""
+from __future__ import annotations
+
+from pathlib import Path
+
+import pytest
+
+from agit import PyAgentState, PyRepository, migrate
+
+
+def _source(path: Path) -> PyRepository:
+    path.mkdir()
+    repo = PyRepository(str(path), "agent")
+    for i in range(3):
+        repo.commit(PyAgentState({"step": i}, {}), f"step {i}", "tool_call")
+    repo.branch("feature")
+    return repo
+
+
+def test_migrate_sqlite_to_sqlite(tmp_path: Path) -> None:
+    source = _source(tmp_path / "src")
+    target_path = tmp_path / "dst"
+    target_path.mkdir()
+    calls: list[tuple[str, int, int]] = []
+
+    result = migrate(str(tmp_path / "src"), str(target_path), lambda *args: calls.append(args))
+
+    assert result["migrated_objects"] == result["total_objects"] > 0
+    assert result["migrated_refs"] == result["total_refs"]
+    assert result["cancelled"] is False
+    assert calls[-1][0] == "refs"
+    assert ("objects", result["total_objects"], result["total_objects"]) in calls
+
+    target = PyRepository(str(target_path), "agent")
+    assert [c.hash for c in target.log()] == [c.hash for c in source.log()]
+    assert [c.hash for c in target.log(branch="feature")] == [
+        c.hash for c in source.log(branch="feature")
+    ]
+
+
+def test_rerun_skips_copied_objects(tmp_path: Path) -> None:
+    _source(tmp_path / "src")
+    (tmp_path / "dst").mkdir()
+    first = migrate(str(tmp_path / "src"), str(tmp_path / "dst"))
+    again = migrate(str(tmp_path / "src"), str(tmp_path / "dst"))
+    assert again["migrated_objects"] == 0
+    assert again["skipped_objects"] == first["total_objects"]
+
+
+def test_interrupt_reports_partial_progress(tmp_path: Path) -> None:
+    _source(tmp_path / "src")
+    (tmp_path / "dst").mkdir()
+
+    def interrupt(phase: str, current: int, total: int) -> None:
+        raise KeyboardInterrupt
+
+    partial = migrate(str(tmp_path / "src"), str(tmp_path / "dst"), interrupt, batch_size=1)
+    assert partial["cancelled"] is True
+    assert partial["migrated_objects"] == 1
+    assert partial["migrated_refs"] == 0
+
+    rest = migrate(str(tmp_path / "src"), str(tmp_path / "dst"))
+    assert rest["skipped_objects"] == 1
+    assert rest["migrated_objects"] == rest["total_objects"] - 1
+
+
+def test_callback_errors_are_raised(tmp_path: Path) -> None:
+    _source(tmp_path / "src")
+    (tmp_path / "dst").mkdir()
+
+    def fail(phase: str, current: int, total: int) -> None:
+        raise ValueError("boom")
+
+    with pytest.raises(ValueError, match="boom"):
+        migrate(str(tmp_path / "src"), str(tmp_path / "dst"), fail)
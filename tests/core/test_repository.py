@@ -0,0 +1,111 @@
+"""Tests for audit queries, squash, merge bases and agent identity."""
+from __future__ import annotations
+
+import pytest
+
+from agit import AgitError, PyAgentState, PyRepository
+
+
+def _commit(repo: PyRepository, step: int, message: str) -> str:
+    return repo.commit(PyAgentState({"step": step}, {}), message, "tool_call")
+
+
+class TestAuditLog:
+    def test_filters_by_agent_and_action(self) -> None:
+        repo = PyRepository(":memory:", "agent-a")
+        _commit(repo, 1, "first")
+        repo.set_agent_id("agent-b")
+        _commit(repo, 2, "second")
+        repo.log_warning("slow tool")
+
+        by_b = repo.audit_log(agent_id="agent-b")
+        assert sorted(e["message"] for e in by_b) == ["second", "slow tool"]
+        assert all(e["agent_id"] == "agent-b" for e in by_b)
+
+        warnings = repo.audit_log(action="warning")
+        assert [e["message"] for e in warnings] == ["slow tool"]
+
+    def test_filters_by_level_and_limit(self) -> None:
+        repo = PyRepository(":memory:", "agent")
+        _commit(repo, 1, "first")
+        repo.log_warning("careful")
+        repo.log_error("broken")
+
+        warnings = repo.audit_log(level="warn")
+        assert [e["message"] for e in warnings] == ["careful"]
+        assert len(repo.audit_log(limit=2)) == 2
+
+    def test_since_excludes_older_entries(self) -> None:
+        repo = PyRepository(":memory:", "agent")
+        _commit(repo, 1, "first")
+        assert repo.audit_log(since="9999-01-01T00:00:00Z") == []
+
+
+class TestSetAgentId:
+    def test_commits_are_attributed(self) -> None:
+        repo = PyRepository(":memory:", "before")
+        repo.set_agent_id("after")
+        _commit(repo, 1, "attributed")
+        assert repo.log(limit=1)[0].author == "after"
+
+
+class TestFindMergeBase:
+    def test_returns_fork_point(self) -> None:
+        repo = PyRepository(":memory:", "agent")
+        base = _commit(repo, 1, "base")
+        repo.branch("feature")
+        main_tip = _commit(repo, 2, "main work")
+        repo.checkout("feature")
+        feature_tip = _commit(repo, 3, "feature work")
+        assert repo.find_merge_base(main_tip, feature_tip) == base
+
+
+class TestBranchFrom:
+    def test_unknown_refs_are_rejected(self) -> None:
+        repo = PyRepository(":memory:", "agent")
+        h1 = _commit(repo, 1, "one")
+        for source in ["tpyo", h1[:8]]:
+            with pytest.raises(AgitError, match="ref not found"):
+                repo.branch("new", source)
+        assert "new" not in repo.list_branches()
+
+        repo.branch("new", h1)
+        assert repo.list_branches()["new"] == h1
+
+
+class TestDeleteBranch:
+    def test_checked_out_branch_needs_force(self) -> None:
+        repo = PyRepository(":memory:", "agent")
+        _commit(repo, 1, "one")
+        repo.branch("scratch")
+        repo.checkout("scratch")
+        tip = _commit(repo, 2, "two")
+
+        with pytest.raises(AgitError, match="checked out"):
+            repo.delete_branch("scratch")
+        assert "scratch" in repo.list_branches()
+
+        repo.delete_branch("scratch", force=True)
+        assert "scratch" not in repo.list_branches()
+        assert repo.current_branch() is None
+        assert repo.head() == tip
+
+
+class TestSquash:
+    def test_squashes_range_and_keeps_later_commits(self) -> None:
+        repo = PyRepository(":memory:", "agent")
+        _commit(repo, 1, "one")
+        h2 = _commit(repo, 2, "two")
+        h3 = _commit(repo, 3, "three")
+        _commit(repo, 4, "four")
+
+        result = repo.squash("main", h2, h3)
+        assert result["commits_squashed"] == 2
+        assert result["squashed_hashes"] == [h2, h3]
+        assert result["message"] == "squash 2 commits: two; three"
+        assert repo.get_state(result["new_hash"]).memory == {"step": 3}
+
+        tip = repo.list_branches()["main"]
+        assert tip == result["new_tip"]
+        assert repo.get_state(tip).memory == {"step": 4}
+        assert len(repo.log(limit=10)) == 3
@@ -88,6 +88,68 @@ class TestAgentStateSerialization:
         assert d["memory"]["list"] == [1, "two", 3.0]
 
 
+class TestAgentStateFromNative:
+    """Test building states from native dicts and JSON strings."""
+
+    def test_nested_structures(self) -> None:
+        memory = {"a": {"b": {"c": [1, {"d": [True, None]}]}}}
+        state = PyAgentState(memory, {"tools": {"search": {"calls": 2}}})
+        assert state.memory == memory
+        assert state.world_state["tools"]["search"]["calls"] == 2
+
+    def test_non_string_keys_become_strings(self) -> None:
+        state = PyAgentState({1: "one", 2.5: "two and a half"}, {})
+        assert state.memory == {"1": "one", "2.5": "two and a half"}
+
+    def test_tuples_become_lists(self) -> None:
+        state = PyAgentState({"pair": (1, 2), "nested": [("a", "b")]}, {})
+        assert state.memory == {"pair": [1, 2], "nested": [["a", "b"]]}
+
+    def test_none_values(self) -> None:
+        state = PyAgentState({"missing": None}, None)
+        assert state.memory == {"missing": None}
+        assert state.world_state == {}
+
+    def test_json_strings_still_accepted(self) -> None:
+        state = PyAgentState('{"k": [1, 2]}', '{"env": "test"}')
+        assert state.memory == {"k": [1, 2]}
+        assert state.world_state == {"env": "test"}
+
+    def test_mixed_dict_and_string(self) -> None:
+        state = PyAgentState({"k": "v"}, '{"env": "test"}')
+        assert state.memory == {"k": "v"}
+        assert state.world_state == {"env": "test"}
+
+    def test_rejects_other_types(self) -> None:
+        with pytest.raises(TypeError):
+            PyAgentState(["not", "a", "dict"], {})
+
+    def test_metadata_keyword(self) -> None:
+        state = PyAgentState({"k": "v"}, {}, metadata={"run": 7})
+        assert state.metadata == {"run": 7}
+
+    def test_from_dict_with_cost_and_metadata(self) -> None:
+        state = PyAgentState.from_dict(
+            {"memory": {"k": "v"}, "cost": 0.5, "metadata": {"tag": "x"}}
+        )
+        assert state.memory == {"k": "v"}
+        assert state.cost == pytest.approx(0.5)
+        assert state.metadata == {"tag": "x"}
+
+    def test_commit_accepts_a_dict(self) -> None:
+        from agit import PyRepository
+
+        repo = PyRepository(":memory:", "test-agent")
+        h = repo.commit(
+            {"memory": {"nested": {"n": (1, 2)}}, "world_state": {"env": "test"}},
+            "from dict",
+            "checkpoint",
+        )
+        state = repo.get_state(h)
+        assert state.memory == {"nested": {"n": [1, 2]}}
+        assert state.world_state == {"env": "test"}
+
+
 class TestStateDiff:
     """Test PyStateDiff and diff computation via PyRepository."""
 
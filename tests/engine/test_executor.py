@@ -233,3 +233,11 @@ class TestErrorHandling:
         log = engine.audit_log(limit=10)
         assert isinstance(log, list)
         assert len(log) >= 1
+
+    def test_audit_log_message_query(
+        self, engine: ExecutionEngine, base_state: dict[str, Any]
+    ) -> None:
+        engine.commit_state(base_state, "tool call timed out", "checkpoint")
+        engine.commit_state({**base_state, "extra": 1}, "all good", "checkpoint")
+        log = engine.audit_log(limit=10, message_query="TIMED OUT")
+        assert [e["message"] for e in log] == ["tool call timed out"]
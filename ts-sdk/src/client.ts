@@ -23,16 +23,17 @@ import { ActionType, ChangeType, MergeStrategy } from "./types.js";
 // Native binding import (optional)
 // ---------------------------------------------------------------------------
 
+/** State passed to the native `commit`. */
+interface NativeStateInput {
+  memory: unknown;
+  worldState?: unknown;
+  cost?: number;
+  metadata?: Record<string, unknown>;
+}
+
 /** Shape of the native JsRepository exposed by napi-rs. */
 interface NativeRepository {
-  commit(
-    memory_json: string,
-    world_state_json: string,
-    message: string,
-    action_type: string,
-    cost?: number,
-    metadata_json?: string
-  ): Promise<string>;
+  commit(state: NativeStateInput, message: string, action_type: string): Promise<string>;
   branch(name: string, from?: string): Promise<void>;
   checkout(target: string): Promise<AgentState>;
   diff(hash1: string, hash2: string): Promise<StateDiff>;
@@ -40,9 +41,9 @@ interface NativeRepository {
   log(branch?: string, limit?: number): Promise<Commit[]>;
   revert(toHash: string): Promise<AgentState>;
   getState(hash: string): Promise<AgentState>;
-  head(): string | null;
-  currentBranch(): string | null;
-  listBranches(): string[];
+  head(): Promise<string | null>;
+  currentBranch(): Promise<string | null>;
+  listBranches(): Promise<Record<string, string>>;
 }
 
 interface NativeModule {
@@ -77,11 +78,13 @@ function parseJsonValue(value: unknown): unknown {
   }
 }
 
-function normalizeState(state: AgentState): AgentState {
+/** Native states name the world state `worldState`. */
+function normalizeState(state: AgentState & { worldState?: unknown }): AgentState {
+  const { worldState, ...rest } = state;
   return {
-    ...state,
+    ...rest,
     memory: parseJsonValue(state.memory),
-    world_state: parseJsonValue(state.world_state),
+    world_state: parseJsonValue(state.world_state ?? worldState),
     metadata: parseJsonValue(state.metadata),
   };
 }
@@ -118,27 +121,17 @@ class PureTsRepository implements NativeRepository {
     return this.detachedHash;
   }
 
-  async commit(
-    memory_json: string,
-    world_state_json: string,
-    message: string,
-    action_type: string,
-    cost?: number,
-    metadata?: unknown
-  ): Promise<string> {
+  async commit(input: NativeStateInput, message: string, action_type: string): Promise<string> {
     const now = new Date().toISOString();
     const parentHash = this.resolveHead();
     const parent_hashes = parentHash ? [parentHash] : [];
 
-    const memory = parseJsonValue(memory_json);
-    const world_state = parseJsonValue(world_state_json);
-
     const state: AgentState = {
-      memory,
-      world_state: world_state ?? {},
+      memory: input.memory,
+      world_state: input.worldState ?? {},
       timestamp: now,
-      cost: cost ?? 0,
-      metadata: parseJsonValue(metadata),
+      cost: input.cost ?? 0,
+      metadata: input.metadata,
     };
 
     const treeHash = sha256Sync(JSON.stringify(state));
@@ -242,12 +235,14 @@ class PureTsRepository implements NativeRepository {
 
     const currentBranch = this._currentBranch ?? "HEAD";
     return this.commit(
-      JSON.stringify(mergedState.memory),
-      JSON.stringify(mergedState.world_state),
+      {
+        memory: mergedState.memory,
+        worldState: mergedState.world_state,
+        cost: mergedState.cost,
+        metadata: mergedState.metadata as Record<string, unknown> | undefined,
+      },
       `merge branch '${branch}' into '${currentBranch}'`,
-      ActionType.Merge,
-      mergedState.cost,
-      mergedState.metadata as Record<string, unknown> | undefined
+      ActionType.Merge
     );
   }
 
@@ -313,12 +308,14 @@ class PureTsRepository implements NativeRepository {
     const entry = this.commits.get(toHash);
     if (!entry) throw new Error(`Object not found: ${toHash}`);
     await this.commit(
-      JSON.stringify(entry.state.memory),
-      JSON.stringify(entry.state.world_state),
+      {
+        memory: entry.state.memory,
+        worldState: entry.state.world_state,
+        cost: entry.state.cost,
+        metadata: entry.state.metadata as Record<string, unknown> | undefined,
+      },
       `revert to ${toHash.slice(0, 8)}`,
-      ActionType.Rollback,
-      entry.state.cost,
-      entry.state.metadata as Record<string, unknown> | undefined
+      ActionType.Rollback
     );
     return entry.state;
   }
@@ -329,16 +326,16 @@ class PureTsRepository implements NativeRepository {
     return entry.state;
   }
 
-  head(): string | null {
+  async head(): Promise<string | null> {
     return this.resolveHead();
   }
 
-  currentBranch(): string | null {
+  async currentBranch(): Promise<string | null> {
     return this._currentBranch;
   }
 
-  listBranches(): string[] {
-    return Object.keys(this.branches);
+  async listBranches(): Promise<Record<string, string>> {
+    return { ...this.branches };
   }
 }
 
@@ -534,12 +531,14 @@ export class AgitClient {
     } = options;
 
     return this.repo.commit(
-      JSON.stringify(memory),
-      JSON.stringify(world_state),
+      {
+        memory,
+        worldState: world_state,
+        cost,
+        metadata: metadata as Record<string, unknown> | undefined,
+      },
       message,
-      typeof action_type === "string" ? action_type : action_type,
-      cost,
-      metadata === undefined ? undefined : JSON.stringify(metadata)
+      action_type
     );
   }
 
@@ -602,11 +601,11 @@ export class AgitClient {
   /**
    * Return repository status information.
    */
-  status(): RepoStatus {
+  async status(): Promise<RepoStatus> {
     return {
-      head: this.repo.head(),
-      current_branch: this.repo.currentBranch(),
-      branches: this.repo.listBranches(),
+      head: await this.repo.head(),
+      current_branch: await this.repo.currentBranch(),
+      branches: Object.keys(await this.repo.listBranches()),
     };
   }
 }
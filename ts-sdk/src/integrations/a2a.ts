@@ -178,7 +178,7 @@ export class AgitA2AHooks {
 
   private async ensureBranch(name: string): Promise<void> {
     try {
-      const status = this.client.status();
+      const status = await this.client.status();
       if (!status.branches.includes(name)) {
         await this.client.branch({ name });
       }
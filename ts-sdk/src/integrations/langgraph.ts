@@ -49,7 +49,7 @@ export class AgitCheckpointSaver {
     const branchName = `langgraph/${threadId}`;
 
     // Ensure branch exists
-    const status = this.client.status();
+    const status = await this.client.status();
     if (!status.branches.includes(branchName)) {
       try {
         await this.client.branch({ name: branchName });
@@ -327,7 +327,7 @@ export function createAgitMcpServer(client: AgitClient): McpServer {
     { type: "object", properties: {} },
     async () => {
       try {
-        return ok(client.status());
+        return ok(await client.status());
       } catch (e) {
         return fail(String(e));
       }
@@ -35,7 +35,7 @@ describe("AgitClient open() safety defaults", () => {
     const previous = process.env.AGIT_ALLOW_STUBS;
     process.env.AGIT_ALLOW_STUBS = "1";
     const client = await AgitClient.open("/tmp/agit-test-fallback");
-    expect(client.status().current_branch).toBe("main");
+    expect((await client.status()).current_branch).toBe("main");
     if (previous === undefined) {
       delete process.env.AGIT_ALLOW_STUBS;
     } else {
@@ -57,8 +57,8 @@ describe("AgitClient (pure-TS fallback)", () => {
 
   // ---- status / init -------------------------------------------------------
 
-  it("has no commits on init", () => {
-    const s = client.status();
+  it("has no commits on init", async () => {
+    const s = await client.status();
     expect(s.head).toBeNull();
     expect(s.current_branch).toBe("main");
     expect(s.branches).toEqual([]);
@@ -77,9 +77,9 @@ describe("AgitClient (pure-TS fallback)", () => {
 
   it("HEAD advances after each commit", async () => {
     await client.commit({ memory: { n: 1 }, message: "one" });
-    const s1 = client.status();
+    const s1 = await client.status();
     await client.commit({ memory: { n: 2 }, message: "two" });
-    const s2 = client.status();
+    const s2 = await client.status();
     expect(s2.head).not.toBe(s1.head);
     expect(s2.head).not.toBeNull();
   });
@@ -153,7 +153,7 @@ describe("AgitClient (pure-TS fallback)", () => {
   it("creates a branch and lists it", async () => {
     await client.commit({ memory: { v: 1 }, message: "base" });
     await client.branch({ name: "feature" });
-    const s = client.status();
+    const s = await client.status();
     expect(s.branches).toContain("main");
     expect(s.branches).toContain("feature");
   });
@@ -172,7 +172,7 @@ describe("AgitClient (pure-TS fallback)", () => {
     await client.commit({ memory: { v: 1 }, message: "base" });
     await client.branch({ name: "feat" });
     await client.checkout("feat");
-    const s = client.status();
+    const s = await client.status();
     expect(s.current_branch).toBe("feat");
   });
 
@@ -188,7 +188,7 @@ describe("AgitClient (pure-TS fallback)", () => {
     await client.commit({ memory: { v: 2 }, message: "two" });
     const state = await client.checkout(h1);
     expect(state.memory).toEqual({ v: 1 });
-    expect(client.status().current_branch).toBeNull();
+    expect((await client.status()).current_branch).toBeNull();
   });
 
   it("throws when checking out unknown ref", async () => {
@@ -310,7 +310,7 @@ describe("AgitClient (pure-TS fallback)", () => {
 
   it("status reflects current HEAD and branch", async () => {
     const h = await client.commit({ memory: {}, message: "init" });
-    const s = client.status();
+    const s = await client.status();
     expect(s.head).toBe(h);
     expect(s.current_branch).toBe("main");
     expect(s.branches).toContain("main");